@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS ledger (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    chain_id BIGINT NOT NULL,
+    contract TEXT NOT NULL,
+    job TEXT NOT NULL,
+    tx_hash TEXT NOT NULL,
+    submitted_at BIGINT NOT NULL,
+    entry TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS ledger_job ON ledger (kind, chain_id, contract, job);
+CREATE INDEX IF NOT EXISTS ledger_tx_hash ON ledger (tx_hash);
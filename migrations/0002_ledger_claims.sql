@@ -0,0 +1,9 @@
+-- A job's first submission claims it: the unique index lets only one
+-- instance record it, before broadcasting. Replacements and forced resends
+-- are recorded after it as resends.
+ALTER TABLE ledger ADD COLUMN IF NOT EXISTS resend BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE ledger SET resend = TRUE
+WHERE id NOT IN (SELECT MIN(id) FROM ledger GROUP BY kind, chain_id, contract, job);
+
+CREATE UNIQUE INDEX IF NOT EXISTS ledger_job_claim ON ledger (kind, chain_id, contract, job) WHERE NOT resend;
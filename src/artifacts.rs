@@ -0,0 +1,62 @@
+//! Per-job artifacts for batch runs: everything needed to reconstruct what
+//! was sent for a row long after the run, written to `<dir>/row-<n>/`:
+//!
+//! - `job.json`: the row's job
+//! - `tx.json`: the prepared transaction, filled in but unsigned
+//! - `raw_tx.hex`: the signed transaction exactly as broadcast
+//! - `receipt.json`: the receipt
+//! - `events.json`: the receipt's logs, decoded against abi.json where possible
+
+use crate::config::Job;
+use crate::events;
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+
+pub struct Artifacts {
+    root: PathBuf,
+}
+
+impl Artifacts {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The directory of the row at `index` (counting from 0).
+    pub fn dir(&self, index: usize) -> PathBuf {
+        self.root.join(format!("row-{}", index + 1))
+    }
+
+    /// Writes the job and its prepared transaction, before signing.
+    pub fn write_prepared(&self, index: usize, job: &Job, tx: &TypedTransaction) -> anyhow::Result<()> {
+        self.write_json(index, "job.json", job)?;
+        self.write_json(index, "tx.json", tx)
+    }
+
+    /// Writes the signed transaction, before it is broadcast.
+    pub fn write_signed(&self, index: usize, raw: &Bytes) -> anyhow::Result<()> {
+        self.write(index, "raw_tx.hex", format!("0x{}\n", hex::encode(raw)))
+    }
+
+    /// Writes the receipt and its decoded events.
+    pub fn write_receipt(&self, index: usize, receipt: &TransactionReceipt) -> anyhow::Result<()> {
+        self.write_json(index, "receipt.json", receipt)?;
+        let events: Vec<Value> = receipt.logs.iter().map(events::to_json).collect();
+        self.write_json(index, "events.json", &events)
+    }
+
+    fn write_json(&self, index: usize, name: &str, value: &impl Serialize) -> anyhow::Result<()> {
+        self.write(index, name, serde_json::to_string_pretty(value)? + "\n")
+    }
+
+    fn write(&self, index: usize, name: &str, contents: String) -> anyhow::Result<()> {
+        let dir = self.dir(index);
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        let path = dir.join(name);
+        fs::write(&path, contents).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
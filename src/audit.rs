@@ -0,0 +1,196 @@
+//! Compliance audit log: an append-only JSONL record of every signature this
+//! tool produces and every transaction it broadcasts, with the parameters,
+//! the signing key's address and the outcome.
+//!
+//! Each record is synced to disk before the call returns, and a transaction
+//! is only broadcast once its signature has been recorded, so a crash can
+//! never leave a broadcast the log doesn't know about.
+
+use crate::config;
+use crate::pipeline::{self, Sender};
+use crate::policy;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// A transaction, or a hash such as a Safe transaction's, a job digest, a
+    /// permit or a UserOperation, was signed.
+    Sign,
+    /// A signed transaction was handed to the node.
+    Broadcast,
+    /// A broadcast transaction was mined.
+    Receipt,
+    /// The signing policy refused to sign a transaction.
+    Refuse,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    /// Unix timestamp of the record.
+    pub timestamp: u64,
+    pub action: Action,
+    /// Address of the key that signed or sent.
+    pub key: Address,
+    /// What the signature or transaction was for, e.g. `lock` or `cancel`.
+    pub purpose: String,
+    pub params: Value,
+    pub tx_hash: Option<H256>,
+    pub outcome: String,
+}
+
+impl Record {
+    pub fn new(
+        action: Action,
+        key: Address,
+        purpose: &str,
+        params: Value,
+        tx_hash: Option<H256>,
+        outcome: &str,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            action,
+            key,
+            purpose: purpose.to_string(),
+            params,
+            tx_hash,
+            outcome: outcome.to_string(),
+        }
+    }
+}
+
+/// Appends `record` to the audit log and syncs it to disk. Does nothing when
+/// no AUDIT_LOG is configured.
+pub fn record(record: &Record) -> anyhow::Result<()> {
+    let Some(path) = config::audit_log_path() else {
+        return Ok(());
+    };
+    append(&path, record)
+}
+
+fn append(path: &Path, record: &Record) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Records a signature `key` made over something other than a transaction,
+/// such as a job digest, a permit or a UserOperation; `params` say what.
+pub fn record_signature(key: Address, purpose: &str, params: Value) -> anyhow::Result<()> {
+    record(&Record::new(Action::Sign, key, purpose, params, None, "signed"))
+}
+
+/// Signs `tx` with the client's key, records the signature, broadcasts it
+/// and records the node's answer. Used in place of
+/// `Middleware::send_transaction` for everything this tool sends.
+pub async fn send_transaction<'a, M: Sender>(
+    client: &'a M,
+    tx: impl Into<TypedTransaction>,
+    purpose: &str,
+) -> anyhow::Result<PendingTransaction<'a, M::Provider>>
+where
+    M::Error: 'static,
+{
+    let mut tx = tx.into();
+    let raw = sign(client, &mut tx, purpose).await?;
+    broadcast(client, raw, purpose).await
+}
+
+/// Signs `tx` with the client's key and records the signature, returning
+/// the raw transaction for [`broadcast`].
+pub async fn sign<M: Sender>(client: &M, tx: &mut TypedTransaction, purpose: &str) -> anyhow::Result<Bytes>
+where
+    M::Error: 'static,
+{
+    let raw = pipeline::sign(client, tx).await?;
+    let tx_hash = H256(ethers::utils::keccak256(&raw));
+    record(&Record::new(Action::Sign, client.sender(), purpose, serde_json::to_value(&*tx)?, Some(tx_hash), "signed"))?;
+    Ok(raw)
+}
+
+/// Broadcasts a transaction from [`sign`] and records the node's answer. An
+/// accepted transaction counts toward the signing policy's daily total.
+pub async fn broadcast<'a, M: Sender>(
+    client: &'a M,
+    raw: Bytes,
+    purpose: &str,
+) -> anyhow::Result<PendingTransaction<'a, M::Provider>>
+where
+    M::Error: 'static,
+{
+    broadcast_as(client, client.sender(), client.chain_id(), raw, purpose).await
+}
+
+/// [`broadcast`] through `provider` of a transaction `from` signed for
+/// `chain_id` elsewhere, such as by an external signer.
+pub async fn broadcast_as<'a, M: Middleware>(
+    provider: &'a M,
+    from: Address,
+    chain_id: u64,
+    raw: Bytes,
+    purpose: &str,
+) -> anyhow::Result<PendingTransaction<'a, M::Provider>>
+where
+    M::Error: 'static,
+{
+    let tx_hash = H256(ethers::utils::keccak256(&raw));
+    let result = provider.send_raw_transaction(raw.clone()).await;
+    let outcome = match &result {
+        Ok(_) => "accepted".to_string(),
+        Err(e) => format!("rejected: {}", e),
+    };
+    record(&Record::new(Action::Broadcast, from, purpose, Value::Null, Some(tx_hash), &outcome))?;
+    let pending = result?;
+    policy::record_spend(from, chain_id, &raw)?;
+    Ok(pending)
+}
+
+/// Records the receipt of a transaction sent with [`send_transaction`].
+pub fn record_receipt(key: Address, purpose: &str, receipt: &TransactionReceipt) -> anyhow::Result<()> {
+    let outcome = match receipt.status == Some(U64::from(1)) {
+        true => "confirmed",
+        false => "reverted",
+    };
+    let params = json!({ "block": receipt.block_number, "gas_used": receipt.gas_used });
+    record(&Record::new(Action::Receipt, key, purpose, params, Some(receipt.transaction_hash), outcome))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let path = std::env::temp_dir().join(format!("audit-test-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let key = Address::repeat_byte(1);
+        let hash = H256::repeat_byte(2);
+        append(&path, &Record::new(Action::Sign, key, "lock", json!({ "nonce": 7 }), Some(hash), "signed")).unwrap();
+        append(&path, &Record::new(Action::Broadcast, key, "lock", Value::Null, Some(hash), "rejected: nonce too low"))
+            .unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let records: Vec<Record> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].action, records[0].key, records[0].tx_hash), (Action::Sign, key, Some(hash)));
+        assert_eq!(records[0].params, json!({ "nonce": 7 }));
+        assert_eq!((records[1].action, records[1].outcome.as_str()), (Action::Broadcast, "rejected: nonce too low"));
+        assert!(contents.lines().next().unwrap().contains(r#""action":"sign""#));
+        fs::remove_file(&path).unwrap();
+    }
+}
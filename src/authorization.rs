@@ -0,0 +1,161 @@
+//! Funding a lock with an EIP-3009 authorization, for USDC-style tokens
+//! that support it. Instead of approving the contract, the user signs a
+//! `ReceiveWithAuthorization` for the amount, and the newer contract's
+//! `lockWithAuthorization` redeems it while locking. Unlike
+//! `transferWithAuthorization`, which anyone holding the signature can
+//! submit, a receive authorization can only be redeemed by its recipient
+//! (the lock contract), so it can't be front-run out of the lock.
+//!
+//! EIP-3009 nonces are random 32-byte values rather than a counter; each is
+//! checked unused with `authorizationState` before it is signed. The
+//! authorization is signed with USER_PRIVATE_KEY.
+
+use crate::audit;
+use crate::contract::Erc3009;
+use crate::validity;
+use anyhow::Context;
+use ethers::abi::{encode, Token};
+use ethers::core::rand::{thread_rng, RngCore};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+const RECEIVE_TYPE: &str = "ReceiveWithAuthorization(address from,address to,uint256 value,uint256 validAfter,uint256 validBefore,bytes32 nonce)";
+
+/// A signed authorization for `to` to receive `value` from `from`.
+pub struct Authorization {
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub valid_after: U256,
+    pub valid_before: U256,
+    pub nonce: [u8; 32],
+    pub signature: Signature,
+}
+
+impl Authorization {
+    /// The signature as the `v`, `r` and `s` the token takes.
+    pub fn vrs(&self) -> (u8, [u8; 32], [u8; 32]) {
+        let (mut r, mut s) = ([0; 32], [0; 32]);
+        self.signature.r.to_big_endian(&mut r);
+        self.signature.s.to_big_endian(&mut s);
+        (self.signature.v as u8, r, s)
+    }
+}
+
+/// The EIP-712 digest a receive authorization signs, under the token's
+/// domain separator.
+pub fn digest(
+    domain_separator: [u8; 32],
+    from: Address,
+    to: Address,
+    value: U256,
+    valid_after: U256,
+    valid_before: U256,
+    nonce: [u8; 32],
+) -> H256 {
+    let authorization = keccak256(encode(&[
+        Token::FixedBytes(keccak256(RECEIVE_TYPE).to_vec()),
+        Token::Address(from),
+        Token::Address(to),
+        Token::Uint(value),
+        Token::Uint(valid_after),
+        Token::Uint(valid_before),
+        Token::FixedBytes(nonce.to_vec()),
+    ]));
+    let mut message = vec![0x19, 0x01];
+    message.extend_from_slice(&domain_separator);
+    message.extend_from_slice(&authorization);
+    H256(keccak256(message))
+}
+
+/// A random authorization nonce the wallet hasn't used with `token`.
+async fn fresh_nonce<M: Middleware + 'static>(token: &Erc3009<M>, from: Address) -> anyhow::Result<[u8; 32]> {
+    loop {
+        let mut nonce = [0; 32];
+        thread_rng().fill_bytes(&mut nonce);
+        if !token.authorization_state(from, nonce).call().await? {
+            return Ok(nonce);
+        }
+    }
+}
+
+/// Signs an authorization for `to` to receive `value` of `token` from the
+/// wallet, valid after `valid_after` (Unix seconds, 0 for right away) and
+/// then for `valid_for`, counted from the latest block's timestamp at the
+/// earliest.
+pub async fn sign<M: Middleware + 'static>(
+    client: Arc<M>,
+    wallet: &LocalWallet,
+    token: Address,
+    to: Address,
+    value: U256,
+    valid_after: u64,
+    valid_for: Duration,
+) -> anyhow::Result<Authorization>
+where
+    M::Error: 'static,
+{
+    let opens = validity::chain_time(&*client).await?.max(valid_after);
+    let (valid_after, valid_before) = (U256::from(valid_after), U256::from(opens + valid_for.as_secs()));
+    let erc3009 = Erc3009::new(token, client);
+    let not_3009 = || format!("token {:?} doesn't implement EIP-3009 authorizations", token);
+    let domain_separator = erc3009.domain_separator().call().await.with_context(not_3009)?;
+    let nonce = fresh_nonce(&erc3009, wallet.address()).await.with_context(not_3009)?;
+    let hash = digest(domain_separator, wallet.address(), to, value, valid_after, valid_before, nonce);
+    let signature = wallet.sign_hash(hash)?;
+    let params = json!({
+        "token": token,
+        "to": to,
+        "value": value,
+        "valid_after": valid_after,
+        "valid_before": valid_before,
+        "nonce": H256(nonce),
+    });
+    audit::record_signature(wallet.address(), "authorization", params)?;
+    Ok(Authorization { from: wallet.address(), to, value, valid_after, valid_before, nonce, signature })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::transaction::eip712::{EIP712Domain, Eip712, Eip712DomainType, TypedData};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn digest_matches_typed_data() {
+        let (from, to, nonce) = (Address::repeat_byte(1), Address::repeat_byte(2), [9; 32]);
+        let domain = EIP712Domain {
+            name: Some("USD Coin".to_string()),
+            version: Some("2".to_string()),
+            chain_id: Some(1.into()),
+            verifying_contract: Some(Address::repeat_byte(3)),
+            salt: None,
+        };
+        let field = |name: &str, r#type: &str| Eip712DomainType { name: name.to_string(), r#type: r#type.to_string() };
+        let types = BTreeMap::from([(
+            "ReceiveWithAuthorization".to_string(),
+            vec![
+                field("from", "address"),
+                field("to", "address"),
+                field("value", "uint256"),
+                field("validAfter", "uint256"),
+                field("validBefore", "uint256"),
+                field("nonce", "bytes32"),
+            ],
+        )]);
+        let message = serde_json::from_value(serde_json::json!({
+            "from": from, "to": to, "value": 1000, "validAfter": 0, "validBefore": 1_700_000_000u64,
+            "nonce": H256(nonce),
+        }))
+        .unwrap();
+        let primary_type = "ReceiveWithAuthorization".to_string();
+        let typed = TypedData { domain: domain.clone(), types, primary_type, message };
+        let expected = typed.encode_eip712().unwrap();
+        let valid_before = 1_700_000_000u64.into();
+        let actual = digest(domain.separator(), from, to, 1000.into(), U256::zero(), valid_before, nonce);
+        assert_eq!(actual, H256(expected));
+    }
+}
@@ -0,0 +1,252 @@
+//! Balance alerts for the wallets an operator keeps funded (relayers,
+//! treasuries, account owners) across chains, read from a TOML file:
+//!
+//! ```toml
+//! [chains]
+//! mainnet = "https://eth.example"
+//! base = "https://base.example"
+//!
+//! [[wallets]]
+//! name = "base relayer"
+//! chain = "base"
+//! address = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8"
+//! threshold = 0.5     # ether
+//! recover_at = 0.75   # ether; 20% above the threshold when unset
+//! ```
+//!
+//! A wallet alerts once when it drops below its threshold, and not again
+//! until it has recovered to `recover_at`, so a balance hovering around the
+//! threshold doesn't flap. Which wallets are low is kept in the state
+//! directory, so that holds across runs as well.
+
+use crate::config;
+use crate::tls;
+use crate::units;
+use anyhow::Context;
+use ethers::prelude::*;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchFile {
+    /// RPC URL of each chain, by name.
+    pub chains: BTreeMap<String, String>,
+    pub wallets: Vec<Wallet>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Wallet {
+    pub name: String,
+    /// Name of the chain in `chains`.
+    pub chain: String,
+    pub address: Address,
+    /// Balance, in ether, below which the wallet alerts.
+    pub threshold: f64,
+    /// Balance, in ether, the wallet must be back at before it can alert
+    /// again.
+    pub recover_at: Option<f64>,
+}
+
+impl WatchFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("failed to read balance watch file {}", path.display()))?;
+        let file: Self =
+            toml::from_str(&contents).with_context(|| format!("invalid balance watch file {}", path.display()))?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut names = BTreeSet::new();
+        for wallet in &self.wallets {
+            anyhow::ensure!(names.insert(&wallet.name), "wallet {:?} is listed twice", wallet.name);
+            anyhow::ensure!(
+                self.chains.contains_key(&wallet.chain),
+                "wallet {:?} is on chain {:?}, which isn't in [chains]",
+                wallet.name,
+                wallet.chain
+            );
+            anyhow::ensure!(
+                wallet.threshold()? <= wallet.recover_at()?,
+                "wallet {:?} recovers below its threshold",
+                wallet.name
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Wallet {
+    /// The threshold in wei.
+    pub fn threshold(&self) -> anyhow::Result<U256> {
+        units::from_f64(self.threshold, 18).with_context(|| format!("invalid threshold of wallet {:?}", self.name))
+    }
+
+    /// `recover_at` in wei.
+    pub fn recover_at(&self) -> anyhow::Result<U256> {
+        units::from_f64(self.recover_at.unwrap_or(self.threshold * 1.2), 18)
+            .with_context(|| format!("invalid recover_at of wallet {:?}", self.name))
+    }
+}
+
+/// A wallet crossing into or out of the low state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Low,
+    Recovered,
+}
+
+impl Change {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::Low => "low",
+            Change::Recovered => "recovered",
+        }
+    }
+}
+
+/// Whether a wallet that `was_low` is low at `balance`, and how that changed.
+pub fn transition(was_low: bool, balance: U256, threshold: U256, recover_at: U256) -> (bool, Option<Change>) {
+    match was_low {
+        false if balance < threshold => (true, Some(Change::Low)),
+        true if balance >= recover_at => (false, Some(Change::Recovered)),
+        low => (low, None),
+    }
+}
+
+/// The names of the wallets that are low, kept in `balance-alerts.json` in
+/// the state directory.
+pub struct AlertState {
+    path: PathBuf,
+    low: BTreeSet<String>,
+}
+
+impl AlertState {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let path = config::state_dir().join("balance-alerts.json");
+        let low = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).with_context(|| format!("corrupt alert state {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read alert state {}", path.display())),
+        };
+        Ok(Self { path, low })
+    }
+
+    pub fn is_low(&self, name: &str) -> bool {
+        self.low.contains(name)
+    }
+
+    /// Writes the state, replacing the file in one rename.
+    pub fn save(&self) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(&self.low)?)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+/// One wallet's balance as of a check.
+#[derive(Clone, Debug)]
+pub struct Reading {
+    pub name: String,
+    pub chain: String,
+    pub address: Address,
+    pub balance: U256,
+    pub threshold: U256,
+    pub recover_at: U256,
+    pub low: bool,
+    pub change: Option<Change>,
+}
+
+/// Reads every wallet's balance and moves it into or out of the low state.
+/// A wallet whose chain can't be read gets an error and keeps its state.
+pub async fn check(file: &WatchFile, state: &mut AlertState) -> Vec<(String, anyhow::Result<Reading>)> {
+    let mut providers = HashMap::new();
+    for (chain, url) in &file.chains {
+        providers.insert(chain.as_str(), tls::provider(url));
+    }
+    let mut readings = Vec::new();
+    for wallet in &file.wallets {
+        let reading: anyhow::Result<Reading> = async {
+            let provider = providers[wallet.chain.as_str()]
+                .as_ref()
+                .map_err(|e| anyhow::anyhow!("invalid RPC URL of chain {:?}: {:#}", wallet.chain, e))?;
+            let balance = provider.get_balance(wallet.address, None).await?;
+            let (threshold, recover_at) = (wallet.threshold()?, wallet.recover_at()?);
+            let (low, change) = transition(state.is_low(&wallet.name), balance, threshold, recover_at);
+            Ok(Reading {
+                name: wallet.name.clone(),
+                chain: wallet.chain.clone(),
+                address: wallet.address,
+                balance,
+                threshold,
+                recover_at,
+                low,
+                change,
+            })
+        }
+        .await;
+        if let Ok(reading) = &reading {
+            match reading.low {
+                true => state.low.insert(reading.name.clone()),
+                false => state.low.remove(&reading.name),
+            };
+        }
+        readings.push((wallet.name.clone(), reading));
+    }
+    readings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hysteresis() {
+        let (threshold, recover_at) = (U256::from(100), U256::from(120));
+        assert_eq!(transition(false, 150.into(), threshold, recover_at), (false, None));
+        assert_eq!(transition(false, 99.into(), threshold, recover_at), (true, Some(Change::Low)));
+        // Back above the threshold, but not yet recovered: no new alert either way.
+        assert_eq!(transition(true, 110.into(), threshold, recover_at), (true, None));
+        assert_eq!(transition(true, 90.into(), threshold, recover_at), (true, None));
+        assert_eq!(transition(true, 120.into(), threshold, recover_at), (false, Some(Change::Recovered)));
+        assert_eq!(transition(false, 110.into(), threshold, recover_at), (false, None));
+    }
+
+    #[test]
+    fn watch_files() {
+        let file: WatchFile = toml::from_str(
+            r#"
+            chains = { base = "http://localhost:8545" }
+
+            [[wallets]]
+            name = "relayer"
+            chain = "base"
+            address = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8"
+            threshold = 0.5
+            "#,
+        )
+        .unwrap();
+        file.validate().unwrap();
+        assert_eq!(file.wallets[0].recover_at().unwrap(), U256::exp10(17) * 6);
+
+        let mut unknown_chain = file.clone();
+        unknown_chain.wallets[0].chain = "mainnet".to_string();
+        assert!(unknown_chain.validate().is_err());
+        let mut recovers_below = file.clone();
+        recovers_below.wallets[0].recover_at = Some(0.4);
+        assert!(recovers_below.validate().is_err());
+        let mut twice = file;
+        twice.wallets.push(twice.wallets[0].clone());
+        assert!(twice.validate().is_err());
+    }
+}
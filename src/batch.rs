@@ -0,0 +1,956 @@
+//! Batch locking: sends many jobs from a pool of accounts, keeping several
+//! transactions in flight per account instead of waiting for each receipt.
+//!
+//! Broadcasting stays sequential so nonces are assigned in order, while the
+//! receipt waits run concurrently; a job that fails before broadcast never
+//! consumes a nonce, so it cannot leave a gap behind it.
+
+use crate::artifacts::Artifacts;
+use crate::audit;
+use crate::calldata;
+use crate::checkpoint::{Checkpoint, Record, State};
+use crate::config::{Config, Job};
+use crate::contract::MyContract;
+use crate::error::Error;
+use crate::fees::{FeeModel, GasGate};
+use crate::gas_usage;
+use crate::hooks;
+use crate::ledger::Ledger;
+use crate::nonce;
+use crate::pipeline::{self, Client, Rpc, Sender};
+use crate::plugins::{self, Preflight};
+use crate::policy;
+use crate::postcheck;
+use crate::print_warn;
+use crate::signature;
+use crate::style;
+use anyhow::Context;
+use ethers::prelude::*;
+use futures::stream::{self, FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const KIND: &str = "lock";
+/// Gas estimates requested at once in the estimation phase.
+const ESTIMATE_CONCURRENCY: usize = 8;
+
+#[derive(Deserialize)]
+struct Row {
+    user: String,
+    token: String,
+    amount: String,
+    nonce: String,
+    signature: String,
+}
+
+/// Reads jobs from a CSV file with a `user,token,amount,nonce,signature`
+/// header, each field in the same format as the corresponding variable.
+pub fn read_jobs(path: &Path) -> anyhow::Result<Vec<Job>> {
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("failed to open batch file {}", path.display()))?;
+    reader
+        .deserialize::<Row>()
+        .enumerate()
+        .map(|(i, row)| {
+            let row = row?;
+            let mut job = Job::parse(&row.user, &row.token, &row.amount, &row.nonce, "0x")
+                .with_context(|| format!("batch row {}", i + 1))?;
+            job.signature = signature::parse(&row.signature).with_context(|| format!("batch row {}", i + 1))?;
+            Ok(job)
+        })
+        .collect()
+}
+
+/// Writes `jobs` as a batch file [`read_jobs`] reads back, with the nonce in
+/// `0x`-prefixed hex.
+pub fn write_jobs(path: &Path, jobs: &[Job]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_path(path).with_context(|| format!("failed to create {}", path.display()))?;
+    writer.write_record(["user", "token", "amount", "nonce", "signature"])?;
+    for job in jobs {
+        writer.write_record([
+            format!("{:?}", job.user),
+            format!("{:?}", job.token),
+            job.amount.to_string(),
+            format!("{:#x}", job.nonce),
+            format!("0x{}", hex::encode(&job.signature)),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub struct Options {
+    /// Maximum number of broadcast but unconfirmed transactions per sender.
+    pub concurrency: usize,
+    /// Resend jobs the ledger shows as already submitted.
+    pub force: bool,
+    /// Prices each transaction; when fee history is unavailable the signer
+    /// falls back to the node's suggestion.
+    pub fees: FeeModel,
+    /// Holds each broadcast back until the base fee is low enough. Once its
+    /// maximum wait runs out, the remaining jobs are skipped.
+    pub gas_gate: Option<GasGate>,
+    /// Don't print a progress line per job, e.g. when stdout carries
+    /// machine-readable results.
+    pub quiet: bool,
+    /// Records each row's progress; rows it shows as confirmed are skipped
+    /// and rows still awaiting a receipt are waited on instead of resent.
+    pub checkpoint: Option<Checkpoint>,
+    /// Keeps each row's prepared and signed transaction, receipt and events.
+    pub artifacts: Option<Artifacts>,
+    /// The account nonce the operator expects the (single) sender to
+    /// continue from, checked against the chain before anything is sent.
+    pub from_nonce: Option<U256>,
+}
+
+pub enum Status {
+    Confirmed { block: Option<U64>, gas_used: U256 },
+    Reverted { block: Option<U64> },
+    /// The node stopped reporting the transaction before it was mined.
+    Dropped,
+    /// Not sent because it would be a duplicate.
+    Skipped(String),
+    Failed(String),
+}
+
+impl Status {
+    pub fn is_confirmed(&self) -> bool {
+        matches!(self, Status::Confirmed { .. })
+    }
+
+    /// Short machine-readable name of the status.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Confirmed { .. } => "confirmed",
+            Status::Reverted { .. } => "reverted",
+            Status::Dropped => "dropped",
+            Status::Skipped(_) => "skipped",
+            Status::Failed(_) => "failed",
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Confirmed { block, gas_used } => write!(
+                f,
+                "{}",
+                style::ok(&format!("confirmed in block {} (gas used {})", block.unwrap_or_default(), gas_used))
+            ),
+            Status::Reverted { block } => {
+                write!(f, "{}", style::error(&format!("reverted in block {}", block.unwrap_or_default())))
+            }
+            Status::Dropped => write!(f, "{}", style::error("dropped (receipt not found)")),
+            Status::Skipped(reason) => write!(f, "⏭️  skipped: {}", reason),
+            Status::Failed(reason) => write!(f, "{}", style::error(&format!("failed: {}", reason))),
+        }
+    }
+}
+
+pub struct Outcome {
+    /// Position of the job in the batch, starting at 0.
+    pub index: usize,
+    /// Account the job was (or would have been) sent from.
+    pub sender: Address,
+    pub nonce: Option<U256>,
+    pub tx_hash: Option<H256>,
+    pub status: Status,
+    /// Gas used and the price paid per gas, in wei, once mined.
+    pub gas_used: Option<U256>,
+    pub effective_gas_price: Option<U256>,
+    /// When the row was broadcast, if in this run, and how long its receipt
+    /// took from then.
+    pub broadcast_at: Option<SystemTime>,
+    pub confirmation_time: Option<Duration>,
+}
+
+impl Outcome {
+    /// The outcome of a row that wasn't broadcast in this run.
+    fn unsent(index: usize, sender: Address, tx_hash: Option<H256>, status: Status) -> Self {
+        Self {
+            index,
+            sender,
+            nonce: None,
+            tx_hash,
+            status,
+            gas_used: None,
+            effective_gas_price: None,
+            broadcast_at: None,
+            confirmation_time: None,
+        }
+    }
+
+    /// Native currency paid for gas, in wei, once mined.
+    pub fn fee(&self) -> Option<U256> {
+        self.gas_used.zip(self.effective_gas_price).map(|(gas, price)| gas * price)
+    }
+}
+
+/// The estimation phase's result for one row: its gas estimate, or why the
+/// lock would revert.
+pub struct RowEstimate {
+    pub index: usize,
+    pub gas: Result<U256, String>,
+}
+
+/// Estimates gas for the rows in `indices` concurrently, each from the sender
+/// [`run`] will deal it to, so rows that would revert can be pulled before
+/// the batch starts. Estimates come back in input order.
+pub async fn estimate(
+    clients: &[Arc<Client>],
+    config: &Config,
+    jobs: &[Job],
+    indices: impl IntoIterator<Item = usize>,
+) -> anyhow::Result<Vec<RowEstimate>> {
+    anyhow::ensure!(!clients.is_empty(), "batch needs at least one sender");
+    let contracts: Vec<_> =
+        clients.iter().map(|client| MyContract::new(config.contract_address, client.clone())).collect();
+
+    let mut estimates: Vec<RowEstimate> = stream::iter(indices)
+        .map(|index| {
+            let contract = &contracts[index % contracts.len()];
+            let job = &jobs[index];
+            async move {
+                let gas = match job.lock_value() {
+                    Ok(value) => contract
+                        .lock(job.user, job.token, job.amount, job.nonce, job.signature.clone())
+                        .value(value)
+                        .estimate_gas()
+                        .await
+                        .map_err(|e| calldata::revert_reason(&e)),
+                    Err(e) => Err(format!("{:#}", e)),
+                };
+                RowEstimate { index, gas }
+            }
+        })
+        .buffer_unordered(ESTIMATE_CONCURRENCY)
+        .collect()
+        .await;
+    estimates.sort_by_key(|estimate| estimate.index);
+    Ok(estimates)
+}
+
+/// Hands out the sending account's nonces sequentially, so transactions can
+/// be broadcast without waiting for the previous one to be mined.
+pub struct NonceTracker {
+    next: U256,
+}
+
+impl NonceTracker {
+    /// Starts from the account's pending transaction count.
+    pub async fn new<M: Sender>(client: &M) -> anyhow::Result<Self>
+    where
+        M::Error: 'static,
+    {
+        let next = client
+            .get_transaction_count(client.sender(), Some(BlockNumber::Pending.into()))
+            .await?;
+        Ok(Self { next })
+    }
+
+    pub fn assign(&mut self) -> U256 {
+        let nonce = self.next;
+        self.next += U256::one();
+        nonce
+    }
+
+    /// Starts from `nonce`, once the account's mined and pending transaction
+    /// counts show it is the next one: neither taken nor leaving a gap.
+    pub async fn starting_at<M: Sender>(client: &M, nonce: U256) -> anyhow::Result<Self>
+    where
+        M::Error: 'static,
+    {
+        let address = client.sender();
+        let mined = client.get_transaction_count(address, Some(BlockNumber::Latest.into())).await?;
+        let pending = client.get_transaction_count(address, Some(BlockNumber::Pending.into())).await?;
+        check_start(nonce, mined, pending).map_err(|reason| anyhow::anyhow!("--from-nonce {}: {}", nonce, reason))?;
+        Ok(Self { next: nonce })
+    }
+
+    /// Re-reads the pending count, e.g. after a rejected broadcast left it
+    /// unclear whether the nonce was consumed.
+    pub async fn resync<M: Sender>(&mut self, client: &M) -> anyhow::Result<()>
+    where
+        M::Error: 'static,
+    {
+        *self = Self::new(client).await?;
+        Ok(())
+    }
+}
+
+/// Why starting at `nonce` would collide with or leave a gap after the
+/// account's `mined` and `pending` transaction counts, if it would.
+fn check_start(nonce: U256, mined: U256, pending: U256) -> Result<(), String> {
+    if nonce < mined {
+        return Err(format!("the account has already mined nonces up to {}; the next one is {}", mined - 1, pending));
+    }
+    if nonce < pending {
+        return Err(format!(
+            "nonces {} to {} are held by pending transactions; wait for them, or cancel them with `pending --cancel`",
+            nonce,
+            pending - 1
+        ));
+    }
+    if nonce > pending {
+        return Err(format!("the next nonce is {}, so starting at {} would leave a gap", pending, nonce));
+    }
+    Ok(())
+}
+
+/// One sending account of the pool, with its own nonce sequence.
+struct PoolAccount {
+    client: Arc<Client>,
+    contract: MyContract<Client>,
+    nonces: NonceTracker,
+    in_flight: usize,
+}
+
+/// Sends every job in `jobs`, returning one outcome per job in input order.
+///
+/// Jobs are dealt round-robin across `clients`; each account keeps at most
+/// `options.concurrency` transactions in flight.
+pub async fn run(
+    clients: &[Arc<Client>],
+    config: &Config,
+    jobs: &[Job],
+    ledger: &Ledger,
+    options: &Options,
+) -> anyhow::Result<Vec<Outcome>> {
+    anyhow::ensure!(!clients.is_empty(), "batch needs at least one sender");
+    anyhow::ensure!(
+        options.from_nonce.is_none() || clients.len() == 1,
+        "--from-nonce needs a single sender, but PRIVATE_KEYS names {}",
+        clients.len()
+    );
+    let mut senders = Vec::with_capacity(clients.len());
+    for client in clients {
+        let nonces = match options.from_nonce {
+            Some(nonce) => NonceTracker::starting_at(&**client, nonce).await?,
+            None => NonceTracker::new(&**client).await?,
+        };
+        senders.push(PoolAccount {
+            client: client.clone(),
+            contract: MyContract::new(config.contract_address, client.clone()),
+            nonces,
+            in_flight: 0,
+        });
+    }
+
+    let limit = options.concurrency.max(1);
+    let mut outcomes = Vec::with_capacity(jobs.len());
+    let mut in_flight = FuturesUnordered::new();
+    let mut queue = jobs.iter().enumerate().peekable();
+    let mut gate_open = true;
+
+    loop {
+        // Keep the pipeline full before waiting on the next receipt.
+        while let Some(&(index, job)) = queue.peek() {
+            let slot = index % senders.len();
+            let sender = &mut senders[slot];
+            if sender.in_flight >= limit {
+                break;
+            }
+            queue.next();
+
+            let from = sender.client.address();
+            match options.checkpoint.as_ref().and_then(|checkpoint| checkpoint.last(index)) {
+                Some(&Record { state: State::Confirmed, sender: previous, tx_hash, .. }) => {
+                    let status = Status::Skipped(format!("confirmed in an earlier run as {:?}", tx_hash.unwrap_or_default()));
+                    if !options.quiet {
+                        println!("[{}] {}", index + 1, status);
+                    }
+                    outcomes.push(Outcome::unsent(index, previous, tx_hash, status));
+                    continue;
+                }
+                Some(&Record {
+                    state: State::Submitted,
+                    sender: previous,
+                    account_nonce: Some(nonce),
+                    tx_hash: Some(tx_hash),
+                    ..
+                }) => {
+                    if !options.quiet {
+                        println!("[{}] Resuming the wait for {:?} from an earlier run", index + 1, tx_hash);
+                    }
+                    sender.in_flight += 1;
+                    let artifacts = options.artifacts.as_ref();
+                    let provider = clients[slot].provider();
+                    let sent = Sent { sender: previous, nonce, tx_hash, estimated: None, broadcast_at: None };
+                    in_flight.push(wait(provider, config, job, artifacts, slot, index, sent));
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(gate) = &options.gas_gate {
+                gate_open = gate_open && gate.wait(&*sender.client, sender.client.provider().get_interval()).await?;
+                if !gate_open {
+                    let status = Status::Skipped("base fee stayed above the threshold".to_string());
+                    if !options.quiet {
+                        println!("[{}] {}", index + 1, status);
+                    }
+                    outcomes.push(Outcome::unsent(index, from, None, status));
+                    continue;
+                }
+            }
+            match broadcast(sender, config, ledger, index, job, options).await? {
+                Ok((nonce, tx_hash, gas)) => {
+                    if let Some(checkpoint) = &options.checkpoint {
+                        checkpoint.record(&Record::new(index, job, State::Submitted, from, Some(nonce), Some(tx_hash)))?;
+                    }
+                    if !options.quiet {
+                        println!("[{}] Broadcast {:?} from {:?} (nonce {})", index + 1, tx_hash, from, nonce);
+                    }
+                    sender.in_flight += 1;
+                    let artifacts = options.artifacts.as_ref();
+                    let provider = clients[slot].provider();
+                    let broadcast_at = Some(SystemTime::now());
+                    let sent = Sent { sender: from, nonce, tx_hash, estimated: Some(gas), broadcast_at };
+                    in_flight.push(wait(provider, config, job, artifacts, slot, index, sent));
+                }
+                Err(status) => {
+                    if !options.quiet {
+                        println!("[{}] {}", index + 1, status);
+                    }
+                    let outcome = Outcome::unsent(index, from, None, status);
+                    run_hook(config, job, &outcome).await;
+                    outcomes.push(outcome);
+                }
+            }
+        }
+
+        match in_flight.next().await {
+            Some((slot, outcome)) => {
+                senders[slot].in_flight -= 1;
+                if let Some(checkpoint) = &options.checkpoint {
+                    let state = if outcome.status.is_confirmed() { State::Confirmed } else { State::Failed };
+                    let job = &jobs[outcome.index];
+                    checkpoint.record(&Record::new(outcome.index, job, state, outcome.sender, outcome.nonce, outcome.tx_hash))?;
+                }
+                if !options.quiet {
+                    println!("[{}] {}", outcome.index + 1, outcome.status);
+                }
+                run_hook(config, &jobs[outcome.index], &outcome).await;
+                outcomes.push(outcome);
+            }
+            None => break,
+        }
+    }
+
+    outcomes.sort_by_key(|outcome| outcome.index);
+    Ok(outcomes)
+}
+
+/// What a batch run achieved and cost, for its closing report.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Report {
+    pub chain_id: u64,
+    pub rows: usize,
+    /// Transactions broadcast, in this run or an earlier one it resumed.
+    pub transactions: usize,
+    pub confirmed: usize,
+    /// Rows that reverted, were dropped or failed.
+    pub failed: usize,
+    pub skipped: usize,
+    /// Native currency paid for gas by every mined transaction, reverted
+    /// ones included, in wei.
+    pub gas_spent: U256,
+    /// Amount locked by the confirmed rows, per token.
+    pub value_locked: BTreeMap<Address, U256>,
+    /// Mean time from broadcast to receipt of the rows mined in this run.
+    pub mean_confirmation_secs: Option<f64>,
+}
+
+impl Report {
+    pub fn new(chain_id: u64, jobs: &[Job], outcomes: &[Outcome]) -> Self {
+        let count = |matches: fn(&Status) -> bool| outcomes.iter().filter(|outcome| matches(&outcome.status)).count();
+        let mut value_locked = BTreeMap::new();
+        for outcome in outcomes.iter().filter(|outcome| outcome.status.is_confirmed()) {
+            let job = &jobs[outcome.index];
+            *value_locked.entry(job.token).or_insert_with(U256::zero) += job.amount;
+        }
+        let times: Vec<Duration> = outcomes.iter().filter_map(|outcome| outcome.confirmation_time).collect();
+        Self {
+            chain_id,
+            rows: outcomes.len(),
+            transactions: outcomes.iter().filter(|outcome| outcome.tx_hash.is_some()).count(),
+            confirmed: count(Status::is_confirmed),
+            failed: count(|status| !matches!(status, Status::Confirmed { .. } | Status::Skipped(_))),
+            skipped: count(|status| matches!(status, Status::Skipped(_))),
+            gas_spent: outcomes.iter().filter_map(Outcome::fee).fold(U256::zero(), |total, fee| total + fee),
+            value_locked,
+            mean_confirmation_secs: match times.len() {
+                0 => None,
+                n => Some(times.iter().sum::<Duration>().as_secs_f64() / n as f64),
+            },
+        }
+    }
+}
+
+/// One job of an exported run report, with amounts in decimal and times in
+/// Unix seconds so spreadsheets take them as they are.
+#[derive(Serialize)]
+pub struct ReportRow {
+    pub row: usize,
+    pub user: Address,
+    pub token: Address,
+    pub amount: String,
+    pub nonce: String,
+    pub sender: Address,
+    pub account_nonce: Option<String>,
+    pub tx_hash: Option<H256>,
+    pub status: &'static str,
+    pub block: Option<u64>,
+    pub gas_used: Option<String>,
+    pub effective_gas_price: Option<String>,
+    pub fee: Option<String>,
+    pub error: Option<String>,
+    pub broadcast_at: Option<u64>,
+    pub confirmed_at: Option<u64>,
+}
+
+impl ReportRow {
+    pub fn new(job: &Job, outcome: &Outcome) -> Self {
+        let (block, error) = match &outcome.status {
+            Status::Confirmed { block, .. } | Status::Reverted { block } => (*block, None),
+            Status::Dropped => (None, Some("transaction dropped".to_string())),
+            Status::Skipped(reason) | Status::Failed(reason) => (None, Some(reason.clone())),
+        };
+        let unix = |time: SystemTime| time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        let confirmed_at = outcome.broadcast_at.zip(outcome.confirmation_time).map(|(at, took)| at + took);
+        Self {
+            row: outcome.index + 1,
+            user: job.user,
+            token: job.token,
+            amount: job.amount.to_string(),
+            nonce: job.nonce.to_string(),
+            sender: outcome.sender,
+            account_nonce: outcome.nonce.map(|nonce| nonce.to_string()),
+            tx_hash: outcome.tx_hash,
+            status: outcome.status.name(),
+            block: block.map(|block| block.as_u64()),
+            gas_used: outcome.gas_used.map(|gas| gas.to_string()),
+            effective_gas_price: outcome.effective_gas_price.map(|price| price.to_string()),
+            fee: outcome.fee().map(|fee| fee.to_string()),
+            error,
+            broadcast_at: outcome.broadcast_at.map(unix),
+            confirmed_at: confirmed_at.map(unix),
+        }
+    }
+}
+
+/// Checks `path` names a report format [`write_report`] writes: `.csv` or
+/// `.json`.
+pub fn check_report_path(path: &Path) -> anyhow::Result<()> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv" | "json") => Ok(()),
+        _ => anyhow::bail!("--report {} must end in .csv or .json", path.display()),
+    }
+}
+
+/// Writes the run report to `path`: one row per job as CSV, or, as JSON,
+/// the summary with the rows under `jobs`.
+pub fn write_report(path: &Path, report: &Report, jobs: &[Job], outcomes: &[Outcome]) -> anyhow::Result<()> {
+    check_report_path(path)?;
+    let rows = outcomes.iter().map(|outcome| ReportRow::new(&jobs[outcome.index], outcome));
+    let failed = || format!("failed to write the report to {}", path.display());
+    if path.extension().is_some_and(|extension| extension == "csv") {
+        let mut writer = csv::Writer::from_path(path).with_context(failed)?;
+        for row in rows {
+            writer.serialize(row).with_context(failed)?;
+        }
+        writer.flush().with_context(failed)?;
+        return Ok(());
+    }
+    let json = serde_json::json!({ "summary": report, "jobs": rows.collect::<Vec<_>>() });
+    std::fs::write(path, serde_json::to_string_pretty(&json)?).with_context(failed)
+}
+
+/// Runs the post-confirm or failure hook for a finished row; a skipped row
+/// runs neither.
+async fn run_hook(config: &Config, job: &Job, outcome: &Outcome) {
+    let mut payload = hooks::payload(KIND, config, outcome.sender, job);
+    let (block, gas_used, error) = match &outcome.status {
+        Status::Confirmed { block, gas_used } => (*block, Some(*gas_used), None),
+        Status::Reverted { block } => (*block, None, Some("transaction reverted".to_string())),
+        Status::Dropped => (None, None, Some("transaction dropped".to_string())),
+        Status::Failed(reason) => (None, None, Some(reason.clone())),
+        Status::Skipped(_) => return,
+    };
+    let hook = hooks::settle(&mut payload, outcome.tx_hash, block, gas_used, error);
+    hooks::notify(hook, &payload).await;
+}
+
+/// Checks, estimates and broadcasts a single job. The inner error is the
+/// job's final status when it was not sent; the outer one aborts the batch.
+async fn broadcast(
+    sender: &mut PoolAccount,
+    config: &Config,
+    ledger: &Ledger,
+    index: usize,
+    job: &Job,
+    options: &Options,
+) -> anyhow::Result<Result<(U256, H256, U256), Status>> {
+    if let Some(previous) = ledger.find(KIND, config.chain_id, config.contract_address, job).await? {
+        if !options.force {
+            return Ok(Err(Status::Skipped(format!("already submitted as {:?}", previous.tx_hash))));
+        }
+    }
+    if let Err(e) = policy::screen(sender.client.address(), config.contract_address, job) {
+        return Ok(Err(Status::Failed(format!("{:#}", e))));
+    }
+    let contract = &sender.contract;
+    if nonce::is_lock_nonce_used(contract, job.user, job.token, job.nonce).await? {
+        return Ok(Err(Status::Skipped(format!("nonce {} already processed on-chain", job.nonce))));
+    }
+
+    let call = contract
+        .lock(job.user, job.token, job.amount, job.nonce, job.signature.clone())
+        .value(job.lock_value()?);
+    let gas = match call.estimate_gas().await {
+        Ok(gas) => gas,
+        Err(e) => return Ok(Err(Status::Failed(format!("gas estimation failed: {}", calldata::revert_reason(&e))))),
+    };
+
+    let mut tx = call.tx;
+    options.fees.set_transaction_type(&mut tx);
+    if let Ok(fees) = options.fees.suggest(&*sender.client).await {
+        fees.apply(&mut tx);
+    }
+    let preflight = Preflight { kind: KIND, config, client: &sender.client, sender: sender.client.address(), job, tx: &tx };
+    if let Err(reason) = plugins::run(&preflight).await {
+        return Ok(Err(Status::Failed(reason)));
+    }
+
+    let nonce = sender.nonces.assign();
+    tx.set_nonce(nonce);
+    tx.set_gas(gas);
+
+    let raw = match audit::sign(&*sender.client, &mut tx, KIND).await {
+        Ok(raw) => raw,
+        Err(e) => {
+            sender.nonces.resync(&*sender.client).await?;
+            return Ok(Err(Status::Failed(format!("signing failed: {:#}", e))));
+        }
+    };
+    if let Some(artifacts) = &options.artifacts {
+        artifacts.write_prepared(index, job, &tx)?;
+        artifacts.write_signed(index, &raw)?;
+    }
+
+    let signed_hash = H256(ethers::utils::keccak256(&raw));
+    let entry = match pipeline::claim(ledger, KIND, config, job, signed_hash, options.force).await {
+        Ok(entry) => entry,
+        Err(e) => {
+            let Some(Error::DuplicateSubmission { tx_hash }) = e.downcast_ref::<Error>() else {
+                return Err(e);
+            };
+            sender.nonces.resync(&*sender.client).await?;
+            return Ok(Err(Status::Skipped(format!("already submitted as {:?}", tx_hash))));
+        }
+    };
+    let tx_hash = match audit::broadcast(&*sender.client, raw, KIND).await {
+        Ok(pending) => pending.tx_hash(),
+        Err(e) => {
+            ledger.release(&entry).await?;
+            sender.nonces.resync(&*sender.client).await?;
+            return Ok(Err(Status::Failed(format!("broadcast failed: {:#}", e))));
+        }
+    };
+
+    Ok(Ok((nonce, tx_hash, gas)))
+}
+
+/// A broadcast transaction to wait for.
+struct Sent {
+    sender: Address,
+    nonce: U256,
+    tx_hash: H256,
+    /// Its gas estimate and when it was broadcast, unless that was in an
+    /// earlier run.
+    estimated: Option<U256>,
+    broadcast_at: Option<SystemTime>,
+}
+
+async fn wait(
+    provider: &Rpc,
+    config: &Config,
+    job: &Job,
+    artifacts: Option<&Artifacts>,
+    slot: usize,
+    index: usize,
+    sent: Sent,
+) -> (usize, Outcome) {
+    let Sent { sender, nonce, tx_hash, estimated, broadcast_at } = sent;
+    let receipt = PendingTransaction::new(tx_hash, provider).await;
+    let confirmation_time = broadcast_at.and_then(|broadcast_at| broadcast_at.elapsed().ok());
+    let (gas_used, effective_gas_price) = match &receipt {
+        Ok(Some(receipt)) => (receipt.gas_used, receipt.effective_gas_price),
+        _ => (None, None),
+    };
+    if let Ok(Some(receipt)) = &receipt {
+        // The transaction is already mined, so a write failure must not end the batch.
+        if let Err(e) = audit::record_receipt(sender, KIND, receipt) {
+            print_warn!("[{}] Could not record the receipt in the audit log: {:#}", index + 1, e);
+        }
+        if let Some(Err(e)) = artifacts.map(|artifacts| artifacts.write_receipt(index, receipt)) {
+            print_warn!("[{}] Could not write the receipt artifacts: {:#}", index + 1, e);
+        }
+        if let Some(estimated) = estimated {
+            let usage = gas_usage::Usage::new(KIND, config.chain_id, estimated, receipt);
+            match gas_usage::record_and_check(&usage) {
+                Ok(Some(regression)) => print_warn!("[{}] {}", index + 1, regression.describe(&usage)),
+                Ok(None) => {}
+                Err(e) => print_warn!("[{}] Could not record the gas usage: {:#}", index + 1, e),
+            }
+        }
+    }
+    let status = match receipt {
+        Ok(Some(receipt)) if receipt.status == Some(U64::from(1)) => {
+            let provider = Arc::new(provider.clone());
+            match postcheck::verify_lock(provider, config.contract_address, job, &receipt).await {
+                Ok(()) => {
+                    Status::Confirmed { block: receipt.block_number, gas_used: receipt.gas_used.unwrap_or_default() }
+                }
+                Err(e) => Status::Failed(format!("{:#}", e)),
+            }
+        }
+        Ok(Some(receipt)) => Status::Reverted { block: receipt.block_number },
+        Ok(None) => Status::Dropped,
+        Err(e) => Status::Failed(format!("receipt wait failed: {}", e)),
+    };
+    let outcome = Outcome {
+        index,
+        sender,
+        nonce: Some(nonce),
+        tx_hash: Some(tx_hash),
+        status,
+        gas_used,
+        effective_gas_price,
+        broadcast_at,
+        confirmation_time,
+    };
+    (slot, outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_files_round_trip() {
+        let job = Job {
+            user: Address::repeat_byte(1),
+            token: Address::repeat_byte(2),
+            amount: 1_500.into(),
+            nonce: 26.into(),
+            signature: [[0x11; 64].as_slice(), &[27]].concat().into(),
+        };
+        let path = std::env::temp_dir().join(format!("batch-test-{}.csv", std::process::id()));
+        write_jobs(&path, std::slice::from_ref(&job)).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.lines().nth(1).unwrap().contains(",1500,0x1a,0x"));
+        assert_eq!(read_jobs(&path).unwrap(), vec![job]);
+
+        // A nonce without the prefix is decimal, as NONCE is.
+        std::fs::write(&path, written.replace(",0x1a,", ",26,")).unwrap();
+        assert_eq!(read_jobs(&path).unwrap()[0].nonce, 26.into());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reports() {
+        let job = |token: u8, amount: u64| Job {
+            user: Address::zero(),
+            token: Address::repeat_byte(token),
+            amount: amount.into(),
+            nonce: U256::zero(),
+            signature: Bytes::default(),
+        };
+        let jobs = [job(1, 100), job(1, 50), job(2, 7), job(1, 1_000), job(1, 5)];
+        let mined = |index, status, gas_used: u64, secs: Option<u64>| Outcome {
+            index,
+            sender: Address::zero(),
+            nonce: Some(index.into()),
+            tx_hash: Some(H256::zero()),
+            status,
+            gas_used: Some(gas_used.into()),
+            effective_gas_price: Some(2.into()),
+            broadcast_at: None,
+            confirmation_time: secs.map(Duration::from_secs),
+        };
+        let confirmed = || Status::Confirmed { block: None, gas_used: U256::zero() };
+        let outcomes = [
+            mined(0, confirmed(), 15, Some(10)),
+            mined(1, confirmed(), 10, Some(20)),
+            // Resumed from an earlier run: its wait wasn't timed.
+            mined(2, confirmed(), 12, None),
+            mined(3, Status::Reverted { block: None }, 8, Some(3)),
+            Outcome::unsent(4, Address::zero(), None, Status::Skipped("already submitted".to_string())),
+        ];
+        let report = Report::new(1, &jobs, &outcomes);
+        assert_eq!((report.rows, report.transactions), (5, 4));
+        assert_eq!((report.confirmed, report.failed, report.skipped), (3, 1, 1));
+        assert_eq!(report.gas_spent, U256::from(90));
+        let locked: Vec<_> = report.value_locked.into_iter().collect();
+        assert_eq!(locked, [(Address::repeat_byte(1), U256::from(150)), (Address::repeat_byte(2), U256::from(7))]);
+        assert_eq!(report.mean_confirmation_secs, Some(11.0));
+
+        let row = ReportRow::new(&jobs[0], &outcomes[0]);
+        assert_eq!((row.row, row.amount.as_str(), row.fee.as_deref()), (1, "100", Some("30")));
+        let row = ReportRow::new(&jobs[4], &outcomes[4]);
+        assert_eq!((row.status, row.error.as_deref(), row.fee), ("skipped", Some("already submitted"), None));
+        assert!(check_report_path(Path::new("run.csv")).is_ok() && check_report_path(Path::new("run.txt")).is_err());
+    }
+
+    #[test]
+    fn starting_nonces() {
+        let check = |nonce: u64| check_start(nonce.into(), 10.into(), 12.into());
+        assert!(check(12).is_ok());
+        assert_eq!(check(9).unwrap_err(), "the account has already mined nonces up to 9; the next one is 12");
+        assert!(check(10).unwrap_err().starts_with("nonces 10 to 11 are held by pending transactions"));
+        assert_eq!(check(13).unwrap_err(), "the next nonce is 12, so starting at 13 would leave a gap");
+        assert!(check_start(5.into(), 5.into(), 5.into()).is_ok());
+    }
+
+    fn options() -> Options {
+        Options {
+            concurrency: 2,
+            force: false,
+            fees: FeeModel { oracle: crate::profile::FeeOracle::Node, ..FeeModel::default() },
+            gas_gate: None,
+            quiet: true,
+            checkpoint: None,
+            artifacts: None,
+            from_nonce: None,
+        }
+    }
+
+    fn test_job(nonce: u64) -> Job {
+        Job {
+            user: Address::repeat_byte(1),
+            token: Address::repeat_byte(2),
+            amount: 100.into(),
+            nonce: nonce.into(),
+            signature: Bytes::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn resyncing_nonces_after_a_rejected_broadcast() {
+        use crate::mock_node::{self, MockNode, Reply};
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // Nonce 5 turns out to be taken by a transaction sent elsewhere.
+        let pending = Arc::new(AtomicU64::new(5));
+        let node_pending = pending.clone();
+        let node = MockNode::start(move |method, params| match method {
+            "eth_getTransactionCount" => {
+                Reply::Result(serde_json::json!(U256::from(node_pending.load(Ordering::SeqCst))))
+            }
+            "eth_sendRawTransaction" if mock_node::transaction(params)["nonce"] == "0x5" => {
+                node_pending.store(6, Ordering::SeqCst);
+                Reply::Error("nonce too low".to_string())
+            }
+            "eth_sendRawTransaction" => Reply::Result(serde_json::json!(mock_node::raw_hash(params))),
+            _ => mock_node::idle(method, params),
+        })
+        .await;
+        let client = node.client();
+        let config = Config {
+            rpc_url: node.url.clone(),
+            chain_id: mock_node::CHAIN_ID,
+            contract_address: Address::repeat_byte(9),
+        };
+        let path = std::env::temp_dir().join(format!("batch-resync-{}.jsonl", std::process::id()));
+        let ledger = Ledger::new(&path);
+        let mut sender = PoolAccount {
+            client: client.clone(),
+            contract: MyContract::new(config.contract_address, client.clone()),
+            nonces: NonceTracker::new(&*client).await.unwrap(),
+            in_flight: 0,
+        };
+        let (job, options) = (test_job(1), options());
+
+        let status = broadcast(&mut sender, &config, &ledger, 0, &job, &options).await.unwrap().unwrap_err();
+        assert!(matches!(&status, Status::Failed(reason) if reason.contains("nonce too low")), "{}", status);
+        assert!(ledger.find(KIND, config.chain_id, config.contract_address, &job).await.unwrap().is_none());
+
+        let (nonce, tx_hash, gas) = broadcast(&mut sender, &config, &ledger, 0, &job, &options)
+            .await
+            .unwrap()
+            .map_err(|status| status.to_string())
+            .unwrap();
+        assert_eq!((nonce, gas), (6.into(), 21_000.into()));
+        assert_eq!(sender.nonces.assign(), 7.into());
+        let entry = ledger.find(KIND, config.chain_id, config.contract_address, &job).await.unwrap().unwrap();
+        assert_eq!(entry.tx_hash, tx_hash);
+
+        // The ledger now holds the job, so it isn't sent again.
+        let status = broadcast(&mut sender, &config, &ledger, 0, &job, &options).await.unwrap().unwrap_err();
+        assert!(matches!(status, Status::Skipped(_)));
+        assert_eq!(node.calls("eth_sendRawTransaction").len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resuming_from_a_checkpoint() {
+        use crate::mock_node::{self, MockNode, Reply};
+
+        // The second row's transaction from the earlier run was mined but
+        // reverted; the third row's is sent now and then dropped.
+        let earlier = H256::repeat_byte(0xe1);
+        let node = MockNode::start(move |method, params| match method {
+            "eth_getTransactionCount" => Reply::Result(serde_json::json!(U256::from(4))),
+            "eth_sendRawTransaction" => Reply::Result(serde_json::json!(mock_node::raw_hash(params))),
+            "eth_getTransactionByHash" if params[0] == serde_json::json!(earlier) => {
+                Reply::Result(serde_json::json!(Transaction {
+                    hash: earlier,
+                    block_number: Some(100.into()),
+                    ..Default::default()
+                }))
+            }
+            "eth_getTransactionReceipt" if params[0] == serde_json::json!(earlier) => {
+                Reply::Result(mock_node::receipt(earlier, false))
+            }
+            _ => mock_node::idle(method, params),
+        })
+        .await;
+        let client = node.client();
+        let config = Config {
+            rpc_url: node.url.clone(),
+            chain_id: mock_node::CHAIN_ID,
+            contract_address: Address::repeat_byte(9),
+        };
+        let jobs = [test_job(1), test_job(2), test_job(3)];
+        let dir = std::env::temp_dir().join(format!("batch-checkpoint-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let from = client.address();
+        let checkpoint = Checkpoint::create(dir.join("jobs.csv.checkpoint")).unwrap();
+        checkpoint
+            .record(&Record::new(0, &jobs[0], State::Confirmed, from, Some(2.into()), Some(H256::repeat_byte(0xc0))))
+            .unwrap();
+        checkpoint.record(&Record::new(1, &jobs[1], State::Submitted, from, Some(3.into()), Some(earlier))).unwrap();
+
+        let checkpoint = Some(Checkpoint::resume(checkpoint.path(), &jobs).unwrap());
+        let options = Options { checkpoint, ..options() };
+        let ledger = Ledger::new(dir.join("ledger.jsonl"));
+        let outcomes = run(&[client], &config, &jobs, &ledger, &options).await.unwrap();
+
+        let statuses: Vec<_> = outcomes.iter().map(|outcome| outcome.status.name()).collect();
+        assert_eq!(statuses, ["skipped", "reverted", "dropped"]);
+        assert_eq!((outcomes[1].nonce, outcomes[1].tx_hash), (Some(3.into()), Some(earlier)));
+        assert_eq!(outcomes[2].nonce, Some(4.into()));
+        assert_eq!(node.calls("eth_sendRawTransaction").len(), 1);
+
+        // The next resume retries the reverted and dropped rows only.
+        let resumed = Checkpoint::resume(dir.join("jobs.csv.checkpoint"), &jobs).unwrap();
+        let states: Vec<_> = (0..3).map(|index| resumed.last(index).unwrap().state).collect();
+        assert_eq!(states, [State::Confirmed, State::Failed, State::Failed]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! RPC provider benchmarking: latency and error rate of the calls the relayer
+//! depends on, measured one request at a time.
+
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use std::time::{Duration, Instant};
+
+/// The calls each provider is measured on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    BlockNumber,
+    Call,
+    EstimateGas,
+}
+
+impl Method {
+    pub const ALL: [Method; 3] = [Method::BlockNumber, Method::Call, Method::EstimateGas];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Method::BlockNumber => "eth_blockNumber",
+            Method::Call => "eth_call",
+            Method::EstimateGas => "eth_estimateGas",
+        }
+    }
+}
+
+/// Results of one method against one provider.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    /// Latencies of the successful requests, sorted.
+    pub latencies: Vec<Duration>,
+    pub errors: usize,
+    pub first_error: Option<String>,
+}
+
+impl Stats {
+    pub fn requests(&self) -> usize {
+        self.latencies.len() + self.errors
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        self.errors as f64 / self.requests().max(1) as f64
+    }
+
+    /// The latency below which `percent` of the successful requests fell.
+    pub fn percentile(&self, percent: usize) -> Option<Duration> {
+        let last = self.latencies.len().checked_sub(1)?;
+        Some(self.latencies[(last * percent + 50) / 100])
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.latencies.len()).ok().filter(|&n| n > 0)?;
+        Some(self.latencies.iter().sum::<Duration>() / count)
+    }
+}
+
+/// A plain value-less call between two accounts without code, which every
+/// node can execute and which never reverts, so errors are the provider's.
+fn probe() -> TypedTransaction {
+    TransactionRequest::new().from(Address::zero()).to(Address::zero()).value(0).into()
+}
+
+/// Sends `method` to `provider` `calls` times in sequence.
+pub async fn measure(provider: &Provider<Http>, method: Method, calls: usize) -> Stats {
+    let tx = probe();
+    let mut stats = Stats::default();
+    for _ in 0..calls {
+        let started = Instant::now();
+        let result = match method {
+            Method::BlockNumber => provider.get_block_number().await.map(drop),
+            Method::Call => provider.call(&tx, None).await.map(drop),
+            Method::EstimateGas => provider.estimate_gas(&tx, None).await.map(drop),
+        };
+        match result {
+            Ok(()) => stats.latencies.push(started.elapsed()),
+            Err(e) => {
+                stats.errors += 1;
+                stats.first_error.get_or_insert_with(|| e.to_string());
+            }
+        }
+    }
+    stats.latencies.sort();
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let stats = Stats {
+            latencies: (1..=10).map(Duration::from_millis).collect(),
+            errors: 10,
+            first_error: None,
+        };
+        assert_eq!(stats.percentile(50), Some(Duration::from_millis(6)));
+        assert_eq!(stats.percentile(95), Some(Duration::from_millis(10)));
+        assert_eq!(stats.mean(), Some(Duration::from_micros(5_500)));
+        assert_eq!(stats.error_rate(), 0.5);
+        assert_eq!(Stats::default().percentile(50), None);
+    }
+}
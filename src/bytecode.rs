@@ -0,0 +1,116 @@
+//! Static inspection of deployed bytecode.
+
+use ethers::abi::Abi;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+
+/// EIP-1967 implementation slot: `keccak256("eip1967.proxy.implementation") - 1`.
+pub const EIP1967_IMPLEMENTATION_SLOT: H256 = H256([
+    0x36, 0x08, 0x94, 0xa1, 0x3b, 0xa1, 0xa3, 0x21, 0x06, 0x67, 0xc8, 0x28, 0x49, 0x2d, 0xb9, 0x8d,
+    0xca, 0x3e, 0x20, 0x76, 0xcc, 0x37, 0x35, 0xa9, 0x20, 0xa3, 0xca, 0x50, 0x5d, 0x38, 0x2b, 0xbc,
+]);
+
+/// EIP-1967 beacon slot: `keccak256("eip1967.proxy.beacon") - 1`.
+pub const EIP1967_BEACON_SLOT: H256 = H256([
+    0xa3, 0xf0, 0xad, 0x74, 0xe5, 0x42, 0x3a, 0xeb, 0xfd, 0x80, 0xd3, 0xef, 0x43, 0x46, 0x57, 0x83,
+    0x35, 0xa9, 0xa7, 0x2a, 0xee, 0xd2, 0xdd, 0x8f, 0x3c, 0x0d, 0xa1, 0xb7, 0xc7, 0xa7, 0xc8, 0x4d,
+]);
+
+/// Selector of a beacon's `implementation()`.
+const IMPLEMENTATION_SELECTOR: [u8; 4] = [0x5c, 0x60, 0xda, 0x1b];
+
+const PUSH1: u8 = 0x60;
+const PUSH4: u8 = 0x63;
+const PUSH32: u8 = 0x7f;
+
+/// Returns whether `code` pushes `selector` as a PUSH4 constant, which is how
+/// Solidity's function dispatcher compares selectors. Push data is skipped
+/// while walking, so bytes inside other constants don't produce matches.
+pub fn contains_selector(code: &[u8], selector: [u8; 4]) -> bool {
+    let mut i = 0;
+    while i < code.len() {
+        let op = code[i];
+        if (PUSH1..=PUSH32).contains(&op) {
+            let size = (op - PUSH1 + 1) as usize;
+            if op == PUSH4 && code.get(i + 1..i + 5) == Some(&selector[..]) {
+                return true;
+            }
+            i += 1 + size;
+        } else {
+            i += 1;
+        }
+    }
+    false
+}
+
+/// Signatures of the functions in `abi` that `code` doesn't dispatch.
+pub fn missing_functions(code: &[u8], abi: &Abi) -> Vec<String> {
+    abi.functions()
+        .filter(|function| !contains_selector(code, function.short_signature()))
+        .map(|function| function.signature())
+        .collect()
+}
+
+/// An EIP-1967 proxy and the implementation its calls are delegated to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Proxy {
+    pub implementation: Address,
+    /// The beacon the implementation was read from, for beacon proxies.
+    pub beacon: Option<Address>,
+}
+
+/// Resolves an EIP-1967 proxy at `address`, through its implementation slot
+/// or, for beacon proxies, its beacon. `None` when it isn't a proxy.
+pub async fn resolve_proxy<M: Middleware>(client: &M, address: Address) -> anyhow::Result<Option<Proxy>>
+where
+    M::Error: 'static,
+{
+    if let Some(implementation) = eip1967_implementation(client, address).await? {
+        return Ok(Some(Proxy { implementation, beacon: None }));
+    }
+    let beacon = Address::from(client.get_storage_at(address, EIP1967_BEACON_SLOT, None).await?);
+    if beacon.is_zero() {
+        return Ok(None);
+    }
+    let tx: TypedTransaction = TransactionRequest::new().to(beacon).data(IMPLEMENTATION_SELECTOR.to_vec()).into();
+    let output = client.call(&tx, None).await?;
+    anyhow::ensure!(output.len() == 32, "beacon {:?} of proxy {:?} returned no implementation", beacon, address);
+    let implementation = Address::from(H256::from_slice(&output));
+    Ok(Some(Proxy { implementation, beacon: Some(beacon) }))
+}
+
+/// The implementation behind an EIP-1967 proxy at `address`, if any.
+pub async fn eip1967_implementation<M: Middleware>(client: &M, address: Address) -> anyhow::Result<Option<Address>>
+where
+    M::Error: 'static,
+{
+    let slot = client.get_storage_at(address, EIP1967_IMPLEMENTATION_SLOT, None).await?;
+    let implementation = Address::from(slot);
+    Ok((!implementation.is_zero()).then_some(implementation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispatched_selectors() {
+        let abi = ethers::abi::parse_abi(&["function lock()", "function redeem(uint256)"]).unwrap();
+        let lock = abi.function("lock").unwrap().short_signature();
+        let redeem = abi.function("redeem").unwrap().short_signature();
+
+        // PUSH4 lock; EQ; then PUSH32 whose data happens to hold redeem's selector.
+        let mut code = vec![PUSH4];
+        code.extend(lock);
+        code.push(0x14);
+        code.push(PUSH32);
+        code.extend(redeem);
+        code.extend([0; 28]);
+        assert!(contains_selector(&code, lock));
+        assert!(!contains_selector(&code, redeem));
+        assert_eq!(missing_functions(&code, &abi), vec!["redeem(uint256)".to_string()]);
+
+        // A PUSH4 cut off by the end of the code matches nothing.
+        assert!(!contains_selector(&[PUSH4, lock[0], lock[1]], lock));
+    }
+}
@@ -0,0 +1,338 @@
+//! Encoding and decoding of contract calldata, for handing payloads to other
+//! systems and for triaging transactions this tool did not build itself.
+
+use crate::config::{Job, LOCK_KIND};
+use crate::contract::{LockCall, MyContractCalls, RedeemWithSignatureCall, MYCONTRACT_ABI};
+use anyhow::Context;
+use ethers::abi::token::{LenientTokenizer, Tokenizer};
+use ethers::abi::{Abi, AbiParser, Function, ParamType, RawLog, Token};
+use ethers::abi::{AbiDecode, AbiEncode};
+use ethers::prelude::*;
+
+/// Well-known functions recognised when the selector is not in abi.json, in
+/// the spirit of a small local 4byte directory.
+const KNOWN_SIGNATURES: &[&str] = &[
+    "function transfer(address to, uint256 amount)",
+    "function approve(address spender, uint256 amount)",
+    "function transferFrom(address from, address to, uint256 amount)",
+    "function safeTransferFrom(address from, address to, uint256 tokenId)",
+    "function deposit()",
+    "function withdraw(uint256 amount)",
+    "function execTransaction(address to, uint256 value, bytes data, uint8 operation, uint256 safeTxGas, uint256 baseGas, uint256 gasPrice, address gasToken, address refundReceiver, bytes signatures)",
+    "function multicall(bytes[] data)",
+];
+
+/// Well-known events recognised when abi.json doesn't declare them. ERC-721
+/// shares the ERC-20 topics with the value indexed, so both forms are kept.
+const KNOWN_EVENTS: &[&str] = &[
+    "event Transfer(address indexed from, address indexed to, uint256 value)",
+    "event Approval(address indexed owner, address indexed spender, uint256 value)",
+    "event Transfer(address indexed from, address indexed to, uint256 indexed tokenId)",
+    "event Approval(address indexed owner, address indexed approved, uint256 indexed tokenId)",
+];
+
+/// Where a selector or event was resolved from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Abi,
+    Builtin,
+}
+
+/// A call decoded against a known function.
+pub struct Decoded {
+    pub function: Function,
+    pub source: Source,
+    pub args: Vec<Token>,
+}
+
+/// `lock(...)` calldata for `job`.
+pub fn encode_lock(job: &Job) -> Bytes {
+    LockCall {
+        user: job.user,
+        token: job.token,
+        amount: job.amount,
+        nonce: job.nonce,
+        signature: job.signature.clone(),
+    }
+    .encode()
+    .into()
+}
+
+/// `redeemWithSignature(...)` calldata for `job`.
+pub fn encode_unlock(job: &Job) -> Bytes {
+    RedeemWithSignatureCall {
+        user: job.user,
+        token: job.token,
+        amount: job.amount,
+        nonce: job.nonce,
+        signature: job.signature.clone(),
+    }
+    .encode()
+    .into()
+}
+
+/// The job in `lock(...)` or `redeemWithSignature(...)` calldata, with the
+/// kind of job it is (`lock` or `unlock`).
+pub fn decode_job(data: &[u8]) -> Option<(&'static str, Job)> {
+    let (kind, user, token, amount, nonce, signature) = match MyContractCalls::decode(data).ok()? {
+        MyContractCalls::Lock(call) => (LOCK_KIND, call.user, call.token, call.amount, call.nonce, call.signature),
+        MyContractCalls::RedeemWithSignature(call) => {
+            ("unlock", call.user, call.token, call.amount, call.nonce, call.signature)
+        }
+        _ => return None,
+    };
+    Some((kind, Job { user, token, amount, nonce, signature }))
+}
+
+/// Encodes a call to the function with the human-readable `signature`
+/// (e.g. `recover(address,address)`), parsing each of `args` as its
+/// parameter's type.
+pub fn encode_call(signature: &str, args: &[String]) -> anyhow::Result<Bytes> {
+    let function = AbiParser::default()
+        .parse_function(signature)
+        .with_context(|| format!("invalid function signature {:?}", signature))?;
+    encode_function(&function, args)
+}
+
+/// Encodes a call to `function`, parsing each of `args` as its parameter's
+/// type.
+pub fn encode_function(function: &Function, args: &[String]) -> anyhow::Result<Bytes> {
+    anyhow::ensure!(
+        function.inputs.len() == args.len(),
+        "{} takes {} argument(s), got {}",
+        function.name,
+        function.inputs.len(),
+        args.len()
+    );
+    let tokens = function
+        .inputs
+        .iter()
+        .zip(args)
+        .map(|(param, arg)| {
+            LenientTokenizer::tokenize(&param.kind, arg)
+                .with_context(|| format!("invalid {} argument {:?} for {}", param.kind, arg, param.name))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(function.encode_input(&tokens)?.into())
+}
+
+/// Parses hex calldata, with or without the `0x` prefix.
+pub fn parse_hex(input: &str) -> anyhow::Result<Bytes> {
+    let input = input.trim();
+    let digits = input.strip_prefix("0x").unwrap_or(input);
+    Ok(hex::decode(digits).context("calldata is not valid hex")?.into())
+}
+
+/// Finds the function `data`'s selector belongs to, preferring abi.json over
+/// the built-in list.
+pub fn lookup(selector: [u8; 4]) -> anyhow::Result<Option<(Function, Source)>> {
+    if let Some(function) = MYCONTRACT_ABI.functions().find(|f| f.short_signature() == selector) {
+        return Ok(Some((function.clone(), Source::Abi)));
+    }
+    let mut parser = AbiParser::default();
+    for signature in KNOWN_SIGNATURES {
+        let function = parser.parse_function(signature)?;
+        if function.short_signature() == selector {
+            return Ok(Some((function, Source::Builtin)));
+        }
+    }
+    Ok(None)
+}
+
+/// Decodes `data` as a call to a known function. Returns `None` when the
+/// selector is unknown; fails when it is known but the arguments don't decode.
+pub fn decode(data: &[u8]) -> anyhow::Result<Option<Decoded>> {
+    let Some(selector) = data.get(..4) else {
+        anyhow::bail!("calldata is shorter than a 4-byte selector");
+    };
+    let Some((function, source)) = lookup(selector.try_into()?)? else {
+        return Ok(None);
+    };
+    let args = function
+        .decode_input(&data[4..])
+        .with_context(|| format!("arguments don't decode as {}", function.signature()))?;
+    Ok(Some(Decoded { function, source, args }))
+}
+
+/// Renders a decoded value the way the rest of the tool prints them: checksum
+/// addresses, decimal integers and `0x` hex bytes.
+pub fn format_token(token: &Token) -> String {
+    match token {
+        Token::Address(address) => ethers::utils::to_checksum(address, None),
+        Token::Uint(value) => value.to_string(),
+        Token::Int(value) => I256::from_raw(*value).to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => format!("{:?}", value),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", hex::encode(bytes)),
+        Token::Array(tokens) | Token::FixedArray(tokens) => {
+            format!("[{}]", tokens.iter().map(format_token).collect::<Vec<_>>().join(", "))
+        }
+        Token::Tuple(tokens) => {
+            format!("({})", tokens.iter().map(format_token).collect::<Vec<_>>().join(", "))
+        }
+    }
+}
+
+/// Decodes `log` against abi.json's events, returning the event's name and
+/// its arguments by parameter name.
+pub fn decode_event(log: &Log) -> Option<(String, Arguments)> {
+    decode_event_in(&MYCONTRACT_ABI, log)
+}
+
+/// Decodes `log` against the events of `abi`, as [`decode_event`] does.
+pub fn decode_event_in(abi: &Abi, log: &Log) -> Option<(String, Arguments)> {
+    let topic = log.topics.first()?;
+    let event = abi.events().find(|event| event.signature() == *topic)?;
+    let parsed = event.parse_log(RawLog { topics: log.topics.clone(), data: log.data.to_vec() }).ok()?;
+    Some((event.name.clone(), parsed.params.into_iter().map(|param| (param.name, param.value)).collect()))
+}
+
+/// An event's decoded arguments, by name.
+pub type Arguments = Vec<(String, Token)>;
+
+/// Decodes `log` against abi.json's events and then the built-in ones, for
+/// logs emitted by any contract, such as the token transfers of a lock.
+pub fn decode_any_event(log: &Log) -> Option<(String, Arguments, Source)> {
+    if let Some((name, args)) = decode_event(log) {
+        return Some((name, args, Source::Abi));
+    }
+    let raw = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+    KNOWN_EVENTS.iter().find_map(|signature| {
+        let event = AbiParser::default().parse_event(signature).ok()?;
+        let parsed = event.parse_log(raw.clone()).ok()?;
+        let args = parsed.params.into_iter().map(|param| (param.name, param.value)).collect();
+        Some((event.name, args, Source::Builtin))
+    })
+}
+
+/// Describes revert data: a `require` message, a panic code, or one of
+/// abi.json's custom errors with its arguments.
+pub fn decode_revert(data: &[u8]) -> String {
+    if data.len() < 4 {
+        return match data.is_empty() {
+            true => "reverted without a reason".to_string(),
+            false => format!("reverted with 0x{}", hex::encode(data)),
+        };
+    }
+    let (selector, args) = data.split_at(4);
+    match selector {
+        // Error(string)
+        [0x08, 0xc3, 0x79, 0xa0] => {
+            if let Some(Token::String(reason)) = decode_single(ParamType::String, args) {
+                return format!("reverted: {}", reason);
+            }
+        }
+        // Panic(uint256)
+        [0x4e, 0x48, 0x7b, 0x71] => {
+            if let Some(Token::Uint(code)) = decode_single(ParamType::Uint(256), args) {
+                return format!("panicked with code {:#x}", code);
+            }
+        }
+        _ => {
+            let error = MYCONTRACT_ABI.errors().find(|error| error.signature()[..4] == *selector);
+            if let Some((error, tokens)) = error.and_then(|error| Some((error, error.decode(args).ok()?))) {
+                let args = tokens.iter().map(format_token).collect::<Vec<_>>().join(", ");
+                return format!("reverted with {}({})", error.name, args);
+            }
+        }
+    }
+    format!("reverted with 0x{}", hex::encode(data))
+}
+
+fn decode_single(kind: ParamType, data: &[u8]) -> Option<Token> {
+    ethers::abi::decode(&[kind], data).ok()?.into_iter().next()
+}
+
+/// Why a contract call failed, with the revert data decoded when the node
+/// returned any.
+pub fn revert_reason<M: Middleware>(error: &ContractError<M>) -> String {
+    match error.as_revert() {
+        Some(data) => decode_revert(data),
+        None => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::encode;
+    use proptest::prelude::*;
+
+    fn revert(selector: [u8; 4], args: &[Token]) -> Vec<u8> {
+        [selector.as_slice(), &encode(args)].concat()
+    }
+
+    #[test]
+    fn revert_reasons() {
+        let reason = revert([0x08, 0xc3, 0x79, 0xa0], &[Token::String("nonce used".to_string())]);
+        assert_eq!(decode_revert(&reason), "reverted: nonce used");
+        let panic = revert([0x4e, 0x48, 0x7b, 0x71], &[Token::Uint(U256::from(0x11))]);
+        assert_eq!(decode_revert(&panic), "panicked with code 0x11");
+
+        let selector = ethers::utils::id("ECDSAInvalidSignatureLength(uint256)");
+        let custom = revert(selector, &[Token::Uint(U256::from(64))]);
+        assert_eq!(decode_revert(&custom), "reverted with ECDSAInvalidSignatureLength(64)");
+
+        assert_eq!(decode_revert(&[]), "reverted without a reason");
+        assert_eq!(decode_revert(&[0xde, 0xad, 0xbe, 0xef]), "reverted with 0xdeadbeef");
+    }
+
+    #[test]
+    fn builtin_events() {
+        let (from, to) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let transfer = H256(ethers::utils::keccak256("Transfer(address,address,uint256)"));
+        let erc20 = Log {
+            topics: vec![transfer, from.into(), to.into()],
+            data: encode(&[Token::Uint(U256::from(5))]).into(),
+            ..Default::default()
+        };
+        let (name, args, source) = decode_any_event(&erc20).unwrap();
+        assert_eq!((name.as_str(), source), ("Transfer", Source::Builtin));
+        assert_eq!(args[2], ("value".to_string(), Token::Uint(U256::from(5))));
+
+        let erc721 = Log { topics: vec![transfer, from.into(), to.into(), H256::from_low_u64_be(7)], ..Default::default() };
+        let (_, args, _) = decode_any_event(&erc721).unwrap();
+        assert_eq!(args[2], ("tokenId".to_string(), Token::Uint(U256::from(7))));
+
+        assert!(decode_any_event(&Log { topics: vec![H256::repeat_byte(9)], ..Default::default() }).is_none());
+    }
+
+    #[test]
+    fn encoding_calls_by_signature() {
+        let to = Address::repeat_byte(1);
+        let args = vec![format!("{:?}", to), "1000".to_string()];
+        let data = encode_call("transfer(address,uint256)", &args).unwrap();
+        let selector = ethers::utils::id("transfer(address,uint256)");
+        assert_eq!(data.to_vec(), revert(selector, &[Token::Address(to), Token::Uint(1000.into())]));
+        assert!(encode_call("transfer(address,uint256)", &args[..1]).is_err());
+        assert!(encode_call("transfer(address,uint256)", &["0x01".to_string(), "1000".to_string()]).is_err());
+    }
+
+    #[test]
+    fn jobs_from_calldata() {
+        let job = Job {
+            user: Address::repeat_byte(1),
+            token: Address::repeat_byte(2),
+            amount: 100.into(),
+            nonce: 7.into(),
+            signature: Bytes::from(vec![0xab; 65]),
+        };
+        assert_eq!(decode_job(&encode_lock(&job)), Some((LOCK_KIND, job.clone())));
+        assert_eq!(decode_job(&encode_unlock(&job)), Some(("unlock", job)));
+        assert_eq!(decode_job(&[0xde, 0xad, 0xbe, 0xef]), None);
+    }
+
+    proptest! {
+        #[test]
+        fn encoded_calls_decode_to_their_arguments(to in any::<[u8; 20]>(), amount in any::<u128>(), memo in ".*") {
+            let signature = "transferWithMemo(address,uint256,string)";
+            let args = [format!("{:?}", Address::from(to)), amount.to_string(), memo.clone()];
+            let data = encode_call(signature, &args).unwrap();
+            let function = AbiParser::default().parse_function(signature).unwrap();
+            let selector = function.short_signature();
+            prop_assert_eq!(&data[..4], selector.as_slice());
+            let decoded = function.decode_input(&data[4..]).unwrap();
+            prop_assert_eq!(decoded, vec![Token::Address(to.into()), Token::Uint(amount.into()), Token::String(memo)]);
+        }
+    }
+}
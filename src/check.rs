@@ -0,0 +1,393 @@
+//! Offline configuration validation: every setting is parsed the way the
+//! commands would parse it, and all problems are collected instead of
+//! stopping at the first one. Nothing here touches the network.
+
+use crate::config::{self, env_var};
+use crate::contract::MYCONTRACT_ABI;
+use crate::fees::FeeModel;
+use crate::policy::Policy;
+use crate::profile::{ConfigFile, ReplacementPolicy};
+use crate::secrets;
+use crate::signature;
+use crate::tls;
+use ethers::abi::Abi;
+use ethers::prelude::*;
+use ethers::utils::{parse_ether, to_checksum};
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+pub struct Finding {
+    pub setting: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn new(setting: &str, severity: Severity, message: impl Into<String>) -> Self {
+        Self { setting: setting.to_string(), severity, message: message.into() }
+    }
+}
+
+/// Functions the commands call, which the embedded ABI must provide.
+const REQUIRED_FUNCTIONS: &[&str] = &["lock", "redeemWithSignature", "locks"];
+
+/// Validates the whole configuration, returning one finding per setting.
+pub fn check_config() -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    // A custom chain's profile may stand in for RPC_URL and CHAIN_ID.
+    let file = ConfigFile::load().unwrap_or_default();
+    let named = env_var("PROFILE").and_then(|name| file.profiles.get(&name).cloned());
+    let chain_id = env_var("CHAIN_ID").or_else(|| named.and_then(|profile| profile.chain_id).map(|id| id.to_string()));
+    let active = chain_id.as_ref().and_then(|id| file.active_profile(id.parse().ok()?).ok().flatten());
+    let rpc_url = active.and_then(|(_, profile)| profile.rpc_url.clone());
+    required_or(&mut findings, "RPC_URL", rpc_url, |value| {
+        let url = reqwest::Url::parse(value).map_err(|e| e.to_string())?;
+        match url.scheme() {
+            "http" | "https" => Ok(None),
+            scheme => Err(format!("unsupported scheme {:?}, expected http or https", scheme)),
+        }
+    });
+    required_or(&mut findings, "CHAIN_ID", chain_id, |value| {
+        value.parse::<u64>().map(|_| None).map_err(|e| format!("not a number: {}", e))
+    });
+    required(&mut findings, "CONTRACT_ADDRESS", check_address);
+    secret_key(&mut findings, "PRIVATE_KEY", true);
+
+    required(&mut findings, "USER_ADDRESS", check_address);
+    required(&mut findings, "TOKEN_ADDRESS", check_address);
+    required(&mut findings, "AMOUNT", |value| {
+        U256::from_dec_str(value).map(|_| None).map_err(|e| format!("not a decimal integer: {:?}", e))
+    });
+    required(&mut findings, "NONCE", |value| {
+        config::parse_nonce(value).map(|_| None).map_err(|e| e.to_string())
+    });
+    // With a signing service, signatures are fetched per send instead.
+    if env_var("SIGNER_SERVICE_URL").is_some() {
+        optional(&mut findings, "SIGNATURE", check_signature);
+        required(&mut findings, "SIGNER_SERVICE_URL", |value| {
+            let url = reqwest::Url::parse(value).map_err(|e| e.to_string())?;
+            match url.scheme() {
+                "https" => Ok(None),
+                "http" => Ok(Some("signatures are requested over plain HTTP".to_string())),
+                scheme => Err(format!("unsupported scheme {:?}, expected https", scheme)),
+            }
+        });
+    } else {
+        required(&mut findings, "SIGNATURE", check_signature);
+    }
+
+    optional(&mut findings, "SENDER_ADDRESS", check_address);
+    optional(&mut findings, "NATIVE_TOKEN_ADDRESS", check_address);
+    optional(&mut findings, "LOCK_EXTRA_VALUE", |value| {
+        U256::from_dec_str(value).map(|_| None).map_err(|e| format!("not a wei amount: {:?}", e))
+    });
+    secret_key(&mut findings, "SIMULATION_PRIVATE_KEY", false);
+    secret_key(&mut findings, "TREASURY_PRIVATE_KEY", false);
+    secret_key(&mut findings, "USER_PRIVATE_KEY", false);
+    secret_key(&mut findings, "SESSION_KEY", false);
+    optional(&mut findings, "LOCK_ROUTER", check_address);
+    optional(&mut findings, "CLOCK_SKEW_TOLERANCE", |value| {
+        config::parse_duration(value).map(|_| None).map_err(|e| e.to_string())
+    });
+    optional(&mut findings, "SMART_ACCOUNT", check_address);
+    optional(&mut findings, "ENTRY_POINT", check_address);
+    optional(&mut findings, "ACCOUNT_FACTORY", check_address);
+    optional(&mut findings, "ACCOUNT_OWNER", check_address);
+    optional(&mut findings, "ACCOUNT_SALT", |value| {
+        U256::from_dec_str(value).map(|_| None).map_err(|e| format!("not a decimal integer: {:?}", e))
+    });
+    for name in ["BUNDLER_URL", "PAYMASTER_URL"] {
+        optional(&mut findings, name, |value| {
+            let url = reqwest::Url::parse(value).map_err(|e| e.to_string())?;
+            match url.scheme() {
+                "http" | "https" => Ok(None),
+                scheme => Err(format!("unsupported scheme {:?}, expected http or https", scheme)),
+            }
+        });
+    }
+    if env_var("TREASURY_PRIVATE_KEY").is_some() {
+        for name in ["GAS_TANK_THRESHOLD", "GAS_TANK_TOP_UP"] {
+            required(&mut findings, name, |value| {
+                parse_ether(value).map(|_| None).map_err(|e| format!("not an ether amount: {}", e))
+            });
+        }
+    }
+    optional(&mut findings, "WS_RPC_URL", |value| {
+        if value.starts_with("ws://") || value.starts_with("wss://") {
+            Ok(None)
+        } else {
+            Err("must be a ws:// or wss:// URL".to_string())
+        }
+    });
+    let providers = 1 + env_var("RPC_URLS").unwrap_or_default().split(',').filter(|url| !url.trim().is_empty()).count();
+    optional(&mut findings, "READ_QUORUM", |value| match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) if n > providers => Err(format!("more than the {} providers in RPC_URL and RPC_URLS", providers)),
+        Ok(_) => Ok(None),
+        Err(e) => Err(format!("not a provider count: {}", e)),
+    });
+    optional(&mut findings, "CROSS_CHECK", |value| match value {
+        "warn" | "refuse" | "off" => Ok(None),
+        _ => Err("must be warn, refuse or off".to_string()),
+    });
+    optional(&mut findings, "CROSS_CHECK_GAS_PERCENT", |value| {
+        value.parse::<u64>().map(|_| None).map_err(|e| format!("not a whole percentage: {}", e))
+    });
+    optional(&mut findings, "TLS_BACKEND", |value| match tls::Backend::parse(Some(value)) {
+        Ok(_) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    });
+    optional(&mut findings, "RPC_BREAKER_FAILURES", |value| match value.parse::<u32>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(_) => Ok(None),
+        Err(e) => Err(format!("not a failure count: {}", e)),
+    });
+    optional(&mut findings, "RPC_BREAKER_COOLDOWN", |value| {
+        config::parse_duration(value).map(|_| None).map_err(|e| e.to_string())
+    });
+    optional(&mut findings, "RPC_RETRY_BUDGET", |value| {
+        value.parse::<u32>().map(|_| None).map_err(|e| format!("not a retry count: {}", e))
+    });
+    optional(&mut findings, "FEE_HISTORY_BLOCKS", |value| match value.parse::<u64>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(_) => Ok(None),
+        Err(e) => Err(format!("not a block count: {}", e)),
+    });
+    optional(&mut findings, "FEE_PERCENTILE", |value| match value.parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(None),
+        Ok(_) => Err("must be between 0 and 100".to_string()),
+        Err(e) => Err(format!("not a number: {}", e)),
+    });
+    optional(&mut findings, "PRICE_SOURCE", |value| match value {
+        "chainlink" | "coingecko" => Ok(None),
+        _ => Err("must be chainlink or coingecko".to_string()),
+    });
+    optional(&mut findings, "NATIVE_USD_FEED", check_address);
+    optional(&mut findings, "TOKEN_USD_FEED", check_address);
+    if let Some(keys) = env_var("PRIVATE_KEYS") {
+        for (i, key) in keys.split(',').map(str::trim).filter(|k| !k.is_empty()).enumerate() {
+            let name = format!("PRIVATE_KEYS[{}]", i);
+            findings.push(match check_key(key) {
+                Ok(message) => Finding::new(&name, Severity::Ok, message),
+                Err(message) => Finding::new(&name, Severity::Error, message),
+            });
+        }
+    }
+
+    findings.push(check_config_file());
+    if env_var("POLICY_FILE").is_some() {
+        findings.push(match Policy::load() {
+            Ok(_) => Finding::new("POLICY_FILE", Severity::Ok, "signing policy ok"),
+            Err(e) => Finding::new("POLICY_FILE", Severity::Error, format!("{:#}", e)),
+        });
+    }
+    findings.push(check_abi());
+    findings
+}
+
+/// Parses a required setting with `check`, which returns an optional warning.
+fn required(findings: &mut Vec<Finding>, name: &str, check: impl Fn(&str) -> Result<Option<String>, String>) {
+    match env_var(name) {
+        Some(value) => findings.push(evaluate(name, &value, check)),
+        None => findings.push(Finding::new(name, Severity::Error, "not set")),
+    }
+}
+
+/// Like [`required`], but falling back to `fallback`, e.g. from the profile,
+/// when the setting isn't set.
+fn required_or(
+    findings: &mut Vec<Finding>,
+    name: &str,
+    fallback: Option<String>,
+    check: impl Fn(&str) -> Result<Option<String>, String>,
+) {
+    match env_var(name).or(fallback) {
+        Some(value) => findings.push(evaluate(name, &value, check)),
+        None => findings.push(Finding::new(name, Severity::Error, "not set")),
+    }
+}
+
+fn optional(findings: &mut Vec<Finding>, name: &str, check: impl Fn(&str) -> Result<Option<String>, String>) {
+    if let Some(value) = env_var(name) {
+        findings.push(evaluate(name, &value, check));
+    }
+}
+
+fn evaluate(name: &str, value: &str, check: impl Fn(&str) -> Result<Option<String>, String>) -> Finding {
+    match check(value) {
+        Ok(None) => Finding::new(name, Severity::Ok, "ok"),
+        Ok(Some(warning)) => Finding::new(name, Severity::Warning, warning),
+        Err(error) => Finding::new(name, Severity::Error, error),
+    }
+}
+
+fn secret_key(findings: &mut Vec<Finding>, name: &str, required: bool) {
+    let Some(value) = env_var(name) else {
+        if required {
+            findings.push(Finding::new(name, Severity::Error, "not set"));
+        }
+        return;
+    };
+    findings.push(match check_key(&value) {
+        Ok(message) => Finding::new(name, Severity::Ok, message),
+        Err(message) => Finding::new(name, Severity::Error, message),
+    });
+}
+
+/// Checks a private key or secret reference. Vault references are not
+/// resolved, since that would need the network.
+fn check_key(value: &str) -> Result<String, String> {
+    if value.starts_with("vault:") {
+        return Ok("Vault reference (not resolved offline)".to_string());
+    }
+    let key = secrets::resolve(value).map_err(|e| e.to_string())?;
+    let wallet = key.parse::<LocalWallet>().map_err(|e| format!("not a valid private key: {}", e))?;
+    Ok(format!("wallet {:?}", wallet.address()))
+}
+
+fn check_address(value: &str) -> Result<Option<String>, String> {
+    let address = value.parse::<Address>().map_err(|e| format!("not an address: {}", e))?;
+    let hex = value.trim_start_matches("0x");
+    let mixed_case = hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+    let checksummed = to_checksum(&address, None);
+    if !mixed_case {
+        return Ok(Some(format!("not checksummed, expected {}", checksummed)));
+    }
+    if value != checksummed {
+        return Err(format!("bad checksum, expected {}", checksummed));
+    }
+    Ok(None)
+}
+
+fn check_signature(value: &str) -> Result<Option<String>, String> {
+    let bytes = hex::decode(value.trim_start_matches("0x")).map_err(|e| format!("not valid hex: {}", e))?;
+    let normalized = signature::normalize(&bytes).map_err(|e| e.to_string())?;
+    match normalized.as_ref() == bytes.as_slice() {
+        true => Ok(None),
+        false => Ok(Some("not in canonical r, s, v form; it is normalized before use".to_string())),
+    }
+}
+
+/// Checks the embedded ABI has the functions we call and, if an `abi.json`
+/// sits in the working directory, that it still matches what was compiled in.
+fn check_abi() -> Finding {
+    let embedded: &Abi = &MYCONTRACT_ABI;
+    let missing: Vec<_> = REQUIRED_FUNCTIONS
+        .iter()
+        .filter(|name| embedded.function(name).is_err())
+        .collect();
+    if !missing.is_empty() {
+        return Finding::new("ABI", Severity::Error, format!("embedded ABI lacks {:?}", missing));
+    }
+
+    let path = Path::new("abi.json");
+    if !path.exists() {
+        return Finding::new("ABI", Severity::Ok, "embedded ABI ok");
+    }
+    let on_disk: Abi = match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|s| {
+        serde_json::from_str(&s).map_err(|e| e.to_string())
+    }) {
+        Ok(abi) => abi,
+        Err(e) => return Finding::new("ABI", Severity::Error, format!("abi.json does not parse: {}", e)),
+    };
+    if on_disk != *embedded {
+        return Finding::new("ABI", Severity::Warning, "abi.json differs from the ABI compiled into this binary; rebuild");
+    }
+    Finding::new("ABI", Severity::Ok, "embedded ABI ok, matches abi.json")
+}
+
+/// Checks the config file parses, the active profile for CHAIN_ID resolves,
+/// and the fee parameters it yields are in range.
+fn check_config_file() -> Finding {
+    let name = "CONFIG_FILE";
+    let file = match ConfigFile::load() {
+        Ok(file) => file,
+        Err(e) => return Finding::new(name, Severity::Error, format!("{:#}", e)),
+    };
+    let Some(chain_id) = env_var("CHAIN_ID").and_then(|id| id.parse().ok()) else {
+        return Finding::new(name, Severity::Ok, format!("{} profile(s) defined", file.profiles.len()));
+    };
+    let result = file.active_profile(chain_id).and_then(|active| {
+        FeeModel::from_env(chain_id)?;
+        ReplacementPolicy::for_chain(chain_id)?;
+        Ok(active.map(|(name, _)| name.to_string()))
+    });
+    match result {
+        Ok(Some(profile)) => Finding::new(name, Severity::Ok, format!("using profile {:?}", profile)),
+        Ok(None) => Finding::new(name, Severity::Ok, format!("no profile for chain {}", chain_id)),
+        Err(e) => Finding::new(name, Severity::Error, format!("{:#}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings() {
+        let checksummed = "0x5FbDB2315678afecb367f032d93F642f64180aa3";
+        assert_eq!(check_address(checksummed), Ok(None));
+        let lowercase = check_address(&checksummed.to_lowercase()).unwrap().unwrap();
+        assert_eq!(lowercase, format!("not checksummed, expected {}", checksummed));
+        let miscased = checksummed.replace("5FbDB", "5fBdb");
+        assert!(check_address(&miscased).unwrap_err().starts_with("bad checksum"));
+        assert!(check_address("0x1234").unwrap_err().starts_with("not an address"));
+
+        let key = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+        let wallet: LocalWallet = key.parse().unwrap();
+        assert_eq!(check_key(key), Ok(format!("wallet {:?}", wallet.address())));
+        assert!(check_key("vault:secret/relayer#key").unwrap().contains("not resolved"));
+        assert!(check_key("0x1234").unwrap_err().starts_with("not a valid private key"));
+
+        let canonical = format!("0x{:064x}{:064x}1b", 1, 1);
+        assert_eq!(check_signature(&canonical), Ok(None));
+        let zero_based = format!("0x{:064x}{:064x}00", 1, 1);
+        assert!(check_signature(&zero_based).unwrap().unwrap().contains("normalized before use"));
+        assert!(check_signature("0xzz").unwrap_err().starts_with("not valid hex"));
+        assert!(check_signature("0xabcd").is_err());
+
+        let finding = evaluate("NONCE", "x", |_| Err("bad".to_string()));
+        assert!(finding.severity == Severity::Error && finding.message == "bad");
+        assert!(evaluate("NONCE", "7", |_| Ok(Some("odd".to_string()))).severity == Severity::Warning);
+        assert!(check_abi().severity == Severity::Ok);
+    }
+
+    #[test]
+    fn checking_the_environment() {
+        let settings = [
+            ("USER_ADDRESS", "0x5FbDB2315678afecb367f032d93F642f64180aa3".to_string()),
+            ("TOKEN_ADDRESS", "0x5fbdb2315678afecb367f032d93f642f64180aa3".to_string()),
+            ("AMOUNT", "1.5".to_string()),
+            ("NONCE", "0x1a".to_string()),
+            ("SIGNATURE", format!("0x{:064x}{:064x}1b", 1, 1)),
+            ("LOCK_ROUTER", "0x1234".to_string()),
+            ("CROSS_CHECK", "sometimes".to_string()),
+        ];
+        for (name, value) in &settings {
+            std::env::set_var(name, value);
+        }
+        let findings = check_config();
+        for (name, _) in &settings {
+            std::env::remove_var(name);
+        }
+
+        let severity =
+            |name: &str| findings.iter().find(|finding| finding.setting == name).map(|finding| finding.severity);
+        assert!(severity("USER_ADDRESS") == Some(Severity::Ok));
+        assert!(severity("TOKEN_ADDRESS") == Some(Severity::Warning));
+        assert!(severity("AMOUNT") == Some(Severity::Error));
+        assert!(severity("NONCE") == Some(Severity::Ok));
+        assert!(severity("SIGNATURE") == Some(Severity::Ok));
+        assert!(severity("LOCK_ROUTER") == Some(Severity::Error));
+        assert!(severity("CROSS_CHECK") == Some(Severity::Error));
+        assert!(severity("ABI") == Some(Severity::Ok));
+    }
+}
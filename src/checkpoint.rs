@@ -0,0 +1,161 @@
+//! Batch checkpoints: an append-only JSONL record of each row's progress, so
+//! an interrupted batch can be resumed without resending rows that were
+//! already broadcast or confirmed.
+
+use crate::config::Job;
+use anyhow::Context;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    /// Broadcast, receipt not yet seen.
+    Submitted,
+    Confirmed,
+    /// Reverted, dropped or failed; the row is attempted again on resume.
+    Failed,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Position of the row in the batch, starting at 0.
+    pub index: usize,
+    pub user: Address,
+    pub token: Address,
+    pub nonce: U256,
+    pub state: State,
+    pub sender: Address,
+    pub account_nonce: Option<U256>,
+    pub tx_hash: Option<H256>,
+}
+
+impl Record {
+    pub fn new(
+        index: usize,
+        job: &Job,
+        state: State,
+        sender: Address,
+        account_nonce: Option<U256>,
+        tx_hash: Option<H256>,
+    ) -> Self {
+        Self { index, user: job.user, token: job.token, nonce: job.nonce, state, sender, account_nonce, tx_hash }
+    }
+
+    fn matches(&self, job: &Job) -> bool {
+        self.user == job.user && self.token == job.token && self.nonce == job.nonce
+    }
+}
+
+pub struct Checkpoint {
+    path: PathBuf,
+    /// Latest record of each row when the checkpoint was opened.
+    rows: BTreeMap<usize, Record>,
+}
+
+impl Checkpoint {
+    /// The default checkpoint of a batch file, `<file>.checkpoint`.
+    pub fn default_path(batch_file: &Path) -> PathBuf {
+        let mut path = batch_file.as_os_str().to_owned();
+        path.push(".checkpoint");
+        PathBuf::from(path)
+    }
+
+    /// Starts a new checkpoint, refusing to overwrite the progress of an
+    /// earlier run.
+    pub fn create(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        anyhow::ensure!(
+            !path.exists(),
+            "checkpoint {} is left from an earlier run; pass --resume to continue it, or delete it to start over",
+            path.display()
+        );
+        Ok(Self { path, rows: BTreeMap::new() })
+    }
+
+    /// Opens the checkpoint of an interrupted run of `jobs`, failing if it
+    /// records rows that don't match the batch file.
+    pub fn resume(path: impl Into<PathBuf>, jobs: &[Job]) -> anyhow::Result<Self> {
+        let path = path.into();
+        let file = File::open(&path).with_context(|| format!("failed to open checkpoint {}", path.display()))?;
+
+        let mut rows = BTreeMap::new();
+        for (line_number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Record = serde_json::from_str(&line)
+                .with_context(|| format!("checkpoint {} line {}", path.display(), line_number + 1))?;
+            anyhow::ensure!(
+                jobs.get(record.index).is_some_and(|job| record.matches(job)),
+                "checkpoint {} row {} doesn't match the batch file",
+                path.display(),
+                record.index + 1
+            );
+            rows.insert(record.index, record);
+        }
+        Ok(Self { path, rows })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The row's state when the checkpoint was opened.
+    pub fn last(&self, index: usize) -> Option<&Record> {
+        self.rows.get(&index)
+    }
+
+    /// Appends `record`, flushing it to disk before returning.
+    pub fn record(&self, record: &Record) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(nonce: u64) -> Job {
+        Job::parse(
+            "0x0000000000000000000000000000000000000001",
+            "0x0000000000000000000000000000000000000002",
+            "1000",
+            &nonce.to_string(),
+            "0x00",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resume_keeps_latest_state() {
+        let path = std::env::temp_dir().join(format!("checkpoint-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let jobs = [job(1), job(2)];
+
+        let checkpoint = Checkpoint::create(&path).unwrap();
+        let record = |state| Record::new(0, &jobs[0], state, Address::zero(), Some(U256::zero()), Some(H256::zero()));
+        checkpoint.record(&record(State::Submitted)).unwrap();
+        checkpoint.record(&record(State::Confirmed)).unwrap();
+        assert!(Checkpoint::create(&path).is_err());
+
+        let resumed = Checkpoint::resume(&path, &jobs).unwrap();
+        assert_eq!(resumed.last(0).map(|r| r.state), Some(State::Confirmed));
+        assert!(resumed.last(1).is_none());
+
+        // A different batch file doesn't match the recorded rows.
+        assert!(Checkpoint::resume(&path, &[job(3)]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
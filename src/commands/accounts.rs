@@ -0,0 +1,49 @@
+use eth_contract_caller::config::Config;
+use eth_contract_caller::{hardware, pipeline};
+use ethers::prelude::*;
+use ethers::utils::format_ether;
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Signer {
+    Ledger,
+}
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Hardware wallet to read the accounts from
+    #[arg(long, value_enum)]
+    signer: Signer,
+    /// Number of accounts to list
+    #[arg(long, default_value_t = 10)]
+    count: usize,
+    /// Index of the first account
+    #[arg(long, default_value_t = 0)]
+    start: usize,
+    /// Derive along the legacy m/44'/60'/0'/i path instead of Ledger Live's
+    #[arg(long)]
+    legacy: bool,
+}
+
+/// Lists the hardware wallet's derived accounts with their balances, so the
+/// right account index can be picked before sending.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::without_contract_from_env()?;
+    let provider = pipeline::provider(&config)?;
+    let indices = args.start..args.start + args.count;
+    let addresses = match args.signer {
+        Signer::Ledger => hardware::ledger_addresses(config.chain_id, indices.clone(), args.legacy).await?,
+    };
+
+    println!("=== Accounts ===");
+    for (index, address) in indices.zip(addresses) {
+        let balance = provider.get_balance(address, None).await?;
+        println!(
+            "{:>3}  {:<20} {:?}  {} ETH",
+            index,
+            hardware::path_string(index, args.legacy),
+            address,
+            format_ether(balance)
+        );
+    }
+    Ok(())
+}
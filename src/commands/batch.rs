@@ -0,0 +1,188 @@
+use eth_contract_caller::artifacts::Artifacts;
+use eth_contract_caller::batch::{self, Options, Report};
+use eth_contract_caller::checkpoint::{Checkpoint, State};
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::contract::LockCall;
+use eth_contract_caller::fees::{self, FeeModel, GasGate};
+use eth_contract_caller::gas_tank::GasTank;
+use eth_contract_caller::ledger::Ledger;
+use eth_contract_caller::{output, pipeline, print_error, upgrades};
+use ethers::contract::EthCall;
+use ethers::types::U256;
+use ethers::utils::format_ether;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// CSV file with a `user,token,amount,nonce,signature` header
+    file: PathBuf,
+    /// Maximum number of transactions in flight per sender
+    #[arg(long, default_value_t = 1)]
+    concurrency: usize,
+    /// Send even jobs the ledger shows as already submitted
+    #[arg(long)]
+    force: bool,
+    /// Hold each transaction back until the base fee is below this price
+    /// (e.g. `30gwei`)
+    #[arg(long, value_name = "PRICE", value_parser = fees::parse_gas_price)]
+    wait_until_gas_below: Option<U256>,
+    /// Skip the remaining jobs if the base fee hasn't dropped in this long
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, requires = "wait_until_gas_below")]
+    max_gas_wait: Option<Duration>,
+    /// Print each summary row from this template, e.g.
+    /// `{{index}},{{tx_hash}},{{status}},{{gas_used}}`
+    #[arg(long, value_name = "TEMPLATE")]
+    format: Option<String>,
+    /// Record progress here (default `<FILE>.checkpoint`)
+    #[arg(long, value_name = "PATH")]
+    checkpoint: Option<PathBuf>,
+    /// Continue an interrupted run from its checkpoint, skipping confirmed
+    /// rows and waiting on rows already broadcast
+    #[arg(long)]
+    resume: bool,
+    /// Write each row's prepared and signed transaction, receipt and decoded
+    /// events to DIR/row-<n>/
+    #[arg(long, value_name = "DIR")]
+    artifacts: Option<PathBuf>,
+    /// Only estimate gas for every row and report the ones that would revert
+    #[arg(long)]
+    estimate_only: bool,
+    /// Send even when some rows fail gas estimation
+    #[arg(long, conflicts_with = "estimate_only")]
+    ignore_estimate_failures: bool,
+    /// Send even though the contract's implementation or code changed since
+    /// the last run, and remember the new one
+    #[arg(long)]
+    acknowledge_upgrade: bool,
+    /// Continue the sender's transactions from this account nonce, after
+    /// checking the chain agrees it is the next one (needs a single sender)
+    #[arg(long, value_name = "N", conflicts_with = "estimate_only")]
+    from_nonce: Option<u64>,
+    /// Also write the run report to this file: one row per job with its
+    /// parameters, transaction, status, gas and timestamps, as CSV (`.csv`)
+    /// or, with the cost summary, as JSON (`.json`)
+    #[arg(long, value_name = "PATH", conflicts_with = "estimate_only")]
+    report: Option<PathBuf>,
+}
+
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let jobs = batch::read_jobs(&args.file)?;
+    if let Some(template) = &args.format {
+        output::validate(template, output::BATCH_FIELDS)?;
+    }
+    if let Some(path) = &args.report {
+        batch::check_report_path(path)?;
+    }
+    let checkpoint_path = args.checkpoint.clone().unwrap_or_else(|| Checkpoint::default_path(&args.file));
+    let checkpoint = match args.resume {
+        true => Checkpoint::resume(checkpoint_path, &jobs)?,
+        false => Checkpoint::create(checkpoint_path)?,
+    };
+
+    println!("=== Configuration ===");
+    println!("RPC URL: {}", config.rpc_url);
+    println!("Chain ID: {}", config.chain_id);
+    println!("Contract Address: {:?}", config.contract_address);
+    println!("Batch File: {} ({} jobs)", args.file.display(), jobs.len());
+    println!("Concurrency: {} per sender", args.concurrency);
+    println!("Checkpoint: {}{}", checkpoint.path().display(), if args.resume { " (resuming)" } else { "" });
+    if let Some(dir) = &args.artifacts {
+        println!("Artifacts: {}", dir.display());
+    }
+    println!();
+
+    let clients = pipeline::connect_pool(&config)?;
+    let provider = pipeline::provider(&config)?;
+    let code = pipeline::ensure_contract_deployed(&provider, &config).await?;
+    pipeline::warn_if_selector_missing(&provider, &config, &code, LockCall::selector()).await?;
+    upgrades::check(&provider, &config, args.acknowledge_upgrade).await?;
+    println!("=== Senders ===");
+    for client in &clients {
+        println!("Wallet Address: {:?}", client.address());
+    }
+    println!();
+
+    // An estimate-only run spends nothing, not even on top-ups.
+    if let Some(tank) = GasTank::from_env(&config)?.filter(|_| !args.estimate_only) {
+        for client in &clients {
+            tank.ensure_funded(client.address()).await?;
+        }
+    }
+
+    // Rows the checkpoint shows as broadcast would fail estimation now that
+    // their nonce is used, so only the rest are estimated.
+    let pending = (0..jobs.len()).filter(|&index| {
+        !matches!(checkpoint.last(index).map(|record| record.state), Some(State::Submitted | State::Confirmed))
+    });
+    println!("=== Gas Estimates ===");
+    let estimates = batch::estimate(&clients, &config, &jobs, pending).await?;
+    let mut failures = 0;
+    for estimate in &estimates {
+        if let Err(reason) = &estimate.gas {
+            failures += 1;
+            print_error!("[{}] {}", estimate.index + 1, reason);
+        }
+    }
+    println!("{} of {} row(s) would revert", failures, estimates.len());
+    println!();
+    if args.estimate_only {
+        anyhow::ensure!(failures == 0, "{} row(s) would revert", failures);
+        return Ok(());
+    }
+    anyhow::ensure!(
+        failures == 0 || args.ignore_estimate_failures,
+        "{} row(s) would revert; remove them from {} or pass --ignore-estimate-failures",
+        failures,
+        args.file.display()
+    );
+
+    let ledger = Ledger::open().await?;
+
+    println!("=== Sending Batch ===");
+    let options = Options {
+        concurrency: args.concurrency,
+        force: args.force,
+        fees: FeeModel::from_env(config.chain_id)?,
+        gas_gate: args.wait_until_gas_below.map(|threshold| GasGate { threshold, max_wait: args.max_gas_wait }),
+        quiet: false,
+        checkpoint: Some(checkpoint),
+        artifacts: args.artifacts.map(Artifacts::new),
+        from_nonce: args.from_nonce.map(U256::from),
+    };
+    let outcomes = batch::run(&clients, &config, &jobs, &ledger, &options).await?;
+    println!();
+
+    println!("=== Batch Summary ===");
+    for outcome in &outcomes {
+        if let Some(template) = &args.format {
+            println!("{}", output::render(template, &output::batch_values(outcome)));
+            continue;
+        }
+        let nonce = outcome.nonce.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string());
+        let hash = outcome.tx_hash.map(|h| format!("{:?}", h)).unwrap_or_else(|| "-".to_string());
+        println!("[{}] from {:?} nonce {} tx {} {}", outcome.index + 1, outcome.sender, nonce, hash, outcome.status);
+    }
+    let confirmed = outcomes.iter().filter(|o| o.status.is_confirmed()).count();
+    println!("Confirmed: {}/{}", confirmed, outcomes.len());
+    println!();
+
+    let report = Report::new(config.chain_id, &jobs, &outcomes);
+    println!("=== Cost Report ===");
+    println!("Transactions: {}", report.transactions);
+    println!("Confirmed: {}, failed: {}, skipped: {}", report.confirmed, report.failed, report.skipped);
+    println!("Gas Spent: {} ETH on chain {}", format_ether(report.gas_spent), report.chain_id);
+    for (token, amount) in &report.value_locked {
+        println!("Value Locked: {} of token {:?}", amount, token);
+    }
+    if let Some(secs) = report.mean_confirmation_secs {
+        println!("Mean Confirmation Time: {:.1}s", secs);
+    }
+    if let Some(path) = &args.report {
+        batch::write_report(path, &report, &jobs, &outcomes)?;
+        println!("Report written to {}", path.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use eth_contract_caller::bench::{self, Method, Stats};
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::{print_ok, print_warn, tls};
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Requests per method and provider
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u64).range(1..))]
+    calls: u64,
+    /// Providers to compare (defaults to RPC_URL and RPC_URLS)
+    #[arg(long = "rpc-url", value_name = "URL")]
+    rpc_urls: Vec<String>,
+}
+
+/// Measures each provider's latency and error rate on the calls the relayer
+/// makes and prints a comparison, fastest reliable provider first.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let urls = match args.rpc_urls.is_empty() {
+        true => config::rpc_urls(&Config::from_env()?),
+        false => args.rpc_urls,
+    };
+
+    let mut results = Vec::new();
+    for url in &urls {
+        let provider = tls::provider(url)?;
+        let mut stats = Vec::new();
+        for method in Method::ALL {
+            stats.push((method, bench::measure(&provider, method, args.calls as usize).await));
+        }
+        results.push((config::rpc_label(url), stats));
+    }
+    // Fewest errors first, then the lowest median latency over all methods.
+    results.sort_by_key(|(_, stats)| {
+        let errors: usize = stats.iter().map(|(_, s)| s.errors).sum();
+        let median: Duration = stats.iter().filter_map(|(_, s)| s.percentile(50)).sum();
+        (errors, median)
+    });
+
+    let width = results.iter().map(|(label, _)| label.len()).max().unwrap_or(0).max("Provider".len());
+    println!("=== RPC Benchmark ({} calls per method) ===", args.calls);
+    println!("{:<width$}  {:<15} {:>7} {:>8} {:>8} {:>8}", "Provider", "Method", "Errors", "p50", "p95", "Mean");
+    for (label, stats) in &results {
+        for (method, stats) in stats {
+            println!(
+                "{:<width$}  {:<15} {:>6.0}% {:>8} {:>8} {:>8}",
+                label,
+                method.name(),
+                stats.error_rate() * 100.0,
+                millis(stats.percentile(50)),
+                millis(stats.percentile(95)),
+                millis(stats.mean()),
+            );
+        }
+    }
+    println!();
+
+    for (label, stats) in &results {
+        for (method, Stats { first_error, .. }) in stats {
+            if let Some(error) = first_error {
+                print_warn!("{} {}: {}", label, method.name(), error);
+            }
+        }
+    }
+    if let Some((label, _)) = results.first() {
+        print_ok!("Preferred provider: {}", label);
+    }
+    Ok(())
+}
+
+fn millis(latency: Option<Duration>) -> String {
+    match latency {
+        Some(latency) => format!("{}ms", latency.as_millis()),
+        None => "-".to_string(),
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::Context;
+use eth_contract_caller::audit::{self, Action, Record};
+use eth_contract_caller::config::{Config, LOCK_KIND};
+use eth_contract_caller::explorer::Explorer;
+#[cfg(feature = "kms")]
+use eth_contract_caller::fireblocks::Fireblocks;
+use eth_contract_caller::ledger::Ledger;
+#[cfg(feature = "kms")]
+use eth_contract_caller::privy::Privy;
+#[cfg(feature = "kms")]
+use eth_contract_caller::turnkey::Turnkey;
+use eth_contract_caller::unsigned::UnsignedTx;
+#[cfg(feature = "walletconnect")]
+use eth_contract_caller::walletconnect::WalletConnect;
+use eth_contract_caller::web3signer::Web3Signer;
+use eth_contract_caller::{pipeline, postcheck, print_ok, style};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// File written by `--unsigned`
+    file: PathBuf,
+    /// The external signer's 65-byte signature over the file's signing hash,
+    /// as hex
+    #[arg(long, required_unless_present_any = ["fireblocks", "web3signer", "walletconnect", "turnkey", "privy"])]
+    signature: Option<String>,
+    /// Have the Fireblocks vault account (FIREBLOCKS_VAULT_ACCOUNT_ID) sign
+    /// the hash, waiting for the approval workflow, instead of --signature
+    #[arg(long, conflicts_with = "signature")]
+    fireblocks: bool,
+    /// Have the Web3Signer at WEB3SIGNER_URL sign the transaction, instead
+    /// of --signature
+    #[arg(long, conflicts_with_all = ["signature", "fireblocks"])]
+    web3signer: bool,
+    /// Pair with a mobile wallet over WalletConnect (printing the pairing QR
+    /// code) and have it sign the transaction, instead of --signature
+    #[arg(long, conflicts_with_all = ["signature", "fireblocks", "web3signer"])]
+    walletconnect: bool,
+    /// Have the Turnkey wallet account of the sender sign the hash, waiting
+    /// for consensus if policies require it, instead of --signature
+    #[arg(long, conflicts_with_all = ["signature", "fireblocks", "web3signer", "walletconnect"])]
+    turnkey: bool,
+    /// Have the Privy server wallet PRIVY_WALLET_ID sign the hash, instead of
+    /// --signature
+    #[arg(long, conflicts_with_all = ["signature", "fireblocks", "web3signer", "walletconnect", "turnkey"])]
+    privy: bool,
+    /// Send even if the ledger shows this exact job was already submitted
+    #[arg(long)]
+    force: bool,
+}
+
+/// Merges an external signature into a transaction prepared with
+/// `--unsigned`, broadcasts it and waits for it to be mined. The signature
+/// is given, or requested from Fireblocks, Turnkey, Privy, Web3Signer or a
+/// wallet over WalletConnect, and must recover to the file's sender; the
+/// ledger and audit log are kept as for any other send.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let unsigned = UnsignedTx::load(&args.file)?;
+    anyhow::ensure!(
+        (unsigned.chain_id, unsigned.contract) == (config.chain_id, config.contract_address),
+        "{} is for contract {:?} on chain {}, not {:?} on chain {}",
+        args.file.display(),
+        unsigned.contract,
+        unsigned.chain_id,
+        config.contract_address,
+        config.chain_id
+    );
+    unsigned.verify()?;
+    let (kind, job) = (unsigned.kind.as_str(), &unsigned.job);
+    let provider = pipeline::provider(&config)?;
+    pipeline::ensure_same_chain(&[
+        ("file", Some(unsigned.chain_id)),
+        ("node", Some(provider.get_chainid().await?.as_u64())),
+    ])?;
+    let ledger = Ledger::open().await?;
+    pipeline::check_ledger(&ledger, kind, &config, job, args.force).await?;
+    let nonce = unsigned.tx.nonce().copied().unwrap_or_default();
+    let mined = provider.get_transaction_count(unsigned.from, None).await?;
+    anyhow::ensure!(
+        mined <= nonce,
+        "nonce {} of {:?} is already used; prepare the transaction again",
+        nonce,
+        unsigned.from
+    );
+
+    // Only now that the transaction can still be sent is anyone asked to approve it.
+    let raw = match &args.signature {
+        Some(signature) => unsigned.merge(signature.trim().parse().context("invalid --signature")?)?,
+        None if args.web3signer => {
+            println!("=== Web3Signer Signing ===");
+            let signer = Web3Signer::from_env()?;
+            signer.ensure_holds(unsigned.from).await?;
+            let raw = signer.sign_transaction(&unsigned.transaction()).await?;
+            unsigned.check_signed(&raw)?;
+            print_ok!("Signed by {:?}", unsigned.from);
+            println!();
+            raw
+        }
+        #[cfg(feature = "walletconnect")]
+        None if args.walletconnect => {
+            println!("=== WalletConnect Signing ===");
+            let tx = eth_contract_caller::unsigned::json_rpc(&unsigned.transaction())?;
+            let raw = WalletConnect::from_env()?.sign_transaction(tx, unsigned.from, unsigned.chain_id).await?;
+            unsigned.check_signed(&raw)?;
+            print_ok!("Signed by {:?}", unsigned.from);
+            println!();
+            raw
+        }
+        #[cfg(feature = "kms")]
+        None if args.turnkey => {
+            println!("=== Turnkey Signing ===");
+            let signature = Turnkey::from_env()?.sign_hash(unsigned.sighash, unsigned.from).await?;
+            print_ok!("Signed by {:?}", unsigned.from);
+            println!();
+            unsigned.merge(signature)?
+        }
+        #[cfg(feature = "kms")]
+        None if args.privy => {
+            println!("=== Privy Signing ===");
+            let signature = Privy::from_env()?.sign_hash(unsigned.sighash, unsigned.from).await?;
+            print_ok!("Signed by {:?}", unsigned.from);
+            println!();
+            unsigned.merge(signature)?
+        }
+        #[cfg(feature = "kms")]
+        None if args.fireblocks => {
+            println!("=== Fireblocks Signing ===");
+            let note = format!("{} for user {:?}, nonce {}", kind, job.user, job.nonce);
+            let signature = Fireblocks::from_env()?.sign_hash(unsigned.sighash, &note).await?;
+            print_ok!("Signed by the vault account");
+            println!();
+            unsigned.merge(signature)?
+        }
+        // Clap requires --signature or a signer, so only one left out of the build gets here.
+        None => {
+            let feature = if args.walletconnect { "walletconnect" } else { "kms" };
+            anyhow::bail!("this signer needs a build with the `{}` feature", feature)
+        }
+    };
+    let tx_hash = H256(keccak256(&raw));
+
+    println!("=== Sending Transaction ===");
+    let params = serde_json::to_value(&unsigned.tx)?;
+    audit::record(&Record::new(Action::Sign, unsigned.from, kind, params, Some(tx_hash), "signed externally"))?;
+    let entry = pipeline::claim(&ledger, kind, &config, job, tx_hash, args.force).await?;
+    let pending = match audit::broadcast_as(&provider, unsigned.from, unsigned.chain_id, raw, kind).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            ledger.release(&entry).await?;
+            return Err(e);
+        }
+    };
+    println!("Transaction Hash: {}", style::dim(format!("{:?}", tx_hash)));
+    if let Some(explorer) = Explorer::for_chain(config.chain_id)? {
+        println!("Explorer: {}", explorer.tx(tx_hash));
+    }
+    println!("Waiting for transaction to be mined...");
+
+    let receipt = pending.await?;
+    if let Some(receipt) = &receipt {
+        audit::record_receipt(unsigned.from, kind, receipt)?;
+    }
+    pipeline::print_receipt(receipt.clone());
+    if let Some(receipt) = receipt.filter(|receipt| kind == LOCK_KIND && receipt.status == Some(1.into())) {
+        postcheck::verify_lock(Arc::new(provider.clone()), config.contract_address, job, &receipt).await?;
+        print_ok!("Locked event and lock record for nonce {} match the job", job.nonce);
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use eth_contract_caller::check::{self, Finding, Severity};
+use eth_contract_caller::style;
+
+pub fn run() -> anyhow::Result<()> {
+    print_findings("Configuration Check", &check::check_config())
+}
+
+/// Prints a pass/fail table of `findings`, failing when any is an error.
+pub fn print_findings(title: &str, findings: &[Finding]) -> anyhow::Result<()> {
+    println!("{}", style::header(title));
+    let width = findings.iter().map(|f| f.setting.len()).max().unwrap_or(0);
+    for finding in findings {
+        let line = style::field(&finding.setting, width, &finding.message);
+        let line = match finding.severity {
+            Severity::Ok => style::ok(&line),
+            Severity::Warning => style::warn(&line),
+            Severity::Error => style::error(&line),
+        };
+        println!("{}", line);
+    }
+
+    let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+    let warnings = findings.iter().filter(|f| f.severity == Severity::Warning).count();
+    println!();
+    println!("{} error(s), {} warning(s)", errors, warnings);
+
+    anyhow::ensure!(errors == 0, "{} found {} error(s)", title.to_lowercase(), errors);
+    Ok(())
+}
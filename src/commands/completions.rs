@@ -0,0 +1,14 @@
+use clap_complete::Shell;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Shell to generate the completion script for
+    #[arg(value_enum)]
+    shell: Shell,
+}
+
+/// Writes the completion script for `command` to stdout.
+pub fn run(args: &Args, mut command: clap::Command) {
+    let name = command.get_name().to_string();
+    clap_complete::generate(args.shell, &mut command, name, &mut std::io::stdout());
+}
@@ -0,0 +1,20 @@
+use eth_contract_caller::profile;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Print the config file's JSON Schema, for editor completion and checks
+    Schema,
+}
+
+pub fn run(args: &Args) -> anyhow::Result<()> {
+    match args.command {
+        Command::Schema => println!("{}", serde_json::to_string_pretty(&profile::schema())?),
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use crossterm::event::{Event, EventStream, KeyCode, KeyModifiers};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use eth_contract_caller::balances::{self, AlertState, WatchFile};
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::dashboard::{self, Confirmation, Snapshot, EVENT_FEED};
+use eth_contract_caller::fees::FeeModel;
+use eth_contract_caller::ledger::Ledger;
+use eth_contract_caller::pipeline::Rpc;
+use eth_contract_caller::profile::NativeCurrency;
+use eth_contract_caller::{events, mempool, pipeline, profile};
+use ethers::prelude::*;
+use futures::StreamExt;
+use ratatui::backend::CrosstermBackend;
+use ratatui::Terminal;
+use std::collections::VecDeque;
+use std::io::{self, Stdout};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// How often to refresh
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "5s")]
+    interval: Duration,
+    /// watch-balances file of the wallets whose balances to show
+    #[arg(long, value_name = "PATH")]
+    balances: Option<PathBuf>,
+    /// Show the events of this many recent blocks at startup
+    #[arg(long, default_value_t = 100)]
+    blocks: u64,
+    /// Recent ledger submissions to show
+    #[arg(long, default_value_t = 10)]
+    submissions: usize,
+}
+
+/// One screen for the on-call operator: stuck transactions, recent
+/// submissions, gas, balances and live events, refreshed every --interval
+/// until q, Esc or Ctrl-C. A source that can't be read shows its error in
+/// its panel; the rest keep updating.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let provider = pipeline::provider(&config)?;
+    let latest = provider.get_block_number().await?;
+    let mut sources = Sources {
+        filter: events::filter(config.contract_address, &[], &[])?,
+        ledger: Ledger::open().await?,
+        watch: args.balances.as_deref().map(WatchFile::load).transpose()?,
+        model: FeeModel::from_env(config.chain_id)?,
+        native: profile::native_currency(config.chain_id)?,
+        sender: match config::sender_address()? {
+            Some(sender) => Some(sender),
+            None => config::private_key().ok().and_then(|key| key.parse::<LocalWallet>().ok()).map(|key| key.address()),
+        },
+        submissions: args.submissions,
+        next_block: latest.saturating_sub(args.blocks.saturating_sub(1).into()),
+        feed: VecDeque::new(),
+        config,
+        provider,
+    };
+
+    let mut screen = Screen::enter()?;
+    let mut keys = EventStream::new();
+    let mut ticks = tokio::time::interval(args.interval);
+    let mut snapshot = None;
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => snapshot = Some(sources.refresh().await?),
+            event = keys.next() => match event {
+                Some(Ok(Event::Key(key))) if quits(key.code, key.modifiers) => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+            },
+        }
+        // Other key and resize events only redraw.
+        if let Some(snapshot) = &snapshot {
+            screen.0.draw(|frame| dashboard::render(frame, snapshot))?;
+        }
+    }
+}
+
+/// Everything a refresh reads from, and the event feed it keeps.
+struct Sources {
+    config: Config,
+    provider: Rpc,
+    ledger: Ledger,
+    filter: Filter,
+    watch: Option<WatchFile>,
+    model: FeeModel,
+    native: NativeCurrency,
+    sender: Option<Address>,
+    submissions: usize,
+    /// First block whose events aren't in the feed yet.
+    next_block: U64,
+    feed: VecDeque<Log>,
+}
+
+impl Sources {
+    async fn refresh(&mut self) -> anyhow::Result<Snapshot> {
+        let provider = &self.provider;
+        let block = provider.get_block_number().await.map_err(|e| format!("{:#}", e));
+        if let Ok(block) = &block {
+            // On a failure the same blocks are read again next time.
+            if *block >= self.next_block {
+                if let Ok(logs) = events::range(provider, &self.filter, self.next_block, *block).await {
+                    for log in logs {
+                        self.feed.push_front(log);
+                    }
+                    self.feed.truncate(EVENT_FEED);
+                    self.next_block = *block + 1;
+                }
+            }
+        }
+        let (pending, sender_balance) = match self.sender {
+            Some(sender) => (
+                mempool::stuck_transactions(provider, &self.ledger, sender).await.map_err(|e| format!("{:#}", e)),
+                provider.get_balance(sender, None).await.map_err(|e| format!("{:#}", e)),
+            ),
+            None => (Err("no sender".to_string()), Err("no sender".to_string())),
+        };
+        let balances = match &self.watch {
+            Some(watch) => {
+                // A fresh state each time: the dashboard shows balances, it doesn't alert on them.
+                let mut state = AlertState::from_env()?;
+                let readings = balances::check(watch, &mut state).await;
+                readings.into_iter().map(|(name, reading)| (name, reading.map_err(|e| format!("{:#}", e)))).collect()
+            }
+            None => Vec::new(),
+        };
+        Ok(Snapshot {
+            chain_id: self.config.chain_id,
+            native: self.native.clone(),
+            block,
+            sender: self.sender,
+            sender_balance,
+            pending,
+            confirmations: self.confirmations().await.map_err(|e| format!("{:#}", e)),
+            gas: self.model.report(provider).await.map_err(|e| format!("{:#}", e)),
+            balances,
+            events: self.feed.clone(),
+            updated_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        })
+    }
+
+    /// The latest ledger submissions on this chain and contract, newest
+    /// first, with their receipts.
+    async fn confirmations(&self) -> anyhow::Result<Vec<Confirmation>> {
+        let (chain_id, contract) = (self.config.chain_id, self.config.contract_address);
+        let mut entries = self.ledger.entries().await?;
+        entries.retain(|entry| entry.chain_id == chain_id && entry.contract == contract);
+        let mut confirmations = Vec::new();
+        for entry in entries.into_iter().rev().take(self.submissions) {
+            let receipt = self.provider.get_transaction_receipt(entry.tx_hash).await?;
+            confirmations.push(Confirmation { entry, receipt });
+        }
+        Ok(confirmations)
+    }
+}
+
+fn quits(code: KeyCode, modifiers: KeyModifiers) -> bool {
+    match code {
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        KeyCode::Char('c') => modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped,
+/// including on an error.
+struct Screen(Terminal<CrosstermBackend<Stdout>>);
+
+impl Screen {
+    fn enter() -> anyhow::Result<Self> {
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(io::stdout(), EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        terminal.hide_cursor()?;
+        Ok(Self(terminal))
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = crossterm::execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = self.0.show_cursor();
+    }
+}
@@ -0,0 +1,32 @@
+use eth_contract_caller::calldata::{self, Source};
+use eth_contract_caller::print_error;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Calldata to decode, as hex (`0x` optional)
+    calldata: String,
+}
+
+pub fn run(args: Args) -> anyhow::Result<()> {
+    let data = calldata::parse_hex(&args.calldata)?;
+
+    println!("=== Decoded Calldata ===");
+    println!("Selector: 0x{}", hex::encode(data.get(..4).unwrap_or(&data)));
+
+    let Some(decoded) = calldata::decode(&data)? else {
+        print_error!("Unknown selector: not in abi.json or the built-in signature list");
+        return Ok(());
+    };
+
+    let source = match decoded.source {
+        Source::Abi => "abi.json",
+        Source::Builtin => "built-in signatures",
+    };
+    println!("Function: {} (from {})", decoded.function.signature(), source);
+    for (index, (param, value)) in decoded.function.inputs.iter().zip(&decoded.args).enumerate() {
+        let name = if param.name.is_empty() { format!("arg{}", index) } else { param.name.clone() };
+        println!("  {} ({}): {}", name, param.kind, calldata::format_token(value));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use anyhow::Context;
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::deploy::{self, ConstructorArgs};
+use eth_contract_caller::verify::{self, Etherscan, Sourcify, Submission};
+use eth_contract_caller::{pipeline, print_ok};
+use ethers::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Creation bytecode of the lock contract: a hex file, or a Foundry or
+    /// Hardhat artifact
+    #[arg(long, value_name = "PATH")]
+    bytecode: PathBuf,
+    /// Relayer the contract accepts locks and redeems from (defaults to the
+    /// wallet)
+    #[arg(long, value_name = "ADDRESS")]
+    relayer: Option<Address>,
+    /// LayerZero endpoint of the chain
+    #[arg(long, value_name = "ADDRESS")]
+    lz_endpoint: Address,
+    /// Initial owner (defaults to the wallet)
+    #[arg(long, value_name = "ADDRESS")]
+    owner: Option<Address>,
+    /// LayerZero chain id of Soneium
+    #[arg(long)]
+    soneium_lz_chain_id: u16,
+    #[command(flatten)]
+    verify: VerifyArgs,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Verifier {
+    Etherscan,
+    Sourcify,
+}
+
+#[derive(clap::Args)]
+struct VerifyArgs {
+    /// After deploying, verify the source on the chain's Etherscan (needs
+    /// ETHERSCAN_API_KEY) or on Sourcify
+    #[arg(long)]
+    verify: bool,
+    /// Where to verify
+    #[arg(long, value_enum, default_value_t = Verifier::Etherscan, requires = "verify")]
+    verifier: Verifier,
+    /// solc standard JSON input the bytecode was compiled from (for
+    /// Sourcify, optional: sources are read from disk otherwise)
+    #[arg(long, value_name = "PATH")]
+    standard_json: Option<PathBuf>,
+    /// solc metadata, or a Foundry artifact with rawMetadata (Sourcify)
+    #[arg(long, value_name = "PATH")]
+    metadata: Option<PathBuf>,
+    /// Fully qualified contract name, e.g. `src/Vault.sol:Vault` (Etherscan)
+    #[arg(long, value_name = "NAME")]
+    contract_name: Option<String>,
+    /// Full compiler version, e.g. `v0.8.24+commit.e11b9ed9` (Etherscan)
+    #[arg(long, value_name = "VERSION")]
+    compiler_version: Option<String>,
+    /// How long to wait for Etherscan to verify
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "5m")]
+    verify_timeout: Duration,
+}
+
+/// A verification backend with everything it needs, gathered before deploying.
+enum Verification {
+    Etherscan { explorer: Etherscan, contract_name: String, compiler_version: String, standard_json: String },
+    Sourcify { sourcify: Sourcify, metadata: String, sources: BTreeMap<String, String> },
+}
+
+impl VerifyArgs {
+    fn prepare(&self, chain_id: u64) -> anyhow::Result<Verification> {
+        let standard_json = match &self.standard_json {
+            Some(path) => Some(fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?),
+            None => None,
+        };
+        match self.verifier {
+            Verifier::Etherscan => Ok(Verification::Etherscan {
+                explorer: Etherscan::from_env(chain_id)?,
+                contract_name: self.contract_name.clone().context("--verify needs --contract-name")?,
+                compiler_version: self.compiler_version.clone().context("--verify needs --compiler-version")?,
+                standard_json: standard_json.context("--verify needs --standard-json")?,
+            }),
+            Verifier::Sourcify => {
+                let path = self.metadata.as_deref().context("--verifier sourcify needs --metadata")?;
+                let metadata = verify::read_metadata(path)?;
+                let sources = verify::collect_sources(&metadata, standard_json.as_deref())?;
+                Ok(Verification::Sourcify { sourcify: Sourcify::from_env(), metadata, sources })
+            }
+        }
+    }
+}
+
+/// Deploys the lock contract from the wallet, optionally verifying it.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::without_contract_from_env()?;
+    let bytecode = deploy::read_bytecode(&args.bytecode)?;
+    // Fail on missing verification settings before spending gas.
+    let verification = match args.verify.verify {
+        true => Some(args.verify.prepare(config.chain_id)?),
+        false => None,
+    };
+    let client = pipeline::connect(&config)?;
+    let constructor = ConstructorArgs {
+        relayer: args.relayer.unwrap_or(client.address()),
+        lz_endpoint: args.lz_endpoint,
+        owner: args.owner.unwrap_or(client.address()),
+        soneium_lz_chain_id: args.soneium_lz_chain_id,
+    };
+
+    println!("=== Deploy ===");
+    println!("RPC URL: {}", config.rpc_url);
+    println!("Chain ID: {}", config.chain_id);
+    println!("Deployer: {:?}", client.address());
+    println!("Constructor: {:?}", constructor);
+    let receipt = deploy::deploy(&client, bytecode, &constructor).await?;
+    let address = receipt.contract_address.unwrap_or_default();
+    print_ok!("Deployed at {:?} in block {:?}", address, receipt.block_number);
+    println!();
+
+    match verification {
+        Some(Verification::Etherscan { explorer, contract_name, compiler_version, standard_json }) => {
+            println!("=== Verification (Etherscan) ===");
+            let submission = Submission {
+                address,
+                contract_name,
+                compiler_version,
+                standard_json,
+                constructor_args: constructor.encode(),
+            };
+            let guid = explorer.submit(&submission).await?;
+            println!("Submitted; waiting for the explorer (request {})", guid);
+            explorer.wait(&guid, args.verify.verify_timeout).await?;
+            print_ok!("Source verified");
+            println!();
+        }
+        Some(Verification::Sourcify { sourcify, metadata, sources }) => {
+            println!("=== Verification (Sourcify) ===");
+            let found = sourcify.verify(config.chain_id, address, &metadata, &sources).await?;
+            print_ok!("Source verified ({:?} match)", found);
+            println!();
+        }
+        None => {}
+    }
+
+    println!("Set CONTRACT_ADDRESS={:?} to use it", address);
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use super::lock;
+use anyhow::Context;
+use clap::ValueEnum;
+use eth_contract_caller::config::{Config, Job, ENV_PREFIX};
+use eth_contract_caller::contract::MyContract;
+use eth_contract_caller::deploy::{self, ConstructorArgs};
+use eth_contract_caller::error::Error;
+use eth_contract_caller::pipeline::Client;
+use eth_contract_caller::stress::{self, TestSigner};
+use eth_contract_caller::{audit, nonce, pipeline, print_ok};
+use ethers::core::k256::SecretKey;
+use ethers::core::rand::thread_rng;
+use ethers::prelude::*;
+use ethers::utils::{format_ether, parse_ether, Anvil};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Start anvil, deploy the contract, replay lock scenarios end-to-end
+    /// against it and tear everything down again
+    Up(UpArgs),
+}
+
+/// A lock replayed against the devnet from the snapshot taken after setup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Scenario {
+    /// A correctly signed job is locked.
+    HappyPath,
+    /// A job signed by another key doesn't lock.
+    BadSignature,
+    /// A job locked once is refused the second time.
+    ReusedNonce,
+}
+
+impl Scenario {
+    fn name(self) -> String {
+        self.to_possible_value().expect("no skipped variants").get_name().to_string()
+    }
+}
+
+#[derive(clap::Args)]
+struct UpArgs {
+    /// Creation bytecode of the lock contract: a hex file, or a Foundry or
+    /// Hardhat artifact
+    #[arg(long, value_name = "PATH")]
+    bytecode: PathBuf,
+    /// LayerZero endpoint passed to the constructor
+    #[arg(long, value_name = "ADDRESS", default_value_t = Address::zero())]
+    lz_endpoint: Address,
+    /// Soneium LayerZero chain id passed to the constructor
+    #[arg(long, default_value_t = 0)]
+    soneium_lz_chain_id: u16,
+    /// Native amount the test job locks, e.g. `0.01` (ether)
+    #[arg(long, value_name = "ETHER", default_value = "0.01")]
+    amount: String,
+    /// Scenario to run; repeat for several (default: all)
+    #[arg(long = "scenario", value_enum)]
+    scenarios: Vec<Scenario>,
+}
+
+pub async fn run(args: &Args) -> anyhow::Result<()> {
+    match &args.command {
+        Command::Up(args) => up(args).await,
+    }
+}
+
+/// The devnet's settings replace the caller's, so the smoke test can't reach
+/// a real network or key.
+fn isolate_env(settings: &[(&str, String)]) {
+    for (name, _) in env::vars() {
+        if name.starts_with(ENV_PREFIX) {
+            env::remove_var(name);
+        }
+    }
+    for (name, value) in settings {
+        env::set_var(format!("{}{}", ENV_PREFIX, name), value);
+    }
+}
+
+async fn up(args: &UpArgs) -> anyhow::Result<()> {
+    let bytecode = deploy::read_bytecode(&args.bytecode)?;
+    let amount = parse_ether(&args.amount).context("invalid --amount")?;
+    // Killed when dropped, which tears the devnet down however this returns.
+    // Anvil::spawn panics when anvil can't be run, so check for it first.
+    let installed = std::process::Command::new("anvil").arg("--version").output();
+    installed.context("failed to run anvil; is Foundry installed?")?;
+    let anvil = Anvil::new().spawn();
+    let state_dir = env::temp_dir().join(format!("ethers-rusty-devnet-{}", std::process::id()));
+    let funder_key = &anvil.keys()[0];
+    let result = smoke_test(&anvil.endpoint(), anvil.chain_id(), funder_key, &bytecode, amount, args, &state_dir).await;
+    drop(anvil);
+    fs::remove_dir_all(&state_dir).ok();
+    println!("Devnet torn down");
+    result
+}
+
+async fn smoke_test(
+    rpc_url: &str,
+    chain_id: u64,
+    funder_key: &SecretKey,
+    bytecode: &Bytes,
+    amount: U256,
+    args: &UpArgs,
+    state_dir: &Path,
+) -> anyhow::Result<()> {
+    let mut config = Config { rpc_url: rpc_url.to_string(), chain_id, contract_address: Address::zero() };
+    let funder = pipeline::connect_with_key(&config, &hex::encode(funder_key.to_bytes()))?;
+    let relayer = LocalWallet::new(&mut thread_rng());
+    let relayer_key = hex::encode(relayer.signer().to_bytes());
+
+    println!("=== Devnet ===");
+    println!("RPC URL: {}", rpc_url);
+    println!("Chain ID: {}", chain_id);
+    println!("Funder: {:?}", funder.address());
+    println!("Relayer: {:?}", relayer.address());
+    println!();
+
+    println!("=== Deploy ===");
+    let constructor = ConstructorArgs {
+        relayer: relayer.address(),
+        lz_endpoint: args.lz_endpoint,
+        owner: funder.address(),
+        soneium_lz_chain_id: args.soneium_lz_chain_id,
+    };
+    let receipt = deploy::deploy(&funder, bytecode.clone(), &constructor).await?;
+    config.contract_address = receipt.contract_address.unwrap_or_default();
+    print_ok!("Deployed at {:?}", config.contract_address);
+    println!();
+
+    let funding = amount + parse_ether(1)?;
+    let transfer = TransactionRequest::pay(relayer.address(), funding);
+    let receipt = audit::send_transaction(&*funder, transfer, "devnet funding")
+        .await?
+        .await?
+        .context("relayer funding dropped before it was mined")?;
+    anyhow::ensure!(receipt.status == Some(U64::from(1)), "relayer funding reverted");
+    print_ok!("Funded the relayer with {} ETH", format_ether(funding));
+    println!();
+
+    let scenarios = match args.scenarios.is_empty() {
+        true => Scenario::value_variants().to_vec(),
+        false => args.scenarios.clone(),
+    };
+    // Each scenario starts from the chain as set up, rather than from a new
+    // deployment.
+    let devnet = Devnet { rpc_url, chain_id, relayer, relayer_key, state_dir };
+    for scenario in scenarios {
+        let snapshot: U256 = funder.provider().request("evm_snapshot", ()).await?;
+        println!("=== Scenario: {} ===", scenario.name());
+        let result = devnet.run(&config, &funder, scenario, amount).await;
+        let reverted: bool = funder.provider().request("evm_revert", [snapshot]).await?;
+        anyhow::ensure!(reverted, "anvil couldn't revert to snapshot {}", snapshot);
+        result.with_context(|| format!("scenario {} failed", scenario.name()))?;
+        println!();
+    }
+    print_ok!("Smoke test passed");
+    Ok(())
+}
+
+/// What each scenario needs of the devnet.
+struct Devnet<'a> {
+    rpc_url: &'a str,
+    chain_id: u64,
+    relayer: LocalWallet,
+    relayer_key: String,
+    state_dir: &'a Path,
+}
+
+impl Devnet<'_> {
+    /// Signs a fresh job (with a stranger's key for a bad signature) and
+    /// points the lock command's settings at it, with its own state
+    /// directory so scenarios don't share a ledger.
+    async fn job(&self, config: &Config, scenario: Scenario, amount: U256) -> anyhow::Result<Job> {
+        let mut job = stress::synthetic_job(Address::zero(), amount);
+        job.nonce = U256::one();
+        let signer = match scenario {
+            Scenario::BadSignature => LocalWallet::new(&mut thread_rng()),
+            _ => self.relayer.clone(),
+        };
+        TestSigner::Key(signer).sign(config, &mut job).await?;
+        isolate_env(&[
+            ("RPC_URL", self.rpc_url.to_string()),
+            ("CHAIN_ID", self.chain_id.to_string()),
+            ("CONTRACT_ADDRESS", format!("{:?}", config.contract_address)),
+            ("PRIVATE_KEY", self.relayer_key.clone()),
+            ("USER_ADDRESS", format!("{:?}", job.user)),
+            ("TOKEN_ADDRESS", format!("{:?}", job.token)),
+            ("AMOUNT", job.amount.to_string()),
+            ("NONCE", job.nonce.to_string()),
+            ("SIGNATURE", job.signature.to_string()),
+            ("STATE_DIR", self.state_dir.join(scenario.name()).display().to_string()),
+        ]);
+        Ok(job)
+    }
+
+    async fn run(&self, config: &Config, funder: &Arc<Client>, scenario: Scenario, amount: U256) -> anyhow::Result<()> {
+        let job = self.job(config, scenario, amount).await?;
+        let contract = MyContract::new(config.contract_address, funder.clone());
+        let locked = || nonce::is_lock_nonce_used(&contract, job.user, job.token, job.nonce);
+        match scenario {
+            Scenario::HappyPath => {
+                lock::run(Default::default()).await?;
+                anyhow::ensure!(
+                    locked().await?,
+                    "the lock went through but the contract has no lock record for nonce {}",
+                    job.nonce
+                );
+                print_ok!("The contract recorded the lock");
+            }
+            Scenario::BadSignature => {
+                let result = lock::run(Default::default()).await;
+                anyhow::ensure!(!locked().await?, "the contract recorded a lock signed by the wrong key");
+                match result {
+                    Ok(()) => print_ok!("The contract recorded no lock"),
+                    Err(e) => print_ok!("Refused: {:#}", e),
+                }
+            }
+            Scenario::ReusedNonce => {
+                lock::run(Default::default()).await?;
+                let Err(e) = lock::run(Default::default()).await else {
+                    anyhow::bail!("locking nonce {} a second time didn't fail", job.nonce);
+                };
+                anyhow::ensure!(
+                    matches!(e.downcast_ref::<Error>(), Some(Error::AlreadyProcessed { .. })),
+                    "locking nonce {} a second time failed, but not as already processed: {:#}",
+                    job.nonce,
+                    e
+                );
+                print_ok!("Refused the second lock: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+use super::check_config::print_findings;
+use eth_contract_caller::config::Config;
+use eth_contract_caller::doctor;
+
+pub async fn run() -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    print_findings("Doctor", &doctor::run(&config).await)
+}
@@ -0,0 +1,78 @@
+use eth_contract_caller::{calldata, signature};
+use eth_contract_caller::config::{self, Job};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Encode `lock(...)`, attaching the amount as value for native-currency locks
+    Lock(JobArgs),
+    /// Encode `redeemWithSignature(...)`
+    Unlock(JobArgs),
+}
+
+#[derive(clap::Args)]
+struct JobArgs {
+    /// User address
+    user: String,
+    /// Token address
+    token: String,
+    /// Amount, in the token's smallest unit (decimal)
+    amount: String,
+    /// Nonce, in the same format as NONCE (decimal, or hex with `0x`)
+    nonce: String,
+    /// Backend signature, as hex
+    signature: String,
+}
+
+impl JobArgs {
+    fn job(&self) -> anyhow::Result<Job> {
+        let mut job = Job::parse(&self.user, &self.token, &self.amount, &self.nonce, "0x")?;
+        job.signature = signature::parse(&self.signature)?;
+        Ok(job)
+    }
+}
+
+/// Prints the calldata and the unsigned transaction this tool would send.
+/// Only CONTRACT_ADDRESS is required; CHAIN_ID and SENDER_ADDRESS are filled
+/// in when set. No RPC is contacted.
+pub fn run(args: Args) -> anyhow::Result<()> {
+    let (function, data, value) = match &args.command {
+        Command::Lock(job_args) => {
+            let job = job_args.job()?;
+            ("lock", calldata::encode_lock(&job), job.lock_value()?)
+        }
+        Command::Unlock(job_args) => {
+            let job = job_args.job()?;
+            ("redeemWithSignature", calldata::encode_unlock(&job), U256::zero())
+        }
+    };
+
+    let contract_address: Address = config::var("CONTRACT_ADDRESS")?.parse()?;
+    let mut tx = Eip1559TransactionRequest::new()
+        .to(contract_address)
+        .value(value)
+        .data(data.clone());
+    if let Some(sender) = config::sender_address()? {
+        tx = tx.from(sender);
+    }
+    let mut json = serde_json::to_value(TypedTransaction::Eip1559(tx))?;
+    // ethers leaves the chain id out of the JSON-RPC form; signers need it.
+    if let Some(chain_id) = config::env_var("CHAIN_ID") {
+        json["chainId"] = format!("{:#x}", chain_id.parse::<u64>()?).into();
+    }
+
+    println!("=== Encoded {} ===", function);
+    println!("Calldata: 0x{}", hex::encode(&data));
+    println!();
+    println!("=== Unsigned Transaction ===");
+    println!("{}", serde_json::to_string_pretty(&json)?);
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use eth_contract_caller::config::Config;
+use eth_contract_caller::export::{self, Exporter, Format};
+use eth_contract_caller::{events, pipeline};
+use ethers::prelude::*;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// First block to fetch events from
+    #[arg(long, value_name = "BLOCK")]
+    from_block: u64,
+    /// Last block to fetch events from (defaults to the latest)
+    #[arg(long, value_name = "BLOCK")]
+    to_block: Option<u64>,
+    /// Blocks per eth_getLogs request; ranges the provider refuses are split
+    #[arg(long, value_name = "BLOCKS", default_value_t = events::DEFAULT_CHUNK)]
+    chunk: u64,
+    /// Write the events to this `.csv` or `.parquet` file instead of stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+/// Which events to show, for the commands that read the contract's logs.
+#[derive(clap::Args)]
+pub struct FilterArgs {
+    /// Only this abi.json event (repeat for several)
+    #[arg(long = "event", value_name = "NAME")]
+    events: Vec<String>,
+    /// Only events whose indexed parameter NAME is VALUE, e.g.
+    /// `user=0xabc…` (repeat to combine; repeating a name matches any value)
+    #[arg(long = "where", value_name = "NAME=VALUE", value_parser = events::parse_condition)]
+    conditions: Vec<(String, String)>,
+}
+
+impl FilterArgs {
+    pub fn filter(&self, contract: Address) -> anyhow::Result<Filter> {
+        events::filter(contract, &self.events, &self.conditions)
+    }
+
+    /// An exporter writing the selected events to `path`.
+    pub fn exporter(&self, path: &Path) -> anyhow::Result<Exporter> {
+        Exporter::create(path, Format::from_path(path)?, export::parameter_columns(&self.events))
+    }
+}
+
+/// Prints the contract's events in a block range, in order, as one JSON
+/// object per line on stdout, or exports them to a file; progress goes to
+/// stderr.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let provider = pipeline::provider(&config)?;
+    let to = match args.to_block {
+        Some(block) => block,
+        None => provider.get_block_number().await?.as_u64(),
+    };
+    anyhow::ensure!(args.from_block <= to, "--from-block {} is after --to-block {}", args.from_block, to);
+    let filter = args.filter.filter(config.contract_address)?;
+    eprintln!("Events of {:?} on chain {}, blocks {}..={}", config.contract_address, config.chain_id, args.from_block, to);
+
+    let mut exporter = args.output.as_deref().map(|path| args.filter.exporter(path)).transpose()?;
+    let mut count = 0;
+    events::backfill(&provider, &filter, args.from_block, to, args.chunk, |log| {
+        count += 1;
+        match &mut exporter {
+            Some(exporter) => exporter.write(log),
+            None => {
+                println!("{}", events::to_json(log));
+                Ok(())
+            }
+        }
+    })
+    .await?;
+    if let (Some(exporter), Some(path)) = (exporter, &args.output) {
+        exporter.finish()?;
+        eprintln!("Wrote {}", path.display());
+    }
+    eprintln!("{} event(s)", count);
+    Ok(())
+}
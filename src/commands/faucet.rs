@@ -0,0 +1,53 @@
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::faucet::{self, Faucet};
+use eth_contract_caller::{pipeline, print_ok};
+use ethers::prelude::*;
+use ethers::utils::{format_ether, parse_ether};
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Wallet to fund (defaults to the sender wallet)
+    #[arg(long, value_name = "ADDRESS")]
+    address: Option<Address>,
+    /// Skip the request when the wallet already holds this much ether
+    #[arg(long, value_name = "ETHER")]
+    min_balance: Option<String>,
+    /// How long to wait for the funds to arrive
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "5m")]
+    timeout: Duration,
+}
+
+/// Requests test funds for the sender wallet and waits until they arrive.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let faucet = Faucet::from_env(config.chain_id)?;
+    let address = match args.address {
+        Some(address) => address,
+        None => pipeline::sender_address(&*pipeline::connect_simulation(&config)?)?,
+    };
+    let provider = pipeline::provider(&config)?;
+    let before = provider.get_balance(address, None).await?;
+
+    println!("=== Faucet ===");
+    println!("Chain: {} ({})", config.chain_id, faucet::testnet_name(config.chain_id).unwrap_or("testnet"));
+    println!("Wallet: {:?}", address);
+    println!("Balance: {} ETH", format_ether(before));
+    if let Some(min_balance) = &args.min_balance {
+        if before >= parse_ether(min_balance)? {
+            print_ok!("Balance is already at least {} ETH; not requesting funds", min_balance);
+            return Ok(());
+        }
+    }
+
+    println!("Requesting funds from {}", faucet.url());
+    let response = faucet.request(config.chain_id, address).await?;
+    println!("Faucet Response: {}", response);
+    match faucet::wait_for_funds(&provider, address, before, args.timeout).await? {
+        Some(balance) => {
+            print_ok!("Received {} ETH; balance is now {} ETH", format_ether(balance - before), format_ether(balance));
+            Ok(())
+        }
+        None => anyhow::bail!("no funds arrived within {:?}", args.timeout),
+    }
+}
@@ -0,0 +1,49 @@
+use eth_contract_caller::config::Config;
+use eth_contract_caller::fees::{self, FeeModel};
+use eth_contract_caller::pipeline;
+use ethers::prelude::*;
+use ethers::utils::format_units;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Number of recent blocks to sample (defaults to the profile's
+    /// history_blocks, or FEE_HISTORY_BLOCKS)
+    #[arg(long)]
+    blocks: Option<u64>,
+}
+
+/// Charts the base fee and tip over recent blocks and prints the fees each
+/// speed tier would bid now.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let provider = pipeline::provider(&config)?;
+    let mut model = FeeModel::from_env(config.chain_id)?;
+    if let Some(blocks) = args.blocks {
+        model.blocks = blocks;
+        model.validate()?;
+    }
+    let report = model.report(&provider).await?;
+
+    let gwei = |value: &U256| format_units(*value, "gwei");
+    let (min, max) = (report.base_fees.iter().min(), report.base_fees.iter().max());
+    println!("=== Gas Price History ===");
+    println!("Blocks: {} from {}", report.tips.len(), report.oldest_block);
+    if let (Some(min), Some(max)) = (min, max) {
+        println!("Base Fee: {}  ({} - {} Gwei)", fees::sparkline(&report.base_fees), gwei(min)?, gwei(max)?);
+    }
+    let (min, max) = (report.tips.iter().min(), report.tips.iter().max());
+    if let (Some(min), Some(max)) = (min, max) {
+        println!("Tip p50:  {}  ({} - {} Gwei)", fees::sparkline(&report.tips), gwei(min)?, gwei(max)?);
+    }
+    println!();
+
+    println!("=== Recommendations ===");
+    if let Some((_, fees)) = report.tiers.first() {
+        println!("Next Base Fee: {} Gwei", gwei(&fees.base_fee)?);
+    }
+    for (name, fees) in &report.tiers {
+        println!("{:<9} tip {} Gwei, max fee {} Gwei", name, gwei(&fees.max_priority_fee_per_gas)?, gwei(&fees.max_fee_per_gas)?);
+    }
+
+    Ok(())
+}
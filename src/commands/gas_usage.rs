@@ -0,0 +1,46 @@
+use eth_contract_caller::gas_usage;
+use ethers::utils::format_units;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Only report sends on this chain
+    #[arg(long)]
+    chain_id: Option<u64>,
+    /// Print the summaries as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// Summarizes how the gas of past sends compared with its estimate, per
+/// chain and subcommand.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let mut usages = gas_usage::load()?;
+    usages.retain(|usage| args.chain_id.is_none_or(|chain_id| usage.chain_id == chain_id));
+    let summaries = gas_usage::summarize(&usages);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&summaries)?);
+        return Ok(());
+    }
+
+    println!("=== Gas Usage ===");
+    if summaries.is_empty() {
+        println!("No sends recorded yet");
+        return Ok(());
+    }
+    for summary in &summaries {
+        println!();
+        println!("Chain {} {} ({} sends)", summary.chain_id, summary.kind, summary.sends);
+        println!(
+            "Used vs estimated: {:+.1}% on average ({:+.1}% to {:+.1}%)",
+            summary.mean_delta_percent, summary.min_delta_percent, summary.max_delta_percent
+        );
+        println!("Total Gas Used: {}", summary.total_gas_used);
+        if let Some(price) = summary.mean_gas_price {
+            println!("Mean Effective Gas Price: {} Gwei", format_units(price, "gwei")?);
+        }
+        if summary.out_of_gas > 0 {
+            println!("Reverted at the estimate, likely out of gas: {}", summary.out_of_gas);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use anyhow::Context;
+use eth_contract_caller::config::{self, Config, Job, JobParams};
+use eth_contract_caller::contract::MyContract;
+use eth_contract_caller::pipeline::Client;
+use eth_contract_caller::{nonce, signature, signing_service};
+use ethers::prelude::*;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Where the job's parameters come from, for commands that send one job.
+#[derive(clap::Args, Default)]
+pub struct JobArgs {
+    /// Read the whole job as JSON (`user`, `token`, `amount` or `token_id`,
+    /// `nonce`, `signature`) from this file, or `-` for stdin, instead of the
+    /// environment
+    #[arg(long, value_name = "PATH")]
+    params_json: Option<PathBuf>,
+    /// Read the signature, as hex, from this file (or `-` for stdin) instead
+    /// of SIGNATURE
+    #[arg(long, value_name = "PATH")]
+    signature_file: Option<PathBuf>,
+}
+
+impl JobArgs {
+    /// Loads the job for a `kind` command. With `auto_nonce`, the nonce is the
+    /// contract's next unused lock nonce for the job's user and token.
+    ///
+    /// The signature is taken from the first of: the JSON job, the signature
+    /// file, the signing service, SIGNATURE.
+    pub async fn load(&self, kind: &str, config: &Config, auto_nonce: Option<&MyContract<Client>>) -> anyhow::Result<Job> {
+        let (mut job, signature) = match &self.params_json {
+            Some(path) => {
+                let params = JobParams::from_json(&read(path)?)
+                    .with_context(|| format!("invalid job in {}", path.display()))?;
+                let nonce = match (params.nonce, auto_nonce) {
+                    (Some(nonce), _) => nonce,
+                    (None, Some(_)) => U256::zero(),
+                    (None, None) => anyhow::bail!("the job JSON has no nonce"),
+                };
+                let job = Job { user: params.user, token: params.token, amount: params.amount, nonce, signature: Bytes::new() };
+                (job, params.signature)
+            }
+            None => {
+                let nonce = match auto_nonce {
+                    Some(_) => U256::zero(),
+                    None => config::parse_nonce(&config::var("NONCE")?)?,
+                };
+                let job = match kind {
+                    config::NFT_LOCK_KIND => Job::unsigned_nft_from_env(nonce)?,
+                    config::ERC1155_LOCK_KIND => Job::unsigned_erc1155_from_env(nonce)?,
+                    _ => Job::unsigned_from_env(nonce)?,
+                };
+                (job, None)
+            }
+        };
+        if let Some(contract) = auto_nonce {
+            job.nonce = nonce::next_lock_nonce(contract, job.user, job.token).await?;
+        }
+
+        match (signature, &self.signature_file) {
+            (Some(signature), _) => job.signature = signature,
+            (None, Some(path)) => {
+                let text = read(path)?;
+                job.signature = signature::parse(text.trim())
+                    .with_context(|| format!("invalid signature in {}", path.display()))?;
+            }
+            (None, None) => signing_service::sign_from_env(kind, config, &mut job).await?,
+        }
+        Ok(job)
+    }
+}
+
+/// Reads `path`, or stdin for `-`.
+fn read(path: &Path) -> anyhow::Result<String> {
+    if path == Path::new("-") {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).context("failed to read stdin")?;
+        return Ok(text);
+    }
+    fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+}
@@ -0,0 +1,36 @@
+use eth_contract_caller::print_ok;
+use eth_contract_caller::secrets;
+use std::io::{self, BufRead};
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Store a secret read from stdin under ENTRY, for use as `keyring:ENTRY`
+    Set { entry: String },
+    /// Remove the secret stored under ENTRY
+    Delete { entry: String },
+}
+
+pub fn run(args: Args) -> anyhow::Result<()> {
+    match args.command {
+        Command::Set { entry } => {
+            println!("Enter the secret for {:?} and press Enter:", entry);
+            let mut secret = String::new();
+            io::stdin().lock().read_line(&mut secret)?;
+            let secret = secret.trim();
+            anyhow::ensure!(!secret.is_empty(), "no secret given");
+            secrets::keyring_set(&entry, secret)?;
+            print_ok!("Stored; reference it as keyring:{}", entry);
+        }
+        Command::Delete { entry } => {
+            secrets::keyring_delete(&entry)?;
+            print_ok!("Deleted keyring:{}", entry);
+        }
+    }
+    Ok(())
+}
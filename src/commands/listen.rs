@@ -0,0 +1,124 @@
+use super::events::FilterArgs;
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::export::Format;
+use eth_contract_caller::registry::{Registry, Watch};
+use eth_contract_caller::shutdown::{self, CancellationToken};
+use eth_contract_caller::{events, pipeline, style};
+use ethers::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Print the events of this many recent blocks
+    #[arg(long, default_value_t = 100)]
+    blocks: u64,
+    /// Stay attached and print new events as they are emitted, like `tail -f`
+    #[arg(long)]
+    follow: bool,
+    /// How often to poll for new blocks when WS_RPC_URL isn't set
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "4s")]
+    poll_interval: Duration,
+    /// Write the events to this `.csv` file instead of printing them (or to
+    /// a `.parquet` file, without --follow)
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// Watch every contract in this registry file, across its chains,
+    /// instead of CONTRACT_ADDRESS; filters come from the file
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["output", "events", "conditions"])]
+    registry: Option<PathBuf>,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+/// Prints the contract's events as one JSON object per line on stdout;
+/// everything else goes to stderr.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    if let Some(path) = &args.registry {
+        return run_registry(&args, path).await;
+    }
+    let config = Config::from_env()?;
+    let provider = pipeline::provider(&config)?;
+    let filter = args.filter.filter(config.contract_address)?;
+    let mut exporter = match &args.output {
+        Some(path) => {
+            // Parquet is only written once the stream ends, which it doesn't.
+            anyhow::ensure!(
+                !args.follow || Format::from_path(path)? == Format::Csv,
+                "--follow can only export to CSV, which is written as events arrive"
+            );
+            Some(args.filter.exporter(path)?)
+        }
+        None => None,
+    };
+    let mut emit = |log: &Log| match &mut exporter {
+        Some(exporter) => exporter.write(log),
+        None => {
+            println!("{}", events::to_json(log));
+            Ok(())
+        }
+    };
+    let latest = provider.get_block_number().await?;
+    eprintln!("Events of {:?} on chain {}", config.contract_address, config.chain_id);
+
+    if args.blocks > 0 {
+        let from = latest.as_u64().saturating_sub(args.blocks - 1);
+        events::backfill(&provider, &filter, from, latest.as_u64(), events::DEFAULT_CHUNK, &mut emit).await?;
+    }
+    if !args.follow {
+        return match exporter {
+            Some(exporter) => exporter.finish(),
+            None => Ok(()),
+        };
+    }
+    let ws_url = config::ws_rpc_url().ok();
+    eprintln!("Press Ctrl-C to stop");
+    let next = latest + 1;
+    let cancel = shutdown::on_signal()?;
+    events::follow(&provider, ws_url.as_deref(), &filter, next, args.poll_interval, &cancel, emit).await?;
+    match exporter {
+        Some(exporter) => exporter.finish(),
+        None => Ok(()),
+    }
+}
+
+/// Prints the events of every contract in the registry at `path`, each
+/// tagged with the contract's name and chain. A contract whose chain fails
+/// is reported and the others carry on.
+async fn run_registry(args: &Args, path: &Path) -> anyhow::Result<()> {
+    let registry = Registry::load(path)?;
+    let watches = registry.watches(path.parent().unwrap_or(Path::new("")))?;
+    let cancel = shutdown::on_signal()?;
+    if args.follow {
+        eprintln!("Press Ctrl-C to stop");
+    }
+    let runs = watches.iter().map(|watch| async {
+        let result = listen(&registry, watch, args, &cancel).await;
+        if let Err(e) = &result {
+            eprintln!("{}", style::error(&format!("{}: {:#}", watch.name, e)));
+        }
+        result
+    });
+    let failed = futures::future::join_all(runs).await.iter().filter(|result| result.is_err()).count();
+    anyhow::ensure!(failed == 0, "{} of {} contracts failed", failed, watches.len());
+    Ok(())
+}
+
+async fn listen(registry: &Registry, watch: &Watch, args: &Args, cancel: &CancellationToken) -> anyhow::Result<()> {
+    let provider = registry.provider(&watch.chain)?;
+    let mut emit = |log: &Log| -> anyhow::Result<()> {
+        println!("{}", watch.to_json(log));
+        Ok(())
+    };
+    let latest = provider.get_block_number().await?;
+    eprintln!("Events of {} on {}", watch.name, watch.chain);
+    if args.blocks > 0 {
+        let from = latest.as_u64().saturating_sub(args.blocks - 1);
+        events::backfill(&provider, &watch.filter, from, latest.as_u64(), events::DEFAULT_CHUNK, &mut emit).await?;
+    }
+    if !args.follow {
+        return Ok(());
+    }
+    let ws_url = registry.chains[&watch.chain].ws_url.as_deref();
+    events::follow(&provider, ws_url, &watch.filter, latest + 1, args.poll_interval, cancel, emit).await
+}
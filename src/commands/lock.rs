@@ -0,0 +1,226 @@
+use super::job::JobArgs;
+use super::send::{self, SendArgs};
+use anyhow::Context;
+use eth_contract_caller::config::{Config, LOCK_KIND};
+use eth_contract_caller::contract::{AuthorizedLock, Erc20, LockCall, LockWithAuthorizationCall, MyContract};
+use eth_contract_caller::error::Error;
+use eth_contract_caller::interfaces::{self, Support};
+use eth_contract_caller::lock_request::{LockRequest, ValueMismatch, ValuePolicy};
+use eth_contract_caller::quorum::Quorum;
+use eth_contract_caller::validity::{self, Window};
+use eth_contract_caller::{authorization, config, nonce, permit, pipeline, schedule, token};
+use eth_contract_caller::{print_ok, print_warn};
+use ethers::contract::EthCall;
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::{BlockId, H256, U256};
+use std::sync::Arc;
+use std::time::Duration;
+
+const KIND: &str = LOCK_KIND;
+
+#[derive(clap::Args, Default)]
+pub struct Args {
+    /// Use the contract's next unused lock nonce instead of NONCE
+    #[arg(long)]
+    auto_nonce: bool,
+    /// Abort unless NONCE is the contract's next unused lock nonce
+    #[arg(long, conflicts_with = "auto_nonce")]
+    check_nonce: bool,
+    /// Value to attach (e.g. `0.5ether`, `1000` wei); defaults to the amount
+    /// for native-currency locks plus LOCK_EXTRA_VALUE
+    #[arg(long, value_parser = token::parse_value)]
+    value: Option<U256>,
+    /// Send even if --value differs from the value the lock is expected to carry
+    #[arg(long, requires = "value")]
+    allow_value_mismatch: bool,
+    /// Approve with an EIP-2612 permit signed by USER_PRIVATE_KEY, sent
+    /// together with the lock in one transaction through LOCK_ROUTER
+    #[arg(long)]
+    permit: bool,
+    /// Fund the lock with an EIP-3009 authorization signed by
+    /// USER_PRIVATE_KEY instead of an approval
+    #[arg(long, conflicts_with = "permit")]
+    authorize: bool,
+    /// Send from the user's smart account (SMART_ACCOUNT) as a UserOperation
+    /// signed by USER_PRIVATE_KEY, with the gas paid by PAYMASTER_URL
+    #[arg(long, conflicts_with_all = ["permit", "authorize", "safe"])]
+    gasless: bool,
+    /// How long the permit or authorization stays valid
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "1h")]
+    valid_for: Duration,
+    /// Make the authorization valid only after this time (e.g.
+    /// `2024-07-01T00:00Z`, or Unix seconds)
+    #[arg(long, value_name = "TIME", value_parser = schedule::parse_timestamp, requires = "authorize")]
+    valid_after: Option<u64>,
+    /// Wait for new blocks until the authorization is valid, then send,
+    /// instead of failing while it isn't yet
+    #[arg(long, requires = "authorize")]
+    wait_until_valid: bool,
+    #[command(flatten)]
+    job: JobArgs,
+    #[command(flatten)]
+    send: SendArgs,
+}
+
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let client = pipeline::connect_simulation(&config)?;
+    let code = pipeline::ensure_contract_deployed(&*client, &config).await?;
+    let selector = match args.authorize {
+        true => LockWithAuthorizationCall::selector(),
+        false => LockCall::selector(),
+    };
+    pipeline::warn_if_selector_missing(&*client, &config, &code, selector).await?;
+    let contract = MyContract::new(config.contract_address, client.clone());
+
+    let job = args.job.load(KIND, &config, args.auto_nonce.then_some(&contract)).await?;
+    // Malformed jobs and an unexpected --value fail before anything is read from the chain.
+    let policy = match args.value {
+        None => ValuePolicy::Expected,
+        Some(value) if args.allow_value_mismatch => ValuePolicy::Override(value),
+        Some(value) => ValuePolicy::Exact(value),
+    };
+    let request = LockRequest::builder().job(&job).value(policy).build().map_err(|e| match e.downcast_ref() {
+        Some(ValueMismatch { value, expected, kind }) => anyhow::anyhow!(
+            "--value {} differs from the {} wei {} lock is expected to carry (pass --allow-value-mismatch to send anyway)",
+            value,
+            expected,
+            kind
+        ),
+        None => e,
+    })?;
+    // The request's job carries the signature normalized as the contract expects it.
+    let job = request.job().clone();
+    let quorum = Quorum::from_env(&config)?;
+    pipeline::print_configuration(&config, &job);
+    pipeline::print_token(client.clone(), &job).await?;
+    // Native-currency locks are paid with the attached value; ERC-20 locks
+    // are pulled from the user, who must have approved the contract.
+    anyhow::ensure!(
+        !((args.permit || args.authorize) && job.is_native()?),
+        "--permit and --authorize apply to ERC-20 locks; native-currency locks need no approval"
+    );
+    if !job.is_native()? {
+        // An NFT contract would take `amount` as a token id, if anything.
+        if interfaces::supports(client.clone(), job.token, interfaces::ERC721).await? == Support::Supported {
+            print_warn!("Token {:?} is an ERC-721 collection; lock moves ERC-20 amounts", job.token);
+            println!();
+        }
+        // With --permit, --authorize or --gasless, the approval comes with the lock.
+        if !args.permit && !args.authorize && !args.gasless {
+            println!("=== Allowance ===");
+            let (token_address, user, spender) = (job.token, job.user, config.contract_address);
+            let (allowance, balance) = match &quorum {
+                Some(quorum) => {
+                    let read = |provider: Arc<Provider<Http>>, block: BlockId| {
+                        token::allowance_and_balance(provider, token_address, user, spender, Some(block))
+                    };
+                    quorum.read("allowance and balance", read).await?
+                }
+                None => token::allowance_and_balance(client.clone(), token_address, user, spender, None).await?,
+            };
+            token::check_allowance(allowance, balance, spender, job.amount);
+            println!();
+        }
+    }
+
+    // A lock record for this nonce means the job already went through; sending
+    // again would only burn gas on a guaranteed revert.
+    let used = match &quorum {
+        Some(quorum) => quorum.is_lock_nonce_used(config.contract_address, &job).await?,
+        None => nonce::is_lock_nonce_used(&contract, job.user, job.token, job.nonce).await?,
+    };
+    if used {
+        return Err(Error::AlreadyProcessed { user: job.user, token: job.token, nonce: job.nonce }.into());
+    }
+
+    if args.check_nonce {
+        let expected = nonce::next_lock_nonce(&contract, job.user, job.token).await?;
+        if job.nonce != expected {
+            return Err(Error::NonceMismatch { nonce: job.nonce, expected }.into());
+        }
+        print_ok!("Nonce {} is the next unused lock nonce", job.nonce);
+        println!();
+    }
+
+    let value = request.value();
+    if value != request.expected_value() {
+        let expected = request.expected_value();
+        print_warn!("Attaching {} wei instead of the expected {} wei because of --allow-value-mismatch", value, expected);
+        println!();
+    }
+
+    if args.gasless {
+        let lock_data = contract
+            .lock(job.user, job.token, job.amount, job.nonce, job.signature.clone())
+            .calldata()
+            .context("lock call has no calldata")?;
+        let mut calls = vec![(config.contract_address, value, lock_data)];
+        // The account approves the contract in the same operation.
+        if !job.is_native()? {
+            let approve = Erc20::new(job.token, client.clone())
+                .approve(config.contract_address, job.amount)
+                .calldata()
+                .context("approve call has no calldata")?;
+            calls.insert(0, (job.token, U256::zero(), approve));
+        }
+        return send::send_gasless(KIND, &config, &job, &client, calls, &args.send).await;
+    }
+
+    if args.authorize {
+        println!("=== Authorization ===");
+        let wallet = permit::user_wallet(&config, &job)?;
+        let to = config.contract_address;
+        let valid_after = args.valid_after.unwrap_or_default();
+        let signed =
+            authorization::sign(client.clone(), &wallet, job.token, to, job.amount, valid_after, args.valid_for).await?;
+        print_ok!("User authorized {} base units, valid until {}", job.amount, signed.valid_before);
+        let window = Window { valid_after: signed.valid_after.as_u64(), valid_until: signed.valid_before.as_u64() };
+        if args.wait_until_valid {
+            validity::wait_until_open(&*client, "Authorization", window, client.provider().get_interval()).await?;
+        }
+        let sender = pipeline::sender_address(&*client)?;
+        send::check_window(&*client, KIND, &config, sender, &job, "Authorization", window).await?;
+        println!("Authorization nonce: {:?}", H256(signed.nonce));
+        println!();
+        let (v, r, s) = signed.vrs();
+        let call = AuthorizedLock::new(config.contract_address, client.clone())
+            .lock_with_authorization(
+                job.user,
+                job.token,
+                job.amount,
+                job.nonce,
+                job.signature.clone(),
+                signed.valid_after,
+                signed.valid_before,
+                signed.nonce,
+                v,
+                r,
+                s,
+            )
+            .value(value);
+        return send::send(KIND, &config, &job, &client, call, &args.send).await;
+    }
+
+    let call = contract
+        .lock(job.user, job.token, job.amount, job.nonce, job.signature.clone())
+        .value(value);
+    let call = match args.permit {
+        true => {
+            println!("=== Permit ===");
+            let wallet = permit::user_wallet(&config, &job)?;
+            let router = permit::router()?;
+            let spender = config.contract_address;
+            let signed = permit::sign(client.clone(), &wallet, job.token, spender, job.amount, args.valid_for).await?;
+            print_ok!("User signed a permit for {} base units, valid until {}", job.amount, signed.deadline);
+            let window = Window::until(signed.deadline.as_u64());
+            let sender = pipeline::sender_address(&*client)?;
+            send::check_window(&*client, KIND, &config, sender, &job, "Permit", window).await?;
+            println!("Sending permit and lock together through router {:?}", router);
+            println!();
+            permit::bundle(client.clone(), router, job.token, &signed, &call, value)?
+        }
+        false => call,
+    };
+    send::send(KIND, &config, &job, &client, call, &args.send).await
+}
@@ -0,0 +1,85 @@
+use super::job::JobArgs;
+use super::send::{self, SendArgs};
+use eth_contract_caller::config::{self, Config, ERC1155_LOCK_KIND};
+use eth_contract_caller::contract::{MyContract, NftLock};
+use eth_contract_caller::error::Error;
+use eth_contract_caller::quorum::Quorum;
+use eth_contract_caller::interfaces::{self, ERC1155};
+use eth_contract_caller::{nft, nonce, pipeline, print_error, print_ok, print_warn};
+use ethers::types::U256;
+
+const KIND: &str = ERC1155_LOCK_KIND;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// If the lock contract isn't an operator of the user's tokens and the
+    /// wallet is the user, approve it with setApprovalForAll first
+    #[arg(long)]
+    approve: bool,
+    #[command(flatten)]
+    job: JobArgs,
+    #[command(flatten)]
+    send: SendArgs,
+}
+
+/// Locks the ERC-1155 positions TOKEN_IDS and AMOUNTS of TOKEN_ADDRESS in one
+/// `lockERC1155Batch`. The rest of the job is read as for `lock`, with the
+/// total of AMOUNTS standing in for AMOUNT.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let positions = config::erc1155_positions()?;
+    let client = pipeline::connect_simulation(&config)?;
+    pipeline::ensure_contract_deployed(&*client, &config).await?;
+    let job = args.job.load(KIND, &config, None).await?;
+    pipeline::print_configuration_as(&config, &job, "Total Amount");
+    let total = positions.iter().fold(U256::zero(), |total, (_, amount)| total + *amount);
+    anyhow::ensure!(job.amount == total, "the job's amount {} is not the total {} of AMOUNTS", job.amount, total);
+
+    println!("=== Positions ===");
+    interfaces::require(client.clone(), job.token, ERC1155, "ERC-1155").await?;
+    for (id, amount) in &positions {
+        println!("Token {}: {}", id, amount);
+    }
+    let shortfalls = nft::erc1155_shortfalls(client.clone(), job.token, job.user, &positions).await?;
+    for (id, held, amount) in &shortfalls {
+        print_error!("User holds {} of token {}; the lock needs {}", held, id, amount);
+    }
+    anyhow::ensure!(shortfalls.is_empty(), "the user holds too little of {} token id(s)", shortfalls.len());
+    print_ok!("User holds every position");
+    let contract_address = config.contract_address;
+    if nft::erc1155_is_operator(client.clone(), job.token, job.user, contract_address).await? {
+        print_ok!("Contract is an approved operator for the user's tokens");
+    } else if !args.approve {
+        anyhow::bail!(
+            "contract {:?} isn't an operator of the user's tokens; have the user call setApprovalForAll, or pass --approve as the user",
+            contract_address
+        );
+    } else if args.send.is_dry_run() {
+        print_warn!("Dry run: not approving the contract, so the preflight will revert");
+    } else {
+        let wallet = pipeline::connect(&config)?;
+        anyhow::ensure!(wallet.address() == job.user, "--approve needs the wallet to be the user {:?}", job.user);
+        nft::erc1155_approve(&wallet, job.token, contract_address).await?;
+    }
+    println!();
+
+    let contract = MyContract::new(contract_address, client.clone());
+    let used = match Quorum::from_env(&config)? {
+        Some(quorum) => quorum.is_lock_nonce_used(contract_address, &job).await?,
+        None => nonce::is_lock_nonce_used(&contract, job.user, job.token, job.nonce).await?,
+    };
+    if used {
+        return Err(Error::AlreadyProcessed { user: job.user, token: job.token, nonce: job.nonce }.into());
+    }
+
+    let (ids, amounts) = positions.into_iter().unzip();
+    let call = NftLock::new(contract_address, client.clone()).lock_erc1155_batch(
+        job.user,
+        job.token,
+        ids,
+        amounts,
+        job.nonce,
+        job.signature.clone(),
+    );
+    send::send(KIND, &config, &job, &client, call, &args.send).await
+}
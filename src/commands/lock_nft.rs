@@ -0,0 +1,87 @@
+use super::job::JobArgs;
+use super::send::{self, SendArgs};
+use eth_contract_caller::config::{Config, NFT_LOCK_KIND};
+use eth_contract_caller::contract::{MyContract, NftLock};
+use eth_contract_caller::error::Error;
+use eth_contract_caller::quorum::Quorum;
+use eth_contract_caller::interfaces::{self, ERC721};
+use eth_contract_caller::nft::{self, Approval};
+use eth_contract_caller::{nonce, pipeline, print_ok, print_warn};
+
+const KIND: &str = NFT_LOCK_KIND;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// If the lock contract isn't approved for the token and the wallet owns
+    /// it, approve the contract from the wallet first
+    #[arg(long)]
+    approve: bool,
+    #[command(flatten)]
+    job: JobArgs,
+    #[command(flatten)]
+    send: SendArgs,
+}
+
+/// Locks the ERC-721 token TOKEN_ID of TOKEN_ADDRESS via `lockNFT`. The rest
+/// of the job is read as for `lock`; the signature must cover the token id
+/// where a `lock` one covers the amount.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let client = pipeline::connect_simulation(&config)?;
+    pipeline::ensure_contract_deployed(&*client, &config).await?;
+    let job = args.job.load(KIND, &config, None).await?;
+    pipeline::print_configuration_as(&config, &job, "Token ID");
+
+    println!("=== Token ===");
+    interfaces::require(client.clone(), job.token, ERC721, "ERC-721").await?;
+    let (owner, approval) = nft::approval(client.clone(), job.token, job.amount, config.contract_address).await?;
+    anyhow::ensure!(
+        owner == job.user,
+        "token {} of {:?} is owned by {:?}, not the user {:?}",
+        job.amount,
+        job.token,
+        owner,
+        job.user
+    );
+    print_ok!("User owns token {}", job.amount);
+    match approval {
+        Approval::Token => print_ok!("Contract is approved for the token"),
+        Approval::Operator => print_ok!("Contract is an approved operator for the user's tokens"),
+        Approval::Missing if args.approve && args.send.is_dry_run() => {
+            print_warn!("Dry run: not approving the contract, so the preflight will revert")
+        }
+        Approval::Missing if args.approve => {
+            let wallet = pipeline::connect(&config)?;
+            anyhow::ensure!(
+                wallet.address() == owner,
+                "--approve needs the wallet to own the token, but it belongs to {:?}",
+                owner
+            );
+            nft::approve(&wallet, job.token, job.amount, config.contract_address).await?;
+        }
+        Approval::Missing => anyhow::bail!(
+            "contract {:?} isn't approved for token {}; have the user approve it, or pass --approve as the owner",
+            config.contract_address,
+            job.amount
+        ),
+    }
+    println!();
+
+    let contract = MyContract::new(config.contract_address, client.clone());
+    let used = match Quorum::from_env(&config)? {
+        Some(quorum) => quorum.is_lock_nonce_used(config.contract_address, &job).await?,
+        None => nonce::is_lock_nonce_used(&contract, job.user, job.token, job.nonce).await?,
+    };
+    if used {
+        return Err(Error::AlreadyProcessed { user: job.user, token: job.token, nonce: job.nonce }.into());
+    }
+
+    let call = NftLock::new(config.contract_address, client.clone()).lock_nft(
+        job.user,
+        job.token,
+        job.amount,
+        job.nonce,
+        job.signature.clone(),
+    );
+    send::send(KIND, &config, &job, &client, call, &args.send).await
+}
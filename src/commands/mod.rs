@@ -0,0 +1,45 @@
+#[cfg(feature = "hardware")]
+pub mod accounts;
+pub mod batch;
+pub mod bench_rpc;
+pub mod broadcast;
+pub mod check_config;
+pub mod completions;
+pub mod config;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+pub mod decode;
+pub mod deploy;
+pub mod devnet;
+pub mod doctor;
+pub mod encode;
+pub mod events;
+pub mod faucet;
+pub mod gas;
+pub mod gas_usage;
+pub mod job;
+pub mod keyring;
+pub mod listen;
+pub mod lock;
+pub mod lock_erc1155;
+pub mod lock_nft;
+pub mod pending;
+pub mod rotate_key;
+pub mod safe;
+pub mod script;
+pub mod prove;
+pub mod receipt;
+pub mod repl;
+pub mod send;
+pub mod session_key;
+pub mod smart_account;
+pub mod status;
+pub mod storage;
+pub mod stress;
+pub mod stream;
+pub mod unlock;
+pub mod wallet;
+pub mod watch_balances;
+pub mod watch_contract;
+pub mod watch_mempool;
+pub mod workflow;
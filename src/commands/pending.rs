@@ -0,0 +1,150 @@
+use anyhow::Context;
+use eth_contract_caller::audit;
+use eth_contract_caller::config::Config;
+use eth_contract_caller::fees::FeeModel;
+use eth_contract_caller::ledger::Ledger;
+use eth_contract_caller::mempool::{self, StuckTx};
+use eth_contract_caller::pipeline;
+use eth_contract_caller::print_ok;
+use eth_contract_caller::profile::ReplacementPolicy;
+use eth_contract_caller::shutdown::CancellationToken;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::format_units;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Rebroadcast the transaction at this account nonce with higher fees
+    #[arg(long, value_name = "NONCE", conflicts_with = "cancel")]
+    speed_up: Option<u64>,
+    /// Replace the transaction at this account nonce with a zero-value self-transfer
+    #[arg(long, value_name = "NONCE")]
+    cancel: Option<u64>,
+}
+
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let simulation = pipeline::connect_simulation(&config)?;
+    let sender = pipeline::sender_address(&*simulation)?;
+    let ledger = Ledger::open().await?;
+
+    let gap = mempool::stuck_transactions(&*simulation, &ledger, sender).await?;
+    println!("=== Pending Transactions ===");
+    println!("Sender: {:?}", sender);
+    println!("Latest Nonce: {}", gap.latest);
+    println!("Pending Nonce: {}", gap.pending);
+    println!();
+    if gap.stuck.is_empty() {
+        print_ok!("No stuck transactions");
+        return Ok(());
+    }
+    for stuck in &gap.stuck {
+        print_stuck(stuck)?;
+    }
+
+    let (nonce, cancel) = match (args.speed_up, args.cancel) {
+        (Some(nonce), _) => (nonce, false),
+        (None, Some(nonce)) => (nonce, true),
+        (None, None) => return Ok(()),
+    };
+    let stuck = gap
+        .stuck
+        .iter()
+        .find(|stuck| stuck.nonce == U256::from(nonce))
+        .with_context(|| format!("nonce {} is not pending (latest {}, pending {})", nonce, gap.latest, gap.pending))?;
+
+    let client = pipeline::connect(&config)?;
+    anyhow::ensure!(
+        client.address() == sender,
+        "PRIVATE_KEY belongs to {:?}, but the stuck transactions are from {:?}",
+        client.address(),
+        sender
+    );
+    let policy = ReplacementPolicy::for_chain(config.chain_id)?;
+    let fees = FeeModel::from_env(config.chain_id)?.suggest(&*client).await.ok();
+    let tx = if cancel {
+        mempool::cancel(sender, stuck.nonce, stuck.tx.as_ref(), fees, &policy)
+    } else {
+        let original = stuck
+            .tx
+            .as_ref()
+            .with_context(|| format!("the transaction at nonce {} is not visible to this node; cancel it instead", nonce))?;
+        let tx = mempool::speed_up(original, fees, &policy);
+        anyhow::ensure!(
+            !mempool::exceeds_ceiling(&tx, &policy)?,
+            "the bumped max fee exceeds the profile's replacement max_fee_gwei"
+        );
+        tx
+    };
+
+    println!("=== {} Nonce {} ===", if cancel { "Cancelling" } else { "Speeding Up" }, nonce);
+    print_fees("New ", &tx)?;
+
+    // A sped-up job keeps its ledger record, under the new hash.
+    let entry = match (&stuck.tx, cancel) {
+        (Some(original), false) => ledger.find_by_tx_hash(original.hash).await?,
+        _ => None,
+    };
+    match entry {
+        Some(entry) => {
+            let never = CancellationToken::new();
+            pipeline::send_and_wait(&*client, tx, &ledger, &entry.kind, &config, &entry.job, true, None, &never).await?;
+            Ok(())
+        }
+        None => {
+            let purpose = if cancel { "cancel" } else { "speed-up" };
+            let pending = audit::send_transaction(&*client, tx, purpose).await?;
+            println!("Transaction Hash: {:?}", pending.tx_hash());
+            println!("Waiting for transaction to be mined...");
+            let receipt = pending.await?;
+            if let Some(receipt) = &receipt {
+                audit::record_receipt(client.address(), purpose, receipt)?;
+            }
+            pipeline::print_receipt(receipt);
+            Ok(())
+        }
+    }
+}
+
+fn print_stuck(stuck: &StuckTx) -> anyhow::Result<()> {
+    let Some(tx) = &stuck.tx else {
+        println!("Nonce {}: transaction not visible to this node", stuck.nonce);
+        println!("  Cancel: cargo run -- pending --cancel {}", stuck.nonce);
+        println!();
+        return Ok(());
+    };
+    println!("Nonce {}: {:?}", stuck.nonce, tx.hash);
+    println!("  To: {:?}", tx.to);
+    print_fees("  ", &tx.into())?;
+    match stuck.submitted_at {
+        Some(submitted_at) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            println!("  Age: {}s", now.saturating_sub(submitted_at));
+        }
+        None => println!("  Age: unknown (not in the ledger)"),
+    }
+    println!("  Speed up: cargo run -- pending --speed-up {}", stuck.nonce);
+    println!("  Cancel: cargo run -- pending --cancel {}", stuck.nonce);
+    println!();
+    Ok(())
+}
+
+fn print_fees(prefix: &str, tx: &TypedTransaction) -> anyhow::Result<()> {
+    match tx {
+        TypedTransaction::Eip1559(tx) => {
+            if let Some(max_fee) = tx.max_fee_per_gas {
+                println!("{}Max Fee: {} Gwei", prefix, format_units(max_fee, "gwei")?);
+            }
+            if let Some(tip) = tx.max_priority_fee_per_gas {
+                println!("{}Max Priority Fee: {} Gwei", prefix, format_units(tip, "gwei")?);
+            }
+        }
+        tx => {
+            if let Some(gas_price) = tx.gas_price() {
+                println!("{}Gas Price: {} Gwei", prefix, format_units(gas_price, "gwei")?);
+            }
+        }
+    }
+    Ok(())
+}
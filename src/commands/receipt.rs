@@ -0,0 +1,73 @@
+use eth_contract_caller::calldata::{self, Source};
+use eth_contract_caller::config::Config;
+use eth_contract_caller::{pipeline, print_error, print_ok};
+use ethers::prelude::*;
+use ethers::utils::format_units;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Hash of the mined transaction
+    hash: H256,
+    /// Decode the logs against abi.json and the standard ERC-20/ERC-721
+    /// events instead of printing raw topics and data
+    #[arg(long)]
+    decode: bool,
+}
+
+/// Prints a mined transaction's receipt and every log it emitted, from any
+/// contract, for looking into transactions this tool didn't send.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::without_contract_from_env()?;
+    let provider = pipeline::provider(&config)?;
+    let Some(receipt) = provider.get_transaction_receipt(args.hash).await? else {
+        anyhow::bail!("no receipt for {:?}; it is pending or unknown to {} (see `status`)", args.hash, config.rpc_url);
+    };
+
+    println!("=== Receipt ===");
+    println!("Hash: {:?}", receipt.transaction_hash);
+    println!("Block: {}", receipt.block_number.unwrap_or_default());
+    println!("From: {:?}", receipt.from);
+    match (receipt.to, receipt.contract_address) {
+        (Some(to), _) => println!("To: {:?}", to),
+        (None, Some(created)) => println!("Created: {:?}", created),
+        (None, None) => {}
+    }
+    if receipt.status == Some(U64::from(1)) {
+        print_ok!("Status: success");
+    } else {
+        print_error!("Status: failed");
+    }
+    println!("Gas Used: {}", receipt.gas_used.unwrap_or_default());
+    if let Some(price) = receipt.effective_gas_price {
+        println!("Effective Gas Price: {} Gwei", format_units(price, "gwei")?);
+    }
+    println!();
+
+    println!("=== Logs ({}) ===", receipt.logs.len());
+    for log in &receipt.logs {
+        println!("[{}] {:?}", log.log_index.unwrap_or_default(), log.address);
+        let decoded = if args.decode { calldata::decode_any_event(log) } else { None };
+        match decoded {
+            Some((name, params, source)) => {
+                let source = match source {
+                    Source::Abi => "abi.json",
+                    Source::Builtin => "built-in events",
+                };
+                println!("  {} (from {})", name, source);
+                for (name, value) in params {
+                    println!("    {}: {}", name, calldata::format_token(&value));
+                }
+            }
+            None => {
+                if args.decode {
+                    println!("  Unknown event: not in abi.json or the built-in events");
+                }
+                for (index, topic) in log.topics.iter().enumerate() {
+                    println!("  topic{}: {:?}", index, topic);
+                }
+                println!("  data: {}", log.data);
+            }
+        }
+    }
+    Ok(())
+}
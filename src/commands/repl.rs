@@ -0,0 +1,212 @@
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::contract::MYCONTRACT_ABI;
+use eth_contract_caller::pipeline::{self, Client};
+use eth_contract_caller::repl::{self, Input, Invocation};
+use eth_contract_caller::units::{self, Rounding};
+use eth_contract_caller::{audit, calldata, print_error, print_ok, print_warn, profile, style};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, Helper, Highlighter, Hinter, Validator};
+use std::sync::Arc;
+
+const USAGE: &str = "\
+call FUNCTION ARGS...                 eth_call a view function and decode its outputs
+estimate FUNCTION ARGS... [--value N] estimate gas from the sender
+send FUNCTION ARGS... [--value N]     preflight, confirm, sign and send
+encode FUNCTION ARGS...               print the calldata
+decode 0xCALLDATA                     decode calldata against abi.json
+functions                             list abi.json's functions
+quit                                  leave (or Ctrl-D)
+
+Overloaded functions are named by signature, e.g. setTrustedRemote(uint16,address).
+--value is in the native currency, e.g. 0.1.";
+
+/// Tab completion over commands and abi.json function names.
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct Completion;
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(repl::complete(line, pos))
+    }
+}
+
+/// An interactive prompt against the configured contract. Reads and
+/// estimates go through the simulation client; `send` loads PRIVATE_KEY on
+/// first use and asks before broadcasting. A failing line prints its error
+/// and the prompt carries on. History is kept in the state directory.
+pub async fn run() -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let simulation = pipeline::connect_simulation(&config)?;
+    let sender = pipeline::sender_address(&*simulation)?;
+    let mut session = Session { config, simulation, sender, client: None };
+
+    let history = config::state_dir().join("repl-history");
+    let mut editor = Editor::<Completion, DefaultHistory>::new()?;
+    editor.set_helper(Some(Completion));
+    // No history yet on the first run.
+    let _ = editor.load_history(&history);
+
+    let contract = session.config.contract_address;
+    println!("Contract {:?} on chain {}, sending from {:?}", contract, session.config.chain_id, sender);
+    println!("{}", style::dim("Type help for commands, Tab to complete, Ctrl-D to leave"));
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+        let input = match repl::parse(&line) {
+            Ok(Some(Input::Quit)) => break,
+            Ok(Some(input)) => input,
+            Ok(None) => continue,
+            Err(e) => {
+                print_error!("{:#}", e);
+                continue;
+            }
+        };
+        if let Err(e) = session.run(input, &mut editor).await {
+            print_error!("{:#}", e);
+        }
+    }
+    if let Some(parent) = history.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    editor.save_history(&history)?;
+    Ok(())
+}
+
+struct Session {
+    config: Config,
+    simulation: Arc<Client>,
+    sender: Address,
+    /// The signing client, connected on the first `send`.
+    client: Option<Arc<Client>>,
+}
+
+impl Session {
+    async fn run(&mut self, input: Input, editor: &mut Editor<Completion, DefaultHistory>) -> anyhow::Result<()> {
+        match input {
+            Input::Call(call) => self.call(call).await,
+            Input::Estimate(estimate) => {
+                let tx = self.transaction(&estimate)?;
+                let gas = self.simulation.estimate_gas(&tx, None).await.map_err(revert)?;
+                println!("Estimated Gas: {}", gas);
+                Ok(())
+            }
+            Input::Send(send) => self.send(send, editor).await,
+            Input::Encode(encode) => {
+                println!("0x{}", hex::encode(calldata::encode_function(&encode.function, &encode.args)?));
+                Ok(())
+            }
+            Input::Decode(data) => decode(&data),
+            Input::Functions => {
+                for function in MYCONTRACT_ABI.functions() {
+                    let mutability = format!("{:?}", function.state_mutability).to_lowercase();
+                    println!("{:<60} {}", repl::canonical(function), style::dim(mutability));
+                }
+                Ok(())
+            }
+            Input::Help => {
+                println!("{}", USAGE);
+                Ok(())
+            }
+            Input::Quit => Ok(()),
+        }
+    }
+
+    async fn call(&self, call: Invocation) -> anyhow::Result<()> {
+        anyhow::ensure!(call.value.is_none(), "call doesn't take --value; estimate or send a payable function");
+        let tx = self.transaction(&call)?;
+        let output = self.simulation.call(&tx, None).await.map_err(revert)?;
+        let tokens = call.function.decode_output(&output)?;
+        for (index, (param, value)) in call.function.outputs.iter().zip(&tokens).enumerate() {
+            let name = if param.name.is_empty() { format!("out{}", index) } else { param.name.clone() };
+            println!("  {} ({}): {}", name, param.kind, calldata::format_token(value));
+        }
+        if tokens.is_empty() {
+            println!("  (no outputs)");
+        }
+        Ok(())
+    }
+
+    /// Runs the fee model and preflight, asks, and only then signs and
+    /// broadcasts. Sends here are audited but not entered in the ledger:
+    /// they aren't jobs.
+    async fn send(&mut self, send: Invocation, editor: &mut Editor<Completion, DefaultHistory>) -> anyhow::Result<()> {
+        if repl::is_read(&send.function) {
+            print_warn!("{} only reads; call it instead of paying gas", send.function.name);
+        }
+        let mut tx = self.transaction(&send)?;
+        pipeline::apply_fees(&*self.simulation, &mut tx).await?;
+        let balance = self.simulation.get_balance(self.sender, None).await?;
+        if pipeline::preflight(&*self.simulation, &tx, balance).await?.is_none() {
+            return Ok(());
+        }
+        let answer = editor.readline(&format!("Send {} from {:?}? [y/N] ", send.function.name, self.sender))?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            println!("Not sent");
+            return Ok(());
+        }
+
+        let client = match &self.client {
+            Some(client) => client.clone(),
+            None => self.client.insert(pipeline::connect(&self.config)?).clone(),
+        };
+        anyhow::ensure!(
+            client.address() == self.sender,
+            "PRIVATE_KEY belongs to {:?}, but the preflight simulated sender {:?}",
+            client.address(),
+            self.sender
+        );
+        let pending = audit::send_transaction(&*client, tx, "repl").await?;
+        print_ok!("Transaction sent: {:?}", pending.tx_hash());
+        let receipt = pending.await?;
+        if let Some(receipt) = &receipt {
+            audit::record_receipt(client.address(), "repl", receipt)?;
+        }
+        pipeline::print_receipt(receipt);
+        Ok(())
+    }
+
+    /// A transaction from the sender to the contract calling `invocation`.
+    fn transaction(&self, invocation: &Invocation) -> anyhow::Result<TypedTransaction> {
+        let data = calldata::encode_function(&invocation.function, &invocation.args)?;
+        let mut tx = Eip1559TransactionRequest::new().from(self.sender).to(self.config.contract_address).data(data);
+        if let Some(value) = &invocation.value {
+            let decimals = profile::native_currency(self.config.chain_id)?.decimals;
+            tx = tx.value(units::parse_decimal(value, decimals, Rounding::Exact)?);
+        }
+        Ok(tx.into())
+    }
+}
+
+/// A node error, with its revert data decoded when it carries some.
+fn revert<E: MiddlewareError + 'static>(error: E) -> anyhow::Error {
+    match error.as_error_response().and_then(|response| response.as_revert_data()) {
+        Some(data) => anyhow::anyhow!(calldata::decode_revert(&data)),
+        None => error.into(),
+    }
+}
+
+fn decode(data: &str) -> anyhow::Result<()> {
+    let data = calldata::parse_hex(data)?;
+    let Some(decoded) = calldata::decode(&data)? else {
+        anyhow::bail!("unknown selector 0x{}", hex::encode(data.get(..4).unwrap_or(&data)));
+    };
+    println!("{}", decoded.function.signature());
+    for (index, (param, value)) in decoded.function.inputs.iter().zip(&decoded.args).enumerate() {
+        let name = if param.name.is_empty() { format!("arg{}", index) } else { param.name.clone() };
+        println!("  {} ({}): {}", name, param.kind, calldata::format_token(value));
+    }
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use eth_contract_caller::checkpoint::Checkpoint;
+use eth_contract_caller::config::Config;
+use eth_contract_caller::rotation::{self, NewSigner};
+use eth_contract_caller::{pipeline, print_ok, print_warn};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Batch file whose unsent rows are re-signed and rewritten
+    file: PathBuf,
+    /// Checkpoint of the interrupted run (default `<FILE>.checkpoint`)
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+}
+
+/// Re-signs the batch's unsent jobs with the new signer (NEW_SIGNER_KEY, or
+/// the signing service) so the run can be resumed after a key rotation.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let signer = NewSigner::from_env()?;
+    let checkpoint = args.checkpoint.unwrap_or_else(|| Checkpoint::default_path(&args.file));
+
+    if let Some(address) = signer.address() {
+        println!("New signer: {:?}", address);
+        let provider = Arc::new(pipeline::provider(&config)?);
+        let relayer = rotation::contract_relayer(provider, &config).await?;
+        if relayer != address {
+            print_warn!(
+                "The contract still checks signatures against {:?}; the re-signed rows will fail until it is updated",
+                relayer
+            );
+        }
+    }
+
+    let report = rotation::rotate(&config, &args.file, &checkpoint, &signer).await?;
+    print_ok!(
+        "Re-signed {} unsent row(s) of {}; {} already broadcast left as they were",
+        report.resigned,
+        args.file.display(),
+        report.kept
+    );
+    println!("Original kept as {}", report.backup.display());
+    println!("Resume with: batch {} --resume", args.file.display());
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use super::job::JobArgs;
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::contract::{GnosisSafe, MyContract};
+use eth_contract_caller::ledger::Ledger;
+use eth_contract_caller::pipeline;
+use eth_contract_caller::print_ok;
+use eth_contract_caller::safe::{Bundle, SafeTx};
+use eth_contract_caller::shutdown::CancellationToken;
+use ethers::prelude::*;
+use std::path::PathBuf;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Wrap the configured job in a Safe transaction and write it to a bundle file
+    Export {
+        /// Safe the call will be executed from
+        #[arg(long, value_name = "ADDRESS")]
+        safe: Address,
+        /// Wrap an unlock (redeemWithSignature) instead of a lock
+        #[arg(long)]
+        unlock: bool,
+        /// Bundle file to write
+        #[arg(long, default_value = "safe-tx.json")]
+        out: PathBuf,
+        #[command(flatten)]
+        job: JobArgs,
+    },
+    /// Add this wallet's signature to a bundle (works offline)
+    Sign {
+        bundle: PathBuf,
+    },
+    /// Show the signatures collected so far against the Safe's owners and threshold
+    Status {
+        bundle: PathBuf,
+    },
+    /// Execute a bundle once it holds enough signatures
+    Execute {
+        bundle: PathBuf,
+    },
+}
+
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    match args.command {
+        Command::Export { safe, unlock, out, job } => export(safe, unlock, out, job).await,
+        Command::Sign { bundle } => sign(bundle),
+        Command::Status { bundle } => status(bundle).await,
+        Command::Execute { bundle } => execute(bundle).await,
+    }
+}
+
+async fn export(safe_address: Address, unlock: bool, out: PathBuf, job_args: JobArgs) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let kind = if unlock { "unlock" } else { "lock" };
+    let job = job_args.load(kind, &config, None).await?;
+    pipeline::print_configuration(&config, &job);
+
+    let provider = std::sync::Arc::new(pipeline::provider(&config)?);
+    pipeline::ensure_contract_deployed(&*provider, &config).await?;
+    let contract = MyContract::new(config.contract_address, provider.clone());
+    let call = if unlock {
+        contract.redeem_with_signature(job.user, job.token, job.amount, job.nonce, job.signature.clone())
+    } else {
+        contract
+            .lock(job.user, job.token, job.amount, job.nonce, job.signature.clone())
+            .value(job.lock_value()?)
+    };
+
+    let safe = GnosisSafe::new(safe_address, provider);
+    let tx = SafeTx::from_call(&call.tx, safe.nonce().call().await?)?;
+    let safe_tx_hash = tx.eip712_hash(config.chain_id, safe_address);
+    // Co-signers verify against the local hash, so it must match the Safe's.
+    let onchain_hash = tx.hash(&safe).await?;
+    anyhow::ensure!(
+        onchain_hash == safe_tx_hash,
+        "Safe reports hash {:?}, expected {:?}; only Safe v1.3+ is supported",
+        onchain_hash,
+        safe_tx_hash
+    );
+    let bundle = Bundle {
+        kind: kind.to_string(),
+        job,
+        chain_id: config.chain_id,
+        safe: safe_address,
+        safe_tx_hash,
+        tx,
+        signatures: Vec::new(),
+    };
+    bundle.save(&out)?;
+
+    println!("=== Safe Transaction ===");
+    println!("Safe Address: {:?}", safe_address);
+    println!("Safe Nonce: {}", bundle.tx.nonce);
+    println!("Safe Tx Hash: {:?}", safe_tx_hash);
+    print_ok!("Bundle written to {}", out.display());
+    Ok(())
+}
+
+fn sign(path: PathBuf) -> anyhow::Result<()> {
+    let mut bundle = Bundle::load(&path)?;
+    let wallet = config::private_key()?.parse::<LocalWallet>()?;
+    // Offline there is no node to ask, but a configured CHAIN_ID must match
+    // the chain in the bundle's EIP-712 domain.
+    let chain_id = config::env_var("CHAIN_ID").map(|id| id.parse::<u64>()).transpose()?;
+    pipeline::ensure_same_chain(&[("CHAIN_ID", chain_id), ("bundle EIP-712 domain", Some(bundle.chain_id))])?;
+
+    println!("=== Signing Safe Transaction ===");
+    println!("Safe Address: {:?}", bundle.safe);
+    println!("Chain ID: {}", bundle.chain_id);
+    println!("Call: {} to {:?} with value {}", bundle.kind, bundle.tx.to, bundle.tx.value);
+    println!("Safe Tx Hash: {:?}", bundle.safe_tx_hash);
+
+    bundle.add_signature(&wallet)?;
+    bundle.save(&path)?;
+    print_ok!("Signed by {:?} ({} signature(s) collected)", wallet.address(), bundle.signatures.len());
+    Ok(())
+}
+
+async fn status(path: PathBuf) -> anyhow::Result<()> {
+    let bundle = Bundle::load(&path)?;
+    let config = Config::from_env()?;
+    let safe = GnosisSafe::new(bundle.safe, std::sync::Arc::new(pipeline::provider(&config)?));
+    let owners = safe.get_owners().call().await?;
+    let threshold = safe.get_threshold().call().await?;
+
+    println!("=== Safe Bundle Status ===");
+    println!("Safe Address: {:?}", bundle.safe);
+    println!("Safe Tx Hash: {:?}", bundle.safe_tx_hash);
+    for owner in &owners {
+        let signed = bundle.signatures.iter().any(|s| s.signer == *owner);
+        println!("{} {:?}", if signed { "✅" } else { "⏳" }, owner);
+    }
+    println!("Signatures: {}/{} required", bundle.signatures.len(), threshold);
+    Ok(())
+}
+
+async fn execute(path: PathBuf) -> anyhow::Result<()> {
+    let bundle = Bundle::load(&path)?;
+    bundle.verify_hash()?;
+    let config = Config::from_env()?;
+    anyhow::ensure!(
+        config.chain_id == bundle.chain_id,
+        "bundle is for chain {}, but CHAIN_ID is {}",
+        bundle.chain_id,
+        config.chain_id
+    );
+
+    let client = pipeline::connect(&config)?;
+    let safe = GnosisSafe::new(bundle.safe, client.clone());
+    let threshold = safe.get_threshold().call().await?;
+    anyhow::ensure!(
+        U256::from(bundle.signatures.len()) >= threshold,
+        "bundle has {} signature(s), but the Safe requires {}",
+        bundle.signatures.len(),
+        threshold
+    );
+
+    let balance = pipeline::print_wallet_info(&*client, client.address(), bundle.job.user).await?;
+    let mut tx = bundle.tx.exec_call(&safe, bundle.packed_signatures()?).tx;
+    pipeline::apply_fees(&*client, &mut tx).await?;
+    let Some(estimate) = pipeline::preflight(&*client, &tx, balance).await? else {
+        return Ok(());
+    };
+    pipeline::print_usd(client.clone(), &bundle.job, &estimate).await?;
+
+    let ledger = Ledger::open().await?;
+    let never = CancellationToken::new();
+    // The Safe's own nonce keeps the bundle from executing twice.
+    pipeline::send_and_wait(&*client, tx, &ledger, &bundle.kind, &config, &bundle.job, true, None, &never).await?;
+    Ok(())
+}
@@ -0,0 +1,39 @@
+#[cfg(feature = "scripting")]
+use anyhow::Context;
+#[cfg(feature = "scripting")]
+use eth_contract_caller::config::Config;
+#[cfg(feature = "scripting")]
+use eth_contract_caller::ledger::Ledger;
+#[cfg(feature = "scripting")]
+use eth_contract_caller::script::{self, Host};
+use std::path::PathBuf;
+#[cfg(feature = "scripting")]
+use std::sync::Arc;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Rhai script to run
+    file: PathBuf,
+    /// Arguments, available to the script as ARGS
+    args: Vec<String>,
+    /// Shell command `notify` runs, with its value as JSON on stdin;
+    /// without one the value is printed
+    #[arg(long, value_name = "COMMAND")]
+    on_notify: Option<String>,
+}
+
+/// Runs a transaction workflow script (see the library's `script` module for
+/// what it can call) on a blocking thread, since the engine calls into the
+/// node synchronously.
+#[cfg(feature = "scripting")]
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let source =
+        std::fs::read_to_string(&args.file).with_context(|| format!("failed to read {}", args.file.display()))?;
+    let host = Arc::new(Host::new(Config::from_env()?, Ledger::open().await?, args.on_notify)?);
+    tokio::task::spawn_blocking(move || script::run(host, &source, args.args)).await?
+}
+
+#[cfg(not(feature = "scripting"))]
+pub async fn run(_: Args) -> anyhow::Result<()> {
+    anyhow::bail!("scripts need a build with the `scripting` feature")
+}
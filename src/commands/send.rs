@@ -0,0 +1,556 @@
+use anyhow::Context;
+use eth_contract_caller::config::{self, Config, Job, LOCK_KIND};
+use eth_contract_caller::crosscheck;
+use eth_contract_caller::error::Error;
+use eth_contract_caller::fees::{self, GasGate};
+use eth_contract_caller::gas_tank::GasTank;
+use eth_contract_caller::gas_usage;
+use eth_contract_caller::hooks;
+use eth_contract_caller::ledger::{Entry, Ledger};
+use eth_contract_caller::mempool;
+use eth_contract_caller::output;
+use eth_contract_caller::pipeline::{self, Client, Rpc};
+use eth_contract_caller::plugins::{self, Preflight};
+use eth_contract_caller::policy;
+use eth_contract_caller::postcheck;
+use eth_contract_caller::price;
+use eth_contract_caller::profile;
+use eth_contract_caller::safe::{self, Wrapped};
+use eth_contract_caller::schedule::{self, Schedule};
+use eth_contract_caller::shutdown::CancellationToken;
+use eth_contract_caller::smart_account::{self, SmartAccount};
+use eth_contract_caller::unsigned::UnsignedTx;
+use eth_contract_caller::upgrades;
+use eth_contract_caller::validity::{self, Window};
+use eth_contract_caller::{print_ok, print_warn};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Options shared by every command that sends a single contract call.
+#[derive(clap::Args, Default)]
+pub struct SendArgs {
+    /// Send even if the ledger shows this exact job was already submitted
+    #[arg(long)]
+    force: bool,
+    /// Route the call through this Gnosis Safe, of which the wallet is an owner
+    #[arg(long, value_name = "ADDRESS")]
+    safe: Option<Address>,
+    /// Run every preflight check but stop before broadcasting
+    #[arg(long)]
+    dry_run: bool,
+    /// Run every preflight check, then write the unsigned transaction to
+    /// this file for an external signer instead of signing it; send it with
+    /// `broadcast`
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["safe", "dry_run", "watch_mempool", "deadline"])]
+    unsigned: Option<PathBuf>,
+    /// While waiting to be mined, report other pending calls to the contract
+    /// (needs WS_RPC_URL)
+    #[arg(long)]
+    watch_mempool: bool,
+    /// Cancel the transaction and fail if it isn't mined within this long
+    /// (e.g. `90s`, `5m`, `1h`)
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration)]
+    deadline: Option<Duration>,
+    /// Wait until this block has been mined before sending
+    #[arg(long, value_name = "BLOCK", conflicts_with = "send_at")]
+    send_at_block: Option<u64>,
+    /// Wait until a block at or after this time (e.g. `2024-07-01T00:00Z`, or
+    /// Unix seconds) has been mined before sending
+    #[arg(long, value_name = "TIME", value_parser = schedule::parse_timestamp)]
+    send_at: Option<u64>,
+    /// Hold the transaction back until the base fee is below this price
+    /// (e.g. `30gwei`)
+    #[arg(long, value_name = "PRICE", value_parser = fees::parse_gas_price)]
+    wait_until_gas_below: Option<U256>,
+    /// Give up if the base fee hasn't dropped in this long (e.g. `2h`)
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, requires = "wait_until_gas_below")]
+    max_gas_wait: Option<Duration>,
+    /// Abort if gas plus value would cost more than this many US dollars
+    /// (needs PRICE_SOURCE)
+    #[arg(long, value_name = "USD")]
+    max_usd_cost: Option<f64>,
+    /// Print a final result line from this template, e.g.
+    /// `{{tx_hash}} {{status}} {{gas_used}}`
+    #[arg(long, value_name = "TEMPLATE")]
+    format: Option<String>,
+    /// Simulate the call with debug_traceCall and show the expected native
+    /// and ERC-20 balance changes of the wallet, user and contract
+    #[arg(long)]
+    balance_diff: bool,
+    /// Simulate the call with debug_traceCall and show the contract storage
+    /// slots it would write, named where they can be matched to the job
+    #[arg(long)]
+    storage_diff: bool,
+    /// Simulate the call with the node's opcode tracer and break its gas down
+    /// by opcode group and internal call
+    #[arg(long)]
+    gas_profile: bool,
+    /// Send even though the contract's implementation or code changed since
+    /// the last run, and remember the new one
+    #[arg(long)]
+    acknowledge_upgrade: bool,
+}
+
+impl SendArgs {
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    fn schedule(&self) -> Option<Schedule> {
+        match (self.send_at_block, self.send_at) {
+            (Some(block), _) => Some(Schedule::Block(block)),
+            (None, Some(timestamp)) => Some(Schedule::Timestamp(timestamp)),
+            (None, None) => None,
+        }
+    }
+
+    fn gas_gate(&self) -> Option<GasGate> {
+        self.wait_until_gas_below.map(|threshold| GasGate { threshold, max_wait: self.max_gas_wait })
+    }
+}
+
+/// Takes a prepared contract call through the ledger check, gas tank,
+/// preflight and broadcast.
+///
+/// `simulation` is the client the call was built on; preflights run through
+/// it, and the production key is only loaded once the transaction is about
+/// to be broadcast.
+pub async fn send(
+    kind: &str,
+    config: &Config,
+    job: &Job,
+    simulation: &Arc<Client>,
+    call: ContractCall<Client, ()>,
+    args: &SendArgs,
+) -> anyhow::Result<()> {
+    if let Some(template) = &args.format {
+        output::validate(template, output::RECEIPT_FIELDS)?;
+    }
+    let ledger = Ledger::open().await?;
+    pipeline::check_ledger(&ledger, kind, config, job, args.force).await?;
+    policy::screen(pipeline::sender_address(&**simulation)?, config.contract_address, job)?;
+    upgrades::check(&**simulation, config, args.acknowledge_upgrade).await?;
+    let ws_url = match args.watch_mempool {
+        true => Some(config::ws_rpc_url().context("--watch-mempool needs a WebSocket endpoint")?),
+        false => None,
+    };
+
+    // Scheduled sends wait before the preflight, which may only pass once
+    // the target block (e.g. the contract's activation) is reached.
+    if let Some(schedule) = args.schedule() {
+        if args.dry_run {
+            println!("Dry run: not waiting for the scheduled send time");
+            println!();
+        } else {
+            println!("=== Scheduled Send ===");
+            schedule::wait_until(&**simulation, schedule, simulation.provider().get_interval()).await?;
+        }
+    }
+    if let Some(gate) = args.gas_gate() {
+        if args.dry_run {
+            println!("Dry run: not waiting for the base fee to drop");
+            println!();
+        } else {
+            println!("=== Gas Price Gate ===");
+            let interval = simulation.provider().get_interval();
+            anyhow::ensure!(gate.wait(&**simulation, interval).await?, "the base fee did not drop below the threshold in time");
+            println!();
+        }
+    }
+
+    // A Safe transaction is wrapped for an owner, so that path uses the
+    // production key from the start; nothing is signed before the checks
+    // below pass. A proposal is checked as the call the Safe will make.
+    let safe = match args.safe {
+        Some(safe_address) => {
+            let client = pipeline::connect(config)?;
+            let wrapped = safe::wrap(&client, safe_address, &call.tx).await?;
+            let (tx, nonce) = (&wrapped.tx, wrapped.tx.nonce);
+            let calls = [(tx.to, tx.value, tx.data.clone())];
+            policy::enforce_calls(client.address(), wrapped.safe, config.chain_id, nonce, &calls)?;
+            Some((client, wrapped))
+        }
+        None => None,
+    };
+    let (sender, mut tx) = match &safe {
+        Some((client, wrapped)) if wrapped.executes() => (client.address(), wrapped.exec_transaction(client, client.address())),
+        Some((_, wrapped)) => (wrapped.safe, call.tx),
+        None => (pipeline::sender_address(&**simulation)?, call.tx),
+    };
+    tx.set_from(sender);
+    let proposing = safe.as_ref().is_some_and(|(_, wrapped)| !wrapped.executes());
+
+    if let Some(tank) = GasTank::from_env(config)?.filter(|_| !proposing) {
+        tank.ensure_funded(sender).await?;
+    }
+    let balance = pipeline::print_wallet_info(&**simulation, sender, job.user).await?;
+    pipeline::apply_fees(&**simulation, &mut tx).await?;
+
+    let Some(estimate) = pipeline::preflight(&**simulation, &tx, balance).await? else {
+        return Ok(());
+    };
+    crosscheck::check(config, &tx).await?;
+    plugins::enforce(&Preflight { kind, config, client: simulation, sender, job, tx: &tx }).await?;
+    if args.balance_diff {
+        let parties = [("Wallet", sender), ("User", job.user), ("Contract", config.contract_address)];
+        pipeline::print_balance_diff(simulation.clone(), &tx, &parties).await?;
+    }
+    if args.storage_diff {
+        pipeline::print_storage_diff(&**simulation, &tx, config.contract_address, job).await?;
+    }
+    if args.gas_profile {
+        let parties = [("Contract", config.contract_address), ("Token", job.token), ("User", job.user)];
+        pipeline::print_gas_profile(&**simulation, &tx, &parties).await?;
+    }
+    let usd_cost = pipeline::print_usd(simulation.clone(), job, &estimate).await?;
+    if let Some(limit) = args.max_usd_cost {
+        // An unknown cost can't be shown to be under the limit.
+        let cost = usd_cost.context("--max-usd-cost needs PRICE_SOURCE, a native currency price and a gas estimate")?;
+        if cost > limit {
+            return Err(Error::UsdCostExceeded { cost: price::format_usd(cost), limit: price::format_usd(limit) }.into());
+        }
+        print_ok!("Cost {} is within the {} limit", price::format_usd(cost), price::format_usd(limit));
+        println!();
+    }
+    if let Some(path) = &args.unsigned {
+        return write_unsigned(kind, config, job, simulation, sender, tx, path).await;
+    }
+    if args.dry_run {
+        println!("Dry run: not broadcasting");
+        return Ok(());
+    }
+
+    let client = match &safe {
+        Some((client, wrapped)) if proposing => return propose(&ledger, kind, config, job, client, wrapped, args.force).await,
+        Some((client, _)) => client.clone(),
+        None => pipeline::connect(config)?,
+    };
+    anyhow::ensure!(
+        client.address() == sender,
+        "PRIVATE_KEY belongs to {:?}, but the preflight simulated sender {:?}",
+        client.address(),
+        sender
+    );
+
+    let stop_watching = CancellationToken::new();
+    let watcher = ws_url.map(|url| {
+        let (contract, job, stop) = (config.contract_address, job.clone(), stop_watching.clone());
+        tokio::spawn(async move {
+            if let Err(e) = mempool::watch_contract(&url, contract, Some(&job), Some(sender), &stop).await {
+                print_warn!("Mempool watch stopped: {:?}", e);
+            }
+        })
+    });
+    // Ctrl-C is handled in the wait itself, at a terminal.
+    let never = CancellationToken::new();
+    let result = pipeline::send_and_wait(&*client, tx, &ledger, kind, config, job, args.force, args.deadline, &never).await;
+    if let Some((_, wrapped)) = &safe {
+        // The value is the Safe's, not the execTransaction's: counted once
+        // that was broadcast, even if it then wasn't waited for.
+        let broadcast = match &result {
+            Ok(_) => true,
+            Err(e) => matches!(e.downcast_ref::<Error>(), Some(Error::Detached { .. } | Error::DeadlineExceeded { .. })),
+        };
+        if broadcast {
+            policy::record_call_spend(wrapped.safe, config.chain_id, wrapped.tx.nonce, wrapped.tx.value)?;
+        }
+    }
+    stop_watching.cancel();
+    if let Some(watcher) = watcher {
+        let _ = watcher.await;
+    }
+    if let (Ok(Some(receipt)), Some(estimated)) = (&result, estimate.gas) {
+        record_gas(kind, config, estimated, receipt);
+    }
+    let result = verify(client.clone(), kind, config, job, result).await;
+    run_hook(kind, config, sender, job, &result).await;
+    let receipt = result?;
+    if let Some(template) = &args.format {
+        println!("{}", output::render(template, &output::receipt_values(kind, job, receipt.as_ref())));
+    }
+    Ok(())
+}
+
+/// Proposes the wrapped call to the Safe Transaction Service, claiming the
+/// job in the ledger under the Safe transaction hash first so it isn't
+/// proposed twice. A failed proposal is released: until the Safe executes a
+/// transaction at its nonce, proposing again yields the same hash.
+async fn propose(
+    ledger: &Ledger,
+    kind: &str,
+    config: &Config,
+    job: &Job,
+    client: &Client,
+    wrapped: &Wrapped,
+    force: bool,
+) -> anyhow::Result<()> {
+    let entry = pipeline::claim(ledger, kind, config, job, wrapped.safe_tx_hash, force).await?;
+    if let Err(e) = wrapped.propose(client).await {
+        ledger.release(&entry).await?;
+        return Err(e);
+    }
+    policy::record_call_spend(wrapped.safe, config.chain_id, wrapped.tx.nonce, wrapped.tx.value)
+
+}
+
+/// Sends `calls` (target, value, data) from the user's smart account as a
+/// UserOperation sponsored by the paymaster, so the user's key signs but
+/// never pays gas. The ledger, policy and plugin checks apply as in [`send`],
+/// the signing policy to the account's calls;
+/// the options that shape an EOA transaction (Safe routing, scheduling, fee
+/// gates, simulations) don't. `--deadline` bounds the wait for inclusion
+/// (default 5 minutes).
+pub async fn send_gasless(
+    kind: &str,
+    config: &Config,
+    job: &Job,
+    simulation: &Arc<Client>,
+    calls: Vec<(Address, U256, Bytes)>,
+    args: &SendArgs,
+) -> anyhow::Result<()> {
+    if let Some(template) = &args.format {
+        output::validate(template, output::RECEIPT_FIELDS)?;
+    }
+    anyhow::ensure!(args.unsigned.is_none(), "--unsigned doesn't apply to --gasless");
+    let account = SmartAccount::from_env()?;
+    anyhow::ensure!(account.is_sponsored(), "--gasless needs a paymaster to pay the gas: set PAYMASTER_URL");
+    anyhow::ensure!(
+        account.address == job.user,
+        "SMART_ACCOUNT {:?} is not the job's user {:?}",
+        account.address,
+        job.user
+    );
+    let ledger = Ledger::open().await?;
+    pipeline::check_ledger(&ledger, kind, config, job, args.force).await?;
+    policy::screen(account.address, config.contract_address, job)?;
+    upgrades::check(&**simulation, config, args.acknowledge_upgrade).await?;
+
+    println!("=== User Operation ===");
+    println!("Account: {:?}", account.address);
+    let value = calls.iter().fold(U256::zero(), |total, (_, value, _)| total + *value);
+    let signer = match smart_account::session_key(config)? {
+        Some(key) => {
+            let session = account
+                .session(simulation.clone(), key.address())
+                .await?
+                .with_context(|| format!("session key {:?} isn't registered with the account", key.address()))?;
+            let window = Window::until(session.valid_until);
+            let now = check_window(&**simulation, kind, config, account.address, job, "Session key", window).await?;
+            session.check(value, now)?;
+            println!("Signer: session key {:?}, valid until {}", key.address(), session.valid_until);
+            key
+        }
+        None => {
+            let owner = smart_account::owner(config)?;
+            println!("Signer: owner {:?}", owner.address());
+            owner
+        }
+    };
+    let call_data = smart_account::execute(simulation.clone(), account.address, calls.clone())?;
+    // What the EntryPoint will call, for the checks that look at the transaction.
+    let tx: TypedTransaction =
+        Eip1559TransactionRequest::new().from(account.entry_point).to(account.address).data(call_data.clone()).into();
+    plugins::enforce(&Preflight { kind, config, client: simulation, sender: account.address, job, tx: &tx }).await?;
+    let mut op = account.user_operation(simulation.clone(), config, call_data).await?;
+    account.sponsor(&mut op).await?;
+    println!("Nonce: {}", op.nonce);
+    println!(
+        "Gas Limits: call {}, verification {}, pre-verification {}",
+        op.call_gas_limit, op.verification_gas_limit, op.pre_verification_gas
+    );
+    print_ok!("Sponsored by the paymaster");
+    println!();
+    if args.dry_run {
+        println!("Dry run: not submitting the operation");
+        return Ok(());
+    }
+
+    policy::enforce_calls(signer.address(), account.address, config.chain_id, op.nonce, &calls)?;
+    account.sign(&signer, &mut op, config.chain_id).await?;
+    // Claimed under the operation hash before it is submitted, as a
+    // transaction is before its broadcast, and released only if the bundler
+    // refused it: after a timeout it may still be included.
+    let claimed = op.hash(account.entry_point, config.chain_id);
+    let entry = pipeline::claim(&ledger, kind, config, job, claimed, args.force).await?;
+    let op_hash = match account.send(&op).await {
+        Ok(op_hash) => op_hash,
+        Err(e) => {
+            if smart_account::is_rejection(&e) {
+                ledger.release(&entry).await?;
+            }
+            return Err(e);
+        }
+    };
+    print_ok!("UserOperation submitted: {:?}", op_hash);
+    policy::record_call_spend(account.address, config.chain_id, op.nonce, value)?;
+    let result = match account.wait(op_hash, args.deadline.unwrap_or(Duration::from_secs(300))).await {
+        Ok(Some(receipt)) => {
+            // Also under the bundle's transaction, where the job was included.
+            let tx_hash = receipt.receipt.transaction_hash;
+            ledger.record(&Entry::new(kind, config.chain_id, config.contract_address, job, tx_hash)).await?;
+            match receipt.success {
+                true => Ok(Some(receipt.receipt)),
+                false => Err(anyhow::anyhow!(
+                    "the operation reverted in {:?}: {}",
+                    tx_hash,
+                    receipt.reason.unwrap_or_default()
+                )),
+            }
+        }
+        Ok(None) => Err(anyhow::anyhow!("operation {:?} was not included in time", op_hash)),
+        Err(e) => Err(e),
+    };
+    let result = verify(simulation.clone(), kind, config, job, result).await;
+    run_hook(kind, config, account.address, job, &result).await;
+    let receipt = result?;
+    pipeline::print_receipt(receipt.clone());
+    if let Some(template) = &args.format {
+        println!("{}", output::render(template, &output::receipt_values(kind, job, receipt.as_ref())));
+    }
+    Ok(())
+}
+
+/// Sends `call_data` from the account for its own management, signed by its
+/// owner and sponsored when PAYMASTER_URL is set, and waits for it to be
+/// included.
+pub async fn submit_user_operation(
+    config: &Config,
+    client: Arc<Rpc>,
+    account: &SmartAccount,
+    call_data: Bytes,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let owner = smart_account::owner(config)?;
+    let mut op = account.user_operation(client, config, call_data).await?;
+    match account.is_sponsored() {
+        true => {
+            account.sponsor(&mut op).await?;
+            print_ok!("Sponsored by the paymaster");
+        }
+        false => println!("No PAYMASTER_URL: the account pays the gas"),
+    }
+    println!();
+    if dry_run {
+        println!("Dry run: not submitting the operation");
+        return Ok(());
+    }
+    account.sign(&owner, &mut op, config.chain_id).await?;
+    let op_hash = account.send(&op).await?;
+    print_ok!("UserOperation submitted: {:?}", op_hash);
+    match account.wait(op_hash, Duration::from_secs(300)).await? {
+        Some(receipt) if receipt.success => {
+            print_ok!("Included in transaction {:?}", receipt.receipt.transaction_hash);
+            Ok(())
+        }
+        Some(receipt) => anyhow::bail!(
+            "the operation reverted in {:?}: {}",
+            receipt.receipt.transaction_hash,
+            receipt.reason.unwrap_or_default()
+        ),
+        None => anyhow::bail!("operation {:?} was not included in time", op_hash),
+    }
+}
+
+/// Fills in `tx` for `sender` and writes it to `path` unsigned, for an
+/// external signer, instead of broadcasting it.
+async fn write_unsigned(
+    kind: &str,
+    config: &Config,
+    job: &Job,
+    simulation: &Client,
+    sender: Address,
+    mut tx: TypedTransaction,
+    path: &Path,
+) -> anyhow::Result<()> {
+    pipeline::prepare(simulation, sender, &mut tx).await?;
+    let unsigned = UnsignedTx::new(kind, config, job, tx, profile::replay_protected(config.chain_id)?)?;
+    unsigned.save(path)?;
+    println!("=== Unsigned Transaction ===");
+    println!("From: {:?}", unsigned.from);
+    println!("Nonce: {}", unsigned.tx.nonce().copied().unwrap_or_default());
+    println!("Payload: {}", unsigned.payload);
+    println!("Signing Hash: {:?}", unsigned.sighash);
+    print_ok!("Written to {}", path.display());
+    println!("Once {:?} has signed the hash: broadcast {} --signature <hex>", sender, path.display());
+    Ok(())
+}
+
+/// Reports and records how the gas `receipt` used compares with the
+/// estimate. The transaction is mined, so failing to record it only warns.
+fn record_gas(kind: &str, config: &Config, estimated: U256, receipt: &TransactionReceipt) {
+    let usage = gas_usage::Usage::new(kind, config.chain_id, estimated, receipt);
+    println!("{}", usage.describe());
+    match gas_usage::record_and_check(&usage) {
+        Ok(Some(regression)) => print_warn!("{}", regression.describe(&usage)),
+        Ok(None) => {}
+        Err(e) => print_warn!("Could not record the gas usage: {:#}", e),
+    }
+}
+
+/// Fails a confirmed lock whose event or record on chain doesn't match the job.
+async fn verify(
+    client: Arc<Client>,
+    kind: &str,
+    config: &Config,
+    job: &Job,
+    result: anyhow::Result<Option<TransactionReceipt>>,
+) -> anyhow::Result<Option<TransactionReceipt>> {
+    match result {
+        Ok(Some(receipt)) if kind == LOCK_KIND && receipt.status == Some(1.into()) => {
+            postcheck::verify_lock(client, config.contract_address, job, &receipt).await?;
+            print_ok!("Locked event and lock record for nonce {} match the job", job.nonce);
+            Ok(Some(receipt))
+        }
+        result => result,
+    }
+}
+
+/// Runs the post-confirm or failure hook for a send's `result`. Detaching
+/// leaves the transaction unsettled, so it runs neither.
+async fn run_hook(
+    kind: &str,
+    config: &Config,
+    sender: Address,
+    job: &Job,
+    result: &anyhow::Result<Option<TransactionReceipt>>,
+) {
+    let mut payload = hooks::payload(kind, config, sender, job);
+    let hook = match result {
+        Ok(Some(receipt)) => {
+            let error = (receipt.status != Some(1.into())).then(|| "transaction reverted".to_string());
+            hooks::settle(&mut payload, Some(receipt.transaction_hash), receipt.block_number, receipt.gas_used, error)
+        }
+        Ok(None) => hooks::settle(&mut payload, None, None, None, Some("transaction receipt not found".to_string())),
+        Err(e) if matches!(e.downcast_ref::<Error>(), Some(Error::Detached { .. })) => return,
+        Err(e) => hooks::fail(&mut payload, e),
+    };
+    hooks::notify(hook, &payload).await;
+}
+
+/// [`validity::preflight`] for a signature the job is sent with, which also
+/// tells ON_FAILURE_HOOK when it has expired, so the job can be routed back
+/// to be signed again.
+pub async fn check_window<M: Middleware>(
+    client: &M,
+    kind: &str,
+    config: &Config,
+    sender: Address,
+    job: &Job,
+    what: &str,
+    window: Window,
+) -> anyhow::Result<u64>
+where
+    M::Error: 'static,
+{
+    let result = validity::preflight(client, what, window).await;
+    if let Err(e) = &result {
+        if matches!(e.downcast_ref::<Error>(), Some(Error::SignatureExpired { .. })) {
+            let mut payload = hooks::payload(kind, config, sender, job);
+            hooks::notify(hooks::fail(&mut payload, e), &payload).await;
+        }
+    }
+    result
+}
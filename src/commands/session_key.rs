@@ -0,0 +1,116 @@
+use super::send;
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::contract::LockCall;
+use eth_contract_caller::pipeline;
+use eth_contract_caller::smart_account::{self, Scope, SmartAccount};
+use eth_contract_caller::token;
+use eth_contract_caller::validity;
+use eth_contract_caller::{print_ok, print_warn};
+use ethers::contract::EthCall;
+use ethers::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Register a scoped session key with SMART_ACCOUNT, signed by USER_PRIVATE_KEY
+    Register(RegisterArgs),
+    /// Revoke a session key, signed by USER_PRIVATE_KEY
+    Revoke {
+        /// The key (default: the SESSION_KEY wallet)
+        key: Option<Address>,
+        /// Build and sponsor the operation without submitting it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show a session key's expiry and value limit
+    Show {
+        /// The key (default: the SESSION_KEY wallet)
+        key: Option<Address>,
+    },
+}
+
+#[derive(clap::Args)]
+struct RegisterArgs {
+    /// The key (default: the SESSION_KEY wallet)
+    key: Option<Address>,
+    /// A call the key may make, as `ADDRESS:FUNCTION` with a 4-byte selector
+    /// or a signature; repeatable (default: lock on CONTRACT_ADDRESS)
+    #[arg(long = "allow", value_name = "ADDRESS:FUNCTION", value_parser = smart_account::parse_scope)]
+    scopes: Vec<Scope>,
+    /// The most native value one operation may attach (e.g. `0.1ether`)
+    #[arg(long, value_parser = token::parse_value, default_value = "0")]
+    max_value: U256,
+    /// How long the key stays valid
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "24h")]
+    valid_for: Duration,
+    /// Build and sponsor the operation without submitting it
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let client = Arc::new(pipeline::provider(&config)?);
+    let account = SmartAccount::from_env()?;
+    match args.command {
+        Command::Register(args) => {
+            let key = key_or_session_key(&config, args.key)?;
+            let scopes = match args.scopes.is_empty() {
+                true => vec![Scope { target: config.contract_address, selector: LockCall::selector() }],
+                false => args.scopes,
+            };
+            let valid_until = validity::chain_time(&*client).await? + args.valid_for.as_secs();
+            println!("=== Register Session Key ===");
+            println!("Account: {:?}", account.address);
+            println!("Key: {:?}", key);
+            for scope in &scopes {
+                println!("Allowed: {:?} 0x{}", scope.target, hex::encode(scope.selector));
+            }
+            println!("Max Value: {} wei", args.max_value);
+            println!("Valid Until: {}", valid_until);
+            let call_data = account.register_session(client.clone(), key, valid_until, args.max_value, &scopes)?;
+            send::submit_user_operation(&config, client, &account, call_data, args.dry_run).await
+        }
+        Command::Revoke { key, dry_run } => {
+            let key = key_or_session_key(&config, key)?;
+            println!("=== Revoke Session Key ===");
+            println!("Account: {:?}", account.address);
+            println!("Key: {:?}", key);
+            let call_data = account.revoke_session(client.clone(), key)?;
+            send::submit_user_operation(&config, client, &account, call_data, dry_run).await
+        }
+        Command::Show { key } => {
+            let key = key_or_session_key(&config, key)?;
+            match account.session(client.clone(), key).await? {
+                Some(session) => {
+                    println!("Key: {:?}", key);
+                    println!("Max Value: {} wei", session.value_limit);
+                    println!("Valid Until: {}", session.valid_until);
+                    match session.valid_until > validity::chain_time(&*client).await? {
+                        true => print_ok!("Session key is active"),
+                        false => print_warn!("Session key has expired"),
+                    }
+                }
+                None => print_warn!("{:?} is not a session key of {:?}", key, account.address),
+            }
+            Ok(())
+        }
+    }
+}
+
+fn key_or_session_key(config: &Config, key: Option<Address>) -> anyhow::Result<Address> {
+    if let Some(key) = key {
+        return Ok(key);
+    }
+    match smart_account::session_key(config)? {
+        Some(wallet) => Ok(wallet.address()),
+        None => anyhow::bail!("pass the session key's address, or set SESSION_KEY"),
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::Context;
+use super::send;
+use eth_contract_caller::config::{env_var, Config};
+use eth_contract_caller::contract::{EntryPoint, SimpleAccount};
+use eth_contract_caller::smart_account::{self, Factory, ModuleType, SmartAccount};
+use eth_contract_caller::{audit, calldata, pipeline};
+use eth_contract_caller::{print_ok, print_warn};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::format_ether;
+use std::sync::Arc;
+
+const MODULE_CALL_PURPOSE: &str = "module call";
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Print the owner's account address for ACCOUNT_FACTORY and ACCOUNT_SALT,
+    /// deployed or not
+    Address {
+        /// The owner (default: ACCOUNT_OWNER, or the USER_PRIVATE_KEY wallet)
+        #[arg(long)]
+        owner: Option<Address>,
+    },
+    /// Deploy the owner's account with a UserOperation signed by USER_PRIVATE_KEY
+    Deploy {
+        /// Build and sponsor the operation without submitting it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show an account's code, owner, balance, EntryPoint deposit and nonce
+    Info {
+        /// The account (default: SMART_ACCOUNT, or the owner's account)
+        account: Option<Address>,
+    },
+    /// Install an ERC-7579 module on SMART_ACCOUNT, signed by USER_PRIVATE_KEY
+    InstallModule(ModuleArgs),
+    /// Uninstall an ERC-7579 module from SMART_ACCOUNT, signed by USER_PRIVATE_KEY
+    UninstallModule(ModuleArgs),
+    /// List the modules installed on SMART_ACCOUNT
+    Modules {
+        /// First block to look for installations in (e.g. the account's deployment)
+        #[arg(long, default_value_t = 0)]
+        from_block: u64,
+    },
+    /// Call a module from the wallet, e.g. a recovery module's
+    /// `recover(address,address)` as a guardian
+    ExecuteViaModule {
+        module: Address,
+        /// The function's signature, e.g. `recover(address,address)`
+        function: String,
+        /// The function's arguments
+        args: Vec<String>,
+        /// Simulate the call without sending it
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(clap::Args)]
+struct ModuleArgs {
+    module: Address,
+    /// Module type: validator, executor, fallback or hook
+    #[arg(long = "type", value_parser = ModuleType::parse)]
+    kind: ModuleType,
+    /// Data passed to the module's onInstall or onUninstall
+    #[arg(long, value_name = "HEX", value_parser = calldata::parse_hex, default_value = "0x")]
+    data: Bytes,
+    /// Build and sponsor the operation without submitting it
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let client = Arc::new(pipeline::provider(&config)?);
+    let factory = Factory::from_env()?;
+    match args.command {
+        Command::Address { owner } => {
+            let owner = match owner {
+                Some(owner) => owner,
+                None => smart_account::owner_address(&config)?,
+            };
+            let address = factory.account_address(client.clone(), owner).await?;
+            println!("Factory: {:?}", factory.address);
+            println!("Owner: {:?}", owner);
+            println!("Salt: {}", factory.salt);
+            println!("Account: {:?}", address);
+            match client.get_code(address, None).await?.is_empty() {
+                true => println!("Not deployed yet; its first operation deploys it"),
+                false => print_ok!("Deployed"),
+            }
+            Ok(())
+        }
+        Command::Deploy { dry_run } => {
+            let owner = smart_account::owner_address(&config)?;
+            let address = factory.account_address(client.clone(), owner).await?;
+            println!("=== Deploy Smart Account ===");
+            println!("Account: {:?}", address);
+            println!("Owner: {:?}", owner);
+            if !client.get_code(address, None).await?.is_empty() {
+                print_ok!("Already deployed");
+                return Ok(());
+            }
+            let account = SmartAccount::at(address)?;
+            if !account.is_sponsored() {
+                let balance = client.get_balance(address, None).await?;
+                println!("Prefund: {} ETH at the account address", format_ether(balance));
+            }
+            // An empty call reaches the account's receive function, so the
+            // operation does nothing beyond the deployment.
+            send::submit_user_operation(&config, client, &account, Bytes::new(), dry_run).await
+        }
+        Command::Info { account } => {
+            let address = match (account, env_var("SMART_ACCOUNT")) {
+                (Some(account), _) => account,
+                (None, Some(account)) => {
+                    account.parse().with_context(|| format!("invalid SMART_ACCOUNT {:?}", account))?
+                }
+                (None, None) => factory.account_address(client.clone(), smart_account::owner_address(&config)?).await?,
+            };
+            let entry_point = SmartAccount::entry_point_from_env()?;
+            println!("Account: {:?}", address);
+            if client.get_code(address, None).await?.is_empty() {
+                print_warn!("Not deployed");
+            } else {
+                print_ok!("Deployed");
+                println!("Owner: {:?}", SimpleAccount::new(address, client.clone()).owner().call().await?);
+            }
+            let entry_point_contract = EntryPoint::new(entry_point, client.clone());
+            let deposit = entry_point_contract.balance_of(address).call().await?;
+            let nonce = entry_point_contract.get_nonce(address, U256::zero()).call().await?;
+            println!("Balance: {} ETH", format_ether(client.get_balance(address, None).await?));
+            println!("EntryPoint Deposit: {} ETH", format_ether(deposit));
+            println!("Nonce: {}", nonce);
+            Ok(())
+        }
+        Command::InstallModule(args) => {
+            let account = SmartAccount::from_env()?;
+            println!("=== Install Module ===");
+            println!("Account: {:?}", account.address);
+            println!("Module: {:?} ({})", args.module, args.kind);
+            let call_data = account.install_module(client.clone(), args.kind, args.module, args.data)?;
+            send::submit_user_operation(&config, client, &account, call_data, args.dry_run).await
+        }
+        Command::UninstallModule(args) => {
+            let account = SmartAccount::from_env()?;
+            println!("=== Uninstall Module ===");
+            println!("Account: {:?}", account.address);
+            println!("Module: {:?} ({})", args.module, args.kind);
+            let call_data = account.uninstall_module(client.clone(), args.kind, args.module, args.data)?;
+            send::submit_user_operation(&config, client, &account, call_data, args.dry_run).await
+        }
+        Command::Modules { from_block } => {
+            let account = SmartAccount::from_env()?;
+            let modules = smart_account::installed_modules(client, account.address, from_block).await?;
+            println!("=== Modules of {:?} ===", account.address);
+            if modules.is_empty() {
+                println!("No modules installed");
+            }
+            for (kind, module) in modules {
+                println!("{:<9} {:?}", kind.to_string(), module);
+            }
+            Ok(())
+        }
+        Command::ExecuteViaModule { module, function, args, dry_run } => {
+            let data = calldata::encode_call(&function, &args)?;
+            let wallet = pipeline::connect(&config)?;
+            let request = TransactionRequest::new().from(wallet.address()).to(module).data(data);
+            let mut tx: TypedTransaction = request.into();
+            println!("=== Module Call ===");
+            println!("Module: {:?}", module);
+            println!("Call: {}", function);
+            println!("From: {:?}", wallet.address());
+            if let Err(e) = wallet.call(&tx, None).await {
+                anyhow::bail!("the module call would revert: {}", e);
+            }
+            print_ok!("Simulation succeeded");
+            if dry_run {
+                println!("Dry run: not sending");
+                return Ok(());
+            }
+            pipeline::apply_fees(&*wallet, &mut tx).await?;
+            let pending = audit::send_transaction(&*wallet, tx, MODULE_CALL_PURPOSE).await?;
+            println!("Transaction Hash: {:?}", pending.tx_hash());
+            let receipt = pending.await?.context("module call dropped before it was mined")?;
+            audit::record_receipt(wallet.address(), MODULE_CALL_PURPOSE, &receipt)?;
+            let hash = receipt.transaction_hash;
+            anyhow::ensure!(receipt.status == Some(U64::from(1)), "module call {:?} reverted", hash);
+            print_ok!("Module call mined in block {:?}", receipt.block_number);
+            Ok(())
+        }
+    }
+}
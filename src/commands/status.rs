@@ -0,0 +1,111 @@
+use anyhow::Context;
+use eth_contract_caller::calldata;
+use eth_contract_caller::config::Config;
+use eth_contract_caller::explorer::Explorer;
+use eth_contract_caller::status::{self, Status};
+use eth_contract_caller::trace::{self, InternalCall};
+use eth_contract_caller::{pipeline, print_error, print_ok, print_warn, style};
+use ethers::prelude::*;
+use ethers::utils::format_units;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Hash of the transaction
+    hash: H256,
+    /// Summarise the internal calls of a mined transaction, from
+    /// debug_traceTransaction; needs a node with the debug namespace
+    #[arg(long)]
+    calls: bool,
+}
+
+/// Reports whether a transaction is pending, succeeded or failed, with its
+/// confirmations, effective gas price, and its decoded logs or revert reason.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::without_contract_from_env()?;
+    let provider = pipeline::provider(&config)?;
+    let Some(status) = status::lookup(&provider, args.hash).await? else {
+        anyhow::bail!("transaction {:?} is unknown to {}", args.hash, config.rpc_url);
+    };
+
+    let tx = status.tx();
+    println!("=== Transaction ===");
+    println!("Hash: {:?}", tx.hash);
+    println!("From: {:?}", tx.from);
+    match tx.to {
+        Some(to) => println!("To: {:?}", to),
+        None => println!("To: (contract creation)"),
+    }
+    println!("Nonce: {}", tx.nonce);
+    println!("Value: {} ETH", format_units(tx.value, "ether")?);
+    if let Some(explorer) = Explorer::for_chain(config.chain_id)? {
+        println!("Explorer: {}", explorer.tx(tx.hash));
+    }
+    println!();
+
+    println!("=== Status ===");
+    let Status::Mined { tx, receipt, confirmations } = &status else {
+        print_warn!("Pending: not yet mined");
+        return Ok(());
+    };
+    let succeeded = receipt.status == Some(U64::from(1));
+    if succeeded {
+        print_ok!("Success");
+    } else {
+        print_error!("Failed");
+    }
+    println!("Block: {}", receipt.block_number.unwrap_or_default());
+    println!("Confirmations: {}", confirmations);
+    println!("Gas Used: {} of {}", receipt.gas_used.unwrap_or_default(), tx.gas);
+    if let Some(price) = receipt.effective_gas_price {
+        println!("Effective Gas Price: {} Gwei", format_units(price, "gwei")?);
+    }
+    println!();
+
+    if args.calls {
+        let calls = trace::call_tree(&provider, args.hash)
+            .await
+            .context("debug_traceTransaction failed; does the node enable the debug namespace?")?;
+        println!("=== Internal Calls ({}) ===", calls.len());
+        for call in &calls {
+            print_call(call)?;
+        }
+        println!();
+    }
+
+    if !succeeded {
+        println!("=== Revert Reason ===");
+        println!("{}", status::revert_reason(&provider, tx).await?);
+        return Ok(());
+    }
+    println!("=== Logs ({}) ===", receipt.logs.len());
+    for log in &receipt.logs {
+        println!("[{}] {}", log.log_index.unwrap_or_default(), status::describe_log(log));
+    }
+    Ok(())
+}
+
+/// One call per line, indented by depth: kind, callee, function, value and
+/// gas, with failed calls in red and their error.
+fn print_call(call: &InternalCall) -> anyhow::Result<()> {
+    let target = match call.to {
+        Some(to) => format!("{:?}", to),
+        None => "new contract".to_string(),
+    };
+    let function = match call.selector {
+        Some(selector) => match calldata::lookup(selector)? {
+            Some((function, _)) => function.name,
+            None => format!("0x{}", hex::encode(selector)),
+        },
+        None => "(no calldata)".to_string(),
+    };
+    let mut line = format!("{} {} {} gas {}", call.kind, target, function, call.gas_used);
+    if !call.value.is_zero() {
+        line.push_str(&format!(" value {} ETH", format_units(call.value, "ether")?));
+    }
+    let indent = "  ".repeat(call.depth);
+    match &call.error {
+        Some(error) => println!("{}{}", indent, style::error(&format!("{} ({})", line, error))),
+        None => println!("{}{}", indent, line),
+    }
+    Ok(())
+}
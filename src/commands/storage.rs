@@ -0,0 +1,47 @@
+use eth_contract_caller::config::Config;
+use eth_contract_caller::pipeline;
+use eth_contract_caller::storage;
+use ethers::prelude::*;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Declaration slot of the variable (decimal or 0x-prefixed hex)
+    slot: String,
+    /// Mapping key, applied in order for nested mappings (address, bytes32 or uint)
+    #[arg(long = "key")]
+    keys: Vec<String>,
+    /// Word offset of the struct field within the resolved slot
+    #[arg(long, default_value_t = 0)]
+    offset: u64,
+    /// Block number to read at (defaults to latest)
+    #[arg(long)]
+    block: Option<u64>,
+}
+
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let provider = pipeline::provider(&config)?;
+
+    let base = storage::parse_slot(&args.slot)?;
+    let keys = args
+        .keys
+        .iter()
+        .map(|key| storage::parse_key(key))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let slot = storage::resolve_slot(base, &keys, args.offset);
+
+    let value = provider
+        .get_storage_at(config.contract_address, slot, args.block.map(|b| b.into()))
+        .await?;
+
+    println!("=== Storage ===");
+    println!("Contract Address: {:?}", config.contract_address);
+    println!("Slot: {:?}", slot);
+    println!("Raw Value: {:?}", value);
+    println!("As uint256: {}", value.into_uint());
+    if value.as_bytes()[..12].iter().all(|b| *b == 0) {
+        println!("As address: {:?}", Address::from(value));
+    }
+
+    Ok(())
+}
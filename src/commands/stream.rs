@@ -0,0 +1,445 @@
+use eth_contract_caller::batch::{self, Options, Outcome, Status};
+use eth_contract_caller::config::{self, Config, Job, JobParams};
+use eth_contract_caller::contract::LockCall;
+use eth_contract_caller::fees::FeeModel;
+use eth_contract_caller::headers::HeaderCache;
+use eth_contract_caller::profile;
+use eth_contract_caller::ledger::Ledger;
+use eth_contract_caller::pipeline::{Client, Rpc};
+use eth_contract_caller::queue::{self, Claim, Queue};
+use eth_contract_caller::shutdown::{self, CancellationToken};
+use eth_contract_caller::upgrades;
+use eth_contract_caller::{bytecode, pipeline, print_warn, style};
+use ethers::contract::EthCall;
+use serde_json::{json, Value};
+use std::fs;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Send even jobs the ledger shows as already submitted
+    #[arg(long)]
+    force: bool,
+    /// Pop jobs from the Redis queue at REDIS_URL instead of reading stdin,
+    /// and publish each result on the result channel as well
+    #[arg(long)]
+    redis: bool,
+    /// On SIGTERM or Ctrl-C, how long to let the job in flight finish
+    /// before exiting anyway (e.g. `90s`, `5m`)
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "60s")]
+    grace_period: Duration,
+    /// How often to sync the block header cache, reporting reorgs
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "12s")]
+    header_interval: Duration,
+    /// Start even though the contract's implementation or code changed since
+    /// the last run, and remember the new one
+    #[arg(long)]
+    acknowledge_upgrade: bool,
+}
+
+struct Sender {
+    clients: Vec<Arc<Client>>,
+    config: Config,
+    ledger: Ledger,
+    options: Options,
+    /// When the config file was last changed, as of the last reload.
+    config_modified: Option<SystemTime>,
+    /// When the env file was last changed, as of the last reload.
+    env_modified: Option<SystemTime>,
+}
+
+/// Locks every job read from stdin, one JSON object per line in the format
+/// of `--params-json` (nonce and signature required), until stdin closes.
+/// Each job gets one JSON result line on stdout, a job that fails outright
+/// included; everything else goes to stderr, so stdout can be piped straight
+/// into another program.
+///
+/// Jobs are sent one at a time, in order, through the batch pipeline. With
+/// `--redis` they come from the queue instead (see [`queue`]) and the
+/// command runs until stopped.
+///
+/// SIGTERM or Ctrl-C stops the intake of new jobs; the job in flight gets
+/// `--grace-period` to confirm before the command exits. One still
+/// unresolved then is reported as `unresolved` (its broadcast, if any, is in
+/// the ledger), and a queued one is pushed back onto the queue.
+///
+/// The env file and the fee model are reloaded whenever the env or config
+/// file changes, so new hook commands and a new POLICY_FILE apply to the
+/// next job; the policy file and replacement settings are read for every
+/// send anyway. Connection settings and keys still need a restart.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    style::to_stderr();
+    let config = Config::from_env()?;
+    let clients = pipeline::connect_pool(&config)?;
+    let provider = pipeline::provider(&config)?;
+    let code = pipeline::ensure_contract_deployed(&provider, &config).await?;
+    if !bytecode::contains_selector(&code, LockCall::selector()) {
+        print_warn!("Selector 0x{} not found in the contract bytecode", hex::encode(LockCall::selector()));
+    }
+    upgrades::check(&provider, &config, args.acknowledge_upgrade).await?;
+    let ledger = Ledger::open().await?;
+    let options = Options {
+        concurrency: 1,
+        force: args.force,
+        fees: FeeModel::from_env(config.chain_id)?,
+        gas_gate: None,
+        quiet: true,
+        checkpoint: None,
+        artifacts: None,
+        from_nonce: None,
+    };
+    let config_modified = modified(&profile::path());
+    let env_modified = config::env_file().and_then(modified);
+    let mut sender = Sender { clients, config, ledger, options, config_modified, env_modified };
+    let mut shutdown = Shutdown::listen(args.grace_period)?;
+    tokio::spawn(track_headers(provider, sender.config.chain_id, args.header_interval));
+    if args.redis {
+        return consume(&mut sender, &mut shutdown).await;
+    }
+    eprintln!("Reading jobs from stdin (chain {}, contract {:?})", sender.config.chain_id, sender.config.contract_address);
+
+    let mut lines = BufReader::new(io::stdin()).lines();
+    let mut line_number = 0;
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line?,
+            _ = shutdown.wait() => break,
+        };
+        let Some(line) = line else { break };
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        sender.reload();
+        match shutdown.drain(sender.send(line_number, &line)).await {
+            Some(result) => println!("{}", result.unwrap_or_else(|e| failed(line_number, &e))),
+            None => {
+                println!("{}", unresolved(line_number));
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Processes queued jobs until stopped, numbering them in the order popped.
+async fn consume(sender: &mut Sender, shutdown: &mut Shutdown) -> anyhow::Result<()> {
+    let mut queue = Queue::from_env().await?;
+    let recovered = queue.recover().await?;
+    if recovered > 0 {
+        print_warn!("Put {} job(s) left unfinished by the last run back on the queue", recovered);
+    }
+    eprintln!(
+        "Reading jobs from Redis list {} (chain {}, contract {:?})",
+        queue.key(),
+        sender.config.chain_id,
+        sender.config.contract_address
+    );
+    let mut line_number = 0;
+    while !shutdown.requested() {
+        let Some(message) = queue.pop().await? else {
+            continue;
+        };
+        if shutdown.requested() {
+            queue.release(None, &message).await?;
+            break;
+        }
+        line_number += 1;
+        let (key, job) = match queue::split_key(&message) {
+            Ok(split) => split,
+            Err(e) => {
+                let result = json!({ "line": line_number, "status": "invalid", "error": format!("{:#}", e) });
+                println!("{}", result);
+                queue.publish(&result).await?;
+                queue.ack(&message).await?;
+                continue;
+            }
+        };
+        sender.reload();
+        match queue.claim(key.as_deref()).await? {
+            Claim::New => {
+                let outcome = tokio::select! {
+                    outcome = shutdown.drain(sender.send(line_number, &job)) => outcome,
+                    _ = queue.heartbeat(key.as_deref()) => unreachable!("the heartbeat runs until dropped"),
+                };
+                match outcome {
+                    Some(result) => {
+                        let mut result = result.unwrap_or_else(|e| failed(line_number, &e));
+                        result["idempotency_key"] = json!(key);
+                        println!("{}", result);
+                        queue.finish(key.as_deref(), &message, &result).await?;
+                    }
+                    // The ledger keeps whoever picks it up next from sending it twice.
+                    None => {
+                        queue.release(key.as_deref(), &message).await?;
+                        let mut result = unresolved(line_number);
+                        result["idempotency_key"] = json!(key);
+                        println!("{}", result);
+                        break;
+                    }
+                }
+            }
+            // A repeat is answered without touching the stored result.
+            claim => {
+                let mut result = match claim {
+                    Claim::Done(first) => first,
+                    _ => json!({ "status": "duplicate", "error": "already being processed", "idempotency_key": key }),
+                };
+                result["line"] = json!(line_number);
+                result["duplicate"] = json!(true);
+                println!("{}", result);
+                queue.publish(&result).await?;
+                queue.ack(&message).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the block header cache synced with the chain while the command
+/// runs, reporting reorgs. Failed syncs are reported and retried.
+async fn track_headers(provider: Rpc, chain_id: u64, interval: Duration) {
+    let mut headers = match HeaderCache::from_env(chain_id) {
+        Ok(headers) => headers,
+        Err(e) => return print_warn!("Not tracking block headers: {:#}", e),
+    };
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        match headers.sync(&provider).await {
+            Ok((_, Some(reorg))) => {
+                print_warn!("Reorg: {} cached headers from block {} were replaced", reorg.depth, reorg.number)
+            }
+            Ok((_, None)) => {}
+            Err(e) => print_warn!("Failed to sync block headers: {:#}", e),
+        }
+    }
+}
+
+/// When the config file was last changed, if it exists.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// The result line of a job whose send failed outright, on a ledger or node
+/// error, rather than ending in a status of its own.
+fn failed(line: usize, error: &anyhow::Error) -> Value {
+    json!({ "line": line, "status": "failed", "error": format!("{:#}", error) })
+}
+
+fn unresolved(line: usize) -> Value {
+    json!({
+        "line": line,
+        "status": "unresolved",
+        "error": "not confirmed within the shutdown grace period; any broadcast is in the ledger",
+    })
+}
+
+/// Cancelled once SIGTERM or Ctrl-C arrives.
+struct Shutdown {
+    requested: CancellationToken,
+    grace: Duration,
+}
+
+impl Shutdown {
+    fn listen(grace: Duration) -> anyhow::Result<Self> {
+        let requested = shutdown::on_signal()?;
+        let announce = requested.clone();
+        tokio::spawn(async move {
+            announce.cancelled().await;
+            eprintln!("Shutting down: no new jobs will be started");
+        });
+        Ok(Self { requested, grace })
+    }
+
+    fn requested(&self) -> bool {
+        self.requested.is_cancelled()
+    }
+
+    async fn wait(&mut self) {
+        self.requested.cancelled().await;
+    }
+
+    /// Runs `job` to completion, unless shutdown is requested meanwhile and
+    /// the grace period then runs out first, in which case it's abandoned.
+    async fn drain<T>(&mut self, job: impl Future<Output = T>) -> Option<T> {
+        tokio::pin!(job);
+        tokio::select! {
+            output = &mut job => return Some(output),
+            _ = self.wait() => {}
+        }
+        tokio::time::timeout(self.grace, job).await.ok()
+    }
+}
+
+impl Sender {
+    /// Picks up an edited config or env file. An invalid edit is reported
+    /// and the previous settings kept.
+    fn reload(&mut self) {
+        let config_modified = modified(&profile::path());
+        let env_modified = config::env_file().and_then(modified);
+        if (config_modified, env_modified) == (self.config_modified, self.env_modified) {
+            return;
+        }
+        if env_modified != self.env_modified {
+            self.env_modified = env_modified;
+            match config::reload_env_file() {
+                Ok(()) => eprintln!("Reloaded {}", config::env_file().expect("env file loaded").display()),
+                Err(e) => print_warn!("Keeping the previous env file settings: {:#}", e),
+            }
+        }
+        self.config_modified = config_modified;
+        match FeeModel::from_env(self.config.chain_id) {
+            Ok(fees) => {
+                eprintln!("Reloaded {}", profile::path().display());
+                self.options.fees = fees;
+            }
+            Err(e) => print_warn!("Keeping the previous fee settings: {:#}", e),
+        }
+    }
+
+    /// Locks one job, returning its result line.
+    async fn send(&self, line: usize, text: &str) -> anyhow::Result<Value> {
+        Ok(match parse(text) {
+            Ok(job) => {
+                let outcomes =
+                    batch::run(&self.clients, &self.config, std::slice::from_ref(&job), &self.ledger, &self.options)
+                        .await?;
+                let outcome = outcomes.into_iter().next().expect("one outcome per job");
+                result(line, &job, &outcome)
+            }
+            Err(e) => json!({ "line": line, "status": "invalid", "error": format!("{:#}", e) }),
+        })
+    }
+}
+
+fn parse(line: &str) -> anyhow::Result<Job> {
+    let params = JobParams::from_json(line)?;
+    Ok(Job {
+        user: params.user,
+        token: params.token,
+        amount: params.amount,
+        nonce: params.nonce.ok_or_else(|| anyhow::anyhow!("job has no nonce"))?,
+        signature: params.signature.ok_or_else(|| anyhow::anyhow!("job has no signature"))?,
+    })
+}
+
+fn result(line: usize, job: &Job, outcome: &Outcome) -> Value {
+    let mut result = json!({
+        "line": line,
+        "status": outcome.status.name(),
+        "sender": outcome.sender,
+        "account_nonce": outcome.nonce.map(|nonce| nonce.to_string()),
+        "tx_hash": outcome.tx_hash,
+        "user": job.user,
+        "token": job.token,
+        "nonce": job.nonce.to_string(),
+    });
+    match &outcome.status {
+        Status::Confirmed { block, gas_used } => {
+            result["block"] = json!(block.map(|block| block.as_u64()));
+            result["gas_used"] = json!(gas_used.to_string());
+        }
+        Status::Reverted { block } => result["block"] = json!(block.map(|block| block.as_u64())),
+        Status::Skipped(reason) | Status::Failed(reason) => result["error"] = json!(reason),
+        Status::Dropped => {}
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Address, Bytes, H256, U256, U64};
+
+    const USER: &str = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8";
+
+    fn outcome(status: Status) -> Outcome {
+        Outcome {
+            index: 0,
+            sender: Address::repeat_byte(0x5e),
+            nonce: Some(4.into()),
+            tx_hash: Some(H256::repeat_byte(0xe1)),
+            status,
+            gas_used: None,
+            effective_gas_price: None,
+            broadcast_at: None,
+            confirmation_time: None,
+        }
+    }
+
+    #[test]
+    fn result_lines() {
+        let signature = format!("0x{:064x}{:064x}1b", 1, 1);
+        let line =
+            format!(r#"{{"user":"{0}","token":"{0}","amount":"1500","nonce":26,"signature":"{1}"}}"#, USER, signature);
+        let job = parse(&line).unwrap();
+        assert_eq!((job.amount, job.nonce), (U256::from(1500), U256::from(26)));
+        assert_eq!(job.signature, signature.parse::<Bytes>().unwrap());
+        let unsigned = format!(r#"{{"user":"{0}","token":"{0}","amount":"1","nonce":1}}"#, USER);
+        assert!(parse(&unsigned).unwrap_err().to_string().contains("no signature"));
+        let unnumbered = format!(r#"{{"user":"{0}","token":"{0}","amount":"1","signature":"{1}"}}"#, USER, signature);
+        assert!(parse(&unnumbered).unwrap_err().to_string().contains("no nonce"));
+
+        let confirmed =
+            result(3, &job, &outcome(Status::Confirmed { block: Some(U64::from(100)), gas_used: 21_000.into() }));
+        assert_eq!(confirmed["line"], 3);
+        assert_eq!(confirmed["status"], "confirmed");
+        assert_eq!((confirmed["nonce"].as_str(), confirmed["account_nonce"].as_str()), (Some("26"), Some("4")));
+        assert_eq!((confirmed["block"].as_u64(), confirmed["gas_used"].as_str()), (Some(100), Some("21000")));
+        assert_eq!(confirmed["tx_hash"], json!(H256::repeat_byte(0xe1)));
+        assert!(confirmed.get("error").is_none());
+        let reverted = result(3, &job, &outcome(Status::Reverted { block: None }));
+        assert_eq!((reverted["status"].as_str(), &reverted["block"]), (Some("reverted"), &Value::Null));
+        let skipped = result(3, &job, &outcome(Status::Skipped("already submitted".to_string())));
+        assert_eq!(
+            (skipped["status"].as_str(), skipped["error"].as_str()),
+            (Some("skipped"), Some("already submitted"))
+        );
+        let dropped = result(3, &job, &outcome(Status::Dropped));
+        assert!(dropped.get("block").is_none() && dropped.get("error").is_none());
+
+        let error = anyhow::anyhow!("connection refused").context("eth_getTransactionCount failed");
+        assert_eq!(failed(4, &error)["error"], "eth_getTransactionCount failed: connection refused");
+        assert_eq!(unresolved(5)["status"], "unresolved");
+    }
+
+    #[tokio::test]
+    async fn draining_on_shutdown() {
+        let mut shutdown = Shutdown { requested: CancellationToken::new(), grace: Duration::from_millis(200) };
+        assert_eq!(shutdown.drain(async { 1 }).await, Some(1));
+
+        // A job in flight when shutdown is requested gets the grace period,
+        // and is abandoned once it runs out.
+        let requested = shutdown.requested.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            requested.cancel();
+        });
+        let finishing = tokio::time::sleep(Duration::from_millis(100));
+        assert_eq!(
+            shutdown
+                .drain(async {
+                    finishing.await;
+                    2
+                })
+                .await,
+            Some(2)
+        );
+        assert!(shutdown.requested());
+        let hanging = tokio::time::sleep(Duration::from_secs(60));
+        assert_eq!(
+            shutdown
+                .drain(async {
+                    hanging.await;
+                    3
+                })
+                .await,
+            None
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use eth_contract_caller::bench::Stats;
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::fees::FeeModel;
+use eth_contract_caller::gas_tank::GasTank;
+use eth_contract_caller::pipeline;
+use eth_contract_caller::stress::{self, Options, TestSigner};
+use ethers::prelude::*;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Locks started per second
+    #[arg(long, default_value_t = 1.0)]
+    rate: f64,
+    /// Number of locks to send
+    #[arg(long, default_value_t = 10)]
+    count: usize,
+    /// Token the synthetic jobs lock (defaults to the native currency)
+    #[arg(long, value_name = "ADDRESS")]
+    token: Option<Address>,
+    /// Amount each job locks, in the token's smallest unit
+    #[arg(long, default_value = "1", value_parser = U256::from_dec_str)]
+    amount: U256,
+}
+
+/// Sends locks for synthetic users at a fixed rate against a test
+/// deployment and reports throughput and latency.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    config::ensure_testnet(config.chain_id)?;
+    anyhow::ensure!(args.rate > 0.0, "--rate must be positive");
+    let signer = TestSigner::from_env()?;
+    let clients = pipeline::connect_pool(&config)?;
+
+    println!("=== Configuration ===");
+    println!("RPC URL: {}", config.rpc_url);
+    println!("Chain ID: {}", config.chain_id);
+    println!("Contract Address: {:?}", config.contract_address);
+    println!("Load: {} lock(s) at {}/s from {} sender(s)", args.count, args.rate, clients.len());
+    println!();
+
+    let provider = pipeline::provider(&config)?;
+    pipeline::ensure_contract_deployed(&provider, &config).await?;
+    if let Some(tank) = GasTank::from_env(&config)? {
+        for client in &clients {
+            tank.ensure_funded(client.address()).await?;
+        }
+    }
+
+    println!("=== Sending ===");
+    let options = Options {
+        rate: args.rate,
+        count: args.count,
+        token: match args.token {
+            Some(token) => token,
+            None => config::native_token()?,
+        },
+        amount: args.amount,
+        fees: FeeModel::from_env(config.chain_id)?,
+    };
+    let report = stress::run(&clients, &config, &signer, &options).await?;
+    println!();
+
+    println!("=== Stress Report ===");
+    println!("Elapsed: {:.1}s", report.elapsed.as_secs_f64());
+    println!("Send Rate: {:.2} tx/s (asked for {})", report.send_rate(), args.rate);
+    println!("Throughput: {:.2} confirmed tx/s", report.throughput());
+    print_stats("Broadcast", &report.broadcast);
+    print_stats("Confirmation", &report.confirmation);
+    Ok(())
+}
+
+fn print_stats(name: &str, stats: &Stats) {
+    let millis = |latency: Option<std::time::Duration>| match latency {
+        Some(latency) => format!("{}ms", latency.as_millis()),
+        None => "-".to_string(),
+    };
+    println!(
+        "{}: {} ok, {} failed; p50 {}, p95 {}, max {}",
+        name,
+        stats.latencies.len(),
+        stats.errors,
+        millis(stats.percentile(50)),
+        millis(stats.percentile(95)),
+        millis(stats.latencies.last().copied()),
+    );
+    if let Some(error) = &stats.first_error {
+        println!("  first failure: {}", error);
+    }
+}
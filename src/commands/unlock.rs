@@ -0,0 +1,35 @@
+use super::job::JobArgs;
+use super::send::{self, SendArgs};
+use eth_contract_caller::config::Config;
+use eth_contract_caller::contract::{MyContract, RedeemWithSignatureCall};
+use eth_contract_caller::pipeline;
+use ethers::contract::EthCall;
+
+const KIND: &str = "unlock";
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(flatten)]
+    job: JobArgs,
+    #[command(flatten)]
+    send: SendArgs,
+}
+
+/// Releases a lock via `redeemWithSignature`. The job is read from the same
+/// variables as `lock`, but SIGNATURE (or the signing service's answer) must
+/// be the backend's redeem signature, which covers a different payload than
+/// the lock one.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let job = args.job.load(KIND, &config, None).await?;
+    pipeline::print_configuration(&config, &job);
+
+    // redeemWithSignature is nonpayable, so no value is attached.
+    let client = pipeline::connect_simulation(&config)?;
+    let code = pipeline::ensure_contract_deployed(&*client, &config).await?;
+    pipeline::warn_if_selector_missing(&*client, &config, &code, RedeemWithSignatureCall::selector()).await?;
+    pipeline::print_token(client.clone(), &job).await?;
+    let contract = MyContract::new(config.contract_address, client.clone());
+    let call = contract.redeem_with_signature(job.user, job.token, job.amount, job.nonce, job.signature.clone());
+    send::send(KIND, &config, &job, &client, call, &args.send).await
+}
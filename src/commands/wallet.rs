@@ -0,0 +1,93 @@
+use eth_contract_caller::config;
+use eth_contract_caller::keystore::{self, ScryptParams};
+use eth_contract_caller::{print_ok, print_warn};
+use ethers::prelude::*;
+use ethers::signers::coins_bip39::English;
+use std::path::PathBuf;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Generate a new key and write it as an encrypted keystore
+    New {
+        /// Hex digits the address must start with, e.g. 0xdead
+        #[arg(long)]
+        vanity: Option<String>,
+        /// Directory to write the keystore to
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+        #[command(flatten)]
+        scrypt: ScryptArgs,
+    },
+    /// Write the configured PRIVATE_KEY, or a MNEMONIC account, as an
+    /// encrypted keystore
+    ExportKeystore {
+        /// Export account INDEX of MNEMONIC instead of PRIVATE_KEY
+        #[arg(long, value_name = "INDEX")]
+        mnemonic_index: Option<u32>,
+        /// Directory to write the keystore to
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+        #[command(flatten)]
+        scrypt: ScryptArgs,
+    },
+}
+
+#[derive(clap::Args)]
+struct ScryptArgs {
+    /// scrypt cost as a power of two; each step doubles the time and memory
+    /// needed to decrypt, or to guess the password
+    #[arg(long, default_value_t = ScryptParams::default().log_n)]
+    scrypt_log_n: u8,
+    /// scrypt block size
+    #[arg(long, default_value_t = ScryptParams::default().r)]
+    scrypt_r: u32,
+    /// scrypt parallelism
+    #[arg(long, default_value_t = ScryptParams::default().p)]
+    scrypt_p: u32,
+}
+
+impl ScryptArgs {
+    fn params(&self) -> ScryptParams {
+        ScryptParams { log_n: self.scrypt_log_n, r: self.scrypt_r, p: self.scrypt_p }
+    }
+}
+
+pub fn run(args: Args) -> anyhow::Result<()> {
+    match args.command {
+        Command::New { vanity, dir, scrypt } => {
+            let prefix = keystore::parse_prefix(vanity.as_deref().unwrap_or(""))?;
+            if prefix.len() > 6 {
+                print_warn!("A {}-digit prefix takes about 16^{} tries on average", prefix.len(), prefix.len());
+            }
+            let password = keystore::password(true)?;
+            let (wallet, attempts) = keystore::generate(&prefix);
+            let path = keystore::write(&dir, &wallet, &password, scrypt.params())?;
+            if !prefix.is_empty() {
+                println!("Found after {} tries", attempts);
+            }
+            println!("Address: {:?}", wallet.address());
+            print_ok!("Keystore written to {}", path.display());
+        }
+        Command::ExportKeystore { mnemonic_index, dir, scrypt } => {
+            let wallet = match mnemonic_index {
+                Some(index) => MnemonicBuilder::<English>::default()
+                    .phrase(config::secret("MNEMONIC")?.as_str())
+                    .index(index)?
+                    .build()?,
+                None => config::private_key()?.parse::<LocalWallet>()?,
+            };
+            let password = keystore::password(true)?;
+            let path = keystore::write(&dir, &wallet, &password, scrypt.params())?;
+            println!("Address: {:?}", wallet.address());
+            print_ok!("Keystore written to {}", path.display());
+            println!("Set PRIVATE_KEY=keystore:{} and KEYSTORE_PASSWORD to use it.", path.display());
+        }
+    }
+    Ok(())
+}
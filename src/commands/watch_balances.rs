@@ -0,0 +1,122 @@
+use eth_contract_caller::balances::{self, AlertState, Change, Reading, WatchFile};
+use eth_contract_caller::units::format_decimal;
+use eth_contract_caller::{config, hooks, print_error, print_ok, print_warn, style};
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// TOML file of the chains and the wallets to watch on them
+    file: PathBuf,
+    /// How often to check the balances
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "5m")]
+    interval: Duration,
+    /// Check once and exit, e.g. from cron
+    #[arg(long)]
+    once: bool,
+    /// Print every wallet's balance and state once, as a report, without
+    /// alerting
+    #[arg(long, conflicts_with = "once")]
+    summary: bool,
+    /// Command run through `sh -c` for each alert, with the wallet, balance
+    /// and change (`low` or `recovered`) as a JSON object on stdin
+    #[arg(long, value_name = "COMMAND")]
+    on_alert: Option<String>,
+}
+
+/// Checks the wallets' balances, alerting when one drops below its threshold
+/// and again once it has recovered.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let file = WatchFile::load(&args.file)?;
+    let mut state = AlertState::from_env()?;
+    if args.summary {
+        return summary(&file, &mut state).await;
+    }
+
+    println!("=== Balance Watch ===");
+    println!("Watching {} wallets on {} chains every {}s", file.wallets.len(), file.chains.len(), args.interval.as_secs());
+    println!();
+    let mut ticks = tokio::time::interval(args.interval);
+    loop {
+        ticks.tick().await;
+        for (name, reading) in balances::check(&file, &mut state).await {
+            match reading {
+                Ok(reading) => alert(&reading, args.on_alert.as_deref()).await,
+                Err(e) => print_warn!("{}: failed to read the balance: {:#}", name, e),
+            }
+        }
+        state.save()?;
+        if args.once {
+            return Ok(());
+        }
+    }
+}
+
+async fn alert(reading: &Reading, command: Option<&str>) {
+    let Some(change) = reading.change else {
+        return;
+    };
+    let balance = format_decimal(reading.balance, 18);
+    match change {
+        Change::Low => print_error!(
+            "{} ({:?} on {}) is down to {} ETH, below its {} ETH threshold",
+            reading.name,
+            reading.address,
+            reading.chain,
+            balance,
+            format_decimal(reading.threshold, 18)
+        ),
+        Change::Recovered => {
+            print_ok!("{} ({:?} on {}) is back at {} ETH", reading.name, reading.address, reading.chain, balance)
+        }
+    }
+    let Some(command) = command else {
+        return;
+    };
+    let payload = json!({
+        "name": reading.name,
+        "chain": reading.chain,
+        "address": reading.address,
+        "balance": reading.balance.to_string(),
+        "threshold": reading.threshold.to_string(),
+        "recover_at": reading.recover_at.to_string(),
+        "change": change.name(),
+    });
+    match hooks::execute(command, &payload).await {
+        Ok((true, _)) => {}
+        Ok((false, stderr)) => eprintln!("{}", style::warn(&format!("--on-alert failed: {}", stderr))),
+        Err(e) => eprintln!("{}", style::warn(&format!("--on-alert failed: {:#}", e))),
+    }
+}
+
+/// One line per wallet, low ones marked, for a daily report.
+async fn summary(file: &WatchFile, state: &mut AlertState) -> anyhow::Result<()> {
+    println!("=== Balance Summary ===");
+    let mut low = 0;
+    for (name, reading) in balances::check(file, state).await {
+        match reading {
+            Ok(reading) => {
+                let line = format!(
+                    "{:<20} {:<10} {:?}  {} ETH (threshold {} ETH)",
+                    reading.name,
+                    reading.chain,
+                    reading.address,
+                    format_decimal(reading.balance, 18),
+                    format_decimal(reading.threshold, 18)
+                );
+                match reading.low {
+                    true => {
+                        low += 1;
+                        print_warn!("{}", line)
+                    }
+                    false => print_ok!("{}", line),
+                }
+            }
+            Err(e) => print_error!("{:<20} failed to read the balance: {:#}", name, e),
+        }
+    }
+    println!();
+    println!("{} of {} wallets low", low, file.wallets.len());
+    Ok(())
+}
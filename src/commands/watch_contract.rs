@@ -0,0 +1,91 @@
+use eth_contract_caller::config::{self, Config};
+use eth_contract_caller::error::Error;
+use eth_contract_caller::upgrades::{self, CodeCache, Snapshot};
+use eth_contract_caller::{hooks, pipeline, print_error, print_ok, print_warn, style};
+use serde_json::json;
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// How often to hash the contract's code
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration, default_value = "30s")]
+    interval: Duration,
+    /// Check once and exit, e.g. from cron
+    #[arg(long)]
+    once: bool,
+    /// Command run through `sh -c` when the code changes, with the contract,
+    /// old and new snapshots as a JSON object on stdin, e.g. to stop the
+    /// relayer
+    #[arg(long, value_name = "COMMAND")]
+    on_change: Option<String>,
+}
+
+/// Hashes the contract's code, and its implementation's behind a proxy, until
+/// either changes. The change is then reported, `--on-change` run, and the
+/// command fails with [`Error::ContractUpgraded`]. The new code is not
+/// remembered, so every later send refuses it too until one is run with
+/// `--acknowledge-upgrade`.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let provider = pipeline::provider(&config)?;
+    let cache = CodeCache::from_env();
+
+    println!("=== Contract Watch ===");
+    println!("Contract Address: {:?}", config.contract_address);
+    println!("Checking every {}s", args.interval.as_secs());
+    println!();
+    let mut ticks = tokio::time::interval(args.interval);
+    loop {
+        ticks.tick().await;
+        let current = match upgrades::snapshot(&provider, config.contract_address).await {
+            Ok(current) => current,
+            Err(e) => {
+                print_warn!("Failed to read the contract's code: {:#}", e);
+                if args.once {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+        match cache.get(config.chain_id, config.contract_address)? {
+            Some(previous) if previous == current => {}
+            Some(previous) => return changed(&config, &previous, &current, args.on_change.as_deref()).await,
+            None => {
+                cache.store(config.chain_id, config.contract_address, current)?;
+                print_ok!("Recorded code hash {:?}", current.code_hash);
+                if let Some(implementation) = current.implementation {
+                    println!("Implementation: {:?}", implementation);
+                }
+            }
+        }
+        if args.once {
+            return Ok(());
+        }
+    }
+}
+
+/// Reports the change from `previous` to `current` and fails with it.
+async fn changed(
+    config: &Config,
+    previous: &Snapshot,
+    current: &Snapshot,
+    command: Option<&str>,
+) -> anyhow::Result<()> {
+    let change = current.describe_change(previous);
+    print_error!("Contract {:?} changed: {}", config.contract_address, change);
+    if let Some(command) = command {
+        let payload = json!({
+            "chain_id": config.chain_id,
+            "address": config.contract_address,
+            "previous": previous,
+            "current": current,
+            "change": change,
+        });
+        match hooks::execute(command, &payload).await {
+            Ok((true, _)) => {}
+            Ok((false, stderr)) => eprintln!("{}", style::warn(&format!("--on-change failed: {}", stderr))),
+            Err(e) => eprintln!("{}", style::warn(&format!("--on-change failed: {:#}", e))),
+        }
+    }
+    Err(Error::ContractUpgraded { address: config.contract_address, change }.into())
+}
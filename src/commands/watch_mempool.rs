@@ -0,0 +1,22 @@
+use eth_contract_caller::config::{self, Config, Job};
+use eth_contract_caller::{mempool, shutdown};
+
+/// Prints pending calls to the contract until interrupted, flagging those
+/// that conflict with the job in the environment, if one is configured.
+pub async fn run() -> anyhow::Result<()> {
+    let config = Config::from_env()?;
+    let ws_url = config::ws_rpc_url()?;
+    let job = Job::from_env().ok();
+
+    println!("=== Mempool Watch ===");
+    println!("Contract Address: {:?}", config.contract_address);
+    match &job {
+        Some(job) => println!("Flagging conflicts with user {:?}, token {:?}, nonce {}", job.user, job.token, job.nonce),
+        None => println!("No job configured; listing every pending call"),
+    }
+    println!("Press Ctrl-C to stop");
+    println!();
+
+    let cancel = shutdown::on_signal()?;
+    mempool::watch_contract(&ws_url, config.contract_address, job.as_ref(), None, &cancel).await
+}
@@ -0,0 +1,61 @@
+use eth_contract_caller::style;
+use eth_contract_caller::workflow::{StepStatus, Workflow};
+use eth_contract_caller::{print_error, print_ok};
+use std::path::PathBuf;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Workflow definition (YAML)
+    file: PathBuf,
+    /// List the steps and their conditions without running anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Runs the workflow's steps in order, skipping those whose conditions don't
+/// hold, and fails if any step that ran failed.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let workflow = Workflow::load(&args.file)?;
+    if let Some(name) = &workflow.name {
+        println!("{}", style::header(&format!("Workflow: {}", name)));
+    }
+    if args.dry_run {
+        for (index, step) in workflow.steps.iter().enumerate() {
+            let action = match &step.shell {
+                Some(shell) => format!("sh -c {:?}", shell),
+                None => step.run.join(" "),
+            };
+            println!("{}. {}: {}", index + 1, step.name, action);
+            if !step.when.is_empty() {
+                println!("   when {}", step.when.join(" and "));
+            }
+            if step.retries > 0 {
+                println!("   retried up to {} time(s)", step.retries);
+            }
+        }
+        return Ok(());
+    }
+
+    let mut results: Vec<(String, StepStatus)> = Vec::new();
+    for step in &workflow.steps {
+        let status = match step.should_run(&results)? {
+            true => {
+                println!("{}", style::header(&format!("Step: {}", step.name)));
+                step.execute().await?
+            }
+            false => StepStatus::Skipped,
+        };
+        println!("Step {} {}", step.name, status);
+        println!();
+        results.push((step.name.clone(), status));
+    }
+
+    let failed: Vec<&str> =
+        results.iter().filter(|(_, status)| *status == StepStatus::Failed).map(|(name, _)| name.as_str()).collect();
+    if failed.is_empty() {
+        print_ok!("Workflow completed");
+        return Ok(());
+    }
+    print_error!("Workflow failed at step(s): {}", failed.join(", "));
+    anyhow::bail!("{} step(s) failed", failed.len())
+}
@@ -0,0 +1,532 @@
+use crate::profile;
+use crate::secrets;
+use crate::signature;
+use anyhow::Context;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+use std::time::Duration;
+
+/// Prefix that scopes every setting to this tool: `ETHERS_RUSTY_RPC_URL`
+/// takes precedence over `RPC_URL`, so instances with different settings can
+/// share a host environment.
+pub const ENV_PREFIX: &str = "ETHERS_RUSTY_";
+
+/// Reads an optional setting from the environment, preferring the
+/// [`ENV_PREFIX`]ed variable over the plain one.
+pub fn env_var(name: &str) -> Option<String> {
+    lookup(&format!("{}{}", ENV_PREFIX, name)).or_else(|| lookup(name))
+}
+
+/// A variable's value, as of the last [`reload_env_file`] if the env file
+/// sets it.
+fn lookup(key: &str) -> Option<String> {
+    match RELOADED.read().expect("env file values poisoned").get(key) {
+        Some(value) => value.clone(),
+        None => env::var(key).ok(),
+    }
+}
+
+/// Reads a required setting from the environment, falling back to the Vault
+/// secret loaded at startup, and naming it in the error.
+pub fn var(name: &str) -> anyhow::Result<String> {
+    env_var(name)
+        .or_else(|| secrets::lookup(name))
+        .with_context(|| format!("missing environment variable {}", name))
+}
+
+/// Connection settings shared by every subcommand.
+pub struct Config {
+    pub rpc_url: String,
+    pub chain_id: u64,
+    pub contract_address: Address,
+}
+
+impl Config {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self { contract_address: var("CONTRACT_ADDRESS")?.parse()?, ..Self::without_contract_from_env()? })
+    }
+
+    /// Reads RPC_URL and CHAIN_ID only, for commands that run before the
+    /// contract exists; the contract address is left zero.
+    pub fn without_contract_from_env() -> anyhow::Result<Self> {
+        // A custom chain's profile can stand in for both.
+        let chain_id = match var("CHAIN_ID") {
+            Ok(chain_id) => chain_id.parse()?,
+            Err(e) => profile::named()?.and_then(|profile| profile.chain_id).ok_or(e)?,
+        };
+        let rpc_url = match var("RPC_URL") {
+            Ok(rpc_url) => rpc_url,
+            Err(e) => profile::active(chain_id)?.and_then(|profile| profile.rpc_url).ok_or(e)?,
+        };
+        Ok(Self { rpc_url, chain_id, contract_address: Address::zero() })
+    }
+}
+
+/// Every configured HTTP provider: RPC_URL, then the comma-separated
+/// RPC_URLS, without duplicates.
+pub fn rpc_urls(config: &Config) -> Vec<String> {
+    let mut urls = vec![config.rpc_url.clone()];
+    for url in env_var("RPC_URLS").unwrap_or_default().split(',').map(str::trim) {
+        if !url.is_empty() && !urls.iter().any(|known| known == url) {
+            urls.push(url.to_string());
+        }
+    }
+    urls
+}
+
+/// The provider's host, leaving out paths and credentials that often carry
+/// an API key.
+pub fn rpc_label(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => url.to_string(),
+        },
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Chain ids of well-known mainnets, where test tooling refuses to run.
+const MAINNET_CHAIN_IDS: &[u64] = &[1, 10, 56, 100, 137, 1868, 8453, 42161, 43114, 59144];
+
+/// Refuses a known mainnet, for commands meant only for test deployments.
+pub fn ensure_testnet(chain_id: u64) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !MAINNET_CHAIN_IDS.contains(&chain_id),
+        "chain {} is a mainnet; this command only runs against test deployments",
+        chain_id
+    );
+    Ok(())
+}
+
+/// WebSocket endpoint (WS_RPC_URL) for subscriptions, which the HTTP
+/// RPC_URL cannot serve.
+pub fn ws_rpc_url() -> anyhow::Result<String> {
+    var("WS_RPC_URL")
+}
+
+/// The env file loaded at startup, and the variables the environment had
+/// already set, which it never overrides.
+struct EnvFile {
+    path: PathBuf,
+    inherited: HashSet<String>,
+}
+
+static ENV_FILE: OnceLock<EnvFile> = OnceLock::new();
+
+/// The env file's variables as last reloaded; `None` for one it no longer
+/// sets.
+static RELOADED: RwLock<BTreeMap<String, Option<String>>> = RwLock::new(BTreeMap::new());
+
+/// Loads settings from `path`, or from `.env` in the working directory when
+/// no path is given. Variables already set in the environment win; a missing
+/// default `.env` is not an error.
+pub fn load_env_file(path: Option<&Path>) -> anyhow::Result<()> {
+    let inherited = env::vars_os().filter_map(|(key, _)| key.into_string().ok()).collect();
+    let path = match path {
+        Some(path) => {
+            dotenv::from_path(path).with_context(|| format!("failed to load env file {}", path.display()))?;
+            path.to_path_buf()
+        }
+        None => match dotenv::dotenv() {
+            Ok(path) => path,
+            Err(_) => return Ok(()),
+        },
+    };
+    ENV_FILE.set(EnvFile { path, inherited }).ok();
+    Ok(())
+}
+
+/// The env file loaded at startup, if any.
+pub fn env_file() -> Option<&'static Path> {
+    ENV_FILE.get().map(|file| file.path.as_path())
+}
+
+/// Reads the env file loaded at startup again, so settings looked up from
+/// then on, such as the hooks and POLICY_FILE, see its current contents.
+/// Variables the environment itself set still win. A file that no longer
+/// parses changes nothing.
+pub fn reload_env_file() -> anyhow::Result<()> {
+    let Some(file) = ENV_FILE.get() else {
+        return Ok(());
+    };
+    let context = || format!("failed to load env file {}", file.path.display());
+    let mut values = BTreeMap::new();
+    // The only way dotenv offers to read a file without setting what it reads.
+    #[allow(deprecated)]
+    for item in dotenv::from_path_iter(&file.path).with_context(context)? {
+        let (key, value) = item.with_context(context)?;
+        if !file.inherited.contains(&key) {
+            values.insert(key, Some(value));
+        }
+    }
+    let mut reloaded = RELOADED.write().expect("env file values poisoned");
+    // Whatever the file set before and no longer does is unset.
+    let removed: Vec<String> = env::vars_os()
+        .filter_map(|(key, _)| key.into_string().ok())
+        .chain(reloaded.keys().cloned())
+        .filter(|key| !file.inherited.contains(key) && !values.contains_key(key))
+        .collect();
+    values.extend(removed.into_iter().map(|key| (key, None)));
+    *reloaded = values;
+    Ok(())
+}
+
+/// Directory for the tool's local state (ledger, caches). Set with STATE_DIR,
+/// defaulting to `.ethers-rusty` in the working directory.
+pub fn state_dir() -> PathBuf {
+    env_var("STATE_DIR").unwrap_or_else(|| ".ethers-rusty".to_string()).into()
+}
+
+/// Path of the replay-protection ledger. Set with LEDGER_PATH, defaulting to
+/// `ledger.jsonl` in [`state_dir`].
+pub fn ledger_path() -> PathBuf {
+    env_var("LEDGER_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| state_dir().join("ledger.jsonl"))
+}
+
+/// Path of the compliance audit log (AUDIT_LOG); auditing is off when unset.
+pub fn audit_log_path() -> Option<PathBuf> {
+    env_var("AUDIT_LOG").map(PathBuf::from)
+}
+
+/// Parses a duration such as `90s`, `5m` or `2h`; a bare number is seconds.
+pub fn parse_duration(input: &str) -> anyhow::Result<Duration> {
+    let input = input.trim();
+    let split = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let number: u64 = number.parse().with_context(|| format!("invalid duration {:?}", input))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => anyhow::bail!("invalid duration {:?}: unit must be s, m or h", input),
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+/// Reads a required secret variable, resolving references such as
+/// `keyring:prod-relayer` (see [`secrets`]).
+pub fn secret(name: &str) -> anyhow::Result<String> {
+    secrets::resolve(&var(name)?).with_context(|| format!("failed to resolve {}", name))
+}
+
+/// The relayer's private key. Kept out of [`Config`] so read-only commands
+/// run without key material.
+pub fn private_key() -> anyhow::Result<String> {
+    secret("PRIVATE_KEY")
+}
+
+/// Throwaway key for preflight simulation (SIMULATION_PRIVATE_KEY), if any.
+pub fn simulation_private_key() -> anyhow::Result<Option<String>> {
+    match var("SIMULATION_PRIVATE_KEY") {
+        Ok(_) => Ok(Some(secret("SIMULATION_PRIVATE_KEY")?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The production sender's address (SENDER_ADDRESS), which preflights
+/// simulate from when the production key itself is not available.
+pub fn sender_address() -> anyhow::Result<Option<Address>> {
+    match env_var("SENDER_ADDRESS") {
+        Some(address) => Ok(Some(address.parse().context("invalid SENDER_ADDRESS")?)),
+        None => Ok(None),
+    }
+}
+
+/// The address standing for the chain's native currency in a job's token
+/// field: NATIVE_TOKEN_ADDRESS, defaulting to the zero address.
+pub fn native_token() -> anyhow::Result<Address> {
+    match env_var("NATIVE_TOKEN_ADDRESS") {
+        Some(address) => address.parse().context("invalid NATIVE_TOKEN_ADDRESS"),
+        None => Ok(Address::zero()),
+    }
+}
+
+/// Value every `lock` carries on top of a native-currency amount, such as a
+/// cross-chain messaging fee: LOCK_EXTRA_VALUE, in wei, defaulting to zero.
+pub fn lock_extra_value() -> anyhow::Result<U256> {
+    match env_var("LOCK_EXTRA_VALUE") {
+        Some(value) => U256::from_dec_str(&value).context("invalid LOCK_EXTRA_VALUE"),
+        None => Ok(U256::zero()),
+    }
+}
+
+/// The ERC-1155 positions a `lock-erc1155` job locks, as `(id, amount)`
+/// pairs: TOKEN_IDS and AMOUNTS, comma-separated and in matching order.
+pub fn erc1155_positions() -> anyhow::Result<Vec<(U256, U256)>> {
+    parse_positions(&var("TOKEN_IDS")?, &var("AMOUNTS")?)
+}
+
+fn parse_positions(ids: &str, amounts: &str) -> anyhow::Result<Vec<(U256, U256)>> {
+    let decimal = |list: &str, name: &str| -> anyhow::Result<Vec<U256>> {
+        list.split(',')
+            .map(|item| U256::from_dec_str(item.trim()).with_context(|| format!("invalid {} entry {:?}", name, item)))
+            .collect()
+    };
+    let (ids, amounts) = (decimal(ids, "TOKEN_IDS")?, decimal(amounts, "AMOUNTS")?);
+    anyhow::ensure!(
+        ids.len() == amounts.len(),
+        "TOKEN_IDS has {} entries but AMOUNTS has {}",
+        ids.len(),
+        amounts.len()
+    );
+    let mut seen = std::collections::HashSet::new();
+    if let Some(id) = ids.iter().find(|id| !seen.insert(**id)) {
+        anyhow::bail!("token id {} appears twice in TOKEN_IDS", id);
+    }
+    Ok(ids.into_iter().zip(amounts).collect())
+}
+
+/// The batch sender pool: the comma-separated PRIVATE_KEYS if set, otherwise
+/// just PRIVATE_KEY.
+pub fn private_keys() -> anyhow::Result<Vec<String>> {
+    match var("PRIVATE_KEYS") {
+        Ok(keys) => keys
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(|key| secrets::resolve(key).context("failed to resolve PRIVATE_KEYS"))
+            .collect(),
+        Err(_) => Ok(vec![private_key()?]),
+    }
+}
+
+/// Kind of the jobs `lock` sends.
+pub const LOCK_KIND: &str = "lock";
+/// Kind of the jobs `lock-nft` sends, whose `amount` is the token id.
+pub const NFT_LOCK_KIND: &str = "lock-nft";
+/// Kind of the jobs `lock-erc1155` sends, whose `amount` is the total of
+/// their [`erc1155_positions`].
+pub const ERC1155_LOCK_KIND: &str = "lock-erc1155";
+
+/// The signed parameters of a single lock or unlock, as handed to us by the
+/// backend signer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub user: Address,
+    pub token: Address,
+    pub amount: U256,
+    pub nonce: U256,
+    pub signature: Bytes,
+}
+
+impl Job {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_env_with_nonce(parse_nonce(&var("NONCE")?)?)
+    }
+
+    /// Reads the job from the environment, using `nonce` in place of NONCE.
+    pub fn from_env_with_nonce(nonce: U256) -> anyhow::Result<Self> {
+        let mut job = Self::unsigned_from_env(nonce)?;
+        job.signature = signature::parse(&var("SIGNATURE")?)?;
+        Ok(job)
+    }
+
+    /// Reads every field of the job but the signature, which is left empty
+    /// for the caller to obtain.
+    pub fn unsigned_from_env(nonce: U256) -> anyhow::Result<Self> {
+        let mut job = Self::parse(&var("USER_ADDRESS")?, &var("TOKEN_ADDRESS")?, &var("AMOUNT")?, "0", "0x")?;
+        job.nonce = nonce;
+        Ok(job)
+    }
+
+    /// Like [`Job::unsigned_from_env`], for an NFT lock: the token id is read
+    /// from TOKEN_ID into `amount`.
+    pub fn unsigned_nft_from_env(nonce: U256) -> anyhow::Result<Self> {
+        let mut job = Self::parse(&var("USER_ADDRESS")?, &var("TOKEN_ADDRESS")?, &var("TOKEN_ID")?, "0", "0x")?;
+        job.nonce = nonce;
+        Ok(job)
+    }
+
+    /// Like [`Job::unsigned_from_env`], for an ERC-1155 lock: `amount` is the
+    /// total of AMOUNTS.
+    pub fn unsigned_erc1155_from_env(nonce: U256) -> anyhow::Result<Self> {
+        let total = erc1155_positions()?.iter().fold(U256::zero(), |total, (_, amount)| total + *amount);
+        let mut job = Self::parse(&var("USER_ADDRESS")?, &var("TOKEN_ADDRESS")?, "0", "0", "0x")?;
+        job.amount = total;
+        job.nonce = nonce;
+        Ok(job)
+    }
+
+    /// Whether the job moves the native currency rather than an ERC-20.
+    pub fn is_native(&self) -> anyhow::Result<bool> {
+        Ok(self.token == native_token()?)
+    }
+
+    /// The value `lock` must carry: the amount for a native-currency lock,
+    /// nothing for an ERC-20 one, whose tokens the contract pulls itself,
+    /// plus LOCK_EXTRA_VALUE either way.
+    pub fn lock_value(&self) -> anyhow::Result<U256> {
+        let amount = if self.is_native()? { self.amount } else { U256::zero() };
+        Ok(amount + lock_extra_value()?)
+    }
+
+    /// Parses a job from its textual fields, in the formats the environment
+    /// variables use: hex addresses, a decimal amount, a nonce as
+    /// [`parse_nonce`] reads it and a hex signature.
+    pub fn parse(user: &str, token: &str, amount: &str, nonce: &str, signature: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            user: user.parse().with_context(|| format!("invalid user address {:?}", user))?,
+            token: token.parse().with_context(|| format!("invalid token address {:?}", token))?,
+            amount: U256::from_dec_str(amount).with_context(|| format!("invalid amount {:?}", amount))?,
+            nonce: parse_nonce(nonce)?,
+            signature: signature.parse().with_context(|| format!("invalid signature {:?}", signature))?, // or hex::decode + Bytes::from
+        })
+    }
+}
+
+/// Parses a nonce: decimal, or hex with a `0x` prefix.
+pub fn parse_nonce(input: &str) -> anyhow::Result<U256> {
+    let parsed = match input.strip_prefix("0x") {
+        _ if input.is_empty() => None,
+        Some(hex) => U256::from_str_radix(hex, 16).ok(),
+        None => U256::from_dec_str(input).ok(),
+    };
+    parsed.with_context(|| format!("invalid nonce {:?}", input))
+}
+
+/// A job given as a JSON object, e.g. piped in by an upstream service. The
+/// fields use the same formats as the variables, except that the amount and
+/// nonce may also be JSON numbers; the nonce and signature may be left out
+/// for the caller to fill in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JobParams {
+    pub user: Address,
+    pub token: Address,
+    pub amount: U256,
+    pub nonce: Option<U256>,
+    pub signature: Option<Bytes>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonJob {
+    user: String,
+    token: String,
+    #[serde(alias = "token_id")]
+    amount: serde_json::Value,
+    nonce: Option<serde_json::Value>,
+    signature: Option<String>,
+}
+
+impl JobParams {
+    pub fn from_json(text: &str) -> anyhow::Result<Self> {
+        let json: JsonJob = serde_json::from_str(text).context("invalid job JSON")?;
+        let job = Job::parse(&json.user, &json.token, &number(&json.amount, "amount")?, "0", "0x")?;
+        let nonce = match &json.nonce {
+            Some(serde_json::Value::Number(n)) => Some(U256::from(n.as_u64().context("nonce must be a non-negative integer")?)),
+            Some(nonce) => Some(parse_nonce(&number(nonce, "nonce")?)?),
+            None => None,
+        };
+        let signature = json.signature.as_deref().map(signature::parse).transpose()?;
+        Ok(Self { user: job.user, token: job.token, amount: job.amount, nonce, signature })
+    }
+}
+
+/// The text of a JSON string or number.
+fn number(value: &serde_json::Value, field: &str) -> anyhow::Result<String> {
+    match value {
+        serde_json::Value::String(s) => Ok(s.clone()),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        _ => anyhow::bail!("{} must be a string or a number", field),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("-5m").is_err());
+    }
+
+    #[test]
+    fn reloading_the_env_file() {
+        let path = std::env::temp_dir().join(format!("env-test-{}", std::process::id()));
+        std::fs::write(&path, "RELOAD_TEST_HOOK=one\nRELOAD_TEST_GONE=x\n").unwrap();
+        load_env_file(Some(&path)).unwrap();
+        assert_eq!(env_var("RELOAD_TEST_HOOK").as_deref(), Some("one"));
+
+        std::fs::write(&path, "RELOAD_TEST_HOOK=two\nRELOAD_TEST_NEW=y\n").unwrap();
+        reload_env_file().unwrap();
+        assert_eq!(env_var("RELOAD_TEST_HOOK").as_deref(), Some("two"));
+        assert_eq!(env_var("RELOAD_TEST_NEW").as_deref(), Some("y"));
+        assert_eq!(env_var("RELOAD_TEST_GONE"), None);
+
+        // An edit that doesn't parse keeps the values it had.
+        std::fs::write(&path, "RELOAD_TEST_HOOK='three\n").unwrap();
+        assert!(reload_env_file().is_err());
+        assert_eq!(env_var("RELOAD_TEST_HOOK").as_deref(), Some("two"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn nonces() {
+        assert_eq!(parse_nonce("26").unwrap(), 26.into());
+        assert_eq!(parse_nonce("0x1a").unwrap(), 26.into());
+        assert!(parse_nonce("1a").is_err());
+        assert!(parse_nonce("").is_err());
+    }
+
+    #[test]
+    fn positions() {
+        let positions = parse_positions("1, 7", "10,20").unwrap();
+        assert_eq!(positions, vec![(1.into(), 10.into()), (7.into(), 20.into())]);
+        assert!(parse_positions("1,7", "10").is_err());
+        assert!(parse_positions("1,1", "10,20").is_err());
+        assert!(parse_positions("1,x", "10,20").is_err());
+    }
+
+    #[test]
+    fn job_json() {
+        let user = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8";
+        // r = 1, s = 1, v = 0, normalized to v = 27.
+        let signature = format!("0x{:064x}{:064x}00", 1, 1);
+        let json = format!(
+            r#"{{"user":"{0}","token":"{0}","amount":"1500","nonce":"0x1a","signature":"{1}"}}"#,
+            user, signature
+        );
+        let params = JobParams::from_json(&json).unwrap();
+        assert_eq!(params.amount, 1500.into());
+        assert_eq!(params.nonce, Some(26.into()));
+        let mut normalized: Vec<u8> = signature.parse::<Bytes>().unwrap().to_vec();
+        normalized[64] = 27;
+        assert_eq!(params.signature, Some(normalized.into()));
+        let json = format!(r#"{{"user":"{0}","token":"{0}","amount":"1","signature":"0xabcd"}}"#, user);
+        assert!(JobParams::from_json(&json).is_err());
+
+        // Numbers are decimal, and the nonce and signature are optional.
+        let json = format!(r#"{{"user":"{0}","token":"{0}","amount":1500,"nonce":10}}"#, user);
+        let params = JobParams::from_json(&json).unwrap();
+        assert_eq!((params.amount, params.nonce, params.signature), (1500.into(), Some(10.into()), None));
+        let json = format!(r#"{{"user":"{0}","token":"{0}","amount":"1"}}"#, user);
+        assert_eq!(JobParams::from_json(&json).unwrap().nonce, None);
+        let json = format!(r#"{{"user":"{0}","token":"{0}","amount":"1","nonce":"10"}}"#, user);
+        assert_eq!(JobParams::from_json(&json).unwrap().nonce, Some(10.into()));
+        let json = format!(r#"{{"user":"{0}","token":"{0}","amount":"1","nonce":"0x10"}}"#, user);
+        assert_eq!(JobParams::from_json(&json).unwrap().nonce, Some(16.into()));
+        let json = format!(r#"{{"user":"{0}","token":"{0}","amount":"1","nonce":"1a"}}"#, user);
+        assert!(JobParams::from_json(&json).is_err());
+        let json = format!(r#"{{"user":"{0}","token":"{0}","token_id":"42"}}"#, user);
+        assert_eq!(JobParams::from_json(&json).unwrap().amount, 42.into());
+
+        assert!(JobParams::from_json(&format!(r#"{{"user":"{0}","token":"{0}","amount":-1}}"#, user)).is_err());
+        assert!(JobParams::from_json(&format!(r#"{{"user":"{0}","token":"{0}","amount":"1","extra":1}}"#, user)).is_err());
+        assert!(JobParams::from_json(r#"{"user":"0x12","token":"0x12","amount":"1"}"#).is_err());
+    }
+}
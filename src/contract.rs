@@ -0,0 +1,172 @@
+use ethers::prelude::*;
+
+abigen!(
+    MyContract,
+    "./abi.json" // save your ABI to a file called `abi.json` in the project root
+);
+
+abigen!(
+    GnosisSafe,
+    r#"[
+        function nonce() external view returns (uint256)
+        function getThreshold() external view returns (uint256)
+        function getOwners() external view returns (address[])
+        function isOwner(address owner) external view returns (bool)
+        function getTransactionHash(address to, uint256 value, bytes data, uint8 operation, uint256 safeTxGas, uint256 baseGas, uint256 gasPrice, address gasToken, address refundReceiver, uint256 _nonce) external view returns (bytes32)
+        function execTransaction(address to, uint256 value, bytes data, uint8 operation, uint256 safeTxGas, uint256 baseGas, uint256 gasPrice, address gasToken, address refundReceiver, bytes signatures) external payable returns (bool success)
+    ]"#
+);
+
+abigen!(
+    Erc20,
+    r#"[
+        function name() external view returns (string)
+        function symbol() external view returns (string)
+        function decimals() external view returns (uint8)
+        function balanceOf(address owner) external view returns (uint256)
+        function allowance(address owner, address spender) external view returns (uint256)
+        function approve(address spender, uint256 amount) external returns (bool)
+    ]"#
+);
+
+// EIP-2612 approvals by signature.
+abigen!(
+    Erc20Permit,
+    r#"[
+        function nonces(address owner) external view returns (uint256)
+        function DOMAIN_SEPARATOR() external view returns (bytes32)
+        function permit(address owner, address spender, uint256 value, uint256 deadline, uint8 v, bytes32 r, bytes32 s) external
+    ]"#
+);
+
+// EIP-3009 transfers by signature, as in USDC.
+abigen!(
+    Erc3009,
+    r#"[
+        function DOMAIN_SEPARATOR() external view returns (bytes32)
+        function authorizationState(address authorizer, bytes32 nonce) external view returns (bool)
+    ]"#
+);
+
+// Multicall3's value-forwarding batch, which the lock router implements.
+// The per-call results are left out so the call sends like any other.
+abigen!(
+    LockRouter,
+    r#"[
+        struct Call3Value { address target; bool allowFailure; uint256 value; bytes callData; }
+        function aggregate3Value(Call3Value[] calls) external payable
+    ]"#
+);
+
+abigen!(
+    AggregatorV3,
+    r#"[
+        function decimals() external view returns (uint8)
+        function latestRoundData() external view returns (uint80 roundId, int256 answer, uint256 startedAt, uint256 updatedAt, uint80 answeredInRound)
+    ]"#
+);
+
+abigen!(
+    Erc165,
+    r#"[
+        function supportsInterface(bytes4 interfaceId) external view returns (bool)
+    ]"#
+);
+
+abigen!(
+    Erc721,
+    r#"[
+        function ownerOf(uint256 tokenId) external view returns (address)
+        function getApproved(uint256 tokenId) external view returns (address)
+        function isApprovedForAll(address owner, address operator) external view returns (bool)
+        function approve(address to, uint256 tokenId) external
+    ]"#
+);
+
+abigen!(
+    Erc1155,
+    r#"[
+        function balanceOfBatch(address[] accounts, uint256[] ids) external view returns (uint256[])
+        function isApprovedForAll(address account, address operator) external view returns (bool)
+        function setApprovalForAll(address operator, bool approved) external
+    ]"#
+);
+
+// The NFT locks of the newer lock contract, which abi.json predates.
+abigen!(
+    NftLock,
+    r#"[
+        function lockNFT(address user, address token, uint256 tokenId, uint256 nonce, bytes signature) external
+        function lockERC1155Batch(address user, address token, uint256[] ids, uint256[] amounts, uint256 nonce, bytes signature) external
+    ]"#
+);
+
+// ERC-4337: the v0.6 EntryPoint, a SimpleAccount-compatible account and its
+// factory.
+abigen!(
+    EntryPoint,
+    r#"[
+        function getNonce(address sender, uint192 key) external view returns (uint256)
+        function balanceOf(address account) external view returns (uint256)
+    ]"#
+);
+
+abigen!(
+    SimpleAccount,
+    r#"[
+        function execute(address dest, uint256 value, bytes func) external
+        function executeBatch(address[] dest, bytes[] func) external
+        function owner() external view returns (address)
+    ]"#
+);
+
+abigen!(
+    SimpleAccountFactory,
+    r#"[
+        function createAccount(address owner, uint256 salt) external returns (address)
+        function getAddress(address owner, uint256 salt) external view returns (address)
+    ]"#
+);
+
+// ERC-7579 modular accounts: modules are installed and uninstalled by the
+// account itself, and announced with events.
+abigen!(
+    ModularAccount,
+    r#"[
+        function installModule(uint256 moduleTypeId, address module, bytes initData) external
+        function uninstallModule(uint256 moduleTypeId, address module, bytes deInitData) external
+        function isModuleInstalled(uint256 moduleTypeId, address module, bytes additionalContext) external view returns (bool)
+        event ModuleInstalled(uint256 moduleTypeId, address module)
+        event ModuleUninstalled(uint256 moduleTypeId, address module)
+    ]"#
+);
+
+// Session keys of a session-key-enabled account: a registered key may sign
+// operations that only call the allowed (target, selector) pairs, attach at
+// most `valueLimit` and are made before `validUntil`.
+abigen!(
+    SessionKeys,
+    r#"[
+        function registerSessionKey(address key, uint48 validUntil, uint256 valueLimit, address[] targets, bytes4[] selectors) external
+        function revokeSessionKey(address key) external
+        function sessionKey(address key) external view returns (uint48 validUntil, uint256 valueLimit)
+    ]"#
+);
+
+// The EIP-3009 lock of the newer lock contract, which abi.json predates. It
+// pulls the amount with the token's receiveWithAuthorization.
+abigen!(
+    AuthorizedLock,
+    r#"[
+        function lockWithAuthorization(address user, address token, uint256 amount, uint256 nonce, bytes signature, uint256 validAfter, uint256 validBefore, bytes32 authorizationNonce, uint8 v, bytes32 r, bytes32 s) external
+    ]"#
+);
+
+// The destination chain's claim verifier, which checks a lock record's
+// storage proof against a relayed block header.
+abigen!(
+    ClaimVerifier,
+    r#"[
+        function claimLock(bytes header, bytes accountProof, bytes[] storageProofs, address user, address token, uint256 nonce) external
+    ]"#
+);
@@ -0,0 +1,170 @@
+//! Cross-provider preflight: a transaction's eth_call and gas estimate run
+//! against every provider in RPC_URL and RPC_URLS at the same block, and
+//! disagreements are flagged: some providers reverting where others succeed,
+//! or gas estimates further apart than CROSS_CHECK_GAS_PERCENT (default 10).
+//! That has been the earliest sign of a provider serving a stale or forked
+//! view. CROSS_CHECK is `warn` (the default), `refuse` to stop the send on a
+//! disagreement, or `off`; with a single provider there is nothing to
+//! compare.
+
+use crate::calldata;
+use crate::config::{self, env_var, Config};
+use crate::error::Error;
+use crate::tls;
+use crate::{print_ok, print_warn};
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+
+const DEFAULT_GAS_PERCENT: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Off,
+    Warn,
+    Refuse,
+}
+
+impl Mode {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env_var("CROSS_CHECK").as_deref() {
+            None | Some("warn") => Ok(Mode::Warn),
+            Some("refuse") => Ok(Mode::Refuse),
+            Some("off") => Ok(Mode::Off),
+            Some(other) => anyhow::bail!("CROSS_CHECK must be warn, refuse or off, not {:?}", other),
+        }
+    }
+}
+
+/// What one provider made of the transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Succeeds { gas: U256 },
+    Reverts { reason: String },
+    /// The provider couldn't answer, e.g. it doesn't have the block yet.
+    Failed { error: String },
+}
+
+/// Runs `tx` against every configured provider and reports how they
+/// compare; with CROSS_CHECK=refuse a disagreement fails with
+/// [`Error::CheckFailed`].
+pub async fn check(config: &Config, tx: &TypedTransaction) -> anyhow::Result<()> {
+    let mode = Mode::from_env()?;
+    let urls = config::rpc_urls(config);
+    if mode == Mode::Off || urls.len() < 2 {
+        return Ok(());
+    }
+    let percent = match env_var("CROSS_CHECK_GAS_PERCENT") {
+        Some(percent) => percent.parse().context("invalid CROSS_CHECK_GAS_PERCENT")?,
+        None => DEFAULT_GAS_PERCENT,
+    };
+    let providers = urls
+        .iter()
+        .map(|url| {
+            let provider = tls::provider(url)
+                .with_context(|| format!("invalid RPC URL {}", config::rpc_label(url)))?;
+            Ok((config::rpc_label(url), provider))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    println!("=== Provider Consistency ===");
+    // RPC_URL's latest block, so a provider that hasn't seen it stands out.
+    let block: BlockId = providers[0].1.get_block_number().await?.into();
+    let outcomes = providers.iter().map(|(_, provider)| outcome(provider, tx, block));
+    let outcomes = futures::future::join_all(outcomes).await;
+    let answers: Vec<(String, Outcome)> = providers.into_iter().map(|(label, _)| label).zip(outcomes).collect();
+    for (label, outcome) in &answers {
+        match outcome {
+            Outcome::Succeeds { gas } => println!("{}: succeeds, {} gas", label, gas),
+            Outcome::Reverts { reason } => println!("{}: {}", label, reason),
+            Outcome::Failed { error } => print_warn!("{} could not answer: {}", label, error),
+        }
+    }
+    let found = disagreements(&answers, percent);
+    if found.is_empty() {
+        print_ok!("Providers agree at block {:?}", block);
+        println!();
+        return Ok(());
+    }
+    for disagreement in &found {
+        print_warn!("{}", disagreement);
+    }
+    println!("A provider may be serving a stale or forked view of the chain");
+    println!();
+    match mode {
+        Mode::Refuse => Err(Error::CheckFailed { reason: found.join("; ") }.into()),
+        _ => Ok(()),
+    }
+}
+
+async fn outcome(provider: &Provider<Http>, tx: &TypedTransaction, block: BlockId) -> Outcome {
+    let reverted = |error: ProviderError| match RpcError::as_error_response(&error) {
+        Some(response) => match response.as_revert_data() {
+            Some(data) => Outcome::Reverts { reason: calldata::decode_revert(&data) },
+            None => Outcome::Reverts { reason: response.message.clone() },
+        },
+        None => Outcome::Failed { error: error.to_string() },
+    };
+    if let Err(e) = provider.call(tx, Some(block)).await {
+        return reverted(e);
+    }
+    match provider.estimate_gas(tx, Some(block)).await {
+        Ok(gas) => Outcome::Succeeds { gas },
+        Err(e) => reverted(e),
+    }
+}
+
+/// The ways the providers' answers disagree, failures aside: reverting
+/// where others succeed, and gas estimates more than `percent` apart.
+pub fn disagreements(answers: &[(String, Outcome)], percent: u64) -> Vec<String> {
+    let labels = |wanted: fn(&Outcome) -> bool| -> Vec<&str> {
+        answers.iter().filter(|(_, outcome)| wanted(outcome)).map(|(label, _)| label.as_str()).collect()
+    };
+    let succeeding = labels(|outcome| matches!(outcome, Outcome::Succeeds { .. }));
+    let reverting = labels(|outcome| matches!(outcome, Outcome::Reverts { .. }));
+    let mut found = Vec::new();
+    if !succeeding.is_empty() && !reverting.is_empty() {
+        found.push(format!("{} revert(s) where {} succeed(s)", reverting.join(", "), succeeding.join(", ")));
+    }
+    let gas: Vec<(&str, U256)> = answers
+        .iter()
+        .filter_map(|(label, outcome)| match outcome {
+            Outcome::Succeeds { gas } => Some((label.as_str(), *gas)),
+            _ => None,
+        })
+        .collect();
+    let lowest = gas.iter().min_by_key(|(_, gas)| *gas);
+    let highest = gas.iter().max_by_key(|(_, gas)| *gas);
+    if let (Some((low_label, low)), Some((high_label, high))) = (lowest, highest) {
+        if (*high - *low) * 100 > *low * percent {
+            found.push(format!(
+                "gas estimates differ by more than {}%: {} on {}, {} on {}",
+                percent, low, low_label, high, high_label
+            ));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finding_disagreements() {
+        let succeeds = |gas: u64| Outcome::Succeeds { gas: gas.into() };
+        let answer = |label: &str, outcome| (label.to_string(), outcome);
+        let failed = Outcome::Failed { error: "header not found".to_string() };
+        let agreeing = [answer("a", succeeds(100_000)), answer("b", succeeds(105_000)), answer("c", failed)];
+        assert!(disagreements(&agreeing, 10).is_empty());
+
+        let apart = [answer("a", succeeds(100_000)), answer("b", succeeds(120_000))];
+        assert_eq!(disagreements(&apart, 10), ["gas estimates differ by more than 10%: 100000 on a, 120000 on b"]);
+        assert!(disagreements(&apart, 25).is_empty());
+
+        let reverts = Outcome::Reverts { reason: "reverted: nonce used".to_string() };
+        let forked = [answer("a", succeeds(100_000)), answer("b", reverts.clone()), answer("c", succeeds(100_000))];
+        assert_eq!(disagreements(&forked, 10), ["b revert(s) where a, c succeed(s)"]);
+        assert!(disagreements(&[answer("a", reverts.clone()), answer("b", reverts)], 10).is_empty());
+    }
+}
@@ -0,0 +1,309 @@
+//! The `dashboard` screen: the sender's stuck transactions, the ledger's
+//! latest submissions and whether they confirmed, gas, wallet balances and
+//! the contract's events, side by side in one terminal. [`Snapshot`] holds
+//! what one refresh read; [`render`] draws it.
+
+use crate::balances::Reading;
+use crate::calldata;
+use crate::fees::{self, FeeReport};
+use crate::ledger::Entry;
+use crate::mempool::Gap;
+use crate::profile::NativeCurrency;
+use crate::units::format_decimal;
+use ethers::prelude::*;
+use ethers::utils::format_units;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Frame;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Events kept in the feed.
+pub const EVENT_FEED: usize = 100;
+
+/// A panel's data, or why it couldn't be read this time.
+pub type Panel<T> = Result<T, String>;
+
+pub struct Snapshot {
+    pub chain_id: u64,
+    pub native: NativeCurrency,
+    pub block: Panel<U64>,
+    /// The address whose transactions are shown, when one is configured.
+    pub sender: Option<Address>,
+    pub sender_balance: Panel<U256>,
+    pub pending: Panel<Gap>,
+    pub confirmations: Panel<Vec<Confirmation>>,
+    pub gas: Panel<FeeReport>,
+    /// The `--balances` file's wallets, by name.
+    pub balances: Vec<(String, Panel<Reading>)>,
+    /// The contract's latest events, newest first.
+    pub events: VecDeque<Log>,
+    /// Unix timestamp of the refresh.
+    pub updated_at: u64,
+}
+
+/// A ledger submission and what became of it.
+pub struct Confirmation {
+    pub entry: Entry,
+    /// `None` while the transaction has no receipt.
+    pub receipt: Option<TransactionReceipt>,
+}
+
+/// Draws `snapshot` over the whole frame.
+pub fn render(frame: &mut Frame, snapshot: &Snapshot) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Percentage(35),
+            Constraint::Percentage(35),
+            Constraint::Min(5),
+        ])
+        .split(frame.size());
+    frame.render_widget(Paragraph::new(header(snapshot)), rows[0]);
+    let [pending, gas] = halves(rows[1]);
+    panel(frame, pending, "Pending", pending_lines(snapshot));
+    panel(frame, gas, "Gas", gas_lines(snapshot));
+    let [confirmations, balances] = halves(rows[2]);
+    panel(frame, confirmations, "Recent Submissions", confirmation_lines(snapshot));
+    panel(frame, balances, "Balances", balance_lines(snapshot));
+    panel(frame, rows[3], "Events", event_lines(snapshot));
+}
+
+fn halves(area: Rect) -> [Rect; 2] {
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(area);
+    [columns[0], columns[1]]
+}
+
+fn panel(frame: &mut Frame, area: Rect, title: &str, lines: Vec<Line<'static>>) {
+    let block = Block::default().borders(Borders::ALL).title(format!(" {} ", title));
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn header(snapshot: &Snapshot) -> Line<'static> {
+    let block = match &snapshot.block {
+        Ok(block) => format!("block {}", block),
+        Err(_) => "block ?".to_string(),
+    };
+    let sender = match (snapshot.sender, &snapshot.sender_balance) {
+        (Some(sender), Ok(balance)) => {
+            format!("sender {:?} ({} {})", sender, format_decimal(*balance, snapshot.native.decimals), snapshot.native.symbol)
+        }
+        (Some(sender), Err(_)) => format!("sender {:?}", sender),
+        (None, _) => "no sender configured".to_string(),
+    };
+    Line::from(vec![
+        Span::styled(format!(" chain {} ", snapshot.chain_id), Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(format!("| {} | {} | updated {}s ago ", block, sender, age(snapshot.updated_at))),
+        Span::styled("| q to quit", Style::default().fg(Color::DarkGray)),
+    ])
+}
+
+fn pending_lines(snapshot: &Snapshot) -> Vec<Line<'static>> {
+    if snapshot.sender.is_none() {
+        return vec![dim("set SENDER_ADDRESS or PRIVATE_KEY to follow a wallet")];
+    }
+    let gap = match &snapshot.pending {
+        Ok(gap) => gap,
+        Err(e) => return vec![failed(e)],
+    };
+    let mut lines = vec![Line::from(format!("latest nonce {}, pending nonce {}", gap.latest, gap.pending))];
+    if gap.stuck.is_empty() {
+        lines.push(good("nothing pending".to_string()));
+    }
+    for stuck in &gap.stuck {
+        let hash = stuck.tx.as_ref().map(|tx| short(tx.hash)).unwrap_or_else(|| "unknown tx".to_string());
+        let fee = stuck
+            .tx
+            .as_ref()
+            .and_then(|tx| tx.max_fee_per_gas.or(tx.gas_price))
+            .and_then(|fee| format_units(fee, "gwei").ok())
+            .map(|fee| format!("{} gwei", trim(&fee)))
+            .unwrap_or_default();
+        let waiting = stuck.submitted_at.map(|at| format!("waiting {}s", age(at))).unwrap_or_default();
+        lines.push(warning(format!("nonce {:<6} {}  {}  {}", stuck.nonce, hash, fee, waiting)));
+    }
+    lines
+}
+
+fn gas_lines(snapshot: &Snapshot) -> Vec<Line<'static>> {
+    let report = match &snapshot.gas {
+        Ok(report) => report,
+        Err(e) => return vec![failed(e)],
+    };
+    let gwei = |value: U256| format_units(value, "gwei").map(|value| trim(&value)).unwrap_or_default();
+    let mut lines = Vec::new();
+    if let Some((_, fees)) = report.tiers.first() {
+        lines.push(Line::from(format!("next base fee {} gwei", gwei(fees.base_fee))));
+    }
+    lines.push(Line::from(format!("base fee {}", fees::sparkline(&report.base_fees))));
+    lines.push(Line::from(format!("tip p50  {}", fees::sparkline(&report.tips))));
+    for (name, fees) in &report.tiers {
+        lines.push(Line::from(format!(
+            "{:<9} tip {} gwei, max fee {} gwei",
+            name,
+            gwei(fees.max_priority_fee_per_gas),
+            gwei(fees.max_fee_per_gas)
+        )));
+    }
+    lines
+}
+
+fn confirmation_lines(snapshot: &Snapshot) -> Vec<Line<'static>> {
+    let confirmations = match &snapshot.confirmations {
+        Ok(confirmations) => confirmations,
+        Err(e) => return vec![failed(e)],
+    };
+    if confirmations.is_empty() {
+        return vec![dim("no submissions in the ledger")];
+    }
+    confirmations
+        .iter()
+        .map(|confirmation| {
+            let entry = &confirmation.entry;
+            let line = format!("{:<7} nonce {:<6} {}", entry.kind, entry.job.nonce, short(entry.tx_hash));
+            match &confirmation.receipt {
+                None => warning(format!("{}  pending {}s", line, age(entry.submitted_at))),
+                Some(receipt) if receipt.status == Some(1.into()) => {
+                    let block = receipt.block_number.unwrap_or_default();
+                    good(format!("{}  confirmed in {}", line, block))
+                }
+                Some(receipt) => failed(&format!("{}  reverted in {}", line, receipt.block_number.unwrap_or_default())),
+            }
+        })
+        .collect()
+}
+
+fn balance_lines(snapshot: &Snapshot) -> Vec<Line<'static>> {
+    if snapshot.balances.is_empty() {
+        return vec![dim("pass --balances with a watch-balances file")];
+    }
+    snapshot
+        .balances
+        .iter()
+        .map(|(name, reading)| match reading {
+            Ok(reading) => {
+                let balance = format_decimal(reading.balance, 18);
+                let line = format!("{:<20} {:<10} {}", reading.name, reading.chain, balance);
+                match reading.low {
+                    true => warning(format!("{}  below {}", line, format_decimal(reading.threshold, 18))),
+                    false => Line::from(line),
+                }
+            }
+            Err(e) => failed(&format!("{:<20} {}", name, e)),
+        })
+        .collect()
+}
+
+fn event_lines(snapshot: &Snapshot) -> Vec<Line<'static>> {
+    if snapshot.events.is_empty() {
+        return vec![dim("no events yet")];
+    }
+    snapshot
+        .events
+        .iter()
+        .map(|log| {
+            let block = log.block_number.unwrap_or_default();
+            let event = match calldata::decode_event(log) {
+                Some((name, args)) => {
+                    let args: Vec<String> = args
+                        .iter()
+                        .map(|(name, value)| format!("{}={}", name, calldata::format_token(value)))
+                        .collect();
+                    format!("{}({})", name, args.join(", "))
+                }
+                None => format!("unknown event {:?}", log.topics.first().copied().unwrap_or_default()),
+            };
+            let line = format!("{:<10} {}  {}", block, event, short(log.transaction_hash.unwrap_or_default()));
+            match log.removed == Some(true) {
+                true => dim(&format!("{}  (removed by a reorg)", line)),
+                false => Line::from(line),
+            }
+        })
+        .collect()
+}
+
+/// `0x1234…abcd`, for hashes in narrow columns.
+fn short(hash: H256) -> String {
+    let hash = format!("{:?}", hash);
+    format!("{}…{}", &hash[..6], &hash[hash.len() - 4..])
+}
+
+/// Drops the trailing zeros `format_units` pads to nine decimals.
+fn trim(amount: &str) -> String {
+    match amount.contains('.') {
+        true => amount.trim_end_matches('0').trim_end_matches('.').to_string(),
+        false => amount.to_string(),
+    }
+}
+
+/// Seconds since the Unix timestamp `at`.
+fn age(at: u64) -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    now.saturating_sub(at)
+}
+
+fn good(text: String) -> Line<'static> {
+    Line::styled(text, Style::default().fg(Color::Green))
+}
+
+fn warning(text: String) -> Line<'static> {
+    Line::styled(text, Style::default().fg(Color::Yellow))
+}
+
+fn failed(text: &str) -> Line<'static> {
+    Line::styled(text.to_string(), Style::default().fg(Color::Red))
+}
+
+fn dim(text: &str) -> Line<'static> {
+    Line::styled(text.to_string(), Style::default().fg(Color::DarkGray))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Job;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    #[test]
+    fn renders_every_panel() {
+        let job = Job {
+            user: Address::repeat_byte(1),
+            token: Address::zero(),
+            amount: 1000.into(),
+            nonce: 7.into(),
+            signature: Bytes::default(),
+        };
+        let entry = Entry::new("lock", 5, Address::repeat_byte(9), &job, H256::repeat_byte(0xab));
+        let receipt =
+            TransactionReceipt { status: Some(1.into()), block_number: Some(42.into()), ..Default::default() };
+        let snapshot = Snapshot {
+            chain_id: 5,
+            native: NativeCurrency { symbol: "ETH".to_string(), decimals: 18 },
+            block: Ok(50.into()),
+            sender: Some(Address::repeat_byte(2)),
+            sender_balance: Ok(U256::exp10(18)),
+            pending: Ok(Gap { latest: 3.into(), pending: 3.into(), stuck: Vec::new() }),
+            confirmations: Ok(vec![Confirmation { entry, receipt: Some(receipt) }]),
+            gas: Err("eth_feeHistory unsupported".to_string()),
+            balances: Vec::new(),
+            events: VecDeque::new(),
+            updated_at: 0,
+        };
+        let mut terminal = Terminal::new(TestBackend::new(140, 30)).unwrap();
+        terminal.draw(|frame| render(frame, &snapshot)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("chain 5"));
+        assert!(screen.contains("nothing pending"));
+        assert!(screen.contains("lock    nonce 7      0xabab…abab  confirmed in 42"));
+        assert!(screen.contains("eth_feeHistory unsupported"));
+        assert!(screen.contains("pass --balances"));
+        assert!(screen.contains("no events yet"));
+    }
+}
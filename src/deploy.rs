@@ -0,0 +1,94 @@
+//! Deployment of the lock contract from compiled bytecode.
+
+use crate::audit;
+use crate::contract::MYCONTRACT_ABI;
+use crate::pipeline::Client;
+use anyhow::Context;
+use ethers::abi::{self, Tokenize};
+use ethers::prelude::*;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+const PURPOSE: &str = "deploy";
+
+/// The lock contract's constructor arguments.
+#[derive(Clone, Debug)]
+pub struct ConstructorArgs {
+    pub relayer: Address,
+    pub lz_endpoint: Address,
+    pub owner: Address,
+    pub soneium_lz_chain_id: u16,
+}
+
+impl ConstructorArgs {
+    pub fn tokens(&self) -> (Address, Address, Address, u16) {
+        (self.relayer, self.lz_endpoint, self.owner, self.soneium_lz_chain_id)
+    }
+
+    /// The arguments ABI-encoded, as appended to the creation bytecode.
+    pub fn encode(&self) -> Bytes {
+        abi::encode(&self.tokens().into_tokens()).into()
+    }
+}
+
+/// Reads creation bytecode from `path`: a hex file, or a Foundry or Hardhat
+/// artifact (`bytecode.object` or `bytecode`).
+pub fn read_bytecode(path: &Path) -> anyhow::Result<Bytes> {
+    let text = fs::read_to_string(path).with_context(|| format!("failed to read bytecode {}", path.display()))?;
+    let hex = match serde_json::from_str::<Value>(&text) {
+        Ok(artifact) => artifact
+            .pointer("/bytecode/object")
+            .or_else(|| artifact.get("bytecode"))
+            .and_then(Value::as_str)
+            .with_context(|| format!("{} has no bytecode or bytecode.object", path.display()))?
+            .to_string(),
+        Err(_) => text,
+    };
+    let bytecode: Bytes = hex.trim().parse().with_context(|| format!("invalid bytecode in {}", path.display()))?;
+    anyhow::ensure!(!bytecode.is_empty(), "{} holds no bytecode", path.display());
+    Ok(bytecode)
+}
+
+/// Deploys the lock contract from `client` and waits for it to be mined,
+/// returning the receipt with its address.
+pub async fn deploy(client: &Client, bytecode: Bytes, args: &ConstructorArgs) -> anyhow::Result<TransactionReceipt> {
+    let factory = ContractFactory::new(MYCONTRACT_ABI.clone(), bytecode, Arc::new(client.clone()));
+    let tx = factory.deploy(args.tokens())?.tx;
+    let pending = audit::send_transaction(client, tx, PURPOSE).await?;
+    println!("Deployment Hash: {:?}", pending.tx_hash());
+    let receipt = pending.await?.context("deployment dropped before it was mined")?;
+    audit::record_receipt(client.address(), PURPOSE, &receipt)?;
+    anyhow::ensure!(
+        receipt.status == Some(U64::from(1)),
+        "deployment {:?} reverted",
+        receipt.transaction_hash
+    );
+    anyhow::ensure!(receipt.contract_address.is_some(), "deployment receipt has no contract address");
+    Ok(receipt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_hex_and_artifacts() {
+        let dir = std::env::temp_dir().join(format!("deploy-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cases = [
+            ("plain.hex", "0x6080604052\n"),
+            ("foundry.json", r#"{"abi": [], "bytecode": {"object": "0x6080604052"}}"#),
+            ("hardhat.json", r#"{"abi": [], "bytecode": "0x6080604052"}"#),
+        ];
+        for (name, contents) in cases {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            assert_eq!(read_bytecode(&path).unwrap(), Bytes::from(vec![0x60, 0x80, 0x60, 0x40, 0x52]), "{}", name);
+        }
+        fs::write(dir.join("empty.json"), r#"{"abi": []}"#).unwrap();
+        assert!(read_bytecode(&dir.join("empty.json")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
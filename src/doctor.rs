@@ -0,0 +1,173 @@
+//! Environment diagnostics against the live RPC: the checks that explain
+//! nearly every first-run failure, reported as [`Finding`]s.
+
+use crate::bytecode;
+use crate::check::{Finding, Severity};
+use crate::config::{self, Config};
+use crate::contract::MYCONTRACT_ABI;
+use crate::pipeline::{self, Rpc};
+use crate::upgrades::{self, CodeCache};
+use ethers::prelude::*;
+use ethers::utils::format_units;
+use std::time::{Duration, Instant};
+
+/// Latency above which the RPC is flagged as slow.
+const SLOW_RPC: Duration = Duration::from_secs(1);
+
+pub async fn run(config: &Config) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let provider = match pipeline::provider(config) {
+        Ok(provider) => provider,
+        Err(e) => {
+            findings.push(Finding::new("RPC", Severity::Error, format!("invalid RPC_URL: {}", e)));
+            return findings;
+        }
+    };
+
+    let started = Instant::now();
+    match provider.get_block_number().await {
+        Ok(block) => {
+            let latency = started.elapsed();
+            let severity = if latency > SLOW_RPC { Severity::Warning } else { Severity::Ok };
+            findings.push(Finding::new(
+                "RPC",
+                severity,
+                format!("reachable, block {} in {} ms", block, latency.as_millis()),
+            ));
+        }
+        Err(e) => {
+            findings.push(Finding::new("RPC", Severity::Error, format!("unreachable: {}", e)));
+            return findings;
+        }
+    }
+
+    findings.push(match provider.get_chainid().await {
+        Ok(id) if id == config.chain_id.into() => Finding::new("Chain ID", Severity::Ok, format!("{}", id)),
+        Ok(id) => Finding::new(
+            "Chain ID",
+            Severity::Error,
+            format!("RPC reports {}, but CHAIN_ID is {}", id, config.chain_id),
+        ),
+        Err(e) => Finding::new("Chain ID", Severity::Error, format!("eth_chainId failed: {}", e)),
+    });
+
+    findings.push(match provider.get_code(config.contract_address, None).await {
+        Ok(code) if !code.is_empty() => Finding::new(
+            "Contract",
+            Severity::Ok,
+            format!("{} bytes of code at {:?}", code.len(), config.contract_address),
+        ),
+        Ok(_) => Finding::new(
+            "Contract",
+            Severity::Error,
+            format!("no code at {:?} on chain {}", config.contract_address, config.chain_id),
+        ),
+        Err(e) => Finding::new("Contract", Severity::Error, format!("eth_getCode failed: {}", e)),
+    });
+
+    findings.extend(contract_abi(&provider, config).await);
+    if let Some(finding) = upgrade(&provider, config).await {
+        findings.push(finding);
+    }
+
+    let sender = match config::sender_address() {
+        Ok(Some(sender)) => Some(sender),
+        Ok(None) => config::private_key()
+            .ok()
+            .and_then(|key| key.parse::<LocalWallet>().ok())
+            .map(|wallet| wallet.address()),
+        Err(e) => {
+            findings.push(Finding::new("Wallet", Severity::Error, e.to_string()));
+            return findings;
+        }
+    };
+    let Some(sender) = sender else {
+        findings.push(Finding::new("Wallet", Severity::Warning, "no PRIVATE_KEY or SENDER_ADDRESS to check"));
+        return findings;
+    };
+
+    findings.push(match provider.get_balance(sender, None).await {
+        Ok(balance) if balance.is_zero() => Finding::new("Balance", Severity::Error, format!("{:?} has no funds", sender)),
+        Ok(balance) => Finding::new(
+            "Balance",
+            Severity::Ok,
+            format!("{:?} holds {} ETH", sender, format_units(balance, "ether").unwrap_or_default()),
+        ),
+        Err(e) => Finding::new("Balance", Severity::Error, format!("eth_getBalance failed: {}", e)),
+    });
+
+    let latest = provider.get_transaction_count(sender, Some(BlockNumber::Latest.into())).await;
+    let pending = provider.get_transaction_count(sender, Some(BlockNumber::Pending.into())).await;
+    findings.push(match (latest, pending) {
+        (Ok(latest), Ok(pending)) if pending > latest => Finding::new(
+            "Nonce",
+            Severity::Warning,
+            format!("{} transaction(s) pending (latest {}, pending {})", pending - latest, latest, pending),
+        ),
+        (Ok(latest), Ok(_)) => Finding::new("Nonce", Severity::Ok, format!("next nonce {}, nothing pending", latest)),
+        (Err(e), _) | (_, Err(e)) => Finding::new("Nonce", Severity::Error, format!("eth_getTransactionCount failed: {}", e)),
+    });
+
+    findings
+}
+
+/// Whether the contract is an EIP-1967 proxy, and whether the code calls end
+/// up in (the implementation's, for a proxy) dispatches every function in
+/// abi.json.
+async fn contract_abi(provider: &Rpc, config: &Config) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let target = match bytecode::resolve_proxy(provider, config.contract_address).await {
+        Ok(Some(proxy)) => {
+            let via = proxy.beacon.map(|beacon| format!(" via beacon {:?}", beacon)).unwrap_or_default();
+            let message = format!("EIP-1967 proxy for {:?}{}", proxy.implementation, via);
+            findings.push(Finding::new("Proxy", Severity::Ok, message));
+            proxy.implementation
+        }
+        Ok(None) => config.contract_address,
+        Err(e) => {
+            let message = format!("failed to resolve the implementation: {:#}", e);
+            findings.push(Finding::new("Proxy", Severity::Error, message));
+            return findings;
+        }
+    };
+    let code = match provider.get_code(target, None).await {
+        Ok(code) if !code.is_empty() => code,
+        Ok(_) => {
+            findings.push(Finding::new("ABI", Severity::Error, format!("no code at {:?} to check abi.json against", target)));
+            return findings;
+        }
+        Err(e) => {
+            findings.push(Finding::new("ABI", Severity::Error, format!("eth_getCode failed: {}", e)));
+            return findings;
+        }
+    };
+    let missing = bytecode::missing_functions(&code, &MYCONTRACT_ABI);
+    let total = MYCONTRACT_ABI.functions().count();
+    findings.push(match missing.is_empty() {
+        true => Finding::new("ABI", Severity::Ok, format!("all {} abi.json functions found in {:?}", total, target)),
+        false => Finding::new(
+            "ABI",
+            Severity::Warning,
+            format!("{} of {} abi.json functions not found in {:?}: {}", missing.len(), total, target, missing.join(", ")),
+        ),
+    });
+    findings
+}
+
+/// Whether the contract changed since the last run recorded it; `None` when
+/// no run has yet. Nothing is recorded, so sends still stop on the change.
+async fn upgrade(provider: &Rpc, config: &Config) -> Option<Finding> {
+    let previous = match CodeCache::from_env().get(config.chain_id, config.contract_address) {
+        Ok(previous) => previous?,
+        Err(e) => return Some(Finding::new("Upgrade", Severity::Error, format!("{:#}", e))),
+    };
+    Some(match upgrades::snapshot(provider, config.contract_address).await {
+        Ok(current) if current == previous => Finding::new("Upgrade", Severity::Ok, "unchanged since the last run"),
+        Ok(current) => Finding::new(
+            "Upgrade",
+            Severity::Warning,
+            format!("changed since the last run: {}; sends need --acknowledge-upgrade", current.describe_change(&previous)),
+        ),
+        Err(e) => Finding::new("Upgrade", Severity::Error, format!("failed to read the contract: {:#}", e)),
+    })
+}
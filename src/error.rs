@@ -0,0 +1,55 @@
+use ethers::prelude::*;
+
+/// Failures that callers are expected to handle differently from a generic
+/// error, each mapped to its own process exit code.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("already processed: a lock for user {user:?}, token {token:?} with nonce {nonce} exists on-chain")]
+    AlreadyProcessed { user: Address, token: Address, nonce: U256 },
+    #[error("duplicate submission: this exact job was already broadcast as {tx_hash:?} (pass --force to send it again)")]
+    DuplicateSubmission { tx_hash: H256 },
+    #[error("no contract deployed at {address:?} on chain {chain_id}")]
+    NoContract { address: Address, chain_id: u64 },
+    #[error("deadline exceeded: {tx_hash:?} was not mined in time and a cancellation was sent")]
+    DeadlineExceeded { tx_hash: H256 },
+    #[error("spend limit exceeded: gas plus value would cost {cost}, above the {limit} limit")]
+    UsdCostExceeded { cost: String, limit: String },
+    #[error("policy violation: {reason}")]
+    PolicyViolation { reason: String },
+    #[error("chain id mismatch ({ids}); refusing to sign")]
+    ChainMismatch { ids: String },
+    #[error("contract {address:?} changed since the last run ({change}); pass --acknowledge-upgrade to continue")]
+    ContractUpgraded { address: Address, change: String },
+    #[error("detached: {tx_hash:?} is still pending; follow it with `status`")]
+    Detached { tx_hash: H256 },
+    #[error("preflight check failed: {reason}")]
+    CheckFailed { reason: String },
+    #[error("signature expired: the {what} expired {ago} seconds ago by the chain's clock and must be signed again")]
+    SignatureExpired { what: String, ago: u64 },
+    #[error("post-confirmation check failed: {tx_hash:?} was mined, but {reason}")]
+    PostCheckFailed { tx_hash: H256, reason: String },
+    #[error("nonce mismatch: the job uses nonce {nonce}, but the contract expects {expected}")]
+    NonceMismatch { nonce: U256, expected: U256 },
+}
+
+impl Error {
+    /// Exit code for this failure. 1 is left for generic errors and 2 for
+    /// usage errors reported by clap.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::AlreadyProcessed { .. } => 3,
+            Error::DuplicateSubmission { .. } => 4,
+            Error::NoContract { .. } => 5,
+            Error::DeadlineExceeded { .. } => 6,
+            Error::UsdCostExceeded { .. } => 7,
+            Error::PolicyViolation { .. } => 8,
+            Error::ChainMismatch { .. } => 9,
+            Error::ContractUpgraded { .. } => 10,
+            Error::Detached { .. } => 11,
+            Error::CheckFailed { .. } => 12,
+            Error::SignatureExpired { .. } => 13,
+            Error::PostCheckFailed { .. } => 14,
+            Error::NonceMismatch { .. } => 15,
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! The contract's event logs, each rendered as one JSON object, and a follow
+//! mode that streams new ones as they are emitted: over a WebSocket
+//! subscription when WS_RPC_URL is set, and by polling otherwise.
+
+use crate::calldata;
+use crate::contract::MYCONTRACT_ABI;
+use crate::pipeline::Rpc;
+use crate::shutdown::{self, CancellationToken};
+use crate::style;
+use anyhow::Context;
+use ethers::abi::token::{LenientTokenizer, Tokenizer};
+use ethers::abi::{self, Abi, Event, ParamType, Token};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use serde_json::{json, Map, Value};
+use std::time::Duration;
+
+/// A log as JSON: the decoded event when abi.json knows it, the raw topics
+/// and data otherwise, with where it was emitted.
+pub fn to_json(log: &Log) -> Value {
+    to_json_in(&MYCONTRACT_ABI, log)
+}
+
+/// A log as JSON, as [`to_json`] renders it, decoded against `abi`.
+pub fn to_json_in(abi: &Abi, log: &Log) -> Value {
+    let mut value = match calldata::decode_event_in(abi, log) {
+        Some((name, args)) => {
+            let args: Map<String, Value> =
+                args.iter().map(|(name, value)| (name.clone(), Value::String(calldata::format_token(value)))).collect();
+            json!({ "address": log.address, "log_index": log.log_index, "event": name, "args": args })
+        }
+        None => json!({ "address": log.address, "log_index": log.log_index, "topics": log.topics, "data": log.data }),
+    };
+    value["block_number"] = json!(log.block_number);
+    value["transaction_hash"] = json!(log.transaction_hash);
+    // Set on logs a reorg took back out of the chain.
+    if log.removed == Some(true) {
+        value["removed"] = json!(true);
+    }
+    value
+}
+
+/// A log filter for `contract`, narrowed to the abi.json `events` named (all
+/// of them when empty) and to the `conditions` on indexed parameters, given
+/// as `(name, value)`. Several values for one parameter match any of them.
+///
+/// Every event left must carry each parameter at the same topic position,
+/// since a filter can only match positions.
+pub fn filter(contract: Address, events: &[String], conditions: &[(String, String)]) -> anyhow::Result<Filter> {
+    filter_in(&MYCONTRACT_ABI, contract, events, conditions)
+}
+
+/// A log filter for `contract`, as [`filter`] builds it, over the events of
+/// `abi`.
+pub fn filter_in(
+    abi: &Abi,
+    contract: Address,
+    events: &[String],
+    conditions: &[(String, String)],
+) -> anyhow::Result<Filter> {
+    let mut candidates: Vec<&Event> = match events.is_empty() {
+        true => abi.events().collect(),
+        false => events
+            .iter()
+            .map(|name| abi.event(name).with_context(|| format!("the ABI has no event {}", name)))
+            .collect::<anyhow::Result<_>>()?,
+    };
+    for (name, _) in conditions {
+        candidates.retain(|event| event.inputs.iter().any(|input| input.indexed && input.name == *name));
+        anyhow::ensure!(!candidates.is_empty(), "no selected event has an indexed parameter {:?}", name);
+    }
+
+    let mut filter = Filter::new().address(contract);
+    if !events.is_empty() || !conditions.is_empty() {
+        filter.topics[0] = Some(ValueOrArray::Array(candidates.iter().map(|event| Some(event.signature())).collect()));
+    }
+    for (name, value) in conditions {
+        let mut positions = candidates.iter().map(|event| {
+            let indexed = event.inputs.iter().filter(|input| input.indexed);
+            let (position, input) = indexed.enumerate().find(|(_, input)| input.name == *name).expect("kept above");
+            (position + 1, input.kind.clone())
+        });
+        let (position, kind) = positions.next().expect("at least one candidate");
+        anyhow::ensure!(
+            positions.all(|(other, _)| other == position),
+            "{:?} is at different topic positions across the selected events; pick one with --event",
+            name
+        );
+        let topic = encode_topic(&kind, value).with_context(|| format!("invalid value {:?} for {}", value, name))?;
+        match &mut filter.topics[position] {
+            Some(ValueOrArray::Array(topics)) => topics.push(Some(topic)),
+            slot => *slot = Some(ValueOrArray::Array(vec![Some(topic)])),
+        }
+    }
+    Ok(filter)
+}
+
+/// The topic an indexed parameter of type `kind` holding `value` is logged
+/// as: the value itself, ABI-encoded, or the hash of a dynamic one.
+fn encode_topic(kind: &ParamType, value: &str) -> anyhow::Result<H256> {
+    let token = LenientTokenizer::tokenize(kind, value)?;
+    Ok(match token {
+        Token::String(text) => H256(keccak256(text)),
+        Token::Bytes(bytes) => H256(keccak256(bytes)),
+        token => H256::from_slice(&abi::encode(&[token])),
+    })
+}
+
+/// Parses a `NAME=VALUE` condition.
+pub fn parse_condition(input: &str) -> anyhow::Result<(String, String)> {
+    let (name, value) = input.split_once('=').context("expected NAME=VALUE")?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
+/// The logs `filter` matches in blocks `from..=to`.
+pub async fn range<M: Middleware>(client: &M, filter: &Filter, from: U64, to: U64) -> anyhow::Result<Vec<Log>>
+where
+    M::Error: 'static,
+{
+    Ok(client.get_logs(&filter.clone().from_block(from).to_block(to)).await?)
+}
+
+/// Blocks per eth_getLogs request, before any splitting.
+pub const DEFAULT_CHUNK: u64 = 2_000;
+
+/// Calls `emit` with the logs `filter` matches in blocks `from..=to`, in order,
+/// fetching `chunk` blocks per request. A range the provider refuses as too
+/// large (most cap a response at 10,000 logs) is halved and retried, down to
+/// a single block.
+pub async fn backfill<M: Middleware>(
+    client: &M,
+    filter: &Filter,
+    from: u64,
+    to: u64,
+    chunk: u64,
+    mut emit: impl FnMut(&Log) -> anyhow::Result<()>,
+) -> anyhow::Result<()>
+where
+    M::Error: 'static,
+{
+    let (mut start, mut size) = (from, chunk.max(1));
+    while start <= to {
+        let end = to.min(start + size - 1);
+        match range(client, filter, start.into(), end.into()).await {
+            Ok(logs) => {
+                eprintln!("Blocks {}..={}: {} event(s)", start, end, logs.len());
+                logs.iter().try_for_each(&mut emit)?;
+                start = end + 1;
+                // Dense stretches are usually short; widen again after one.
+                size = (size * 2).min(chunk.max(1));
+            }
+            Err(e) if end > start && is_range_too_large(&format!("{:#}", e)) => size = (end - start).div_ceil(2),
+            Err(e) => return Err(e.context(format!("eth_getLogs for blocks {}..={} failed", start, end))),
+        }
+    }
+    Ok(())
+}
+
+/// Whether an eth_getLogs error means the range holds too many logs or spans
+/// too many blocks, in the wordings of the common providers.
+fn is_range_too_large(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "query returned more than",
+        "more than 10000",
+        "too many",
+        "limit exceeded",
+        "block range",
+        "range is too large",
+        "response size",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Calls `emit` with every new log `filter` matches from now on, until the
+/// stream or `emit` fails, or until `cancel` is cancelled, which closes the
+/// subscription and returns `Ok`. Subscribes over `ws_url` when given, and
+/// falls back to polling `provider` every `interval`, from block `next` on,
+/// when there is none or it can't be reached. Progress goes to stderr,
+/// leaving stdout to the events.
+pub async fn follow(
+    provider: &Rpc,
+    ws_url: Option<&str>,
+    filter: &Filter,
+    mut next: U64,
+    interval: Duration,
+    cancel: &CancellationToken,
+    mut emit: impl FnMut(&Log) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    if let Some(url) = ws_url {
+        match Provider::<Ws>::connect(url).await {
+            Ok(ws) => {
+                eprintln!("Following over a WebSocket subscription ({})", url);
+                let mut logs = ws.subscribe_logs(filter).await?;
+                while let Some(log) = shutdown::unless_cancelled(cancel, logs.next()).await {
+                    let Some(log) = log else {
+                        anyhow::bail!("the log subscription on {} closed", url);
+                    };
+                    emit(&log)?;
+                }
+                logs.unsubscribe().await?;
+                return Ok(());
+            }
+            Err(e) => eprintln!("{}", style::warn(&format!("Could not connect to {} ({}); polling instead", url, e))),
+        }
+    }
+    eprintln!("Following by polling every {:?}", interval);
+    while shutdown::unless_cancelled(cancel, tokio::time::sleep(interval)).await.is_some() {
+        let Some(latest) = shutdown::unless_cancelled(cancel, provider.get_block_number()).await else {
+            break;
+        };
+        let latest = latest?;
+        if latest < next {
+            continue;
+        }
+        let Some(logs) = shutdown::unless_cancelled(cancel, range(provider, filter, next, latest)).await else {
+            break;
+        };
+        for log in logs? {
+            emit(&log)?;
+        }
+        next = latest + 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_logs_keep_raw_data() {
+        let log = Log {
+            address: Address::repeat_byte(1),
+            topics: vec![H256::repeat_byte(2)],
+            data: vec![0xab].into(),
+            block_number: Some(7.into()),
+            removed: Some(true),
+            ..Default::default()
+        };
+        let value = to_json(&log);
+        assert_eq!(value["data"], "0xab");
+        assert_eq!(value["block_number"], "0x7");
+        assert_eq!(value["removed"], true);
+        assert!(value.get("event").is_none());
+    }
+
+    #[test]
+    fn indexed_filters() {
+        let contract = Address::repeat_byte(0xaa);
+        let user = Address::repeat_byte(1);
+        let conditions = [("user".to_string(), format!("{:?}", user))];
+        let by_user = filter(contract, &[], &conditions).unwrap();
+        let Some(ValueOrArray::Array(events)) = &by_user.topics[0] else { panic!("no event topics") };
+        assert_eq!(events.len(), 5);
+        assert_eq!(by_user.topics[1], Some(ValueOrArray::Array(vec![Some(H256::from(user))])));
+
+        let tokens = [("token".to_string(), "0x0202020202020202020202020202020202020202".to_string())];
+        let locked = filter(contract, &["Locked".to_string()], &tokens).unwrap();
+        assert_eq!(locked.topics[0], Some(ValueOrArray::Array(vec![Some(MYCONTRACT_ABI.event("Locked").unwrap().signature())])));
+        assert!(locked.topics[2].is_some());
+
+        // amount isn't indexed, and DepositedETH has no token.
+        assert!(filter(contract, &[], &[("amount".to_string(), "1".to_string())]).is_err());
+        assert!(filter(contract, &["DepositedETH".to_string()], &tokens).is_err());
+        assert!(filter(contract, &[], &[]).unwrap().topics[0].is_none());
+    }
+
+    #[test]
+    fn range_errors() {
+        assert!(is_range_too_large("(code: -32005, message: query returned more than 10000 results)"));
+        assert!(is_range_too_large("eth_getLogs is limited to a 10,000 block range"));
+        assert!(is_range_too_large("Log response size exceeded"));
+        assert!(!is_range_too_large("execution reverted"));
+        assert!(!is_range_too_large("connection refused"));
+    }
+}
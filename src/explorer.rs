@@ -0,0 +1,61 @@
+//! Block-explorer links for transactions and addresses.
+//!
+//! The explorer of a chain is EXPLORER_URL if set, else the active profile's
+//! `explorer_url`, else the one the ethers chain registry knows for the chain
+//! id; custom chains need one of the first two.
+
+use crate::config;
+use crate::profile;
+use ethers::prelude::*;
+
+pub struct Explorer {
+    base: String,
+}
+
+impl Explorer {
+    pub fn new(base: impl Into<String>) -> Self {
+        Self { base: base.into().trim_end_matches('/').to_string() }
+    }
+
+    /// The explorer for `chain_id`, if one is configured or known.
+    pub fn for_chain(chain_id: u64) -> anyhow::Result<Option<Self>> {
+        if let Some(url) = config::env_var("EXPLORER_URL") {
+            return Ok(Some(Self::new(url)));
+        }
+        if let Some(url) = profile::active(chain_id)?.and_then(|profile| profile.explorer_url) {
+            return Ok(Some(Self::new(url)));
+        }
+        Ok(registry_url(chain_id).map(Self::new))
+    }
+
+    pub fn tx(&self, hash: H256) -> String {
+        format!("{}/tx/{:?}", self.base, hash)
+    }
+
+    pub fn address(&self, address: Address) -> String {
+        format!("{}/address/{:?}", self.base, address)
+    }
+}
+
+/// The explorer the chain registry lists for `chain_id`.
+fn registry_url(chain_id: u64) -> Option<&'static str> {
+    let chain = Chain::try_from(chain_id).ok()?;
+    chain.etherscan_urls().map(|(_, browser)| browser)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links() {
+        let explorer = Explorer::new("https://scan.example/");
+        assert_eq!(
+            explorer.address(Address::repeat_byte(0xab)),
+            "https://scan.example/address/0xabababababababababababababababababababab"
+        );
+        assert!(explorer.tx(H256::zero()).starts_with("https://scan.example/tx/0x0000"));
+        assert_eq!(registry_url(1), Some("https://etherscan.io"));
+        assert_eq!(registry_url(424_242_424_242), None);
+    }
+}
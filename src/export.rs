@@ -0,0 +1,216 @@
+//! Export of the contract's events to CSV or Parquet files, for loading into
+//! an analytics warehouse.
+//!
+//! The columns are the log's position (`block_number`, `transaction_hash`,
+//! `log_index`), the `event` name and then the parameters of the exported
+//! abi.json events, in ABI order; a parameter an event doesn't have is left
+//! empty. Parameter values are strings in the tool's usual formats
+//! (checksummed addresses, decimal integers, `0x` hex bytes), since uint256
+//! fits no Parquet integer type.
+
+use crate::calldata;
+use crate::contract::MYCONTRACT_ABI;
+use anyhow::Context;
+use ethers::prelude::*;
+#[cfg(feature = "parquet")]
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+#[cfg(feature = "parquet")]
+use parquet::file::properties::WriterProperties;
+#[cfg(feature = "parquet")]
+use parquet::file::writer::SerializedFileWriter;
+#[cfg(feature = "parquet")]
+use parquet::schema::parser::parse_message_type;
+use std::fs::File;
+use std::path::Path;
+#[cfg(feature = "parquet")]
+use std::path::PathBuf;
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Parquet,
+}
+
+impl Format {
+    /// The format a path's extension names: `.csv` or `.parquet`.
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Ok(Format::Csv),
+            Some("parquet") => Ok(Format::Parquet),
+            _ => anyhow::bail!("can't tell the format of {}; use a .csv or .parquet extension", path.display()),
+        }
+    }
+}
+
+/// The parameter columns for `events` (every abi.json event when empty):
+/// each parameter name once, in the order the events declare them.
+pub fn parameter_columns(events: &[String]) -> Vec<String> {
+    let mut columns: Vec<String> = Vec::new();
+    let selected = MYCONTRACT_ABI.events().filter(|event| events.is_empty() || events.contains(&event.name));
+    for input in selected.flat_map(|event| event.inputs.iter()) {
+        if !columns.contains(&input.name) {
+            columns.push(input.name.clone());
+        }
+    }
+    columns
+}
+
+/// One exported log: its position, then the event name and parameters.
+pub struct Row {
+    block_number: Option<i64>,
+    transaction_hash: Option<String>,
+    log_index: Option<i64>,
+    /// The event name and each parameter column's value, when decoded.
+    values: Vec<Option<String>>,
+}
+
+impl Row {
+    fn new(log: &Log, parameters: &[String]) -> Self {
+        let decoded = calldata::decode_event(log);
+        let mut values = vec![decoded.as_ref().map(|(name, _)| name.clone())];
+        values.extend(parameters.iter().map(|column| {
+            let (_, args) = decoded.as_ref()?;
+            let (_, value) = args.iter().find(|(name, _)| name == column)?;
+            Some(calldata::format_token(value))
+        }));
+        Self {
+            block_number: log.block_number.map(|block| block.as_u64() as i64),
+            transaction_hash: log.transaction_hash.map(|hash| format!("{:?}", hash)),
+            log_index: log.log_index.map(|index| index.as_u64() as i64),
+            values,
+        }
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        let number = |value: Option<i64>| value.map(|value| value.to_string()).unwrap_or_default();
+        let transaction_hash = self.transaction_hash.clone().unwrap_or_default();
+        let mut record = vec![number(self.block_number), transaction_hash, number(self.log_index)];
+        record.extend(self.values.iter().map(|value| value.clone().unwrap_or_default()));
+        record
+    }
+}
+
+/// Writes exported logs to a file. CSV rows are flushed as they come, so a
+/// followed stream can be tailed; Parquet is written out by [`finish`].
+///
+/// [`finish`]: Exporter::finish
+pub enum Exporter {
+    Csv { writer: Box<csv::Writer<File>>, parameters: Vec<String> },
+    #[cfg(feature = "parquet")]
+    Parquet { path: PathBuf, parameters: Vec<String>, rows: Vec<Row> },
+}
+
+impl Exporter {
+    /// Creates `path` for the given `parameters` columns (see
+    /// [`parameter_columns`]).
+    pub fn create(path: &Path, format: Format, parameters: Vec<String>) -> anyhow::Result<Self> {
+        match format {
+            Format::Csv => {
+                let mut writer =
+                    csv::Writer::from_path(path).with_context(|| format!("failed to create {}", path.display()))?;
+                let mut header = vec!["block_number", "transaction_hash", "log_index", "event"];
+                header.extend(parameters.iter().map(String::as_str));
+                writer.write_record(&header)?;
+                writer.flush()?;
+                Ok(Exporter::Csv { writer: Box::new(writer), parameters })
+            }
+            // Fail on an unwritable path now rather than after the fetch.
+            #[cfg(feature = "parquet")]
+            Format::Parquet => {
+                File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+                Ok(Exporter::Parquet { path: path.to_path_buf(), parameters, rows: Vec::new() })
+            }
+            #[cfg(not(feature = "parquet"))]
+            Format::Parquet => anyhow::bail!("Parquet export needs a build with the `parquet` feature"),
+        }
+    }
+
+    pub fn write(&mut self, log: &Log) -> anyhow::Result<()> {
+        match self {
+            Exporter::Csv { writer, parameters } => {
+                writer.write_record(Row::new(log, parameters).csv_record())?;
+                writer.flush()?;
+            }
+            #[cfg(feature = "parquet")]
+            Exporter::Parquet { parameters, rows, .. } => rows.push(Row::new(log, parameters)),
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Exporter::Csv { mut writer, .. } => Ok(writer.flush()?),
+            #[cfg(feature = "parquet")]
+            Exporter::Parquet { path, parameters, rows } => write_parquet(&path, &parameters, &rows),
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(path: &Path, parameters: &[String], rows: &[Row]) -> anyhow::Result<()> {
+    let mut message = String::from(
+        "message events { optional int64 block_number; optional binary transaction_hash (UTF8); \
+         optional int64 log_index; optional binary event (UTF8);",
+    );
+    for column in parameters {
+        message.push_str(&format!(" optional binary {} (UTF8);", column));
+    }
+    message.push_str(" }");
+    let schema = Arc::new(parse_message_type(&message)?);
+    let file = File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = SerializedFileWriter::new(file, schema, Arc::new(WriterProperties::builder().build()))?;
+
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match index {
+            0 | 2 => {
+                let values: Vec<Option<i64>> =
+                    rows.iter().map(|row| if index == 0 { row.block_number } else { row.log_index }).collect();
+                let (present, levels) = levels(&values);
+                column.typed::<Int64Type>().write_batch(&present, Some(&levels), None)?;
+            }
+            _ => {
+                let values: Vec<Option<String>> = rows
+                    .iter()
+                    .map(|row| match index {
+                        1 => row.transaction_hash.clone(),
+                        _ => row.values[index - 3].clone(),
+                    })
+                    .collect();
+                let (present, levels) = levels(&values);
+                let present: Vec<ByteArray> = present.into_iter().map(|value| ByteArray::from(value.as_str())).collect();
+                column.typed::<ByteArrayType>().write_batch(&present, Some(&levels), None)?;
+            }
+        }
+        column.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+/// The present values of an optional column, and its definition levels.
+#[cfg(feature = "parquet")]
+fn levels<T: Clone>(values: &[Option<T>]) -> (Vec<T>, Vec<i16>) {
+    let present = values.iter().flatten().cloned().collect();
+    let levels = values.iter().map(|value| value.is_some() as i16).collect();
+    (present, levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_follow_the_abi() {
+        let columns = parameter_columns(&["Locked".to_string(), "DepositedETH".to_string()]);
+        assert_eq!(columns, ["user", "amount", "token", "nonce"].map(String::from));
+        assert!(parameter_columns(&[]).contains(&"newOwner".to_string()));
+        assert_eq!(Format::from_path(Path::new("out.parquet")).unwrap(), Format::Parquet);
+        assert!(Format::from_path(Path::new("out.json")).is_err());
+    }
+}
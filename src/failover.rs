@@ -0,0 +1,343 @@
+//! The transport behind the read provider and the signing client: RPC_URL
+//! first, failing over to the RPC_URLS in order. Each endpoint has a circuit
+//! breaker: after RPC_BREAKER_FAILURES consecutive failures (default 5) its
+//! circuit opens and calls skip it for RPC_BREAKER_COOLDOWN (default `30s`),
+//! after which a single probe decides whether it closes again. So a dying
+//! endpoint costs one timeout per cooldown rather than one per call. Moving
+//! on to the next endpoint is a retry, and RPC_RETRY_BUDGET (default 60)
+//! caps retries per minute across all of them, so an outage everywhere fails
+//! fast instead of multiplying every call's timeouts.
+//!
+//! Only the endpoint failing counts: a connection error or a response that
+//! isn't JSON-RPC. A JSON-RPC error, such as a revert, is the node answering
+//! and is returned as is. A transaction is only sent again elsewhere when
+//! the first endpoint couldn't be connected to, since otherwise it may have
+//! been received.
+
+use crate::config::{self, env_var, Config};
+use crate::{style, tls};
+use anyhow::Context;
+use async_trait::async_trait;
+use ethers::providers::{Http, HttpClientError, JsonRpcClient, JsonRpcError, ProviderError, RpcError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The period the retry budget applies to.
+const BUDGET_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// Consecutive failures that open an endpoint's circuit.
+    pub failures: u32,
+    /// How long an open circuit refuses calls before a probe.
+    pub cooldown: Duration,
+    /// Retries allowed per minute, across every endpoint.
+    pub retries_per_minute: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self { failures: 5, cooldown: Duration::from_secs(30), retries_per_minute: 60 }
+    }
+}
+
+impl Settings {
+    /// The defaults, overridden by RPC_BREAKER_FAILURES, RPC_BREAKER_COOLDOWN
+    /// and RPC_RETRY_BUDGET.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut settings = Self::default();
+        if let Some(failures) = env_var("RPC_BREAKER_FAILURES") {
+            settings.failures = failures.parse().context("invalid RPC_BREAKER_FAILURES")?;
+            anyhow::ensure!(settings.failures > 0, "RPC_BREAKER_FAILURES must be at least 1");
+        }
+        if let Some(cooldown) = env_var("RPC_BREAKER_COOLDOWN") {
+            settings.cooldown = config::parse_duration(&cooldown).context("invalid RPC_BREAKER_COOLDOWN")?;
+        }
+        if let Some(budget) = env_var("RPC_RETRY_BUDGET") {
+            settings.retries_per_minute = budget.parse().context("invalid RPC_RETRY_BUDGET")?;
+        }
+        Ok(settings)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Circuit {
+    /// Calls go through; `failures` is the current run of failed ones.
+    Closed { failures: u32 },
+    /// Calls skip the endpoint until `until`.
+    Open { until: Instant },
+    /// A probe has been let through; its outcome closes or reopens the
+    /// circuit. One that never reports (its call was dropped) is replaced
+    /// by another after a cooldown.
+    HalfOpen { since: Instant },
+}
+
+#[derive(Debug)]
+pub struct Breaker {
+    circuit: Circuit,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl Breaker {
+    pub fn new(settings: &Settings) -> Self {
+        Self { circuit: Circuit::Closed { failures: 0 }, threshold: settings.failures, cooldown: settings.cooldown }
+    }
+
+    pub fn circuit(&self) -> Circuit {
+        self.circuit
+    }
+
+    /// Whether a call may go to the endpoint at `now`.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.circuit {
+            Circuit::Closed { .. } => true,
+            Circuit::Open { until } if now < until => false,
+            Circuit::HalfOpen { since } if now < since + self.cooldown => false,
+            _ => {
+                self.circuit = Circuit::HalfOpen { since: now };
+                true
+            }
+        }
+    }
+
+    pub fn succeeded(&mut self) {
+        self.circuit = Circuit::Closed { failures: 0 };
+    }
+
+    /// Records a failure at `now`; true if that opened the circuit.
+    pub fn failed(&mut self, now: Instant) -> bool {
+        self.circuit = match self.circuit {
+            Circuit::Closed { failures } if failures + 1 < self.threshold => Circuit::Closed { failures: failures + 1 },
+            _ => Circuit::Open { until: now + self.cooldown },
+        };
+        matches!(self.circuit, Circuit::Open { .. })
+    }
+}
+
+/// Retries left in the current minute.
+#[derive(Debug)]
+pub struct RetryBudget {
+    per_minute: u32,
+    window: Instant,
+    used: u32,
+}
+
+impl RetryBudget {
+    pub fn new(per_minute: u32, now: Instant) -> Self {
+        Self { per_minute, window: now, used: 0 }
+    }
+
+    /// Takes a retry at `now`, if any are left.
+    pub fn take(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.window) >= BUDGET_WINDOW {
+            (self.window, self.used) = (now, 0);
+        }
+        if self.used >= self.per_minute {
+            return false;
+        }
+        self.used += 1;
+        true
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FailoverError {
+    #[error(transparent)]
+    Http(#[from] HttpClientError),
+    #[error("every RPC endpoint's circuit is open after repeated failures; retrying after the cooldown")]
+    AllOpen,
+    #[error("invalid request parameters: {0}")]
+    Params(#[from] serde_json::Error),
+}
+
+impl RpcError for FailoverError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            FailoverError::Http(e) => e.as_error_response(),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            FailoverError::Http(e) => e.as_serde_error(),
+            FailoverError::Params(e) => Some(e),
+            FailoverError::AllOpen => None,
+        }
+    }
+}
+
+impl From<FailoverError> for ProviderError {
+    fn from(error: FailoverError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(error))
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    label: String,
+    http: Http,
+    breaker: Mutex<Breaker>,
+}
+
+#[derive(Debug)]
+struct Endpoints {
+    endpoints: Vec<Endpoint>,
+    budget: Mutex<RetryBudget>,
+}
+
+/// A JSON-RPC transport over several endpoints; see the module docs.
+#[derive(Clone, Debug)]
+pub struct Failover(Arc<Endpoints>);
+
+impl Failover {
+    /// Endpoints `urls`, tried in order.
+    pub fn new(urls: &[String], settings: Settings) -> anyhow::Result<Self> {
+        anyhow::ensure!(!urls.is_empty(), "no RPC endpoints");
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                let label = config::rpc_label(url);
+                let parsed: reqwest::Url = url.parse().with_context(|| format!("invalid RPC URL {}", label))?;
+                Ok(Endpoint {
+                    label,
+                    http: Http::new_with_client(parsed, tls::client()?),
+                    breaker: Mutex::new(Breaker::new(&settings)),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let budget = Mutex::new(RetryBudget::new(settings.retries_per_minute, Instant::now()));
+        Ok(Self(Arc::new(Endpoints { endpoints, budget })))
+    }
+
+    /// RPC_URL and RPC_URLS, with the settings from the environment.
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        Self::new(&config::rpc_urls(config), Settings::from_env()?)
+    }
+
+    /// Each endpoint's label and circuit.
+    pub fn circuits(&self) -> Vec<(String, Circuit)> {
+        let circuit = |endpoint: &Endpoint| endpoint.breaker.lock().expect("breaker lock").circuit();
+        self.0.endpoints.iter().map(|endpoint| (endpoint.label.clone(), circuit(endpoint))).collect()
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for Failover {
+    type Error = FailoverError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, FailoverError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = serde_json::to_value(params)?;
+        let mut last = None;
+        for endpoint in &self.0.endpoints {
+            if !endpoint.breaker.lock().expect("breaker lock").allow(Instant::now()) {
+                continue;
+            }
+            if last.is_some() && !self.0.budget.lock().expect("budget lock").take(Instant::now()) {
+                eprintln!("{}", style::warn("RPC retry budget spent; not failing over"));
+                break;
+            }
+            match endpoint.http.request(method, &params).await {
+                Err(e) if is_outage(&e) => {
+                    if endpoint.breaker.lock().expect("breaker lock").failed(Instant::now()) {
+                        let message = format!("{} keeps failing ({}); skipping it for a while", endpoint.label, e);
+                        eprintln!("{}", style::warn(&message));
+                    }
+                    if method.starts_with("eth_send") && !is_connect(&e) {
+                        return Err(e.into());
+                    }
+                    last = Some(e);
+                }
+                result => {
+                    endpoint.breaker.lock().expect("breaker lock").succeeded();
+                    return Ok(result?);
+                }
+            }
+        }
+        Err(last.map(FailoverError::Http).unwrap_or(FailoverError::AllOpen))
+    }
+}
+
+/// Whether `error` is the endpoint failing rather than the node answering.
+fn is_outage(error: &HttpClientError) -> bool {
+    matches!(error, HttpClientError::ReqwestError(_) | HttpClientError::SerdeJson { .. })
+}
+
+/// Whether the request never reached the endpoint.
+fn is_connect(error: &HttpClientError) -> bool {
+    matches!(error, HttpClientError::ReqwestError(e) if e.is_connect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaking_circuits() {
+        let settings = Settings { failures: 2, cooldown: Duration::from_secs(30), retries_per_minute: 2 };
+        let start = Instant::now();
+        let mut breaker = Breaker::new(&settings);
+        assert!(!breaker.failed(start));
+        assert!(breaker.allow(start));
+        assert!(breaker.failed(start));
+        assert!(!breaker.allow(start + Duration::from_secs(29)));
+
+        // One probe after the cooldown; a failed probe reopens at once.
+        let later = start + Duration::from_secs(30);
+        assert!(breaker.allow(later));
+        assert_eq!(breaker.circuit(), Circuit::HalfOpen { since: later });
+        assert!(!breaker.allow(later));
+        assert!(breaker.failed(later));
+        assert_eq!(breaker.circuit(), Circuit::Open { until: later + Duration::from_secs(30) });
+        assert!(breaker.allow(later + Duration::from_secs(30)));
+        breaker.succeeded();
+        assert_eq!(breaker.circuit(), Circuit::Closed { failures: 0 });
+
+        let mut budget = RetryBudget::new(settings.retries_per_minute, start);
+        assert!(budget.take(start) && budget.take(start));
+        assert!(!budget.take(start + Duration::from_secs(59)));
+        assert!(budget.take(start + Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn failing_over() {
+        use crate::mock_node::{self, MockNode, Reply};
+        use ethers::providers::{Middleware, Provider};
+        use ethers::types::Bytes;
+
+        let backup = MockNode::start(mock_node::idle).await;
+        let provider = |urls: &[String]| Provider::new(Failover::new(urls, Settings::default()).unwrap());
+
+        // An endpoint that can't be reached is skipped, even for a send.
+        let unreachable = provider(&[mock_node::closed_url().await, backup.url.clone()]);
+        assert_eq!(unreachable.get_block_number().await.unwrap(), 100.into());
+        let sent = unreachable.send_raw_transaction(Bytes::from(vec![1])).await.unwrap_err();
+        assert!(sent.to_string().contains("does not exist"));
+        assert_eq!(backup.calls("eth_sendRawTransaction").len(), 1);
+
+        // One that answers garbage is failed over for reads, but a send it
+        // may have received isn't repeated elsewhere.
+        let broken = MockNode::start(|_, _| Reply::Garbage).await;
+        let garbled = provider(&[broken.url.clone(), backup.url.clone()]);
+        assert_eq!(garbled.get_block_number().await.unwrap(), 100.into());
+        assert!(garbled.send_raw_transaction(Bytes::from(vec![1])).await.is_err());
+        assert_eq!(broken.calls("eth_sendRawTransaction").len(), 1);
+        assert_eq!(backup.calls("eth_sendRawTransaction").len(), 1);
+
+        // A JSON-RPC error is the node's answer, not an outage.
+        let reverting = MockNode::start(|_, _| Reply::Error("execution reverted".to_string())).await;
+        let answered = provider(&[reverting.url.clone(), backup.url.clone()]);
+        let error = answered.get_block_number().await.unwrap_err();
+        assert_eq!(error.as_error_response().unwrap().message, "execution reverted");
+        assert_eq!(backup.calls("eth_blockNumber").len(), 2);
+        assert_eq!(answered.provider().as_ref().circuits()[0].1, Circuit::Closed { failures: 0 });
+    }
+}
@@ -0,0 +1,109 @@
+//! Testnet faucet requests, so CI runs can fund their own sender wallet.
+//!
+//! The public faucets of Sepolia, Holesky and Amoy sit behind captchas or
+//! logins and can't be called unattended, so requests go to the faucet API
+//! at FAUCET_URL: an internal faucet, or a provider's faucet API. The
+//! request is a JSON POST of FAUCET_TEMPLATE with `{{address}}` and
+//! `{{chain_id}}` filled in (default `{"address": ..., "chainId": ...}`);
+//! FAUCET_TOKEN, a secret setting, is sent as a bearer token.
+
+use crate::config::{self, env_var};
+use anyhow::Context;
+use ethers::prelude::*;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TEMPLATE: &str = r#"{"address":"{{address}}","chainId":{{chain_id}}}"#;
+
+/// Testnets with well-known public faucets, by chain id.
+const KNOWN_TESTNETS: &[(u64, &str)] = &[(11155111, "Sepolia"), (17000, "Holesky"), (80002, "Polygon Amoy")];
+
+/// The name of a testnet with a well-known public faucet.
+pub fn testnet_name(chain_id: u64) -> Option<&'static str> {
+    KNOWN_TESTNETS.iter().find(|(id, _)| *id == chain_id).map(|(_, name)| *name)
+}
+
+pub struct Faucet {
+    url: String,
+    token: Option<String>,
+    template: String,
+}
+
+impl Faucet {
+    /// Reads FAUCET_URL, FAUCET_TOKEN and FAUCET_TEMPLATE.
+    pub fn from_env(chain_id: u64) -> anyhow::Result<Self> {
+        config::ensure_testnet(chain_id)?;
+        let url = env_var("FAUCET_URL").with_context(|| match testnet_name(chain_id) {
+            Some(name) => format!("set FAUCET_URL; the public {} faucets can't be called without a browser", name),
+            None => format!("set FAUCET_URL to a faucet API for chain {}", chain_id),
+        })?;
+        let token = match env_var("FAUCET_TOKEN") {
+            Some(_) => Some(config::secret("FAUCET_TOKEN")?),
+            None => None,
+        };
+        let template = env_var("FAUCET_TEMPLATE").unwrap_or_else(|| DEFAULT_TEMPLATE.to_string());
+        Ok(Self { url, token, template })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Asks the faucet to fund `address`, returning its JSON response.
+    pub async fn request(&self, chain_id: u64, address: Address) -> anyhow::Result<Value> {
+        let body = render(&self.template, chain_id, address);
+        let body: Value = serde_json::from_str(&body).context("FAUCET_TEMPLATE does not render to valid JSON")?;
+        let mut request = reqwest::Client::new().post(&self.url).json(&body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.with_context(|| format!("faucet {} unreachable", self.url))?;
+        let status = response.status();
+        let text = response.text().await?;
+        anyhow::ensure!(status.is_success(), "faucet refused the request ({}): {}", status, text.trim());
+        Ok(serde_json::from_str(&text).unwrap_or(Value::String(text)))
+    }
+}
+
+/// Polls `address`'s balance until it rises above `before`, returning the
+/// new balance, or `None` once `timeout` has passed.
+pub async fn wait_for_funds<M: Middleware>(
+    client: &M,
+    address: Address,
+    before: U256,
+    timeout: Duration,
+) -> anyhow::Result<Option<U256>>
+where
+    M::Error: 'static,
+{
+    let started = Instant::now();
+    loop {
+        let balance = client.get_balance(address, None).await?;
+        if balance > before {
+            return Ok(Some(balance));
+        }
+        if started.elapsed() >= timeout {
+            return Ok(None);
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+fn render(template: &str, chain_id: u64, address: Address) -> String {
+    template
+        .replace("{{address}}", &format!("{:?}", address))
+        .replace("{{chain_id}}", &chain_id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        let body: Value = serde_json::from_str(&render(DEFAULT_TEMPLATE, 17000, Address::repeat_byte(0x11))).unwrap();
+        assert_eq!(body["address"], format!("0x{}", "11".repeat(20)));
+        assert_eq!(body["chainId"], 17000);
+        assert_eq!(testnet_name(80002), Some("Polygon Amoy"));
+    }
+}
@@ -0,0 +1,438 @@
+//! Fee suggestion from eth_feeHistory: the priority fee is a chosen reward
+//! percentile over recent blocks and the max fee adds a multiple of the next
+//! block's base fee, rather than trusting a single eth_gasPrice snapshot.
+//! Chains whose fee history is unreliable can use the node's suggestion
+//! instead, and chains without EIP-1559 get legacy transactions.
+
+use crate::config;
+use crate::profile::{self, FeeOracle, TransactionType};
+use crate::units::{self, Rounding};
+use crate::{print_ok, print_warn};
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::format_units;
+use std::time::{Duration, Instant};
+
+/// Parameters of the fee algorithm.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeModel {
+    /// Number of recent blocks sampled.
+    pub blocks: u64,
+    /// Reward percentile (0-100) taken from each sampled block.
+    pub percentile: f64,
+    /// Headroom over the next block's base fee, as a multiplier.
+    pub base_fee_multiplier: f64,
+    pub oracle: FeeOracle,
+    pub transaction_type: TransactionType,
+    /// Highest max fee per gas suggested, in wei.
+    pub max_fee: Option<U256>,
+    /// Highest priority fee per gas suggested, in wei.
+    pub max_priority_fee: Option<U256>,
+    /// Whether transactions carry EIP-155 replay protection; see
+    /// [`profile::replay_protected`].
+    pub eip155: bool,
+}
+
+impl Default for FeeModel {
+    fn default() -> Self {
+        Self {
+            blocks: 10,
+            percentile: 50.0,
+            base_fee_multiplier: 2.0,
+            oracle: FeeOracle::FeeHistory,
+            transaction_type: TransactionType::Eip1559,
+            max_fee: None,
+            max_priority_fee: None,
+            eip155: true,
+        }
+    }
+}
+
+/// A suggested set of EIP-1559 fees, in wei.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fees {
+    pub base_fee: U256,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+}
+
+impl FeeModel {
+    /// The model for `chain_id`: the defaults (10 blocks, 50th percentile,
+    /// 2x base fee, EIP-1559, no caps), overridden by the active profile's
+    /// `gas` section, then by TRANSACTION_TYPE, FEE_HISTORY_BLOCKS and
+    /// FEE_PERCENTILE.
+    pub fn from_env(chain_id: u64) -> anyhow::Result<Self> {
+        let mut model = Self::default();
+        if let Some(profile) = profile::active(chain_id)? {
+            let gas = profile.gas;
+            if profile.eip1559 == Some(false) {
+                anyhow::ensure!(
+                    gas.transaction_type != Some(TransactionType::Eip1559),
+                    "the profile sets eip1559 = false, but gas.transaction_type = \"eip1559\""
+                );
+                model.transaction_type = TransactionType::Legacy;
+            }
+            model.blocks = gas.history_blocks.unwrap_or(model.blocks);
+            model.percentile = gas.percentile.unwrap_or(model.percentile);
+            model.base_fee_multiplier = gas.base_fee_multiplier.unwrap_or(model.base_fee_multiplier);
+            model.oracle = gas.oracle.unwrap_or(model.oracle);
+            model.transaction_type = gas.transaction_type.unwrap_or(model.transaction_type);
+            model.max_fee = gwei(gas.max_fee_gwei, "max_fee_gwei")?;
+            model.max_priority_fee = gwei(gas.max_priority_fee_gwei, "max_priority_fee_gwei")?;
+            model.eip155 = profile.eip155.unwrap_or(model.eip155);
+        }
+        if let Some(transaction_type) = config::env_var("TRANSACTION_TYPE") {
+            model.transaction_type = transaction_type.parse().context("invalid TRANSACTION_TYPE")?;
+        }
+        if let Some(blocks) = config::env_var("FEE_HISTORY_BLOCKS") {
+            model.blocks = blocks.parse().context("invalid FEE_HISTORY_BLOCKS")?;
+        }
+        if let Some(percentile) = config::env_var("FEE_PERCENTILE") {
+            model.percentile = percentile.parse().context("invalid FEE_PERCENTILE")?;
+        }
+        model.validate()?;
+        Ok(model)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.blocks > 0, "fee history window must be at least one block");
+        anyhow::ensure!((0.0..=100.0).contains(&self.percentile), "fee percentile must be between 0 and 100");
+        anyhow::ensure!(self.base_fee_multiplier >= 1.0, "base fee multiplier must be at least 1");
+        // Typed transactions always commit to the chain id.
+        anyhow::ensure!(
+            self.eip155 || self.transaction_type == TransactionType::Legacy,
+            "eip155 = false needs legacy transactions"
+        );
+        Ok(())
+    }
+
+    /// Samples eth_feeHistory and computes fees: the priority fee is the
+    /// median of the sampled blocks' percentile rewards, and the max fee is
+    /// the next block's base fee times the multiplier, plus the priority fee.
+    /// With the node oracle, the priority fee is the node's
+    /// eth_maxPriorityFeePerGas (none if unsupported) and the rest of its
+    /// eth_gasPrice stands in for the base fee. Either way the caps apply.
+    pub async fn suggest<M: Middleware>(&self, client: &M) -> anyhow::Result<Fees>
+    where
+        M::Error: 'static,
+    {
+        if self.oracle == FeeOracle::Node {
+            let gas_price = client.get_gas_price().await.context("eth_gasPrice failed")?;
+            let priority_fee: U256 =
+                client.provider().request("eth_maxPriorityFeePerGas", ()).await.unwrap_or_default();
+            let priority_fee = priority_fee.min(gas_price);
+            return Ok(self.fees(gas_price - priority_fee, priority_fee));
+        }
+        let history = client
+            .fee_history(self.blocks, BlockNumber::Latest, &[self.percentile])
+            .await
+            .context("eth_feeHistory failed")?;
+        self.fees_at(&history, 0)
+    }
+
+    /// Samples eth_feeHistory once for every [`TIERS`] percentile, returning
+    /// the sampled base fees alongside the fees each tier suggests.
+    pub async fn report<M: Middleware>(&self, client: &M) -> anyhow::Result<FeeReport>
+    where
+        M::Error: 'static,
+    {
+        let percentiles: Vec<f64> = TIERS.iter().map(|(_, percentile)| *percentile).collect();
+        let history = client
+            .fee_history(self.blocks, BlockNumber::Latest, &percentiles)
+            .await
+            .context("eth_feeHistory failed")?;
+        let tiers = TIERS
+            .iter()
+            .enumerate()
+            .map(|(column, (name, _))| Ok((*name, self.fees_at(&history, column)?)))
+            .collect::<anyhow::Result<_>>()?;
+        let tips = history.reward.iter().filter_map(|block| block.get(1).copied()).collect();
+        Ok(FeeReport { oldest_block: history.oldest_block, base_fees: history.base_fee_per_gas, tips, tiers })
+    }
+
+    /// Fees from the `column`th reward percentile of `history`.
+    fn fees_at(&self, history: &FeeHistory, column: usize) -> anyhow::Result<Fees> {
+        // The last entry is the base fee of the block after the newest one.
+        let base_fee = *history.base_fee_per_gas.last().context("eth_feeHistory returned no base fees")?;
+        let mut rewards: Vec<U256> = history.reward.iter().filter_map(|block| block.get(column).copied()).collect();
+        anyhow::ensure!(!rewards.is_empty(), "eth_feeHistory returned no rewards");
+        rewards.sort();
+        Ok(self.fees(base_fee, rewards[rewards.len() / 2]))
+    }
+
+    /// Fees over `base_fee` with `priority_fee`, within the caps.
+    fn fees(&self, base_fee: U256, priority_fee: U256) -> Fees {
+        // Scale in hundredths so fractional multipliers survive integer math.
+        let multiplier = U256::from((self.base_fee_multiplier * 100.0).round() as u64);
+        let mut max_fee_per_gas = base_fee * multiplier / 100 + priority_fee;
+        let mut max_priority_fee_per_gas = priority_fee;
+        if let Some(cap) = self.max_fee {
+            max_fee_per_gas = max_fee_per_gas.min(cap);
+        }
+        if let Some(cap) = self.max_priority_fee {
+            max_priority_fee_per_gas = max_priority_fee_per_gas.min(cap);
+        }
+        max_priority_fee_per_gas = max_priority_fee_per_gas.min(max_fee_per_gas);
+        Fees { base_fee, max_fee_per_gas, max_priority_fee_per_gas }
+    }
+
+    /// Turns `tx` into a legacy or EIP-2930 transaction if the model sends
+    /// those. Call it before applying fees, so the gas price is set on the
+    /// new form.
+    pub fn set_transaction_type(&self, tx: &mut TypedTransaction) {
+        let TypedTransaction::Eip1559(request) = &*tx else {
+            return;
+        };
+        if self.transaction_type == TransactionType::Eip1559 {
+            return;
+        }
+        let legacy = TransactionRequest {
+            from: request.from,
+            to: request.to.clone(),
+            gas: request.gas,
+            gas_price: None,
+            value: request.value,
+            data: request.data.clone(),
+            nonce: request.nonce,
+            chain_id: request.chain_id,
+        };
+        *tx = match self.transaction_type {
+            TransactionType::Eip2930 => Eip2930TransactionRequest::new(legacy, request.access_list.clone()).into(),
+            _ => legacy.into(),
+        };
+    }
+}
+
+/// A gwei setting named `name` in wei.
+fn gwei(amount: Option<f64>, name: &str) -> anyhow::Result<Option<U256>> {
+    let Some(amount) = amount else {
+        return Ok(None);
+    };
+    anyhow::ensure!(amount > 0.0, "gas {} must be positive", name);
+    Ok(Some(units::from_f64(amount, 9).with_context(|| format!("invalid gas {}", name))?))
+}
+
+/// Speed tiers fees are recommended for, with the reward percentile each
+/// takes its priority fee from.
+pub const TIERS: &[(&str, f64)] = &[("slow", 10.0), ("standard", 50.0), ("fast", 90.0)];
+
+/// Recent fee history and the fees suggested for each of [`TIERS`].
+pub struct FeeReport {
+    pub oldest_block: U256,
+    /// Base fee of every sampled block, then of the next block.
+    pub base_fees: Vec<U256>,
+    /// The median (standard tier) reward of every sampled block.
+    pub tips: Vec<U256>,
+    pub tiers: Vec<(&'static str, Fees)>,
+}
+
+/// Renders `values` as a one-line bar chart, scaled between their minimum
+/// and maximum.
+pub fn sparkline(values: &[U256]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let (Some(min), Some(max)) = (values.iter().min(), values.iter().max()) else {
+        return String::new();
+    };
+    let range = *max - *min;
+    values
+        .iter()
+        .map(|value| match range.is_zero() {
+            true => BARS[BARS.len() / 2],
+            false => BARS[((*value - *min) * (BARS.len() - 1) / range).as_usize()],
+        })
+        .collect()
+}
+
+impl Fees {
+    /// Sets these fees on `tx`; a legacy transaction gets the max fee as its
+    /// gas price.
+    pub fn apply(&self, tx: &mut TypedTransaction) {
+        match tx {
+            TypedTransaction::Eip1559(tx) => {
+                tx.max_fee_per_gas = Some(self.max_fee_per_gas);
+                tx.max_priority_fee_per_gas = Some(self.max_priority_fee_per_gas);
+            }
+            _ => {
+                tx.set_gas_price(self.max_fee_per_gas);
+            }
+        }
+    }
+}
+
+/// Holds broadcasts back until the base fee drops below a threshold, for
+/// sends that are cheap to delay but expensive at peak fees.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GasGate {
+    /// Base fee, in wei, that must be undercut.
+    pub threshold: U256,
+    /// How long to wait at most; `None` waits indefinitely.
+    pub max_wait: Option<Duration>,
+}
+
+impl GasGate {
+    /// Polls the latest block's base fee every `interval` until it is below
+    /// the threshold. Returns `false` if `max_wait` passes first. Chains
+    /// without a base fee are judged by eth_gasPrice instead.
+    pub async fn wait<M: Middleware>(&self, client: &M, interval: Duration) -> anyhow::Result<bool>
+    where
+        M::Error: 'static,
+    {
+        let started = Instant::now();
+        let mut announced = false;
+        loop {
+            let block = client.get_block(BlockNumber::Latest).await?.context("node returned no latest block")?;
+            let base_fee = match block.base_fee_per_gas {
+                Some(base_fee) => base_fee,
+                None => client.get_gas_price().await?,
+            };
+            if base_fee < self.threshold {
+                if announced {
+                    print_ok!("Base fee {} Gwei is below {} Gwei",
+                        format_units(base_fee, "gwei")?,
+                        format_units(self.threshold, "gwei")?);
+                }
+                return Ok(true);
+            }
+            if self.max_wait.is_some_and(|max_wait| started.elapsed() >= max_wait) {
+                print_warn!("Base fee still {} Gwei, not below {} Gwei, after {}s",
+                    format_units(base_fee, "gwei")?,
+                    format_units(self.threshold, "gwei")?,
+                    started.elapsed().as_secs());
+                return Ok(false);
+            }
+            if !announced {
+                println!("Waiting for the base fee to drop below {} Gwei (currently {} Gwei)",
+                    format_units(self.threshold, "gwei")?,
+                    format_units(base_fee, "gwei")?);
+                announced = true;
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/// Parses a gas price such as `30gwei`, `1.5gwei` or `2000000000wei`; a bare
+/// number is gwei.
+pub fn parse_gas_price(input: &str) -> anyhow::Result<U256> {
+    let input = input.trim();
+    let split = input.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split);
+    anyhow::ensure!(!amount.trim().is_empty(), "invalid gas price {:?}: no amount", input);
+    let unit = match unit.to_ascii_lowercase().as_str() {
+        "" | "gwei" => 9,
+        "wei" => 0,
+        "ether" | "eth" => 18,
+        other => anyhow::bail!("invalid gas price {:?}: unknown unit {:?}", input, other),
+    };
+    units::parse_decimal(amount, unit, Rounding::Exact).with_context(|| format!("invalid gas price {:?}", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::MockProvider;
+    use ethers::types::transaction::eip2930::{AccessList, AccessListItem};
+
+    fn gwei(amount: u64) -> U256 {
+        U256::from(amount) * 1_000_000_000u64
+    }
+
+    fn history(base_fees: &[u64], rewards: &[u64]) -> FeeHistory {
+        FeeHistory {
+            base_fee_per_gas: base_fees.iter().map(|fee| gwei(*fee)).collect(),
+            gas_used_ratio: vec![0.5; rewards.len()],
+            oldest_block: 100.into(),
+            reward: rewards.iter().map(|reward| vec![gwei(*reward)]).collect(),
+        }
+    }
+
+    async fn suggest(model: &FeeModel, history: FeeHistory) -> anyhow::Result<Fees> {
+        let mock = MockProvider::new();
+        mock.push(history).unwrap();
+        model.suggest(&Provider::new(mock)).await
+    }
+
+    #[tokio::test]
+    async fn fees_from_history() {
+        let model = FeeModel::default();
+        // The median reward, plus twice the next block's base fee.
+        let fees = suggest(&model, history(&[10, 11, 20], &[3, 1, 2])).await.unwrap();
+        assert_eq!(fees, Fees { base_fee: gwei(20), max_fee_per_gas: gwei(42), max_priority_fee_per_gas: gwei(2) });
+        // With an even count the upper middle reward is taken.
+        let fees = suggest(&model, history(&[10], &[4, 1, 3, 2])).await.unwrap();
+        assert_eq!(fees.max_priority_fee_per_gas, gwei(3));
+        // Fractional multipliers survive the integer math.
+        let model = FeeModel { base_fee_multiplier: 1.25, ..Default::default() };
+        let fees = suggest(&model, history(&[8], &[1])).await.unwrap();
+        assert_eq!(fees.max_fee_per_gas, gwei(11));
+
+        assert!(suggest(&model, history(&[], &[1])).await.is_err());
+        assert!(suggest(&model, history(&[8], &[])).await.is_err());
+
+        // The caps bound both fees, and the priority fee never exceeds the max fee.
+        let model = FeeModel { max_fee: Some(gwei(30)), max_priority_fee: Some(gwei(1)), ..Default::default() };
+        let fees = suggest(&model, history(&[20], &[3])).await.unwrap();
+        assert_eq!((fees.max_fee_per_gas, fees.max_priority_fee_per_gas), (gwei(30), gwei(1)));
+        let model = FeeModel { max_fee: Some(gwei(2)), ..Default::default() };
+        let fees = suggest(&model, history(&[20], &[3])).await.unwrap();
+        assert_eq!((fees.max_fee_per_gas, fees.max_priority_fee_per_gas), (gwei(2), gwei(2)));
+    }
+
+    #[test]
+    fn validation() {
+        assert!(FeeModel::default().validate().is_ok());
+        assert!(FeeModel { blocks: 0, ..Default::default() }.validate().is_err());
+        assert!(FeeModel { percentile: 100.5, ..Default::default() }.validate().is_err());
+        assert!(FeeModel { base_fee_multiplier: 0.9, ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn sparklines() {
+        assert_eq!(sparkline(&[]), "");
+        assert_eq!(sparkline(&[gwei(5), gwei(5)]), "▅▅");
+        let values: Vec<U256> = [0u64, 1, 2, 3, 4, 5, 6, 7].into_iter().map(gwei).collect();
+        assert_eq!(sparkline(&values), "▁▂▃▄▅▆▇█");
+        assert_eq!(sparkline(&[gwei(10), gwei(30), gwei(20)]), "▁█▄");
+    }
+
+    #[test]
+    fn gas_prices() {
+        assert_eq!(parse_gas_price("30gwei").unwrap(), gwei(30));
+        assert_eq!(parse_gas_price("30").unwrap(), gwei(30));
+        assert_eq!(parse_gas_price("1.5 Gwei").unwrap(), gwei(3) / 2);
+        assert_eq!(parse_gas_price("2000wei").unwrap(), 2000.into());
+        assert!(parse_gas_price("30 sats").is_err());
+        assert!(parse_gas_price("gwei").is_err());
+    }
+
+    #[test]
+    fn applying_fees() {
+        let fees = Fees { base_fee: gwei(20), max_fee_per_gas: gwei(42), max_priority_fee_per_gas: gwei(2) };
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new().value(5).into();
+        fees.apply(&mut tx);
+        assert!(matches!(&tx, TypedTransaction::Eip1559(request)
+            if request.max_fee_per_gas == Some(gwei(42)) && request.max_priority_fee_per_gas == Some(gwei(2))));
+
+        let mut tx: TypedTransaction = TransactionRequest::new().value(5).into();
+        fees.apply(&mut tx);
+        assert_eq!(tx.gas_price(), Some(gwei(42)));
+        assert_eq!(tx.value(), Some(&5.into()));
+
+        let legacy = FeeModel { transaction_type: TransactionType::Legacy, ..Default::default() };
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new().value(5).nonce(7).into();
+        legacy.set_transaction_type(&mut tx);
+        fees.apply(&mut tx);
+        assert!(matches!(&tx, TypedTransaction::Legacy(request)
+            if request.gas_price == Some(gwei(42)) && request.nonce == Some(7.into())));
+
+        let access_list = AccessList(vec![AccessListItem { address: Address::repeat_byte(1), storage_keys: vec![] }]);
+        let eip2930 = FeeModel { transaction_type: TransactionType::Eip2930, ..Default::default() };
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new().access_list(access_list.clone()).into();
+        eip2930.set_transaction_type(&mut tx);
+        fees.apply(&mut tx);
+        assert!(matches!(&tx, TypedTransaction::Eip2930(request)
+            if request.tx.gas_price == Some(gwei(42)) && request.access_list == access_list));
+        assert!(FeeModel { eip155: false, ..eip2930 }.validate().is_err());
+    }
+}
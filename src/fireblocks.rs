@@ -0,0 +1,208 @@
+//! Fireblocks custody: transaction hashes signed by a vault account through
+//! the RAW signing API. A request is subject to the workspace's Transaction
+//! Authorization Policy like any other, so it may wait on approvers; it is
+//! polled until it is signed, refused or FIREBLOCKS_APPROVAL_TIMEOUT
+//! (default 1h) passes.
+//!
+//! Settings: FIREBLOCKS_API_KEY, FIREBLOCKS_API_SECRET (a secret setting:
+//! the API user's RSA private key, PEM), FIREBLOCKS_VAULT_ACCOUNT_ID,
+//! FIREBLOCKS_ASSET_ID (default `ETH`) and FIREBLOCKS_API_URL (default
+//! `https://api.fireblocks.io`).
+
+use crate::config::{self, env_var, var};
+use anyhow::Context;
+use ethers::prelude::*;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often a pending request is polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Fireblocks {
+    url: String,
+    api_key: String,
+    secret: EncodingKey,
+    vault_account_id: String,
+    asset_id: String,
+    timeout: Duration,
+    client: reqwest::Client,
+}
+
+/// Where a signing request stands.
+#[derive(Debug, PartialEq)]
+enum Progress {
+    /// Still in the workflow, with its status, e.g. `PENDING_AUTHORIZATION`.
+    Pending(String),
+    Signed(Signature),
+    /// Cancelled, rejected, blocked or failed, with the reason.
+    Refused(String),
+}
+
+impl Fireblocks {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let secret = config::secret("FIREBLOCKS_API_SECRET")?;
+        let secret = EncodingKey::from_rsa_pem(secret.as_bytes())
+            .context("FIREBLOCKS_API_SECRET is not an RSA private key in PEM format")?;
+        let timeout = match env_var("FIREBLOCKS_APPROVAL_TIMEOUT") {
+            Some(timeout) => config::parse_duration(&timeout).context("invalid FIREBLOCKS_APPROVAL_TIMEOUT")?,
+            None => Duration::from_secs(3600),
+        };
+        Ok(Self {
+            url: env_var("FIREBLOCKS_API_URL").unwrap_or_else(|| "https://api.fireblocks.io".to_string()),
+            api_key: var("FIREBLOCKS_API_KEY")?,
+            secret,
+            vault_account_id: var("FIREBLOCKS_VAULT_ACCOUNT_ID")?,
+            asset_id: env_var("FIREBLOCKS_ASSET_ID").unwrap_or_else(|| "ETH".to_string()),
+            timeout,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Has the vault account sign `hash`, with `note` shown to approvers,
+    /// and waits for the signature. The request is identified by the hash,
+    /// so asking again for the same one resumes waiting on the earlier
+    /// request rather than sending approvers a second one.
+    pub async fn sign_hash(&self, hash: H256, note: &str) -> anyhow::Result<Signature> {
+        let external_id = format!("{:?}", hash);
+        let id = match self.find(&external_id).await? {
+            Some(id) => {
+                println!("Resuming Fireblocks request {}", id);
+                id
+            }
+            None => {
+                let body = json!({
+                    "operation": "RAW",
+                    "assetId": self.asset_id,
+                    "source": { "type": "VAULT_ACCOUNT", "id": self.vault_account_id },
+                    "note": note,
+                    "externalTxId": external_id,
+                    "extraParameters": {
+                        "rawMessageData": { "messages": [{ "content": hex::encode(hash) }] },
+                    },
+                });
+                let created = self.request(Method::POST, "/v1/transactions", Some(&body)).await?;
+                let id = created["id"].as_str().context("Fireblocks returned no request id")?.to_string();
+                println!("Fireblocks request {} submitted", id);
+                id
+            }
+        };
+
+        let started = Instant::now();
+        let mut last = String::new();
+        loop {
+            let transaction = self.request(Method::GET, &format!("/v1/transactions/{}", id), None).await?;
+            match progress(&transaction)? {
+                Progress::Signed(signature) => return Ok(signature),
+                Progress::Refused(reason) => anyhow::bail!("Fireblocks request {} was {}", id, reason),
+                Progress::Pending(status) => {
+                    if status != last {
+                        println!("Fireblocks request {}: {}", id, status);
+                        last = status;
+                    }
+                }
+            }
+            anyhow::ensure!(
+                started.elapsed() < self.timeout,
+                "Fireblocks request {} is still {} after {}s; run again to keep waiting",
+                id,
+                last,
+                self.timeout.as_secs()
+            );
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// The id of the request made earlier under `external_id`, if any.
+    async fn find(&self, external_id: &str) -> anyhow::Result<Option<String>> {
+        let path = format!("/v1/transactions/external_tx_id/{}", external_id);
+        match self.request(Method::GET, &path, None).await {
+            Ok(transaction) => Ok(transaction["id"].as_str().map(str::to_string)),
+            Err(e) => match e.downcast_ref::<reqwest::Error>().and_then(reqwest::Error::status) {
+                Some(StatusCode::NOT_FOUND) => Ok(None),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Makes an authenticated API call, returning its JSON response.
+    async fn request(&self, method: Method, path: &str, body: Option<&Value>) -> anyhow::Result<Value> {
+        let body = body.map(Value::to_string).unwrap_or_default();
+        let mut request = self
+            .client
+            .request(method.clone(), format!("{}{}", self.url, path))
+            .header("X-API-Key", &self.api_key)
+            .bearer_auth(self.token(path, &body)?);
+        if method != Method::GET {
+            request = request.header(reqwest::header::CONTENT_TYPE, "application/json").body(body);
+        }
+        let response = request.send().await.with_context(|| format!("Fireblocks API {} unreachable", self.url))?;
+        Ok(response.error_for_status()?.json().await?)
+    }
+
+    /// The JWT authenticating a call to `path` with `body`, signed with the
+    /// API secret as Fireblocks requires.
+    fn token(&self, path: &str, body: &str) -> anyhow::Result<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let claims = json!({
+            "uri": path,
+            "nonce": ethers::core::rand::random::<u64>().to_string(),
+            "iat": now,
+            "exp": now + 25,
+            "sub": self.api_key,
+            "bodyHash": hex::encode(Sha256::digest(body.as_bytes())),
+        });
+        Ok(jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.secret)?)
+    }
+}
+
+/// Reads a request's progress from its transaction object.
+fn progress(transaction: &Value) -> anyhow::Result<Progress> {
+    let status = transaction["status"].as_str().context("Fireblocks returned a request without a status")?;
+    match status {
+        "COMPLETED" => {
+            let signature = &transaction["signedMessages"][0]["signature"];
+            let part = |name: &str| -> anyhow::Result<U256> {
+                let value = signature[name].as_str().with_context(|| format!("the signature has no {}", name))?;
+                Ok(U256::from_str_radix(value, 16)?)
+            };
+            let v = signature["v"].as_u64().context("the signature has no v")?;
+            Ok(Progress::Signed(Signature { r: part("r")?, s: part("s")?, v }))
+        }
+        "CANCELLED" | "REJECTED" | "BLOCKED" | "FAILED" => {
+            let reason = match transaction["subStatus"].as_str() {
+                Some(sub_status) if !sub_status.is_empty() => format!("{} ({})", status.to_lowercase(), sub_status),
+                _ => status.to_lowercase(),
+            };
+            Ok(Progress::Refused(reason))
+        }
+        _ => Ok(Progress::Pending(status.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_progress() {
+        let pending = json!({ "id": "1", "status": "PENDING_AUTHORIZATION" });
+        assert_eq!(progress(&pending).unwrap(), Progress::Pending("PENDING_AUTHORIZATION".to_string()));
+        let rejected = json!({ "id": "1", "status": "REJECTED", "subStatus": "REJECTED_BY_USER" });
+        assert_eq!(progress(&rejected).unwrap(), Progress::Refused("rejected (REJECTED_BY_USER)".to_string()));
+
+        let completed = json!({
+            "id": "1",
+            "status": "COMPLETED",
+            "signedMessages": [{
+                "content": "ab",
+                "signature": { "fullSig": "0a0b", "r": "0a", "s": "0b", "v": 1 },
+            }],
+        });
+        let expected = Signature { r: 10.into(), s: 11.into(), v: 1 };
+        assert_eq!(progress(&completed).unwrap(), Progress::Signed(expected));
+        assert!(progress(&json!({ "status": "COMPLETED" })).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Gas tank: tops the relayer up from a treasury wallet before sending, so
+//! long batch runs don't stall once the relayer runs out of gas money.
+
+use crate::audit;
+use crate::config::{self, Config};
+use crate::pipeline::{self, Client};
+use crate::print_ok;
+use ethers::prelude::*;
+use ethers::utils::{format_units, parse_ether};
+use std::sync::Arc;
+
+pub struct GasTank {
+    treasury: Arc<Client>,
+    /// Top up when the relayer balance falls below this many wei.
+    threshold: U256,
+    /// Amount of wei sent per top-up.
+    top_up: U256,
+}
+
+impl GasTank {
+    /// Reads TREASURY_PRIVATE_KEY, GAS_TANK_THRESHOLD and GAS_TANK_TOP_UP
+    /// (both in ether). Returns `None` when no treasury key is configured.
+    pub fn from_env(config: &Config) -> anyhow::Result<Option<Self>> {
+        if config::var("TREASURY_PRIVATE_KEY").is_err() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            treasury: pipeline::connect_with_key(config, &config::secret("TREASURY_PRIVATE_KEY")?)?,
+            threshold: parse_ether(config::var("GAS_TANK_THRESHOLD")?)?,
+            top_up: parse_ether(config::var("GAS_TANK_TOP_UP")?)?,
+        }))
+    }
+
+    /// Sends a top-up to `relayer` if its balance is under the threshold and
+    /// waits for it to be mined. Returns the top-up receipt, if one was sent.
+    pub async fn ensure_funded(&self, relayer: Address) -> anyhow::Result<Option<TransactionReceipt>> {
+        let balance = self.treasury.get_balance(relayer, None).await?;
+        if balance >= self.threshold {
+            return Ok(None);
+        }
+
+        let treasury = self.treasury.address();
+        println!("=== Gas Tank ===");
+        println!("Relayer Balance: {} ETH (threshold {} ETH)",
+            format_units(balance, "ether")?,
+            format_units(self.threshold, "ether")?);
+        println!("Topping up {} ETH from treasury {:?}", format_units(self.top_up, "ether")?, treasury);
+
+        let treasury_balance = self.treasury.get_balance(treasury, None).await?;
+        anyhow::ensure!(
+            treasury_balance > self.top_up,
+            "treasury {:?} has only {} ETH, cannot top up {} ETH",
+            treasury,
+            format_units(treasury_balance, "ether")?,
+            format_units(self.top_up, "ether")?
+        );
+
+        let tx = TransactionRequest::pay(relayer, self.top_up);
+        let pending = audit::send_transaction(&*self.treasury, tx, "gas-tank top-up").await?;
+        println!("Top-up Hash: {:?}", pending.tx_hash());
+
+        let receipt = pending
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("top-up transaction dropped before it was mined"))?;
+        audit::record_receipt(treasury, "gas-tank top-up", &receipt)?;
+        anyhow::ensure!(
+            receipt.status == Some(U64::from(1)),
+            "top-up transaction {:?} failed",
+            receipt.transaction_hash
+        );
+        print_ok!("Top-up mined in block: {:?}", receipt.block_number);
+        println!();
+
+        Ok(Some(receipt))
+    }
+}
@@ -0,0 +1,295 @@
+//! Estimated against actual gas: every mined send's gas estimate, gas used
+//! and effective gas price, appended to `gas-usage.jsonl` in the state
+//! directory, so how far estimates are off can be read from data across
+//! runs and batches rather than guessed.
+//!
+//! The record also serves as a baseline: a successful send whose gas used is
+//! more than GAS_REGRESSION_PERCENT (default 20) off the median of the last
+//! GAS_BASELINE_WINDOW (default 20) successful sends of the same kind on the
+//! same chain is flagged, which is how a contract upgrade or pathological
+//! state growth shows up before it gets expensive.
+
+use crate::config;
+use crate::token::group_thousands;
+use anyhow::Context;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Usage {
+    /// Unix timestamp of the record.
+    pub timestamp: u64,
+    /// Subcommand the send was made through, e.g. `lock`.
+    pub kind: String,
+    pub chain_id: u64,
+    pub tx_hash: H256,
+    pub estimated: U256,
+    pub gas_used: U256,
+    /// Price per gas actually paid, in wei, when the node reports it.
+    pub effective_gas_price: Option<U256>,
+    /// Whether the transaction succeeded; a revert at the estimate may have
+    /// run out of gas.
+    pub success: bool,
+}
+
+impl Usage {
+    /// The usage of the mined `receipt` of a transaction estimated at
+    /// `estimated` gas.
+    pub fn new(kind: &str, chain_id: u64, estimated: U256, receipt: &TransactionReceipt) -> Self {
+        Self {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+            kind: kind.to_string(),
+            chain_id,
+            tx_hash: receipt.transaction_hash,
+            estimated,
+            gas_used: receipt.gas_used.unwrap_or_default(),
+            effective_gas_price: receipt.effective_gas_price,
+            success: receipt.status == Some(U64::from(1)),
+        }
+    }
+
+    /// How far gas used was from the estimate, in percent of the estimate;
+    /// negative when the estimate was higher.
+    pub fn delta_percent(&self) -> f64 {
+        if self.estimated.is_zero() {
+            return 0.0;
+        }
+        (as_f64(self.gas_used) - as_f64(self.estimated)) * 100.0 / as_f64(self.estimated)
+    }
+
+    /// E.g. `Gas used 41,230 of 45,100 estimated (-8.6%) at 12.5 Gwei`.
+    pub fn describe(&self) -> String {
+        let price = match self.effective_gas_price {
+            Some(price) => format!(" at {} Gwei", ethers::utils::format_units(price, "gwei").unwrap_or_default()),
+            None => String::new(),
+        };
+        format!(
+            "Gas used {} of {} estimated ({:+.1}%){}",
+            group_thousands(&self.gas_used.to_string()),
+            group_thousands(&self.estimated.to_string()),
+            self.delta_percent(),
+            price
+        )
+    }
+}
+
+fn as_f64(value: U256) -> f64 {
+    value.as_u128() as f64
+}
+
+/// Path of the usage record.
+fn path() -> PathBuf {
+    config::state_dir().join("gas-usage.jsonl")
+}
+
+/// Appends `usage` to the record.
+pub fn record(usage: &Usage) -> anyhow::Result<()> {
+    let path = path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(usage)?)?;
+    Ok(())
+}
+
+/// Every recorded usage, oldest first.
+pub fn load() -> anyhow::Result<Vec<Usage>> {
+    let file = match File::open(path()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut usages = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        usages.push(serde_json::from_str(&line)?);
+    }
+    Ok(usages)
+}
+
+/// Fewest earlier sends a baseline is drawn from; below that a deviation
+/// says more about the sample than about the contract.
+const MIN_BASELINE_SENDS: usize = 5;
+
+/// How far the gas of a send was from its baseline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Regression {
+    /// Median gas used by the earlier sends.
+    pub baseline: U256,
+    /// Number of earlier sends the baseline is drawn from.
+    pub sends: usize,
+    /// Deviation from the baseline, in percent of it.
+    pub delta_percent: f64,
+}
+
+impl Regression {
+    /// E.g. `Gas used 61,000 is +35.2% off the lock baseline of 45,100 (median of the last 20 sends)`.
+    pub fn describe(&self, usage: &Usage) -> String {
+        format!(
+            "Gas used {} is {:+.1}% off the {} baseline of {} (median of the last {} sends)",
+            group_thousands(&usage.gas_used.to_string()),
+            self.delta_percent,
+            usage.kind,
+            group_thousands(&self.baseline.to_string()),
+            self.sends
+        )
+    }
+}
+
+/// Compares `usage` with the median gas used by the last `window`
+/// successful sends of its kind and chain in `history`, returning the
+/// deviation if it exceeds `threshold_percent` either way. Reverted sends,
+/// and baselines of fewer than [`MIN_BASELINE_SENDS`], are never flagged.
+pub fn regression(history: &[Usage], usage: &Usage, window: usize, threshold_percent: f64) -> Option<Regression> {
+    if !usage.success {
+        return None;
+    }
+    let mut gas: Vec<U256> = history
+        .iter()
+        .rev()
+        .filter(|earlier| earlier.success && earlier.chain_id == usage.chain_id && earlier.kind == usage.kind)
+        .take(window)
+        .map(|earlier| earlier.gas_used)
+        .collect();
+    if gas.len() < MIN_BASELINE_SENDS {
+        return None;
+    }
+    gas.sort_unstable();
+    let baseline = gas[gas.len() / 2];
+    if baseline.is_zero() {
+        return None;
+    }
+    let delta_percent = (as_f64(usage.gas_used) - as_f64(baseline)) * 100.0 / as_f64(baseline);
+    (delta_percent.abs() > threshold_percent).then_some(Regression { baseline, sends: gas.len(), delta_percent })
+}
+
+/// Appends `usage` to the record, first comparing it with the baseline of
+/// the sends recorded before it, per GAS_BASELINE_WINDOW and
+/// GAS_REGRESSION_PERCENT.
+pub fn record_and_check(usage: &Usage) -> anyhow::Result<Option<Regression>> {
+    let window = match config::env_var("GAS_BASELINE_WINDOW") {
+        Some(window) => window.parse().context("invalid GAS_BASELINE_WINDOW")?,
+        None => 20,
+    };
+    let threshold = match config::env_var("GAS_REGRESSION_PERCENT") {
+        Some(threshold) => threshold.parse().context("invalid GAS_REGRESSION_PERCENT")?,
+        None => 20.0,
+    };
+    let found = regression(&load()?, usage, window, threshold);
+    record(usage)?;
+    Ok(found)
+}
+
+/// The usages of one chain and kind, summarized.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Summary {
+    pub chain_id: u64,
+    pub kind: String,
+    pub sends: usize,
+    /// Mean, lowest and highest [`Usage::delta_percent`].
+    pub mean_delta_percent: f64,
+    pub min_delta_percent: f64,
+    pub max_delta_percent: f64,
+    /// Sends that reverted having used their whole estimate, likely out of
+    /// gas.
+    pub out_of_gas: usize,
+    pub total_gas_used: U256,
+    /// Mean effective gas price, in wei, over the sends that report one.
+    pub mean_gas_price: Option<U256>,
+}
+
+/// Summarizes `usages` per chain and kind.
+pub fn summarize(usages: &[Usage]) -> Vec<Summary> {
+    let mut groups: BTreeMap<(u64, &str), Vec<&Usage>> = BTreeMap::new();
+    for usage in usages {
+        groups.entry((usage.chain_id, &usage.kind)).or_default().push(usage);
+    }
+    groups
+        .into_iter()
+        .map(|((chain_id, kind), usages)| {
+            let deltas: Vec<f64> = usages.iter().map(|usage| usage.delta_percent()).collect();
+            let prices: Vec<U256> = usages.iter().filter_map(|usage| usage.effective_gas_price).collect();
+            Summary {
+                chain_id,
+                kind: kind.to_string(),
+                sends: usages.len(),
+                mean_delta_percent: deltas.iter().sum::<f64>() / deltas.len() as f64,
+                min_delta_percent: deltas.iter().copied().fold(f64::INFINITY, f64::min),
+                max_delta_percent: deltas.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                out_of_gas: usages.iter().filter(|usage| !usage.success && usage.gas_used >= usage.estimated).count(),
+                total_gas_used: usages.iter().fold(U256::zero(), |total, usage| total + usage.gas_used),
+                mean_gas_price: match prices.len() {
+                    0 => None,
+                    n => Some(prices.iter().fold(U256::zero(), |total, price| total + price) / n),
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(kind: &str, estimated: u64, gas_used: u64, price: Option<u64>, success: bool) -> Usage {
+        Usage {
+            timestamp: 0,
+            kind: kind.to_string(),
+            chain_id: 1,
+            tx_hash: H256::zero(),
+            estimated: estimated.into(),
+            gas_used: gas_used.into(),
+            effective_gas_price: price.map(U256::from),
+            success,
+        }
+    }
+
+    #[test]
+    fn summaries() {
+        let usages = [
+            usage("lock", 100_000, 90_000, Some(10), true),
+            usage("lock", 100_000, 80_000, Some(20), true),
+            usage("lock", 50_000, 50_000, None, false),
+            usage("unlock", 40_000, 40_000, Some(5), true),
+        ];
+        assert_eq!(usages[0].delta_percent(), -10.0);
+        let summaries = summarize(&usages);
+        assert_eq!(summaries.len(), 2);
+        let lock = &summaries[0];
+        assert_eq!((lock.kind.as_str(), lock.sends, lock.out_of_gas), ("lock", 3, 1));
+        assert_eq!((lock.min_delta_percent, lock.max_delta_percent), (-20.0, 0.0));
+        assert_eq!(lock.mean_delta_percent, -10.0);
+        assert_eq!(lock.total_gas_used, U256::from(220_000));
+        assert_eq!(lock.mean_gas_price, Some(15.into()));
+        // Using the whole estimate and succeeding isn't running out of gas.
+        assert_eq!(summaries[1].out_of_gas, 0);
+    }
+
+    #[test]
+    fn regressions() {
+        let mut history: Vec<Usage> = [50_000, 51_000, 49_000, 50_500, 49_500]
+            .into_iter()
+            .map(|gas| usage("lock", 60_000, gas, None, true))
+            .collect();
+        let send = |gas: u64| usage("lock", 60_000, gas, None, true);
+        assert_eq!(regression(&history, &send(55_000), 20, 20.0), None);
+        let found = regression(&history, &send(65_000), 20, 20.0).unwrap();
+        assert_eq!((found.baseline, found.sends, found.delta_percent), (50_000.into(), 5, 30.0));
+        assert!(regression(&history, &send(35_000), 20, 20.0).is_some());
+        // Reverts, other kinds and short histories don't count.
+        assert_eq!(regression(&history, &usage("lock", 60_000, 65_000, None, false), 20, 20.0), None);
+        history.push(usage("unlock", 60_000, 90_000, None, true));
+        history.push(usage("lock", 60_000, 10_000, None, false));
+        assert_eq!(regression(&history, &send(55_000), 20, 20.0), None);
+        assert_eq!(regression(&history, &send(65_000), 4, 20.0), None);
+    }
+}
@@ -0,0 +1,48 @@
+//! Hardware wallets, for keys that never leave the device. A Ledger is
+//! reached over USB and must be unlocked with the Ethereum app open.
+
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::signers::{HDPath, Ledger};
+use std::ops::Range;
+
+/// The derivation path of account `index`: Ledger Live's `m/44'/60'/i'/0/0`,
+/// or the `m/44'/60'/0'/i` of legacy (MyEtherWallet-era) accounts.
+pub fn path(index: usize, legacy: bool) -> HDPath {
+    match legacy {
+        true => HDPath::Legacy(index),
+        false => HDPath::LedgerLive(index),
+    }
+}
+
+/// [`path`] written out, for display.
+pub fn path_string(index: usize, legacy: bool) -> String {
+    match legacy {
+        true => format!("m/44'/60'/0'/{}", index),
+        false => format!("m/44'/60'/{}'/0/0", index),
+    }
+}
+
+/// The addresses of the Ledger's accounts `indices`, in order.
+pub async fn ledger_addresses(chain_id: u64, indices: Range<usize>, legacy: bool) -> anyhow::Result<Vec<Address>> {
+    let ledger = Ledger::new(path(indices.start, legacy), chain_id)
+        .await
+        .context("failed to reach the Ledger; is it connected and unlocked, with the Ethereum app open?")?;
+    let mut addresses = Vec::with_capacity(indices.len());
+    for index in indices {
+        addresses.push(ledger.get_address_with_path(&path(index, legacy)).await?);
+    }
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        assert_eq!(path_string(0, false), "m/44'/60'/0'/0/0");
+        assert_eq!(path_string(3, false), "m/44'/60'/3'/0/0");
+        assert_eq!(path_string(3, true), "m/44'/60'/0'/3");
+    }
+}
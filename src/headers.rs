@@ -0,0 +1,207 @@
+//! A local store of recent block headers per chain (number, hash, parent,
+//! state root and timestamp), so proof verification can take a block's
+//! state root without fetching it again, and reorgs show up as cached
+//! headers the chain no longer links to. `stream` keeps it up to date while
+//! it runs; other commands sync it when they need it.
+
+use crate::config;
+use anyhow::Context;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// How many of the most recent headers are kept.
+pub const CAPACITY: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub number: u64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub state_root: H256,
+    pub timestamp: u64,
+}
+
+impl Header {
+    pub fn from_block<TX>(block: &Block<TX>) -> anyhow::Result<Self> {
+        Ok(Self {
+            number: block.number.context("the block is still pending")?.as_u64(),
+            hash: block.hash.context("the block is still pending")?,
+            parent_hash: block.parent_hash,
+            state_root: block.state_root,
+            timestamp: block.timestamp.as_u64(),
+        })
+    }
+}
+
+/// Cached headers a newer chain replaced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reorg {
+    /// The first block number whose cached header was replaced.
+    pub number: u64,
+    /// How many cached headers were replaced or dropped.
+    pub depth: usize,
+}
+
+pub struct HeaderCache {
+    path: PathBuf,
+    headers: BTreeMap<u64, Header>,
+}
+
+impl HeaderCache {
+    /// Loads the cache at `path`, empty when the file doesn't exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let headers = match fs::read_to_string(&path) {
+            Ok(text) => {
+                let headers: Vec<Header> = serde_json::from_str(&text)
+                    .with_context(|| format!("corrupt header cache {}", path.display()))?;
+                headers.into_iter().map(|header| (header.number, header)).collect()
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read header cache {}", path.display())),
+        };
+        Ok(Self { path, headers })
+    }
+
+    /// The cache at `headers-<chain id>.json` in the state directory.
+    pub fn from_env(chain_id: u64) -> anyhow::Result<Self> {
+        Self::open(config::state_dir().join(format!("headers-{}.json", chain_id)))
+    }
+
+    pub fn get(&self, number: u64) -> Option<&Header> {
+        self.headers.get(&number)
+    }
+
+    /// The highest cached header.
+    pub fn latest(&self) -> Option<&Header> {
+        self.headers.values().next_back()
+    }
+
+    /// Adds `headers`, a linked run of ascending headers, in place of every
+    /// cached header from the first of them up. Returns the reorg when any
+    /// cached header in that range is replaced by a different one or left
+    /// above the new tip.
+    pub fn extend(&mut self, headers: &[Header]) -> Option<Reorg> {
+        let first = headers.first()?;
+        let replaced = self.headers.split_off(&first.number);
+        let mut reorged = replaced
+            .values()
+            .filter(|old| headers.iter().find(|new| new.number == old.number) != Some(*old))
+            .map(|old| old.number);
+        let reorg = reorged.next().map(|number| Reorg { number, depth: 1 + reorged.count() });
+        self.headers.extend(headers.iter().map(|header| (header.number, *header)));
+        while self.headers.len() > CAPACITY {
+            self.headers.pop_first();
+        }
+        reorg
+    }
+
+    /// Fetches the latest header and, walking back by parent hash, any the
+    /// cache lacks or holds differently down to where it links up with the
+    /// cached chain, at most [`CAPACITY`] blocks back.
+    pub async fn sync<M: Middleware>(&mut self, client: &M) -> anyhow::Result<(Header, Option<Reorg>)>
+    where
+        M::Error: 'static,
+    {
+        let latest = client.get_block(BlockNumber::Latest).await?.context("the node returned no latest block")?;
+        let mut chain = vec![Header::from_block(&latest)?];
+        let lowest = chain[0].number.saturating_sub(CAPACITY as u64 - 1);
+        // Walking back stops at the first cached parent, and with nothing
+        // cached below there is nothing to link up with.
+        while let Some(child) = chain.last().copied().filter(|child| child.number > lowest) {
+            let linked = self.get(child.number - 1).is_some_and(|parent| parent.hash == child.parent_hash);
+            let below = self.headers.range(..child.number).next().is_some();
+            if linked || !below {
+                break;
+            }
+            let parent = client
+                .get_block(child.parent_hash)
+                .await?
+                .with_context(|| format!("the node doesn't know block {:?}", child.parent_hash))?;
+            chain.push(Header::from_block(&parent)?);
+        }
+        chain.reverse();
+        let reorg = self.extend(&chain);
+        self.save()?;
+        Ok((*chain.last().expect("holds the latest header"), reorg))
+    }
+
+    /// The header of block `number`: the cached one, or fetched and cached.
+    pub async fn header<M: Middleware>(&mut self, client: &M, number: u64) -> anyhow::Result<Header>
+    where
+        M::Error: 'static,
+    {
+        if let Some(header) = self.get(number) {
+            return Ok(*header);
+        }
+        let block = client.get_block(number).await?.with_context(|| format!("block {} not found", number))?;
+        let header = Header::from_block(&block)?;
+        // Kept only when it doesn't displace newer headers.
+        if self.latest().is_none_or(|latest| latest.number < number) {
+            self.extend(&[header]);
+            self.save()?;
+        }
+        Ok(header)
+    }
+
+    /// Writes the cache, replacing the file in one rename so a crash can't
+    /// leave it half written.
+    pub fn save(&self) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let headers: Vec<&Header> = self.headers.values().collect();
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(&headers)?)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(from: u64, to: u64, fork: u8) -> Vec<Header> {
+        (from..=to)
+            .map(|number| Header {
+                number,
+                hash: H256::from_low_u64_be((number << 8) | fork as u64),
+                parent_hash: H256::from_low_u64_be(((number - 1) << 8) | fork as u64),
+                state_root: H256::repeat_byte(fork),
+                timestamp: number * 12,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reorgs_and_capacity() {
+        let path = std::env::temp_dir().join(format!("header-cache-test-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut cache = HeaderCache::open(&path).unwrap();
+        assert_eq!(cache.extend(&chain(10, 20, 0)), None);
+        // Extending the same chain replaces nothing.
+        assert_eq!(cache.extend(&chain(18, 22, 0)), None);
+        assert_eq!(cache.latest().unwrap().number, 22);
+
+        // A fork from block 19 up replaces the four headers above 18.
+        let fork = chain(19, 21, 1);
+        assert_eq!(cache.extend(&fork), Some(Reorg { number: 19, depth: 4 }));
+        assert_eq!(cache.get(20), Some(&fork[1]));
+        assert_eq!(cache.get(22), None);
+
+        cache.save().unwrap();
+        let reopened = HeaderCache::open(&path).unwrap();
+        assert_eq!(reopened.get(18), cache.get(18));
+        assert_eq!(reopened.latest(), cache.latest());
+
+        cache.extend(&chain(100, 100 + CAPACITY as u64, 0));
+        assert_eq!(cache.headers.len(), CAPACITY);
+        assert_eq!(cache.get(100), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
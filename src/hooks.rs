@@ -0,0 +1,185 @@
+//! External hook commands, for site-specific logic around a send without
+//! changing the crate. Each hook is a shell command set by a setting:
+//!
+//! - PRE_SEND_HOOK runs before a job is signed; a non-zero exit refuses the
+//!   send, with the hook's stderr as the reason.
+//! - POST_CONFIRM_HOOK runs once the job's transaction is mined successfully.
+//! - ON_FAILURE_HOOK runs when the job reverts, is dropped or fails to send.
+//!
+//! A hook gets the job as a JSON object on stdin: `kind`, `chain_id`,
+//! `contract`, `sender`, `user`, `token`, `amount`, `nonce` and `signature`,
+//! plus `tx_hash`, `block` and `gas_used` after a send and `error` (and
+//! `exit_code`, for failures with their own) on failure. Only the pre-send hook's exit code matters; the others are
+//! reported when they fail and otherwise ignored.
+
+use crate::config::{env_var, Config, Job};
+use crate::error::Error;
+use crate::plugins::{self, BoxFuture, Preflight, PreflightCheck};
+use crate::style;
+use anyhow::Context;
+use ethers::prelude::*;
+use serde_json::{json, Value};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    PreSend,
+    PostConfirm,
+    OnFailure,
+}
+
+impl Hook {
+    pub fn setting(self) -> &'static str {
+        match self {
+            Hook::PreSend => "PRE_SEND_HOOK",
+            Hook::PostConfirm => "POST_CONFIRM_HOOK",
+            Hook::OnFailure => "ON_FAILURE_HOOK",
+        }
+    }
+
+    /// The hook's command, if one is set.
+    pub fn command(self) -> Option<String> {
+        env_var(self.setting()).filter(|command| !command.trim().is_empty())
+    }
+}
+
+/// Registers the pre-send hook as a [`PreflightCheck`]. Its command is read
+/// for every send, so one set after startup (by a reloaded env file) runs
+/// too, and an unset one passes.
+pub fn register() {
+    plugins::register(PreSendHook);
+}
+
+/// The JSON a hook receives for `job`, before any send.
+pub fn payload(kind: &str, config: &Config, sender: Address, job: &Job) -> Value {
+    json!({
+        "kind": kind,
+        "chain_id": config.chain_id,
+        "contract": config.contract_address,
+        "sender": sender,
+        "user": job.user,
+        "token": job.token,
+        "amount": job.amount.to_string(),
+        "nonce": job.nonce.to_string(),
+        "signature": job.signature,
+    })
+}
+
+/// Runs `command` with `payload` on stdin. Returns whether it exited
+/// successfully, and its trimmed stderr.
+pub async fn execute(command: &str, payload: &Value) -> anyhow::Result<(bool, String)> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to start {:?}", command))?;
+    let mut stdin = child.stdin.take().context("hook stdin was not captured")?;
+    // A hook that exits without reading its input is not an error.
+    let _ = stdin.write_all(format!("{}\n", payload).as_bytes()).await;
+    drop(stdin);
+    let output = child.wait_with_output().await?;
+    Ok((output.status.success(), String::from_utf8_lossy(&output.stderr).trim().to_string()))
+}
+
+/// Runs a post-send hook, if it is set. A hook that fails is reported on
+/// stderr, which keeps `stream`'s stdout clean, and otherwise ignored, since
+/// the transaction is already settled.
+pub async fn notify(hook: Hook, payload: &Value) {
+    let Some(command) = hook.command() else {
+        return;
+    };
+    match execute(&command, payload).await {
+        Ok((true, _)) => {}
+        Ok((false, stderr)) => eprintln!("{}", style::warn(&format!("{} failed: {}", hook.setting(), stderr))),
+        Err(e) => eprintln!("{}", style::warn(&format!("{} failed: {:#}", hook.setting(), e))),
+    }
+}
+
+/// Adds a send's result to its `payload`, returning the hook to run for it:
+/// the failure hook when there is an `error`.
+pub fn settle(
+    payload: &mut Value,
+    tx_hash: Option<H256>,
+    block: Option<U64>,
+    gas_used: Option<U256>,
+    error: Option<String>,
+) -> Hook {
+    payload["tx_hash"] = json!(tx_hash);
+    payload["block"] = json!(block.map(|block| block.as_u64()));
+    payload["gas_used"] = json!(gas_used.map(|gas| gas.to_string()));
+    match error {
+        Some(error) => {
+            payload["error"] = json!(error);
+            Hook::OnFailure
+        }
+        None => Hook::PostConfirm,
+    }
+}
+
+/// Adds a failure that stopped a send to its `payload`, with the exit code
+/// it maps to when it has one, returning the failure hook.
+pub fn fail(payload: &mut Value, error: &anyhow::Error) -> Hook {
+    if let Some(error) = error.downcast_ref::<Error>() {
+        payload["exit_code"] = json!(error.exit_code());
+    }
+    settle(payload, None, None, None, Some(format!("{:#}", error)))
+}
+
+struct PreSendHook;
+
+impl PreflightCheck for PreSendHook {
+    fn name(&self) -> &str {
+        Hook::PreSend.setting()
+    }
+
+    fn check<'a>(&'a self, preflight: &'a Preflight<'a>) -> BoxFuture<'a, anyhow::Result<Result<(), String>>> {
+        Box::pin(async move {
+            let Some(command) = Hook::PreSend.command() else {
+                return Ok(Ok(()));
+            };
+            let payload = payload(preflight.kind, preflight.config, preflight.sender, preflight.job);
+            Ok(match execute(&command, &payload).await? {
+                (true, _) => Ok(()),
+                (false, stderr) if stderr.is_empty() => Err("refused by the hook".to_string()),
+                (false, stderr) => Err(stderr),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hooks_get_the_job_and_can_refuse() {
+        let payload = json!({ "user": "0x01", "nonce": "7" });
+        let (ok, _) = execute(r#"grep -q '"nonce":"7"'"#, &payload).await.unwrap();
+        assert!(ok);
+        let (ok, stderr) = execute("echo 'user not allowed' >&2; exit 1", &payload).await.unwrap();
+        assert!(!ok);
+        assert_eq!(stderr, "user not allowed");
+    }
+
+    #[test]
+    fn outcomes() {
+        let mut payload = json!({});
+        assert_eq!(settle(&mut payload, Some(H256::zero()), Some(9.into()), Some(21000.into()), None), Hook::PostConfirm);
+        assert_eq!((&payload["block"], &payload["gas_used"]), (&json!(9), &json!("21000")));
+        assert!(payload.get("error").is_none());
+
+        let mut payload = json!({});
+        assert_eq!(settle(&mut payload, None, None, None, Some("nonce too low".to_string())), Hook::OnFailure);
+        assert_eq!(payload["error"], json!("nonce too low"));
+
+        let mut payload = json!({});
+        let expired = Error::SignatureExpired { what: "permit".to_string(), ago: 5 }.into();
+        assert_eq!(fail(&mut payload, &expired), Hook::OnFailure);
+        assert_eq!(payload["exit_code"], json!(13));
+    }
+}
@@ -0,0 +1,96 @@
+//! ERC-165 interface detection, so flows specific to a token standard can
+//! confirm the target implements it before building a call.
+
+use crate::contract::Erc165;
+use crate::{print_ok, print_warn};
+use ethers::prelude::*;
+use ethers::utils::id;
+use std::sync::Arc;
+
+/// Interface id of ERC-721 (`0x80ac58cd`).
+pub const ERC721: [u8; 4] = [0x80, 0xac, 0x58, 0xcd];
+/// Interface id of ERC-1155 (`0xd9b67a26`).
+pub const ERC1155: [u8; 4] = [0xd9, 0xb6, 0x7a, 0x26];
+/// Interface id of ERC-165 itself.
+const ERC165: [u8; 4] = [0x01, 0xff, 0xc9, 0xa7];
+/// An id no contract may claim to support (ERC-165).
+const INVALID: [u8; 4] = [0xff; 4];
+
+/// The interface id of a set of functions: the XOR of their selectors.
+pub fn interface_id(signatures: &[&str]) -> [u8; 4] {
+    signatures.iter().fold([0; 4], |acc, signature| {
+        let selector = id(signature);
+        [acc[0] ^ selector[0], acc[1] ^ selector[1], acc[2] ^ selector[2], acc[3] ^ selector[3]]
+    })
+}
+
+/// What a contract reports for an interface id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Support {
+    Supported,
+    Unsupported,
+    /// The contract doesn't implement ERC-165, so it can't say.
+    Unknown,
+}
+
+/// Asks `address` whether it supports `interface`, following the ERC-165
+/// detection steps: the contract must claim ERC-165 and deny `0xffffffff`
+/// before its answer for `interface` is trusted.
+pub async fn supports<M: Middleware + 'static>(
+    client: Arc<M>,
+    address: Address,
+    interface: [u8; 4],
+) -> anyhow::Result<Support> {
+    let contract = Erc165::new(address, client);
+    let claims_erc165 = matches!(contract.supports_interface(ERC165).call().await, Ok(true));
+    if !claims_erc165 || !matches!(contract.supports_interface(INVALID).call().await, Ok(false)) {
+        return Ok(Support::Unknown);
+    }
+    Ok(match contract.supports_interface(interface).call().await? {
+        true => Support::Supported,
+        false => Support::Unsupported,
+    })
+}
+
+/// Fails when `address` reports that it doesn't support `interface` (named
+/// `name` in messages), and warns when it can't tell.
+pub async fn require<M: Middleware + 'static>(
+    client: Arc<M>,
+    address: Address,
+    interface: [u8; 4],
+    name: &str,
+) -> anyhow::Result<()> {
+    match supports(client, address, interface).await? {
+        Support::Supported => print_ok!("{:?} supports {}", address, name),
+        Support::Unsupported => anyhow::bail!(
+            "{:?} does not support {} (ERC-165 interface 0x{})",
+            address,
+            name,
+            hex::encode(interface)
+        ),
+        Support::Unknown => print_warn!("{:?} doesn't implement ERC-165; can't confirm it supports {}", address, name),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interface_ids() {
+        assert_eq!(interface_id(&["supportsInterface(bytes4)"]), ERC165);
+        let erc721 = [
+            "balanceOf(address)",
+            "ownerOf(uint256)",
+            "safeTransferFrom(address,address,uint256,bytes)",
+            "safeTransferFrom(address,address,uint256)",
+            "transferFrom(address,address,uint256)",
+            "approve(address,uint256)",
+            "setApprovalForAll(address,bool)",
+            "getApproved(uint256)",
+            "isApprovedForAll(address,address)",
+        ];
+        assert_eq!(interface_id(&erc721), ERC721);
+    }
+}
@@ -0,0 +1,177 @@
+//! Encrypted JSON keystores (Web3 Secret Storage), the format wallets and
+//! node tooling exchange keys in, so keys don't sit in plaintext settings.
+
+use crate::config::env_var;
+use anyhow::Context;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use ethers::core::rand::{thread_rng, RngCore};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use serde_json::json;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+/// scrypt cost parameters. Memory use is `128 * r * 2^log_n` bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScryptParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    /// geth's standard parameters: 2^18 iterations, 256 MiB.
+    fn default() -> Self {
+        Self { log_n: 18, r: 8, p: 1 }
+    }
+}
+
+/// The keystore password: KEYSTORE_PASSWORD when set, otherwise read from
+/// stdin, twice when `confirm` is set so a typo can't lock the key away.
+pub fn password(confirm: bool) -> anyhow::Result<String> {
+    if let Some(password) = env_var("KEYSTORE_PASSWORD") {
+        return crate::secrets::resolve(&password).context("failed to resolve KEYSTORE_PASSWORD");
+    }
+    let read = |prompt: &str| -> anyhow::Result<String> {
+        println!("{}", prompt);
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    let password = read("Enter the keystore password and press Enter:")?;
+    anyhow::ensure!(!password.is_empty(), "no password given");
+    if confirm {
+        anyhow::ensure!(read("Enter it again:")? == password, "the passwords don't match");
+    }
+    Ok(password)
+}
+
+/// Parses a vanity prefix: hex digits the address should start with, with or
+/// without `0x`, matched case-insensitively.
+pub fn parse_prefix(input: &str) -> anyhow::Result<String> {
+    let prefix = input.strip_prefix("0x").unwrap_or(input).to_lowercase();
+    anyhow::ensure!(prefix.chars().all(|c| c.is_ascii_hexdigit()), "vanity prefix {:?} is not hex", input);
+    anyhow::ensure!(prefix.len() <= 40, "vanity prefix {:?} is longer than an address", input);
+    Ok(prefix)
+}
+
+fn has_prefix(address: Address, prefix: &str) -> bool {
+    hex::encode(address).starts_with(prefix)
+}
+
+/// A fresh random wallet whose address starts with `prefix` (see
+/// [`parse_prefix`]), and how many keys were tried to find it. Each further
+/// hex digit makes the search 16 times longer.
+pub fn generate(prefix: &str) -> (LocalWallet, u64) {
+    let mut rng = thread_rng();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let wallet = LocalWallet::new(&mut rng);
+        if has_prefix(wallet.address(), prefix) {
+            return (wallet, attempts);
+        }
+    }
+}
+
+/// `wallet`'s key as a version 3 keystore, encrypted with `password` under
+/// scrypt with `params` and AES-128-CTR.
+pub fn encrypt(wallet: &LocalWallet, password: &str, params: ScryptParams) -> anyhow::Result<serde_json::Value> {
+    let mut rng = thread_rng();
+    let (mut salt, mut iv, mut id) = ([0u8; 32], [0u8; 16], [0u8; 16]);
+    rng.fill_bytes(&mut salt);
+    rng.fill_bytes(&mut iv);
+    rng.fill_bytes(&mut id);
+
+    let scrypt_params = scrypt::Params::new(params.log_n, params.r, params.p, 32)
+        .map_err(|e| anyhow::anyhow!("invalid scrypt parameters: {}", e))?;
+    let mut derived = [0u8; 32];
+    scrypt::scrypt(password.as_bytes(), &salt, &scrypt_params, &mut derived)
+        .map_err(|e| anyhow::anyhow!("scrypt failed: {}", e))?;
+
+    let mut ciphertext = wallet.signer().to_bytes().to_vec();
+    Aes128Ctr::new(derived[..16].into(), &iv.into()).apply_keystream(&mut ciphertext);
+    let mac = keccak256([&derived[16..], &ciphertext[..]].concat());
+
+    Ok(json!({
+        "address": hex::encode(wallet.address()),
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": hex::encode(iv) },
+            "ciphertext": hex::encode(ciphertext),
+            "kdf": "scrypt",
+            "kdfparams": { "dklen": 32, "n": 1u64 << params.log_n, "p": params.p, "r": params.r, "salt": hex::encode(salt) },
+            "mac": hex::encode(mac),
+        },
+        "id": uuid_v4(id),
+        "version": 3,
+    }))
+}
+
+/// A random (version 4) UUID from 16 random bytes.
+fn uuid_v4(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Writes `wallet`'s key to `dir` as `<address>.json` (see [`encrypt`]),
+/// checks the file decrypts back to the same key, and returns its path.
+pub fn write(dir: &Path, wallet: &LocalWallet, password: &str, params: ScryptParams) -> anyhow::Result<PathBuf> {
+    let path = dir.join(format!("{:?}.json", wallet.address()));
+    anyhow::ensure!(!path.exists(), "{} already exists", path.display());
+    let keystore = encrypt(wallet, password, params)?;
+    fs::write(&path, serde_json::to_string_pretty(&keystore)? + "\n")
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    let decrypted = decrypt(&path, password)?;
+    anyhow::ensure!(decrypted.address() == wallet.address(), "{} doesn't decrypt back to the key", path.display());
+    Ok(path)
+}
+
+/// The wallet in the keystore at `path`.
+pub fn decrypt(path: &Path, password: &str) -> anyhow::Result<LocalWallet> {
+    LocalWallet::decrypt_keystore(path, password).with_context(|| format!("failed to decrypt {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vanity_prefixes() {
+        assert_eq!(parse_prefix("0xDEAD").unwrap(), "dead");
+        assert_eq!(parse_prefix("beef").unwrap(), "beef");
+        assert!(parse_prefix("0xgg").is_err());
+        assert!(parse_prefix(&"a".repeat(41)).is_err());
+
+        let address: Address = "0xdeAD000000000000000000000000000000000001".parse().unwrap();
+        assert!(has_prefix(address, "dead"));
+        assert!(has_prefix(address, ""));
+        assert!(!has_prefix(address, "beef"));
+
+        let (wallet, attempts) = generate("a");
+        assert!(has_prefix(wallet.address(), "a"));
+        assert!(attempts >= 1);
+    }
+
+    #[test]
+    fn keystores_round_trip() {
+        let dir = std::env::temp_dir().join(format!("keystore-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let wallet = LocalWallet::new(&mut thread_rng());
+        let params = ScryptParams { log_n: 4, r: 8, p: 1 };
+        let path = write(&dir, &wallet, "hunter2", params).unwrap();
+
+        let keystore: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(keystore["crypto"]["kdfparams"]["n"], 16);
+        assert_eq!(keystore["id"].as_str().unwrap().as_bytes()[14], b'4');
+        assert_eq!(decrypt(&path, "hunter2").unwrap().signer().to_bytes(), wallet.signer().to_bytes());
+        assert!(decrypt(&path, "wrong").is_err());
+        assert!(write(&dir, &wallet, "hunter2", params).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+//! Local replay protection: an append-only JSONL record of every job this
+//! tool has broadcast, consulted before sending so a retried upstream request
+//! cannot make us submit the same signed job twice.
+//!
+//! With LEDGER_DATABASE_URL set, the record is kept in a PostgreSQL table
+//! instead (created by the migrations in `migrations/`), so instances
+//! sharing the database also share replay protection. A job is
+//! [claimed](Ledger::claim) before it is broadcast, and the table's unique
+//! index lets only one instance claim it. Claims on the file are taken
+//! under an exclusive lock on `<file>.lock`, which serializes the processes
+//! of one machine; the lock isn't reliable on network filesystems, so
+//! instances on several machines should share the database instead.
+
+use crate::config::{self, env_var, Job};
+use anyhow::Context;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Subcommand the job was submitted through, `lock` or `unlock`.
+    pub kind: String,
+    pub chain_id: u64,
+    pub contract: Address,
+    #[serde(flatten)]
+    pub job: Job,
+    pub tx_hash: H256,
+    /// Unix timestamp of the broadcast.
+    pub submitted_at: u64,
+}
+
+impl Entry {
+    pub fn new(kind: &str, chain_id: u64, contract: Address, job: &Job, tx_hash: H256) -> Self {
+        Self {
+            kind: kind.to_string(),
+            chain_id,
+            contract,
+            job: job.clone(),
+            tx_hash,
+            submitted_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub struct Ledger {
+    backend: Backend,
+}
+
+enum Backend {
+    File(PathBuf),
+    Postgres(PgPool),
+}
+
+impl Ledger {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { backend: Backend::File(path.into()) }
+    }
+
+    /// The configured ledger: the database at LEDGER_DATABASE_URL, migrated
+    /// on connect, or else the file at [`config::ledger_path`].
+    pub async fn open() -> anyhow::Result<Self> {
+        if env_var("LEDGER_DATABASE_URL").is_none() {
+            return Ok(Self::new(config::ledger_path()));
+        }
+        let url = config::secret("LEDGER_DATABASE_URL")?;
+        let pool = PgPoolOptions::new()
+            .max_connections(4)
+            .connect(&url)
+            .await
+            .context("failed to connect to the ledger database")?;
+        sqlx::migrate!().run(&pool).await.context("failed to migrate the ledger database")?;
+        Ok(Self { backend: Backend::Postgres(pool) })
+    }
+
+    /// Returns the earlier submission of an identical job, if any.
+    pub async fn find(
+        &self,
+        kind: &str,
+        chain_id: u64,
+        contract: Address,
+        job: &Job,
+    ) -> anyhow::Result<Option<Entry>> {
+        let Backend::Postgres(pool) = &self.backend else {
+            return Ok(self.entries().await?.into_iter().find(|entry| {
+                entry.kind == kind && entry.chain_id == chain_id && entry.contract == contract && entry.job == *job
+            }));
+        };
+        let row: Option<(String,)> = sqlx::query_as(
+            "SELECT entry FROM ledger WHERE kind = $1 AND chain_id = $2 AND contract = $3 AND job = $4 ORDER BY id LIMIT 1",
+        )
+        .bind(kind)
+        .bind(chain_id as i64)
+        .bind(format!("{:?}", contract))
+        .bind(serde_json::to_string(job)?)
+        .fetch_optional(pool)
+        .await?;
+        row.map(|(entry,)| Ok(serde_json::from_str(&entry)?)).transpose()
+    }
+
+    /// Returns the submission that was broadcast as `tx_hash`, if any.
+    pub async fn find_by_tx_hash(&self, tx_hash: H256) -> anyhow::Result<Option<Entry>> {
+        let Backend::Postgres(pool) = &self.backend else {
+            return Ok(self.entries().await?.into_iter().find(|entry| entry.tx_hash == tx_hash));
+        };
+        let row: Option<(String,)> = sqlx::query_as("SELECT entry FROM ledger WHERE tx_hash = $1 ORDER BY id LIMIT 1")
+            .bind(format!("{:?}", tx_hash))
+            .fetch_optional(pool)
+            .await?;
+        row.map(|(entry,)| Ok(serde_json::from_str(&entry)?)).transpose()
+    }
+
+    /// Every recorded submission, oldest first.
+    pub async fn entries(&self) -> anyhow::Result<Vec<Entry>> {
+        let path = match &self.backend {
+            Backend::File(path) => path,
+            Backend::Postgres(pool) => {
+                let rows: Vec<(String,)> = sqlx::query_as("SELECT entry FROM ledger ORDER BY id").fetch_all(pool).await?;
+                return rows.into_iter().map(|(entry,)| Ok(serde_json::from_str(&entry)?)).collect();
+            }
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(serde_json::from_str(&line)?);
+        }
+        Ok(entries)
+    }
+
+    /// Records `entry` as its job's first submission, before it is
+    /// broadcast, unless the job is already recorded; that earlier
+    /// submission is returned instead and nothing is recorded. In the
+    /// database this is one insert, so of two instances claiming the same
+    /// job only one gets it.
+    pub async fn claim(&self, entry: &Entry) -> anyhow::Result<Option<Entry>> {
+        let pool = match &self.backend {
+            Backend::File(path) => {
+                let _lock = lock(path)?;
+                let previous = self.find(&entry.kind, entry.chain_id, entry.contract, &entry.job).await?;
+                if previous.is_none() {
+                    append(path, entry)?;
+                }
+                return Ok(previous);
+            }
+            Backend::Postgres(pool) => pool,
+        };
+        match insert(pool, entry, false).await? {
+            true => Ok(None),
+            false => self.find(&entry.kind, entry.chain_id, entry.contract, &entry.job).await,
+        }
+    }
+
+    /// Records a submission of `entry`'s job: its first, or a replacement or
+    /// forced resend of one already recorded.
+    pub async fn record(&self, entry: &Entry) -> anyhow::Result<()> {
+        let pool = match &self.backend {
+            Backend::File(path) => {
+                let _lock = lock(path)?;
+                return append(path, entry);
+            }
+            Backend::Postgres(pool) => pool,
+        };
+        if !insert(pool, entry, false).await? {
+            insert(pool, entry, true).await?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the submission broadcast as `entry.tx_hash`, after the node
+    /// refused it, so the job can be sent again.
+    pub async fn release(&self, entry: &Entry) -> anyhow::Result<()> {
+        let path = match &self.backend {
+            Backend::File(path) => path,
+            Backend::Postgres(pool) => {
+                sqlx::query("DELETE FROM ledger WHERE tx_hash = $1")
+                    .bind(format!("{:?}", entry.tx_hash))
+                    .execute(pool)
+                    .await?;
+                return Ok(());
+            }
+        };
+        let _lock = lock(path)?;
+        let mut text = String::new();
+        for kept in self.entries().await?.iter().filter(|kept| kept.tx_hash != entry.tx_hash) {
+            text.push_str(&serde_json::to_string(kept)?);
+            text.push('\n');
+        }
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+/// Takes the exclusive lock on the ledger file at `path`, held until the
+/// returned file is dropped. The lock is on a file of its own, since
+/// [`Ledger::release`] replaces the ledger file.
+fn lock(path: &Path) -> anyhow::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let lock_path = lock_path(path);
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)?;
+    file.lock().with_context(|| format!("failed to lock {}", lock_path.display()))?;
+    Ok(file)
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    lock_path.into()
+}
+
+fn append(path: &Path, entry: &Entry) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Inserts `entry`, returning whether it was. Only one first submission
+/// (`resend` false) of a job gets in.
+async fn insert(pool: &PgPool, entry: &Entry, resend: bool) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        "INSERT INTO ledger (kind, chain_id, contract, job, tx_hash, submitted_at, entry, resend) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+         ON CONFLICT (kind, chain_id, contract, job) WHERE NOT resend DO NOTHING",
+    )
+    .bind(&entry.kind)
+    .bind(entry.chain_id as i64)
+    .bind(format!("{:?}", entry.contract))
+    .bind(serde_json::to_string(&entry.job)?)
+    .bind(format!("{:?}", entry.tx_hash))
+    .bind(entry.submitted_at as i64)
+    .bind(serde_json::to_string(entry)?)
+    .bind(resend)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(nonce: u64) -> Job {
+        let user = Address::repeat_byte(1);
+        Job { user, token: Address::zero(), amount: 100.into(), nonce: nonce.into(), signature: Bytes::default() }
+    }
+
+    #[tokio::test]
+    async fn duplicates_survive_reopening() {
+        let path = std::env::temp_dir().join(format!("ledger-test-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let contract = Address::repeat_byte(9);
+        let ledger = Ledger::new(&path);
+        assert!(ledger.find("lock", 1, contract, &job(1)).await.unwrap().is_none());
+        ledger.record(&Entry::new("lock", 1, contract, &job(1), H256::repeat_byte(1))).await.unwrap();
+
+        let reopened = Ledger::new(&path);
+        let found = reopened.find("lock", 1, contract, &job(1)).await.unwrap().unwrap();
+        assert_eq!((found.job, found.tx_hash), (job(1), H256::repeat_byte(1)));
+        assert_eq!(reopened.find_by_tx_hash(H256::repeat_byte(1)).await.unwrap().unwrap().kind, "lock");
+        // Any difference in the command, chain, contract or job is a new job.
+        assert!(reopened.find("unlock", 1, contract, &job(1)).await.unwrap().is_none());
+        assert!(reopened.find("lock", 5, contract, &job(1)).await.unwrap().is_none());
+        assert!(reopened.find("lock", 1, Address::zero(), &job(1)).await.unwrap().is_none());
+        assert!(reopened.find("lock", 1, contract, &job(2)).await.unwrap().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn claims() {
+        let path = std::env::temp_dir().join(format!("ledger-claim-test-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let ledger = Ledger::new(&path);
+        let entry = |nonce, hash| Entry::new("lock", 1, Address::repeat_byte(9), &job(nonce), H256::repeat_byte(hash));
+
+        assert!(ledger.claim(&entry(1, 1)).await.unwrap().is_none());
+        let previous = ledger.claim(&entry(1, 2)).await.unwrap().unwrap();
+        assert_eq!(previous.tx_hash, H256::repeat_byte(1));
+        assert!(ledger.claim(&entry(2, 3)).await.unwrap().is_none());
+
+        // A resend is recorded alongside, and a refused broadcast forgotten.
+        ledger.record(&entry(1, 2)).await.unwrap();
+        ledger.release(&entry(1, 1)).await.unwrap();
+        let hashes: Vec<H256> = ledger.entries().await.unwrap().iter().map(|entry| entry.tx_hash).collect();
+        assert_eq!(hashes, [H256::repeat_byte(3), H256::repeat_byte(2)]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn one_of_racing_claims_wins() {
+        let path = std::env::temp_dir().join(format!("ledger-race-test-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        // Each thread stands in for an instance with its own handle on the file.
+        let claim = |hash| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                let entry = Entry::new("lock", 1, Address::repeat_byte(9), &job(1), H256::repeat_byte(hash));
+                runtime.block_on(Ledger::new(path).claim(&entry)).unwrap().is_none()
+            })
+        };
+        let threads: Vec<_> = (0..8).map(claim).collect();
+        let won = threads.into_iter().map(|thread| thread.join().unwrap());
+        assert_eq!(won.filter(|won| *won).count(), 1);
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        assert_eq!(runtime.block_on(Ledger::new(&path).entries()).unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(lock_path(&path)).unwrap();
+    }
+}
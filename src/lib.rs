@@ -0,0 +1,90 @@
+//! Shared building blocks for the contract caller: the generated contract
+//! bindings, environment-driven configuration and the preflight / send /
+//! receipt pipeline every subcommand goes through.
+
+pub mod artifacts;
+pub mod audit;
+pub mod balances;
+pub mod authorization;
+pub mod batch;
+pub mod bench;
+pub mod bytecode;
+pub mod calldata;
+pub mod check;
+pub mod checkpoint;
+pub mod config;
+pub mod contract;
+pub mod crosscheck;
+pub mod deploy;
+pub mod doctor;
+pub mod error;
+pub mod events;
+pub mod explorer;
+pub mod export;
+pub mod failover;
+pub mod faucet;
+pub mod fees;
+pub mod gas_tank;
+pub mod gas_usage;
+pub mod headers;
+pub mod hooks;
+pub mod interfaces;
+pub mod keystore;
+pub mod ledger;
+pub mod lock_request;
+pub mod mempool;
+pub mod nft;
+pub mod nonce;
+pub mod output;
+pub mod permit;
+pub mod pipeline;
+pub mod plugins;
+pub mod policy;
+pub mod postcheck;
+pub mod price;
+pub mod profile;
+pub mod proof;
+pub mod queue;
+pub mod quorum;
+pub mod registry;
+pub mod repl;
+pub mod rotation;
+pub mod safe;
+pub mod schedule;
+pub mod secrets;
+pub mod shutdown;
+pub mod signature;
+pub mod smart_account;
+pub mod signing_service;
+pub mod status;
+pub mod storage;
+pub mod stress;
+pub mod style;
+pub mod tls;
+pub mod token;
+pub mod trace;
+pub mod units;
+pub mod unsigned;
+pub mod upgrades;
+pub mod validity;
+pub mod verify;
+pub mod web3signer;
+pub mod workflow;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+#[cfg(feature = "kms")]
+pub mod fireblocks;
+#[cfg(feature = "hardware")]
+pub mod hardware;
+#[cfg(feature = "kms")]
+pub mod privy;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "kms")]
+pub mod turnkey;
+#[cfg(feature = "walletconnect")]
+pub mod walletconnect;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(test)]
+mod mock_node;
@@ -0,0 +1,313 @@
+//! Locks assembled in code rather than read from USER_ADDRESS, TOKEN_ADDRESS,
+//! AMOUNT, NONCE and SIGNATURE. [`LockRequest::builder`] takes each field
+//! typed, along with how much value to attach, how to price the transaction
+//! and how many confirmations to wait for, and [`LockRequestBuilder::build`]
+//! checks everything that can be checked without a node, so a malformed
+//! request fails before it is priced or signed.
+
+use crate::calldata;
+use crate::config::{Config, Job, LOCK_KIND};
+use crate::fees::{FeeModel, Fees};
+use crate::ledger::Ledger;
+use crate::pipeline::{self, Sender};
+use crate::shutdown::CancellationToken;
+use crate::signature;
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+
+/// How much value the lock carries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ValuePolicy {
+    /// The value the lock is expected to carry; see [`Job::lock_value`].
+    #[default]
+    Expected,
+    /// This value, which must be the expected one.
+    Exact(U256),
+    /// This value, even if it isn't the expected one.
+    Override(U256),
+}
+
+/// How the lock transaction is priced.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum FeePolicy {
+    /// The chain's fee model, as [`FeeModel::from_env`] reads it when the
+    /// request is sent.
+    #[default]
+    Chain,
+    Model(FeeModel),
+    /// Fixed EIP-1559 fees, in wei.
+    Fixed { max_fee_per_gas: U256, max_priority_fee_per_gas: U256 },
+}
+
+impl FeePolicy {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            FeePolicy::Chain => Ok(()),
+            FeePolicy::Model(model) => model.validate(),
+            FeePolicy::Fixed { max_fee_per_gas, max_priority_fee_per_gas } => {
+                anyhow::ensure!(!max_fee_per_gas.is_zero(), "the max fee per gas is zero");
+                anyhow::ensure!(
+                    max_priority_fee_per_gas <= max_fee_per_gas,
+                    "the priority fee ({} wei) is above the max fee ({} wei)",
+                    max_priority_fee_per_gas,
+                    max_fee_per_gas
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Prices `tx` for `client`'s chain.
+    pub async fn apply<M: Sender>(&self, client: &M, tx: &mut TypedTransaction) -> anyhow::Result<()>
+    where
+        M::Error: 'static,
+    {
+        let model = match self {
+            FeePolicy::Chain => FeeModel::from_env(client.chain_id())?,
+            FeePolicy::Model(model) => model.clone(),
+            FeePolicy::Fixed { max_fee_per_gas, max_priority_fee_per_gas } => {
+                // Only the caps are set; the base fee is for display.
+                let fees = Fees {
+                    base_fee: U256::zero(),
+                    max_fee_per_gas: *max_fee_per_gas,
+                    max_priority_fee_per_gas: *max_priority_fee_per_gas,
+                };
+                fees.apply(tx);
+                return Ok(());
+            }
+        };
+        model.set_transaction_type(tx);
+        model.suggest(client).await?.apply(tx);
+        Ok(())
+    }
+}
+
+/// A value that isn't the one the lock is expected to carry, under
+/// [`ValuePolicy::Exact`].
+#[derive(Debug, thiserror::Error)]
+#[error("value {value} differs from the {expected} wei {kind} lock is expected to carry")]
+pub struct ValueMismatch {
+    pub value: U256,
+    pub expected: U256,
+    /// `a native-currency` or `an ERC-20`.
+    pub kind: &'static str,
+}
+
+/// A validated lock, ready to send.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockRequest {
+    job: Job,
+    value: U256,
+    expected_value: U256,
+    fees: FeePolicy,
+    confirmations: usize,
+}
+
+impl LockRequest {
+    pub fn builder() -> LockRequestBuilder {
+        LockRequestBuilder::default()
+    }
+
+    pub fn job(&self) -> &Job {
+        &self.job
+    }
+
+    /// The value attached, in wei.
+    pub fn value(&self) -> U256 {
+        self.value
+    }
+
+    /// The value the lock is expected to carry, in wei; differs from
+    /// [`LockRequest::value`] only under [`ValuePolicy::Override`].
+    pub fn expected_value(&self) -> U256 {
+        self.expected_value
+    }
+
+    pub fn fees(&self) -> &FeePolicy {
+        &self.fees
+    }
+
+    pub fn confirmations(&self) -> usize {
+        self.confirmations
+    }
+
+    /// The unpriced `lock` transaction from `from` to `contract`.
+    pub fn transaction(&self, from: Address, contract: Address) -> TypedTransaction {
+        let tx = Eip1559TransactionRequest::new().from(from).to(contract).data(calldata::encode_lock(&self.job));
+        tx.value(self.value).into()
+    }
+
+    /// Prices the lock with the fee policy, sends it through `client` to the
+    /// contract with [`pipeline::send_and_wait`] and waits for the requested
+    /// confirmations. The send is audited and claimed in `ledger` first, so
+    /// a job already submitted fails with
+    /// [`crate::error::Error::DuplicateSubmission`]; the preflight checks are
+    /// left to the caller.
+    pub async fn send<M: Sender>(
+        &self,
+        client: &M,
+        ledger: &Ledger,
+        config: &Config,
+    ) -> anyhow::Result<Option<TransactionReceipt>>
+    where
+        M::Error: 'static,
+    {
+        let mut tx = self.transaction(client.sender(), config.contract_address);
+        self.fees.apply(client, &mut tx).await?;
+        let cancel = CancellationToken::new();
+        let receipt =
+            pipeline::send_and_wait(client, tx, ledger, LOCK_KIND, config, &self.job, false, None, &cancel).await?;
+        if let Some(block) = receipt.as_ref().and_then(|receipt| receipt.block_number) {
+            let confirmed = block + (self.confirmations - 1) as u64;
+            while client.get_block_number().await? < confirmed {
+                tokio::time::sleep(client.provider().get_interval()).await;
+            }
+        }
+        Ok(receipt)
+    }
+}
+
+/// Builds a [`LockRequest`]; user, token, amount, nonce and signature are
+/// required, the rest default to the expected value, the chain's fee model
+/// and one confirmation.
+#[derive(Clone, Debug, Default)]
+pub struct LockRequestBuilder {
+    user: Option<Address>,
+    token: Option<Address>,
+    amount: Option<U256>,
+    nonce: Option<U256>,
+    signature: Option<Bytes>,
+    value: ValuePolicy,
+    fees: FeePolicy,
+    confirmations: Option<usize>,
+}
+
+impl LockRequestBuilder {
+    pub fn user(mut self, user: Address) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// The ERC-20 to lock, or [`crate::config::native_token`] for the native
+    /// currency.
+    pub fn token(mut self, token: Address) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// In the token's base units.
+    pub fn amount(mut self, amount: impl Into<U256>) -> Self {
+        self.amount = Some(amount.into());
+        self
+    }
+
+    pub fn nonce(mut self, nonce: impl Into<U256>) -> Self {
+        self.nonce = Some(nonce.into());
+        self
+    }
+
+    /// The backend signer's signature, in any shape [`signature::normalize`]
+    /// accepts.
+    pub fn signature(mut self, signature: impl Into<Bytes>) -> Self {
+        self.signature = Some(signature.into());
+        self
+    }
+
+    /// All five job fields at once.
+    pub fn job(self, job: &Job) -> Self {
+        self.user(job.user).token(job.token).amount(job.amount).nonce(job.nonce).signature(job.signature.clone())
+    }
+
+    pub fn value(mut self, value: ValuePolicy) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn fees(mut self, fees: FeePolicy) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    /// Blocks to wait for after the one the lock is mined in, plus one.
+    pub fn confirmations(mut self, confirmations: usize) -> Self {
+        self.confirmations = Some(confirmations);
+        self
+    }
+
+    /// Checks the request: every job field is set, the user isn't the zero
+    /// address, the amount isn't zero, the signature normalizes, the value
+    /// matches the policy (the expected value depends on NATIVE_TOKEN_ADDRESS
+    /// and LOCK_EXTRA_VALUE), the fees are consistent and at least one
+    /// confirmation is awaited. A value refused under [`ValuePolicy::Exact`]
+    /// is a [`ValueMismatch`].
+    pub fn build(self) -> anyhow::Result<LockRequest> {
+        let missing: Vec<&str> = [
+            ("user", self.user.is_none()),
+            ("token", self.token.is_none()),
+            ("amount", self.amount.is_none()),
+            ("nonce", self.nonce.is_none()),
+            ("signature", self.signature.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(field, unset)| unset.then_some(field))
+        .collect();
+        let (Some(user), Some(token), Some(amount), Some(nonce), Some(signature)) =
+            (self.user, self.token, self.amount, self.nonce, self.signature)
+        else {
+            anyhow::bail!("lock request has no {}", missing.join(", "));
+        };
+        anyhow::ensure!(!user.is_zero(), "the user is the zero address");
+        anyhow::ensure!(!amount.is_zero(), "the amount is zero");
+        let signature = signature::normalize(&signature).context("invalid signature")?;
+        let job = Job { user, token, amount, nonce, signature };
+
+        let expected_value = job.lock_value()?;
+        let value = match self.value {
+            ValuePolicy::Expected => expected_value,
+            ValuePolicy::Exact(value) if value != expected_value => {
+                let kind = if job.is_native()? { "a native-currency" } else { "an ERC-20" };
+                return Err(ValueMismatch { value, expected: expected_value, kind }.into());
+            }
+            ValuePolicy::Exact(value) | ValuePolicy::Override(value) => value,
+        };
+        self.fees.validate()?;
+        let confirmations = self.confirmations.unwrap_or(1);
+        anyhow::ensure!(confirmations > 0, "confirmations must be at least 1");
+        Ok(LockRequest { job, value, expected_value, fees: self.fees, confirmations })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn building_requests() {
+        let user: Address = "0x00000000000000000000000000000000000000aa".parse().unwrap();
+        let token: Address = "0x00000000000000000000000000000000000000bb".parse().unwrap();
+        let mut signature = vec![0x11; 64];
+        signature.push(28);
+        let builder = || LockRequest::builder().user(user).token(token).amount(1_000u64).nonce(7u64);
+
+        let error = builder().build().unwrap_err();
+        assert_eq!(error.to_string(), "lock request has no signature");
+        let request = builder().signature(signature.clone()).build().unwrap();
+        assert_eq!((request.value(), request.confirmations()), (U256::zero(), 1));
+        assert_eq!(request.fees(), &FeePolicy::Chain);
+        assert_eq!(request.job().signature.len(), 65);
+
+        // An ERC-20 lock carries no value unless told to.
+        let exact = builder().signature(signature.clone()).value(ValuePolicy::Exact(5.into())).build().unwrap_err();
+        assert!(exact.downcast_ref::<ValueMismatch>().is_some());
+        let overridden = builder().signature(signature.clone()).value(ValuePolicy::Override(5.into()));
+        let overridden = overridden.build().unwrap();
+        assert_eq!((overridden.value(), overridden.expected_value()), (5.into(), U256::zero()));
+
+        assert!(builder().signature(signature.clone()).amount(0u64).build().is_err());
+        assert!(builder().signature(signature.clone()).confirmations(0).build().is_err());
+        assert!(builder().signature(vec![0x11; 10]).build().is_err());
+        let fees = FeePolicy::Fixed { max_fee_per_gas: 10.into(), max_priority_fee_per_gas: 20.into() };
+        assert!(builder().signature(signature).fees(fees).build().is_err());
+    }
+}
@@ -1,108 +1,204 @@
-use ethers::prelude::*;
-use std::sync::Arc;
-use std::env;
-use dotenv::dotenv;
+use clap::{CommandFactory, Parser, Subcommand};
+use eth_contract_caller::error::Error;
+use eth_contract_caller::{config, hooks, plugins, secrets, style};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-abigen!(
-    MyContract,
-    "./abi.json" // save your ABI to a file called `abi.json` in the project root
-);
+mod commands;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    dotenv().ok();
-
-    let rpc_url = env::var("RPC_URL")?;
-    let private_key = env::var("PRIVATE_KEY")?;
-    let chain_id: u64 = env::var("CHAIN_ID")?.parse()?;
-    let contract_address: Address = env::var("CONTRACT_ADDRESS")?.parse()?;
-
-    let user: Address = env::var("USER_ADDRESS")?.parse()?;
-    let token: Address = env::var("TOKEN_ADDRESS")?.parse()?;
-    let amount: U256 = U256::from_dec_str(&env::var("AMOUNT")?)?;
-    let nonce: U256 = env::var("NONCE")?.parse()?;
-    let signature: Bytes = env::var("SIGNATURE")?.parse()?; // or hex::decode + Bytes::from
-
-    println!("=== Configuration ===");
-    println!("RPC URL: {}", rpc_url);
-    println!("Chain ID: {}", chain_id);
-    println!("Contract Address: {:?}", contract_address);
-    println!("User Address: {:?}", user);
-    println!("Token Address: {:?}", token);
-    println!("Amount: {}", amount);
-    println!("Nonce: {}", nonce);
-    println!("Signature: 0x{}", hex::encode(&signature));
-    println!();
+/// Operate the lock contract's lock and release paths from the command line.
+///
+/// Connection settings and job parameters are read from the environment (or a
+/// `.env` file); see the README for the full list of variables.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Load settings from this file instead of `.env`
+    #[arg(long, global = true, value_name = "PATH")]
+    env_file: Option<PathBuf>,
+    /// Print plain output even on a terminal (also set by a non-empty NO_COLOR)
+    #[arg(long, global = true)]
+    no_color: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    let provider = Provider::<Http>::try_from(rpc_url)?;
-    let wallet = private_key.parse::<LocalWallet>()?.with_chain_id(chain_id);
-    let wallet_address = wallet.address();
-    let client = SignerMiddleware::new(provider, wallet);
-    let client = Arc::new(client);
+#[derive(Subcommand)]
+enum Command {
+    /// Lock funds with `lock(...)` (the default when no subcommand is given)
+    Lock(commands::lock::Args),
+    /// Lock an ERC-721 token with `lockNFT(...)`
+    LockNft(commands::lock_nft::Args),
+    /// Lock a batch of ERC-1155 positions with `lockERC1155Batch(...)`
+    #[command(name = "lock-erc1155")]
+    LockErc1155(commands::lock_erc1155::Args),
+    /// Release locked funds with `redeemWithSignature(...)`
+    #[command(alias = "withdraw")]
+    Unlock(commands::unlock::Args),
+    /// Validate the configuration without touching the network
+    CheckConfig,
+    /// Print the config file's JSON Schema
+    Config(commands::config::Args),
+    /// Diagnose the RPC, chain id, contract, wallet balance and pending nonce
+    Doctor,
+    /// Lock every job in a CSV file, several transactions at a time
+    Batch(commands::batch::Args),
+    /// Lock jobs read as NDJSON from stdin, writing one JSON result per job to stdout
+    Stream(commands::stream::Args),
+    /// Run a YAML workflow of dependent steps, with conditions and retries
+    Workflow(commands::workflow::Args),
+    /// Run a Rhai script that reads, estimates, signs, sends and waits on contract calls
+    Script(commands::script::Args),
+    /// Manage secrets stored in the OS keychain
+    Keyring(commands::keyring::Args),
+    /// Re-sign a batch's unsent jobs with a new signing key
+    RotateKey(commands::rotate_key::Args),
+    /// Generate keys as encrypted keystores
+    Wallet(commands::wallet::Args),
+    /// List a hardware wallet's derived accounts with their balances
+    #[cfg(feature = "hardware")]
+    Accounts(commands::accounts::Args),
+    /// Merge an external signature into a `--unsigned` transaction and send it
+    Broadcast(commands::broadcast::Args),
+    /// Collect Safe owner signatures offline and execute once the threshold is met
+    Safe(commands::safe::Args),
+    /// Compute, deploy and inspect the user's ERC-4337 smart account
+    SmartAccount(commands::smart_account::Args),
+    /// Register, revoke and inspect scoped session keys of SMART_ACCOUNT
+    SessionKey(commands::session_key::Args),
+    /// Prove a lock record from a block's state root with eth_getProof
+    Prove(commands::prove::Args),
+    /// Read a raw storage slot of the contract, resolving mapping keys
+    Storage(commands::storage::Args),
+    /// Decode calldata against abi.json and a list of well-known functions
+    Decode(commands::decode::Args),
+    /// Print the calldata and unsigned transaction for a job without sending
+    Encode(commands::encode::Args),
+    /// Interactive prompt to call, estimate, send, encode and decode against the contract
+    Repl,
+    /// Print a mined transaction's receipt and logs, optionally decoded
+    Receipt(commands::receipt::Args),
+    /// Report whether a transaction is pending, succeeded or failed, and why
+    Status(commands::status::Args),
+    /// List the sender's stuck transactions and speed one up or cancel it
+    Pending(commands::pending::Args),
+    /// Chart recent base fees and tips and recommend fees per speed tier
+    Gas(commands::gas::Args),
+    /// Compare the gas past sends used with their estimates, per chain and kind
+    GasUsage(commands::gas_usage::Args),
+    /// Print the contract's events in a block range as JSON, oldest first
+    Events(commands::events::Args),
+    /// Print the contract's recent events as JSON, optionally following new ones
+    Listen(commands::listen::Args),
+    /// Watch the mempool (over WS_RPC_URL) for pending calls to the contract
+    WatchMempool,
+    /// Show pending transactions, submissions, gas, balances and events on one screen
+    #[cfg(feature = "tui")]
+    Dashboard(commands::dashboard::Args),
+    /// Alert when wallets on any chain drop below their balance thresholds
+    WatchBalances(commands::watch_balances::Args),
+    /// Alert, and exit, as soon as the contract's code or proxy implementation changes
+    WatchContract(commands::watch_contract::Args),
+    /// Compare the latency and error rate of RPC_URL and RPC_URLS
+    BenchRpc(commands::bench_rpc::Args),
+    /// Send locks for synthetic users at a fixed rate against a test deployment
+    Stress(commands::stress::Args),
+    /// Request test funds for the sender wallet from FAUCET_URL
+    Faucet(commands::faucet::Args),
+    /// Run a throwaway local anvil devnet for an end-to-end smoke test
+    Devnet(commands::devnet::Args),
+    /// Deploy the lock contract from compiled bytecode, optionally verifying it
+    Deploy(commands::deploy::Args),
+    /// Print the completion script for bash, zsh, fish, elvish or PowerShell
+    Completions(commands::completions::Args),
+}
 
-    println!("=== Wallet Information ===");
-    println!("Wallet Address: {:?}", wallet_address);
-    
-    // Check wallet balance
-    let balance = client.get_balance(wallet_address, None).await?;
-    println!("Wallet Balance: {} ETH", ethers::utils::format_units(balance, "ether")?);
-    
-    // Check user balance
-    let user_balance = client.get_balance(user, None).await?;
-    println!("User Balance: {} ETH", ethers::utils::format_units(user_balance, "ether")?);
-    println!();
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    style::init(cli.no_color);
 
-    // Check if balance is sufficient for the transaction
-    let gas_price = client.get_gas_price().await?;
-    println!("Current Gas Price: {} Gwei", ethers::utils::format_units(gas_price, "gwei")?);
-    
-    // Estimate gas for the transaction
-    let contract = MyContract::new(contract_address, client.clone());
-    let call = contract.lock(user, token, amount, nonce, signature).value(amount);
-    
-    println!("=== Transaction Details ===");
-    println!("Transaction Value: {} ETH", ethers::utils::format_units(amount, "ether")?);
-    
-    // Try to estimate gas (this might fail if there are insufficient funds)
-    match call.estimate_gas().await {
-        Ok(gas_estimate) => {
-            println!("Estimated Gas: {}", gas_estimate);
-            let total_cost = gas_estimate * gas_price + amount;
-            println!("Total Transaction Cost: {} ETH", ethers::utils::format_units(total_cost, "ether")?);
-            
-            if total_cost > balance {
-                println!("❌ INSUFFICIENT FUNDS: Need {} ETH, but wallet has {} ETH", 
-                    ethers::utils::format_units(total_cost, "ether")?,
-                    ethers::utils::format_units(balance, "ether")?);
-                return Ok(());
-            } else {
-                println!("✅ Sufficient funds available");
-            }
-        }
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
-            println!("❌ Failed to estimate gas: {:?}", e);
-            println!("This might be due to insufficient funds or invalid parameters");
+            eprintln!("Error: {:?}", e);
+            match e.downcast_ref::<Error>() {
+                Some(error) => ExitCode::from(error.exit_code()),
+                None => ExitCode::FAILURE,
+            }
         }
     }
-    println!();
+}
 
-    println!("=== Sending Transaction ===");
-    let tx = call.send().await?;
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    // Completions need neither settings nor secrets.
+    if let Some(Command::Completions(args)) = &cli.command {
+        commands::completions::run(args, Cli::command());
+        return Ok(());
+    }
+    // Nor does the config file's schema.
+    if let Some(Command::Config(args)) = &cli.command {
+        return commands::config::run(args);
+    }
+    // The devnet brings its own settings and must not pick up real ones.
+    if let Some(Command::Devnet(args)) = &cli.command {
+        return commands::devnet::run(args).await;
+    }
 
-    println!("Transaction Hash: {:?}", tx.tx_hash());
-    println!("Waiting for transaction to be mined...");
-    
-    let receipt = tx.await?;
-    match receipt {
-        Some(r) => {
-            println!("✅ Transaction mined in block: {:?}", r.block_number);
-            println!("Gas Used: {}", r.gas_used.unwrap_or_default());
-            println!("Status: {}", if r.status.unwrap_or_default() == U64::from(1) { "Success" } else { "Failed" });
-        }
-        None => {
-            println!("❌ Transaction receipt not found");
-        }
+    config::load_env_file(cli.env_file.as_deref())?;
+    let command = cli.command.unwrap_or_else(|| Command::Lock(Default::default()));
+
+    // Fetching the Vault secret is a network call, which the offline commands avoid.
+    if !matches!(command, Command::CheckConfig | Command::Decode(_)) {
+        secrets::init_vault_from_env().await?;
+    }
+    hooks::register();
+    // An invalid config file is check-config's to report.
+    if !matches!(command, Command::CheckConfig) {
+        plugins::register_wasm()?;
     }
 
-    Ok(())
+    match command {
+        Command::Lock(args) => commands::lock::run(args).await,
+        Command::LockNft(args) => commands::lock_nft::run(args).await,
+        Command::LockErc1155(args) => commands::lock_erc1155::run(args).await,
+        Command::Unlock(args) => commands::unlock::run(args).await,
+        Command::CheckConfig => commands::check_config::run(),
+        Command::Doctor => commands::doctor::run().await,
+        Command::Batch(args) => commands::batch::run(args).await,
+        Command::Stream(args) => commands::stream::run(args).await,
+        Command::Workflow(args) => commands::workflow::run(args).await,
+        Command::Script(args) => commands::script::run(args).await,
+        Command::Keyring(args) => commands::keyring::run(args),
+        Command::RotateKey(args) => commands::rotate_key::run(args).await,
+        Command::Wallet(args) => commands::wallet::run(args),
+        #[cfg(feature = "hardware")]
+        Command::Accounts(args) => commands::accounts::run(args).await,
+        Command::Broadcast(args) => commands::broadcast::run(args).await,
+        Command::Safe(args) => commands::safe::run(args).await,
+        Command::SmartAccount(args) => commands::smart_account::run(args).await,
+        Command::SessionKey(args) => commands::session_key::run(args).await,
+        Command::Prove(args) => commands::prove::run(args).await,
+        Command::Storage(args) => commands::storage::run(args).await,
+        Command::Decode(args) => commands::decode::run(args),
+        Command::Encode(args) => commands::encode::run(args),
+        Command::Repl => commands::repl::run().await,
+        Command::Receipt(args) => commands::receipt::run(args).await,
+        Command::Status(args) => commands::status::run(args).await,
+        Command::Pending(args) => commands::pending::run(args).await,
+        Command::Gas(args) => commands::gas::run(args).await,
+        Command::GasUsage(args) => commands::gas_usage::run(args).await,
+        Command::Events(args) => commands::events::run(args).await,
+        Command::Listen(args) => commands::listen::run(args).await,
+        Command::WatchMempool => commands::watch_mempool::run().await,
+        #[cfg(feature = "tui")]
+        Command::Dashboard(args) => commands::dashboard::run(args).await,
+        Command::WatchBalances(args) => commands::watch_balances::run(args).await,
+        Command::WatchContract(args) => commands::watch_contract::run(args).await,
+        Command::BenchRpc(args) => commands::bench_rpc::run(args).await,
+        Command::Stress(args) => commands::stress::run(args).await,
+        Command::Faucet(args) => commands::faucet::run(args).await,
+        Command::Deploy(args) => commands::deploy::run(args).await,
+        Command::Completions(_) | Command::Config(_) | Command::Devnet(_) => unreachable!("handled before loading settings"),
+    }
 }
@@ -0,0 +1,320 @@
+//! Mempool inspection: stuck transaction discovery and replacement for a
+//! sender account, and a watch for competing calls to the contract.
+//!
+//! A transaction is stuck when the account's pending nonce runs ahead of its
+//! latest (mined) nonce. The transactions themselves come from the node's
+//! txpool where it exposes one, and otherwise from the ledger's hashes.
+
+use crate::config::Job;
+use crate::contract::MyContractCalls;
+use crate::fees::Fees;
+use crate::ledger::Ledger;
+use crate::print_warn;
+use crate::profile::ReplacementPolicy;
+use crate::shutdown::{self, CancellationToken};
+use anyhow::Context;
+use ethers::abi::AbiDecode;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// One nonce between the latest and pending nonce.
+pub struct StuckTx {
+    pub nonce: U256,
+    /// The transaction occupying the nonce, if the node or ledger knows it.
+    pub tx: Option<Transaction>,
+    /// When the ledger recorded the broadcast, as a Unix timestamp.
+    pub submitted_at: Option<u64>,
+}
+
+/// The pending nonce gap of an account.
+pub struct Gap {
+    pub latest: U256,
+    pub pending: U256,
+    pub stuck: Vec<StuckTx>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TxpoolContentFrom {
+    #[serde(default)]
+    pending: BTreeMap<String, Transaction>,
+    #[serde(default)]
+    queued: BTreeMap<String, Transaction>,
+}
+
+/// Lists the transactions `sender` has waiting between its latest and
+/// pending nonce.
+pub async fn stuck_transactions<M: Middleware>(client: &M, ledger: &Ledger, sender: Address) -> anyhow::Result<Gap>
+where
+    M::Error: 'static,
+{
+    let latest = client.get_transaction_count(sender, Some(BlockNumber::Latest.into())).await?;
+    let pending = client.get_transaction_count(sender, Some(BlockNumber::Pending.into())).await?;
+    if pending <= latest {
+        return Ok(Gap { latest, pending, stuck: Vec::new() });
+    }
+
+    let mut known: BTreeMap<U256, Transaction> = BTreeMap::new();
+    match client.provider().request::<_, TxpoolContentFrom>("txpool_contentFrom", [sender]).await {
+        Ok(content) => {
+            for tx in content.pending.into_values().chain(content.queued.into_values()) {
+                known.insert(tx.nonce, tx);
+            }
+        }
+        // No txpool API: look up the hashes we broadcast ourselves.
+        Err(_) => {
+            for entry in ledger.entries().await? {
+                if let Some(tx) = client.get_transaction(entry.tx_hash).await? {
+                    if tx.from == sender && tx.block_number.is_none() && tx.nonce >= latest {
+                        known.insert(tx.nonce, tx);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut stuck = Vec::new();
+    let mut nonce = latest;
+    while nonce < pending {
+        let tx = known.remove(&nonce);
+        let submitted_at = match &tx {
+            Some(tx) => ledger.find_by_tx_hash(tx.hash).await?.map(|entry| entry.submitted_at),
+            None => None,
+        };
+        stuck.push(StuckTx { nonce, tx, submitted_at });
+        nonce += U256::one();
+    }
+    Ok(Gap { latest, pending, stuck })
+}
+
+/// Raises `fee` by `percent`, rounding up so the bump is never short.
+fn bump(fee: U256, percent: f64) -> U256 {
+    let per_mille = U256::from(1000 + (percent * 10.0).round() as u64);
+    fee * per_mille / 1000 + 1
+}
+
+/// Sets replacement fees on `tx`: the original's fees bumped by the policy's
+/// step, or the current suggestion if that is higher.
+fn set_replacement_fees(
+    tx: &mut TypedTransaction,
+    original: Option<&Transaction>,
+    current: Option<Fees>,
+    policy: &ReplacementPolicy,
+) {
+    let bump = |fee| bump(fee, policy.bump_percent);
+    let original_max_fee = original.and_then(|tx| tx.max_fee_per_gas.or(tx.gas_price)).map(bump);
+    let original_tip = original.and_then(|tx| tx.max_priority_fee_per_gas.or(tx.gas_price)).map(bump);
+    let max_fee = original_max_fee.max(current.map(|fees| fees.max_fee_per_gas));
+    let tip = original_tip.max(current.map(|fees| fees.max_priority_fee_per_gas));
+
+    match tx {
+        TypedTransaction::Eip1559(tx) => {
+            tx.max_fee_per_gas = max_fee;
+            tx.max_priority_fee_per_gas = tip.zip(max_fee).map(|(tip, max_fee)| tip.min(max_fee));
+        }
+        tx => {
+            if let Some(max_fee) = max_fee {
+                tx.set_gas_price(max_fee);
+            }
+        }
+    }
+}
+
+/// The same call as `original`, at the same nonce, with higher fees.
+pub fn speed_up(original: &Transaction, current: Option<Fees>, policy: &ReplacementPolicy) -> TypedTransaction {
+    let mut request = Eip1559TransactionRequest::new()
+        .from(original.from)
+        .value(original.value)
+        .data(original.input.clone())
+        .gas(original.gas)
+        .nonce(original.nonce);
+    if let Some(to) = original.to {
+        request = request.to(to);
+    }
+    if let Some(chain_id) = original.chain_id {
+        request = request.chain_id(chain_id.as_u64());
+    }
+    let mut tx = TypedTransaction::Eip1559(request);
+    set_replacement_fees(&mut tx, Some(original), current, policy);
+    tx
+}
+
+/// A zero-value transfer from `sender` to itself at `nonce`, which replaces
+/// whatever occupies the nonce.
+pub fn cancel(
+    sender: Address,
+    nonce: U256,
+    original: Option<&Transaction>,
+    current: Option<Fees>,
+    policy: &ReplacementPolicy,
+) -> TypedTransaction {
+    let request = Eip1559TransactionRequest::new()
+        .from(sender)
+        .to(sender)
+        .value(0)
+        .gas(21_000)
+        .nonce(nonce);
+    let mut tx = TypedTransaction::Eip1559(request);
+    set_replacement_fees(&mut tx, original, current, policy);
+    tx
+}
+
+/// Whether `tx`'s max fee is above the policy's ceiling. A cancellation is
+/// exempt, as it only ever burns 21000 gas.
+pub fn exceeds_ceiling(tx: &TypedTransaction, policy: &ReplacementPolicy) -> anyhow::Result<bool> {
+    Ok(match (policy.max_fee()?, tx.gas_price()) {
+        (Some(ceiling), Some(max_fee)) => max_fee > ceiling,
+        _ => false,
+    })
+}
+
+/// Subscribes to newPendingTransactions on the WebSocket endpoint `ws_url`
+/// and prints every pending call to `contract` not sent by `ignore`, flagging
+/// locks and redeems for the same user, token and nonce as `job`. Runs until
+/// the subscription ends, or until `cancel` is cancelled, which unsubscribes.
+pub async fn watch_contract(
+    ws_url: &str,
+    contract: Address,
+    job: Option<&Job>,
+    ignore: Option<Address>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let provider = Provider::<Ws>::connect(ws_url)
+        .await
+        .with_context(|| format!("failed to connect to {}", ws_url))?;
+    let mut hashes = provider.subscribe_pending_txs().await?;
+
+    while let Some(hash) = shutdown::unless_cancelled(cancel, hashes.next()).await {
+        let Some(hash) = hash else {
+            return Ok(());
+        };
+        // The subscription only carries hashes, and the transaction may
+        // already be gone by the time we ask for it.
+        let Ok(Some(tx)) = provider.get_transaction(hash).await else {
+            continue;
+        };
+        if tx.to != Some(contract) || Some(tx.from) == ignore {
+            continue;
+        }
+        report(&tx, job);
+    }
+    hashes.unsubscribe().await?;
+    Ok(())
+}
+
+fn report(tx: &Transaction, job: Option<&Job>) {
+    let (function, params) = match MyContractCalls::decode(&tx.input) {
+        Ok(MyContractCalls::Lock(call)) => ("lock", Some((call.user, call.token, call.nonce))),
+        Ok(MyContractCalls::RedeemWithSignature(call)) => ("redeemWithSignature", Some((call.user, call.token, call.nonce))),
+        Ok(_) => ("other", None),
+        Err(_) => ("unknown", None),
+    };
+    let conflicts = match (job, params) {
+        (Some(job), Some((user, token, nonce))) => user == job.user && token == job.token && nonce == job.nonce,
+        _ => false,
+    };
+    if conflicts {
+        print_warn!("CONFLICT: pending {} {:?} from {:?} uses the same user, token and nonce", function, tx.hash, tx.from);
+    } else {
+        println!("[mempool] pending {} {:?} from {:?}", function, tx.hash, tx.from);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENDER: Address = H160([0x11; 20]);
+    const CONTRACT: Address = H160([0x22; 20]);
+
+    fn gwei(amount: u64) -> U256 {
+        U256::from(amount) * 1_000_000_000u64
+    }
+
+    fn original(transaction_type: Option<u64>) -> Transaction {
+        let priced = |fee: u64| (transaction_type == Some(2)).then(|| gwei(fee));
+        Transaction {
+            hash: H256::repeat_byte(1),
+            nonce: 7.into(),
+            from: SENDER,
+            to: Some(CONTRACT),
+            value: 5.into(),
+            gas: 90_000.into(),
+            input: vec![0xde, 0xad].into(),
+            gas_price: Some(gwei(40)),
+            max_fee_per_gas: priced(40),
+            max_priority_fee_per_gas: priced(2),
+            transaction_type: transaction_type.map(Into::into),
+            chain_id: Some(8453.into()),
+            ..Default::default()
+        }
+    }
+
+    fn fees(max_fee: u64, tip: u64) -> Fees {
+        Fees { base_fee: gwei(1), max_fee_per_gas: gwei(max_fee), max_priority_fee_per_gas: gwei(tip) }
+    }
+
+    fn policy() -> ReplacementPolicy {
+        ReplacementPolicy::default()
+    }
+
+    fn eip1559(tx: &TypedTransaction) -> (U256, U256) {
+        match tx {
+            TypedTransaction::Eip1559(tx) => (tx.max_fee_per_gas.unwrap(), tx.max_priority_fee_per_gas.unwrap()),
+            _ => panic!("not an EIP-1559 transaction"),
+        }
+    }
+
+    #[test]
+    fn bumps_round_up() {
+        assert_eq!(bump(100.into(), 12.5), 113.into());
+        assert_eq!(bump(gwei(40), 12.5), gwei(45) + 1);
+        assert_eq!(bump(U256::zero(), 12.5), 1.into());
+    }
+
+    #[test]
+    fn speed_up_keeps_the_call() {
+        let tx = speed_up(&original(Some(2)), None, &policy());
+        assert_eq!(tx.from(), Some(&SENDER));
+        assert_eq!(tx.to_addr(), Some(&CONTRACT));
+        assert_eq!(tx.nonce(), Some(&7.into()));
+        assert_eq!(tx.value(), Some(&5.into()));
+        assert_eq!(tx.gas(), Some(&90_000.into()));
+        assert_eq!(tx.data().map(|data| data.to_vec()), Some(vec![0xde, 0xad]));
+        assert_eq!(tx.chain_id(), Some(8453.into()));
+        assert_eq!(eip1559(&tx), (gwei(45) + 1, gwei(2) * 1125 / 1000 + 1));
+        // A legacy original is priced from its gas price.
+        assert_eq!(eip1559(&speed_up(&original(None), None, &policy())), (gwei(45) + 1, gwei(45) + 1));
+    }
+
+    #[test]
+    fn speed_up_follows_a_higher_suggestion() {
+        let tx = speed_up(&original(Some(2)), Some(fees(60, 3)), &policy());
+        assert_eq!(eip1559(&tx), (gwei(60), gwei(3)));
+        // A suggestion below the bump is ignored.
+        let tx = speed_up(&original(Some(2)), Some(fees(41, 1)), &policy());
+        assert_eq!(eip1559(&tx), (gwei(45) + 1, gwei(2) * 1125 / 1000 + 1));
+        // The tip never exceeds the max fee.
+        let tx = speed_up(&original(Some(2)), Some(fees(30, 50)), &policy());
+        let (max_fee, tip) = eip1559(&tx);
+        assert_eq!(tip, max_fee);
+    }
+
+    #[test]
+    fn cancellations() {
+        let tx = cancel(SENDER, 7.into(), Some(&original(Some(2))), None, &policy());
+        assert_eq!(tx.to_addr(), Some(&SENDER));
+        assert_eq!(tx.value(), Some(&U256::zero()));
+        assert_eq!(tx.gas(), Some(&21_000.into()));
+        assert_eq!(tx.nonce(), Some(&7.into()));
+        assert_eq!(tx.data(), None);
+        assert_eq!(eip1559(&tx), (gwei(45) + 1, gwei(2) * 1125 / 1000 + 1));
+
+        // Without the original, the chain's suggestion prices it.
+        let tx = cancel(SENDER, 7.into(), None, Some(fees(30, 2)), &policy());
+        assert_eq!(eip1559(&tx), (gwei(30), gwei(2)));
+        let tx = cancel(SENDER, 7.into(), None, None, &policy());
+        assert!(matches!(&tx, TypedTransaction::Eip1559(request) if request.max_fee_per_gas.is_none()));
+    }
+}
@@ -0,0 +1,195 @@
+//! A JSON-RPC node for tests: a local HTTP server answering each request
+//! through a handler and recording what it was asked, so the send pipeline,
+//! batches and failover can be exercised without a chain.
+
+use crate::failover::{Failover, Settings};
+use crate::pipeline::{Client, Rpc};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const CHAIN_ID: u64 = 31337;
+
+/// How the node answers a request.
+pub enum Reply {
+    Result(Value),
+    /// A JSON-RPC error: the node answering, e.g. a revert.
+    Error(String),
+    /// A response that isn't JSON-RPC, as from a failing endpoint.
+    Garbage,
+}
+
+pub struct MockNode {
+    pub url: String,
+    calls: Arc<Mutex<Vec<(String, Value)>>>,
+}
+
+impl MockNode {
+    pub async fn start(handler: impl Fn(&str, &Value) -> Reply + Send + Sync + 'static) -> Self {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let url = http_server(move |_, body| {
+            let request: Value = serde_json::from_str(body).unwrap_or_default();
+            let (method, params) = (request["method"].as_str().unwrap_or_default(), &request["params"]);
+            recorded.lock().unwrap().push((method.to_string(), params.clone()));
+            match handler(method, params) {
+                Reply::Result(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string(),
+                Reply::Error(message) => {
+                    let error = json!({ "code": -32000, "message": message });
+                    json!({ "jsonrpc": "2.0", "id": request["id"], "error": error }).to_string()
+                }
+                Reply::Garbage => "<html>502 Bad Gateway</html>".to_string(),
+            }
+        })
+        .await;
+        Self { url, calls }
+    }
+
+    /// The params of each call to `method` so far.
+    pub fn calls(&self, method: &str) -> Vec<Value> {
+        let calls = self.calls.lock().unwrap();
+        calls.iter().filter(|(called, _)| called == method).map(|(_, params)| params.clone()).collect()
+    }
+
+    /// A provider polling the node every 10ms.
+    pub fn provider(&self) -> Rpc {
+        let failover = Failover::new(std::slice::from_ref(&self.url), Settings::default()).unwrap();
+        Provider::new(failover).interval(Duration::from_millis(10))
+    }
+
+    /// A client signing with a fixed test key for [`CHAIN_ID`].
+    pub fn client(&self) -> Arc<Client> {
+        Arc::new(SignerMiddleware::new(self.provider(), wallet()))
+    }
+}
+
+pub fn wallet() -> LocalWallet {
+    LocalWallet::from_bytes(&[0x42; 32]).unwrap().with_chain_id(CHAIN_ID)
+}
+
+/// An address nothing listens on, for an endpoint that can't be reached.
+pub async fn closed_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+/// The answers of a quiet chain at block 100: no transactions, nothing
+/// mined and 1 gwei gas, with a 21000 estimate and an eth_call returning
+/// zero words. Handlers fall back on it for the calls a test doesn't care
+/// about.
+pub fn idle(method: &str, _params: &Value) -> Reply {
+    Reply::Result(match method {
+        "eth_chainId" => json!(U64::from(CHAIN_ID)),
+        "eth_blockNumber" => json!(U64::from(100)),
+        "eth_getTransactionCount" | "eth_getBalance" => json!(U256::zero()),
+        "eth_gasPrice" => json!(U256::exp10(9)),
+        "eth_maxPriorityFeePerGas" => json!(U256::exp10(8)),
+        "eth_estimateGas" => json!(U256::from(21_000)),
+        "eth_call" => json!(Bytes::from(vec![0; 96])),
+        "eth_getTransactionByHash" | "eth_getTransactionReceipt" => Value::Null,
+        _ => return Reply::Error(format!("the method {} does not exist", method)),
+    })
+}
+
+/// The hash of a raw transaction sent with eth_sendRawTransaction.
+pub fn raw_hash(params: &Value) -> H256 {
+    let raw: Bytes = serde_json::from_value(params[0].clone()).unwrap();
+    H256(ethers::utils::keccak256(&raw))
+}
+
+/// The transaction behind `raw_params`, as eth_getTransactionByHash returns it.
+pub fn transaction(raw_params: &Value) -> Value {
+    let raw: Bytes = serde_json::from_value(raw_params[0].clone()).unwrap();
+    let (tx, signature) = TypedTransaction::decode_signed(&ethers::utils::rlp::Rlp::new(&raw)).unwrap();
+    let (max_fee_per_gas, max_priority_fee_per_gas) = match &tx {
+        TypedTransaction::Eip1559(request) => (request.max_fee_per_gas, request.max_priority_fee_per_gas),
+        _ => (None, None),
+    };
+    let transaction = Transaction {
+        hash: raw_hash(raw_params),
+        nonce: tx.nonce().copied().unwrap_or_default(),
+        from: signature.recover(tx.sighash()).unwrap(),
+        to: tx.to_addr().copied(),
+        value: tx.value().copied().unwrap_or_default(),
+        gas: tx.gas().copied().unwrap_or_default(),
+        gas_price: tx.gas_price(),
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        input: tx.data().cloned().unwrap_or_default(),
+        chain_id: tx.chain_id().map(|id| id.as_u64().into()),
+        ..Default::default()
+    };
+    json!(transaction)
+}
+
+/// A receipt for `hash` mined in block 100, successful or reverted.
+pub fn receipt(hash: H256, success: bool) -> Value {
+    json!(TransactionReceipt {
+        transaction_hash: hash,
+        block_hash: Some(H256::repeat_byte(0xb1)),
+        block_number: Some(100.into()),
+        gas_used: Some(21_000.into()),
+        effective_gas_price: Some(U256::exp10(9)),
+        status: Some(U64::from(success as u64)),
+        ..Default::default()
+    })
+}
+
+type Responder = dyn Fn(&str, &str) -> String + Send + Sync;
+
+/// Starts a local HTTP server answering every request with a 200 and the
+/// body `handler` returns for the request's head (the request line and the
+/// headers, with their names in lower case) and body. Returns its URL.
+pub async fn http_server(handler: impl Fn(&str, &str) -> String + Send + Sync + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handler = Arc::new(handler) as Arc<Responder>;
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve(stream, handler.clone()));
+        }
+    });
+    url
+}
+
+async fn serve(stream: TcpStream, handler: Arc<Responder>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        let (mut head, mut length) = (String::new(), 0);
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            match line.split_once(':') {
+                Some((name, value)) => {
+                    let name = name.to_lowercase();
+                    if name == "content-length" {
+                        length = value.trim().parse().unwrap_or(0);
+                    }
+                    head.push_str(&format!("{}:{}\n", name, value));
+                }
+                None => head.push_str(&format!("{}\n", line)),
+            }
+        }
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).await.is_err() {
+            return;
+        }
+        let response = handler(&head, &String::from_utf8_lossy(&body));
+        let head =
+            format!("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n", response.len());
+        if writer.write_all(format!("{}{}", head, response).as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! ERC-721 and ERC-1155 preflight for NFT locks: the contract pulls the
+//! tokens from the user, so the user must hold them and have approved the
+//! contract to move them.
+
+use crate::audit;
+use crate::contract::{Erc1155, Erc721};
+use crate::pipeline::Client;
+use crate::print_ok;
+use anyhow::Context;
+use ethers::prelude::*;
+use std::sync::Arc;
+
+const APPROVAL_PURPOSE: &str = "nft approval";
+
+/// How the lock contract may move the token, if at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Approval {
+    /// `getApproved(tokenId)` is the contract.
+    Token,
+    /// The owner made the contract an operator with `setApprovalForAll`.
+    Operator,
+    Missing,
+}
+
+/// The token's owner and whether `spender` may move it.
+pub async fn approval<M: Middleware + 'static>(
+    client: Arc<M>,
+    token: Address,
+    token_id: U256,
+    spender: Address,
+) -> anyhow::Result<(Address, Approval)> {
+    let erc721 = Erc721::new(token, client);
+    let owner = erc721
+        .owner_of(token_id)
+        .call()
+        .await
+        .with_context(|| format!("ownerOf({}) failed; does token {} of {:?} exist?", token_id, token_id, token))?;
+    if erc721.get_approved(token_id).call().await? == spender {
+        return Ok((owner, Approval::Token));
+    }
+    let approval = match erc721.is_approved_for_all(owner, spender).call().await? {
+        true => Approval::Operator,
+        false => Approval::Missing,
+    };
+    Ok((owner, approval))
+}
+
+/// Approves `spender` for the token from `client`, which must own it, and
+/// waits for the approval to be mined.
+pub async fn approve(client: &Arc<Client>, token: Address, token_id: U256, spender: Address) -> anyhow::Result<()> {
+    let tx = Erc721::new(token, client.clone()).approve(spender, token_id).tx;
+    let pending = audit::send_transaction(&**client, tx, APPROVAL_PURPOSE).await?;
+    println!("Approval Hash: {:?}", pending.tx_hash());
+    let receipt = pending.await?.context("approval dropped before it was mined")?;
+    audit::record_receipt(client.address(), APPROVAL_PURPOSE, &receipt)?;
+    anyhow::ensure!(receipt.status == Some(U64::from(1)), "approval {:?} reverted", receipt.transaction_hash);
+    print_ok!("Approved {:?} for token {}", spender, token_id);
+    Ok(())
+}
+
+/// The ERC-1155 positions, as `(id, amount)`, that `user` holds less of than
+/// needed, with what they hold: `(id, held, amount)`.
+pub async fn erc1155_shortfalls<M: Middleware + 'static>(
+    client: Arc<M>,
+    token: Address,
+    user: Address,
+    positions: &[(U256, U256)],
+) -> anyhow::Result<Vec<(U256, U256, U256)>> {
+    let ids: Vec<U256> = positions.iter().map(|(id, _)| *id).collect();
+    let held = Erc1155::new(token, client).balance_of_batch(vec![user; ids.len()], ids).call().await?;
+    anyhow::ensure!(held.len() == positions.len(), "balanceOfBatch returned {} balances for {} ids", held.len(), positions.len());
+    Ok(shortfalls(positions, &held))
+}
+
+fn shortfalls(positions: &[(U256, U256)], held: &[U256]) -> Vec<(U256, U256, U256)> {
+    positions
+        .iter()
+        .zip(held)
+        .filter(|((_, amount), held)| *held < amount)
+        .map(|((id, amount), held)| (*id, *held, *amount))
+        .collect()
+}
+
+/// Whether `operator` may move all of `owner`'s ERC-1155 tokens.
+pub async fn erc1155_is_operator<M: Middleware + 'static>(
+    client: Arc<M>,
+    token: Address,
+    owner: Address,
+    operator: Address,
+) -> anyhow::Result<bool> {
+    Ok(Erc1155::new(token, client).is_approved_for_all(owner, operator).call().await?)
+}
+
+/// Makes `operator` an operator of `client`'s ERC-1155 tokens with
+/// `setApprovalForAll` and waits for it to be mined.
+pub async fn erc1155_approve(client: &Arc<Client>, token: Address, operator: Address) -> anyhow::Result<()> {
+    let tx = Erc1155::new(token, client.clone()).set_approval_for_all(operator, true).tx;
+    let pending = audit::send_transaction(&**client, tx, APPROVAL_PURPOSE).await?;
+    println!("Approval Hash: {:?}", pending.tx_hash());
+    let receipt = pending.await?.context("approval dropped before it was mined")?;
+    audit::record_receipt(client.address(), APPROVAL_PURPOSE, &receipt)?;
+    anyhow::ensure!(receipt.status == Some(U64::from(1)), "approval {:?} reverted", receipt.transaction_hash);
+    print_ok!("Approved {:?} as an operator", operator);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erc1155_shortfall() {
+        let positions: [(U256, U256); 3] = [(1.into(), 10.into()), (2.into(), 5.into()), (3.into(), 1.into())];
+        let held: [U256; 3] = [10.into(), 4.into(), 0.into()];
+        let expected: Vec<(U256, U256, U256)> = vec![(2.into(), 4.into(), 5.into()), (3.into(), 0.into(), 1.into())];
+        assert_eq!(shortfalls(&positions, &held), expected);
+    }
+}
@@ -0,0 +1,139 @@
+//! Lock nonce discovery. The contract keeps no per-user nonce counter, only
+//! `locks(user, token, nonce)` records, so the next nonce is the first one
+//! without a record.
+
+use crate::contract::MyContract;
+use ethers::prelude::*;
+
+/// Returns whether `locks(user, token, nonce)` holds a record.
+pub async fn is_lock_nonce_used<M: Middleware + 'static>(
+    contract: &MyContract<M>,
+    user: Address,
+    token: Address,
+    nonce: U256,
+) -> anyhow::Result<bool> {
+    is_lock_nonce_used_at(contract, user, token, nonce, BlockNumber::Latest.into()).await
+}
+
+/// [`is_lock_nonce_used`] as of `block`.
+pub async fn is_lock_nonce_used_at<M: Middleware + 'static>(
+    contract: &MyContract<M>,
+    user: Address,
+    token: Address,
+    nonce: U256,
+    block: BlockId,
+) -> anyhow::Result<bool> {
+    let (amount, timestamp, flag) = contract.locks(user, token, nonce).block(block).call().await?;
+    Ok(!amount.is_zero() || !timestamp.is_zero() || flag)
+}
+
+/// Finds the first unused lock nonce for `(user, token)`.
+///
+/// Nonces are assumed to be handed out sequentially, so the used ones form a
+/// prefix `0..n`; this gallops to find an unused upper bound and then binary
+/// searches for `n`, costing O(log n) calls instead of a linear scan.
+pub async fn next_lock_nonce<M: Middleware + 'static>(
+    contract: &MyContract<M>,
+    user: Address,
+    token: Address,
+) -> anyhow::Result<U256> {
+    if !is_lock_nonce_used(contract, user, token, U256::zero()).await? {
+        return Ok(U256::zero());
+    }
+
+    // Invariant: `used` is used, `unused` is not.
+    let mut used = U256::zero();
+    let mut unused = U256::one();
+    while is_lock_nonce_used(contract, user, token, unused).await? {
+        used = unused;
+        unused = unused
+            .checked_mul(2.into())
+            .ok_or_else(|| anyhow::anyhow!("no unused lock nonce found for {:?}", user))?;
+    }
+
+    while unused - used > U256::one() {
+        let mid = used + (unused - used) / 2;
+        if is_lock_nonce_used(contract, user, token, mid).await? {
+            used = mid;
+        } else {
+            unused = mid;
+        }
+    }
+
+    Ok(unused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{encode, Token};
+    use ethers::providers::MockProvider;
+    use ethers::utils;
+    use std::sync::Arc;
+
+    const USER: Address = H160([0x11; 20]);
+    const TOKEN: Address = H160([0x22; 20]);
+
+    fn record(amount: u64, timestamp: u64, redeemed: bool) -> Bytes {
+        encode(&[Token::Uint(amount.into()), Token::Uint(timestamp.into()), Token::Bool(redeemed)]).into()
+    }
+
+    fn contract(mock: &MockProvider) -> MyContract<Provider<MockProvider>> {
+        MyContract::new(Address::repeat_byte(0x33), Arc::new(Provider::new(mock.clone())))
+    }
+
+    /// Runs `next_lock_nonce` against a contract whose nonces `0..used` are
+    /// taken, checking that it reads exactly the records of `queries`, in
+    /// order.
+    async fn search(used: u64, queries: &[u64]) -> U256 {
+        let mock = MockProvider::new();
+        // The mock answers from the back.
+        for nonce in queries.iter().rev() {
+            mock.push::<Bytes, _>(match *nonce < used {
+                true => record(5, 1_700_000_000 + nonce, false),
+                false => record(0, 0, false),
+            })
+            .unwrap();
+        }
+        let contract = contract(&mock);
+        let next = next_lock_nonce(&contract, USER, TOKEN).await.unwrap();
+        for nonce in queries {
+            let call = contract.locks(USER, TOKEN, (*nonce).into());
+            let latest = BlockId::from(BlockNumber::Latest);
+            mock.assert_request("eth_call", [utils::serialize(&call.tx), utils::serialize(&latest)]).unwrap();
+        }
+        next
+    }
+
+    #[tokio::test]
+    async fn gallops_then_bisects() {
+        assert_eq!(search(0, &[0]).await, 0.into());
+        assert_eq!(search(1, &[0, 1]).await, 1.into());
+        assert_eq!(search(2, &[0, 1, 2]).await, 2.into());
+        assert_eq!(search(5, &[0, 1, 2, 4, 8, 6, 5]).await, 5.into());
+        assert_eq!(search(8, &[0, 1, 2, 4, 8, 6, 7]).await, 8.into());
+        assert_eq!(search(9, &[0, 1, 2, 4, 8, 16, 12, 10, 9]).await, 9.into());
+    }
+
+    #[tokio::test]
+    async fn any_field_marks_a_nonce_used() {
+        for (record, used) in [
+            (record(0, 0, false), false),
+            (record(5, 0, false), true),
+            (record(0, 1_700_000_000, false), true),
+            (record(0, 0, true), true),
+        ] {
+            let mock = MockProvider::new();
+            mock.push::<Bytes, _>(record).unwrap();
+            assert_eq!(is_lock_nonce_used(&contract(&mock), USER, TOKEN, 7.into()).await.unwrap(), used);
+        }
+    }
+
+    #[tokio::test]
+    async fn rpc_errors_propagate() {
+        let mock = MockProvider::new();
+        mock.push::<Bytes, _>(record(5, 1, false)).unwrap();
+        // Nothing left to answer the second read with.
+        assert!(next_lock_nonce(&contract(&mock), USER, TOKEN).await.is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! User-defined result lines: a template such as
+//! `{{tx_hash}} {{status}} {{gas_used}}` rendered from a job's outcome, so
+//! each downstream consumer gets exactly the fields it needs.
+//!
+//! `\t` and `\n` in a template stand for a tab and a newline.
+
+use crate::batch::{Outcome, Status};
+use crate::config::Job;
+use ethers::prelude::*;
+
+/// Fields a single send's result line can use.
+pub const RECEIPT_FIELDS: &[&str] = &[
+    "kind", "user", "token", "amount", "nonce", "tx_hash", "status", "block", "gas_used", "effective_gas_price", "from",
+];
+
+/// Fields a batch row's result line can use.
+pub const BATCH_FIELDS: &[&str] = &["index", "sender", "account_nonce", "tx_hash", "status", "block", "gas_used", "error"];
+
+/// Fails if `template` uses a placeholder outside `fields` or leaves one
+/// unclosed. Checked before sending, so a typo doesn't surface only after
+/// the transaction is out.
+pub fn validate(template: &str, fields: &[&str]) -> anyhow::Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| anyhow::anyhow!("unclosed placeholder in output template {:?}", template))?;
+        let name = after[..end].trim();
+        anyhow::ensure!(
+            fields.contains(&name),
+            "unknown field {:?} in output template; available: {}",
+            name,
+            fields.join(", ")
+        );
+        rest = &after[end + 2..];
+    }
+    Ok(())
+}
+
+/// Fills `template`'s placeholders from `values`; fields without a value
+/// render as `-`.
+pub fn render(template: &str, values: &[(&str, Option<String>)]) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = after[..end].trim();
+        let value = values.iter().find(|(field, _)| *field == name).and_then(|(_, value)| value.as_deref());
+        rendered.push_str(value.unwrap_or("-"));
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered.replace("\\t", "\t").replace("\\n", "\n")
+}
+
+/// The [`RECEIPT_FIELDS`] of a `kind` job's outcome.
+pub fn receipt_values(kind: &str, job: &Job, receipt: Option<&TransactionReceipt>) -> Vec<(&'static str, Option<String>)> {
+    let status = match receipt {
+        Some(receipt) if receipt.status == Some(U64::from(1)) => "confirmed",
+        Some(_) => "reverted",
+        None => "dropped",
+    };
+    vec![
+        ("kind", Some(kind.to_string())),
+        ("user", Some(format!("{:?}", job.user))),
+        ("token", Some(format!("{:?}", job.token))),
+        ("amount", Some(job.amount.to_string())),
+        ("nonce", Some(job.nonce.to_string())),
+        ("tx_hash", receipt.map(|r| format!("{:?}", r.transaction_hash))),
+        ("status", Some(status.to_string())),
+        ("block", receipt.and_then(|r| r.block_number).map(|block| block.to_string())),
+        ("gas_used", receipt.and_then(|r| r.gas_used).map(|gas| gas.to_string())),
+        ("effective_gas_price", receipt.and_then(|r| r.effective_gas_price).map(|price| price.to_string())),
+        ("from", receipt.map(|r| format!("{:?}", r.from))),
+    ]
+}
+
+/// The [`BATCH_FIELDS`] of a batch row's outcome.
+pub fn batch_values(outcome: &Outcome) -> Vec<(&'static str, Option<String>)> {
+    let (block, gas_used, error) = match &outcome.status {
+        Status::Confirmed { block, gas_used } => (*block, Some(gas_used.to_string()), None),
+        Status::Reverted { block } => (*block, None, None),
+        Status::Dropped => (None, None, None),
+        Status::Skipped(reason) | Status::Failed(reason) => (None, None, Some(reason.clone())),
+    };
+    vec![
+        ("index", Some((outcome.index + 1).to_string())),
+        ("sender", Some(format!("{:?}", outcome.sender))),
+        ("account_nonce", outcome.nonce.map(|nonce| nonce.to_string())),
+        ("tx_hash", outcome.tx_hash.map(|hash| format!("{:?}", hash))),
+        ("status", Some(outcome.status.name().to_string())),
+        ("block", block.map(|block| block.to_string())),
+        ("gas_used", gas_used),
+        ("error", error),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        assert!(validate("{{tx_hash}} {{ status }}", RECEIPT_FIELDS).is_ok());
+        assert!(validate("no placeholders", RECEIPT_FIELDS).is_ok());
+        assert!(validate("{{tx_hash}} {{colour}}", RECEIPT_FIELDS).is_err());
+        assert!(validate("{{tx_hash", RECEIPT_FIELDS).is_err());
+        assert!(validate("{{index}}", RECEIPT_FIELDS).is_err());
+        assert!(validate("{{index}}", BATCH_FIELDS).is_ok());
+    }
+
+    #[test]
+    fn rendering() {
+        let values = [("tx_hash", Some("0xabc".to_string())), ("status", Some("confirmed".to_string())), ("block", None)];
+        assert_eq!(render("{{tx_hash}} {{status}}", &values), "0xabc confirmed");
+        assert_eq!(render("{{ tx_hash }},{{block}}", &values), "0xabc,-");
+        assert_eq!(render("{{tx_hash}}\\t{{status}}\\n", &values), "0xabc\tconfirmed\n");
+        assert_eq!(render("plain", &values), "plain");
+    }
+}
@@ -0,0 +1,187 @@
+//! Approval and lock in one transaction, for ERC-20 tokens implementing
+//! EIP-2612 `permit`. Approving and then locking leaves the approval on
+//! chain on its own for a block or more, where it can be used before the
+//! lock lands. Instead the user signs a permit off chain, and a router
+//! calls `permit` and `lock` in a single transaction that reverts as a whole
+//! if either fails.
+//!
+//! The router is LOCK_ROUTER, any contract with Multicall3's
+//! `aggregate3Value`; by default Multicall3's canonical deployment. The
+//! permit is signed with USER_PRIVATE_KEY, which must be the job's user.
+
+use crate::audit;
+use crate::config::{self, env_var, Config, Job};
+use crate::contract::{Call3Value, Erc20Permit, LockRouter};
+use crate::validity;
+use anyhow::Context;
+use ethers::abi::{encode, Token};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Multicall3, deployed at the same address on most chains.
+pub const MULTICALL3: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+
+const PERMIT_TYPE: &str = "Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)";
+
+/// LOCK_ROUTER, or Multicall3.
+pub fn router() -> anyhow::Result<Address> {
+    let router = env_var("LOCK_ROUTER").unwrap_or_else(|| MULTICALL3.to_string());
+    router.parse().with_context(|| format!("invalid LOCK_ROUTER {:?}", router))
+}
+
+/// The USER_PRIVATE_KEY wallet, which must be `job`'s user.
+pub fn user_wallet(config: &Config, job: &Job) -> anyhow::Result<LocalWallet> {
+    let wallet = config::secret("USER_PRIVATE_KEY")?.parse::<LocalWallet>()?.with_chain_id(config.chain_id);
+    anyhow::ensure!(
+        wallet.address() == job.user,
+        "USER_PRIVATE_KEY is {:?}, not the job's user {:?}",
+        wallet.address(),
+        job.user
+    );
+    Ok(wallet)
+}
+
+/// A signed approval of `value` for `spender`, valid until `deadline`.
+pub struct Permit {
+    pub owner: Address,
+    pub spender: Address,
+    pub value: U256,
+    pub deadline: U256,
+    pub signature: Signature,
+}
+
+/// The EIP-712 digest a permit signs, under the token's domain separator.
+pub fn digest(
+    domain_separator: [u8; 32],
+    owner: Address,
+    spender: Address,
+    value: U256,
+    nonce: U256,
+    deadline: U256,
+) -> H256 {
+    let permit = keccak256(encode(&[
+        Token::FixedBytes(keccak256(PERMIT_TYPE).to_vec()),
+        Token::Address(owner),
+        Token::Address(spender),
+        Token::Uint(value),
+        Token::Uint(nonce),
+        Token::Uint(deadline),
+    ]));
+    let mut message = vec![0x19, 0x01];
+    message.extend_from_slice(&domain_separator);
+    message.extend_from_slice(&permit);
+    H256(keccak256(message))
+}
+
+/// Signs a permit for `spender` to pull `value` of `token` from the wallet,
+/// valid for `valid_for` from the latest block's timestamp, using the
+/// token's current permit nonce.
+pub async fn sign<M: Middleware + 'static>(
+    client: Arc<M>,
+    wallet: &LocalWallet,
+    token: Address,
+    spender: Address,
+    value: U256,
+    valid_for: Duration,
+) -> anyhow::Result<Permit>
+where
+    M::Error: 'static,
+{
+    let deadline = U256::from(validity::chain_time(&*client).await? + valid_for.as_secs());
+    let erc20 = Erc20Permit::new(token, client);
+    let not_permit = || format!("token {:?} doesn't implement EIP-2612 permit", token);
+    let nonce = erc20.nonces(wallet.address()).call().await.with_context(not_permit)?;
+    let domain_separator = erc20.domain_separator().call().await.with_context(not_permit)?;
+    let signature = wallet.sign_hash(digest(domain_separator, wallet.address(), spender, value, nonce, deadline))?;
+    let params = json!({ "token": token, "spender": spender, "value": value, "nonce": nonce, "deadline": deadline });
+    audit::record_signature(wallet.address(), "permit", params)?;
+    Ok(Permit { owner: wallet.address(), spender, value, deadline, signature })
+}
+
+/// The router call that runs `permit` on `token` and then `lock`, attaching
+/// `value` to the lock.
+pub fn bundle<M: Middleware + 'static>(
+    client: Arc<M>,
+    router: Address,
+    token: Address,
+    permit: &Permit,
+    lock: &ContractCall<M, ()>,
+    value: U256,
+) -> anyhow::Result<ContractCall<M, ()>> {
+    let (mut r, mut s) = ([0; 32], [0; 32]);
+    permit.signature.r.to_big_endian(&mut r);
+    permit.signature.s.to_big_endian(&mut s);
+    let approve = Erc20Permit::new(token, client.clone())
+        .permit(permit.owner, permit.spender, permit.value, permit.deadline, permit.signature.v as u8, r, s)
+        .calldata()
+        .context("permit call has no calldata")?;
+    let lock_data = lock.calldata().context("lock call has no calldata")?;
+    let calls = vec![
+        Call3Value { target: token, allow_failure: false, value: U256::zero(), call_data: approve },
+        Call3Value { target: permit.spender, allow_failure: false, value, call_data: lock_data },
+    ];
+    Ok(LockRouter::new(router, client).aggregate_3_value(calls).value(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{Aggregate3ValueCall, MyContract, PermitCall};
+    use ethers::abi::AbiDecode;
+    use ethers::types::transaction::eip712::{EIP712Domain, Eip712, Eip712DomainType, TypedData};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn digest_matches_typed_data() {
+        let (owner, spender) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let domain = EIP712Domain {
+            name: Some("USD Coin".to_string()),
+            version: Some("2".to_string()),
+            chain_id: Some(1.into()),
+            verifying_contract: Some(Address::repeat_byte(3)),
+            salt: None,
+        };
+        let field = |name: &str, r#type: &str| Eip712DomainType { name: name.to_string(), r#type: r#type.to_string() };
+        let types = BTreeMap::from([(
+            "Permit".to_string(),
+            vec![
+                field("owner", "address"),
+                field("spender", "address"),
+                field("value", "uint256"),
+                field("nonce", "uint256"),
+                field("deadline", "uint256"),
+            ],
+        )]);
+        let message = serde_json::from_value(serde_json::json!({
+            "owner": owner, "spender": spender, "value": 1000, "nonce": 7, "deadline": 1_700_000_000u64,
+        }))
+        .unwrap();
+        let typed = TypedData { domain: domain.clone(), types, primary_type: "Permit".to_string(), message };
+        let expected = typed.encode_eip712().unwrap();
+        let actual = digest(domain.separator(), owner, spender, 1000.into(), 7.into(), 1_700_000_000u64.into());
+        assert_eq!(actual, H256(expected));
+    }
+
+    #[test]
+    fn bundling_permit_and_lock() {
+        let (provider, _) = Provider::mocked();
+        let client = Arc::new(provider);
+        let (owner, token, spender, router) =
+            (Address::repeat_byte(1), Address::repeat_byte(2), Address::repeat_byte(3), Address::repeat_byte(4));
+        let signature = Signature { r: 5.into(), s: 6.into(), v: 27 };
+        let permit = Permit { owner, spender, value: 1000.into(), deadline: 1_700_000_000u64.into(), signature };
+        let lock = MyContract::new(spender, client.clone()).lock(owner, token, 1000.into(), 7.into(), Bytes::new());
+
+        let call = bundle(client, router, token, &permit, &lock, 10.into()).unwrap();
+        assert_eq!(call.tx.to(), Some(&NameOrAddress::Address(router)));
+        assert_eq!(call.tx.value(), Some(&10.into()));
+        let decoded = Aggregate3ValueCall::decode(call.calldata().unwrap()).unwrap();
+        let targets: Vec<_> = decoded.calls.iter().map(|call| (call.target, call.allow_failure, call.value)).collect();
+        assert_eq!(targets, vec![(token, false, U256::zero()), (spender, false, 10.into())]);
+        assert_eq!(decoded.calls[1].call_data, lock.calldata().unwrap());
+        assert_eq!(decoded.calls[0].call_data[..4], PermitCall::selector());
+    }
+}
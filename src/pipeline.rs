@@ -0,0 +1,1140 @@
+use crate::audit;
+use crate::bytecode;
+use crate::config::{self, Config, Job};
+use crate::contract::MYCONTRACT_ABI;
+use crate::error::Error;
+use crate::explorer::Explorer;
+use crate::failover::Failover;
+use crate::fees::FeeModel;
+use crate::ledger::{Entry, Ledger};
+use crate::mempool;
+use crate::policy;
+use crate::price::{self, PriceSource};
+use crate::profile::{self, CeilingAction, ReplacementPolicy};
+use crate::shutdown::{self, CancellationToken};
+use crate::storage;
+use crate::style;
+use crate::token;
+use crate::trace;
+use crate::units;
+use crate::{print_error, print_ok, print_warn};
+use anyhow::Context;
+use ethers::middleware::gas_oracle::{GasOracle, GasOracleMiddleware};
+use ethers::middleware::nonce_manager::NonceManagerMiddleware;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The provider over RPC_URL and RPC_URLS; see [`crate::failover`].
+pub type Rpc = Provider<Failover>;
+
+/// The signing client every contract call is sent through.
+pub type Client = SignerMiddleware<Rpc, LocalWallet>;
+
+/// What the preflight and send pipeline needs of a middleware stack beyond
+/// [`Middleware`]: the key it signs with and that key's chain. The pipeline
+/// is generic over it, so an embedder can stack its own middleware (a nonce
+/// manager, a gas oracle, a mock) around the signer and still reuse the
+/// preflight, send and verify steps.
+///
+/// Implemented for [`SignerMiddleware`] over a [`LocalWallet`] and for the
+/// nonce manager and gas oracle middlewares wrapping one.
+pub trait Sender: Middleware {
+    /// The address transactions are signed by.
+    fn sender(&self) -> Address;
+    /// The chain the key signs for.
+    fn chain_id(&self) -> u64;
+    /// The local key, where there is one. Chains that need transactions
+    /// without EIP-155 replay protection can only be sent to with one.
+    fn wallet(&self) -> Option<&LocalWallet> {
+        None
+    }
+}
+
+impl<M: Middleware> Sender for SignerMiddleware<M, LocalWallet> {
+    fn sender(&self) -> Address {
+        self.address()
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.signer().chain_id()
+    }
+
+    fn wallet(&self) -> Option<&LocalWallet> {
+        Some(self.signer())
+    }
+}
+
+impl<M: Sender> Sender for NonceManagerMiddleware<M> {
+    fn sender(&self) -> Address {
+        self.inner().sender()
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.inner().chain_id()
+    }
+
+    fn wallet(&self) -> Option<&LocalWallet> {
+        self.inner().wallet()
+    }
+}
+
+impl<M: Sender, G: GasOracle> Sender for GasOracleMiddleware<M, G> {
+    fn sender(&self) -> Address {
+        self.inner().sender()
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.inner().chain_id()
+    }
+
+    fn wallet(&self) -> Option<&LocalWallet> {
+        self.inner().wallet()
+    }
+}
+
+pub fn print_configuration(config: &Config, job: &Job) {
+    print_configuration_as(config, job, "Amount");
+}
+
+/// [`print_configuration`] with the job's `amount` shown under another
+/// label, such as "Token ID" for an NFT lock.
+pub fn print_configuration_as(config: &Config, job: &Job, amount_label: &str) {
+    const WIDTH: usize = "Contract Address".len();
+    println!("{}", style::header("Configuration"));
+    println!("{}", style::field("RPC URL", WIDTH, &config.rpc_url));
+    println!("{}", style::field("Chain ID", WIDTH, config.chain_id));
+    println!("{}", style::field("Contract Address", WIDTH, style::dim(format!("{:?}", config.contract_address))));
+    println!("{}", style::field("User Address", WIDTH, style::dim(format!("{:?}", job.user))));
+    println!("{}", style::field("Token Address", WIDTH, style::dim(format!("{:?}", job.token))));
+    println!("{}", style::field(amount_label, WIDTH, job.amount));
+    println!("{}", style::field("Nonce", WIDTH, job.nonce));
+    println!("{}", style::field("Signature", WIDTH, style::dim(format!("0x{}", hex::encode(&job.signature)))));
+    println!();
+}
+
+/// Prints the job's token and amount in whole tokens, from the token's ERC-20
+/// metadata; a scale mistake is far easier to spot in "1,500.00 USDC" than
+/// in 1500000000.
+pub async fn print_token<M: Sender + 'static>(client: Arc<M>, job: &Job) -> anyhow::Result<()> {
+    println!("=== Token ===");
+    if job.is_native()? {
+        let native = profile::native_currency(client.chain_id())?;
+        println!("Token: native currency ({})", native.symbol);
+        println!("Amount: {} ({} wei)", token::format_amount(job.amount, native.decimals), job.amount);
+        println!();
+        return Ok(());
+    }
+    match token::metadata(client, job.token).await {
+        Some(info) => {
+            println!("Token: {} ({}), {} decimals", info.name, info.symbol, info.decimals);
+            println!("Amount: {} ({} base units)", info.format_amount(job.amount), job.amount);
+        }
+        None => {
+            print_warn!("Could not read ERC-20 metadata of {:?}; the amount is in base units", job.token);
+            println!("Amount: {}", job.amount);
+        }
+    }
+    println!();
+    Ok(())
+}
+
+/// A read-only provider for commands that never sign, polling at the active
+/// profile's block time when it sets one.
+pub fn provider(config: &Config) -> anyhow::Result<Rpc> {
+    let provider = Provider::new(Failover::from_config(config)?);
+    match profile::active(config.chain_id)?.map(|profile| profile.block_time()).transpose()?.flatten() {
+        Some(block_time) => Ok(provider.interval(block_time)),
+        None => Ok(provider),
+    }
+}
+
+pub fn connect(config: &Config) -> anyhow::Result<Arc<Client>> {
+    connect_with_key(config, &config::private_key()?)
+}
+
+/// The client preflights run through. SIMULATION_PRIVATE_KEY, if set, is a
+/// throwaway key used for estimation and eth_call, so preflights can run
+/// where the production key is not available. With neither key but
+/// SENDER_ADDRESS set, a throwaway key stands in, for watch-only use: it
+/// never signs anything that is broadcast.
+pub fn connect_simulation(config: &Config) -> anyhow::Result<Arc<Client>> {
+    let key = match config::simulation_private_key()? {
+        Some(key) => key,
+        None if config::var("PRIVATE_KEY").is_err() && config::sender_address()?.is_some() => {
+            hex::encode(LocalWallet::new(&mut ethers::core::rand::thread_rng()).signer().to_bytes())
+        }
+        None => config::private_key()?,
+    };
+    connect_with_key(config, &key)
+}
+
+/// The address transactions will be broadcast from: SENDER_ADDRESS if set,
+/// otherwise the PRIVATE_KEY wallet, otherwise the simulation wallet.
+pub fn sender_address<M: Sender>(simulation: &M) -> anyhow::Result<Address> {
+    if let Some(sender) = config::sender_address()? {
+        return Ok(sender);
+    }
+    match config::private_key() {
+        Ok(key) => Ok(key.parse::<LocalWallet>()?.address()),
+        Err(_) => Ok(simulation.sender()),
+    }
+}
+
+/// One client per key of the batch sender pool.
+pub fn connect_pool(config: &Config) -> anyhow::Result<Vec<Arc<Client>>> {
+    config::private_keys()?
+        .iter()
+        .map(|key| connect_with_key(config, key))
+        .collect()
+}
+
+pub fn connect_with_key(config: &Config, private_key: &str) -> anyhow::Result<Arc<Client>> {
+    let provider = provider(config)?;
+    let wallet = private_key.parse::<LocalWallet>()?.with_chain_id(config.chain_id);
+    Ok(Arc::new(SignerMiddleware::new(provider, wallet)))
+}
+
+/// Fails with [`Error::ChainMismatch`] unless every known chain id in `ids`,
+/// each named by where it came from, is the same. A signature made for the
+/// wrong chain is at best unusable.
+pub fn ensure_same_chain(ids: &[(&str, Option<u64>)]) -> anyhow::Result<()> {
+    let known: Vec<_> = ids.iter().filter_map(|(source, id)| id.map(|id| (*source, id))).collect();
+    if known.windows(2).all(|pair| pair[0].1 == pair[1].1) {
+        return Ok(());
+    }
+    let ids = known.iter().map(|(source, id)| format!("{} {}", source, id)).collect::<Vec<_>>().join(", ");
+    Err(Error::ChainMismatch { ids }.into())
+}
+
+/// Fails with [`Error::NoContract`] unless code is deployed at the contract
+/// address, returning that code. Catches a wrong address or chain before it
+/// surfaces as a confusing gas estimation or decoding error.
+pub async fn ensure_contract_deployed<M: Middleware>(client: &M, config: &Config) -> anyhow::Result<Bytes>
+where
+    M::Error: 'static,
+{
+    let code = client.get_code(config.contract_address, None).await?;
+    if code.is_empty() {
+        return Err(Error::NoContract { address: config.contract_address, chain_id: config.chain_id }.into());
+    }
+    Ok(code)
+}
+
+/// Warns when neither `code` nor, for an EIP-1967 proxy (plain or beacon),
+/// its implementation dispatches `selector`: usually a sign that the ABI
+/// doesn't match the deployed contract.
+pub async fn warn_if_selector_missing<M: Middleware>(
+    client: &M,
+    config: &Config,
+    code: &Bytes,
+    selector: [u8; 4],
+) -> anyhow::Result<()>
+where
+    M::Error: 'static,
+{
+    if bytecode::contains_selector(code, selector) {
+        return Ok(());
+    }
+    if let Some(proxy) = bytecode::resolve_proxy(client, config.contract_address).await? {
+        let implementation = proxy.implementation;
+        let code = client.get_code(implementation, None).await?;
+        if bytecode::contains_selector(&code, selector) {
+            return Ok(());
+        }
+        print_warn!("Selector 0x{} not found in implementation {:?} behind proxy {:?}; the ABI may not match the contract",
+            hex::encode(selector), implementation, config.contract_address);
+        let missing = bytecode::missing_functions(&code, &MYCONTRACT_ABI);
+        println!("abi.json functions the implementation lacks: {}", missing.join(", "));
+    } else {
+        print_warn!("Selector 0x{} not found in the bytecode at {:?}; the ABI may not match the contract",
+            hex::encode(selector), config.contract_address);
+    }
+    println!();
+    Ok(())
+}
+
+/// Prints the sending wallet's and user's balances, returning the wallet's
+/// balance for the funds check in [`preflight`].
+pub async fn print_wallet_info<M: Sender>(client: &M, wallet_address: Address, user: Address) -> anyhow::Result<U256>
+where
+    M::Error: 'static,
+{
+    const WIDTH: usize = "Wallet Address".len();
+    println!("{}", style::header("Wallet Information"));
+    println!("{}", style::field("Wallet Address", WIDTH, style::dim(format!("{:?}", wallet_address))));
+
+    let native = profile::native_currency(client.chain_id())?;
+    let format = |amount| format!("{} {}", units::format_decimal(amount, native.decimals), native.symbol);
+
+    // Check wallet balance
+    let balance = client.get_balance(wallet_address, None).await?;
+    println!("{}", style::field("Wallet Balance", WIDTH, format(balance)));
+
+    // Check user balance
+    let user_balance = client.get_balance(user, None).await?;
+    let formatted = format(user_balance);
+    println!("{}", style::field("User Balance", WIDTH, formatted));
+    println!();
+
+    Ok(balance)
+}
+
+/// Prices `tx` with the chain's [`FeeModel`], making it a legacy transaction
+/// if the model says so. If the node cannot serve the fee oracle, the fees
+/// are left for the signer to fill in.
+pub async fn apply_fees<M: Sender>(client: &M, tx: &mut TypedTransaction) -> anyhow::Result<()>
+where
+    M::Error: 'static,
+{
+    println!("=== Fees ===");
+    let model = FeeModel::from_env(client.chain_id())?;
+    model.set_transaction_type(tx);
+    match model.suggest(client).await {
+        Ok(fees) => {
+            println!("Next Base Fee: {} Gwei", ethers::utils::format_units(fees.base_fee, "gwei")?);
+            println!("Max Priority Fee: {} Gwei", ethers::utils::format_units(fees.max_priority_fee_per_gas, "gwei")?);
+            println!("Max Fee: {} Gwei", ethers::utils::format_units(fees.max_fee_per_gas, "gwei")?);
+            fees.apply(tx);
+        }
+        Err(e) => {
+            print_warn!("Could not compute fees: {:?}", e);
+            println!("Falling back to the node's fee suggestion");
+        }
+    }
+    println!();
+    Ok(())
+}
+
+/// What [`preflight`] learned about a transaction it let through.
+pub struct Estimate {
+    /// Attached value, in wei.
+    pub value: U256,
+    /// Estimated gas, if it could be estimated.
+    pub gas: Option<U256>,
+    /// Gas cost at the worst-case fee, in wei, if gas could be estimated.
+    pub gas_cost: Option<U256>,
+}
+
+/// Estimates gas for `tx` and checks the wallet can cover gas plus the
+/// attached value. Returns `None` when the send should be abandoned.
+pub async fn preflight<M: Sender>(client: &M, tx: &TypedTransaction, balance: U256) -> anyhow::Result<Option<Estimate>>
+where
+    M::Error: 'static,
+{
+    let value = tx.value().copied().unwrap_or_default();
+
+    // Check if balance is sufficient for the transaction, at the worst-case
+    // fee when one has been set
+    let gas_price = match tx.gas_price() {
+        Some(max_fee) => max_fee,
+        None => {
+            let gas_price = client.get_gas_price().await?;
+            println!("Current Gas Price: {} Gwei", ethers::utils::format_units(gas_price, "gwei")?);
+            gas_price
+        }
+    };
+
+    let native = profile::native_currency(client.chain_id())?;
+    let format = |amount| format!("{} {}", units::format_decimal(amount, native.decimals), native.symbol);
+    println!("=== Transaction Details ===");
+    println!("Transaction Value: {}", format(value));
+
+    // Try to estimate gas (this might fail if there are insufficient funds)
+    let (mut gas, mut gas_cost) = (None, None);
+    match client.estimate_gas(tx, None).await {
+        Ok(gas_estimate) => {
+            println!("Estimated Gas: {}", gas_estimate);
+            check_pending_estimate(client, tx, gas_estimate).await;
+            gas = Some(gas_estimate);
+            gas_cost = Some(gas_estimate * gas_price);
+            let total_cost = gas_estimate * gas_price + value;
+            println!("Total Transaction Cost: {}", format(total_cost));
+
+            if total_cost > balance {
+                print_error!("INSUFFICIENT FUNDS: Need {}, but wallet has {}", format(total_cost), format(balance));
+                return Ok(None);
+            } else {
+                print_ok!("Sufficient funds available");
+            }
+        }
+        Err(e) => {
+            print_error!("Failed to estimate gas: {:?}", e);
+            println!("This might be due to insufficient funds or invalid parameters");
+        }
+    }
+    println!();
+
+    Ok(Some(Estimate { value, gas, gas_cost }))
+}
+
+/// Prints the USD value of the job's amount and of the transaction's gas
+/// cost and value, when a [`PriceSource`] is configured. Returns the gas
+/// cost plus value in USD, when the native price and gas cost are known.
+///
+/// Prices are informational: a price that can't be fetched is reported and
+/// treated as unknown.
+pub async fn print_usd<M: Sender + 'static>(client: Arc<M>, job: &Job, estimate: &Estimate) -> anyhow::Result<Option<f64>> {
+    let Some(source) = PriceSource::from_env()? else {
+        return Ok(None);
+    };
+    println!("=== USD Value ===");
+    let decimals = profile::native_currency(client.chain_id())?.decimals;
+    let native = match source.native_usd(client.clone()).await {
+        Ok(price) => price,
+        Err(e) => {
+            print_warn!("Could not fetch the native currency price: {:#}", e);
+            None
+        }
+    };
+
+    let amount = if job.is_native()? {
+        native.map(|price| price::to_f64(job.amount, decimals) * price)
+    } else {
+        let decimals = token::metadata(client.clone(), job.token).await.map(|info| info.decimals);
+        match (decimals, source.token_usd(client, job.token).await) {
+            (Some(decimals), Ok(Some(price))) => Some(price::to_f64(job.amount, decimals) * price),
+            (_, Err(e)) => {
+                print_warn!("Could not fetch the token price: {:#}", e);
+                None
+            }
+            _ => None,
+        }
+    };
+    let unknown = || "unknown".to_string();
+    println!("Amount: {}", amount.map(price::format_usd).unwrap_or_else(unknown));
+
+    let Some(native) = native else {
+        println!("Gas Cost: {}", unknown());
+        println!();
+        return Ok(None);
+    };
+    let value = price::to_f64(estimate.value, decimals) * native;
+    let total = estimate.gas_cost.map(|gas_cost| price::to_f64(gas_cost, decimals) * native + value);
+    let gas = estimate.gas_cost.map(|gas_cost| price::format_usd(price::to_f64(gas_cost, decimals) * native));
+    println!("Gas Cost: {}", gas.unwrap_or_else(unknown));
+    println!("Total Cost (gas + value): {}", total.map(price::format_usd).unwrap_or_else(unknown));
+    println!();
+    Ok(total)
+}
+
+/// Simulates `tx` with `debug_traceCall` and prints the native and ERC-20
+/// balance changes of each of `parties`, so "who pays what" can be checked at
+/// a glance. The native change of the sender includes the gas fee.
+pub async fn print_balance_diff<M: Sender + 'static>(
+    client: Arc<M>,
+    tx: &TypedTransaction,
+    parties: &[(&str, Address)],
+) -> anyhow::Result<()> {
+    let (diff, logs) = futures::try_join!(trace::state_diff(&*client, tx), trace::logs(&*client, tx))
+        .context("debug_traceCall failed; does the node enable the debug namespace?")?;
+    let accounts: Vec<Address> = parties.iter().map(|(_, address)| *address).collect();
+    let tokens = trace::token_changes(&logs, &accounts);
+
+    let mut metadata = BTreeMap::new();
+    for &(token, _) in tokens.keys() {
+        if let std::collections::btree_map::Entry::Vacant(slot) = metadata.entry(token) {
+            slot.insert(token::metadata(client.clone(), token).await);
+        }
+    }
+
+    let currency = profile::native_currency(client.chain_id())?;
+    println!("=== Expected Balance Changes ===");
+    let width = parties.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for &(name, address) in parties {
+        let mut changes = Vec::new();
+        let native = diff.balance_change(address);
+        if !native.is_zero() {
+            changes.push(format_change(native, currency.decimals, &currency.symbol));
+        }
+        for (&(token, _), delta) in tokens.iter().filter(|((_, account), _)| *account == address) {
+            changes.push(match &metadata[&token] {
+                Some(info) => format_change(*delta, info.decimals, &info.symbol),
+                None => format_change(*delta, 0, &format!("base units of {:?}", token)),
+            });
+        }
+        let changes = if changes.is_empty() { "no change".to_string() } else { changes.join(", ") };
+        println!("{}", style::field(name, width, format!("{} {}", style::dim(format!("{:?}", address)), changes)));
+    }
+    println!();
+    Ok(())
+}
+
+/// Simulates `tx` with `debug_traceCall` and prints the storage slots of
+/// `contract` it would write. Slots are named where they can be matched to a
+/// low declaration slot, an ERC-1967 slot, or a mapping keyed by the job's
+/// user, token and nonce.
+pub async fn print_storage_diff<M: Middleware>(
+    client: &M,
+    tx: &TypedTransaction,
+    contract: Address,
+    job: &Job,
+) -> anyhow::Result<()> {
+    let diff = trace::state_diff(client, tx)
+        .await
+        .context("debug_traceCall failed; does the node enable the debug namespace?")?;
+    let keys = [("user", H256::from(job.user)), ("token", H256::from(job.token)), ("nonce", H256::from_uint(&job.nonce))];
+
+    println!("{}", style::header("Expected Storage Changes"));
+    let changes = diff.storage_changes(contract);
+    if changes.is_empty() {
+        println!("No storage of {:?} changes", contract);
+    }
+    for (slot, before, after) in changes {
+        match storage::describe_slot(slot, &keys) {
+            Some(name) => println!("{} {}", name, style::dim(format!("{:?}", slot))),
+            None => println!("{:?}", slot),
+        }
+        println!("  {} -> {}", format_word(before), format_word(after));
+    }
+    println!();
+    Ok(())
+}
+
+/// Simulates `tx` with the node's struct logger and prints where its gas
+/// goes: by opcode group, then per internal call, naming callees found in
+/// `parties`.
+pub async fn print_gas_profile<M: Middleware>(
+    client: &M,
+    tx: &TypedTransaction,
+    parties: &[(&str, Address)],
+) -> anyhow::Result<()> {
+    let profile = trace::gas_profile(client, tx)
+        .await
+        .context("debug_traceCall failed; does the node enable the debug namespace?")?;
+    let gas = |amount: u64| token::group_thousands(&amount.to_string());
+
+    println!("{}", style::header("Gas Profile"));
+    println!("Total gas used: {}", gas(profile.total));
+    let mut groups: Vec<_> = profile.groups.iter().collect();
+    groups.sort_by(|a, b| b.1.cmp(a.1));
+    let width = groups.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, amount) in groups {
+        let share = *amount as f64 * 100.0 / profile.total.max(1) as f64;
+        let value = format!("{:>9} {}", gas(*amount), style::dim(format!("{:.1}%", share)));
+        println!("  {}", style::field(name, width, value));
+    }
+    if !profile.calls.is_empty() {
+        println!("Internal calls:");
+    }
+    for call in &profile.calls {
+        let target = match call.target {
+            Some(address) => match parties.iter().find(|(_, party)| *party == address) {
+                Some((name, _)) => format!("{} {}", name, style::dim(format!("{:?}", address))),
+                None => format!("{:?}", address),
+            },
+            None => "new contract".to_string(),
+        };
+        let indent = "  ".repeat(call.depth as usize);
+        println!("{}{} {}: {}", indent, call.op, target, gas(call.gas));
+    }
+    println!();
+    Ok(())
+}
+
+/// A storage word, with its value as a number when it is small enough to
+/// plausibly be one.
+fn format_word(word: H256) -> String {
+    let value = word.into_uint();
+    match value.bits() {
+        0 => "0".to_string(),
+        1..=128 => format!("{} ({:?})", value, word),
+        _ => format!("{:?}", word),
+    }
+}
+
+/// A signed amount in whole units, e.g. `-1,500.00 USDC`.
+fn format_change(delta: I256, decimals: u8, unit: &str) -> String {
+    let sign = if delta.is_negative() { "-" } else { "+" };
+    format!("{}{} {}", sign, token::format_amount(delta.unsigned_abs(), decimals), unit)
+}
+
+/// How far, in percent, the pending-state gas estimate may drift from the
+/// latest-state one before [`preflight`] warns.
+const ESTIMATE_DIVERGENCE_PERCENT: u64 = 10;
+
+/// Re-estimates `tx` against the pending block and warns when the result
+/// differs markedly from `latest`, or fails outright: either means a pending
+/// transaction (such as another lock for the same nonce) affects this one.
+async fn check_pending_estimate<M: Middleware>(client: &M, tx: &TypedTransaction, latest: U256) {
+    match client.estimate_gas(tx, Some(BlockNumber::Pending.into())).await {
+        Ok(pending) => {
+            let difference = if pending > latest { pending - latest } else { latest - pending };
+            if difference * 100 > latest * ESTIMATE_DIVERGENCE_PERCENT {
+                print_warn!("Pending-state estimate {} differs from latest-state estimate {} by more than {}%",
+                    pending, latest, ESTIMATE_DIVERGENCE_PERCENT);
+                println!("A pending transaction may change how this one executes");
+            } else {
+                println!("Estimated Gas (pending): {}", pending);
+            }
+        }
+        Err(e) => {
+            print_warn!("Gas estimation against the pending block failed: {:?}", e);
+            println!("A pending transaction may make this one revert");
+        }
+    }
+}
+
+/// Refuses a job the ledger shows as already broadcast, unless `force`d.
+pub async fn check_ledger(ledger: &Ledger, kind: &str, config: &Config, job: &Job, force: bool) -> anyhow::Result<()> {
+    let Some(previous) = ledger.find(kind, config.chain_id, config.contract_address, job).await? else {
+        return Ok(());
+    };
+    if !force {
+        return Err(Error::DuplicateSubmission { tx_hash: previous.tx_hash }.into());
+    }
+    print_warn!("Job was already submitted as {:?}; sending again because of --force", previous.tx_hash);
+    println!();
+    Ok(())
+}
+
+/// Claims the job in the ledger as about to be broadcast as `tx_hash`, so
+/// instances sharing the ledger can't both send it. [`check_ledger`] may have
+/// passed before another instance sent the job; that is refused here unless
+/// `force`d, in which case the send is recorded as a resend.
+pub async fn claim(
+    ledger: &Ledger,
+    kind: &str,
+    config: &Config,
+    job: &Job,
+    tx_hash: H256,
+    force: bool,
+) -> anyhow::Result<Entry> {
+    let entry = Entry::new(kind, config.chain_id, config.contract_address, job, tx_hash);
+    match ledger.claim(&entry).await? {
+        None => {}
+        Some(previous) if !force => return Err(Error::DuplicateSubmission { tx_hash: previous.tx_hash }.into()),
+        Some(_) => ledger.record(&entry).await?,
+    }
+    Ok(entry)
+}
+
+/// Claims the `kind` job in the ledger for `tx` (see [`claim`]), broadcasts
+/// it and waits for it to be mined, printing and returning the receipt. The
+/// chain's [`ReplacementPolicy`] decides whether a transaction that isn't
+/// getting mined is re-priced.
+///
+/// With a `deadline`, a transaction still unmined once it passes is
+/// cancelled, and the send fails with [`Error::DeadlineExceeded`] unless the
+/// original wins the race against the cancellation.
+///
+/// At a terminal, Ctrl-C during the wait asks whether to keep waiting,
+/// detach (failing with [`Error::Detached`]), speed the transaction up or
+/// cancel it. Cancelling `cancel` detaches without asking: the transaction,
+/// and any replacement sent so far, is in the ledger for `pending` or a
+/// later run to pick up.
+#[allow(clippy::too_many_arguments)]
+pub async fn send_and_wait<M: Sender>(
+    client: &M,
+    tx: TypedTransaction,
+    ledger: &Ledger,
+    kind: &str,
+    config: &Config,
+    job: &Job,
+    force: bool,
+    deadline: Option<Duration>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Option<TransactionReceipt>>
+where
+    M::Error: 'static,
+{
+    let policy = ReplacementPolicy::for_chain(config.chain_id)?;
+
+    println!("=== Sending Transaction ===");
+    let mut tx = tx;
+    let raw = audit::sign(client, &mut tx, kind).await?;
+    let entry = claim(ledger, kind, config, job, H256(ethers::utils::keccak256(&raw)), force).await?;
+    let tx = match audit::broadcast(client, raw, kind).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            ledger.release(&entry).await?;
+            return Err(e);
+        }
+    };
+
+    println!("Transaction Hash: {}", style::dim(format!("{:?}", tx.tx_hash())));
+    if let Some(explorer) = Explorer::for_chain(config.chain_id)? {
+        println!("Explorer: {}", explorer.tx(tx.tx_hash()));
+        let parties = [("Contract", config.contract_address), ("Wallet", client.sender()), ("User", job.user)];
+        for (label, address) in parties {
+            println!("  {}: {}", label, explorer.address(address));
+        }
+    }
+    println!("Waiting for transaction to be mined...");
+
+    let interactive = io::stdin().is_terminal() && io::stdout().is_terminal();
+    if policy.max_replacements == 0 && deadline.is_none() && !interactive {
+        let hash = tx.tx_hash();
+        let Some(receipt) = shutdown::unless_cancelled(cancel, tx).await else {
+            return Err(Error::Detached { tx_hash: hash }.into());
+        };
+        let receipt = receipt?;
+        if let Some(receipt) = &receipt {
+            audit::record_receipt(client.sender(), kind, receipt)?;
+        }
+        print_receipt(receipt.clone());
+        return Ok(receipt);
+    }
+    let hash = tx.tx_hash();
+    let mut replacer = Replacer {
+        client,
+        policy,
+        ledger,
+        kind,
+        config,
+        job,
+        hashes: vec![hash],
+        cancel: None,
+        deadline: deadline.map(|deadline| Instant::now() + deadline),
+        expired: false,
+        interactive,
+        shutdown: cancel,
+    };
+    let receipt = replacer.wait().await?;
+    if let Some(receipt) = &receipt {
+        audit::record_receipt(client.sender(), kind, receipt)?;
+    }
+    let cancelled = match (&receipt, replacer.cancel) {
+        (Some(receipt), Some(cancel)) => receipt.transaction_hash == cancel,
+        _ => false,
+    };
+    if replacer.expired && (cancelled || receipt.is_none()) {
+        print_receipt(receipt);
+        return Err(Error::DeadlineExceeded { tx_hash: hash }.into());
+    }
+    if cancelled {
+        print_warn!("The job was cancelled; its nonce went to the self-transfer");
+    } else if replacer.expired {
+        print_warn!("Mined after the deadline, before the cancellation could replace it");
+    }
+    print_receipt(receipt.clone());
+    Ok(receipt)
+}
+
+/// Waits on a sent transaction, replacing it per the [`ReplacementPolicy`]
+/// whenever an interval passes without a receipt, and cancelling it once the
+/// deadline, if any, passes.
+struct Replacer<'a, M> {
+    client: &'a M,
+    policy: ReplacementPolicy,
+    ledger: &'a Ledger,
+    kind: &'a str,
+    config: &'a Config,
+    job: &'a Job,
+    /// The original and every replacement, oldest first; any may be mined.
+    hashes: Vec<H256>,
+    cancel: Option<H256>,
+    deadline: Option<Instant>,
+    /// Whether the deadline passed before a receipt arrived.
+    expired: bool,
+    /// Whether Ctrl-C asks what to do rather than exiting.
+    interactive: bool,
+    /// Detaches the wait when cancelled.
+    shutdown: &'a CancellationToken,
+}
+
+impl<M: Sender> Replacer<'_, M>
+where
+    M::Error: 'static,
+{
+    async fn wait(&mut self) -> anyhow::Result<Option<TransactionReceipt>> {
+        let interval = Duration::from_secs(self.policy.interval_secs);
+        let mut replacements = 0;
+        let mut replacing = self.policy.max_replacements > 0;
+        loop {
+            let timeout = replacing.then_some(interval);
+            let waited = tokio::select! {
+                waited = self.wait_for_any(timeout) => waited?,
+                _ = tokio::signal::ctrl_c(), if self.interactive => Wait::Interrupted,
+                _ = self.shutdown.cancelled() => Wait::Cancelled,
+            };
+            let latest = *self.hashes.last().expect("at least the original was sent");
+            let mut manual = false;
+            match waited {
+                Wait::Mined(receipt) => return Ok(Some(*receipt)),
+                Wait::Dropped => return Ok(None),
+                Wait::Cancelled => return Err(Error::Detached { tx_hash: latest }.into()),
+                Wait::TimedOut => {}
+                Wait::Interrupted => match Choice::ask(latest)? {
+                    Choice::Wait => continue,
+                    Choice::Detach => return Err(Error::Detached { tx_hash: latest }.into()),
+                    Choice::SpeedUp => manual = true,
+                    Choice::Cancel => {
+                        replacing = false;
+                        if self.cancel.is_some() {
+                            println!("A cancellation was already sent");
+                        } else if !self.send_cancel().await? {
+                            return Ok(None);
+                        }
+                        continue;
+                    }
+                },
+                Wait::Expired => {
+                    println!("⏰ Deadline exceeded; cancelling");
+                    self.deadline = None;
+                    self.expired = true;
+                    replacing = false;
+                    if self.cancel.is_none() && !self.send_cancel().await? {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+            }
+
+            if !manual && replacements >= self.policy.max_replacements {
+                print_warn!("Not mined after {} replacement(s); waiting without further bumps", replacements);
+                replacing = false;
+                continue;
+            }
+            let Some(original) = self.client.get_transaction(latest).await? else {
+                return Ok(None);
+            };
+            let fees = FeeModel::from_env(self.config.chain_id)?.suggest(self.client).await.ok();
+            let replacement = mempool::speed_up(&original, fees, &self.policy);
+
+            if manual && mempool::exceeds_ceiling(&replacement, &self.policy)? {
+                print_warn!("Speeding up would exceed the fee ceiling; still waiting on {:?}", latest);
+                continue;
+            }
+            if mempool::exceeds_ceiling(&replacement, &self.policy)? {
+                replacing = false;
+                match self.policy.on_ceiling {
+                    CeilingAction::Wait => {
+                        print_warn!("The next bump would exceed the fee ceiling; waiting on {:?}", latest);
+                    }
+                    CeilingAction::Cancel => {
+                        print_warn!("The next bump would exceed the fee ceiling; cancelling");
+                        if !self.send_cancel().await? {
+                            return Ok(None);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let max_fee = replacement.gas_price().unwrap_or_default();
+            let hash = *audit::send_transaction(self.client, replacement, self.kind).await?;
+            let max_fee = ethers::utils::format_units(max_fee, "gwei")?;
+            if manual {
+                println!("🔁 Sped up: {:?} (max fee {} Gwei)", hash, max_fee);
+            } else {
+                replacements += 1;
+                println!("🔁 Replacement {} of {}: {:?} (max fee {} Gwei)",
+                    replacements, self.policy.max_replacements, hash, max_fee);
+            }
+            let entry = Entry::new(self.kind, self.config.chain_id, self.config.contract_address, self.job, hash);
+            self.ledger.record(&entry).await?;
+            self.hashes.push(hash);
+        }
+    }
+
+    /// Replaces the latest sent transaction with a zero-value self-transfer.
+    /// Returns `false` when the node no longer knows that transaction.
+    async fn send_cancel(&mut self) -> anyhow::Result<bool> {
+        let latest = *self.hashes.last().expect("at least the original was sent");
+        let Some(original) = self.client.get_transaction(latest).await? else {
+            return Ok(false);
+        };
+        let fees = FeeModel::from_env(self.config.chain_id)?.suggest(self.client).await.ok();
+        let cancel = mempool::cancel(original.from, original.nonce, Some(&original), fees, &self.policy);
+        let hash = *audit::send_transaction(self.client, cancel, "cancel").await?;
+        println!("Cancellation Hash: {:?}", hash);
+        self.hashes.push(hash);
+        self.cancel = Some(hash);
+        Ok(true)
+    }
+
+    /// Polls for a receipt of any sent hash until `timeout`, if given, or the
+    /// deadline runs out. Without either it gives up once the node forgets
+    /// every hash.
+    async fn wait_for_any(&self, timeout: Option<Duration>) -> anyhow::Result<Wait> {
+        let started = Instant::now();
+        loop {
+            let mut known = false;
+            for hash in &self.hashes {
+                if let Some(receipt) = self.client.get_transaction_receipt(*hash).await? {
+                    return Ok(Wait::Mined(Box::new(receipt)));
+                }
+                known = known || self.client.get_transaction(*hash).await?.is_some();
+            }
+            if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(Wait::Expired);
+            }
+            match timeout {
+                Some(timeout) if started.elapsed() >= timeout => return Ok(Wait::TimedOut),
+                None if !known => return Ok(Wait::Dropped),
+                _ => {}
+            }
+            tokio::time::sleep(self.client.provider().get_interval()).await;
+        }
+    }
+}
+
+enum Wait {
+    Mined(Box<TransactionReceipt>),
+    TimedOut,
+    Expired,
+    Dropped,
+    Interrupted,
+    Cancelled,
+}
+
+/// What to do about a pending transaction after Ctrl-C.
+enum Choice {
+    Wait,
+    Detach,
+    SpeedUp,
+    Cancel,
+}
+
+impl Choice {
+    /// Asks on the terminal until it gets an answer; closing stdin detaches.
+    fn ask(hash: H256) -> anyhow::Result<Self> {
+        println!();
+        println!("Interrupted while waiting for {:?}", hash);
+        loop {
+            print!("[w]ait, [d]etach, [s]peed up or [c]ancel? ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(Choice::Detach);
+            }
+            match line.trim().to_lowercase().as_str() {
+                "w" | "wait" => return Ok(Choice::Wait),
+                "d" | "detach" => return Ok(Choice::Detach),
+                "s" | "speed up" => return Ok(Choice::SpeedUp),
+                "c" | "cancel" => return Ok(Choice::Cancel),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Fills in and signs `tx` locally, returning the raw transaction, so the
+/// exact bytes broadcast can be recorded before they are sent. The wallet,
+/// the node and the transaction must agree on the chain, and the signing
+/// [`policy`] is enforced on the filled-in transaction.
+pub async fn sign<M: Sender>(client: &M, tx: &mut TypedTransaction) -> anyhow::Result<Bytes>
+where
+    M::Error: 'static,
+{
+    let sender = client.sender();
+    prepare(client, sender, tx).await?;
+    if !profile::replay_protected(client.chain_id())? {
+        let wallet = client.wallet().context("signing without EIP-155 replay protection needs a local key")?;
+        return sign_without_replay_protection(wallet, tx);
+    }
+    let signature = client.sign_transaction(tx, sender).await?;
+    Ok(tx.rlp_signed(&signature))
+}
+
+/// Fills in `tx` (nonce, gas, chain id) and runs the checks it must pass
+/// before `sender` signs it: that the chain ids agree and that the signing
+/// policy allows it.
+pub async fn prepare<M: Sender>(client: &M, sender: Address, tx: &mut TypedTransaction) -> anyhow::Result<()>
+where
+    M::Error: 'static,
+{
+    client.fill_transaction(tx, None).await?;
+    ensure_same_chain(&[
+        ("wallet", Some(client.chain_id())),
+        ("node", Some(client.get_chainid().await?.as_u64())),
+        ("transaction", tx.chain_id().map(|id| id.as_u64())),
+    ])?;
+    policy::enforce(sender, tx)
+}
+
+/// Signs a legacy `tx` without the chain id, the way transactions were signed
+/// before EIP-155, for chains whose nodes reject replay-protected ones. Such a
+/// transaction is valid on every chain the wallet has the nonce on.
+pub fn sign_without_replay_protection(wallet: &LocalWallet, tx: &mut TypedTransaction) -> anyhow::Result<Bytes> {
+    let TypedTransaction::Legacy(request) = tx else {
+        anyhow::bail!("only legacy transactions can be signed without EIP-155 replay protection");
+    };
+    request.chain_id = None;
+    let signature = wallet.sign_hash(tx.sighash())?;
+    Ok(tx.rlp_signed(&signature))
+}
+
+pub fn print_receipt(receipt: Option<TransactionReceipt>) {
+    match receipt {
+        Some(r) => {
+            print_ok!("Transaction mined in block: {:?}", r.block_number);
+            println!("Gas Used: {}", r.gas_used.unwrap_or_default());
+            println!("Status: {}", if r.status.unwrap_or_default() == U64::from(1) { "Success" } else { "Failed" });
+        }
+        None => {
+            print_error!("Transaction receipt not found");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node::{self, MockNode, Reply};
+    use ethers::utils::rlp::Rlp;
+    use serde_json::json;
+
+    #[test]
+    fn stacked_senders() {
+        let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let address = wallet.address();
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let signer = SignerMiddleware::new(provider, wallet.with_chain_id(10_u64));
+        let stack = NonceManagerMiddleware::new(signer, address);
+        assert_eq!(stack.sender(), address);
+        assert_eq!(stack.chain_id(), 10);
+        assert_eq!(stack.wallet().map(|wallet| wallet.address()), Some(address));
+    }
+
+    #[test]
+    fn signing_without_replay_protection() {
+        let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let mut tx: TypedTransaction =
+            TransactionRequest::new().to(Address::repeat_byte(1)).nonce(3).gas(21_000).gas_price(1).chain_id(61).into();
+        let raw = sign_without_replay_protection(&wallet, &mut tx).unwrap();
+        let (decoded, signature) = TransactionRequest::decode_signed_rlp(&Rlp::new(&raw)).unwrap();
+        assert!(matches!(signature.v, 27 | 28));
+        assert_eq!(decoded.chain_id, None);
+        assert_eq!(signature.recover(decoded.sighash()).unwrap(), wallet.address());
+
+        let mut typed: TypedTransaction = Eip1559TransactionRequest::new().into();
+        assert!(sign_without_replay_protection(&wallet, &mut typed).is_err());
+    }
+
+    /// A node that keeps every transaction it is sent pending, except the
+    /// second, which replaces the first and is mined.
+    async fn mining_the_replacement() -> MockNode {
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        MockNode::start(move |method, params| {
+            let mut sent = sent.lock().unwrap();
+            let position = sent.iter().position(|raw| json!(mock_node::raw_hash(raw)) == params[0]);
+            match (method, position) {
+                ("eth_sendRawTransaction", _) => {
+                    sent.push(params.clone());
+                    Reply::Result(json!(mock_node::raw_hash(params)))
+                }
+                ("eth_getTransactionByHash", Some(index)) => Reply::Result(mock_node::transaction(&sent[index])),
+                ("eth_getTransactionReceipt", Some(1)) => {
+                    Reply::Result(mock_node::receipt(mock_node::raw_hash(&sent[1]), true))
+                }
+                _ => mock_node::idle(method, params),
+            }
+        })
+        .await
+    }
+
+    fn test_job(nonce: u64) -> Job {
+        Job {
+            user: Address::repeat_byte(1),
+            token: Address::repeat_byte(2),
+            amount: 100.into(),
+            nonce: nonce.into(),
+            signature: Bytes::default(),
+        }
+    }
+
+    fn lock_tx() -> TypedTransaction {
+        Eip1559TransactionRequest::new()
+            .to(Address::repeat_byte(9))
+            .data(vec![1, 2, 3])
+            .gas(50_000)
+            .nonce(7)
+            .max_fee_per_gas(2_000_000_000u64)
+            .max_priority_fee_per_gas(100_000_000u64)
+            .into()
+    }
+
+    #[tokio::test]
+    async fn cancelling_at_the_deadline() {
+        let node = mining_the_replacement().await;
+        let client = node.client();
+        let config = Config {
+            rpc_url: node.url.clone(),
+            chain_id: mock_node::CHAIN_ID,
+            contract_address: Address::repeat_byte(9),
+        };
+        let path = std::env::temp_dir().join(format!("pipeline-deadline-{}.jsonl", std::process::id()));
+        let ledger = Ledger::new(&path);
+        let job = test_job(1);
+
+        let deadline = Some(Duration::from_millis(50));
+        let error = send_and_wait(
+            &*client,
+            lock_tx(),
+            &ledger,
+            "lock",
+            &config,
+            &job,
+            false,
+            deadline,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+        let sent = node.calls("eth_sendRawTransaction");
+        let original = mock_node::raw_hash(&sent[0]);
+        assert!(
+            matches!(error.downcast_ref::<Error>(), Some(Error::DeadlineExceeded { tx_hash }) if *tx_hash == original)
+        );
+
+        // The cancellation takes the nonce with a self-transfer, at higher fees.
+        let cancel: Transaction = serde_json::from_value(mock_node::transaction(&sent[1])).unwrap();
+        assert_eq!((cancel.to, cancel.value, cancel.nonce), (Some(client.address()), U256::zero(), 7.into()));
+        assert!(cancel.max_fee_per_gas.unwrap() >= U256::from(2_250_000_000u64));
+        // The job stays claimed: the original could still have been mined.
+        assert_eq!(
+            ledger.find("lock", config.chain_id, config.contract_address, &job).await.unwrap().unwrap().tx_hash,
+            original
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replacing_an_unmined_transaction() {
+        let node = mining_the_replacement().await;
+        let client = node.client();
+        let config = Config {
+            rpc_url: node.url.clone(),
+            chain_id: mock_node::CHAIN_ID,
+            contract_address: Address::repeat_byte(9),
+        };
+        let path = std::env::temp_dir().join(format!("pipeline-replace-{}.jsonl", std::process::id()));
+        let ledger = Ledger::new(&path);
+        let job = test_job(2);
+        let mut tx = lock_tx();
+        let raw = audit::sign(&*client, &mut tx, "lock").await.unwrap();
+        let original = audit::broadcast(&*client, raw, "lock").await.unwrap().tx_hash();
+
+        let shutdown = CancellationToken::new();
+        let mut replacer = Replacer {
+            client: &*client,
+            policy: ReplacementPolicy { max_replacements: 1, interval_secs: 1, ..ReplacementPolicy::default() },
+            ledger: &ledger,
+            kind: "lock",
+            config: &config,
+            job: &job,
+            hashes: vec![original],
+            cancel: None,
+            deadline: None,
+            expired: false,
+            interactive: false,
+            shutdown: &shutdown,
+        };
+        let receipt = replacer.wait().await.unwrap().unwrap();
+        assert_eq!(replacer.hashes.len(), 2);
+        assert_eq!(receipt.transaction_hash, replacer.hashes[1]);
+
+        // The same call at the same nonce, bumped by the policy's 12.5%.
+        let sent = node.calls("eth_sendRawTransaction");
+        let replacement: Transaction = serde_json::from_value(mock_node::transaction(&sent[1])).unwrap();
+        assert_eq!((replacement.nonce, replacement.input.to_vec()), (7.into(), vec![1, 2, 3]));
+        assert!(replacement.max_fee_per_gas.unwrap() >= U256::from(2_250_000_000u64));
+        assert!(ledger.find_by_tx_hash(replacer.hashes[1]).await.unwrap().is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+//! Custom preflight checks, for rules this crate can't know about (say, that
+//! the user has passed KYC in an internal database). A program built on the
+//! library registers its checks once at startup; they then run before every
+//! lock is sent, after the built-in ledger, policy and balance checks, and
+//! any of them can refuse the send.
+//!
+//! ```ignore
+//! struct Kyc;
+//!
+//! impl PreflightCheck for Kyc {
+//!     fn name(&self) -> &str {
+//!         "kyc"
+//!     }
+//!
+//!     fn check<'a>(&'a self, preflight: &'a Preflight<'a>) -> BoxFuture<'a, anyhow::Result<Result<(), String>>> {
+//!         Box::pin(async move {
+//!             match kyc_passed(preflight.job.user).await? {
+//!                 true => Ok(Ok(())),
+//!                 false => Ok(Err("user has not passed KYC".to_string())),
+//!             }
+//!         })
+//!     }
+//! }
+//!
+//! plugins::register(Kyc);
+//! ```
+
+use crate::config::{self, Config, Job};
+use crate::error::Error;
+use crate::pipeline::Client;
+use crate::print_error;
+use crate::profile;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+pub use futures::future::BoxFuture;
+use std::sync::{Arc, RwLock};
+
+/// What a check gets to look at: the job and the priced, unsigned
+/// transaction about to be sent for it.
+pub struct Preflight<'a> {
+    /// The command sending it, such as `lock` or `unlock`.
+    pub kind: &'a str,
+    pub config: &'a Config,
+    /// Client the send is simulated through; it holds no production key.
+    pub client: &'a Client,
+    pub sender: Address,
+    pub job: &'a Job,
+    pub tx: &'a TypedTransaction,
+}
+
+pub trait PreflightCheck: Send + Sync {
+    /// Short name the check's refusals are reported under.
+    fn name(&self) -> &str;
+
+    /// `Ok(Err(reason))` refuses the send. An `Err` means the check itself
+    /// failed, which refuses it too.
+    fn check<'a>(&'a self, preflight: &'a Preflight<'a>) -> BoxFuture<'a, anyhow::Result<Result<(), String>>>;
+}
+
+static CHECKS: RwLock<Vec<Arc<dyn PreflightCheck>>> = RwLock::new(Vec::new());
+
+/// Adds `check` to the checks run before every send.
+pub fn register(check: impl PreflightCheck + 'static) {
+    CHECKS.write().expect("check registry poisoned").push(Arc::new(check));
+}
+
+/// Registers the WebAssembly checks the active profile for CHAIN_ID lists.
+/// Without the `wasm` feature, listing any is an error.
+pub fn register_wasm() -> anyhow::Result<()> {
+    let Some(chain_id) = config::env_var("CHAIN_ID").and_then(|id| id.parse().ok()) else {
+        return Ok(());
+    };
+    let plugins = profile::active(chain_id)?.map(|profile| profile.plugins).unwrap_or_default();
+    #[cfg(feature = "wasm")]
+    for path in plugins {
+        register(crate::wasm::WasmCheck::load(&path)?);
+    }
+    #[cfg(not(feature = "wasm"))]
+    anyhow::ensure!(plugins.is_empty(), "the profile lists WebAssembly plugins, but this build lacks the `wasm` feature");
+    Ok(())
+}
+
+/// Runs every registered check in registration order, returning the first
+/// refusal as `name: reason`.
+pub async fn run(preflight: &Preflight<'_>) -> Result<(), String> {
+    let checks = CHECKS.read().expect("check registry poisoned").clone();
+    for check in checks {
+        match check.check(preflight).await {
+            Ok(Ok(())) => {}
+            Ok(Err(reason)) => return Err(format!("{}: {}", check.name(), reason)),
+            Err(e) => return Err(format!("{}: check failed: {:#}", check.name(), e)),
+        }
+    }
+    Ok(())
+}
+
+/// Runs the registered checks for a single send; a refusal is printed and
+/// returned as [`Error::CheckFailed`].
+pub async fn enforce(preflight: &Preflight<'_>) -> anyhow::Result<()> {
+    if let Err(reason) = run(preflight).await {
+        print_error!("CHECK FAILED: {}", reason);
+        return Err(Error::CheckFailed { reason }.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failover::{Failover, Settings};
+
+    /// Refuses one user, and fails to check another.
+    struct Blocklist;
+
+    impl PreflightCheck for Blocklist {
+        fn name(&self) -> &str {
+            "blocklist"
+        }
+
+        fn check<'a>(&'a self, preflight: &'a Preflight<'a>) -> BoxFuture<'a, anyhow::Result<Result<(), String>>> {
+            Box::pin(async move {
+                anyhow::ensure!(preflight.job.user != Address::repeat_byte(0xb2), "database unreachable");
+                Ok(match preflight.job.user == Address::repeat_byte(0xb1) {
+                    true => Err("user is blocked".to_string()),
+                    false => Ok(()),
+                })
+            })
+        }
+    }
+
+    async fn check(user: Address) -> Result<(), String> {
+        let config = Config { rpc_url: "http://localhost:8545".to_string(), chain_id: 1, contract_address: Address::zero() };
+        let provider = Provider::new(Failover::new(std::slice::from_ref(&config.rpc_url), Settings::default()).unwrap());
+        let wallet = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".parse::<LocalWallet>().unwrap();
+        let client = SignerMiddleware::new(provider, wallet);
+        let job = Job { user, token: Address::zero(), amount: 1.into(), nonce: 1.into(), signature: Bytes::new() };
+        let tx = TypedTransaction::default();
+        run(&Preflight { kind: "lock", config: &config, client: &client, sender: client.address(), job: &job, tx: &tx }).await
+    }
+
+    #[tokio::test]
+    async fn registered_checks() {
+        register(Blocklist);
+        assert_eq!(check(Address::repeat_byte(1)).await, Ok(()));
+        assert_eq!(check(Address::repeat_byte(0xb1)).await, Err("blocklist: user is blocked".to_string()));
+        assert_eq!(check(Address::repeat_byte(0xb2)).await, Err("blocklist: check failed: database unreachable".to_string()));
+    }
+}
@@ -0,0 +1,447 @@
+//! Signing policy: limits every transaction is checked against before the
+//! tool signs it, and address lists every job is screened against in the
+//! preflight, read from the file named by POLICY_FILE.
+//!
+//! ```toml
+//! max_value_per_tx = 1.5      # ether
+//! max_total_per_day = 10      # ether, over any 24 hours, per chain
+//! allowed_chains = [1, 8453]
+//! allowed_contracts = ["0x5FbDB2315678afecb367f032d93F642f64180aa3"]
+//! allowed_users = ["0x70997970C51812dc3A010C7d01b50e0d17dc79C8"]
+//! denied_addresses = ["0x8576aCC5C05D6Ce88f4e49bf65BdF0C62F91353C"]
+//! denylist_file = "sanctioned.txt"
+//! ```
+//!
+//! Unset limits don't apply, and an empty list allows anything. A refused
+//! transaction or job is recorded in the audit log. The daily total counts
+//! every transaction broadcast under the policy, whether or not it was
+//! mined; replacements of a transaction (same sender and nonce, such as fee
+//! bumps and cancellations) count once between them. The limits are on the
+//! native currency a transaction carries: the ERC-20 amounts a lock moves
+//! are not counted. Calls a Safe or smart account makes once the tool signs
+//! for them (a Safe transaction or a UserOperation) are held to the same
+//! limits, and counted under the account and its own nonce.
+
+use crate::audit::{self, Action};
+use crate::config::{self, Job};
+use crate::error::Error;
+use crate::print_error;
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::rlp::Rlp;
+use ethers::utils::{format_ether, parse_ether};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Largest value, in ether, a single transaction may carry.
+    pub max_value_per_tx: Option<f64>,
+    /// Largest total value, in ether, signed on one chain in 24 hours.
+    pub max_total_per_day: Option<f64>,
+    #[serde(default)]
+    pub allowed_chains: Vec<u64>,
+    /// Contracts that may be called; plain transfers aren't calls.
+    #[serde(default)]
+    pub allowed_contracts: Vec<Address>,
+    /// Users jobs may lock or release funds for.
+    #[serde(default)]
+    pub allowed_users: Vec<Address>,
+    /// Addresses, such as sanctioned ones, no job may involve as contract,
+    /// user or token.
+    #[serde(default)]
+    pub denied_addresses: Vec<Address>,
+    /// File of further denied addresses, one per line; `#` starts a comment.
+    pub denylist_file: Option<PathBuf>,
+}
+
+/// A broadcast transaction's value, as counted toward the daily total.
+#[derive(Serialize, Deserialize)]
+struct Spend {
+    timestamp: u64,
+    chain_id: u64,
+    /// Sender and nonce, which replacements of the transaction share.
+    from: Address,
+    nonce: U256,
+    value: U256,
+}
+
+impl Policy {
+    /// Loads the policy file named by POLICY_FILE, or `None` when unset.
+    pub fn load() -> anyhow::Result<Option<Self>> {
+        let Some(path) = config::env_var("POLICY_FILE") else {
+            return Ok(None);
+        };
+        let contents = fs::read_to_string(&path).with_context(|| format!("failed to read policy file {}", path))?;
+        let policy: Self = toml::from_str(&contents).with_context(|| format!("invalid policy file {}", path))?;
+        policy.validate()?;
+        Ok(Some(policy))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, limit) in [("max_value_per_tx", self.max_value_per_tx), ("max_total_per_day", self.max_total_per_day)] {
+            if let Some(limit) = limit {
+                anyhow::ensure!(limit >= 0.0, "policy {} must not be negative", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks `tx`, signed for `chain_id`, given what was already signed on
+    /// that chain in the last 24 hours. Returns the violated rule.
+    pub fn check(
+        &self,
+        chain_id: u64,
+        tx: &TypedTransaction,
+        spent_today: U256,
+    ) -> anyhow::Result<Result<(), String>> {
+        if !self.allowed_chains.is_empty() && !self.allowed_chains.contains(&chain_id) {
+            return Ok(Err(format!("chain {} is not in allowed_chains", chain_id)));
+        }
+        let is_call = tx.data().is_some_and(|data| !data.is_empty());
+        if is_call && !self.allowed_contracts.is_empty() {
+            let to = tx.to_addr().copied().unwrap_or_default();
+            if !self.allowed_contracts.contains(&to) {
+                return Ok(Err(format!("contract {:?} is not in allowed_contracts", to)));
+            }
+        }
+
+        let value = tx.value().copied().unwrap_or_default();
+        if let Some(limit) = self.max_value_per_tx {
+            let limit = ether(limit)?;
+            if value > limit {
+                let (value, limit) = (format_ether(value), format_ether(limit));
+                return Ok(Err(format!("value {} ETH exceeds max_value_per_tx {} ETH", value, limit)));
+            }
+        }
+        if let Some(limit) = self.max_total_per_day {
+            let limit = ether(limit)?;
+            let total = spent_today.saturating_add(value);
+            if total > limit {
+                return Ok(Err(format!(
+                    "{} ETH in 24 hours would exceed max_total_per_day {} ETH",
+                    format_ether(total),
+                    format_ether(limit)
+                )));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// [`check`](Self::check) for `calls` (target, value, data) made as one
+    /// transaction: each target must be allowed, and their total value is
+    /// held to the limits.
+    pub fn check_calls(
+        &self,
+        chain_id: u64,
+        calls: &[(Address, U256, Bytes)],
+        spent_today: U256,
+    ) -> anyhow::Result<Result<(), String>> {
+        // The calls without their value, then the value on its own, which
+        // isn't a call to any contract.
+        let value = calls.iter().fold(U256::zero(), |total, (_, value, _)| total.saturating_add(*value));
+        let mut checked: Vec<TypedTransaction> =
+            calls.iter().map(|(to, _, data)| TransactionRequest::new().to(*to).data(data.clone()).into()).collect();
+        checked.push(TransactionRequest::new().value(value).into());
+        for tx in &checked {
+            if let Err(reason) = self.check(chain_id, tx, spent_today)? {
+                return Ok(Err(reason));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// Screens a job for `contract` against the address lists. Returns the
+    /// violated rule.
+    pub fn check_job(&self, contract: Address, job: &Job) -> anyhow::Result<Result<(), String>> {
+        if !self.allowed_contracts.is_empty() && !self.allowed_contracts.contains(&contract) {
+            return Ok(Err(format!("contract {:?} is not in allowed_contracts", contract)));
+        }
+        if !self.allowed_users.is_empty() && !self.allowed_users.contains(&job.user) {
+            return Ok(Err(format!("user {:?} is not in allowed_users", job.user)));
+        }
+        let denied = self.denied()?;
+        for (role, address) in [("contract", contract), ("user", job.user), ("token", job.token)] {
+            if denied.contains(&address) {
+                return Ok(Err(format!("{} {:?} is a denied address", role, address)));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// `denied_addresses` together with the addresses in `denylist_file`.
+    fn denied(&self) -> anyhow::Result<HashSet<Address>> {
+        let mut denied: HashSet<Address> = self.denied_addresses.iter().copied().collect();
+        if let Some(path) = &self.denylist_file {
+            let contents =
+                fs::read_to_string(path).with_context(|| format!("failed to read denylist {}", path.display()))?;
+            for (number, line) in contents.lines().enumerate() {
+                let entry = line.split('#').next().unwrap_or_default().trim();
+                if entry.is_empty() {
+                    continue;
+                }
+                let address = entry.parse().with_context(|| {
+                    format!("denylist {} line {}: invalid address {:?}", path.display(), number + 1, entry)
+                })?;
+                denied.insert(address);
+            }
+        }
+        Ok(denied)
+    }
+}
+
+fn ether(amount: f64) -> anyhow::Result<U256> {
+    Ok(parse_ether(amount.to_string())?)
+}
+
+/// Path of the record of signed values behind the daily total.
+fn spend_path() -> PathBuf {
+    config::state_dir().join("policy-spend.jsonl")
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Value broadcast on `chain_id` in the 24 hours before `now`, other than by
+/// `from` at `nonce`, in the spend record.
+fn spent_since(chain_id: u64, now: u64, from: Address, nonce: U256) -> anyhow::Result<U256> {
+    let file = match File::open(spend_path()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(U256::zero()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut spends = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        spends.push(serde_json::from_str(&line)?);
+    }
+    Ok(total(&spends, chain_id, now, from, nonce))
+}
+
+/// Sums `spends` on `chain_id` in the 24 hours before `now`, leaving out
+/// `from`'s at `nonce`. Transactions sharing a sender and nonce replace one
+/// another, so they count once, at the largest value among them.
+fn total(spends: &[Spend], chain_id: u64, now: u64, from: Address, nonce: U256) -> U256 {
+    let mut values: HashMap<(Address, U256), U256> = HashMap::new();
+    for spend in spends {
+        if spend.chain_id == chain_id && spend.timestamp + DAY_SECS > now && (spend.from, spend.nonce) != (from, nonce) {
+            let value = values.entry((spend.from, spend.nonce)).or_default();
+            *value = (*value).max(spend.value);
+        }
+    }
+    values.values().fold(U256::zero(), |total, value| total.saturating_add(*value))
+}
+
+/// Counts `raw`, a signed transaction `key` broadcast on `chain_id`, toward
+/// the daily total, if a policy is configured. Called once the node accepted
+/// it.
+pub fn record_spend(key: Address, chain_id: u64, raw: &[u8]) -> anyhow::Result<()> {
+    if config::env_var("POLICY_FILE").is_none() {
+        return Ok(());
+    }
+    let (tx, _) = TypedTransaction::decode_signed(&Rlp::new(raw)).context("invalid signed transaction")?;
+    let nonce = tx.nonce().copied().unwrap_or_default();
+    append_spend(&Spend { timestamp: now(), chain_id, from: key, nonce, value: tx.value().copied().unwrap_or_default() })
+}
+
+/// Counts `value`, carried by calls a Safe or smart account `from` makes at
+/// its own `nonce` (see [`enforce_calls`]), toward the daily total, if a
+/// policy is configured. Called once they were sent or proposed.
+pub fn record_call_spend(from: Address, chain_id: u64, nonce: U256, value: U256) -> anyhow::Result<()> {
+    if config::env_var("POLICY_FILE").is_none() {
+        return Ok(());
+    }
+    append_spend(&Spend { timestamp: now(), chain_id, from, nonce, value })
+}
+
+fn append_spend(spend: &Spend) -> anyhow::Result<()> {
+    let path = spend_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(spend)?)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Enforces the policy, if one is configured, on `tx` about to be signed by
+/// `key`. A violation is printed, recorded in the audit log and returned as
+/// [`Error::PolicyViolation`]. Nothing is counted toward the daily total
+/// until the transaction is broadcast (see [`record_spend`]).
+pub fn enforce(key: Address, tx: &TypedTransaction) -> anyhow::Result<()> {
+    let Some(policy) = Policy::load()? else {
+        return Ok(());
+    };
+    let chain_id = tx.chain_id().context("transaction has no chain id to check against the policy")?.as_u64();
+    let nonce = tx.nonce().copied().unwrap_or_default();
+    if let Err(reason) = policy.check(chain_id, tx, spent_since(chain_id, now(), key, nonce)?)? {
+        return Err(refuse(key, "policy", serde_json::to_value(tx)?, reason)?);
+    }
+    Ok(())
+}
+
+/// [`enforce`] for `calls` (target, value, data) that a Safe or smart account
+/// `from` makes at its own `nonce` on `chain_id` once `key` signs for them,
+/// such as a Safe transaction or a UserOperation. Each target must be an
+/// allowed contract, and the calls' total value is held to the limits as one
+/// transaction's.
+pub fn enforce_calls(
+    key: Address,
+    from: Address,
+    chain_id: u64,
+    nonce: U256,
+    calls: &[(Address, U256, Bytes)],
+) -> anyhow::Result<()> {
+    let Some(policy) = Policy::load()? else {
+        return Ok(());
+    };
+    if let Err(reason) = policy.check_calls(chain_id, calls, spent_since(chain_id, now(), from, nonce)?)? {
+        let calls: Vec<_> =
+            calls.iter().map(|(to, value, data)| serde_json::json!({ "to": to, "value": value, "data": data })).collect();
+        let params = serde_json::json!({ "from": from, "nonce": nonce, "calls": calls });
+        return Err(refuse(key, "policy", params, reason)?);
+    }
+    Ok(())
+}
+
+/// Prints and audits a refusal by the policy, returning it as the error.
+fn refuse(key: Address, purpose: &str, params: serde_json::Value, reason: String) -> anyhow::Result<anyhow::Error> {
+    print_error!("POLICY VIOLATION: {}", reason);
+    audit::record(&audit::Record::new(Action::Refuse, key, purpose, params, None, &reason))?;
+    Ok(Error::PolicyViolation { reason }.into())
+}
+
+/// Screens `job` against the policy's address lists, if a policy is
+/// configured, before `sender` sends it. A violation is printed, recorded in
+/// the audit log and returned as [`Error::PolicyViolation`].
+pub fn screen(sender: Address, contract: Address, job: &Job) -> anyhow::Result<()> {
+    let Some(policy) = Policy::load()? else {
+        return Ok(());
+    };
+    if let Err(reason) = policy.check_job(contract, job)? {
+        let params = serde_json::json!({ "contract": contract, "job": job });
+        return Err(refuse(sender, "screening", params, reason)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        Policy {
+            max_value_per_tx: Some(1.0),
+            max_total_per_day: Some(2.0),
+            allowed_chains: vec![1],
+            allowed_contracts: vec![Address::repeat_byte(0xaa)],
+            allowed_users: vec![Address::repeat_byte(0x01), Address::repeat_byte(0x02)],
+            denied_addresses: vec![Address::repeat_byte(0x02), Address::repeat_byte(0xdd)],
+            denylist_file: None,
+        }
+    }
+
+    fn call(to: Address, value: &str) -> TypedTransaction {
+        TransactionRequest::new().to(to).value(parse_ether(value).unwrap()).data(vec![1, 2, 3, 4]).into()
+    }
+
+    #[test]
+    fn checks_each_rule() {
+        let policy = policy();
+        let allowed = Address::repeat_byte(0xaa);
+        assert!(policy.check(1, &call(allowed, "0.5"), U256::zero()).unwrap().is_ok());
+        assert!(policy.check(5, &call(allowed, "0.5"), U256::zero()).unwrap().is_err());
+        assert!(policy.check(1, &call(Address::repeat_byte(0xbb), "0"), U256::zero()).unwrap().is_err());
+        assert!(policy.check(1, &call(allowed, "1.5"), U256::zero()).unwrap().is_err());
+        assert!(policy.check(1, &call(allowed, "1"), parse_ether("1.5").unwrap()).unwrap().is_err());
+
+        // A plain transfer, such as a cancellation, isn't a contract call.
+        let transfer = TransactionRequest::pay(Address::repeat_byte(0xbb), 0).into();
+        assert!(policy.check(1, &transfer, U256::zero()).unwrap().is_ok());
+    }
+
+    #[test]
+    fn checks_calls_as_one_transaction() {
+        let policy = policy();
+        let call = |to: u8, value: &str| (Address::repeat_byte(to), parse_ether(value).unwrap(), Bytes::from(vec![1, 2]));
+        assert!(policy.check_calls(1, &[call(0xaa, "0.5"), call(0xaa, "0.5")], U256::zero()).unwrap().is_ok());
+        // Every target must be allowed, and the values add up, here to 1.5 ether.
+        assert!(policy.check_calls(1, &[call(0xaa, "0"), call(0xbb, "0")], U256::zero()).unwrap().is_err());
+        assert!(policy.check_calls(1, &[call(0xaa, "0.75"), call(0xaa, "0.75")], U256::zero()).unwrap().is_err());
+        assert!(policy.check_calls(1, &[call(0xaa, "0.5")], parse_ether("1.75").unwrap()).unwrap().is_err());
+        assert!(policy.check_calls(5, &[call(0xaa, "0")], U256::zero()).unwrap().is_err());
+    }
+
+    #[test]
+    fn replacements_count_once() {
+        let spend = |timestamp, from: u8, nonce: u64, value: &str| Spend {
+            timestamp,
+            chain_id: 1,
+            from: Address::repeat_byte(from),
+            nonce: nonce.into(),
+            value: parse_ether(value).unwrap(),
+        };
+        let now = DAY_SECS + 100;
+        let spends = [
+            spend(now, 1, 0, "1"),
+            // A fee bump and then a cancellation of the same transaction.
+            spend(now, 1, 0, "1"),
+            spend(now, 1, 0, "0"),
+            spend(now, 1, 1, "0.5"),
+            spend(now, 2, 0, "0.25"),
+            spend(50, 2, 1, "3"),
+            Spend { chain_id: 5, ..spend(now, 2, 2, "3") },
+        ];
+        let total = |from, nonce: u64| format_ether(total(&spends, 1, now, Address::repeat_byte(from), nonce.into()));
+        assert_eq!(total(9, 0), "1.750000000000000000");
+        // A replacement isn't checked against the transaction it replaces.
+        assert_eq!(total(1, 0), "0.750000000000000000");
+    }
+
+    #[test]
+    fn screens_job_addresses() {
+        let policy = policy();
+        let contract = Address::repeat_byte(0xaa);
+        let job = |user: u8, token: u8| Job {
+            user: Address::repeat_byte(user),
+            token: Address::repeat_byte(token),
+            amount: U256::one(),
+            nonce: U256::one(),
+            signature: Bytes::default(),
+        };
+        assert!(policy.check_job(contract, &job(0x01, 0x33)).unwrap().is_ok());
+        assert!(policy.check_job(Address::repeat_byte(0xbb), &job(0x01, 0x33)).unwrap().is_err());
+        assert!(policy.check_job(contract, &job(0x03, 0x33)).unwrap().is_err());
+        // Denied even though allowed_users lists it.
+        assert!(policy.check_job(contract, &job(0x02, 0x33)).unwrap().is_err());
+        assert!(policy.check_job(contract, &job(0x01, 0xdd)).unwrap().is_err());
+    }
+
+    #[test]
+    fn parses_policy_file() {
+        let policy: Policy = toml::from_str(
+            r#"
+            max_value_per_tx = 1.5
+            allowed_chains = [1, 8453]
+            allowed_contracts = ["0x5FbDB2315678afecb367f032d93F642f64180aa3"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(policy.allowed_chains, vec![1, 8453]);
+        assert!(policy.max_total_per_day.is_none());
+        assert!(toml::from_str::<Policy>("max_value = 1").is_err());
+    }
+}
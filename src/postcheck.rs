@@ -0,0 +1,153 @@
+//! Checks made once a send is confirmed that the chain reflects the job. A
+//! success receipt only says the call didn't revert, not that it did the
+//! right thing: behind a proxy, a changed implementation can accept a lock
+//! and record something else, or nothing at all, and a drifted ABI can emit
+//! an event that no longer says what we sent.
+
+use crate::config::Job;
+use crate::contract::{LockedFilter, MyContract};
+use crate::error::Error;
+use anyhow::Context;
+use ethers::contract::EthEvent;
+use ethers::prelude::*;
+use std::sync::Arc;
+
+/// Checks a confirmed lock of `job` on the contract at `contract`: its
+/// Locked event, then its lock record.
+pub async fn verify_lock<M: Middleware + 'static>(
+    client: Arc<M>,
+    contract: Address,
+    job: &Job,
+    receipt: &TransactionReceipt,
+) -> anyhow::Result<()>
+where
+    M::Error: 'static,
+{
+    verify_locked_event(contract, job, receipt)?;
+    verify_lock_record(client, contract, job, receipt).await
+}
+
+/// Decodes the Locked events `contract` emitted in `receipt`, failing with
+/// [`Error::PostCheckFailed`] unless one of them has exactly `job`'s user,
+/// token, amount and nonce.
+pub fn verify_locked_event(contract: Address, job: &Job, receipt: &TransactionReceipt) -> anyhow::Result<()> {
+    let events: Vec<LockedFilter> = receipt
+        .logs
+        .iter()
+        .filter(|log| log.address == contract && log.topics.first() == Some(&LockedFilter::signature()))
+        .map(|log| <LockedFilter as EthEvent>::decode_log(&log.clone().into()))
+        .collect::<Result<_, _>>()
+        .context("failed to decode the Locked event")?;
+    let reason = match events.iter().map(|event| locked_event_mismatches(job, event)).min_by_key(Vec::len) {
+        None => "the contract emitted no Locked event".to_string(),
+        Some(mismatches) if mismatches.is_empty() => return Ok(()),
+        Some(mismatches) => format!("its Locked event has {}", mismatches.join(", ")),
+    };
+    Err(Error::PostCheckFailed { tx_hash: receipt.transaction_hash, reason }.into())
+}
+
+/// Reads `job`'s lock record as of the receipt's block, failing with
+/// [`Error::PostCheckFailed`] unless it holds the job's amount, was written
+/// in that block and isn't redeemed.
+pub async fn verify_lock_record<M: Middleware + 'static>(
+    client: Arc<M>,
+    contract: Address,
+    job: &Job,
+    receipt: &TransactionReceipt,
+) -> anyhow::Result<()>
+where
+    M::Error: 'static,
+{
+    let number = receipt.block_number.context("the receipt has no block number")?;
+    let contract = MyContract::new(contract, client.clone());
+    let record = contract.locks(job.user, job.token, job.nonce).block(number).call().await?;
+    let block = client.get_block(number).await?.with_context(|| format!("block {} not found", number))?;
+    let mismatches = lock_record_mismatches(job.amount, block.timestamp, record);
+    if !mismatches.is_empty() {
+        let reason = format!("the lock record {}", mismatches.join(", "));
+        return Err(Error::PostCheckFailed { tx_hash: receipt.transaction_hash, reason }.into());
+    }
+    Ok(())
+}
+
+/// How a Locked event's fields differ from `job`'s.
+fn locked_event_mismatches(job: &Job, event: &LockedFilter) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    if event.user != job.user {
+        mismatches.push(format!("user {:?} instead of {:?}", event.user, job.user));
+    }
+    if event.token != job.token {
+        mismatches.push(format!("token {:?} instead of {:?}", event.token, job.token));
+    }
+    if event.amount != job.amount {
+        mismatches.push(format!("amount {} instead of {}", event.amount, job.amount));
+    }
+    if event.nonce != job.nonce {
+        mismatches.push(format!("nonce {} instead of {}", event.nonce, job.nonce));
+    }
+    mismatches
+}
+
+/// How a lock record (amount, timestamp, redeemed) differs from one of
+/// `amount` written in a block mined at `mined_at`.
+fn lock_record_mismatches(amount: U256, mined_at: U256, record: (U256, U256, bool)) -> Vec<String> {
+    let (recorded, timestamp, redeemed) = record;
+    if recorded.is_zero() && timestamp.is_zero() && !redeemed {
+        return vec!["doesn't exist".to_string()];
+    }
+    let mut mismatches = Vec::new();
+    if recorded != amount {
+        mismatches.push(format!("holds {} instead of {}", recorded, amount));
+    }
+    if timestamp != mined_at {
+        mismatches.push(format!("is from timestamp {}, not the block's {}", timestamp, mined_at));
+    }
+    if redeemed {
+        mismatches.push("is already redeemed".to_string());
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::Token;
+
+    #[test]
+    fn lock_records() {
+        let (amount, mined_at) = (U256::from(1000), U256::from(1_700_000_000));
+        assert!(lock_record_mismatches(amount, mined_at, (amount, mined_at, false)).is_empty());
+        assert_eq!(lock_record_mismatches(amount, mined_at, (0.into(), 0.into(), false)), ["doesn't exist"]);
+        assert_eq!(
+            lock_record_mismatches(amount, mined_at, (999.into(), 1_600_000_000.into(), true)),
+            [
+                "holds 999 instead of 1000",
+                "is from timestamp 1600000000, not the block's 1700000000",
+                "is already redeemed",
+            ]
+        );
+    }
+
+    #[test]
+    fn locked_events() {
+        let (contract, user, token) = (Address::repeat_byte(1), Address::repeat_byte(2), Address::repeat_byte(3));
+        let job = Job { user, token, amount: 1000.into(), nonce: 7.into(), signature: Bytes::default() };
+        let log = |event: &LockedFilter| Log {
+            address: contract,
+            topics: vec![LockedFilter::signature(), event.user.into(), event.token.into()],
+            data: ethers::abi::encode(&[Token::Uint(event.amount), Token::Uint(event.nonce)]).into(),
+            ..Default::default()
+        };
+        let receipt = |logs: Vec<Log>| TransactionReceipt { logs, ..Default::default() };
+
+        let sent = LockedFilter { user, token, amount: job.amount, nonce: job.nonce };
+        verify_locked_event(contract, &job, &receipt(vec![log(&sent)])).unwrap();
+        let drifted = LockedFilter { nonce: 8.into(), ..sent.clone() };
+        let e = verify_locked_event(contract, &job, &receipt(vec![log(&drifted)])).unwrap_err();
+        assert!(e.to_string().ends_with("but its Locked event has nonce 8 instead of 7"));
+        // One matching event is enough, and another contract's doesn't count.
+        verify_locked_event(contract, &job, &receipt(vec![log(&drifted), log(&sent)])).unwrap();
+        let elsewhere = Log { address: Address::repeat_byte(9), ..log(&sent) };
+        assert!(verify_locked_event(contract, &job, &receipt(vec![elsewhere])).is_err());
+    }
+}
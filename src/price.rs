@@ -0,0 +1,143 @@
+//! USD prices for the native currency and the job's token, from Chainlink
+//! feeds on-chain or the CoinGecko API, so amounts and gas costs can be shown
+//! in dollars.
+//!
+//! PRICE_SOURCE selects the source:
+//! - `chainlink`: NATIVE_USD_FEED and TOKEN_USD_FEED are aggregator
+//!   addresses for the native currency and the token.
+//! - `coingecko`: COINGECKO_NATIVE_ID is the coin id of the native currency
+//!   (e.g. `ethereum`) and COINGECKO_PLATFORM the asset platform tokens are
+//!   looked up on by address (e.g. `base`). COINGECKO_API_URL and
+//!   COINGECKO_API_KEY are optional.
+//!
+//! Prices that aren't configured are simply unknown.
+
+use crate::config::env_var;
+use crate::contract::AggregatorV3;
+use crate::token;
+use crate::units;
+use anyhow::Context;
+use ethers::prelude::*;
+use serde_json::Value;
+use std::sync::Arc;
+
+const COINGECKO_API_URL: &str = "https://api.coingecko.com/api/v3";
+
+pub enum PriceSource {
+    Chainlink {
+        native_feed: Option<Address>,
+        token_feed: Option<Address>,
+    },
+    CoinGecko {
+        api_url: String,
+        api_key: Option<String>,
+        native_id: Option<String>,
+        platform: Option<String>,
+    },
+}
+
+impl PriceSource {
+    /// Reads PRICE_SOURCE and its settings. Returns `None` when no source is
+    /// configured.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let feed = |name: &str| -> anyhow::Result<Option<Address>> {
+            env_var(name).map(|address| address.parse().with_context(|| format!("invalid {}", name))).transpose()
+        };
+        match env_var("PRICE_SOURCE").as_deref() {
+            None => Ok(None),
+            Some("chainlink") => Ok(Some(PriceSource::Chainlink {
+                native_feed: feed("NATIVE_USD_FEED")?,
+                token_feed: feed("TOKEN_USD_FEED")?,
+            })),
+            Some("coingecko") => Ok(Some(PriceSource::CoinGecko {
+                api_url: env_var("COINGECKO_API_URL").unwrap_or_else(|| COINGECKO_API_URL.to_string()),
+                api_key: env_var("COINGECKO_API_KEY"),
+                native_id: env_var("COINGECKO_NATIVE_ID"),
+                platform: env_var("COINGECKO_PLATFORM"),
+            })),
+            Some(other) => anyhow::bail!("unknown PRICE_SOURCE {:?}, expected chainlink or coingecko", other),
+        }
+    }
+
+    /// USD price of one whole unit of the native currency, if configured.
+    pub async fn native_usd<M: Middleware + 'static>(&self, client: Arc<M>) -> anyhow::Result<Option<f64>> {
+        match self {
+            PriceSource::Chainlink { native_feed: Some(feed), .. } => chainlink(client, *feed).await.map(Some),
+            PriceSource::CoinGecko { native_id: Some(id), .. } => {
+                let response = self.coingecko(&format!("simple/price?ids={}&vs_currencies=usd", id)).await?;
+                response[id]["usd"].as_f64().with_context(|| format!("CoinGecko has no USD price for {:?}", id)).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// USD price of one whole `token`, if configured.
+    pub async fn token_usd<M: Middleware + 'static>(&self, client: Arc<M>, token: Address) -> anyhow::Result<Option<f64>> {
+        match self {
+            PriceSource::Chainlink { token_feed: Some(feed), .. } => chainlink(client, *feed).await.map(Some),
+            PriceSource::CoinGecko { platform: Some(platform), .. } => {
+                let address = format!("{:?}", token);
+                let path = format!("simple/token_price/{}?contract_addresses={}&vs_currencies=usd", platform, address);
+                let response = self.coingecko(&path).await?;
+                response[&address]["usd"]
+                    .as_f64()
+                    .with_context(|| format!("CoinGecko has no USD price for {} on {}", address, platform))
+                    .map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn coingecko(&self, path: &str) -> anyhow::Result<Value> {
+        let PriceSource::CoinGecko { api_url, api_key, .. } = self else {
+            unreachable!("only called for CoinGecko sources");
+        };
+        let mut request = reqwest::Client::new().get(format!("{}/{}", api_url.trim_end_matches('/'), path));
+        if let Some(key) = api_key {
+            request = request.header("x-cg-demo-api-key", key);
+        }
+        Ok(request.send().await?.error_for_status()?.json().await?)
+    }
+}
+
+/// The latest answer of the Chainlink aggregator at `feed`.
+async fn chainlink<M: Middleware + 'static>(client: Arc<M>, feed: Address) -> anyhow::Result<f64> {
+    let aggregator = AggregatorV3::new(feed, client);
+    let decimals = aggregator.decimals().call().await?;
+    let (_, answer, _, _, _) = aggregator.latest_round_data().call().await?;
+    anyhow::ensure!(answer > I256::zero(), "price feed {:?} returned a non-positive answer", feed);
+    Ok(to_f64(answer.into_raw(), decimals))
+}
+
+/// `amount` base units with `decimals` decimals, as a float.
+pub fn to_f64(amount: U256, decimals: u8) -> f64 {
+    units::format_decimal(amount, decimals).parse().unwrap_or_default()
+}
+
+/// Formats a dollar amount with grouped thousands: `$1,234.57`.
+pub fn format_usd(value: f64) -> String {
+    let cents = format!("{:.2}", value.abs());
+    let (whole, fraction) = cents.split_once('.').expect("formatted with two decimals");
+    format!("{}${}.{}", if value < 0.0 { "-" } else { "" }, token::group_thousands(whole), fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dollars() {
+        assert_eq!(format_usd(0.0), "$0.00");
+        assert_eq!(format_usd(1234.567), "$1,234.57");
+        assert_eq!(format_usd(999.999), "$1,000.00");
+        assert_eq!(format_usd(1_000_000.0), "$1,000,000.00");
+        assert_eq!(format_usd(-12.5), "-$12.50");
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(to_f64(U256::exp10(18) * 3 / 2, 18), 1.5);
+        assert_eq!(to_f64(350_012_345_678u64.into(), 8), 3500.12345678);
+        assert_eq!(to_f64(U256::zero(), 6), 0.0);
+    }
+}
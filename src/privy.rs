@@ -0,0 +1,141 @@
+//! Privy: transaction hashes signed by a Privy server wallet through its
+//! `secp256k1_sign` RPC method, authenticated with the app's credentials.
+//! Wallets owned by an authorization key also need each request signed with
+//! that key, given as PRIVY_AUTHORIZATION_KEY.
+//!
+//! Settings: PRIVY_APP_ID, PRIVY_APP_SECRET (a secret setting),
+//! PRIVY_WALLET_ID, PRIVY_AUTHORIZATION_KEY (a secret setting, in the
+//! `wallet-auth:` form the dashboard shows; optional) and PRIVY_API_URL
+//! (default `https://api.privy.io`).
+
+use crate::config::{self, env_var, var};
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ethers::prelude::*;
+use p256::ecdsa::signature::Signer as _;
+use p256::ecdsa::{DerSignature, SigningKey};
+use p256::pkcs8::DecodePrivateKey;
+use serde_json::{json, Value};
+
+pub struct Privy {
+    url: String,
+    app_id: String,
+    app_secret: String,
+    wallet_id: String,
+    authorization_key: Option<SigningKey>,
+    client: reqwest::Client,
+}
+
+impl Privy {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let authorization_key = match env_var("PRIVY_AUTHORIZATION_KEY") {
+            Some(_) => Some(parse_authorization_key(&config::secret("PRIVY_AUTHORIZATION_KEY")?)?),
+            None => None,
+        };
+        Ok(Self {
+            url: env_var("PRIVY_API_URL").unwrap_or_else(|| "https://api.privy.io".to_string()),
+            app_id: var("PRIVY_APP_ID")?,
+            app_secret: config::secret("PRIVY_APP_SECRET")?,
+            wallet_id: var("PRIVY_WALLET_ID")?,
+            authorization_key,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Has the wallet sign `hash`, checking it is the wallet of `from`.
+    pub async fn sign_hash(&self, hash: H256, from: Address) -> anyhow::Result<Signature> {
+        let wallet = self.request(reqwest::Method::GET, &format!("/v1/wallets/{}", self.wallet_id), None).await?;
+        let address: Address = wallet["address"]
+            .as_str()
+            .and_then(|address| address.parse().ok())
+            .context("Privy returned a wallet without an address")?;
+        anyhow::ensure!(address == from, "Privy wallet {} is {:?}, not the sender {:?}", self.wallet_id, address, from);
+
+        let body = json!({ "method": "secp256k1_sign", "params": { "hash": format!("{:?}", hash) } });
+        let path = format!("/v1/wallets/{}/rpc", self.wallet_id);
+        let response = self.request(reqwest::Method::POST, &path, Some(&body)).await?;
+        let signature = response["data"]["signature"].as_str().context("Privy returned no signature")?;
+        signature.parse().context("Privy returned an invalid signature")
+    }
+
+    /// Makes an authenticated API call, returning its JSON response.
+    async fn request(&self, method: reqwest::Method, path: &str, body: Option<&Value>) -> anyhow::Result<Value> {
+        let url = format!("{}{}", self.url, path);
+        let mut request = self
+            .client
+            .request(method.clone(), &url)
+            .basic_auth(&self.app_id, Some(&self.app_secret))
+            .header("privy-app-id", &self.app_id);
+        if let Some(body) = body {
+            if let Some(key) = &self.authorization_key {
+                let payload = authorization_payload(method.as_str(), &url, &self.app_id, body);
+                request = request.header("privy-authorization-signature", authorization_signature(key, &payload));
+            }
+            request = request.json(body);
+        }
+        let response = request.send().await.with_context(|| format!("Privy API {} unreachable", self.url))?;
+        Ok(response.error_for_status()?.json().await?)
+    }
+}
+
+/// An authorization key from its `wallet-auth:` form: a base64 PKCS#8 P-256
+/// private key.
+fn parse_authorization_key(key: &str) -> anyhow::Result<SigningKey> {
+    let der = STANDARD.decode(key.trim().trim_start_matches("wallet-auth:"))?;
+    SigningKey::from_pkcs8_der(&der).map_err(|_| anyhow::anyhow!("PRIVY_AUTHORIZATION_KEY is not a P-256 private key"))
+}
+
+/// What an authorization signature covers, in canonical JSON.
+fn authorization_payload(method: &str, url: &str, app_id: &str, body: &Value) -> String {
+    canonical(&json!({
+        "version": 1,
+        "method": method,
+        "url": url,
+        "body": body,
+        "headers": { "privy-app-id": app_id },
+    }))
+}
+
+/// `value` as RFC 8785 JSON: object keys sorted at every level and no
+/// whitespace, whatever order the map keeps them in.
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let fields: Vec<String> =
+                keys.iter().map(|key| format!("{}:{}", Value::from(key.as_str()), canonical(&map[*key]))).collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => format!("[{}]", items.iter().map(canonical).collect::<Vec<_>>().join(",")),
+        _ => value.to_string(),
+    }
+}
+
+fn authorization_signature(key: &SigningKey, payload: &str) -> String {
+    let signature: DerSignature = key.sign(payload.as_bytes());
+    STANDARD.encode(signature.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Verifier as _;
+    use p256::pkcs8::EncodePrivateKey;
+
+    #[test]
+    fn authorization_signatures() {
+        let key = SigningKey::from_slice(&[5u8; 32]).unwrap();
+        let encoded = format!("wallet-auth:{}", STANDARD.encode(key.to_pkcs8_der().unwrap().as_bytes()));
+        let key = parse_authorization_key(&encoded).unwrap();
+        assert!(parse_authorization_key("wallet-auth:AAAA").is_err());
+
+        let body = json!({ "params": { "hash": "0x01" }, "method": "secp256k1_sign" });
+        let payload = authorization_payload("POST", "https://api.privy.io/v1/wallets/w/rpc", "app", &body);
+        assert!(payload.starts_with(r#"{"body":{"method":"secp256k1_sign","params":{"hash":"0x01"}},"headers":"#));
+        let signature = STANDARD.decode(authorization_signature(&key, &payload)).unwrap();
+        let signature = DerSignature::from_bytes(&signature).unwrap();
+        key.verifying_key().verify(payload.as_bytes(), &signature).unwrap();
+    }
+}
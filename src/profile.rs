@@ -0,0 +1,498 @@
+//! The optional config file, holding per-chain profiles for settings that
+//! differ from chain to chain (such as how aggressively to tip).
+//!
+//! ```toml
+//! [profiles.base]
+//! chain_id = 8453
+//!
+//! explorer_url = "https://basescan.org"
+//!
+//! [profiles.base.gas]
+//! percentile = 20
+//! base_fee_multiplier = 1.25
+//! max_fee_gwei = 5
+//!
+//! [profiles.bsc.gas]
+//! oracle = "node"
+//! transaction_type = "legacy"
+//! ```
+//!
+//! A private or custom chain the ethers registry doesn't know can be defined
+//! in full by its profile:
+//!
+//! ```toml
+//! [profiles.poa]
+//! chain_id = 7771
+//! rpc_url = "http://10.0.0.5:8545"
+//! explorer_url = "https://explorer.poa.internal"
+//! native_symbol = "POA"
+//! native_decimals = 18
+//! eip1559 = false
+//! block_time_secs = 5
+//! ```
+//!
+//! The active profile is the one named by PROFILE, or otherwise the one whose
+//! `chain_id` matches CHAIN_ID. A profile named by PROFILE also supplies
+//! CHAIN_ID when it is unset, and the active profile RPC_URL.
+//!
+//! The file is checked against the JSON Schema derived from these types
+//! (which `config schema` prints for editors) before it is read, so a
+//! mistake is reported with its path, e.g. `profiles.base.gas.max_fee_gwei
+//! must be a number`.
+
+use crate::config;
+use crate::units;
+use anyhow::Context;
+use ethers::types::U256;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Chain the profile applies to when no PROFILE is named.
+    pub chain_id: Option<u64>,
+    /// RPC endpoint, used when RPC_URL is unset.
+    pub rpc_url: Option<String>,
+    /// The native currency's symbol and decimals (default ETH, 18).
+    pub native_symbol: Option<String>,
+    pub native_decimals: Option<u8>,
+    /// Whether the chain supports EIP-1559; `false` sends legacy
+    /// transactions, as `gas.transaction_type = "legacy"` does.
+    pub eip1559: Option<bool>,
+    /// Seconds between blocks, which is how often receipts and new blocks
+    /// are polled for (default 7).
+    pub block_time_secs: Option<f64>,
+    /// Whether legacy transactions are signed with the chain id, as EIP-155
+    /// replay protection (default true); `false` signs them the older way,
+    /// for chains that reject replay-protected transactions.
+    pub eip155: Option<bool>,
+    #[serde(default)]
+    pub gas: GasSettings,
+    #[serde(default)]
+    pub replacement: ReplacementPolicy,
+    /// Block explorer base URL, e.g. `https://explorer.example.org`, for
+    /// chains the registry doesn't know.
+    pub explorer_url: Option<String>,
+    /// WebAssembly preflight check modules (see `wasm`).
+    #[serde(default)]
+    pub plugins: Vec<PathBuf>,
+}
+
+/// Fee algorithm parameters; unset fields keep the defaults.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GasSettings {
+    /// Reward percentile (0-100) the priority fee is taken from.
+    #[schemars(range(min = 0, max = 100))]
+    pub percentile: Option<f64>,
+    /// Headroom over the next block's base fee, as a multiplier.
+    #[schemars(range(min = 1))]
+    pub base_fee_multiplier: Option<f64>,
+    /// Number of recent blocks sampled from eth_feeHistory.
+    #[schemars(range(min = 1))]
+    pub history_blocks: Option<u64>,
+    /// Where fee suggestions come from.
+    pub oracle: Option<FeeOracle>,
+    /// Whether transactions are sent as EIP-1559 or legacy transactions.
+    pub transaction_type: Option<TransactionType>,
+    /// Max fee per gas, in gwei, a first send may offer.
+    pub max_fee_gwei: Option<f64>,
+    /// Priority fee per gas, in gwei, a first send may offer.
+    pub max_priority_fee_gwei: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeOracle {
+    /// A reward percentile over recent blocks from eth_feeHistory.
+    #[default]
+    FeeHistory,
+    /// The node's own eth_gasPrice and eth_maxPriorityFeePerGas.
+    Node,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionType {
+    #[default]
+    Eip1559,
+    /// Type-1 transactions with an access list and a gas price (EIP-2930).
+    Eip2930,
+    /// Type-0 transactions with a gas price, for chains without EIP-1559.
+    Legacy,
+}
+
+impl std::str::FromStr for TransactionType {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "eip1559" | "2" => Ok(Self::Eip1559),
+            "eip2930" | "1" => Ok(Self::Eip2930),
+            "legacy" | "0" => Ok(Self::Legacy),
+            _ => anyhow::bail!("unknown transaction type {:?}, expected legacy, eip2930 or eip1559", value),
+        }
+    }
+}
+
+/// How a transaction that isn't getting mined is re-priced.
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplacementPolicy {
+    /// Fee increase per replacement, in percent. Nodes reject replacements
+    /// that bump by less than 10%.
+    #[schemars(range(min = 10))]
+    pub bump_percent: f64,
+    /// Automatic replacements per send; 0 waits on the original indefinitely.
+    pub max_replacements: u32,
+    /// Seconds to wait for a receipt before each replacement.
+    #[schemars(range(min = 1))]
+    pub interval_secs: u64,
+    /// Max fee per gas, in gwei, no replacement may exceed.
+    pub max_fee_gwei: Option<f64>,
+    /// What to do when the next bump would exceed `max_fee_gwei`.
+    pub on_ceiling: CeilingAction,
+}
+
+impl Default for ReplacementPolicy {
+    fn default() -> Self {
+        Self { bump_percent: 12.5, max_replacements: 0, interval_secs: 60, max_fee_gwei: None, on_ceiling: CeilingAction::Wait }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CeilingAction {
+    /// Stop bumping and keep waiting on the last replacement.
+    #[default]
+    Wait,
+    /// Replace the transaction with a zero-value self-transfer.
+    Cancel,
+}
+
+impl ReplacementPolicy {
+    /// The policy for `chain_id`: the active profile's, or the default.
+    pub fn for_chain(chain_id: u64) -> anyhow::Result<Self> {
+        let policy = active(chain_id)?.map(|profile| profile.replacement).unwrap_or_default();
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.bump_percent >= 10.0, "replacement bump_percent must be at least 10");
+        anyhow::ensure!(self.interval_secs > 0, "replacement interval_secs must be at least 1");
+        if let Some(ceiling) = self.max_fee_gwei {
+            anyhow::ensure!(ceiling > 0.0, "replacement max_fee_gwei must be positive");
+        }
+        Ok(())
+    }
+
+    /// [`Self::max_fee_gwei`] in wei.
+    pub fn max_fee(&self) -> anyhow::Result<Option<U256>> {
+        self.max_fee_gwei
+            .map(|gwei| units::from_f64(gwei, 9))
+            .transpose()
+    }
+}
+
+/// Path of the config file: CONFIG_FILE, defaulting to `ethers-rusty.toml`
+/// in the working directory.
+pub fn path() -> PathBuf {
+    config::env_var("CONFIG_FILE").unwrap_or_else(|| "ethers-rusty.toml".to_string()).into()
+}
+
+impl ConfigFile {
+    /// Loads the config file. A missing default file is an empty config; a
+    /// missing CONFIG_FILE is an error.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = path();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound && config::env_var("CONFIG_FILE").is_none() => {
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to read config file {}", path.display())),
+        };
+        let parsed: toml::Value =
+            toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))?;
+        let errors = validate(&schema(), &serde_json::to_value(&parsed)?);
+        anyhow::ensure!(errors.is_empty(), "invalid config file {}: {}", path.display(), errors.join("; "));
+        toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// The profile for `chain_id`: the one named by PROFILE if set (which
+    /// must exist), otherwise the first whose `chain_id` matches.
+    pub fn active_profile(&self, chain_id: u64) -> anyhow::Result<Option<(&str, &Profile)>> {
+        if let Some(name) = config::env_var("PROFILE") {
+            let (name, profile) = self
+                .profiles
+                .get_key_value(&name)
+                .with_context(|| format!("PROFILE {:?} is not defined in {}", name, path().display()))?;
+            return Ok(Some((name.as_str(), profile)));
+        }
+        Ok(self
+            .profiles
+            .iter()
+            .find(|(_, profile)| profile.chain_id == Some(chain_id))
+            .map(|(name, profile)| (name.as_str(), profile)))
+    }
+}
+
+/// The JSON Schema of the config file.
+pub fn schema() -> Value {
+    serde_json::to_value(schemars::schema_for!(ConfigFile)).expect("schemas serialize")
+}
+
+/// Checks `value`, the config file as JSON, against `schema`, returning a
+/// message for each mistake. Only what schemars generates is understood:
+/// types, enums, ranges, properties and `$ref`s with their combinators.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    check(schema, schema, value, "", &mut errors);
+    errors
+}
+
+fn check(root: &Value, schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let at = |path: &str| if path.is_empty() { "the config file".to_string() } else { path.to_string() };
+    if let Some(reference) = schema["$ref"].as_str() {
+        let name = reference.trim_start_matches("#/definitions/");
+        return check(root, &root["definitions"][name], value, path, errors);
+    }
+    for part in schema["allOf"].as_array().into_iter().flatten() {
+        check(root, part, value, path, errors);
+    }
+    if let Some(branches) = schema["anyOf"].as_array().or(schema["oneOf"].as_array()) {
+        let mut first = None;
+        for branch in branches {
+            let mut branch_errors = Vec::new();
+            check(root, branch, value, path, &mut branch_errors);
+            if branch_errors.is_empty() {
+                first = None;
+                break;
+            }
+            if branch["type"] != "null" && first.is_none() {
+                first = Some(branch_errors);
+            }
+        }
+        if let Some(branch_errors) = first {
+            match choices(root, schema) {
+                choices if choices.is_empty() => errors.extend(branch_errors),
+                choices => errors.push(format!("{} must be one of {}", at(path), choices.join(", "))),
+            }
+        }
+    }
+
+    let types: Vec<&str> = match &schema["type"] {
+        Value::String(kind) => vec![kind.as_str()],
+        Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|kind| is_type(value, kind)) {
+        let expected: Vec<&str> = types.iter().filter(|kind| **kind != "null").map(|kind| describe(kind)).collect();
+        errors.push(format!("{} must be {}", at(path), expected.join(" or ")));
+        return;
+    }
+    if let Some(options) = schema["enum"].as_array() {
+        if !options.contains(value) {
+            let options: Vec<String> = options.iter().map(Value::to_string).collect();
+            errors.push(format!("{} must be one of {}", at(path), options.join(", ")));
+        }
+    }
+    if let Some(number) = value.as_f64() {
+        if let Some(min) = schema["minimum"].as_f64().filter(|min| number < *min) {
+            errors.push(format!("{} must be at least {}", at(path), min));
+        }
+        if let Some(max) = schema["maximum"].as_f64().filter(|max| number > *max) {
+            errors.push(format!("{} must be at most {}", at(path), max));
+        }
+    }
+    if let Value::Array(items) = value {
+        for (index, item) in items.iter().enumerate() {
+            check(root, &schema["items"], item, &format!("{}[{}]", path, index), errors);
+        }
+    }
+    if let Value::Object(fields) = value {
+        for (key, field) in fields {
+            let field_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+            match (schema["properties"].get(key), &schema["additionalProperties"]) {
+                (Some(property), _) => check(root, property, field, &field_path, errors),
+                (None, Value::Bool(false)) => errors.push(format!("{} is not a known setting", field_path)),
+                (None, Value::Object(_)) => check(root, &schema["additionalProperties"], field, &field_path, errors),
+                (None, _) => {}
+            }
+        }
+    }
+}
+
+/// The string values an enum schema allows, through its `$ref`s and
+/// combinators; empty when it allows anything else.
+fn choices(root: &Value, schema: &Value) -> Vec<String> {
+    if let Some(reference) = schema["$ref"].as_str() {
+        return choices(root, &root["definitions"][reference.trim_start_matches("#/definitions/")]);
+    }
+    if let Some(options) = schema["enum"].as_array() {
+        return options.iter().map(Value::to_string).collect();
+    }
+    let branches = schema["anyOf"].as_array().or(schema["oneOf"].as_array()).into_iter().flatten();
+    let mut all = Vec::new();
+    for branch in branches.filter(|branch| branch["type"] != "null") {
+        let some = choices(root, branch);
+        if some.is_empty() {
+            return Vec::new();
+        }
+        all.extend(some);
+    }
+    all
+}
+
+fn is_type(value: &Value, kind: &str) -> bool {
+    match kind {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+/// A JSON Schema type as it reads in TOML terms.
+fn describe(kind: &str) -> &str {
+    match kind {
+        "boolean" => "true or false",
+        "integer" => "an integer",
+        "number" => "a number",
+        "string" => "a string",
+        "array" => "an array",
+        "object" => "a table",
+        other => other,
+    }
+}
+
+/// The active profile for `chain_id` from the config file on disk, if any.
+pub fn active(chain_id: u64) -> anyhow::Result<Option<Profile>> {
+    let file = ConfigFile::load()?;
+    Ok(file.active_profile(chain_id)?.map(|(_, profile)| profile.clone()))
+}
+
+/// The profile PROFILE names, if it is set.
+pub fn named() -> anyhow::Result<Option<Profile>> {
+    if config::env_var("PROFILE").is_none() {
+        return Ok(None);
+    }
+    // With PROFILE set, the chain id plays no part in picking the profile.
+    let file = ConfigFile::load()?;
+    Ok(file.active_profile(0)?.map(|(_, profile)| profile.clone()))
+}
+
+/// A chain's native currency.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NativeCurrency {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl Profile {
+    pub fn native_currency(&self) -> NativeCurrency {
+        NativeCurrency {
+            symbol: self.native_symbol.clone().unwrap_or_else(|| "ETH".to_string()),
+            decimals: self.native_decimals.unwrap_or(18),
+        }
+    }
+
+    /// [`Self::block_time_secs`] as a duration.
+    pub fn block_time(&self) -> anyhow::Result<Option<Duration>> {
+        self.block_time_secs
+            .map(|secs| Duration::try_from_secs_f64(secs).context("invalid block_time_secs"))
+            .transpose()
+    }
+}
+
+/// Whether transactions on `chain_id` are signed with EIP-155 replay
+/// protection: unless the active profile sets `eip155 = false`.
+pub fn replay_protected(chain_id: u64) -> anyhow::Result<bool> {
+    Ok(active(chain_id)?.and_then(|profile| profile.eip155).unwrap_or(true))
+}
+
+/// The native currency of `chain_id`: the active profile's, or ETH.
+pub fn native_currency(chain_id: u64) -> anyhow::Result<NativeCurrency> {
+    Ok(active(chain_id)?.unwrap_or_default().native_currency())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_chains() {
+        let file: ConfigFile = toml::from_str(
+            r#"
+            [profiles.poa]
+            chain_id = 7771
+            rpc_url = "http://10.0.0.5:8545"
+            native_symbol = "POA"
+            eip1559 = false
+            block_time_secs = 2.5
+            "#,
+        )
+        .unwrap();
+        let poa = &file.profiles["poa"];
+        assert_eq!(poa.native_currency(), NativeCurrency { symbol: "POA".to_string(), decimals: 18 });
+        assert_eq!(poa.block_time().unwrap(), Some(Duration::from_millis(2500)));
+        assert_eq!(Profile::default().native_currency().symbol, "ETH");
+        let negative = Profile { block_time_secs: Some(-1.0), ..Default::default() };
+        assert!(negative.block_time().is_err());
+    }
+
+    #[test]
+    fn schema_errors() {
+        let file: toml::Value = toml::from_str(
+            r#"
+            [profiles.base]
+            chain_id = 8453
+            colour = "blue"
+            plugins = ["a.wasm", 3]
+
+            [profiles.base.gas]
+            max_fee_gwei = "5"
+            percentile = 120
+            oracle = "mempool"
+
+            [profiles.base.replacement]
+            bump_percent = 5
+            on_ceiling = "cancel"
+            "#,
+        )
+        .unwrap();
+        let mut errors = validate(&schema(), &serde_json::to_value(file).unwrap());
+        errors.sort();
+        assert_eq!(
+            errors,
+            [
+                "profiles.base.colour is not a known setting",
+                "profiles.base.gas.max_fee_gwei must be a number",
+                "profiles.base.gas.oracle must be one of \"fee_history\", \"node\"",
+                "profiles.base.gas.percentile must be at most 100",
+                "profiles.base.plugins[1] must be a string",
+                "profiles.base.replacement.bump_percent must be at least 10",
+            ]
+        );
+        assert_eq!(validate(&schema(), &serde_json::json!({ "profiles": [] })), ["profiles must be a table"]);
+    }
+}
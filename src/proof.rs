@@ -0,0 +1,328 @@
+//! Local verification of eth_getProof responses: Merkle-Patricia proofs of
+//! an account against a block's state root and of its storage slots against
+//! the account's storage root, so a reading doesn't rest on trusting the RPC,
+//! and the claim bundles that carry such proofs to the destination chain.
+
+use crate::contract::ClaimLockCall;
+use anyhow::Context;
+use ethers::abi::AbiEncode;
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use ethers::utils::rlp::{Rlp, RlpStream};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// Root of the empty trie, `keccak256(rlp(""))`.
+const EMPTY_ROOT: H256 = H256([
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e, 0x5b, 0x48, 0xe0,
+    0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+]);
+
+/// How a trie node refers to a child: by hash, or inline when its encoding
+/// is shorter than 32 bytes.
+enum Child {
+    Hash(H256),
+    Inline(Vec<u8>),
+}
+
+/// The value stored under `key` in the trie with `root`, proven by `proof`
+/// (the nodes from the root down, as eth_getProof returns them). `None`
+/// when the proof shows the key is absent; an error when the proof doesn't
+/// hash up to `root`.
+pub fn verify(root: H256, key: &[u8], proof: &[Bytes]) -> anyhow::Result<Option<Vec<u8>>> {
+    if root == EMPTY_ROOT && proof.is_empty() {
+        return Ok(None);
+    }
+    let path: Vec<u8> = key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect();
+    let mut nibbles = path.as_slice();
+    let mut nodes = proof.iter();
+    let mut next = Child::Hash(root);
+    loop {
+        let node = match next {
+            Child::Hash(hash) => {
+                let node = nodes.next().context("the proof ends before reaching the key")?;
+                anyhow::ensure!(H256(keccak256(node)) == hash, "a proof node doesn't match its hash");
+                node.to_vec()
+            }
+            Child::Inline(node) => node,
+        };
+        let rlp = Rlp::new(&node);
+        match rlp.item_count()? {
+            17 => {
+                let Some((first, rest)) = nibbles.split_first() else {
+                    let value = rlp.at(16)?.data()?;
+                    return Ok((!value.is_empty()).then(|| value.to_vec()));
+                };
+                nibbles = rest;
+                match child(&rlp.at(*first as usize)?)? {
+                    Some(child) => next = child,
+                    None => return Ok(None),
+                }
+            }
+            2 => {
+                let (leaf, partial) = decode_path(rlp.at(0)?.data()?)?;
+                if leaf {
+                    if nibbles != partial.as_slice() {
+                        return Ok(None);
+                    }
+                    return Ok(Some(rlp.at(1)?.data()?.to_vec()));
+                }
+                let Some(rest) = nibbles.strip_prefix(partial.as_slice()) else {
+                    return Ok(None);
+                };
+                nibbles = rest;
+                next = child(&rlp.at(1)?)?.context("an extension node has no child")?;
+            }
+            count => anyhow::bail!("a proof node has {} items, expected 2 or 17", count),
+        }
+    }
+}
+
+fn child(item: &Rlp) -> anyhow::Result<Option<Child>> {
+    if item.is_list() {
+        return Ok(Some(Child::Inline(item.as_raw().to_vec())));
+    }
+    match item.data()? {
+        [] => Ok(None),
+        hash if hash.len() == 32 => Ok(Some(Child::Hash(H256::from_slice(hash)))),
+        other => anyhow::bail!("a child reference is {} bytes, expected 32", other.len()),
+    }
+}
+
+/// Decodes a leaf or extension node's hex-prefix encoded path into whether
+/// it is a leaf and its nibbles.
+fn decode_path(encoded: &[u8]) -> anyhow::Result<(bool, Vec<u8>)> {
+    let (first, rest) = encoded.split_first().context("a node path is empty")?;
+    let flag = first >> 4;
+    anyhow::ensure!(flag <= 3, "a node path has an invalid prefix {:#x}", flag);
+    let mut nibbles = Vec::with_capacity(rest.len() * 2 + 1);
+    if flag & 1 == 1 {
+        nibbles.push(first & 0x0f);
+    }
+    nibbles.extend(rest.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]));
+    Ok((flag >= 2, nibbles))
+}
+
+/// An account's fields, as proven against a state root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub nonce: U256,
+    pub balance: U256,
+    pub storage_root: H256,
+    pub code_hash: H256,
+}
+
+/// Verifies `response`'s account proof against `state_root` and each of its
+/// storage proofs against the proven storage root, returning the proven
+/// account and slot values in request order. Fails when any proof doesn't
+/// verify or disagrees with the values the node reported alongside it.
+pub fn verify_response(
+    state_root: H256,
+    response: &EIP1186ProofResponse,
+    slots: &[H256],
+) -> anyhow::Result<(Account, Vec<U256>)> {
+    let leaf = verify(state_root, &keccak256(response.address), &response.account_proof)?
+        .with_context(|| format!("the proof shows no account at {:?}", response.address))?;
+    let rlp = Rlp::new(&leaf);
+    let account = Account {
+        nonce: rlp.val_at(0)?,
+        balance: rlp.val_at(1)?,
+        storage_root: rlp.val_at(2)?,
+        code_hash: rlp.val_at(3)?,
+    };
+    anyhow::ensure!(
+        account.storage_root == response.storage_hash,
+        "the node reported storage root {:?}, but the proof proves {:?}",
+        response.storage_hash,
+        account.storage_root
+    );
+    anyhow::ensure!(response.storage_proof.len() == slots.len(), "the node returned proofs for other slots");
+
+    let mut values = Vec::with_capacity(slots.len());
+    for (slot, storage) in slots.iter().zip(&response.storage_proof) {
+        let value = match verify(account.storage_root, &keccak256(slot), &storage.proof)? {
+            Some(encoded) => U256::from_big_endian(Rlp::new(&encoded).data()?),
+            None => U256::zero(),
+        };
+        anyhow::ensure!(
+            value == storage.value,
+            "the node reported {} for slot {:?}, but the proof proves {}",
+            storage.value,
+            slot,
+            value
+        );
+        values.push(value);
+    }
+    Ok((account, values))
+}
+
+/// The RLP encoding of `block`'s header, checked against its hash. Fields
+/// added by later forks are included when the node reports them.
+pub fn header_rlp<TX>(block: &Block<TX>) -> anyhow::Result<Bytes> {
+    let mut stream = RlpStream::new();
+    stream.begin_unbounded_list();
+    stream
+        .append(&block.parent_hash)
+        .append(&block.uncles_hash)
+        .append(&block.author.unwrap_or_default())
+        .append(&block.state_root)
+        .append(&block.transactions_root)
+        .append(&block.receipts_root)
+        .append(&block.logs_bloom.unwrap_or_default())
+        .append(&block.difficulty)
+        .append(&block.number.context("the block is still pending")?)
+        .append(&block.gas_limit)
+        .append(&block.gas_used)
+        .append(&block.timestamp)
+        .append(&block.extra_data.to_vec())
+        .append(&block.mix_hash.unwrap_or_default())
+        .append(&block.nonce.unwrap_or_default());
+    // Each later field is only present when every earlier one is.
+    if let Some(base_fee) = block.base_fee_per_gas {
+        stream.append(&base_fee);
+    }
+    if let Some(root) = block.withdrawals_root {
+        stream.append(&root);
+    }
+    if let (Some(used), Some(excess)) = (block.blob_gas_used, block.excess_blob_gas) {
+        stream.append(&used).append(&excess);
+    }
+    if let Some(root) = block.parent_beacon_block_root {
+        stream.append(&root);
+    }
+    if let Some(Ok(hash)) = block.other.get_deserialized::<H256>("requestsHash") {
+        stream.append(&hash);
+    }
+    stream.finalize_unbounded_list();
+    let header: Bytes = stream.out().to_vec().into();
+    let hash = block.hash.context("the block is still pending")?;
+    anyhow::ensure!(
+        H256(keccak256(&header)) == hash,
+        "the re-encoded header of block {:?} doesn't hash to it; the chain may use a header format this tool doesn't know",
+        hash
+    );
+    Ok(header)
+}
+
+/// Proof nodes as one RLP list, the form the claim verifier takes.
+fn encode_nodes(nodes: &[Bytes]) -> Bytes {
+    let mut stream = RlpStream::new_list(nodes.len());
+    for node in nodes {
+        stream.append_raw(node, 1);
+    }
+    stream.out().to_vec().into()
+}
+
+/// Everything a relayer needs to claim a lock on the destination chain: the
+/// source block's header and the account and storage proofs of the lock
+/// record, RLP-encoded, with the `claimLock` calldata built from them.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClaimBundle {
+    pub chain_id: u64,
+    pub contract: Address,
+    pub block_number: U64,
+    pub block_hash: H256,
+    pub user: Address,
+    pub token: Address,
+    pub nonce: U256,
+    pub slots: Vec<H256>,
+    pub header: Bytes,
+    pub account_proof: Bytes,
+    pub storage_proofs: Vec<Bytes>,
+    pub calldata: Bytes,
+}
+
+impl ClaimBundle {
+    /// Builds the bundle from a verified `response` for the record's `slots`
+    /// at `block`.
+    pub fn new<TX>(
+        chain_id: u64,
+        block: &Block<TX>,
+        response: &EIP1186ProofResponse,
+        slots: Vec<H256>,
+        user: Address,
+        token: Address,
+        nonce: U256,
+    ) -> anyhow::Result<Self> {
+        let header = header_rlp(block)?;
+        let account_proof = encode_nodes(&response.account_proof);
+        let storage_proofs: Vec<Bytes> =
+            response.storage_proof.iter().map(|storage| encode_nodes(&storage.proof)).collect();
+        let calldata = ClaimLockCall {
+            header: header.clone(),
+            account_proof: account_proof.clone(),
+            storage_proofs: storage_proofs.clone(),
+            user,
+            token,
+            nonce,
+        }
+        .encode()
+        .into();
+        Ok(Self {
+            chain_id,
+            contract: response.address,
+            block_number: block.number.unwrap_or_default(),
+            block_hash: block.hash.unwrap_or_default(),
+            user,
+            token,
+            nonce,
+            slots,
+            header,
+            account_proof,
+            storage_proofs,
+            calldata,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A trie holding only `key`, whose root node is a single leaf.
+    fn single_leaf(key: H256, value: &[u8]) -> (H256, Bytes) {
+        let mut path = vec![0x20];
+        path.extend_from_slice(key.as_bytes());
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&path).append(&value.to_vec());
+        let node = stream.out().to_vec();
+        (H256(keccak256(&node)), node.into())
+    }
+
+    #[test]
+    fn proofs() {
+        let key = H256(keccak256(H256::from_low_u64_be(3)));
+        let (root, node) = single_leaf(key, &[0x82, 0x05, 0x39]);
+        assert_eq!(verify(root, key.as_bytes(), std::slice::from_ref(&node)).unwrap(), Some(vec![0x82, 0x05, 0x39]));
+        // The leaf proves any other key absent.
+        assert_eq!(verify(root, H256::repeat_byte(1).as_bytes(), std::slice::from_ref(&node)).unwrap(), None);
+        assert!(verify(H256::repeat_byte(9), key.as_bytes(), &[node]).is_err());
+        assert!(verify(root, key.as_bytes(), &[]).is_err());
+        assert_eq!(verify(EMPTY_ROOT, key.as_bytes(), &[]).unwrap(), None);
+        assert_eq!(H256(keccak256([0x80])), EMPTY_ROOT);
+    }
+
+    #[test]
+    fn node_lists() {
+        let (_, node) = single_leaf(H256::repeat_byte(1), &[0x01]);
+        let encoded = encode_nodes(&[node.clone(), node.clone()]);
+        let list = Rlp::new(&encoded);
+        assert_eq!(list.item_count().unwrap(), 2);
+        assert_eq!(list.at(1).unwrap().as_raw(), node.as_ref());
+        assert_eq!(encode_nodes(&[]).as_ref(), [0xc0]);
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(decode_path(&[0x20, 0xab]).unwrap(), (true, vec![0xa, 0xb]));
+        assert_eq!(decode_path(&[0x1c, 0xab]).unwrap(), (false, vec![0xc, 0xa, 0xb]));
+        assert_eq!(decode_path(&[0x3f]).unwrap(), (true, vec![0xf]));
+        assert!(decode_path(&[0x40]).is_err());
+    }
+}
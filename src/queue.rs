@@ -0,0 +1,254 @@
+//! A Redis list as a job source for `stream`, so other services can queue
+//! locks without a pipe to the process.
+//!
+//! Jobs are taken from the list QUEUE_KEY (default `ethers-rusty:jobs`),
+//! one `--params-json` object per element, and each result is published on
+//! the channel QUEUE_RESULT_CHANNEL (default `ethers-rusty:results`). A job
+//! may carry an `idempotency_key` field: the first job with a key claims it,
+//! and a repeat is answered with the first job's result instead of being
+//! sent again for QUEUE_DEDUPE_TTL seconds (default one day) after it
+//! finished. REDIS_URL is a secret setting, since it may hold a password.
+//!
+//! A taken job is moved, not popped, onto the consumer's processing list
+//! (`<QUEUE_KEY>:processing:<QUEUE_CONSUMER>`) and only removed from it once
+//! its result is recorded, so a consumer that dies mid-job puts it back on
+//! the queue when it starts again. Consumers sharing a queue need different
+//! QUEUE_CONSUMER names (default `default`). While a job is processed, its
+//! claim on the idempotency key is kept alive every few seconds and lapses
+//! within a minute of its consumer dying, rather than blocking the key for
+//! the whole dedupe window. Needs Redis 6.2 or later.
+
+use crate::config::{self, env_var};
+use crate::print_warn;
+use anyhow::Context;
+use redis::aio::MultiplexedConnection;
+use serde_json::Value;
+use std::time::Duration;
+
+const DEFAULT_KEY: &str = "ethers-rusty:jobs";
+const DEFAULT_CHANNEL: &str = "ethers-rusty:results";
+const DEFAULT_CONSUMER: &str = "default";
+const DEFAULT_TTL: u64 = 24 * 60 * 60;
+
+/// How long a pop blocks waiting for a job, in seconds.
+const POP_TIMEOUT: u64 = 5;
+
+/// Stored under a claimed idempotency key until the job's result replaces it.
+const IN_PROGRESS: &str = "in-progress";
+
+/// Seconds a claim on an idempotency key outlives its last heartbeat.
+const CLAIM_TTL: u64 = 60;
+
+/// How often the claim of the job being processed is renewed.
+const HEARTBEAT: Duration = Duration::from_secs(CLAIM_TTL / 4);
+
+pub struct Queue {
+    connection: MultiplexedConnection,
+    key: String,
+    /// This consumer's processing list.
+    processing: String,
+    channel: String,
+    ttl: u64,
+}
+
+/// What claiming an idempotency key found.
+pub enum Claim {
+    /// The key is new (or the job has none): process the job.
+    New,
+    /// A job with this key is still being processed.
+    InProgress,
+    /// A job with this key already finished with this result.
+    Done(Value),
+}
+
+impl Queue {
+    /// Connects to REDIS_URL and reads the queue settings.
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let url = config::secret("REDIS_URL")?;
+        let client = redis::Client::open(url.as_str()).context("invalid REDIS_URL")?;
+        let connection = client.get_multiplexed_tokio_connection().await.context("failed to connect to Redis")?;
+        let ttl = match env_var("QUEUE_DEDUPE_TTL") {
+            Some(ttl) => ttl.parse().context("QUEUE_DEDUPE_TTL must be a number of seconds")?,
+            None => DEFAULT_TTL,
+        };
+        let key = env_var("QUEUE_KEY").unwrap_or_else(|| DEFAULT_KEY.to_string());
+        let consumer = env_var("QUEUE_CONSUMER").unwrap_or_else(|| DEFAULT_CONSUMER.to_string());
+        Ok(Self {
+            connection,
+            processing: processing_key(&key, &consumer),
+            key,
+            channel: env_var("QUEUE_RESULT_CHANNEL").unwrap_or_else(|| DEFAULT_CHANNEL.to_string()),
+            ttl,
+        })
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Puts the jobs a previous run of this consumer left unfinished back at
+    /// the head of the queue, dropping their claims, and returns how many.
+    pub async fn recover(&mut self) -> anyhow::Result<usize> {
+        let mut recovered = 0;
+        loop {
+            let message: Option<String> = redis::cmd("LMOVE")
+                .arg(&self.processing)
+                .arg(&self.key)
+                .arg("RIGHT")
+                .arg("LEFT")
+                .query_async(&mut self.connection)
+                .await
+                .context("failed to recover unfinished jobs")?;
+            let Some(message) = message else {
+                return Ok(recovered);
+            };
+            if let Ok((Some(key), _)) = split_key(&message) {
+                let seen = seen_key(&self.key, &key);
+                let stored: Option<String> = redis::cmd("GET").arg(&seen).query_async(&mut self.connection).await?;
+                if stored.as_deref() == Some(IN_PROGRESS) {
+                    redis::cmd("DEL").arg(&seen).query_async::<_, ()>(&mut self.connection).await?;
+                }
+            }
+            recovered += 1;
+        }
+    }
+
+    /// Waits a few seconds for the next queued job, moving it onto the
+    /// processing list.
+    pub async fn pop(&mut self) -> anyhow::Result<Option<String>> {
+        redis::cmd("BLMOVE")
+            .arg(&self.key)
+            .arg(&self.processing)
+            .arg("LEFT")
+            .arg("LEFT")
+            .arg(POP_TIMEOUT)
+            .query_async(&mut self.connection)
+            .await
+            .context("failed to take a job from Redis")
+    }
+
+    /// Removes a job that needs no more processing from the processing list.
+    pub async fn ack(&mut self, message: &str) -> anyhow::Result<()> {
+        redis::cmd("LREM")
+            .arg(&self.processing)
+            .arg(1)
+            .arg(message)
+            .query_async::<_, ()>(&mut self.connection)
+            .await
+            .context("failed to acknowledge the job")
+    }
+
+    /// Puts a taken job back at the head of the queue, releasing its
+    /// idempotency key so it isn't taken for a duplicate when popped again.
+    pub async fn release(&mut self, key: Option<&str>, message: &str) -> anyhow::Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic().cmd("LREM").arg(&self.processing).arg(1).arg(message).ignore();
+        pipe.cmd("LPUSH").arg(&self.key).arg(message).ignore();
+        if let Some(key) = key {
+            pipe.cmd("DEL").arg(seen_key(&self.key, key)).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut self.connection).await.context("failed to push the job back onto the queue")
+    }
+
+    /// Claims `key` for a job about to be processed; see [`Claim`].
+    pub async fn claim(&mut self, key: Option<&str>) -> anyhow::Result<Claim> {
+        let Some(key) = key else {
+            return Ok(Claim::New);
+        };
+        let seen = seen_key(&self.key, key);
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(&seen)
+            .arg(IN_PROGRESS)
+            .arg("NX")
+            .arg("EX")
+            .arg(CLAIM_TTL)
+            .query_async(&mut self.connection)
+            .await
+            .context("failed to claim the idempotency key")?;
+        if claimed.is_some() {
+            return Ok(Claim::New);
+        }
+        let stored: Option<String> = redis::cmd("GET").arg(&seen).query_async(&mut self.connection).await?;
+        Ok(match stored.as_deref().map(serde_json::from_str) {
+            Some(Ok(result)) => Claim::Done(result),
+            _ => Claim::InProgress,
+        })
+    }
+
+    /// Renews the claim on `key` until dropped, so it outlives the job's
+    /// processing however long that takes, but not its consumer.
+    pub async fn heartbeat(&self, key: Option<&str>) {
+        let Some(key) = key else {
+            return std::future::pending().await;
+        };
+        let (seen, mut connection) = (seen_key(&self.key, key), self.connection.clone());
+        loop {
+            tokio::time::sleep(HEARTBEAT).await;
+            let renewed = redis::cmd("EXPIRE").arg(&seen).arg(CLAIM_TTL).query_async::<_, ()>(&mut connection).await;
+            if let Err(e) = renewed {
+                print_warn!("Failed to renew the claim on idempotency key {}: {}", key, e);
+            }
+        }
+    }
+
+    /// Records a job's result under its idempotency key, if it has one,
+    /// removes it from the processing list and publishes the result on the
+    /// result channel.
+    pub async fn finish(&mut self, key: Option<&str>, message: &str, result: &Value) -> anyhow::Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if let Some(key) = key {
+            pipe.cmd("SET").arg(seen_key(&self.key, key)).arg(result.to_string()).arg("EX").arg(self.ttl).ignore();
+        }
+        pipe.cmd("LREM").arg(&self.processing).arg(1).arg(message).ignore();
+        pipe.query_async::<_, ()>(&mut self.connection).await.context("failed to record the job result")?;
+        self.publish(result).await
+    }
+
+    /// Publishes `result` on the result channel.
+    pub async fn publish(&mut self, result: &Value) -> anyhow::Result<()> {
+        redis::cmd("PUBLISH")
+            .arg(&self.channel)
+            .arg(result.to_string())
+            .query_async::<_, ()>(&mut self.connection)
+            .await
+            .context("failed to publish the job result")
+    }
+}
+
+fn seen_key(queue: &str, key: &str) -> String {
+    format!("{}:seen:{}", queue, key)
+}
+
+fn processing_key(queue: &str, consumer: &str) -> String {
+    format!("{}:processing:{}", queue, consumer)
+}
+
+/// Splits a queued job into its idempotency key and the job JSON without it,
+/// in the `--params-json` format.
+pub fn split_key(message: &str) -> anyhow::Result<(Option<String>, String)> {
+    let mut job: Value = serde_json::from_str(message).context("invalid job JSON")?;
+    let key = match job.as_object_mut().and_then(|job| job.remove("idempotency_key")) {
+        Some(Value::String(key)) if !key.is_empty() => Some(key),
+        Some(Value::Null) | None => None,
+        Some(_) => anyhow::bail!("idempotency_key must be a non-empty string"),
+    };
+    Ok((key, job.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idempotency_keys() {
+        let (key, job) = split_key(r#"{"user":"0x01","nonce":1,"idempotency_key":"order-7"}"#).unwrap();
+        assert_eq!(key.as_deref(), Some("order-7"));
+        assert_eq!(serde_json::from_str::<Value>(&job).unwrap(), serde_json::json!({"user": "0x01", "nonce": 1}));
+        assert_eq!(split_key(r#"{"user":"0x01"}"#).unwrap().0, None);
+        assert!(split_key(r#"{"idempotency_key":7}"#).is_err());
+        assert!(split_key("not json").is_err());
+        assert_eq!(seen_key("jobs", "order-7"), "jobs:seen:order-7");
+        assert_eq!(processing_key("jobs", "worker-2"), "jobs:processing:worker-2");
+    }
+}
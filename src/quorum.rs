@@ -0,0 +1,133 @@
+//! Quorum reads: the state a lock is decided on (whether its nonce already
+//! has a record, the user's balance and allowance) read from every provider
+//! in RPC_URL and RPC_URLS at the same block, and acted on only when
+//! READ_QUORUM of them return the same answer. A single compromised or
+//! lagging node can then no longer feed the preflight stale state. Off when
+//! READ_QUORUM is unset.
+
+use crate::config::{self, env_var, Config, Job};
+use crate::contract::MyContract;
+use crate::error::Error;
+use crate::nonce;
+use crate::tls;
+use anyhow::Context;
+use ethers::prelude::*;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+
+pub struct Quorum {
+    /// Each provider with its label.
+    providers: Vec<(String, Arc<Provider<Http>>)>,
+    /// How many providers must agree.
+    required: usize,
+}
+
+impl Quorum {
+    /// The quorum of READ_QUORUM providers, or `None` when it is unset.
+    pub fn from_env(config: &Config) -> anyhow::Result<Option<Self>> {
+        let Some(required) = env_var("READ_QUORUM") else {
+            return Ok(None);
+        };
+        let required: usize = required.parse().context("invalid READ_QUORUM")?;
+        let urls = config::rpc_urls(config);
+        anyhow::ensure!(
+            (1..=urls.len()).contains(&required),
+            "READ_QUORUM is {}, but RPC_URL and RPC_URLS name {} providers",
+            required,
+            urls.len()
+        );
+        let providers = urls
+            .iter()
+            .map(|url| {
+                let provider = tls::provider(url)
+                    .with_context(|| format!("invalid RPC URL {}", config::rpc_label(url)))?;
+                Ok((config::rpc_label(url), Arc::new(provider)))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Some(Self { providers, required }))
+    }
+
+    /// The block reads are pinned to: the highest one at least `required`
+    /// providers have, so a lagging provider can't hold everyone back.
+    async fn block(&self) -> anyhow::Result<BlockId> {
+        let heights = self.providers.iter().map(|(_, provider)| provider.get_block_number());
+        let heights = futures::future::join_all(heights).await;
+        let mut heights: Vec<U64> = heights.into_iter().filter_map(Result::ok).collect();
+        heights.sort_unstable_by(|a, b| b.cmp(a));
+        let height = heights.get(self.required - 1).ok_or_else(|| {
+            let reason = format!("only {} providers returned their latest block", heights.len());
+            Error::CheckFailed { reason }
+        })?;
+        Ok(BlockNumber::Number(*height).into())
+    }
+
+    /// Runs `read` against every provider at the same block, returning the
+    /// answer at least `required` of them agree on. Fails with
+    /// [`Error::CheckFailed`], listing each provider's answer, when there is
+    /// no such answer.
+    pub async fn read<T, F, Fut>(&self, what: &str, read: F) -> anyhow::Result<T>
+    where
+        T: PartialEq + Debug,
+        F: Fn(Arc<Provider<Http>>, BlockId) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let block = self.block().await?;
+        let answers = self.providers.iter().map(|(_, provider)| read(provider.clone(), block));
+        let answers = futures::future::join_all(answers).await;
+        let answers: Vec<Option<T>> = answers.into_iter().map(Result::ok).collect();
+        let described: Vec<String> = self
+            .providers
+            .iter()
+            .zip(&answers)
+            .map(|((label, _), answer)| match answer {
+                Some(answer) => format!("{} {:?}", label, answer),
+                None => format!("{} failed", label),
+            })
+            .collect();
+        agreed(answers, self.required).ok_or_else(|| {
+            let reason = format!(
+                "fewer than {} providers agree on the {} at block {:?}: {}",
+                self.required,
+                what,
+                block,
+                described.join(", ")
+            );
+            Error::CheckFailed { reason }.into()
+        })
+    }
+
+    /// Whether the contract at `contract` holds a lock record for `job`'s
+    /// nonce, by quorum.
+    pub async fn is_lock_nonce_used(&self, contract: Address, job: &Job) -> anyhow::Result<bool> {
+        let (user, token, nonce) = (job.user, job.token, job.nonce);
+        let read = |provider: Arc<Provider<Http>>, block: BlockId| async move {
+            nonce::is_lock_nonce_used_at(&MyContract::new(contract, provider), user, token, nonce, block).await
+        };
+        self.read("lock record", read).await
+    }
+}
+
+/// The answer at least `required` of `answers` agree on, if any.
+fn agreed<T: PartialEq>(answers: Vec<Option<T>>, required: usize) -> Option<T> {
+    let answers: Vec<T> = answers.into_iter().flatten().collect();
+    let agreeing = |i: usize| answers.iter().filter(|answer| **answer == answers[i]).count();
+    let index = (0..answers.len()).find(|&i| agreeing(i) >= required)?;
+    answers.into_iter().nth(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agreement() {
+        assert_eq!(agreed(vec![Some(1), Some(1), Some(2)], 2), Some(1));
+        assert_eq!(agreed(vec![Some(2), Some(1), Some(1)], 2), Some(1));
+        assert_eq!(agreed(vec![Some(1), Some(2), None], 2), None);
+        // A failed read counts toward nothing.
+        assert_eq!(agreed(vec![None, Some(3), Some(3)], 2), Some(3));
+        assert_eq!(agreed(vec![Some(1), Some(1), Some(1)], 3), Some(1));
+        assert_eq!(agreed::<u8>(vec![None, None], 1), None);
+    }
+}
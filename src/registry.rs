@@ -0,0 +1,197 @@
+//! Contracts across chains for one `listen` process to follow, read from a
+//! TOML registry file:
+//!
+//! ```toml
+//! [chains.mainnet]
+//! rpc_url = "https://eth.example"
+//! rpc_urls = ["https://eth-backup.example"]   # failed over to in order
+//! ws_url = "wss://eth.example"                # polled when unset
+//!
+//! [[contracts]]
+//! name = "lock-mainnet"
+//! chain = "mainnet"
+//! address = "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+//! abi = "abis/lock.json"   # relative to the registry; abi.json when unset
+//! events = ["Locked"]      # all of the ABI's when unset
+//! where = ["token=0x0000000000000000000000000000000000000000"]
+//! ```
+//!
+//! `events` and `where` select as `--event` and `--where` do. An `abi` file
+//! is a JSON ABI or a Foundry or Hardhat artifact holding one.
+
+use crate::contract::MYCONTRACT_ABI;
+use crate::events;
+use crate::failover::{self, Failover};
+use crate::pipeline::Rpc;
+use anyhow::Context;
+use ethers::abi::Abi;
+use ethers::prelude::*;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Registry {
+    /// Endpoints of each chain, by name.
+    pub chains: BTreeMap<String, Chain>,
+    pub contracts: Vec<Contract>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Chain {
+    pub rpc_url: String,
+    /// Further endpoints, as RPC_URLS is for RPC_URL.
+    #[serde(default)]
+    pub rpc_urls: Vec<String>,
+    pub ws_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Contract {
+    /// The tag on the contract's events.
+    pub name: String,
+    /// Name of the chain in `chains`.
+    pub chain: String,
+    pub address: Address,
+    pub abi: Option<PathBuf>,
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// `NAME=VALUE` conditions on indexed parameters.
+    #[serde(default, rename = "where")]
+    pub conditions: Vec<String>,
+}
+
+/// A registry contract with its ABI loaded and its filter built.
+#[derive(Clone, Debug)]
+pub struct Watch {
+    pub name: String,
+    pub chain: String,
+    pub abi: Abi,
+    pub filter: Filter,
+}
+
+impl Watch {
+    /// `log` as [`events::to_json`] renders it, decoded against the
+    /// contract's ABI and tagged with its name and chain.
+    pub fn to_json(&self, log: &Log) -> Value {
+        let mut value = events::to_json_in(&self.abi, log);
+        value["contract"] = Value::String(self.name.clone());
+        value["chain"] = Value::String(self.chain.clone());
+        value
+    }
+}
+
+impl Registry {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| format!("failed to read registry {}", path.display()))?;
+        let registry: Self = toml::from_str(&contents).with_context(|| format!("invalid registry {}", path.display()))?;
+        registry.validate()?;
+        Ok(registry)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.contracts.is_empty(), "the registry lists no contracts");
+        let mut names = BTreeSet::new();
+        for contract in &self.contracts {
+            anyhow::ensure!(names.insert(&contract.name), "contract {:?} is listed twice", contract.name);
+            anyhow::ensure!(
+                self.chains.contains_key(&contract.chain),
+                "contract {:?} is on chain {:?}, which isn't in [chains]",
+                contract.name,
+                contract.chain
+            );
+        }
+        Ok(())
+    }
+
+    /// Each contract with its ABI, read relative to `base` (the registry's
+    /// directory), and its filter.
+    pub fn watches(&self, base: &Path) -> anyhow::Result<Vec<Watch>> {
+        let watch = |contract: &Contract| -> anyhow::Result<Watch> {
+            let abi = match &contract.abi {
+                Some(path) => read_abi(&base.join(path))?,
+                None => MYCONTRACT_ABI.clone(),
+            };
+            let conditions = contract.conditions.iter().map(String::as_str).map(events::parse_condition);
+            let conditions: Vec<_> = conditions.collect::<anyhow::Result<_>>()?;
+            let filter = events::filter_in(&abi, contract.address, &contract.events, &conditions)?;
+            Ok(Watch { name: contract.name.clone(), chain: contract.chain.clone(), abi, filter })
+        };
+        self.contracts
+            .iter()
+            .map(|contract| watch(contract).with_context(|| format!("contract {:?}", contract.name)))
+            .collect()
+    }
+
+    /// A provider for the chain named `chain`, over its endpoints.
+    pub fn provider(&self, chain: &str) -> anyhow::Result<Rpc> {
+        let chain = self.chains.get(chain).with_context(|| format!("no chain {:?} in the registry", chain))?;
+        let mut urls = vec![chain.rpc_url.clone()];
+        urls.extend(chain.rpc_urls.iter().cloned());
+        Ok(Provider::new(Failover::new(&urls, failover::Settings::from_env()?)?))
+    }
+}
+
+/// Reads a JSON ABI, or the `abi` of a Foundry or Hardhat artifact.
+fn read_abi(path: &Path) -> anyhow::Result<Abi> {
+    let text = fs::read_to_string(path).with_context(|| format!("failed to read ABI {}", path.display()))?;
+    let mut json: Value = serde_json::from_str(&text).with_context(|| format!("invalid ABI {}", path.display()))?;
+    if let Some(abi) = json.get_mut("abi") {
+        json = abi.take();
+    }
+    serde_json::from_value(json).with_context(|| format!("invalid ABI {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loading_registries() {
+        let registry: Registry = toml::from_str(
+            r#"
+            [chains.mainnet]
+            rpc_url = "https://eth.example"
+            [chains.base]
+            rpc_url = "https://base.example"
+            ws_url = "wss://base.example"
+
+            [[contracts]]
+            name = "lock-mainnet"
+            chain = "mainnet"
+            address = "0x00000000000000000000000000000000000000aa"
+            events = ["Locked"]
+            where = ["token=0x0000000000000000000000000000000000000000"]
+
+            [[contracts]]
+            name = "lock-base"
+            chain = "base"
+            address = "0x00000000000000000000000000000000000000bb"
+            "#,
+        )
+        .unwrap();
+        registry.validate().unwrap();
+        let watches = registry.watches(Path::new(".")).unwrap();
+        assert!(watches[0].filter.topics[2].is_some());
+        assert!(watches[1].filter.topics[0].is_none());
+
+        let log = Log { address: Address::repeat_byte(0xbb), topics: vec![H256::repeat_byte(2)], ..Default::default() };
+        let tagged = watches[1].to_json(&log);
+        assert_eq!((tagged["contract"].as_str(), tagged["chain"].as_str()), (Some("lock-base"), Some("base")));
+
+        let mut twice = registry.clone();
+        twice.contracts[1].name = "lock-mainnet".to_string();
+        assert!(twice.validate().is_err());
+        let mut elsewhere = registry;
+        elsewhere.contracts[0].chain = "optimism".to_string();
+        assert_eq!(
+            elsewhere.validate().unwrap_err().to_string(),
+            "contract \"lock-mainnet\" is on chain \"optimism\", which isn't in [chains]"
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! The `repl` line language: parsing an operator's input into what to do
+//! with which abi.json function, and completing commands and function names.
+//!
+//! ```text
+//! call availableBalance 0xU 0xT  eth_call a view function, decoding its outputs
+//! estimate lock ... --value 0.1   estimate the gas of a call from the sender
+//! send lock ... --value 0.1       sign and send it, after confirmation
+//! encode lock ...                 print the calldata
+//! decode 0x...                    decode calldata against abi.json
+//! functions                       list abi.json's functions
+//! ```
+//!
+//! Arguments are separated by spaces; arrays and tuples (`[1, 2]`,
+//! `(0x..., 5)`) and double-quoted strings are one argument each.
+
+use crate::contract::MYCONTRACT_ABI;
+use ethers::abi::{Function, StateMutability};
+
+/// The commands, as completed at the start of a line.
+pub const COMMANDS: &[&str] = &["call", "estimate", "send", "encode", "decode", "functions", "help", "quit"];
+
+/// A call of an abi.json function.
+#[derive(Debug)]
+pub struct Invocation {
+    pub function: Function,
+    pub args: Vec<String>,
+    /// `--value`, in the native currency, for payable functions.
+    pub value: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Input {
+    Call(Invocation),
+    Estimate(Invocation),
+    Send(Invocation),
+    Encode(Invocation),
+    Decode(String),
+    Functions,
+    Help,
+    Quit,
+}
+
+/// Parses one line; `None` for a blank one.
+pub fn parse(line: &str) -> anyhow::Result<Option<Input>> {
+    let words = split(line)?;
+    let Some((command, rest)) = words.split_first() else {
+        return Ok(None);
+    };
+    let input = match command.as_str() {
+        "call" => Input::Call(invocation(rest)?),
+        "estimate" => Input::Estimate(invocation(rest)?),
+        "send" => Input::Send(invocation(rest)?),
+        "encode" => {
+            let invocation = invocation(rest)?;
+            anyhow::ensure!(invocation.value.is_none(), "--value doesn't go into calldata");
+            Input::Encode(invocation)
+        }
+        "decode" => match rest {
+            [data] => Input::Decode(data.clone()),
+            _ => anyhow::bail!("usage: decode 0xCALLDATA"),
+        },
+        "functions" => Input::Functions,
+        "help" | "?" => Input::Help,
+        "quit" | "exit" => Input::Quit,
+        other => anyhow::bail!("unknown command {:?}; try help", other),
+    };
+    Ok(Some(input))
+}
+
+fn invocation(words: &[String]) -> anyhow::Result<Invocation> {
+    let Some((name, rest)) = words.split_first() else {
+        anyhow::bail!("which function? try functions");
+    };
+    let mut args = Vec::new();
+    let mut value = None;
+    let mut rest = rest.iter();
+    while let Some(word) = rest.next() {
+        match word.as_str() {
+            "--value" => {
+                value = Some(rest.next().cloned().ok_or_else(|| anyhow::anyhow!("--value needs an amount"))?);
+            }
+            _ => args.push(word.clone()),
+        }
+    }
+    Ok(Invocation { function: resolve(name)?, args, value })
+}
+
+/// The abi.json function called `name`, or with the signature `name` when
+/// it is overloaded, e.g. `lock(address,address,uint256,uint256,bytes)`.
+pub fn resolve(name: &str) -> anyhow::Result<Function> {
+    if name.contains('(') {
+        let signature: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+        return MYCONTRACT_ABI
+            .functions()
+            .find(|function| canonical(function) == signature)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("abi.json has no function {}", signature));
+    }
+    match MYCONTRACT_ABI.functions_by_name(name).map(Vec::as_slice) {
+        Ok([function]) => Ok(function.clone()),
+        Ok(overloads) => {
+            let signatures: Vec<String> = overloads.iter().map(canonical).collect();
+            anyhow::bail!("{} is overloaded; name one of {}", name, signatures.join(", "))
+        }
+        Err(_) => anyhow::bail!("abi.json has no function {}; try functions", name),
+    }
+}
+
+/// `name(type,...)`, the form a function is selected by.
+pub fn canonical(function: &Function) -> String {
+    let inputs: Vec<String> = function.inputs.iter().map(|param| param.kind.to_string()).collect();
+    format!("{}({})", function.name, inputs.join(","))
+}
+
+/// Whether `function` only reads, so `call` is what it's for.
+pub fn is_read(function: &Function) -> bool {
+    matches!(function.state_mutability, StateMutability::View | StateMutability::Pure)
+}
+
+/// Splits a line into words at spaces, keeping bracketed and quoted
+/// arguments whole; quotes are removed.
+pub fn split(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let (mut depth, mut quoted, mut started) = (0usize, false, false);
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+                if depth > 0 {
+                    word.push(c);
+                }
+            }
+            _ if quoted => word.push(c),
+            '[' | '(' => {
+                depth += 1;
+                word.push(c);
+            }
+            ']' | ')' => {
+                depth = depth.checked_sub(1).ok_or_else(|| anyhow::anyhow!("unbalanced {:?}", c))?;
+                word.push(c);
+            }
+            c if c.is_whitespace() && depth == 0 => {
+                if started || !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                started = false;
+            }
+            c => word.push(c),
+        }
+    }
+    anyhow::ensure!(!quoted, "unterminated quote");
+    anyhow::ensure!(depth == 0, "unclosed bracket");
+    if started || !word.is_empty() {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Completions for the word under the cursor at `pos`: a command first, then
+/// a function name (only reads after `call`, only writes after `send` and
+/// `estimate`). Returns where the word starts and the candidates.
+pub fn complete(line: &str, pos: usize) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before.rfind(char::is_whitespace).map(|index| index + 1).unwrap_or(0);
+    let prefix = &before[start..];
+    let previous: Vec<&str> = before[..start].split_whitespace().collect();
+    let mut candidates: Vec<String> = match previous.as_slice() {
+        [] => COMMANDS.iter().map(|command| command.to_string()).collect(),
+        [command] => {
+            let wanted: fn(&Function) -> bool = match *command {
+                "call" => is_read,
+                "send" | "estimate" => |function| !is_read(function),
+                "encode" => |_| true,
+                _ => return (start, Vec::new()),
+            };
+            let functions = MYCONTRACT_ABI.functions().filter(|function| wanted(function));
+            functions.map(|function| function.name.clone()).collect()
+        }
+        _ => Vec::new(),
+    };
+    candidates.retain(|candidate| candidate.starts_with(prefix));
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_lines() {
+        let words = split(r#"send lock 0x01 [1, 2] ("a b", 3) "x y" "" --value 0.5"#).unwrap();
+        assert_eq!(words, ["send", "lock", "0x01", "[1, 2]", r#"("a b", 3)"#, "x y", "", "--value", "0.5"]);
+        assert!(split("encode f [1, 2").is_err());
+        assert!(split(r#"encode f "open"#).is_err());
+
+        let Some(Input::Send(send)) = parse("send lock 0x01 0x02 5 1 0x --value 0.5").unwrap() else {
+            panic!("not a send");
+        };
+        assert_eq!(send.function.name, "lock");
+        assert_eq!(send.args.len(), 5);
+        assert_eq!(send.value.as_deref(), Some("0.5"));
+        assert!(parse("   ").unwrap().is_none());
+        assert!(parse("encode lock --value 1").is_err());
+        assert!(parse("call noSuchFunction").is_err());
+        // Overloads are picked by signature.
+        assert!(resolve("setTrustedRemote").is_err());
+        assert_eq!(resolve("setTrustedRemote(uint16, address)").unwrap().inputs[1].name, "_remoteVault");
+        assert!(matches!(parse("exit").unwrap(), Some(Input::Quit)));
+    }
+
+    #[test]
+    fn completion() {
+        assert_eq!(complete("es", 2), (0, vec!["estimate".to_string()]));
+        let (start, candidates) = complete("send lo", 7);
+        assert_eq!(start, 5);
+        assert!(candidates.contains(&"lock".to_string()));
+        assert!(candidates.iter().all(|name| resolve(name).map(|function| !is_read(&function)).unwrap_or(true)));
+        assert!(complete("decode 0x", 9).1.is_empty());
+    }
+}
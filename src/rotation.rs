@@ -0,0 +1,159 @@
+//! Rotating the key that signs jobs without orphaning work in progress: the
+//! rows of a batch that haven't been broadcast yet are re-signed with the new
+//! key and the batch file is rewritten in place, so the interrupted run can
+//! be resumed from its checkpoint as if nothing changed.
+//!
+//! The new key is NEW_SIGNER_KEY (a secret setting), which signs the same
+//! digest as STRESS_SIGNER_KEY; without it, the configured signing service
+//! is asked, for when the service already holds the new key.
+
+use crate::audit;
+use crate::batch;
+use crate::checkpoint::{Checkpoint, State};
+use crate::config::{self, env_var, Config, Job};
+use crate::contract::MyContract;
+use crate::signing_service::SigningService;
+use crate::stress::job_digest;
+use anyhow::Context;
+use ethers::prelude::*;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const KIND: &str = "lock";
+
+pub enum NewSigner {
+    Key(LocalWallet),
+    Service(SigningService),
+}
+
+impl NewSigner {
+    /// NEW_SIGNER_KEY if set, otherwise the signing service.
+    pub fn from_env() -> anyhow::Result<Self> {
+        if env_var("NEW_SIGNER_KEY").is_some() {
+            return Ok(Self::Key(config::secret("NEW_SIGNER_KEY")?.parse()?));
+        }
+        match SigningService::from_env()? {
+            Some(service) => Ok(Self::Service(service)),
+            None => anyhow::bail!("rotate-key needs the new signer: set NEW_SIGNER_KEY or SIGNER_SERVICE_URL"),
+        }
+    }
+
+    /// The new key's address, when it is held locally.
+    pub fn address(&self) -> Option<Address> {
+        match self {
+            Self::Key(wallet) => Some(wallet.address()),
+            Self::Service(_) => None,
+        }
+    }
+
+    async fn sign(&self, config: &Config, job: &mut Job) -> anyhow::Result<()> {
+        match self {
+            Self::Key(wallet) => {
+                let digest = job_digest(job)?;
+                job.signature = wallet.sign_message(digest).await?.to_vec().into();
+                audit::record_signature(wallet.address(), KIND, json!({ "job": job, "digest": H256(digest) }))
+            }
+            Self::Service(service) => service.sign(KIND, config, job).await,
+        }
+    }
+}
+
+/// Rows that still have to be sent: those the checkpoint doesn't show as
+/// broadcast or confirmed. Failed rows are sent again on resume, so they
+/// count too.
+pub fn unsent_rows(jobs: &[Job], checkpoint: Option<&Checkpoint>) -> Vec<usize> {
+    (0..jobs.len())
+        .filter(|index| {
+            let state = checkpoint.and_then(|checkpoint| checkpoint.last(*index)).map(|record| record.state);
+            !matches!(state, Some(State::Submitted | State::Confirmed))
+        })
+        .collect()
+}
+
+/// What a rotation changed.
+pub struct Report {
+    pub resigned: usize,
+    /// Rows left alone because they were already broadcast.
+    pub kept: usize,
+    /// The batch file as it was before the rotation.
+    pub backup: PathBuf,
+}
+
+/// Re-signs the unsent rows of the batch `file` (per its checkpoint, when one
+/// exists) with `signer` and rewrites the file, keeping the original next to
+/// it as `<file>.pre-rotation`. The checkpoint matches rows by user, token and
+/// nonce, which stay the same, so it remains valid.
+pub async fn rotate(config: &Config, file: &Path, checkpoint: &Path, signer: &NewSigner) -> anyhow::Result<Report> {
+    let mut jobs = batch::read_jobs(file)?;
+    let checkpoint = match checkpoint.exists() {
+        true => Some(Checkpoint::resume(checkpoint, &jobs)?),
+        false => None,
+    };
+    let rows = unsent_rows(&jobs, checkpoint.as_ref());
+    for index in &rows {
+        signer.sign(config, &mut jobs[*index]).await.with_context(|| format!("failed to re-sign row {}", index + 1))?;
+    }
+
+    let mut backup = file.as_os_str().to_owned();
+    backup.push(".pre-rotation");
+    let backup = PathBuf::from(backup);
+    anyhow::ensure!(!backup.exists(), "{} is left from an earlier rotation; move it away first", backup.display());
+    fs::copy(file, &backup).with_context(|| format!("failed to back up {}", file.display()))?;
+    let mut temporary = file.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    batch::write_jobs(&temporary, &jobs)?;
+    fs::rename(&temporary, file).with_context(|| format!("failed to replace {}", file.display()))?;
+
+    Ok(Report { resigned: rows.len(), kept: jobs.len() - rows.len(), backup })
+}
+
+/// The signer the contract checks job signatures against.
+pub async fn contract_relayer<M: Middleware + 'static>(client: Arc<M>, config: &Config) -> anyhow::Result<Address> {
+    Ok(MyContract::new(config.contract_address, client).relayer().call().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::Record;
+
+    fn job(nonce: u64) -> Job {
+        // r = 1, s = 1, v = 27: well-formed, if signed by no one in particular.
+        let mut signature = [0; 65];
+        (signature[31], signature[63], signature[64]) = (1, 1, 27);
+        Job {
+            user: Address::repeat_byte(1),
+            token: Address::repeat_byte(2),
+            amount: 1000.into(),
+            nonce: nonce.into(),
+            signature: signature.to_vec().into(),
+        }
+    }
+
+    #[test]
+    fn unsent() {
+        let path = std::env::temp_dir().join(format!("rotation-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let jobs = [job(1), job(2), job(3), job(4)];
+        let checkpoint = Checkpoint::create(&path).unwrap();
+        for (index, state) in [(0, State::Confirmed), (1, State::Submitted), (2, State::Failed)] {
+            checkpoint.record(&Record::new(index, &jobs[index], state, Address::zero(), None, None)).unwrap();
+        }
+        let checkpoint = Checkpoint::resume(&path, &jobs).unwrap();
+        assert_eq!(unsent_rows(&jobs, Some(&checkpoint)), [2, 3]);
+        assert_eq!(unsent_rows(&jobs, None), [0, 1, 2, 3]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn batch_files_round_trip() {
+        let path = std::env::temp_dir().join(format!("rotation-batch-test-{}.csv", std::process::id()));
+        let jobs = vec![job(10), job(0x1a)];
+        batch::write_jobs(&path, &jobs).unwrap();
+        assert_eq!(batch::read_jobs(&path).unwrap(), jobs);
+        fs::remove_file(&path).unwrap();
+    }
+}
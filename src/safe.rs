@@ -0,0 +1,564 @@
+//! Gnosis Safe mode: the contract call is wrapped in a Safe transaction and
+//! either executed directly (threshold 1) or proposed to the Safe Transaction
+//! Service for the other owners to confirm.
+
+use crate::audit;
+use crate::config::{var, Job};
+use crate::contract::GnosisSafe;
+use crate::pipeline::{self, Client};
+use crate::print_ok;
+use ethers::abi::{encode, Token};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::{keccak256, to_checksum};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+const DOMAIN_SEPARATOR_TYPEHASH: &str = "EIP712Domain(uint256 chainId,address verifyingContract)";
+const SAFE_TX_TYPEHASH: &str = "SafeTx(address to,uint256 value,bytes data,uint8 operation,uint256 safeTxGas,uint256 baseGas,uint256 gasPrice,address gasToken,address refundReceiver,uint256 nonce)";
+
+/// The fields of a Safe transaction, as hashed by `getTransactionHash`.
+/// Gas refund fields are always zero: the executor pays its own gas.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeTx {
+    pub to: Address,
+    pub value: U256,
+    pub data: Bytes,
+    pub operation: u8,
+    pub safe_tx_gas: U256,
+    pub base_gas: U256,
+    pub gas_price: U256,
+    pub gas_token: Address,
+    pub refund_receiver: Address,
+    pub nonce: U256,
+}
+
+impl SafeTx {
+    /// Wraps a prepared contract call as a plain `CALL` from the Safe.
+    pub fn from_call(tx: &TypedTransaction, nonce: U256) -> anyhow::Result<Self> {
+        let to = tx
+            .to_addr()
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("contract call has no target address"))?;
+        Ok(Self {
+            to,
+            value: tx.value().copied().unwrap_or_default(),
+            data: tx.data().cloned().unwrap_or_default(),
+            operation: 0,
+            safe_tx_gas: U256::zero(),
+            base_gas: U256::zero(),
+            gas_price: U256::zero(),
+            gas_token: Address::zero(),
+            refund_receiver: Address::zero(),
+            nonce,
+        })
+    }
+
+    /// The Safe's EIP-712 hash of this transaction, computed by the Safe itself
+    /// so it matches whatever version is deployed.
+    pub async fn hash<M: Middleware + 'static>(&self, safe: &GnosisSafe<M>) -> anyhow::Result<H256> {
+        let hash = safe
+            .get_transaction_hash(
+                self.to,
+                self.value,
+                self.data.clone(),
+                self.operation,
+                self.safe_tx_gas,
+                self.base_gas,
+                self.gas_price,
+                self.gas_token,
+                self.refund_receiver,
+                self.nonce,
+            )
+            .call()
+            .await?;
+        Ok(H256::from(hash))
+    }
+
+    /// Computes the Safe transaction hash locally, as Safe v1.3+ does, so
+    /// co-signers can check what they sign without trusting an RPC.
+    pub fn eip712_hash(&self, chain_id: u64, safe: Address) -> H256 {
+        let domain_separator = keccak256(encode(&[
+            Token::FixedBytes(keccak256(DOMAIN_SEPARATOR_TYPEHASH).to_vec()),
+            Token::Uint(chain_id.into()),
+            Token::Address(safe),
+        ]));
+        let struct_hash = keccak256(encode(&[
+            Token::FixedBytes(keccak256(SAFE_TX_TYPEHASH).to_vec()),
+            Token::Address(self.to),
+            Token::Uint(self.value),
+            Token::FixedBytes(keccak256(&self.data).to_vec()),
+            Token::Uint(self.operation.into()),
+            Token::Uint(self.safe_tx_gas),
+            Token::Uint(self.base_gas),
+            Token::Uint(self.gas_price),
+            Token::Address(self.gas_token),
+            Token::Address(self.refund_receiver),
+            Token::Uint(self.nonce),
+        ]));
+
+        let mut preimage = Vec::with_capacity(66);
+        preimage.extend_from_slice(&[0x19, 0x01]);
+        preimage.extend_from_slice(&domain_separator);
+        preimage.extend_from_slice(&struct_hash);
+        H256::from(keccak256(preimage))
+    }
+
+    /// The `execTransaction` call carrying `signatures`.
+    pub fn exec_call(&self, safe: &GnosisSafe<Client>, signatures: Bytes) -> ContractCall<Client, bool> {
+        safe.exec_transaction(
+            self.to,
+            self.value,
+            self.data.clone(),
+            self.operation,
+            self.safe_tx_gas,
+            self.base_gas,
+            self.gas_price,
+            self.gas_token,
+            self.refund_receiver,
+            signatures,
+        )
+    }
+}
+
+/// Signs a Safe transaction hash directly (no message prefix), producing the
+/// 65-byte `r . s . v` form the Safe accepts with v = 27/28.
+pub fn sign(wallet: &LocalWallet, safe_tx_hash: H256) -> anyhow::Result<Signature> {
+    let signature = wallet.sign_hash(safe_tx_hash)?;
+    let params = serde_json::json!({ "safe_tx_hash": safe_tx_hash });
+    audit::record_signature(wallet.address(), "safe", params)?;
+    Ok(signature)
+}
+
+/// A contract call wrapped in a Safe transaction by [`wrap`], checked
+/// against the Safe but not yet signed or sent.
+pub struct Wrapped {
+    pub safe: Address,
+    pub tx: SafeTx,
+    pub safe_tx_hash: H256,
+    pub threshold: U256,
+}
+
+impl Wrapped {
+    /// Whether the owner alone meets the threshold, so the Safe transaction
+    /// is executed at once rather than proposed.
+    pub fn executes(&self) -> bool {
+        self.threshold <= U256::one()
+    }
+
+    /// The `execTransaction` call for `owner` to send. As the sender it
+    /// approves the transaction with a `v = 1` signature rather than by
+    /// signing the hash, so nothing is signed until it is broadcast.
+    pub fn exec_transaction(&self, client: &Arc<Client>, owner: Address) -> TypedTransaction {
+        let safe = GnosisSafe::new(self.safe, client.clone());
+        self.tx.exec_call(&safe, approved_by(owner)).tx
+    }
+
+    /// Signs the Safe transaction hash with the client's wallet and proposes
+    /// it to the Safe Transaction Service for the other owners to confirm.
+    pub async fn propose(&self, client: &Client) -> anyhow::Result<()> {
+        let signature = sign(client.signer(), self.safe_tx_hash)?;
+        let service_url = var("SAFE_TX_SERVICE_URL")?;
+        propose(&service_url, self.safe, &self.tx, self.safe_tx_hash, client.address(), &signature).await?;
+        print_ok!("Proposed to the Safe Transaction Service; {} more confirmation(s) needed", self.threshold - 1);
+        Ok(())
+    }
+}
+
+/// Wraps `tx` in a Safe transaction for the client's wallet, which must be a
+/// Safe owner, checking the Safe's hash of it against the EIP-712 one.
+pub async fn wrap(client: &Arc<Client>, safe_address: Address, tx: &TypedTransaction) -> anyhow::Result<Wrapped> {
+    let safe = GnosisSafe::new(safe_address, client.clone());
+    let signer = client.address();
+    anyhow::ensure!(
+        safe.is_owner(signer).call().await?,
+        "wallet {:?} is not an owner of Safe {:?}",
+        signer,
+        safe_address
+    );
+
+    let safe_tx = SafeTx::from_call(tx, safe.nonce().call().await?)?;
+    let safe_tx_hash = safe_tx.hash(&safe).await?;
+    // The Safe hashes with the node's chain id in its EIP-712 domain; the
+    // local hash uses the wallet's.
+    let node_chain_id = client.get_chainid().await?.as_u64();
+    let wallet_chain_id = client.signer().chain_id();
+    pipeline::ensure_same_chain(&[("wallet", Some(wallet_chain_id)), ("node", Some(node_chain_id))])?;
+    anyhow::ensure!(
+        safe_tx.eip712_hash(wallet_chain_id, safe_address) == safe_tx_hash,
+        "Safe reports hash {:?}, which doesn't match the EIP-712 hash for chain {}; only Safe v1.3+ is supported",
+        safe_tx_hash,
+        wallet_chain_id
+    );
+    let threshold = safe.get_threshold().call().await?;
+
+    println!("=== Safe Transaction ===");
+    println!("Safe Address: {:?}", safe_address);
+    println!("Safe Nonce: {}", safe_tx.nonce);
+    println!("Threshold: {}", threshold);
+    println!("Safe Tx Hash: {:?}", safe_tx_hash);
+    println!();
+    Ok(Wrapped { safe: safe_address, tx: safe_tx, safe_tx_hash, threshold })
+}
+
+/// The signature by which `owner` approves a Safe transaction it sends
+/// itself: `r` is the owner, `s` zero and `v` 1.
+fn approved_by(owner: Address) -> Bytes {
+    let mut signature = [0u8; 65];
+    signature[12..32].copy_from_slice(owner.as_bytes());
+    signature[64] = 1;
+    signature.to_vec().into()
+}
+
+/// The Safe Transaction Service's multisig-transaction proposal body.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Proposal<'a> {
+    #[serde(flatten)]
+    tx: ServiceTx<'a>,
+    contract_transaction_hash: H256,
+    sender: String,
+    signature: String,
+    origin: &'static str,
+}
+
+/// The service wants checksummed addresses and decimal amounts.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServiceTx<'a> {
+    to: String,
+    value: String,
+    data: &'a Bytes,
+    operation: u8,
+    safe_tx_gas: String,
+    base_gas: String,
+    gas_price: String,
+    gas_token: String,
+    refund_receiver: String,
+    nonce: String,
+}
+
+pub async fn propose(
+    service_url: &str,
+    safe_address: Address,
+    tx: &SafeTx,
+    safe_tx_hash: H256,
+    sender: Address,
+    signature: &Signature,
+) -> anyhow::Result<()> {
+    let body = Proposal {
+        tx: ServiceTx {
+            to: to_checksum(&tx.to, None),
+            value: tx.value.to_string(),
+            data: &tx.data,
+            operation: tx.operation,
+            safe_tx_gas: tx.safe_tx_gas.to_string(),
+            base_gas: tx.base_gas.to_string(),
+            gas_price: tx.gas_price.to_string(),
+            gas_token: to_checksum(&tx.gas_token, None),
+            refund_receiver: to_checksum(&tx.refund_receiver, None),
+            nonce: tx.nonce.to_string(),
+        },
+        contract_transaction_hash: safe_tx_hash,
+        sender: to_checksum(&sender, None),
+        signature: format!("0x{}", signature),
+        origin: "ethers-rusty",
+    };
+
+    let url = format!(
+        "{}/api/v1/safes/{}/multisig-transactions/",
+        service_url.trim_end_matches('/'),
+        to_checksum(&safe_address, None)
+    );
+    let response = reqwest::Client::new().post(&url).json(&body).send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        anyhow::bail!("Safe Transaction Service rejected the proposal ({}): {}", status, text);
+    }
+    Ok(())
+}
+
+/// A co-signer's signature over a bundle's Safe transaction hash.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OwnerSignature {
+    pub signer: Address,
+    pub signature: Signature,
+}
+
+/// A Safe transaction exported for offline signing by several owners. The
+/// bundle travels between co-signers as a JSON file and collects one
+/// signature per owner until the threshold is met.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bundle {
+    /// Subcommand the wrapped call came from, `lock` or `unlock`.
+    pub kind: String,
+    pub job: Job,
+    pub chain_id: u64,
+    pub safe: Address,
+    pub safe_tx_hash: H256,
+    pub tx: SafeTx,
+    #[serde(default)]
+    pub signatures: Vec<OwnerSignature>,
+}
+
+impl Bundle {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Checks the recorded hash against the transaction fields, so a tampered
+    /// bundle cannot trick a co-signer into signing something else.
+    pub fn verify_hash(&self) -> anyhow::Result<()> {
+        let expected = self.tx.eip712_hash(self.chain_id, self.safe);
+        anyhow::ensure!(
+            expected == self.safe_tx_hash,
+            "bundle hash {:?} does not match its transaction (expected {:?})",
+            self.safe_tx_hash,
+            expected
+        );
+        Ok(())
+    }
+
+    /// Signs the bundle with `wallet`, replacing any earlier signature from
+    /// the same owner.
+    pub fn add_signature(&mut self, wallet: &LocalWallet) -> anyhow::Result<()> {
+        self.verify_hash()?;
+        let signature = sign(wallet, self.safe_tx_hash)?;
+        self.signatures.retain(|s| s.signer != wallet.address());
+        self.signatures.push(OwnerSignature { signer: wallet.address(), signature });
+        Ok(())
+    }
+
+    /// Concatenates the collected signatures in ascending signer order, as
+    /// `execTransaction` requires, after checking each one recovers to the
+    /// owner it claims to be from.
+    pub fn packed_signatures(&self) -> anyhow::Result<Bytes> {
+        let mut signatures = self.signatures.clone();
+        signatures.sort_by_key(|s| s.signer);
+
+        let mut packed = Vec::with_capacity(signatures.len() * 65);
+        for owner in &signatures {
+            let recovered = owner.signature.recover(RecoveryMessage::Hash(self.safe_tx_hash))?;
+            anyhow::ensure!(
+                recovered == owner.signer,
+                "signature listed for {:?} was made by {:?}",
+                owner.signer,
+                recovered
+            );
+            packed.extend_from_slice(&owner.signature.to_vec());
+        }
+        Ok(packed.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node;
+    use ethers::types::transaction::eip712::{Eip712, TypedData};
+    use serde_json::json;
+
+    const SAFE: Address = H160([0x5a; 20]);
+    const TARGET: Address = H160([0x22; 20]);
+
+    fn call() -> TypedTransaction {
+        TransactionRequest::new().to(TARGET).value(7).data(vec![0xde, 0xad, 0xbe, 0xef]).into()
+    }
+
+    #[test]
+    fn wraps_the_call() {
+        let tx = SafeTx::from_call(&call(), 3.into()).unwrap();
+        assert_eq!(tx.to, TARGET);
+        assert_eq!(tx.value, 7.into());
+        assert_eq!(tx.data.to_vec(), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(tx.operation, 0);
+        assert_eq!(tx.nonce, 3.into());
+        assert!(tx.safe_tx_gas.is_zero() && tx.base_gas.is_zero() && tx.gas_price.is_zero());
+        assert_eq!((tx.gas_token, tx.refund_receiver), (Address::zero(), Address::zero()));
+
+        let deployment: TypedTransaction = TransactionRequest::new().data(vec![0x60]).into();
+        assert!(SafeTx::from_call(&deployment, 0.into()).is_err());
+    }
+
+    #[test]
+    fn hashes_as_eip712_typed_data() {
+        let tx = SafeTx::from_call(&call(), 3.into()).unwrap();
+        let typed: TypedData = serde_json::from_value(json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" },
+                ],
+                "SafeTx": [
+                    { "name": "to", "type": "address" },
+                    { "name": "value", "type": "uint256" },
+                    { "name": "data", "type": "bytes" },
+                    { "name": "operation", "type": "uint8" },
+                    { "name": "safeTxGas", "type": "uint256" },
+                    { "name": "baseGas", "type": "uint256" },
+                    { "name": "gasPrice", "type": "uint256" },
+                    { "name": "gasToken", "type": "address" },
+                    { "name": "refundReceiver", "type": "address" },
+                    { "name": "nonce", "type": "uint256" },
+                ],
+            },
+            "primaryType": "SafeTx",
+            "domain": { "chainId": 8453, "verifyingContract": SAFE },
+            "message": {
+                "to": TARGET,
+                "value": "7",
+                "data": "0xdeadbeef",
+                "operation": 0,
+                "safeTxGas": "0",
+                "baseGas": "0",
+                "gasPrice": "0",
+                "gasToken": Address::zero(),
+                "refundReceiver": Address::zero(),
+                "nonce": "3",
+            },
+        }))
+        .unwrap();
+        assert_eq!(tx.eip712_hash(8453, SAFE), H256(typed.encode_eip712().unwrap()));
+        assert_ne!(tx.eip712_hash(1, SAFE), tx.eip712_hash(8453, SAFE));
+    }
+
+    #[test]
+    fn signs_the_hash_directly() {
+        let wallet = LocalWallet::from_bytes(&[0x42; 32]).unwrap();
+        let hash = SafeTx::from_call(&call(), 0.into()).unwrap().eip712_hash(8453, SAFE);
+        let signature = sign(&wallet, hash).unwrap();
+        assert!(signature.v == 27 || signature.v == 28);
+        assert_eq!(signature.recover(RecoveryMessage::Hash(hash)).unwrap(), wallet.address());
+    }
+
+    #[test]
+    fn approves_as_the_sending_owner() {
+        let owner = Address::repeat_byte(0x0a);
+        let signature = approved_by(owner);
+        assert_eq!(signature.len(), 65);
+        assert_eq!(H256::from_slice(&signature[..32]), H256::from(owner));
+        assert!(signature[32..64].iter().all(|byte| *byte == 0));
+        assert_eq!(signature[64], 1);
+    }
+
+    fn bundle() -> Bundle {
+        let tx = SafeTx::from_call(&call(), 0.into()).unwrap();
+        let job = Job::parse(&format!("{:?}", H160([0x11; 20])), &format!("{:?}", H160([0x33; 20])), "100", "1", "0x").unwrap();
+        Bundle { kind: "lock".into(), job, chain_id: 8453, safe: SAFE, safe_tx_hash: tx.eip712_hash(8453, SAFE), tx, signatures: Vec::new() }
+    }
+
+    #[test]
+    fn collects_signatures_in_signer_order() {
+        let owners: Vec<LocalWallet> = [0x42, 0x43, 0x44].iter().map(|byte| LocalWallet::from_bytes(&[*byte; 32]).unwrap()).collect();
+        let mut bundle = bundle();
+        for owner in &owners {
+            bundle.add_signature(owner).unwrap();
+        }
+        bundle.add_signature(&owners[0]).unwrap();
+        assert_eq!(bundle.signatures.len(), 3);
+
+        let packed = bundle.packed_signatures().unwrap();
+        assert_eq!(packed.len(), 3 * 65);
+        let signers: Vec<Address> = packed
+            .chunks(65)
+            .map(|chunk| Signature::try_from(chunk).unwrap().recover(RecoveryMessage::Hash(bundle.safe_tx_hash)).unwrap())
+            .collect();
+        let mut sorted = signers.clone();
+        sorted.sort();
+        assert_eq!(signers, sorted);
+    }
+
+    #[test]
+    fn rejects_tampered_bundles() {
+        let wallet = LocalWallet::from_bytes(&[0x42; 32]).unwrap();
+        let mut tampered = bundle();
+        tampered.verify_hash().unwrap();
+        tampered.tx.value = 8.into();
+        assert!(tampered.verify_hash().is_err());
+        assert!(tampered.add_signature(&wallet).is_err());
+
+        let mut signed = bundle();
+        signed.add_signature(&wallet).unwrap();
+        signed.signatures[0].signer = H160([0x99; 20]);
+        let error = signed.packed_signatures().unwrap_err();
+        assert!(error.to_string().contains("was made by"), "{}", error);
+    }
+
+    /// A Safe at [`SAFE`] with `threshold`, at nonce 5, that reports
+    /// `reported` as the hash of any transaction.
+    async fn safe_node(threshold: u64, reported: H256) -> mock_node::MockNode {
+        mock_node::MockNode::start(move |method, params| {
+            let data = params[0]["data"].as_str().or(params[0]["input"].as_str()).unwrap_or_default();
+            let selector = |signature: &str| {
+                let selector = hex::encode(ethers::utils::id(signature));
+                data.starts_with(&format!("0x{}", selector))
+            };
+            let word = |value: U256| mock_node::Reply::Result(json!(Bytes::from(encode(&[Token::Uint(value)]))));
+            let hash_of =
+                "getTransactionHash(address,uint256,bytes,uint8,uint256,uint256,uint256,address,address,uint256)";
+            match method {
+                "eth_call" if selector("isOwner(address)") => word(U256::one()),
+                "eth_call" if selector("nonce()") => word(5.into()),
+                "eth_call" if selector("getThreshold()") => word(threshold.into()),
+                "eth_call" if selector(hash_of) => mock_node::Reply::Result(json!(Bytes::from(reported.0.to_vec()))),
+                _ => mock_node::idle(method, params),
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn routing_through_the_safe() {
+        let expected = SafeTx::from_call(&call(), 5.into()).unwrap().eip712_hash(mock_node::CHAIN_ID, SAFE);
+
+        // An owner who meets the threshold alone executes the transaction.
+        let node = safe_node(1, expected).await;
+        let client = node.client();
+        let wrapped = wrap(&client, SAFE, &call()).await.unwrap();
+        assert!(wrapped.executes());
+        assert_eq!((wrapped.tx.nonce, wrapped.safe_tx_hash), (5.into(), expected));
+        let exec = wrapped.exec_transaction(&client, client.address());
+        assert_eq!(exec.to_addr(), Some(&SAFE));
+        let data = exec.data().unwrap();
+        assert!(data.starts_with(&ethers::utils::id(
+            "execTransaction(address,uint256,bytes,uint8,uint256,uint256,uint256,address,address,bytes)"
+        )));
+        let approval = approved_by(client.address());
+        assert!(data.windows(approval.len()).any(|window| window == approval.as_ref()));
+
+        // Otherwise it is proposed, signed over the Safe transaction hash.
+        let node = safe_node(2, expected).await;
+        let wrapped = wrap(&node.client(), SAFE, &call()).await.unwrap();
+        assert!(!wrapped.executes());
+        let proposals = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = proposals.clone();
+        let service = mock_node::http_server(move |head, body| {
+            received.lock().unwrap().push((head.lines().next().unwrap_or_default().to_string(), body.to_string()));
+            "{}".to_string()
+        })
+        .await;
+        let wallet = mock_node::wallet();
+        let signature = sign(&wallet, wrapped.safe_tx_hash).unwrap();
+        propose(&service, SAFE, &wrapped.tx, wrapped.safe_tx_hash, wallet.address(), &signature).await.unwrap();
+        let (request, body) = proposals.lock().unwrap()[0].clone();
+        assert_eq!(request, format!("POST /api/v1/safes/{}/multisig-transactions/ HTTP/1.1", to_checksum(&SAFE, None)));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!((body["nonce"].as_str(), body["value"].as_str()), (Some("5"), Some("7")));
+        assert_eq!(body["contractTransactionHash"], json!(expected));
+        assert_eq!(body["sender"], to_checksum(&wallet.address(), None));
+
+        // A Safe whose hash differs from ours isn't one we can sign for.
+        let node = safe_node(1, H256::repeat_byte(1)).await;
+        let error = wrap(&node.client(), SAFE, &call()).await.err().unwrap();
+        assert!(error.to_string().contains("doesn't match the EIP-712 hash"), "{}", error);
+    }
+}
@@ -0,0 +1,122 @@
+//! Scheduled sends: holding a transaction back until the chain reaches a
+//! target block or block timestamp, e.g. for locks that must land after a
+//! contract's activation block.
+//!
+//! Times are compared against block timestamps rather than the local clock,
+//! since that is what the contract sees.
+
+use crate::print_ok;
+use anyhow::Context;
+use ethers::prelude::*;
+use std::time::Duration;
+
+/// When a scheduled transaction may be broadcast.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Once this block has been mined.
+    Block(u64),
+    /// Once a block with at least this Unix timestamp has been mined.
+    Timestamp(u64),
+}
+
+impl Schedule {
+    fn is_due(&self, number: u64, timestamp: u64) -> bool {
+        match *self {
+            Schedule::Block(target) => number >= target,
+            Schedule::Timestamp(target) => timestamp >= target,
+        }
+    }
+}
+
+/// Polls the latest block every `interval` until `schedule` is due.
+pub async fn wait_until<M: Middleware>(client: &M, schedule: Schedule, interval: Duration) -> anyhow::Result<()>
+where
+    M::Error: 'static,
+{
+    let mut announced = false;
+    loop {
+        let block = client.get_block(BlockNumber::Latest).await?.context("node returned no latest block")?;
+        let number = block.number.context("latest block has no number")?.as_u64();
+        let timestamp = block.timestamp.as_u64();
+        if schedule.is_due(number, timestamp) {
+            print_ok!("Block {} (timestamp {}) reached; sending", number, timestamp);
+            println!();
+            return Ok(());
+        }
+        if !announced {
+            match schedule {
+                Schedule::Block(target) => println!("Waiting for block {} (currently {})", target, number),
+                Schedule::Timestamp(target) => println!("Waiting for block timestamp {} (currently {})", target, timestamp),
+            }
+        }
+        announced = true;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Parses a point in time given as Unix seconds or as a UTC date and time
+/// such as `2024-07-01T00:00Z` (seconds optional).
+pub fn parse_timestamp(input: &str) -> anyhow::Result<u64> {
+    let input = input.trim();
+    if let Ok(seconds) = input.parse::<u64>() {
+        return Ok(seconds);
+    }
+    let invalid = || format!("invalid time {:?}: expected Unix seconds or YYYY-MM-DDTHH:MM[:SS]Z", input);
+    let utc = input.strip_suffix('Z').with_context(invalid)?;
+    let (date, time) = utc.split_once('T').with_context(invalid)?;
+
+    let fields = |s: &str, sep: char| -> anyhow::Result<Vec<u32>> {
+        s.split(sep).map(|field| field.parse::<u32>().with_context(invalid)).collect()
+    };
+    let (date, time) = (fields(date, '-')?, fields(time, ':')?);
+    let ([year, month, day], [hour, minute, second]) = match (date.as_slice(), time.as_slice()) {
+        (&[y, mo, d], &[h, mi]) => ([y, mo, d], [h, mi, 0]),
+        (&[y, mo, d], &[h, mi, s]) => ([y, mo, d], [h, mi, s]),
+        _ => anyhow::bail!(invalid()),
+    };
+    anyhow::ensure!(
+        year >= 1970 && (1..=12).contains(&month) && (1..=31).contains(&day) && hour < 24 && minute < 60 && second < 60,
+        invalid()
+    );
+
+    let days = days_from_civil(year as i64, month, day);
+    Ok(days as u64 * 86_400 + (hour * 3600 + minute * 60 + second) as u64)
+}
+
+/// Days between 1970-01-01 and the given proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("1719792000").unwrap(), 1_719_792_000);
+        assert_eq!(parse_timestamp("1970-01-01T00:00Z").unwrap(), 0);
+        assert_eq!(parse_timestamp("2024-07-01T00:00Z").unwrap(), 1_719_792_000);
+        assert_eq!(parse_timestamp("2024-02-29T12:30:15Z").unwrap(), 1_709_209_815);
+        assert_eq!(parse_timestamp("2000-03-01T00:00Z").unwrap(), 951_868_800);
+
+        assert!(parse_timestamp("2024-07-01T00:00").is_err());
+        assert!(parse_timestamp("2024-07-01").is_err());
+        assert!(parse_timestamp("2024-13-01T00:00Z").is_err());
+        assert!(parse_timestamp("2024-07-01T24:00Z").is_err());
+        assert!(parse_timestamp("2024-07-01T00:00:00:00Z").is_err());
+    }
+
+    #[test]
+    fn due() {
+        assert!(!Schedule::Block(100).is_due(99, 5_000));
+        assert!(Schedule::Block(100).is_due(100, 0));
+        assert!(!Schedule::Timestamp(5_000).is_due(1_000, 4_999));
+        assert!(Schedule::Timestamp(5_000).is_due(0, 5_000));
+    }
+}
@@ -0,0 +1,441 @@
+//! Transaction workflows as [Rhai](https://rhai.rs) scripts, for flows too
+//! bespoke for `batch` or a workflow file, without recompiling the crate.
+//! A script gets the contract's functions through a handful of primitives:
+//!
+//! - `read(name, args)` eth_calls a view function and returns its output, or
+//!   a map of its outputs by name when there are several;
+//! - `estimate(name, args[, value])` returns the gas of a call from the sender;
+//! - `encode(name, args)` returns the calldata;
+//! - `sign(name, args[, value])` returns the signed raw transaction, which
+//!   `broadcast(raw)` sends, claiming a lock or unlock in the ledger first;
+//! - `send(name, args[, value])` signs and sends, returning the hash;
+//! - `wait(hash)` waits for the receipt: `#{tx_hash, status, block, gas_used,
+//!   events}`, each event `#{name, args}`;
+//! - `notify(value)` hands `value`, as JSON, to the notify command.
+//!
+//! Functions are named as in the `repl`, by signature when overloaded; a
+//! `value` is in the native currency, e.g. `"0.5"`. Numbers come back as
+//! decimal strings, since they rarely fit Rhai's 64-bit integers, and
+//! addresses and bytes as `0x` strings. Scripts see `CONTRACT`, `SENDER`,
+//! `CHAIN_ID` and their command line as `ARGS`.
+//!
+//! Built only with the `scripting` feature.
+
+use crate::audit;
+use crate::calldata;
+use crate::config::Config;
+use crate::fees::FeeModel;
+use crate::hooks;
+use crate::ledger::Ledger;
+use crate::pipeline::{self, Client};
+use crate::print_warn;
+use crate::profile;
+use crate::repl;
+use crate::units::{self, Rounding};
+use anyhow::Context;
+use ethers::abi::{Function, ParamType, Token};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::rlp::Rlp;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+
+/// What scripts run against.
+pub struct Host {
+    config: Config,
+    simulation: Arc<Client>,
+    sender: Address,
+    /// The signing client, connected on the first `sign` or `send`.
+    client: Mutex<Option<Arc<Client>>>,
+    /// Where the lock and unlock jobs scripts broadcast are claimed.
+    ledger: Ledger,
+    /// Shell command `notify` runs with its value on stdin; without one the
+    /// value is printed.
+    on_notify: Option<String>,
+    runtime: Handle,
+}
+
+type Outcome<T> = Result<T, Box<EvalAltResult>>;
+
+impl Host {
+    /// Must be called inside the Tokio runtime; scripts are then run on a
+    /// blocking thread, see [`run`].
+    pub fn new(config: Config, ledger: Ledger, on_notify: Option<String>) -> anyhow::Result<Self> {
+        let simulation = pipeline::connect_simulation(&config)?;
+        let sender = pipeline::sender_address(&*simulation)?;
+        let client = Mutex::new(None);
+        Ok(Self { config, simulation, sender, client, ledger, on_notify, runtime: Handle::current() })
+    }
+
+    /// Runs `future` to completion from a script, turning its error into a
+    /// script error.
+    fn block<T>(&self, future: impl Future<Output = anyhow::Result<T>>) -> Outcome<T> {
+        self.runtime.block_on(future).map_err(|e| format!("{:#}", e).into())
+    }
+
+    fn client(&self) -> anyhow::Result<Arc<Client>> {
+        let mut client = self.client.lock().expect("client lock poisoned");
+        if let Some(client) = &*client {
+            return Ok(client.clone());
+        }
+        let connected = pipeline::connect(&self.config)?;
+        anyhow::ensure!(
+            connected.address() == self.sender,
+            "PRIVATE_KEY belongs to {:?}, but scripts read and estimate as {:?}",
+            connected.address(),
+            self.sender
+        );
+        Ok(client.insert(connected).clone())
+    }
+
+    /// A transaction from the sender calling the contract's function `name`.
+    fn transaction(
+        &self,
+        name: &str,
+        args: Array,
+        value: Option<&str>,
+    ) -> anyhow::Result<(Function, TypedTransaction)> {
+        let function = repl::resolve(name)?;
+        anyhow::ensure!(
+            function.inputs.len() == args.len(),
+            "{} takes {} argument(s), got {}",
+            function.name,
+            function.inputs.len(),
+            args.len()
+        );
+        let args = function
+            .inputs
+            .iter()
+            .zip(args)
+            .map(|(param, arg)| argument(arg, &param.kind).map_err(anyhow::Error::msg))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let data = calldata::encode_function(&function, &args)?;
+        let mut tx = Eip1559TransactionRequest::new().from(self.sender).to(self.config.contract_address).data(data);
+        if let Some(value) = value {
+            let decimals = profile::native_currency(self.config.chain_id)?.decimals;
+            tx = tx.value(units::parse_decimal(value, decimals, Rounding::Exact)?);
+        }
+        Ok((function, tx.into()))
+    }
+
+    async fn read(&self, name: &str, args: Array) -> anyhow::Result<Dynamic> {
+        let (function, tx) = self.transaction(name, args, None)?;
+        let output = self.simulation.call(&tx, None).await.map_err(revert)?;
+        let mut tokens = function.decode_output(&output)?;
+        if tokens.len() == 1 {
+            return Ok(to_dynamic(tokens.remove(0)));
+        }
+        let mut outputs = Map::new();
+        for (index, (param, token)) in function.outputs.iter().zip(tokens).enumerate() {
+            let name = if param.name.is_empty() { format!("out{}", index) } else { param.name.clone() };
+            outputs.insert(name.into(), to_dynamic(token));
+        }
+        Ok(outputs.into())
+    }
+
+    async fn estimate(&self, name: &str, args: Array, value: Option<&str>) -> anyhow::Result<i64> {
+        let (_, tx) = self.transaction(name, args, value)?;
+        let gas = self.simulation.estimate_gas(&tx, None).await.map_err(revert)?;
+        Ok(gas.as_u64() as i64)
+    }
+
+    /// Signs a call with the fee model's fees, recording the signature in
+    /// the audit log.
+    async fn sign(&self, name: &str, args: Array, value: Option<&str>) -> anyhow::Result<Bytes> {
+        let (_, mut tx) = self.transaction(name, args, value)?;
+        let client = self.client()?;
+        let model = FeeModel::from_env(self.config.chain_id)?;
+        model.set_transaction_type(&mut tx);
+        match model.suggest(&*client).await {
+            Ok(fees) => fees.apply(&mut tx),
+            Err(e) => print_warn!("Could not compute fees, leaving them to the node: {:#}", e),
+        }
+        audit::sign(&*client, &mut tx, "script").await
+    }
+
+    /// Broadcasts a signed transaction. A lock or unlock of a job is claimed
+    /// in the ledger first, as the `lock` and `unlock` commands would, so a
+    /// script can't submit a job twice, nor one they already did.
+    async fn broadcast(&self, raw: Bytes) -> anyhow::Result<H256> {
+        let tx_hash = H256(ethers::utils::keccak256(&raw));
+        let (tx, _) = TypedTransaction::decode_signed(&Rlp::new(&raw)).context("not a signed transaction")?;
+        let job = match tx.to_addr() == Some(&self.config.contract_address) {
+            true => tx.data().and_then(|data| calldata::decode_job(data)),
+            false => None,
+        };
+        let entry = match &job {
+            Some((kind, job)) => Some(pipeline::claim(&self.ledger, kind, &self.config, job, tx_hash, false).await?),
+            None => None,
+        };
+        let client = self.client()?;
+        if let Err(e) = audit::broadcast(&*client, raw, "script").await {
+            if let Some(entry) = &entry {
+                self.ledger.release(entry).await?;
+            }
+            return Err(e);
+        }
+        Ok(tx_hash)
+    }
+
+    async fn send(&self, name: &str, args: Array, value: Option<&str>) -> anyhow::Result<H256> {
+        let raw = self.sign(name, args, value).await?;
+        self.broadcast(raw).await
+    }
+
+    async fn wait(&self, tx_hash: H256) -> anyhow::Result<Map> {
+        let receipt = PendingTransaction::new(tx_hash, self.simulation.provider())
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("{:?} was dropped from the mempool", tx_hash))?;
+        audit::record_receipt(self.sender, "script", &receipt)?;
+        let events: Array = receipt
+            .logs
+            .iter()
+            .filter_map(calldata::decode_event)
+            .map(|(name, args)| {
+                let args: Map = args.into_iter().map(|(name, token)| (name.into(), to_dynamic(token))).collect();
+                let mut event = Map::new();
+                event.insert("name".into(), name.into());
+                event.insert("args".into(), args.into());
+                event.into()
+            })
+            .collect();
+        let mut map = Map::new();
+        map.insert("tx_hash".into(), format!("{:?}", tx_hash).into());
+        map.insert("status".into(), (receipt.status == Some(1.into())).into());
+        map.insert("block".into(), (receipt.block_number.unwrap_or_default().as_u64() as i64).into());
+        map.insert("gas_used".into(), receipt.gas_used.unwrap_or_default().to_string().into());
+        map.insert("events".into(), events.into());
+        Ok(map)
+    }
+
+    async fn notify(&self, value: Dynamic) -> anyhow::Result<()> {
+        let payload = to_json(&value);
+        let Some(command) = &self.on_notify else {
+            println!("{}", payload);
+            return Ok(());
+        };
+        let (success, stderr) = hooks::execute(command, &payload).await?;
+        anyhow::ensure!(success, "notify command failed: {}", stderr);
+        Ok(())
+    }
+}
+
+/// The engine with the primitives registered against `host`.
+pub fn engine(host: Arc<Host>) -> Engine {
+    let mut engine = Engine::new();
+    let h = host.clone();
+    engine.register_fn("read", move |name: &str, args: Array| h.block(h.read(name, args)));
+    let h = host.clone();
+    engine.register_fn("estimate", move |name: &str, args: Array| h.block(h.estimate(name, args, None)));
+    let h = host.clone();
+    engine.register_fn("estimate", move |name: &str, args: Array, value: &str| {
+        h.block(h.estimate(name, args, Some(value)))
+    });
+    let h = host.clone();
+    engine.register_fn("encode", move |name: &str, args: Array| -> Outcome<String> {
+        let (_, tx) = h.transaction(name, args, None).map_err(|e| format!("{:#}", e))?;
+        Ok(format!("0x{}", hex::encode(tx.data().cloned().unwrap_or_default())))
+    });
+    let h = host.clone();
+    engine.register_fn("sign", move |name: &str, args: Array| h.block(h.sign(name, args, None)).map(hex_string));
+    let h = host.clone();
+    engine.register_fn("sign", move |name: &str, args: Array, value: &str| {
+        h.block(h.sign(name, args, Some(value))).map(hex_string)
+    });
+    let h = host.clone();
+    engine.register_fn("broadcast", move |raw: &str| {
+        h.block(async { h.broadcast(calldata::parse_hex(raw)?).await }).map(|hash| format!("{:?}", hash))
+    });
+    let h = host.clone();
+    engine.register_fn("send", move |name: &str, args: Array| {
+        h.block(h.send(name, args, None)).map(|hash| format!("{:?}", hash))
+    });
+    let h = host.clone();
+    engine.register_fn("send", move |name: &str, args: Array, value: &str| {
+        h.block(h.send(name, args, Some(value))).map(|hash| format!("{:?}", hash))
+    });
+    let h = host.clone();
+    engine.register_fn("wait", move |tx_hash: &str| h.block(async { h.wait(tx_hash.parse()?).await }));
+    engine.register_fn("notify", move |value: Dynamic| host.block(host.notify(value)));
+    engine
+}
+
+/// Runs the script at `path` with `args` as `ARGS`. Blocks on the node, so
+/// call it from `spawn_blocking`.
+pub fn run(host: Arc<Host>, source: &str, args: Vec<String>) -> anyhow::Result<()> {
+    let mut scope = Scope::new();
+    scope.push_constant("CONTRACT", format!("{:?}", host.config.contract_address));
+    scope.push_constant("SENDER", format!("{:?}", host.sender));
+    scope.push_constant("CHAIN_ID", host.config.chain_id as i64);
+    scope.push_constant("ARGS", args.into_iter().map(Dynamic::from).collect::<Array>());
+    engine(host).run_with_scope(&mut scope, source).map_err(|e| anyhow::anyhow!("script failed: {}", e))
+}
+
+/// Writes a script value the way [`calldata::encode_function`] parses an
+/// argument of type `kind`: arrays as `[a, b]`, tuples as `(a, b)`, anything
+/// else as its text.
+fn argument(value: Dynamic, kind: &ParamType) -> Result<String, String> {
+    if !value.is_array() {
+        return Ok(value.to_string());
+    }
+    let items = value.cast::<Array>();
+    let items = match kind {
+        ParamType::Array(inner) | ParamType::FixedArray(inner, _) => {
+            let items = items.into_iter().map(|item| argument(item, inner)).collect::<Result<Vec<_>, _>>()?;
+            return Ok(format!("[{}]", items.join(", ")));
+        }
+        ParamType::Tuple(kinds) if kinds.len() == items.len() => {
+            kinds.iter().zip(items).map(|(kind, item)| argument(item, kind)).collect::<Result<Vec<_>, _>>()?
+        }
+        ParamType::Tuple(kinds) => return Err(format!("a tuple of {} needs {} items", kind, kinds.len())),
+        _ => return Err(format!("an array can't be a {} argument", kind)),
+    };
+    Ok(format!("({})", items.join(", ")))
+}
+
+/// A decoded value as scripts see it.
+fn to_dynamic(token: Token) -> Dynamic {
+    match token {
+        Token::Address(address) => format!("{:?}", address).into(),
+        Token::Uint(value) => value.to_string().into(),
+        Token::Int(value) => I256::from_raw(value).to_string().into(),
+        Token::Bool(value) => value.into(),
+        Token::String(value) => value.into(),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", hex::encode(bytes)).into(),
+        Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => {
+            Dynamic::from_array(tokens.into_iter().map(to_dynamic).collect())
+        }
+    }
+}
+
+fn to_json(value: &Dynamic) -> Value {
+    if value.is_unit() {
+        Value::Null
+    } else if let Ok(value) = value.as_bool() {
+        json!(value)
+    } else if let Ok(value) = value.as_int() {
+        json!(value)
+    } else if let Ok(value) = value.as_float() {
+        json!(value)
+    } else if let Some(items) = value.read_lock::<Array>() {
+        Value::Array(items.iter().map(to_json).collect())
+    } else if let Some(map) = value.read_lock::<Map>() {
+        Value::Object(map.iter().map(|(key, value)| (key.to_string(), to_json(value))).collect())
+    } else {
+        json!(value.to_string())
+    }
+}
+
+fn hex_string(bytes: Bytes) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// A node error, with its revert data decoded when it carries some.
+fn revert<E: MiddlewareError + 'static>(error: E) -> anyhow::Error {
+    match error.as_error_response().and_then(|response| response.as_revert_data()) {
+        Some(data) => anyhow::anyhow!(calldata::decode_revert(&data)),
+        None => error.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Job, LOCK_KIND};
+    use crate::error::Error;
+    use crate::failover::{Failover, Settings};
+
+    #[test]
+    fn arguments_and_values() {
+        let tuple = ParamType::Tuple(vec![ParamType::Address, ParamType::Array(Box::new(ParamType::Uint(256)))]);
+        let value = Dynamic::from_array(vec!["0x01".into(), Dynamic::from_array(vec![1_i64.into(), "2".into()])]);
+        assert_eq!(argument(value, &tuple).unwrap(), "(0x01, [1, 2])");
+        assert_eq!(argument(7_i64.into(), &ParamType::Uint(256)).unwrap(), "7");
+        assert!(argument(Dynamic::from_array(vec![]), &ParamType::Address).is_err());
+
+        let decoded = to_dynamic(Token::Tuple(vec![Token::Uint(U256::MAX), Token::Bool(true)]));
+        assert_eq!(to_json(&decoded), json!([U256::MAX.to_string(), true]));
+        let mut map = Map::new();
+        map.insert("rows".into(), 3_i64.into());
+        assert_eq!(to_json(&map.into()), json!({ "rows": 3 }));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn running_scripts() {
+        let config = Config {
+            rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 5,
+            contract_address: Address::repeat_byte(0xcc),
+        };
+        let key: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let provider = Provider::new(Failover::new(std::slice::from_ref(&config.rpc_url), Settings::default()).unwrap());
+        let host = Arc::new(Host {
+            sender: key.address(),
+            simulation: Arc::new(SignerMiddleware::new(provider, key.with_chain_id(5_u64))),
+            client: Mutex::new(None),
+            ledger: Ledger::new(std::env::temp_dir().join(format!("script-ledger-test-{}.jsonl", std::process::id()))),
+            on_notify: None,
+            runtime: Handle::current(),
+            config,
+        });
+        let script = r#"
+            let data = encode("setTrustedRemote(uint16,address)", [101, CONTRACT]);
+            if data.len() != 138 || ARGS[0] != "go" { throw "wrong calldata " + data; }
+        "#;
+        let (ok, failed) = tokio::task::spawn_blocking(move || {
+            (run(host.clone(), script, vec!["go".to_string()]), run(host, r#"encode("lock", [])"#, Vec::new()))
+        })
+        .await
+        .unwrap();
+        ok.unwrap();
+        assert!(format!("{:#}", failed.unwrap_err()).contains("lock takes 5 argument(s), got 0"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn broadcasting_a_submitted_job() {
+        let config = Config {
+            rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 5,
+            contract_address: Address::repeat_byte(0xcc),
+        };
+        let key: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let path = std::env::temp_dir().join(format!("script-claim-test-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let job = Job {
+            user: Address::repeat_byte(1),
+            token: Address::repeat_byte(2),
+            amount: 100.into(),
+            nonce: 7.into(),
+            signature: Bytes::from(vec![0xab; 65]),
+        };
+        let ledger = Ledger::new(&path);
+        pipeline::claim(&ledger, LOCK_KIND, &config, &job, H256::repeat_byte(1), false).await.unwrap();
+
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .from(key.address())
+            .to(config.contract_address)
+            .data(calldata::encode_lock(&job))
+            .chain_id(5_u64)
+            .into();
+        let raw = tx.rlp_signed(&key.sign_transaction_sync(&tx).unwrap());
+        let provider = Provider::new(Failover::new(std::slice::from_ref(&config.rpc_url), Settings::default()).unwrap());
+        let host = Host {
+            sender: key.address(),
+            simulation: Arc::new(SignerMiddleware::new(provider, key.with_chain_id(5_u64))),
+            client: Mutex::new(None),
+            ledger,
+            on_notify: None,
+            runtime: Handle::current(),
+            config,
+        };
+        // Refused from the ledger, before any node is asked.
+        let error = host.broadcast(raw).await.unwrap_err();
+        let duplicate = matches!(error.downcast_ref(), Some(Error::DuplicateSubmission { tx_hash }) if *tx_hash == H256::repeat_byte(1));
+        assert!(duplicate, "{:#}", error);
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("jsonl.lock"));
+    }
+}
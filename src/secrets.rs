@@ -0,0 +1,290 @@
+//! Secret references. A secret setting (PRIVATE_KEY and friends) either
+//! holds the secret itself or names the [`SecretProvider`] to fetch it from,
+//! so key material does not have to sit in a plaintext `.env` file.
+//!
+//! Supported references:
+//! - `keyring:<entry>`: an entry in the OS keychain, under the
+//!   [`KEYRING_SERVICE`] service.
+//! - `vault:<field>`: a field of the HashiCorp Vault secret loaded at startup
+//!   by [`init_vault_from_env`].
+//! - `keystore:<path>`: the key in an encrypted JSON keystore, unlocked with
+//!   KEYSTORE_PASSWORD or a password typed on stdin.
+
+use crate::config::env_var;
+use crate::keystore;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Service name our keychain entries are stored under.
+pub const KEYRING_SERVICE: &str = "ethers-rusty";
+
+/// A backend secrets can be looked up in by key.
+pub trait SecretProvider: Send + Sync {
+    /// Backend name, as used in references (`<name>:<key>`).
+    fn name(&self) -> &'static str;
+    fn get(&self, key: &str) -> anyhow::Result<String>;
+}
+
+/// The OS keychain.
+pub struct Keyring;
+
+impl SecretProvider for Keyring {
+    fn name(&self) -> &'static str {
+        "keyring"
+    }
+
+    fn get(&self, key: &str) -> anyhow::Result<String> {
+        keyring_get(key)
+    }
+}
+
+/// Encrypted JSON keystores, by path.
+pub struct Keystore;
+
+impl SecretProvider for Keystore {
+    fn name(&self) -> &'static str {
+        "keystore"
+    }
+
+    fn get(&self, key: &str) -> anyhow::Result<String> {
+        let wallet = keystore::decrypt(Path::new(key), &keystore::password(false)?)?;
+        Ok(format!("0x{}", hex::encode(wallet.signer().to_bytes())))
+    }
+}
+
+/// A HashiCorp Vault secret, fetched once and then served from memory so
+/// lookups stay synchronous.
+pub struct Vault {
+    values: HashMap<String, String>,
+}
+
+impl SecretProvider for Vault {
+    fn name(&self) -> &'static str {
+        "vault"
+    }
+
+    fn get(&self, key: &str) -> anyhow::Result<String> {
+        self.values
+            .get(key)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Vault secret has no field {:?}", key))
+    }
+}
+
+/// How to reach and authenticate to Vault.
+pub struct VaultConfig {
+    /// Server address, e.g. `https://vault.internal:8200`.
+    pub address: String,
+    pub auth: VaultAuth,
+    /// API path of the secret, e.g. `secret/data/ethers-rusty/prod` for KV v2.
+    pub secret_path: String,
+    pub namespace: Option<String>,
+}
+
+pub enum VaultAuth {
+    Token(String),
+    AppRole { role_id: String, secret_id: String },
+}
+
+impl VaultConfig {
+    /// Reads VAULT_ADDR, VAULT_SECRET_PATH, VAULT_NAMESPACE and either
+    /// VAULT_TOKEN or VAULT_ROLE_ID + VAULT_SECRET_ID. Returns `None` when
+    /// VAULT_ADDR is not set.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(address) = env_var("VAULT_ADDR") else {
+            return Ok(None);
+        };
+        let auth = match (env_var("VAULT_TOKEN"), env_var("VAULT_ROLE_ID"), env_var("VAULT_SECRET_ID")) {
+            (Some(token), _, _) => VaultAuth::Token(token),
+            (_, Some(role_id), Some(secret_id)) => VaultAuth::AppRole { role_id, secret_id },
+            _ => anyhow::bail!("VAULT_ADDR is set, but neither VAULT_TOKEN nor VAULT_ROLE_ID and VAULT_SECRET_ID are"),
+        };
+        let secret_path = env_var("VAULT_SECRET_PATH")
+            .ok_or_else(|| anyhow::anyhow!("VAULT_ADDR is set, but VAULT_SECRET_PATH is not"))?;
+        Ok(Some(Self { address, auth, secret_path, namespace: env_var("VAULT_NAMESPACE") }))
+    }
+}
+
+impl Vault {
+    pub async fn fetch(config: &VaultConfig) -> anyhow::Result<Self> {
+        let http = reqwest::Client::new();
+        let base = config.address.trim_end_matches('/');
+        let with_namespace = |request: reqwest::RequestBuilder| match &config.namespace {
+            Some(namespace) => request.header("X-Vault-Namespace", namespace),
+            None => request,
+        };
+
+        let token = match &config.auth {
+            VaultAuth::Token(token) => token.clone(),
+            VaultAuth::AppRole { role_id, secret_id } => {
+                let body = serde_json::json!({ "role_id": role_id, "secret_id": secret_id });
+                let response: Value = with_namespace(http.post(format!("{}/v1/auth/approle/login", base)))
+                    .json(&body)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                response["auth"]["client_token"]
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Vault AppRole login returned no client token"))?
+                    .to_string()
+            }
+        };
+
+        let path = config.secret_path.trim_start_matches('/');
+        let response: Value = with_namespace(http.get(format!("{}/v1/{}", base, path)))
+            .header("X-Vault-Token", token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // KV v2 nests the fields one level deeper than KV v1.
+        let data = match &response["data"]["data"] {
+            Value::Object(fields) => fields,
+            _ => response["data"]
+                .as_object()
+                .ok_or_else(|| anyhow::anyhow!("Vault secret {} has no data", config.secret_path))?,
+        };
+        let values = data
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                (key.clone(), value)
+            })
+            .collect();
+        Ok(Self { values })
+    }
+}
+
+static VAULT: OnceLock<Vault> = OnceLock::new();
+
+/// Fetches the configured Vault secret, if any, making it available to
+/// [`resolve`] and [`lookup`]. Called once at startup.
+pub async fn init_vault_from_env() -> anyhow::Result<()> {
+    if let Some(config) = VaultConfig::from_env()? {
+        let vault = Vault::fetch(&config).await?;
+        let _ = VAULT.set(vault);
+    }
+    Ok(())
+}
+
+/// The startup Vault secret's value for `key`, used as a fallback for
+/// settings missing from the environment.
+pub fn lookup(key: &str) -> Option<String> {
+    VAULT.get().and_then(|vault| vault.values.get(key).cloned())
+}
+
+fn provider(name: &str) -> anyhow::Result<&'static dyn SecretProvider> {
+    match name {
+        "keyring" => Ok(&Keyring),
+        "keystore" => Ok(&Keystore),
+        "vault" => VAULT
+            .get()
+            .map(|vault| vault as &dyn SecretProvider)
+            .ok_or_else(|| anyhow::anyhow!("vault: reference used, but Vault is not configured (VAULT_ADDR)")),
+        _ => anyhow::bail!("unknown secret provider {:?}", name),
+    }
+}
+
+/// Resolves `value` to the secret it refers to; values without a recognised
+/// scheme are returned as they are.
+pub fn resolve(value: &str) -> anyhow::Result<String> {
+    match value.split_once(':') {
+        Some((scheme, key)) if ["keyring", "vault", "keystore"].contains(&scheme) => provider(scheme)?.get(key),
+        _ => Ok(value.to_string()),
+    }
+}
+
+pub fn keyring_get(entry: &str) -> anyhow::Result<String> {
+    keyring::Entry::new(KEYRING_SERVICE, entry)?
+        .get_password()
+        .map_err(|e| anyhow::anyhow!("failed to read keychain entry {:?}: {}", entry, e))
+}
+
+pub fn keyring_set(entry: &str, secret: &str) -> anyhow::Result<()> {
+    keyring::Entry::new(KEYRING_SERVICE, entry)?
+        .set_password(secret)
+        .map_err(|e| anyhow::anyhow!("failed to write keychain entry {:?}: {}", entry, e))
+}
+
+pub fn keyring_delete(entry: &str) -> anyhow::Result<()> {
+    keyring::Entry::new(KEYRING_SERVICE, entry)?
+        .delete_password()
+        .map_err(|e| anyhow::anyhow!("failed to delete keychain entry {:?}: {}", entry, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node;
+    use ethers::signers::{LocalWallet, Signer};
+    use serde_json::json;
+
+    #[test]
+    fn references() {
+        assert_eq!(resolve("0xabc").unwrap(), "0xabc");
+        // Only the known schemes are references.
+        assert_eq!(resolve("https://rpc.example").unwrap(), "https://rpc.example");
+        assert!(resolve("vault:PRIVATE_KEY").unwrap_err().to_string().contains("Vault is not configured"));
+
+        let dir = std::env::temp_dir().join(format!("secrets-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let wallet = LocalWallet::from_bytes(&[0x42; 32]).unwrap();
+        let params = keystore::ScryptParams { log_n: 4, r: 8, p: 1 };
+        let path = keystore::write(&dir, &wallet, "hunter2", params).unwrap();
+        std::env::set_var("KEYSTORE_PASSWORD", "hunter2");
+        let key = resolve(&format!("keystore:{}", path.display())).unwrap();
+        std::env::remove_var("KEYSTORE_PASSWORD");
+        assert_eq!(key.parse::<LocalWallet>().unwrap().address(), wallet.address());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetching_from_vault() {
+        let address = mock_node::http_server(|head, body| match head.lines().next().unwrap_or_default() {
+            "POST /v1/auth/approle/login HTTP/1.1" if head.contains("x-vault-namespace: team") => {
+                assert_eq!(serde_json::from_str::<Value>(body).unwrap()["role_id"], "relayer");
+                json!({ "auth": { "client_token": "issued" } }).to_string()
+            }
+            "GET /v1/secret/data/relayer HTTP/1.1" if head.contains("x-vault-token: issued") => {
+                json!({ "data": { "data": { "PRIVATE_KEY": "0xabc", "RETRIES": 3 } } }).to_string()
+            }
+            "GET /v1/kv/relayer HTTP/1.1" if head.contains("x-vault-token: root") => {
+                json!({ "data": { "PRIVATE_KEY": "0xdef" } }).to_string()
+            }
+            _ => json!({ "errors": ["permission denied"] }).to_string(),
+        })
+        .await;
+
+        // KV v2 behind AppRole login, in a namespace.
+        let config = VaultConfig {
+            address: format!("{}/", address),
+            auth: VaultAuth::AppRole { role_id: "relayer".to_string(), secret_id: "s3cret".to_string() },
+            secret_path: "/secret/data/relayer".to_string(),
+            namespace: Some("team".to_string()),
+        };
+        let vault = Vault::fetch(&config).await.unwrap();
+        assert_eq!(vault.get("PRIVATE_KEY").unwrap(), "0xabc");
+        assert_eq!(vault.get("RETRIES").unwrap(), "3");
+        assert!(vault.get("MISSING").is_err());
+
+        // KV v1 with a token.
+        let config = VaultConfig {
+            address: address.clone(),
+            auth: VaultAuth::Token("root".to_string()),
+            secret_path: "kv/relayer".to_string(),
+            namespace: None,
+        };
+        assert_eq!(Vault::fetch(&config).await.unwrap().get("PRIVATE_KEY").unwrap(), "0xdef");
+        let config = VaultConfig { auth: VaultAuth::Token("expired".to_string()), ..config };
+        assert!(Vault::fetch(&config).await.is_err());
+    }
+}
@@ -0,0 +1,62 @@
+//! Cooperative shutdown for the long-running operations: waiting on a
+//! receipt ([`crate::pipeline::send_and_wait`]), following events
+//! ([`crate::events::follow`]), watching the mempool
+//! ([`crate::mempool::watch_contract`]) and the `stream` daemon. Each takes a
+//! [`CancellationToken`] and, once it is cancelled, stops at the next await
+//! point with its state left consistent: subscriptions are closed, and a
+//! transaction still pending is in the ledger and reported as
+//! [`crate::error::Error::Detached`]. Programs embedding the library pass a
+//! child of their own shutdown token; the commands use [`on_signal`].
+
+use std::future::Future;
+pub use tokio_util::sync::CancellationToken;
+
+/// A token cancelled on Ctrl-C, or SIGTERM on Unix.
+pub fn on_signal() -> anyhow::Result<CancellationToken> {
+    let token = CancellationToken::new();
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+        cancel.cancel();
+    });
+    Ok(token)
+}
+
+/// Runs `future` to completion, or until `cancel` is cancelled, whichever
+/// comes first; `None` if it was cancelled. The future is dropped then.
+pub async fn unless_cancelled<T>(cancel: &CancellationToken, future: impl Future<Output = T>) -> Option<T> {
+    tokio::select! {
+        output = future => Some(output),
+        _ = cancel.cancelled() => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn cancelling() {
+        let token = CancellationToken::new();
+        assert_eq!(unless_cancelled(&token, async { 7 }).await, Some(7));
+
+        let child = token.child_token();
+        let cancel = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            cancel.cancel();
+        });
+        let forever = std::future::pending::<()>();
+        assert_eq!(unless_cancelled(&child, forever).await, None);
+        assert!(child.is_cancelled());
+    }
+}
@@ -0,0 +1,133 @@
+//! Job signatures in the one shape the contract accepts. Backend signers
+//! hand the same ECDSA signature over in several: with `v` as 0/1 or 27/28,
+//! as a 64-byte EIP-2098 compact signature, or with the high `s` that
+//! OpenZeppelin's ECDSA rejects as malleable. Each is rewritten to the
+//! 65-byte `r . s . v` form with `v` 27 or 28 and a low `s`, which recovers
+//! the same signer; anything else is refused here, saying what is wrong,
+//! rather than surfacing later as an opaque revert.
+
+use anyhow::Context;
+use ethers::prelude::*;
+
+/// The order of the secp256k1 group.
+const N: U256 = U256([0xbfd25e8cd0364141, 0xbaaedce6af48a03b, 0xfffffffffffffffe, 0xffffffffffffffff]);
+
+/// `signature` in the 65-byte form with `v` 27 or 28 and `s` at most N / 2.
+pub fn normalize(signature: &[u8]) -> anyhow::Result<Bytes> {
+    let (r, mut s, mut parity) = match signature.len() {
+        65 => {
+            let parity = match signature[64] {
+                v @ (0 | 1) => v,
+                v @ (27 | 28) => v - 27,
+                v => anyhow::bail!("v is {}, expected 0, 1, 27 or 28", v),
+            };
+            (U256::from_big_endian(&signature[..32]), U256::from_big_endian(&signature[32..64]), parity)
+        }
+        // EIP-2098 keeps the parity in the top bit of `s`.
+        64 => {
+            let mut s = [0; 32];
+            s.copy_from_slice(&signature[32..]);
+            let parity = s[0] >> 7;
+            s[0] &= 0x7f;
+            (U256::from_big_endian(&signature[..32]), U256::from_big_endian(&s), parity)
+        }
+        length => anyhow::bail!("{} bytes, expected 65 (r, s, v) or 64 (EIP-2098 compact)", length),
+    };
+    anyhow::ensure!(!r.is_zero() && r < N, "r is outside the curve order");
+    anyhow::ensure!(!s.is_zero() && s < N, "s is outside the curve order");
+    // (r, N - s) with the other parity is the same signature.
+    if s > N / 2 {
+        s = N - s;
+        parity ^= 1;
+    }
+    let mut normalized = [0; 65];
+    r.to_big_endian(&mut normalized[..32]);
+    s.to_big_endian(&mut normalized[32..64]);
+    normalized[64] = 27 + parity;
+    Ok(normalized.to_vec().into())
+}
+
+/// Parses a hex signature (`0x` optional) and [`normalize`]s it.
+pub fn parse(text: &str) -> anyhow::Result<Bytes> {
+    let bytes: Bytes = text.parse().with_context(|| format!("invalid signature {:?}: not hex", text))?;
+    normalize(&bytes).with_context(|| format!("invalid signature {:?}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn sign(key: u64, digest: [u8; 32]) -> (Address, Signature) {
+        let wallet = LocalWallet::from_bytes(H256::from_low_u64_be(key).as_bytes()).unwrap();
+        (wallet.address(), wallet.sign_hash(H256(digest)).unwrap())
+    }
+
+    fn recover(signature: &[u8], digest: [u8; 32]) -> Address {
+        Signature::try_from(signature).unwrap().recover(H256(digest)).unwrap()
+    }
+
+    #[test]
+    fn forms() {
+        let (signer, signature) = sign(7, [1; 32]);
+        let canonical = signature.to_vec();
+        assert_eq!(normalize(&canonical).unwrap().to_vec(), canonical);
+
+        let mut zero_based = canonical.clone();
+        zero_based[64] -= 27;
+        assert_eq!(normalize(&zero_based).unwrap().to_vec(), canonical);
+
+        let mut compact = canonical[..64].to_vec();
+        compact[32] |= (canonical[64] - 27) << 7;
+        assert_eq!(normalize(&compact).unwrap().to_vec(), canonical);
+
+        let mut high_s = canonical.clone();
+        (N - signature.s).to_big_endian(&mut high_s[32..64]);
+        high_s[64] = 55 - high_s[64];
+        assert_eq!(normalize(&high_s).unwrap().to_vec(), canonical);
+        assert_eq!(recover(&normalize(&high_s).unwrap(), [1; 32]), signer);
+
+        let mut bad_v = canonical.clone();
+        bad_v[64] = 29;
+        assert!(normalize(&bad_v).unwrap_err().to_string().contains("v is 29"));
+        assert!(normalize(&canonical[..63]).unwrap_err().to_string().contains("63 bytes"));
+        assert!(normalize(&[0; 65]).is_err());
+        assert!(parse("0xabcd").is_err());
+        assert!(parse("not hex").unwrap_err().to_string().contains("not hex"));
+    }
+
+    proptest! {
+        #[test]
+        fn normalizing_never_panics_and_is_idempotent(bytes in prop::collection::vec(any::<u8>(), 0..80)) {
+            if let Ok(normalized) = normalize(&bytes) {
+                prop_assert_eq!(normalized.len(), 65);
+                prop_assert!(normalized[64] == 27 || normalized[64] == 28);
+                prop_assert!(U256::from_big_endian(&normalized[32..64]) <= N / 2);
+                prop_assert_eq!(normalize(&normalized).unwrap(), normalized);
+            }
+        }
+
+        #[test]
+        fn every_form_recovers_the_signer(
+            key in 1u64..,
+            digest in any::<[u8; 32]>(),
+            zero_based in any::<bool>(),
+            high_s in any::<bool>(),
+            compact in any::<bool>(),
+        ) {
+            let (signer, signature) = sign(key, digest);
+            let mut bytes = signature.to_vec();
+            if high_s {
+                (N - signature.s).to_big_endian(&mut bytes[32..64]);
+                bytes[64] = 55 - bytes[64];
+            }
+            if compact && !high_s {
+                bytes[32] |= (bytes[64] - 27) << 7;
+                bytes.truncate(64);
+            } else if zero_based {
+                bytes[64] -= 27;
+            }
+            prop_assert_eq!(recover(&normalize(&bytes).unwrap(), digest), signer);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Just-in-time signatures from the backend signing service, so a job's
+//! signature is requested when it is sent rather than pasted into SIGNATURE
+//! ahead of time.
+//!
+//! The service is enabled by SIGNER_SERVICE_URL. Each job is POSTed as the
+//! JSON of SIGNER_SERVICE_TEMPLATE (inline, or `@path` to read a file), with
+//! `{{kind}}`, `{{chain_id}}`, `{{contract}}`, `{{user}}`, `{{token}}`,
+//! `{{amount}}` and `{{nonce}}` filled in; without a template, those fields
+//! are sent as a flat object. The signature is read from the response at the
+//! JSON pointer SIGNER_SERVICE_SIGNATURE_POINTER (default `/signature`).
+//! SIGNER_SERVICE_TOKEN, a secret setting, is sent as a bearer token, or as
+//! the raw value of SIGNER_SERVICE_AUTH_HEADER when that names another header.
+
+use crate::config::{self, env_var, Config, Job};
+use crate::signature;
+use anyhow::Context;
+use serde_json::Value;
+use std::fs;
+
+const DEFAULT_TEMPLATE: &str = r#"{"kind":"{{kind}}","chainId":{{chain_id}},"contract":"{{contract}}","user":"{{user}}","token":"{{token}}","amount":"{{amount}}","nonce":"{{nonce}}"}"#;
+
+pub struct SigningService {
+    url: String,
+    auth: Option<(String, String)>,
+    template: String,
+    pointer: String,
+}
+
+impl SigningService {
+    /// Reads the service settings. Returns `None` when SIGNER_SERVICE_URL is
+    /// not set.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(url) = env_var("SIGNER_SERVICE_URL") else {
+            return Ok(None);
+        };
+        let auth = match env_var("SIGNER_SERVICE_TOKEN") {
+            Some(_) => {
+                let token = config::secret("SIGNER_SERVICE_TOKEN")?;
+                Some(match env_var("SIGNER_SERVICE_AUTH_HEADER") {
+                    Some(header) if !header.eq_ignore_ascii_case("authorization") => (header, token),
+                    _ => ("Authorization".to_string(), format!("Bearer {}", token)),
+                })
+            }
+            None => None,
+        };
+        let template = match env_var("SIGNER_SERVICE_TEMPLATE") {
+            Some(template) => match template.strip_prefix('@') {
+                Some(path) => fs::read_to_string(path).with_context(|| format!("failed to read signing request template {}", path))?,
+                None => template,
+            },
+            None => DEFAULT_TEMPLATE.to_string(),
+        };
+        let pointer = env_var("SIGNER_SERVICE_SIGNATURE_POINTER").unwrap_or_else(|| "/signature".to_string());
+        Ok(Some(Self { url, auth, template, pointer }))
+    }
+
+    /// Requests the signature for a `kind` (`lock` or `unlock`) job and sets
+    /// it on `job`.
+    pub async fn sign(&self, kind: &str, config: &Config, job: &mut Job) -> anyhow::Result<()> {
+        let body: Value = serde_json::from_str(&render(&self.template, kind, config, job))
+            .context("signing request template does not render to valid JSON")?;
+        let mut request = reqwest::Client::new().post(&self.url).json(&body);
+        if let Some((header, value)) = &self.auth {
+            request = request.header(header, value);
+        }
+        let response: Value = request
+            .send()
+            .await
+            .with_context(|| format!("signing service {} unreachable", self.url))?
+            .error_for_status()?
+            .json()
+            .await?;
+        let signature = response
+            .pointer(&self.pointer)
+            .and_then(Value::as_str)
+            .with_context(|| format!("signing service response has no string at {}", self.pointer))?;
+        job.signature = signature::parse(signature).context("signing service returned an invalid signature")?;
+        Ok(())
+    }
+}
+
+/// Sets `job`'s signature for a `kind` command: from the signing service
+/// when one is configured, and from SIGNATURE otherwise.
+pub async fn sign_from_env(kind: &str, config: &Config, job: &mut Job) -> anyhow::Result<()> {
+    match SigningService::from_env()? {
+        Some(service) => {
+            println!("Requesting {} signature from {}", kind, service.url);
+            service.sign(kind, config, job).await
+        }
+        None => {
+            job.signature = signature::parse(&config::var("SIGNATURE")?)?;
+            Ok(())
+        }
+    }
+}
+
+/// Fills the `{{...}}` placeholders of `template` from the job.
+fn render(template: &str, kind: &str, config: &Config, job: &Job) -> String {
+    [
+        ("kind", kind.to_string()),
+        ("chain_id", config.chain_id.to_string()),
+        ("contract", format!("{:?}", config.contract_address)),
+        ("user", format!("{:?}", job.user)),
+        ("token", format!("{:?}", job.token)),
+        ("amount", job.amount.to_string()),
+        ("nonce", job.nonce.to_string()),
+    ]
+    .iter()
+    .fold(template.to_string(), |rendered, (name, value)| rendered.replace(&format!("{{{{{}}}}}", name), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Address, Bytes};
+
+    #[test]
+    fn rendering() {
+        let config = Config { rpc_url: String::new(), chain_id: 8453, contract_address: Address::repeat_byte(0x33) };
+        let job = Job {
+            user: Address::repeat_byte(0x11),
+            token: Address::zero(),
+            amount: 1500.into(),
+            nonce: 7.into(),
+            signature: Bytes::new(),
+        };
+        let body: Value = serde_json::from_str(&render(DEFAULT_TEMPLATE, "lock", &config, &job)).unwrap();
+        assert_eq!(body["kind"], "lock");
+        assert_eq!(body["chainId"], 8453);
+        assert_eq!(body["contract"], format!("0x{}", "33".repeat(20)));
+        assert_eq!(body["user"], format!("0x{}", "11".repeat(20)));
+        assert_eq!(body["amount"], "1500");
+        assert_eq!(body["nonce"], "7");
+
+        let custom = r#"{"payload":{"to":"{{user}}","n":{{nonce}}},"op":"{{kind}}-{{kind}}"}"#;
+        let body: Value = serde_json::from_str(&render(custom, "unlock", &config, &job)).unwrap();
+        assert_eq!(body["payload"]["n"], 7);
+        assert_eq!(body["op"], "unlock-unlock");
+    }
+}
@@ -0,0 +1,691 @@
+//! ERC-4337 smart accounts, for sends the user's key signs without paying
+//! gas. The call is wrapped in a UserOperation from SMART_ACCOUNT, a
+//! SimpleAccount-compatible account owned by USER_PRIVATE_KEY, sponsored by
+//! the paymaster at PAYMASTER_URL and submitted to the bundler at
+//! BUNDLER_URL. Both are spoken to over the usual JSON-RPC methods
+//! (`eth_estimateUserOperationGas`, `pm_sponsorUserOperation`,
+//! `eth_sendUserOperation`, `eth_getUserOperationReceipt`).
+//!
+//! UserOperations are in the v0.6 format, for ENTRY_POINT (by default the
+//! canonical v0.6 EntryPoint).
+//!
+//! So that a long-running relayer doesn't need the owner's key, an account
+//! with session key support can register a scoped key: one that may only
+//! make calls to given (target, selector) pairs, attach a limited value, and
+//! sign until it expires. With SESSION_KEY set, operations are signed with
+//! it instead of USER_PRIVATE_KEY.
+
+use crate::audit;
+use crate::config::{self, env_var, Config};
+use crate::contract::{
+    EntryPoint, ModularAccount, ModularAccountEvents, ModuleInstalledFilter, ModuleUninstalledFilter, SessionKeys,
+    SimpleAccount, SimpleAccountFactory,
+};
+use crate::events;
+use crate::fees::FeeModel;
+use crate::tls;
+use anyhow::Context;
+use ethers::abi::{encode, Token};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use ethers::contract::{EthEvent, EthLogDecode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The v0.6 EntryPoint, deployed at the same address on most chains.
+pub const ENTRY_POINT: &str = "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789";
+
+/// The v0.6 SimpleAccountFactory.
+pub const ACCOUNT_FACTORY: &str = "0x9406Cc6185a346906296840746125a0E44976454";
+
+/// A well-formed signature that recovers to no owner, so gas can be
+/// estimated before the operation is signed.
+const DUMMY_SIGNATURE: &str = "0xfffffffffffffffffffffffffffffff0000000000000000000000000000000007aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa1c";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserOperation {
+    pub sender: Address,
+    pub nonce: U256,
+    pub init_code: Bytes,
+    pub call_data: Bytes,
+    pub call_gas_limit: U256,
+    pub verification_gas_limit: U256,
+    pub pre_verification_gas: U256,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+    pub paymaster_and_data: Bytes,
+    pub signature: Bytes,
+}
+
+impl UserOperation {
+    /// The hash the account's owner signs, binding the operation to
+    /// `entry_point` and `chain_id`.
+    pub fn hash(&self, entry_point: Address, chain_id: u64) -> H256 {
+        let packed = keccak256(encode(&[
+            Token::Address(self.sender),
+            Token::Uint(self.nonce),
+            Token::FixedBytes(keccak256(&self.init_code).to_vec()),
+            Token::FixedBytes(keccak256(&self.call_data).to_vec()),
+            Token::Uint(self.call_gas_limit),
+            Token::Uint(self.verification_gas_limit),
+            Token::Uint(self.pre_verification_gas),
+            Token::Uint(self.max_fee_per_gas),
+            Token::Uint(self.max_priority_fee_per_gas),
+            Token::FixedBytes(keccak256(&self.paymaster_and_data).to_vec()),
+        ]));
+        H256(keccak256(encode(&[
+            Token::FixedBytes(packed.to_vec()),
+            Token::Address(entry_point),
+            Token::Uint(chain_id.into()),
+        ])))
+    }
+}
+
+/// Gas limits and paymaster data, as the bundler and paymaster return them.
+/// Fields a service leaves out keep their previous values.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Quote {
+    call_gas_limit: Option<U256>,
+    verification_gas_limit: Option<U256>,
+    pre_verification_gas: Option<U256>,
+    paymaster_and_data: Option<Bytes>,
+}
+
+impl Quote {
+    fn apply(self, op: &mut UserOperation) {
+        op.call_gas_limit = self.call_gas_limit.unwrap_or(op.call_gas_limit);
+        op.verification_gas_limit = self.verification_gas_limit.unwrap_or(op.verification_gas_limit);
+        op.pre_verification_gas = self.pre_verification_gas.unwrap_or(op.pre_verification_gas);
+        op.paymaster_and_data = self.paymaster_and_data.unwrap_or_else(|| op.paymaster_and_data.clone());
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserOperationReceipt {
+    pub user_op_hash: H256,
+    pub success: bool,
+    #[serde(default)]
+    pub reason: Option<String>,
+    pub actual_gas_cost: U256,
+    pub receipt: TransactionReceipt,
+}
+
+pub struct SmartAccount {
+    pub address: Address,
+    pub entry_point: Address,
+    bundler: Provider<Http>,
+    paymaster: Option<Provider<Http>>,
+}
+
+impl SmartAccount {
+    /// Reads SMART_ACCOUNT, BUNDLER_URL and the optional PAYMASTER_URL and
+    /// ENTRY_POINT.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let address = config::var("SMART_ACCOUNT")?;
+        Self::at(address.parse().with_context(|| format!("invalid SMART_ACCOUNT {:?}", address))?)
+    }
+
+    /// The account at `address`, with the bundler and paymaster settings.
+    pub fn at(address: Address) -> anyhow::Result<Self> {
+        let entry_point = Self::entry_point_from_env()?;
+        let bundler = tls::provider(&config::var("BUNDLER_URL")?).context("invalid BUNDLER_URL")?;
+        let paymaster = match env_var("PAYMASTER_URL") {
+            Some(url) => Some(tls::provider(&url).context("invalid PAYMASTER_URL")?),
+            None => None,
+        };
+        Ok(Self { address, entry_point, bundler, paymaster })
+    }
+
+    /// ENTRY_POINT, or the v0.6 EntryPoint.
+    pub fn entry_point_from_env() -> anyhow::Result<Address> {
+        let entry_point = env_var("ENTRY_POINT").unwrap_or_else(|| ENTRY_POINT.to_string());
+        entry_point.parse().with_context(|| format!("invalid ENTRY_POINT {:?}", entry_point))
+    }
+
+    pub fn is_sponsored(&self) -> bool {
+        self.paymaster.is_some()
+    }
+
+    /// An unsigned operation making `call_data` from the account, at its next
+    /// EntryPoint nonce and the chain's current fees, with gas estimated by
+    /// the bundler. If the account isn't deployed, the operation deploys it.
+    pub async fn user_operation<M: Middleware + 'static>(
+        &self,
+        client: Arc<M>,
+        config: &Config,
+        call_data: Bytes,
+    ) -> anyhow::Result<UserOperation> {
+        let entry_point = EntryPoint::new(self.entry_point, client.clone());
+        let nonce = entry_point.get_nonce(self.address, U256::zero()).call().await?;
+        let fees = FeeModel::from_env(config.chain_id)?.suggest(&*client).await?;
+        let mut op = UserOperation {
+            sender: self.address,
+            nonce,
+            call_data,
+            max_fee_per_gas: fees.max_fee_per_gas,
+            max_priority_fee_per_gas: fees.max_priority_fee_per_gas,
+            signature: DUMMY_SIGNATURE.parse()?,
+            ..Default::default()
+        };
+        if client.get_code(self.address, None).await?.is_empty() {
+            let factory = Factory::from_env()?;
+            let owner = owner_address(config)?;
+            let expected = factory.account_address(client.clone(), owner).await?;
+            anyhow::ensure!(
+                expected == self.address,
+                "account {:?} isn't deployed, and isn't the factory's account {:?} for owner {:?} and ACCOUNT_SALT",
+                self.address,
+                expected,
+                owner
+            );
+            op.init_code = factory.init_code(client.clone(), owner)?;
+        }
+        let quote: Quote = self
+            .bundler
+            .request("eth_estimateUserOperationGas", (&op, self.entry_point))
+            .await
+            .context("the bundler could not estimate the operation's gas")?;
+        quote.apply(&mut op);
+        Ok(op)
+    }
+
+    /// Has the paymaster sponsor `op`, filling in its paymaster data and any
+    /// gas limits the paymaster sets.
+    pub async fn sponsor(&self, op: &mut UserOperation) -> anyhow::Result<()> {
+        let paymaster = self.paymaster.as_ref().context("sponsoring an operation needs PAYMASTER_URL")?;
+        let quote: Quote = paymaster
+            .request("pm_sponsorUserOperation", (&*op, self.entry_point))
+            .await
+            .context("the paymaster declined to sponsor the operation")?;
+        quote.apply(op);
+        Ok(())
+    }
+
+    /// Signs `op` with the account's owner or a session key, as
+    /// SimpleAccount checks it: an EIP-191 signature over the operation hash.
+    pub async fn sign(&self, signer: &LocalWallet, op: &mut UserOperation, chain_id: u64) -> anyhow::Result<()> {
+        let hash = op.hash(self.entry_point, chain_id);
+        op.signature = signer.sign_message(hash.as_bytes()).await?.to_vec().into();
+        let params = json!({ "sender": op.sender, "nonce": op.nonce, "op_hash": hash });
+        audit::record_signature(signer.address(), "user-operation", params)
+    }
+
+    /// Submits `op`, returning its operation hash. See [`is_rejection`] for
+    /// whether a failure means the bundler didn't take it.
+    pub async fn send(&self, op: &UserOperation) -> anyhow::Result<H256> {
+        Ok(self.bundler.request("eth_sendUserOperation", (op, self.entry_point)).await?)
+    }
+
+    /// Polls for `op_hash`'s receipt until it is included or `timeout` passes.
+    pub async fn wait(&self, op_hash: H256, timeout: Duration) -> anyhow::Result<Option<UserOperationReceipt>> {
+        let started = Instant::now();
+        loop {
+            let receipt: Option<UserOperationReceipt> =
+                self.bundler.request("eth_getUserOperationReceipt", [op_hash]).await?;
+            if receipt.is_some() || started.elapsed() >= timeout {
+                return Ok(receipt);
+            }
+            tokio::time::sleep(self.bundler.get_interval()).await;
+        }
+    }
+}
+
+/// Whether `error`, from [`SmartAccount::send`], is the bundler refusing the
+/// operation. Any other failure, such as a timeout, leaves it unknown
+/// whether the bundler took it.
+pub fn is_rejection(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ProviderError>().is_some_and(|e| RpcError::as_error_response(e).is_some())
+}
+
+/// A session key's registration with the account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Session {
+    /// Unix time the key stops being valid.
+    pub valid_until: u64,
+    /// The most native value one operation may attach.
+    pub value_limit: U256,
+}
+
+impl Session {
+    /// Fails unless an operation attaching `value` at Unix time `now` is
+    /// within the session's limits.
+    pub fn check(&self, value: U256, now: u64) -> anyhow::Result<()> {
+        anyhow::ensure!(now < self.valid_until, "the session key expired at {}", self.valid_until);
+        anyhow::ensure!(
+            value <= self.value_limit,
+            "the operation attaches {} wei, over the session key's limit of {} wei",
+            value,
+            self.value_limit
+        );
+        Ok(())
+    }
+}
+
+/// A call a session key is allowed to make.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scope {
+    pub target: Address,
+    pub selector: [u8; 4],
+}
+
+/// Parses a scope as `ADDRESS:FUNCTION`, where the function is a 4-byte
+/// selector (`0x095ea7b3`) or a signature (`approve(address,uint256)`).
+pub fn parse_scope(input: &str) -> anyhow::Result<Scope> {
+    let (target, function) = input.split_once(':').context("expected ADDRESS:FUNCTION")?;
+    let target = target.trim().parse().with_context(|| format!("invalid address {:?}", target))?;
+    let function = function.trim();
+    let selector = match function.strip_prefix("0x") {
+        Some(hex) => <[u8; 4]>::try_from(hex::decode(hex)?.as_slice())
+            .map_err(|_| anyhow::anyhow!("a selector is 4 bytes, got {:?}", function))?,
+        None => {
+            anyhow::ensure!(function.contains('('), "expected a selector or a signature, got {:?}", function);
+            ethers::utils::id(function)
+        }
+    };
+    Ok(Scope { target, selector })
+}
+
+impl SmartAccount {
+    /// The account's registration of session `key`, if it has one.
+    pub async fn session<M: Middleware + 'static>(
+        &self,
+        client: Arc<M>,
+        key: Address,
+    ) -> anyhow::Result<Option<Session>> {
+        let (valid_until, value_limit) = SessionKeys::new(self.address, client).session_key(key).call().await?;
+        Ok((valid_until != 0).then_some(Session { valid_until, value_limit }))
+    }
+
+    /// The account calldata registering session `key` for `scopes` until
+    /// `valid_until`.
+    pub fn register_session<M: Middleware + 'static>(
+        &self,
+        client: Arc<M>,
+        key: Address,
+        valid_until: u64,
+        value_limit: U256,
+        scopes: &[Scope],
+    ) -> anyhow::Result<Bytes> {
+        let targets = scopes.iter().map(|scope| scope.target).collect();
+        let selectors = scopes.iter().map(|scope| scope.selector).collect();
+        SessionKeys::new(self.address, client)
+            .register_session_key(key, valid_until, value_limit, targets, selectors)
+            .calldata()
+            .context("registration has no calldata")
+    }
+
+    /// The account calldata revoking session `key`.
+    pub fn revoke_session<M: Middleware + 'static>(&self, client: Arc<M>, key: Address) -> anyhow::Result<Bytes> {
+        SessionKeys::new(self.address, client).revoke_session_key(key).calldata().context("revocation has no calldata")
+    }
+}
+
+/// The SESSION_KEY wallet, if one is set.
+pub fn session_key(config: &Config) -> anyhow::Result<Option<LocalWallet>> {
+    if env_var("SESSION_KEY").is_none() {
+        return Ok(None);
+    }
+    Ok(Some(config::secret("SESSION_KEY")?.parse::<LocalWallet>()?.with_chain_id(config.chain_id)))
+}
+
+/// An ERC-7579 module type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModuleType {
+    Validator,
+    Executor,
+    Fallback,
+    Hook,
+}
+
+impl ModuleType {
+    const ALL: [Self; 4] = [Self::Validator, Self::Executor, Self::Fallback, Self::Hook];
+
+    pub fn id(self) -> U256 {
+        U256::from(self as u64 + 1)
+    }
+
+    pub fn from_id(id: U256) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    /// Parses a type by name (`validator`, `executor`, `fallback`, `hook`) or id.
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let input = input.trim().to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == input || kind.id().to_string() == input)
+            .with_context(|| format!("unknown module type {:?}, expected validator, executor, fallback or hook", input))
+    }
+}
+
+impl fmt::Display for ModuleType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Validator => "validator",
+            Self::Executor => "executor",
+            Self::Fallback => "fallback",
+            Self::Hook => "hook",
+        })
+    }
+}
+
+impl SmartAccount {
+    /// The account calldata installing `module` as a `kind` module.
+    pub fn install_module<M: Middleware + 'static>(
+        &self,
+        client: Arc<M>,
+        kind: ModuleType,
+        module: Address,
+        init_data: Bytes,
+    ) -> anyhow::Result<Bytes> {
+        let call = ModularAccount::new(self.address, client).install_module(kind.id(), module, init_data);
+        call.calldata().context("installModule has no calldata")
+    }
+
+    /// The account calldata uninstalling the `kind` module `module`.
+    pub fn uninstall_module<M: Middleware + 'static>(
+        &self,
+        client: Arc<M>,
+        kind: ModuleType,
+        module: Address,
+        deinit_data: Bytes,
+    ) -> anyhow::Result<Bytes> {
+        let call = ModularAccount::new(self.address, client).uninstall_module(kind.id(), module, deinit_data);
+        call.calldata().context("uninstallModule has no calldata")
+    }
+}
+
+/// The modules `account` has installed since `from_block`, oldest first:
+/// those its ModuleInstalled events name and no later ModuleUninstalled
+/// removes, each confirmed with `isModuleInstalled`.
+pub async fn installed_modules<M: Middleware + 'static>(
+    client: Arc<M>,
+    account: Address,
+    from_block: u64,
+) -> anyhow::Result<Vec<(ModuleType, Address)>>
+where
+    M::Error: 'static,
+{
+    let filter = Filter::new()
+        .address(account)
+        .topic0(vec![ModuleInstalledFilter::signature(), ModuleUninstalledFilter::signature()]);
+    let latest = client.get_block_number().await?.as_u64();
+    let mut changes = Vec::new();
+    events::backfill(&*client, &filter, from_block, latest, events::DEFAULT_CHUNK, |log| {
+        match ModularAccountEvents::decode_log(&log.clone().into())? {
+            ModularAccountEvents::ModuleInstalledFilter(event) => {
+                changes.push((true, event.module_type_id, event.module))
+            }
+            ModularAccountEvents::ModuleUninstalledFilter(event) => {
+                changes.push((false, event.module_type_id, event.module))
+            }
+        }
+        Ok(())
+    })
+    .await?;
+
+    let modular = ModularAccount::new(account, client);
+    let mut installed = Vec::new();
+    for (id, module) in replay(&changes) {
+        let Some(kind) = ModuleType::from_id(id) else {
+            continue;
+        };
+        if modular.is_module_installed(id, module, Bytes::new()).call().await? {
+            installed.push((kind, module));
+        }
+    }
+    Ok(installed)
+}
+
+/// Applies install (`true`) and uninstall events in order, returning what is
+/// left installed in the order it was installed.
+fn replay(changes: &[(bool, U256, Address)]) -> Vec<(U256, Address)> {
+    let mut installed: Vec<(U256, Address)> = Vec::new();
+    for &(install, id, module) in changes {
+        installed.retain(|entry| *entry != (id, module));
+        if install {
+            installed.push((id, module));
+        }
+    }
+    installed
+}
+
+/// The factory accounts are created by, and the salt telling one owner's
+/// accounts apart.
+pub struct Factory {
+    pub address: Address,
+    pub salt: U256,
+}
+
+impl Factory {
+    /// Reads ACCOUNT_FACTORY and ACCOUNT_SALT (default 0).
+    pub fn from_env() -> anyhow::Result<Self> {
+        let address = env_var("ACCOUNT_FACTORY").unwrap_or_else(|| ACCOUNT_FACTORY.to_string());
+        let address = address.parse().with_context(|| format!("invalid ACCOUNT_FACTORY {:?}", address))?;
+        let salt = match env_var("ACCOUNT_SALT") {
+            Some(salt) => U256::from_dec_str(&salt).with_context(|| format!("invalid ACCOUNT_SALT {:?}", salt))?,
+            None => U256::zero(),
+        };
+        Ok(Self { address, salt })
+    }
+
+    /// The address of `owner`'s account, deployed or not.
+    pub async fn account_address<M: Middleware + 'static>(
+        &self,
+        client: Arc<M>,
+        owner: Address,
+    ) -> anyhow::Result<Address> {
+        let factory = SimpleAccountFactory::new(self.address, client);
+        factory.get_address(owner, self.salt).call().await.context("failed to ask ACCOUNT_FACTORY")
+    }
+
+    /// The `initCode` deploying `owner`'s account: the factory followed by
+    /// its `createAccount` calldata.
+    pub fn init_code<M: Middleware + 'static>(&self, client: Arc<M>, owner: Address) -> anyhow::Result<Bytes> {
+        let call = SimpleAccountFactory::new(self.address, client).create_account(owner, self.salt);
+        let data = call.calldata().context("createAccount has no calldata")?;
+        Ok([self.address.as_bytes(), &data[..]].concat().into())
+    }
+}
+
+/// The account's owner: ACCOUNT_OWNER if set, otherwise the USER_PRIVATE_KEY
+/// wallet. Only the address is needed to find or deploy the account.
+pub fn owner_address(config: &Config) -> anyhow::Result<Address> {
+    match env_var("ACCOUNT_OWNER") {
+        Some(owner) => owner.parse().with_context(|| format!("invalid ACCOUNT_OWNER {:?}", owner)),
+        None => Ok(owner(config)?.address()),
+    }
+}
+
+/// The USER_PRIVATE_KEY wallet, which owns the smart account.
+pub fn owner(config: &Config) -> anyhow::Result<LocalWallet> {
+    Ok(config::secret("USER_PRIVATE_KEY")?.parse::<LocalWallet>()?.with_chain_id(config.chain_id))
+}
+
+/// The account calldata making `calls` (target, value, data): `execute` for
+/// one call, `executeBatch` for several, which can't attach value.
+pub fn execute<M: Middleware + 'static>(
+    client: Arc<M>,
+    account: Address,
+    calls: Vec<(Address, U256, Bytes)>,
+) -> anyhow::Result<Bytes> {
+    let account = SimpleAccount::new(account, client);
+    let call = match <[_; 1]>::try_from(calls) {
+        Ok([(target, value, data)]) => account.execute(target, value, data),
+        Err(calls) => {
+            anyhow::ensure!(
+                calls.iter().all(|(_, value, _)| value.is_zero()),
+                "a batch of calls from the smart account can't attach value"
+            );
+            let (targets, data) = calls.into_iter().map(|(target, _, data)| (target, data)).unzip();
+            account.execute_batch(targets, data)
+        }
+    };
+    call.calldata().context("account call has no calldata")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_node;
+
+    #[test]
+    fn hash_and_wire_format() {
+        let op = UserOperation {
+            sender: Address::repeat_byte(0x11),
+            nonce: 7.into(),
+            call_data: vec![0xb6, 0x1d, 0x27, 0xf6].into(),
+            call_gas_limit: 100_000.into(),
+            verification_gas_limit: 150_000.into(),
+            pre_verification_gas: 50_000.into(),
+            max_fee_per_gas: 1_000_000_000u64.into(),
+            max_priority_fee_per_gas: 100_000_000u64.into(),
+            ..Default::default()
+        };
+        let entry_point: Address = ENTRY_POINT.parse().unwrap();
+        let hash = op.hash(entry_point, 8453);
+        assert_ne!(hash, op.hash(entry_point, 1));
+        assert_ne!(hash, UserOperation { nonce: 8.into(), ..op.clone() }.hash(entry_point, 8453));
+        // The signature isn't part of what is signed.
+        assert_eq!(hash, UserOperation { signature: vec![1].into(), ..op.clone() }.hash(entry_point, 8453));
+
+        let json = serde_json::to_value(&op).unwrap();
+        assert_eq!(json["nonce"], "0x7");
+        assert_eq!(json["callGasLimit"], "0x186a0");
+        assert_eq!(json["paymasterAndData"], "0x");
+        assert_eq!(serde_json::from_value::<UserOperation>(json).unwrap(), op);
+
+        let mut op = op;
+        let quote: Quote = serde_json::from_str(r#"{"callGasLimit":"0x1","paymasterAndData":"0xabcd"}"#).unwrap();
+        quote.apply(&mut op);
+        assert_eq!((op.call_gas_limit, op.verification_gas_limit), (1.into(), 150_000.into()));
+        assert_eq!(op.paymaster_and_data, Bytes::from(vec![0xab, 0xcd]));
+    }
+
+    #[test]
+    fn rejections() {
+        let error = JsonRpcError { code: -32500, message: "AA21 didn't pay prefund".to_string(), data: None };
+        let refused = ProviderError::JsonRpcClientError(Box::new(HttpClientError::JsonRpcError(error)));
+        assert!(is_rejection(&refused.into()));
+        assert!(!is_rejection(&ProviderError::CustomError("request timed out".to_string()).into()));
+        assert!(!is_rejection(&anyhow::anyhow!("connection reset")));
+    }
+
+    #[test]
+    fn module_types_and_replay() {
+        assert_eq!(ModuleType::parse("Executor").unwrap(), ModuleType::Executor);
+        assert_eq!(ModuleType::parse("4").unwrap(), ModuleType::Hook);
+        assert!(ModuleType::parse("plugin").is_err());
+        assert_eq!(ModuleType::from_id(1.into()), Some(ModuleType::Validator));
+        assert_eq!(ModuleType::from_id(5.into()), None);
+
+        let (a, b) = (Address::repeat_byte(0xa), Address::repeat_byte(0xb));
+        let (validator, executor) = (U256::from(1), U256::from(2));
+        let changes = [
+            (true, validator, a),
+            (true, executor, b),
+            (true, executor, a),
+            (false, validator, a),
+            (false, executor, b),
+            (true, executor, b),
+        ];
+        assert_eq!(replay(&changes), vec![(executor, a), (executor, b)]);
+    }
+
+    #[test]
+    fn session_scopes_and_limits() {
+        let target = Address::repeat_byte(0x22);
+        let approve = Scope { target, selector: [0x09, 0x5e, 0xa7, 0xb3] };
+        assert_eq!(parse_scope(&format!("{:?}:0x095ea7b3", target)).unwrap(), approve);
+        assert_eq!(parse_scope(&format!("{:?}:approve(address,uint256)", target)).unwrap(), approve);
+        assert!(parse_scope(&format!("{:?}:approve", target)).is_err());
+        assert!(parse_scope(&format!("{:?}:0x095ea7", target)).is_err());
+        assert!(parse_scope("0x095ea7b3").is_err());
+
+        let session = Session { valid_until: 1_000, value_limit: 5.into() };
+        assert!(session.check(5.into(), 999).is_ok());
+        assert!(session.check(6.into(), 999).is_err());
+        assert!(session.check(0.into(), 1_000).is_err());
+    }
+
+    #[tokio::test]
+    async fn sending_a_sponsored_operation() {
+        let chain = mock_node::MockNode::start(|method, params| match method {
+            "eth_feeHistory" => mock_node::Reply::Result(json!(FeeHistory {
+                oldest_block: 99.into(),
+                base_fee_per_gas: vec![U256::exp10(9); 2],
+                gas_used_ratio: vec![0.5],
+                reward: vec![vec![U256::exp10(8)]],
+            })),
+            "eth_getCode" => mock_node::Reply::Result(json!(Bytes::from(vec![0x60]))),
+            "eth_call" => mock_node::Reply::Result(json!(Bytes::from(encode(&[Token::Uint(3.into())])))),
+            _ => mock_node::idle(method, params),
+        })
+        .await;
+        let services = mock_node::MockNode::start(|method, params| match method {
+            "eth_estimateUserOperationGas" => mock_node::Reply::Result(json!({
+                "callGasLimit": "0x186a0",
+                "verificationGasLimit": "0x249f0",
+                "preVerificationGas": "0xc350",
+            })),
+            "pm_sponsorUserOperation" => {
+                mock_node::Reply::Result(json!({ "paymasterAndData": "0xabcd", "verificationGasLimit": "0x30d40" }))
+            }
+            "eth_sendUserOperation" if params[0]["paymasterAndData"] == "0x" => {
+                mock_node::Reply::Error("AA21 didn't pay prefund".to_string())
+            }
+            "eth_sendUserOperation" => mock_node::Reply::Result(json!(H256::repeat_byte(0x0a))),
+            "eth_getUserOperationReceipt" => mock_node::Reply::Result(json!({
+                "userOpHash": H256::repeat_byte(0x0a),
+                "success": true,
+                "actualGasCost": "0x1",
+                "receipt": mock_node::receipt(H256::repeat_byte(0x0b), true),
+            })),
+            _ => mock_node::idle(method, params),
+        })
+        .await;
+        let provider = || Provider::<Http>::try_from(services.url.as_str()).unwrap();
+        let account = SmartAccount {
+            address: Address::repeat_byte(0x5a),
+            entry_point: ENTRY_POINT.parse().unwrap(),
+            bundler: provider(),
+            paymaster: Some(provider()),
+        };
+        let config =
+            Config { rpc_url: chain.url.clone(), chain_id: mock_node::CHAIN_ID, contract_address: Address::zero() };
+        let call_data = Bytes::from(vec![0xb6, 0x1d, 0x27, 0xf6]);
+
+        let mut op = account.user_operation(chain.client(), &config, call_data.clone()).await.unwrap();
+        assert_eq!((op.sender, op.nonce, op.call_data.clone()), (account.address, 3.into(), call_data));
+        assert_eq!((op.call_gas_limit, op.pre_verification_gas), (100_000.into(), 50_000.into()));
+        assert!(op.init_code.is_empty());
+        assert!(op.max_fee_per_gas >= op.max_priority_fee_per_gas && !op.max_priority_fee_per_gas.is_zero());
+
+        // Unsponsored, the bundler refuses it outright.
+        let error = account.send(&op).await.unwrap_err();
+        assert!(is_rejection(&error), "{}", error);
+
+        account.sponsor(&mut op).await.unwrap();
+        assert_eq!(op.paymaster_and_data, Bytes::from(vec![0xab, 0xcd]));
+        assert_eq!((op.call_gas_limit, op.verification_gas_limit), (100_000.into(), 200_000.into()));
+        let owner = mock_node::wallet();
+        account.sign(&owner, &mut op, mock_node::CHAIN_ID).await.unwrap();
+        let signature = Signature::try_from(op.signature.as_ref()).unwrap();
+        let hash = op.hash(account.entry_point, mock_node::CHAIN_ID);
+        assert_eq!(signature.recover(hash.as_bytes()).unwrap(), owner.address());
+
+        let op_hash = account.send(&op).await.unwrap();
+        let sent = services.calls("eth_sendUserOperation");
+        assert_eq!(serde_json::from_value::<UserOperation>(sent[1][0].clone()).unwrap(), op);
+        assert_eq!(sent[1][1], json!(account.entry_point));
+        let receipt = account.wait(op_hash, Duration::from_secs(1)).await.unwrap().unwrap();
+        assert!(receipt.success);
+        assert_eq!(receipt.receipt.transaction_hash, H256::repeat_byte(0x0b));
+    }
+}
@@ -0,0 +1,95 @@
+//! Status of any transaction by hash, whoever sent it: pending, or mined
+//! with its receipt, and why it failed when it did.
+
+use crate::calldata;
+use crate::pipeline::Rpc;
+use ethers::prelude::*;
+use ethers::providers::RpcError;
+use ethers::types::transaction::eip2718::TypedTransaction;
+
+pub enum Status {
+    Pending(Transaction),
+    Mined { tx: Transaction, receipt: Box<TransactionReceipt>, confirmations: u64 },
+}
+
+impl Status {
+    pub fn tx(&self) -> &Transaction {
+        match self {
+            Status::Pending(tx) | Status::Mined { tx, .. } => tx,
+        }
+    }
+}
+
+/// Looks `hash` up; `None` when the node doesn't know the transaction.
+pub async fn lookup<M: Middleware>(client: &M, hash: H256) -> anyhow::Result<Option<Status>>
+where
+    M::Error: 'static,
+{
+    let Some(tx) = client.get_transaction(hash).await? else {
+        return Ok(None);
+    };
+    let Some(block) = tx.block_number else {
+        return Ok(Some(Status::Pending(tx)));
+    };
+    // Nodes can index the transaction a moment before its receipt.
+    let Some(receipt) = client.get_transaction_receipt(hash).await? else {
+        return Ok(Some(Status::Pending(tx)));
+    };
+    let latest = client.get_block_number().await?;
+    let confirmations = (latest.as_u64() + 1).saturating_sub(block.as_u64());
+    Ok(Some(Status::Mined { tx, receipt: Box::new(receipt), confirmations }))
+}
+
+/// Why a mined transaction reverted, found by replaying it as a call on the
+/// state before its block. Transactions earlier in the same block aren't
+/// replayed, so a failure that depended on them may not reproduce.
+pub async fn revert_reason(provider: &Rpc, tx: &Transaction) -> anyhow::Result<String> {
+    let mut call = TransactionRequest::new().from(tx.from).value(tx.value).data(tx.input.clone()).gas(tx.gas);
+    if let Some(to) = tx.to {
+        call = call.to(to);
+    }
+    let call: TypedTransaction = call.into();
+    let parent = tx.block_number.unwrap_or_default().saturating_sub(1.into());
+    match provider.call(&call, Some(parent.into())).await {
+        Ok(_) => Ok("succeeds when replayed on the parent block; it failed on state from earlier in its block".to_string()),
+        Err(e) => match RpcError::as_error_response(&e).and_then(|response| response.as_revert_data()) {
+            Some(data) => Ok(calldata::decode_revert(&data)),
+            None => Ok(e.to_string()),
+        },
+    }
+}
+
+/// A log decoded against abi.json or the built-in token events, e.g.
+/// `Locked(user=0x…, amount=5)`, or its emitter and first topic when neither
+/// knows it.
+pub fn describe_log(log: &Log) -> String {
+    match calldata::decode_any_event(log) {
+        Some((name, args, _)) => {
+            let args: Vec<String> =
+                args.iter().map(|(name, value)| format!("{}={}", name, calldata::format_token(value))).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+        None => format!("unknown event {:?} from {:?}", log.topics.first().copied().unwrap_or_default(), log.address),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::MYCONTRACT_ABI;
+    use ethers::abi::{encode, Token};
+
+    #[test]
+    fn describes_logs() {
+        let (user, token) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let log = Log {
+            topics: vec![MYCONTRACT_ABI.event("Locked").unwrap().signature(), user.into(), token.into()],
+            data: encode(&[Token::Uint(5.into()), Token::Uint(1.into())]).into(),
+            ..Default::default()
+        };
+        assert_eq!(describe_log(&log), format!("Locked(user={:?}, token={:?}, amount=5, nonce=1)", user, token));
+
+        let unknown = Log { topics: vec![H256::repeat_byte(9)], ..Default::default() };
+        assert!(describe_log(&unknown).starts_with("unknown event 0x0909"));
+    }
+}
@@ -0,0 +1,165 @@
+//! Helpers for locating Solidity storage slots, so contract state can be read
+//! with eth_getStorageAt even when no view function exposes it.
+
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+
+/// Parses a slot given as a decimal number or a `0x`-prefixed hex word.
+pub fn parse_slot(input: &str) -> anyhow::Result<H256> {
+    let value = match input.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16)?,
+        None => U256::from_dec_str(input)?,
+    };
+    Ok(H256::from_uint(&value))
+}
+
+/// Parses a mapping key into its 32-byte ABI encoding. Addresses (20 bytes)
+/// and bytes32 values are recognised by length; anything else is treated as
+/// a decimal or hex `uint256`.
+pub fn parse_key(input: &str) -> anyhow::Result<H256> {
+    match input.strip_prefix("0x") {
+        Some(hex) if hex.len() == 40 => Ok(H256::from(input.parse::<Address>()?)),
+        Some(hex) if hex.len() == 64 => Ok(input.parse::<H256>()?),
+        _ => parse_slot(input),
+    }
+}
+
+/// The slot of `mapping[key]` for a mapping declared at `slot`:
+/// `keccak256(key . slot)`.
+pub fn mapping_slot(slot: H256, key: H256) -> H256 {
+    let mut preimage = [0u8; 64];
+    preimage[..32].copy_from_slice(key.as_bytes());
+    preimage[32..].copy_from_slice(slot.as_bytes());
+    H256::from(keccak256(preimage))
+}
+
+/// Resolves a (possibly nested) mapping lookup and an optional struct field
+/// offset to the final slot.
+pub fn resolve_slot(slot: H256, keys: &[H256], offset: u64) -> H256 {
+    let base = keys.iter().fold(slot, |slot, key| mapping_slot(slot, *key));
+    H256::from_uint(&(base.into_uint() + U256::from(offset)))
+}
+
+/// Finds the declaration slot of a mapping by reading storage: the first low
+/// slot whose entry for `keys`, at word `offset`, holds `expected`. `expected`
+/// must be non-zero for the match to mean anything.
+pub async fn find_mapping_slot<M: Middleware>(
+    client: &M,
+    contract: Address,
+    keys: &[H256],
+    offset: u64,
+    expected: H256,
+) -> anyhow::Result<Option<u64>>
+where
+    M::Error: 'static,
+{
+    for base in 0..SEARCH_SLOTS {
+        let slot = resolve_slot(H256::from_low_u64_be(base), keys, offset);
+        if client.get_storage_at(contract, slot, None).await? == expected {
+            return Ok(Some(base));
+        }
+    }
+    Ok(None)
+}
+
+/// ERC-1967 proxy slots, which show up in the storage of upgradeable
+/// contracts.
+const ERC1967_SLOTS: &[(&str, &str)] = &[
+    ("ERC-1967 implementation", "0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc"),
+    ("ERC-1967 admin", "0xb53127684a568b3173ae13b9f8a6016e243e63b6e8ee1178d6a717850b5d6103"),
+    ("ERC-1967 beacon", "0xa3f0ad74e5423aebfd80d3ef4346578335a9a72aeed2dd8f3c0da1b7c7a7c84d"),
+];
+
+/// Declaration slots and struct offsets tried when naming a slot.
+const SEARCH_SLOTS: u64 = 64;
+const SEARCH_OFFSETS: u64 = 4;
+
+/// Names `slot` where possible: a low declaration slot, an ERC-1967 slot, or
+/// an entry of a mapping declared at a low slot and keyed by a prefix of
+/// `keys` (each with a name), e.g. `slot 5[user][token][nonce] +1`.
+pub fn describe_slot(slot: H256, keys: &[(&str, H256)]) -> Option<String> {
+    if slot.into_uint() < U256::from(SEARCH_SLOTS) {
+        return Some(format!("slot {}", slot.into_uint()));
+    }
+    if let Some((name, _)) = ERC1967_SLOTS.iter().find(|(_, known)| known.parse::<H256>().ok() == Some(slot)) {
+        return Some(name.to_string());
+    }
+    for base in 0..SEARCH_SLOTS {
+        let mut entry = H256::from_low_u64_be(base);
+        let mut path = format!("slot {}", base);
+        for (name, key) in keys {
+            entry = mapping_slot(entry, *key);
+            path.push_str(&format!("[{}]", name));
+            for offset in 0..SEARCH_OFFSETS {
+                if H256::from_uint(&(entry.into_uint() + U256::from(offset))) == slot {
+                    return Some(match offset {
+                        0 => path,
+                        offset => format!("{} +{}", path, offset),
+                    });
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(input: &str) -> H256 {
+        parse_slot(input).unwrap()
+    }
+
+    #[test]
+    fn slots_and_keys() {
+        assert_eq!(slot("3"), H256::from_low_u64_be(3));
+        assert_eq!(slot("0x0a"), H256::from_low_u64_be(10));
+        assert!(parse_slot("0xzz").is_err());
+        assert!(parse_slot("-1").is_err());
+
+        let user = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8";
+        assert_eq!(parse_key(user).unwrap(), H256::from(user.parse::<Address>().unwrap()));
+        let word = format!("0x{}", "ab".repeat(32));
+        assert_eq!(parse_key(&word).unwrap(), H256::repeat_byte(0xab));
+        assert_eq!(parse_key("42").unwrap(), H256::from_low_u64_be(42));
+        assert_eq!(parse_key("0x2a").unwrap(), H256::from_low_u64_be(42));
+    }
+
+    #[test]
+    fn mapping_slots() {
+        // keccak256(abi.encode(uint256(0), uint256(0))), the first entry of a
+        // mapping declared at slot 0.
+        let expected: H256 = "0xad3228b676f7d3cd4284a5443f17f1962b36e491b30a40b2405849e597ba5fb5".parse().unwrap();
+        assert_eq!(mapping_slot(H256::zero(), H256::zero()), expected);
+        // The key comes first in the preimage.
+        let (one, two) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        assert_ne!(mapping_slot(one, two), mapping_slot(two, one));
+        let mut preimage = [0u8; 64];
+        preimage[31] = 2;
+        preimage[63] = 1;
+        assert_eq!(mapping_slot(one, two), H256::from(keccak256(preimage)));
+    }
+
+    #[test]
+    fn describes_slots() {
+        let (user, token, nonce) = (H256::repeat_byte(1), H256::repeat_byte(2), H256::from_low_u64_be(7));
+        let keys = [("user", user), ("token", token), ("nonce", nonce)];
+        assert_eq!(describe_slot(H256::from_low_u64_be(3), &keys).as_deref(), Some("slot 3"));
+        let entry = resolve_slot(H256::from_low_u64_be(5), &[user, token, nonce], 1);
+        assert_eq!(describe_slot(entry, &keys).as_deref(), Some("slot 5[user][token][nonce] +1"));
+        let balance = resolve_slot(H256::from_low_u64_be(2), &[user], 0);
+        assert_eq!(describe_slot(balance, &keys).as_deref(), Some("slot 2[user]"));
+        assert_eq!(describe_slot(H256::repeat_byte(0xee), &keys), None);
+    }
+
+    #[test]
+    fn nested_slots() {
+        let (base, user, token) = (H256::from_low_u64_be(4), H256::repeat_byte(1), H256::repeat_byte(2));
+        assert_eq!(resolve_slot(base, &[], 0), base);
+        assert_eq!(resolve_slot(base, &[], 2), H256::from_low_u64_be(6));
+        let entry = mapping_slot(mapping_slot(base, user), token);
+        assert_eq!(resolve_slot(base, &[user, token], 0), entry);
+        assert_eq!(resolve_slot(base, &[user, token], 1), H256::from_uint(&(entry.into_uint() + 1)));
+    }
+}
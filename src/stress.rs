@@ -0,0 +1,225 @@
+//! Load generation against a testnet deployment: locks for synthetic users at
+//! a fixed rate, measuring how long the node takes to accept each transaction
+//! and how long until it is mined.
+//!
+//! Jobs are signed by a test signer: STRESS_SIGNER_KEY (a secret setting)
+//! signs `keccak256(abi.encodePacked(user, token, amount, nonce))` as an
+//! Ethereum signed message; without it, a configured signing service (see
+//! [`crate::signing_service`]) is asked instead, for deployments whose
+//! contracts check a different digest.
+
+use crate::audit;
+use crate::batch::NonceTracker;
+use crate::bench::Stats;
+use crate::config::{self, env_var, Config, Job};
+use crate::contract::MyContract;
+use crate::fees::FeeModel;
+use crate::pipeline::Client;
+use crate::print_warn;
+use crate::signing_service::SigningService;
+use ethers::abi::{self, Token};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+
+const KIND: &str = "lock";
+
+pub enum TestSigner {
+    Key(LocalWallet),
+    Service(SigningService),
+}
+
+impl TestSigner {
+    /// STRESS_SIGNER_KEY if set, otherwise the signing service.
+    pub fn from_env() -> anyhow::Result<Self> {
+        if env_var("STRESS_SIGNER_KEY").is_some() {
+            return Ok(Self::Key(config::secret("STRESS_SIGNER_KEY")?.parse()?));
+        }
+        match SigningService::from_env()? {
+            Some(service) => Ok(Self::Service(service)),
+            None => anyhow::bail!("stress needs a test signer: set STRESS_SIGNER_KEY or SIGNER_SERVICE_URL"),
+        }
+    }
+
+    pub async fn sign(&self, config: &Config, job: &mut Job) -> anyhow::Result<()> {
+        match self {
+            Self::Key(wallet) => {
+                let digest = job_digest(job)?;
+                job.signature = wallet.sign_message(digest).await?.to_vec().into();
+                audit::record_signature(wallet.address(), KIND, json!({ "job": job, "digest": H256(digest) }))
+            }
+            Self::Service(service) => service.sign(KIND, config, job).await,
+        }
+    }
+}
+
+/// The digest STRESS_SIGNER_KEY signs:
+/// `keccak256(abi.encodePacked(user, token, amount, nonce))`.
+pub fn job_digest(job: &Job) -> anyhow::Result<[u8; 32]> {
+    let packed = abi::encode_packed(&[
+        Token::Address(job.user),
+        Token::Address(job.token),
+        Token::Uint(job.amount),
+        Token::Uint(job.nonce),
+    ])?;
+    Ok(keccak256(packed))
+}
+
+/// An unsigned job for a fresh random user, with a random nonce so it can't
+/// collide with an earlier run.
+pub fn synthetic_job(token: Address, amount: U256) -> Job {
+    Job {
+        user: Address::random(),
+        token,
+        amount,
+        nonce: H256::random().into_uint(),
+        signature: Bytes::new(),
+    }
+}
+
+pub struct Options {
+    /// Transactions started per second.
+    pub rate: f64,
+    pub count: usize,
+    pub token: Address,
+    pub amount: U256,
+    pub fees: FeeModel,
+}
+
+pub struct Report {
+    /// From the start of each transaction to the node accepting it; errors
+    /// are jobs that failed to sign, estimate or broadcast.
+    pub broadcast: Stats,
+    /// From the start of each broadcast transaction to its receipt; errors
+    /// are transactions that reverted, were dropped or never confirmed.
+    pub confirmation: Stats,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// Transactions started per second.
+    pub fn send_rate(&self) -> f64 {
+        self.broadcast.requests() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Confirmed transactions per second.
+    pub fn throughput(&self) -> f64 {
+        self.confirmation.latencies.len() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Starts `options.count` locks at `options.rate` per second, dealt
+/// round-robin across `clients`, and waits for every receipt.
+///
+/// Each transaction is signed and broadcast in turn, so nonces stay in order;
+/// when that takes longer than the interval the rate achieved drops below
+/// the one asked for, which the report shows.
+pub async fn run(
+    clients: &[Arc<Client>],
+    config: &Config,
+    signer: &TestSigner,
+    options: &Options,
+) -> anyhow::Result<Report> {
+    anyhow::ensure!(!clients.is_empty(), "stress needs at least one sender");
+    anyhow::ensure!(options.rate > 0.0, "the rate must be positive");
+    let mut nonces = Vec::with_capacity(clients.len());
+    for client in clients {
+        nonces.push(NonceTracker::new(&**client).await?);
+    }
+
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / options.rate));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut broadcast = Stats::default();
+    let mut waits = Vec::new();
+    let started = Instant::now();
+
+    for index in 0..options.count {
+        ticker.tick().await;
+        let slot = index % clients.len();
+        let client = &clients[slot];
+        let sent = Instant::now();
+        match send(client, config, signer, options, &mut nonces[slot]).await {
+            Ok(tx_hash) => {
+                broadcast.latencies.push(sent.elapsed());
+                let client = client.clone();
+                waits.push(tokio::spawn(async move {
+                    let receipt = PendingTransaction::new(tx_hash, client.provider()).await;
+                    if let Ok(Some(receipt)) = &receipt {
+                        if let Err(e) = audit::record_receipt(client.address(), KIND, receipt) {
+                            print_warn!("Could not record the receipt of {:?} in the audit log: {:#}", tx_hash, e);
+                        }
+                    }
+                    match receipt {
+                        Ok(Some(receipt)) if receipt.status == Some(U64::from(1)) => Ok(sent.elapsed()),
+                        Ok(Some(_)) => Err(format!("{:?} reverted", tx_hash)),
+                        Ok(None) => Err(format!("{:?} was dropped", tx_hash)),
+                        Err(e) => Err(format!("receipt wait for {:?} failed: {}", tx_hash, e)),
+                    }
+                }));
+            }
+            Err(e) => {
+                print_warn!("[{}] {:#}", index + 1, e);
+                broadcast.errors += 1;
+                broadcast.first_error.get_or_insert_with(|| format!("{:#}", e));
+                nonces[slot].resync(&**client).await?;
+            }
+        }
+    }
+
+    let mut confirmation = Stats::default();
+    for wait in waits {
+        match wait.await? {
+            Ok(latency) => confirmation.latencies.push(latency),
+            Err(e) => {
+                print_warn!("{}", e);
+                confirmation.errors += 1;
+                confirmation.first_error.get_or_insert(e);
+            }
+        }
+    }
+    broadcast.latencies.sort();
+    confirmation.latencies.sort();
+    Ok(Report { broadcast, confirmation, elapsed: started.elapsed() })
+}
+
+/// Signs and broadcasts one synthetic lock, returning its hash.
+async fn send(
+    client: &Arc<Client>,
+    config: &Config,
+    signer: &TestSigner,
+    options: &Options,
+    nonces: &mut NonceTracker,
+) -> anyhow::Result<H256> {
+    let mut job = synthetic_job(options.token, options.amount);
+    signer.sign(config, &mut job).await?;
+    let contract = MyContract::new(config.contract_address, client.clone());
+    let mut tx = contract
+        .lock(job.user, job.token, job.amount, job.nonce, job.signature.clone())
+        .value(job.lock_value()?)
+        .tx;
+    options.fees.set_transaction_type(&mut tx);
+    if let Ok(fees) = options.fees.suggest(&**client).await {
+        fees.apply(&mut tx);
+    }
+    tx.set_nonce(nonces.assign());
+    Ok(audit::send_transaction(&**client, tx, KIND).await?.tx_hash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn key_signer_signs_the_packed_digest() {
+        let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let config = Config { rpc_url: String::new(), chain_id: 11155111, contract_address: Address::zero() };
+        let mut job = synthetic_job(Address::zero(), 1000.into());
+        TestSigner::Key(wallet.clone()).sign(&config, &mut job).await.unwrap();
+
+        let signature = Signature::try_from(job.signature.as_ref()).unwrap();
+        assert_eq!(signature.recover(&job_digest(&job).unwrap()[..]).unwrap(), wallet.address());
+    }
+}
@@ -0,0 +1,117 @@
+//! Terminal styling. On a terminal, outcomes are colored (success green,
+//! warnings yellow, errors red) and addresses dimmed; when stdout is piped,
+//! NO_COLOR is set or `--no-color` is passed, output stays plain, with the
+//! emoji markers log scrapers already match on.
+
+use std::env;
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Decides once, at startup, whether to color output.
+pub fn init(no_color: bool) {
+    let no_color_env = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    COLOR.store(!no_color && !no_color_env && std::io::stdout().is_terminal(), Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Sends the lines of the `print_*` macros to stderr from now on, for
+/// commands whose stdout is data for another program, such as `stream`.
+pub fn to_stderr() {
+    STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Prints a line of the `print_*` macros, or one going with it, to stdout,
+/// or to stderr after [`to_stderr`].
+pub fn emit(line: &str) {
+    match STDERR.load(Ordering::Relaxed) {
+        true => eprintln!("{}", line),
+        false => println!("{}", line),
+    }
+}
+
+fn paint(code: &str, text: impl Display) -> String {
+    match enabled() {
+        true => format!("\x1b[{}m{}\x1b[0m", code, text),
+        false => text.to_string(),
+    }
+}
+
+/// A success line.
+pub fn ok(message: &str) -> String {
+    match enabled() {
+        true => paint("32", format!("✔ {}", message)),
+        false => format!("✅ {}", message),
+    }
+}
+
+/// A warning line.
+pub fn warn(message: &str) -> String {
+    match enabled() {
+        true => paint("33", format!("⚠ {}", message)),
+        false => format!("⚠️  {}", message),
+    }
+}
+
+/// An error line.
+pub fn error(message: &str) -> String {
+    match enabled() {
+        true => paint("31", format!("✘ {}", message)),
+        false => format!("❌ {}", message),
+    }
+}
+
+/// De-emphasized text, such as addresses and hashes.
+pub fn dim(text: impl Display) -> String {
+    paint("2", text)
+}
+
+/// A `=== Title ===` section header.
+pub fn header(title: &str) -> String {
+    paint("1", format!("=== {} ===", title))
+}
+
+/// A `Label: value` line with the labels of a section padded to `width`.
+pub fn field(label: &str, width: usize, value: impl Display) -> String {
+    format!("{:<width$} {}", format!("{}:", label), value, width = width + 1)
+}
+
+/// Prints a success line.
+#[macro_export]
+macro_rules! print_ok {
+    ($($arg:tt)*) => { $crate::style::emit(&$crate::style::ok(&format!($($arg)*))) };
+}
+
+/// Prints a warning line.
+#[macro_export]
+macro_rules! print_warn {
+    ($($arg:tt)*) => { $crate::style::emit(&$crate::style::warn(&format!($($arg)*))) };
+}
+
+/// Prints an error line.
+#[macro_export]
+macro_rules! print_error {
+    ($($arg:tt)*) => { $crate::style::emit(&$crate::style::error(&format!($($arg)*))) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_output() {
+        // Tests don't run on a terminal, so nothing is colored.
+        init(false);
+        assert_eq!(ok("done"), "✅ done");
+        assert_eq!(warn("careful"), "⚠️  careful");
+        assert_eq!(error("failed"), "❌ failed");
+        assert_eq!(dim("0xabc"), "0xabc");
+        assert_eq!(field("Chain ID", 16, 8453), "Chain ID:         8453");
+    }
+}
@@ -0,0 +1,88 @@
+//! The TLS implementation behind the RPC providers. Builds pick rustls (the
+//! default, needing no system OpenSSL, so it suits static musl binaries),
+//! the platform's native TLS (OpenSSL, Secure Transport or SChannel, for
+//! hosts whose certificate stores or FIPS-validated libraries must be used),
+//! or both with the `rustls` and `native-tls` features. With both,
+//! TLS_BACKEND chooses for the HTTP providers; WebSocket connections can't
+//! be steered and take native TLS.
+
+use crate::config::env_var;
+use ethers::prelude::*;
+
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("build with the `rustls` or `native-tls` feature");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Rustls,
+    NativeTls,
+}
+
+impl Backend {
+    /// TLS_BACKEND, defaulting to rustls when the build has it.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::parse(env_var("TLS_BACKEND").as_deref())
+    }
+
+    /// `rustls` or `native-tls`, which the build must include.
+    pub fn parse(name: Option<&str>) -> anyhow::Result<Self> {
+        let backend = match name {
+            None if cfg!(feature = "rustls") => Backend::Rustls,
+            None => Backend::NativeTls,
+            Some("rustls") => Backend::Rustls,
+            Some("native-tls") => Backend::NativeTls,
+            Some(other) => anyhow::bail!("TLS_BACKEND must be rustls or native-tls, not {:?}", other),
+        };
+        anyhow::ensure!(backend.built(), "TLS_BACKEND={} needs a build with the `{}` feature", backend, backend);
+        Ok(backend)
+    }
+
+    /// Whether this build includes the backend.
+    pub fn built(self) -> bool {
+        match self {
+            Backend::Rustls => cfg!(feature = "rustls"),
+            Backend::NativeTls => cfg!(feature = "native-tls"),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Backend::Rustls => "rustls",
+            Backend::NativeTls => "native-tls",
+        })
+    }
+}
+
+/// An HTTP client on the TLS_BACKEND.
+pub fn client() -> anyhow::Result<reqwest::Client> {
+    let backend = Backend::from_env()?;
+    let builder = reqwest::Client::builder();
+    #[cfg(feature = "rustls")]
+    let builder = if backend == Backend::Rustls { builder.use_rustls_tls() } else { builder };
+    #[cfg(feature = "native-tls")]
+    let builder = if backend == Backend::NativeTls { builder.use_native_tls() } else { builder };
+    Ok(builder.build()?)
+}
+
+/// A provider for the JSON-RPC endpoint at `url`, over [`client`].
+pub fn provider(url: &str) -> anyhow::Result<Provider<Http>> {
+    let url: reqwest::Url = url.parse()?;
+    Ok(Provider::new(Http::new_with_client(url, client()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choosing_backends() {
+        let default = Backend::parse(None).unwrap();
+        assert!(default.built());
+        assert_eq!(default == Backend::Rustls, cfg!(feature = "rustls"));
+        assert_eq!(Backend::parse(Some("native-tls")).is_ok(), cfg!(feature = "native-tls"));
+        let error = Backend::parse(Some("openssl")).unwrap_err().to_string();
+        assert_eq!(error, "TLS_BACKEND must be rustls or native-tls, not \"openssl\"");
+    }
+}
@@ -0,0 +1,141 @@
+//! ERC-20 metadata, so amounts can be shown in whole tokens ("1,500.00 USDC")
+//! rather than as base-unit integers whose scale is easy to get wrong.
+
+use crate::contract::Erc20;
+use crate::units::{self, Rounding};
+use crate::{print_ok, print_warn};
+use ethers::prelude::*;
+use anyhow::Context;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl TokenInfo {
+    /// `amount` base units in whole tokens, with the symbol.
+    pub fn format_amount(&self, amount: U256) -> String {
+        format!("{} {}", format_amount(amount, self.decimals), self.symbol)
+    }
+}
+
+/// Reads `token`'s metadata. Returns `None` when any of name, symbol or
+/// decimals can't be read, as with tokens that predate the optional ERC-20
+/// metadata functions or return them as bytes32.
+pub async fn metadata<M: Middleware + 'static>(client: Arc<M>, token: Address) -> Option<TokenInfo> {
+    let erc20 = Erc20::new(token, client);
+    let (name, symbol, decimals) = (erc20.name(), erc20.symbol(), erc20.decimals());
+    let (name, symbol, decimals) = futures::join!(name.call(), symbol.call(), decimals.call());
+    Some(TokenInfo { name: name.ok()?, symbol: symbol.ok()?, decimals: decimals.ok()? })
+}
+
+/// What `user` has approved `spender` for, and holds, of `token` as of
+/// `block` (the latest when `None`).
+pub async fn allowance_and_balance<M: Middleware + 'static>(
+    client: Arc<M>,
+    token: Address,
+    user: Address,
+    spender: Address,
+    block: Option<BlockId>,
+) -> anyhow::Result<(U256, U256)> {
+    let erc20 = Erc20::new(token, client);
+    let block = block.unwrap_or_else(|| BlockNumber::Latest.into());
+    let allowance = erc20.allowance(user, spender).block(block).call().await?;
+    Ok((allowance, erc20.balance_of(user).block(block).call().await?))
+}
+
+/// Warns when the user's `allowance` for `spender` (the lock contract) or
+/// `balance` is short of `amount`: either makes an ERC-20 lock revert.
+pub fn check_allowance(allowance: U256, balance: U256, spender: Address, amount: U256) {
+    if allowance < amount {
+        print_warn!("User has approved only {} base units to {:?}; the lock needs {}", allowance, spender, amount);
+    } else {
+        print_ok!("Allowance covers the amount");
+    }
+    if balance < amount {
+        print_warn!("User holds only {} base units; the lock needs {}", balance, amount);
+    }
+}
+
+/// Parses a native-currency value such as `1000`, `30gwei` or `0.5ether`; a
+/// bare number is wei.
+pub fn parse_value(input: &str) -> anyhow::Result<U256> {
+    let input = input.trim();
+    let split = input.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split);
+    anyhow::ensure!(!amount.trim().is_empty(), "invalid value {:?}: no amount", input);
+    let unit = match unit.to_ascii_lowercase().as_str() {
+        "" | "wei" => 0,
+        "gwei" => 9,
+        "ether" | "eth" => 18,
+        other => anyhow::bail!("invalid value {:?}: unknown unit {:?}", input, other),
+    };
+    units::parse_decimal(amount, unit, Rounding::Exact).with_context(|| format!("invalid value {:?}", input))
+}
+
+/// Formats `amount` base units of a token with `decimals` decimals, grouping
+/// thousands and keeping at least two fraction digits: `1,500.00`.
+pub fn format_amount(amount: U256, decimals: u8) -> String {
+    let formatted = units::format_decimal(amount, decimals);
+    let (whole, fraction) = formatted.split_once('.').unwrap_or((&formatted, ""));
+
+    let fraction = fraction.trim_end_matches('0');
+    format!("{}.{:0<2}", group_thousands(whole), fraction)
+}
+
+/// Inserts a comma between every three digits of `digits`, from the right.
+pub(crate) fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn amounts() {
+        assert_eq!(format_amount(1_500_000_000u64.into(), 6), "1,500.00");
+        assert_eq!(format_amount(1_234_567u64.into(), 6), "1.234567");
+        assert_eq!(format_amount(100_000u64.into(), 6), "0.10");
+        assert_eq!(format_amount(U256::zero(), 18), "0.00");
+        assert_eq!(format_amount(U256::exp10(24), 18), "1,000,000.00");
+        assert_eq!(format_amount(123u64.into(), 0), "123.00");
+        assert_eq!(format_amount(1u64.into(), 18), "0.000000000000000001");
+
+        let usdc = TokenInfo { name: "USD Coin".to_string(), symbol: "USDC".to_string(), decimals: 6 };
+        assert_eq!(usdc.format_amount(1_500_000_000u64.into()), "1,500.00 USDC");
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse_value("1000").unwrap(), 1000.into());
+        assert_eq!(parse_value("30gwei").unwrap(), U256::from(30_000_000_000u64));
+        assert_eq!(parse_value("0.5 ether").unwrap(), U256::exp10(17) * 5);
+        assert_eq!(parse_value("2ETH").unwrap(), U256::exp10(18) * 2);
+        assert!(parse_value("ether").is_err());
+        assert!(parse_value("5 btc").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn values_parse_in_every_unit(wei in any::<u64>(), gwei in any::<u32>(), input in ".*") {
+            prop_assert_eq!(parse_value(&wei.to_string()).unwrap(), U256::from(wei));
+            prop_assert_eq!(parse_value(&format!("{}wei", wei)).unwrap(), U256::from(wei));
+            prop_assert_eq!(parse_value(&format!(" {}GWEI ", gwei)).unwrap(), U256::from(gwei) * U256::exp10(9));
+            prop_assert_eq!(parse_value(&format!("{}ether", gwei)).unwrap(), U256::from(gwei) * U256::exp10(18));
+            // Arbitrary input is parsed or refused, never a panic.
+            let _ = parse_value(&input);
+        }
+    }
+}
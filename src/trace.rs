@@ -0,0 +1,386 @@
+//! Transaction simulation with the node's `debug_traceCall`, to show what a
+//! transaction would change before it is sent. Needs a node with the debug
+//! namespace enabled.
+
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// An account's state as reported by the prestate tracer; fields the
+/// transaction doesn't touch are left out of the post state.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccountState {
+    pub balance: Option<U256>,
+    pub nonce: Option<u64>,
+    pub code: Option<Bytes>,
+    pub storage: BTreeMap<H256, H256>,
+}
+
+/// The prestate tracer's diff mode: the accounts the transaction changes,
+/// before and after.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StateDiff {
+    pub pre: BTreeMap<Address, AccountState>,
+    pub post: BTreeMap<Address, AccountState>,
+}
+
+impl StateDiff {
+    /// The change in `account`'s native balance.
+    pub fn balance_change(&self, account: Address) -> I256 {
+        let Some(pre) = self.pre.get(&account).and_then(|state| state.balance) else {
+            // Created by the transaction, or not touched at all.
+            return self.post.get(&account).and_then(|state| state.balance).map(I256::from_raw).unwrap_or_default();
+        };
+        let post = self.post.get(&account).and_then(|state| state.balance).unwrap_or(pre);
+        I256::from_raw(post) - I256::from_raw(pre)
+    }
+
+    /// The storage slots of `account` the transaction writes, with their
+    /// values before and after. A slot missing on one side is zero there.
+    pub fn storage_changes(&self, account: Address) -> Vec<(H256, H256, H256)> {
+        let empty = BTreeMap::new();
+        let pre = self.pre.get(&account).map_or(&empty, |state| &state.storage);
+        let post = self.post.get(&account).map_or(&empty, |state| &state.storage);
+        let slots: BTreeSet<&H256> = pre.keys().chain(post.keys()).collect();
+        slots
+            .into_iter()
+            .map(|slot| (*slot, pre.get(slot).copied().unwrap_or_default(), post.get(slot).copied().unwrap_or_default()))
+            .filter(|(_, before, after)| before != after)
+            .collect()
+    }
+}
+
+/// A log emitted during a traced call.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CallLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    #[serde(default)]
+    pub data: Bytes,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    #[serde(rename = "type", default)]
+    kind: String,
+    to: Option<Address>,
+    #[serde(default)]
+    input: Bytes,
+    value: Option<U256>,
+    #[serde(default)]
+    gas_used: U256,
+    #[serde(default)]
+    logs: Vec<CallLog>,
+    #[serde(default)]
+    calls: Vec<CallFrame>,
+    error: Option<String>,
+    revert_reason: Option<String>,
+}
+
+/// One frame of a mined transaction's call tree, condensed for reading.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InternalCall {
+    /// 0 for the transaction itself.
+    pub depth: usize,
+    /// `CALL`, `STATICCALL`, `DELEGATECALL`, `CREATE`, ...
+    pub kind: String,
+    pub to: Option<Address>,
+    pub selector: Option<[u8; 4]>,
+    pub value: U256,
+    /// Gas the frame used, including its own sub-calls.
+    pub gas_used: u64,
+    /// Why the frame failed, with the revert reason when the node decoded one.
+    pub error: Option<String>,
+}
+
+impl CallFrame {
+    /// Logs of this frame and its sub-calls in order, skipping frames that
+    /// reverted.
+    fn collect_logs(self, logs: &mut Vec<CallLog>) {
+        if self.error.is_some() {
+            return;
+        }
+        logs.extend(self.logs);
+        for call in self.calls {
+            call.collect_logs(logs);
+        }
+    }
+
+    /// This frame and its sub-calls, depth first.
+    fn flatten(self, depth: usize, calls: &mut Vec<InternalCall>) {
+        let error = match (self.error, self.revert_reason) {
+            (Some(error), Some(reason)) => Some(format!("{}: {}", error, reason)),
+            (error, _) => error,
+        };
+        calls.push(InternalCall {
+            depth,
+            kind: self.kind,
+            to: self.to,
+            selector: self.input.get(..4).map(|selector| selector.try_into().expect("four bytes")),
+            value: self.value.unwrap_or_default(),
+            gas_used: self.gas_used.low_u64(),
+            error,
+        });
+        for call in self.calls {
+            call.flatten(depth + 1, calls);
+        }
+    }
+}
+
+/// Traces `tx` against the latest block with the prestate tracer in diff mode.
+pub async fn state_diff<M: Middleware>(client: &M, tx: &TypedTransaction) -> anyhow::Result<StateDiff> {
+    let options = json!({ "tracer": "prestateTracer", "tracerConfig": { "diffMode": true } });
+    let params = (tx, "latest", options);
+    Ok(client.provider().request::<_, StateDiff>("debug_traceCall", params).await?)
+}
+
+/// Traces `tx` against the latest block with the call tracer, returning the
+/// logs it would emit.
+pub async fn logs<M: Middleware>(client: &M, tx: &TypedTransaction) -> anyhow::Result<Vec<CallLog>> {
+    let options = json!({ "tracer": "callTracer", "tracerConfig": { "withLog": true } });
+    let params = (tx, "latest", options);
+    let frame = client.provider().request::<_, CallFrame>("debug_traceCall", params).await?;
+    let mut logs = Vec::new();
+    frame.collect_logs(&mut logs);
+    Ok(logs)
+}
+
+/// The call tree of the mined transaction `hash`, from the call tracer.
+pub async fn call_tree<M: Middleware>(client: &M, hash: H256) -> anyhow::Result<Vec<InternalCall>> {
+    let options = json!({ "tracer": "callTracer" });
+    let frame = client.provider().request::<_, CallFrame>("debug_traceTransaction", (hash, options)).await?;
+    let mut calls = Vec::new();
+    frame.flatten(0, &mut calls);
+    Ok(calls)
+}
+
+/// One step of the struct logger, the node's default opcode-level tracer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Step {
+    pub op: String,
+    /// Gas left before the step.
+    pub gas: u64,
+    pub gas_cost: u64,
+    /// Call depth, starting at 1.
+    pub depth: u64,
+    /// The stack before the step, top last.
+    #[serde(default)]
+    pub stack: Vec<U256>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StructTrace {
+    gas: u64,
+    struct_logs: Vec<Step>,
+}
+
+/// A call or contract creation made while executing the transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallGas {
+    /// Depth of the calling frame, starting at 1.
+    pub depth: u64,
+    pub op: String,
+    /// The callee; unknown for creations.
+    pub target: Option<Address>,
+    /// Gas spent by the call, including everything it called in turn.
+    pub gas: u64,
+}
+
+/// Where a transaction's gas goes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GasProfile {
+    /// Gas used by the whole transaction, net of refunds.
+    pub total: u64,
+    /// Gas spent on execution by opcode group, not counting what called
+    /// contracts spend themselves.
+    pub groups: BTreeMap<&'static str, u64>,
+    pub calls: Vec<CallGas>,
+}
+
+impl GasProfile {
+    /// Builds the profile from the struct logger's steps.
+    pub fn from_steps(total: u64, steps: &[Step]) -> Self {
+        let mut profile = GasProfile { total, ..Default::default() };
+        for (i, step) in steps.iter().enumerate() {
+            let group = opcode_group(&step.op);
+            if group != "calls" {
+                *profile.groups.entry(group).or_default() += step.gas_cost;
+                continue;
+            }
+            // The reported cost of a call includes the gas it forwards, so
+            // measure it by the gas left once execution is back at this depth.
+            let Some(resumed) = steps[i + 1..].iter().position(|s| s.depth <= step.depth).map(|p| i + 1 + p) else {
+                *profile.groups.entry(group).or_default() += step.gas_cost;
+                continue;
+            };
+            let inclusive = step.gas.saturating_sub(steps[resumed].gas);
+            let callee = match steps.get(i + 1) {
+                Some(first) if first.depth > step.depth => {
+                    let last = &steps[resumed - 1];
+                    first.gas.saturating_sub(last.gas.saturating_sub(last.gas_cost))
+                }
+                // A call to an account without code, or one that failed
+                // before entering the callee.
+                _ => 0,
+            };
+            *profile.groups.entry(group).or_default() += inclusive.saturating_sub(callee);
+            let target = match step.op.as_str() {
+                "CREATE" | "CREATE2" | "SELFDESTRUCT" => None,
+                _ => step.stack.len().checked_sub(2).map(|i| Address::from(H256::from_uint(&step.stack[i]))),
+            };
+            profile.calls.push(CallGas { depth: step.depth, op: step.op.clone(), target, gas: inclusive });
+        }
+        profile
+    }
+}
+
+/// The group an opcode's gas is reported under.
+fn opcode_group(op: &str) -> &'static str {
+    match op {
+        "SLOAD" | "SSTORE" | "TLOAD" | "TSTORE" => "storage",
+        "CALL" | "CALLCODE" | "DELEGATECALL" | "STATICCALL" | "CREATE" | "CREATE2" | "SELFDESTRUCT" => "calls",
+        "LOG0" | "LOG1" | "LOG2" | "LOG3" | "LOG4" => "logs",
+        "KECCAK256" | "SHA3" => "hashing",
+        "BALANCE" | "SELFBALANCE" | "EXTCODESIZE" | "EXTCODEHASH" | "EXTCODECOPY" => "account access",
+        "MLOAD" | "MSTORE" | "MSTORE8" | "MCOPY" | "CALLDATACOPY" | "CODECOPY" | "RETURNDATACOPY" | "RETURN"
+        | "REVERT" => "memory",
+        _ => "computation",
+    }
+}
+
+/// Traces `tx` against the latest block with the struct logger and breaks
+/// its gas down by opcode group and internal call.
+pub async fn gas_profile<M: Middleware>(client: &M, tx: &TypedTransaction) -> anyhow::Result<GasProfile> {
+    let options = json!({ "disableStorage": true, "enableMemory": false, "enableReturnData": false });
+    let params = (tx, "latest", options);
+    let trace = client.provider().request::<_, StructTrace>("debug_traceCall", params).await?;
+    Ok(GasProfile::from_steps(trace.gas, &trace.struct_logs))
+}
+
+/// Net ERC-20 balance changes of `accounts`, by token and account, from the
+/// `Transfer` events in `logs`.
+pub fn token_changes(logs: &[CallLog], accounts: &[Address]) -> BTreeMap<(Address, Address), I256> {
+    let transfer = H256(keccak256("Transfer(address,address,uint256)"));
+    let mut changes: BTreeMap<(Address, Address), I256> = BTreeMap::new();
+    for log in logs {
+        // ERC-721 transfers share the signature but index the token id.
+        if log.topics.len() != 3 || log.topics[0] != transfer || log.data.len() != 32 {
+            continue;
+        }
+        let amount = I256::from_raw(U256::from_big_endian(&log.data));
+        let from = Address::from(log.topics[1]);
+        let to = Address::from(log.topics[2]);
+        for (account, delta) in [(from, -amount), (to, amount)] {
+            if accounts.contains(&account) {
+                *changes.entry((log.address, account)).or_default() += delta;
+            }
+        }
+    }
+    changes.retain(|_, delta| !delta.is_zero());
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balance_changes() {
+        let wallet = Address::repeat_byte(1);
+        let diff: StateDiff = serde_json::from_value(json!({
+            "pre": { format!("{:?}", wallet): { "balance": "0x64", "nonce": 3 } },
+            "post": { format!("{:?}", wallet): { "balance": "0x5a", "nonce": 4 } }
+        }))
+        .unwrap();
+        assert_eq!(diff.balance_change(wallet), I256::from(-10));
+        assert_eq!(diff.balance_change(Address::repeat_byte(2)), I256::zero());
+    }
+
+    #[test]
+    fn storage_changes() {
+        let contract = Address::repeat_byte(2);
+        let (one, two, three) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2), H256::from_low_u64_be(3));
+        let diff: StateDiff = serde_json::from_value(json!({
+            "pre": { format!("{:?}", contract): { "storage": { format!("{:?}", one): two, format!("{:?}", two): two } } },
+            "post": { format!("{:?}", contract): { "storage": { format!("{:?}", one): three, format!("{:?}", three): one } } }
+        }))
+        .unwrap();
+        // Slot 2 was cleared, so the post state leaves it out.
+        assert_eq!(
+            diff.storage_changes(contract),
+            vec![(one, two, three), (two, two, H256::zero()), (three, H256::zero(), one)]
+        );
+    }
+
+    #[test]
+    fn call_trees() {
+        let (contract, token) = (Address::repeat_byte(2), Address::repeat_byte(0x70));
+        let frame: CallFrame = serde_json::from_value(json!({
+            "type": "CALL", "to": contract, "input": "0x12345678aa", "value": "0x0", "gasUsed": "0x7530",
+            "error": "execution reverted", "revertReason": "transfer failed",
+            "calls": [{ "type": "STATICCALL", "to": token, "input": "0x70a08231", "gasUsed": "0xa28" },
+                      { "type": "CALL", "to": token, "input": "0x", "gasUsed": "0x1388", "error": "execution reverted" }]
+        }))
+        .unwrap();
+        let mut calls = Vec::new();
+        frame.flatten(0, &mut calls);
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].selector, Some([0x12, 0x34, 0x56, 0x78]));
+        assert_eq!(calls[0].gas_used, 30_000);
+        assert_eq!(calls[0].error.as_deref(), Some("execution reverted: transfer failed"));
+        assert_eq!((calls[1].depth, calls[1].kind.as_str(), calls[1].to), (1, "STATICCALL", Some(token)));
+        assert_eq!((calls[2].selector, calls[2].error.as_deref()), (None, Some("execution reverted")));
+    }
+
+    fn step(op: &str, gas: u64, gas_cost: u64, depth: u64) -> Step {
+        Step { op: op.to_string(), gas, gas_cost, depth, stack: Vec::new() }
+    }
+
+    #[test]
+    fn gas_profile() {
+        let token = Address::repeat_byte(0x70);
+        // A cold call costs 2,600 and forwards 8,000 gas, reported together.
+        let mut call = step("CALL", 20_000, 10_600, 1);
+        call.stack = vec![U256::zero(), H256::from(token).into_uint(), U256::from(8_000)];
+        let steps = [
+            step("SLOAD", 22_100, 2_100, 1),
+            call,
+            // The callee spends 5,003 of what it was given.
+            step("PUSH1", 8_000, 3, 2),
+            step("SSTORE", 7_997, 5_000, 2),
+            step("STOP", 2_997, 0, 2),
+            // The 2,997 it didn't spend comes back.
+            step("POP", 12_397, 2, 1),
+            step("STOP", 12_395, 0, 1),
+        ];
+        let profile = GasProfile::from_steps(30_000, &steps);
+        assert_eq!(profile.total, 30_000);
+        assert_eq!(profile.groups["storage"], 7_100);
+        assert_eq!(profile.groups["computation"], 5);
+        // The call's own cost, without what the callee spent.
+        assert_eq!(profile.groups["calls"], 2_600);
+        assert_eq!(profile.calls, vec![CallGas { depth: 1, op: "CALL".into(), target: Some(token), gas: 7_603 }]);
+    }
+
+    #[test]
+    fn transfer_changes() {
+        let (token, user, contract) = (Address::repeat_byte(0x70), Address::repeat_byte(1), Address::repeat_byte(2));
+        let transfer = H256(keccak256("Transfer(address,address,uint256)"));
+        let log = CallLog {
+            address: token,
+            topics: vec![transfer, H256::from(user), H256::from(contract)],
+            data: Bytes::from(H256::from_low_u64_be(1500).as_bytes().to_vec()),
+        };
+        let changes = token_changes(&[log], &[user, contract]);
+        assert_eq!(changes[&(token, user)], I256::from(-1500));
+        assert_eq!(changes[&(token, contract)], I256::from(1500));
+    }
+}
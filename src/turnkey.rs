@@ -0,0 +1,181 @@
+//! Turnkey: transaction hashes signed by a Turnkey wallet account through
+//! the `SIGN_RAW_PAYLOAD` activity. Calls are stamped with an API key pair;
+//! an activity that needs consensus under the organization's policies is
+//! polled until it is signed, rejected or TURNKEY_APPROVAL_TIMEOUT (default
+//! 1h) passes.
+//!
+//! Settings: TURNKEY_ORGANIZATION_ID, TURNKEY_API_PRIVATE_KEY (a secret
+//! setting: the API key's P-256 private key, hex), TURNKEY_SIGN_WITH (the
+//! wallet account address or private key id; default the sender) and
+//! TURNKEY_API_URL (default `https://api.turnkey.com`).
+
+use crate::config::{self, env_var, var};
+use anyhow::Context;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ethers::prelude::*;
+use p256::ecdsa::signature::Signer as _;
+use p256::ecdsa::{DerSignature, SigningKey};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often an activity waiting on consensus is polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Turnkey {
+    url: String,
+    organization_id: String,
+    api_key: SigningKey,
+    sign_with: Option<String>,
+    timeout: Duration,
+    client: reqwest::Client,
+}
+
+/// Where an activity stands.
+#[derive(Debug, PartialEq)]
+enum Progress {
+    /// Still running or waiting on consensus, with its status.
+    Pending(String),
+    Signed(Signature),
+    /// Rejected or failed, with the status.
+    Refused(String),
+}
+
+impl Turnkey {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let api_key = config::secret("TURNKEY_API_PRIVATE_KEY")?;
+        let api_key = hex::decode(api_key.trim().trim_start_matches("0x"))
+            .ok()
+            .and_then(|bytes| SigningKey::from_slice(&bytes).ok())
+            .context("TURNKEY_API_PRIVATE_KEY is not a P-256 private key in hex")?;
+        let timeout = match env_var("TURNKEY_APPROVAL_TIMEOUT") {
+            Some(timeout) => config::parse_duration(&timeout).context("invalid TURNKEY_APPROVAL_TIMEOUT")?,
+            None => Duration::from_secs(3600),
+        };
+        Ok(Self {
+            url: env_var("TURNKEY_API_URL").unwrap_or_else(|| "https://api.turnkey.com".to_string()),
+            organization_id: var("TURNKEY_ORGANIZATION_ID")?,
+            api_key,
+            sign_with: env_var("TURNKEY_SIGN_WITH"),
+            timeout,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Has the wallet account of `from` (or TURNKEY_SIGN_WITH) sign `hash`
+    /// and waits for the signature.
+    pub async fn sign_hash(&self, hash: H256, from: Address) -> anyhow::Result<Signature> {
+        let body = json!({
+            "type": "ACTIVITY_TYPE_SIGN_RAW_PAYLOAD_V2",
+            "timestampMs": SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis().to_string(),
+            "organizationId": self.organization_id,
+            "parameters": {
+                "signWith": self.sign_with.clone().unwrap_or_else(|| format!("{:?}", from)),
+                "payload": hex::encode(hash),
+                "encoding": "PAYLOAD_ENCODING_HEXADECIMAL",
+                "hashFunction": "HASH_FUNCTION_NO_OP",
+            },
+        });
+        let mut response = self.request("/public/v1/submit/sign_raw_payload", &body).await?;
+        let id = response["activity"]["id"].as_str().context("Turnkey returned no activity id")?.to_string();
+        println!("Turnkey activity {} submitted", id);
+
+        let started = Instant::now();
+        let mut last = String::new();
+        loop {
+            match progress(&response["activity"])? {
+                Progress::Signed(signature) => return Ok(signature),
+                Progress::Refused(status) => anyhow::bail!("Turnkey activity {} ended {}", id, status),
+                Progress::Pending(status) => {
+                    if status != last {
+                        println!("Turnkey activity {}: {}", id, status);
+                        last = status;
+                    }
+                }
+            }
+            anyhow::ensure!(
+                started.elapsed() < self.timeout,
+                "Turnkey activity {} is still {} after {}s",
+                id,
+                last,
+                self.timeout.as_secs()
+            );
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let query = json!({ "organizationId": self.organization_id, "activityId": id });
+            response = self.request("/public/v1/query/get_activity", &query).await?;
+        }
+    }
+
+    /// Makes a stamped API call, returning its JSON response.
+    async fn request(&self, path: &str, body: &Value) -> anyhow::Result<Value> {
+        let body = body.to_string();
+        let response = self
+            .client
+            .post(format!("{}{}", self.url, path))
+            .header("X-Stamp", stamp(&self.api_key, &body))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("Turnkey API {} unreachable", self.url))?;
+        Ok(response.error_for_status()?.json().await?)
+    }
+}
+
+/// The `X-Stamp` header authenticating `body`: the API public key and its
+/// ECDSA signature over the body, as base64url JSON.
+fn stamp(key: &SigningKey, body: &str) -> String {
+    let signature: DerSignature = key.sign(body.as_bytes());
+    let stamp = json!({
+        "publicKey": hex::encode(key.verifying_key().to_encoded_point(true).as_bytes()),
+        "scheme": "SIGNATURE_SCHEME_TK_API_P256",
+        "signature": hex::encode(signature.as_bytes()),
+    });
+    URL_SAFE_NO_PAD.encode(stamp.to_string())
+}
+
+/// Reads an activity's progress.
+fn progress(activity: &Value) -> anyhow::Result<Progress> {
+    let status = activity["status"].as_str().context("Turnkey returned an activity without a status")?;
+    match status {
+        "ACTIVITY_STATUS_COMPLETED" => {
+            let result = &activity["result"]["signRawPayloadResult"];
+            let part = |name: &str| -> anyhow::Result<U256> {
+                let value = result[name].as_str().with_context(|| format!("the signature has no {}", name))?;
+                Ok(U256::from_str_radix(value, 16)?)
+            };
+            Ok(Progress::Signed(Signature { r: part("r")?, s: part("s")?, v: part("v")?.as_u64() }))
+        }
+        "ACTIVITY_STATUS_FAILED" | "ACTIVITY_STATUS_REJECTED" => {
+            Ok(Progress::Refused(status.trim_start_matches("ACTIVITY_STATUS_").to_lowercase()))
+        }
+        _ => Ok(Progress::Pending(status.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Verifier as _;
+
+    #[test]
+    fn stamps_and_activities() {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let stamp = URL_SAFE_NO_PAD.decode(stamp(&key, "{}")).unwrap();
+        let stamp: Value = serde_json::from_slice(&stamp).unwrap();
+        assert_eq!(stamp["publicKey"].as_str().unwrap().len(), 66);
+        let signature = DerSignature::from_bytes(&hex::decode(stamp["signature"].as_str().unwrap()).unwrap()).unwrap();
+        key.verifying_key().verify(b"{}", &signature).unwrap();
+
+        let pending = json!({ "id": "1", "status": "ACTIVITY_STATUS_CONSENSUS_NEEDED" });
+        assert_eq!(progress(&pending).unwrap(), Progress::Pending("ACTIVITY_STATUS_CONSENSUS_NEEDED".to_string()));
+        let rejected = json!({ "id": "1", "status": "ACTIVITY_STATUS_REJECTED" });
+        assert_eq!(progress(&rejected).unwrap(), Progress::Refused("rejected".to_string()));
+        let completed = json!({
+            "id": "1",
+            "status": "ACTIVITY_STATUS_COMPLETED",
+            "result": { "signRawPayloadResult": { "r": "0a", "s": "0b", "v": "01" } },
+        });
+        assert_eq!(progress(&completed).unwrap(), Progress::Signed(Signature { r: 10.into(), s: 11.into(), v: 1 }));
+    }
+}
@@ -0,0 +1,157 @@
+//! Conversion between base-unit integers (wei, or a token's smallest unit)
+//! and decimal text for any number of decimals. Scaling is done on the
+//! digits, never through floating point, so `1.1` with 18 decimals is exactly
+//! 1100000000000000000; fraction digits beyond `decimals` are handled by an
+//! explicit [`Rounding`] rather than silently dropped.
+
+use anyhow::Context;
+use ethers::types::U256;
+
+/// What to do with fraction digits finer than the smallest unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Refuse them unless they are all zero.
+    Exact,
+    /// Drop them.
+    Down,
+    /// Round up when any of them isn't zero.
+    Up,
+    /// Round to the nearest unit, halves up.
+    HalfUp,
+}
+
+/// Parses a non-negative decimal such as `1.5`, `.25` or `1000` into base
+/// units with `decimals` decimals.
+pub fn parse_decimal(input: &str, decimals: u8, rounding: Rounding) -> anyhow::Result<U256> {
+    let input = input.trim();
+    let (whole, fraction) = input.split_once('.').unwrap_or((input, ""));
+    anyhow::ensure!(!whole.is_empty() || !fraction.is_empty(), "invalid amount {:?}: no digits", input);
+    anyhow::ensure!(
+        whole.bytes().chain(fraction.bytes()).all(|byte| byte.is_ascii_digit()),
+        "invalid amount {:?}: expected a non-negative decimal number",
+        input
+    );
+    let (kept, dropped) = fraction.split_at(fraction.len().min(decimals as usize));
+    let digits = format!("{}{}{}", whole, kept, "0".repeat(decimals as usize - kept.len()));
+    let digits = digits.trim_start_matches('0');
+    let too_large = || format!("amount {:?} with {} decimals is too large", input, decimals);
+    let amount = match digits.is_empty() {
+        true => U256::zero(),
+        false => U256::from_dec_str(digits).ok().with_context(too_large)?,
+    };
+    let round_up = match rounding {
+        Rounding::Down => false,
+        Rounding::Exact => {
+            anyhow::ensure!(
+                dropped.bytes().all(|byte| byte == b'0'),
+                "amount {:?} has more than {} decimals",
+                input,
+                decimals
+            );
+            false
+        }
+        Rounding::Up => dropped.bytes().any(|byte| byte != b'0'),
+        Rounding::HalfUp => dropped.bytes().next().is_some_and(|byte| byte >= b'5'),
+    };
+    match round_up {
+        true => amount.checked_add(U256::one()).with_context(too_large),
+        false => Ok(amount),
+    }
+}
+
+/// `amount` base units with `decimals` decimals as an exact decimal, without
+/// trailing fraction zeros: `1.5`, `1000`, `0.000001`.
+pub fn format_decimal(amount: U256, decimals: u8) -> String {
+    let decimals = decimals as usize;
+    let digits = format!("{:0>width$}", amount.to_string(), width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    match fraction.trim_end_matches('0') {
+        "" => whole.to_string(),
+        fraction => format!("{}.{}", whole, fraction),
+    }
+}
+
+/// A decimal setting given as a float, e.g. a gwei price from the config
+/// file, in base units with `decimals` decimals, to the nearest unit.
+pub fn from_f64(amount: f64, decimals: u8) -> anyhow::Result<U256> {
+    anyhow::ensure!(amount.is_finite() && amount >= 0.0, "invalid amount {}", amount);
+    // A float's Display is its shortest exact decimal, never in exponent form.
+    parse_decimal(&amount.to_string(), decimals, Rounding::HalfUp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::utils::{format_units, parse_units};
+    use proptest::prelude::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(parse_decimal("1.5", 6, Rounding::Exact).unwrap(), 1_500_000.into());
+        assert_eq!(parse_decimal(".25", 2, Rounding::Exact).unwrap(), 25.into());
+        assert_eq!(parse_decimal("1.", 0, Rounding::Exact).unwrap(), 1.into());
+        assert_eq!(parse_decimal("1.10", 1, Rounding::Exact).unwrap(), 11.into());
+        assert_eq!(parse_decimal("1.1", 18, Rounding::Exact).unwrap(), U256::exp10(17) * 11);
+        assert!(parse_decimal("1.25", 1, Rounding::Exact).is_err());
+        assert_eq!(parse_decimal("1.25", 1, Rounding::Down).unwrap(), 12.into());
+        assert_eq!(parse_decimal("1.21", 1, Rounding::Up).unwrap(), 13.into());
+        assert_eq!(parse_decimal("1.25", 1, Rounding::HalfUp).unwrap(), 13.into());
+        assert_eq!(parse_decimal("1.249", 1, Rounding::HalfUp).unwrap(), 12.into());
+        for invalid in ["", ".", "-1", "1e18", "1,000", "0x10", "1.2.3"] {
+            assert!(parse_decimal(invalid, 18, Rounding::Down).is_err(), "{:?}", invalid);
+        }
+        assert!(parse_decimal("1", 78, Rounding::Exact).is_err());
+        assert_eq!(parse_decimal(&U256::MAX.to_string(), 0, Rounding::Exact).unwrap(), U256::MAX);
+        assert!(parse_decimal(&format!("{}.9", U256::MAX), 0, Rounding::Up).is_err());
+
+        assert_eq!(format_decimal(1_500_000.into(), 6), "1.5");
+        assert_eq!(format_decimal(1.into(), 6), "0.000001");
+        assert_eq!(format_decimal(1000.into(), 0), "1000");
+        assert_eq!(format_decimal(U256::zero(), 18), "0");
+        assert_eq!(from_f64(0.1 + 0.2, 9).unwrap(), 300_000_000.into());
+        assert_eq!(from_f64(1.5, 9).unwrap(), 1_500_000_000u64.into());
+        assert!(from_f64(-1.0, 9).is_err());
+    }
+
+    fn amounts() -> impl Strategy<Value = U256> {
+        any::<[u64; 4]>().prop_map(U256)
+    }
+
+    proptest! {
+        #[test]
+        fn formatting_round_trips(amount in amounts(), decimals in 0u8..=96) {
+            let text = format_decimal(amount, decimals);
+            prop_assert_eq!(parse_decimal(&text, decimals, Rounding::Exact).unwrap(), amount);
+        }
+
+        #[test]
+        fn rounding_brackets_the_exact_value(amount in amounts(), extra in 1u8..8, decimals in 0u8..=60) {
+            // `amount` with `extra` more decimals than the unit allows.
+            let text = format_decimal(amount, decimals + extra);
+            let scale = U256::exp10(extra as usize);
+            let down = parse_decimal(&text, decimals, Rounding::Down).unwrap();
+            prop_assert_eq!(down, amount / scale);
+            let exact = amount % scale == U256::zero();
+            prop_assert_eq!(parse_decimal(&text, decimals, Rounding::Exact).is_ok(), exact);
+            if let Ok(up) = parse_decimal(&text, decimals, Rounding::Up) {
+                prop_assert_eq!(up, if exact { down } else { down + 1 });
+            }
+            if let Ok(nearest) = parse_decimal(&text, decimals, Rounding::HalfUp) {
+                prop_assert_eq!(nearest, if amount % scale * 2 >= scale { down + 1 } else { down });
+            }
+        }
+
+        #[test]
+        fn agrees_with_ethers(amount in any::<u128>(), decimals in 0u8..=18) {
+            let ours = format_decimal(amount.into(), decimals);
+            let theirs = format_units(U256::from(amount), decimals as u32).unwrap();
+            let theirs = match theirs.contains('.') {
+                true => theirs.trim_end_matches('0').trim_end_matches('.'),
+                false => &theirs,
+            };
+            prop_assert_eq!(ours.as_str(), theirs);
+            let parsed: U256 = parse_units(&ours, decimals as u32).unwrap().into();
+            prop_assert_eq!(parsed, U256::from(amount));
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Watch-only sending, for wallets whose key lives in an external signing
+//! service. `--unsigned` takes a call through every preflight as usual, then
+//! writes the filled-in transaction to a file instead of signing it: as JSON,
+//! as its EIP-2718 payload and as the hash to sign. `broadcast` merges the
+//! signature the service returns, or checks the transaction it signed, and
+//! sends the result.
+
+use crate::config::{Config, Job};
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::rlp::Rlp;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// A prepared transaction waiting for its signature.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UnsignedTx {
+    /// Subcommand the call came from, e.g. `lock`.
+    pub kind: String,
+    pub job: Job,
+    pub chain_id: u64,
+    pub contract: Address,
+    /// The address that must sign.
+    pub from: Address,
+    /// Whether the signature commits to the chain id; only legacy
+    /// transactions on chains with `eip155 = false` don't.
+    pub replay_protected: bool,
+    /// The transaction in its JSON-RPC form, which leaves out the chain id.
+    pub tx: TypedTransaction,
+    /// The unsigned EIP-2718 encoding of the transaction.
+    pub payload: Bytes,
+    /// keccak256 of `payload`, the hash to sign.
+    pub sighash: H256,
+}
+
+impl UnsignedTx {
+    /// Wraps `tx`, which must be filled in (sender, nonce, gas and fees),
+    /// for signing. Without replay protection `tx` must be legacy, and its
+    /// chain id is dropped.
+    pub fn new(
+        kind: &str,
+        config: &Config,
+        job: &Job,
+        mut tx: TypedTransaction,
+        replay_protected: bool,
+    ) -> anyhow::Result<Self> {
+        let from = *tx.from().context("the transaction has no sender")?;
+        anyhow::ensure!(tx.nonce().is_some(), "the transaction has no nonce");
+        anyhow::ensure!(tx.gas().is_some(), "the transaction has no gas limit");
+        match replay_protected {
+            true => {
+                tx.set_chain_id(config.chain_id);
+            }
+            false => {
+                let TypedTransaction::Legacy(request) = &mut tx else {
+                    anyhow::bail!("only legacy transactions can be signed without EIP-155 replay protection");
+                };
+                request.chain_id = None;
+            }
+        }
+        Ok(Self {
+            kind: kind.to_string(),
+            job: job.clone(),
+            chain_id: config.chain_id,
+            contract: config.contract_address,
+            from,
+            replay_protected,
+            payload: tx.rlp(),
+            sighash: tx.sighash(),
+            tx,
+        })
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("{} is not an unsigned transaction", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The transaction with its chain id restored.
+    pub fn transaction(&self) -> TypedTransaction {
+        let mut tx = self.tx.clone();
+        if self.replay_protected {
+            tx.set_chain_id(self.chain_id);
+        }
+        tx
+    }
+
+    /// Checks the payload and hash against the transaction, so an edited
+    /// file can't get a signature for one thing and broadcast another.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        let tx = self.transaction();
+        anyhow::ensure!(tx.from() == Some(&self.from), "the transaction is not from {:?}", self.from);
+        anyhow::ensure!(tx.rlp() == self.payload, "the payload does not match the transaction");
+        anyhow::ensure!(
+            tx.sighash() == self.sighash,
+            "the signing hash {:?} does not match the transaction (expected {:?})",
+            self.sighash,
+            tx.sighash()
+        );
+        Ok(())
+    }
+
+    /// The signed transaction, ready to broadcast. `signature` may carry any
+    /// of the usual `v` forms (0/1, 27/28 or EIP-155); it must recover to
+    /// the sender.
+    pub fn merge(&self, signature: Signature) -> anyhow::Result<Bytes> {
+        self.verify()?;
+        let tx = self.transaction();
+        let parity = match signature.v {
+            0 | 1 => signature.v,
+            27 | 28 => signature.v - 27,
+            v if v >= 35 => (v - 35) % 2,
+            v => anyhow::bail!("invalid signature v value {}", v),
+        };
+        let v = match (&tx, tx.chain_id()) {
+            (TypedTransaction::Legacy(_), Some(chain_id)) => parity + chain_id.as_u64() * 2 + 35,
+            (TypedTransaction::Legacy(_), None) => parity + 27,
+            _ => parity,
+        };
+        let signature = Signature { v, ..signature };
+        let signer = signature.recover(self.sighash).context("invalid signature")?;
+        anyhow::ensure!(signer == self.from, "the signature is by {:?}, not the sender {:?}", signer, self.from);
+        Ok(tx.rlp_signed(&signature))
+    }
+
+    /// Checks that `raw`, a transaction the signer built and signed itself
+    /// from the JSON form, is exactly this transaction signed by the sender.
+    pub fn check_signed(&self, raw: &[u8]) -> anyhow::Result<()> {
+        self.verify()?;
+        let (tx, signature) = TypedTransaction::decode_signed(&Rlp::new(raw)).context("invalid signed transaction")?;
+        anyhow::ensure!(
+            tx.sighash() == self.sighash,
+            "the signer changed the transaction: it signed {:?}, not {:?}",
+            tx.sighash(),
+            self.sighash
+        );
+        let signer = signature.recover(self.sighash).context("invalid signature")?;
+        anyhow::ensure!(signer == self.from, "the signature is by {:?}, not the sender {:?}", signer, self.from);
+        Ok(())
+    }
+}
+
+/// `tx` as remote signers' and wallets' `eth_signTransaction` takes it: the
+/// JSON-RPC form, plus the chain id, which ethers leaves out of it.
+pub fn json_rpc(tx: &TypedTransaction) -> anyhow::Result<serde_json::Value> {
+    let mut params = serde_json::to_value(tx)?;
+    if let Some(chain_id) = tx.chain_id() {
+        params["chainId"] = format!("{:#x}", chain_id).into();
+    }
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config { rpc_url: String::new(), chain_id: 5, contract_address: Address::repeat_byte(9) }
+    }
+
+    fn job() -> Job {
+        Job {
+            user: Address::repeat_byte(1),
+            token: Address::repeat_byte(2),
+            amount: 1000.into(),
+            nonce: 7.into(),
+            signature: Bytes::default(),
+        }
+    }
+
+    #[test]
+    fn merging_signatures() {
+        let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::repeat_byte(9))
+            .nonce(3)
+            .gas(100_000)
+            .max_fee_per_gas(2)
+            .max_priority_fee_per_gas(1)
+            .into();
+        let unsigned = UnsignedTx::new("lock", &config(), &job(), tx, true).unwrap();
+        let unsigned: UnsignedTx = serde_json::from_str(&serde_json::to_string(&unsigned).unwrap()).unwrap();
+        unsigned.verify().unwrap();
+        assert_eq!(unsigned.payload[0], 2);
+
+        let raw = unsigned.merge(wallet.sign_hash(unsigned.sighash).unwrap()).unwrap();
+        let (signed, signature) = TypedTransaction::decode_signed(&Rlp::new(&raw)).unwrap();
+        assert_eq!(signed.chain_id(), Some(5.into()));
+        assert_eq!(signature.recover(signed.sighash()).unwrap(), wallet.address());
+        unsigned.check_signed(&raw).unwrap();
+        let mut other = unsigned.transaction();
+        other.set_nonce(4);
+        let other = other.rlp_signed(&wallet.sign_transaction_sync(&other).unwrap());
+        assert!(unsigned.check_signed(&other).is_err());
+
+        let stranger = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        assert!(unsigned.merge(stranger.sign_hash(unsigned.sighash).unwrap()).is_err());
+        let mut edited = unsigned.clone();
+        edited.tx.set_value(1);
+        assert!(edited.verify().is_err());
+    }
+
+    #[test]
+    fn legacy_signatures() {
+        let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let tx: TypedTransaction =
+            TransactionRequest::new().from(wallet.address()).to(Address::repeat_byte(9)).nonce(3).gas(21_000).into();
+        for (replay_protected, expected) in [(true, [45, 46]), (false, [27, 28])] {
+            let unsigned = UnsignedTx::new("lock", &config(), &job(), tx.clone(), replay_protected).unwrap();
+            let raw = unsigned.merge(wallet.sign_hash(unsigned.sighash).unwrap()).unwrap();
+            let (_, signature) = TransactionRequest::decode_signed_rlp(&Rlp::new(&raw)).unwrap();
+            assert!(expected.contains(&signature.v), "{}", signature.v);
+        }
+    }
+
+    #[test]
+    fn json_rpc_form() {
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .from(Address::repeat_byte(1))
+            .to(Address::repeat_byte(2))
+            .nonce(3)
+            .max_fee_per_gas(2)
+            .chain_id(8453)
+            .into();
+        let params = json_rpc(&tx).unwrap();
+        assert_eq!(params["chainId"], "0x2105");
+        assert_eq!(params["type"], "0x02");
+        assert_eq!(params["from"], format!("{:?}", Address::repeat_byte(1)));
+        assert_eq!(params["nonce"], "0x3");
+    }
+}
@@ -0,0 +1,151 @@
+//! Upgrade detection: the contract's implementation (for a proxy) and code
+//! hash are remembered per chain and address, and a change between runs is
+//! reported before anything is sent, so an upgrade under us never goes
+//! unnoticed.
+
+use crate::bytecode;
+use crate::config::{self, Config};
+use crate::error::Error;
+use crate::print_warn;
+use crate::style;
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// What the contract's calls run: the implementation behind a proxy, or the
+/// contract's own code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub implementation: Option<Address>,
+    /// keccak256 of the implementation's code, or of the contract's own.
+    pub code_hash: H256,
+}
+
+impl Snapshot {
+    /// How `self` differs from `previous`, e.g. `implementation 0x1… -> 0x2…`.
+    pub fn describe_change(&self, previous: &Snapshot) -> String {
+        let mut changes = Vec::new();
+        if self.implementation != previous.implementation {
+            let name = |implementation: Option<Address>| match implementation {
+                Some(address) => format!("{:?}", address),
+                None => "none".to_string(),
+            };
+            changes.push(format!("implementation {} -> {}", name(previous.implementation), name(self.implementation)));
+        }
+        if self.code_hash != previous.code_hash {
+            changes.push(format!("code hash {:?} -> {:?}", previous.code_hash, self.code_hash));
+        }
+        changes.join(", ")
+    }
+}
+
+/// Reads the contract's current [`Snapshot`].
+pub async fn snapshot<M: Middleware>(client: &M, address: Address) -> anyhow::Result<Snapshot>
+where
+    M::Error: 'static,
+{
+    let implementation = bytecode::resolve_proxy(client, address).await?.map(|proxy| proxy.implementation);
+    let code = client.get_code(implementation.unwrap_or(address), None).await?;
+    Ok(Snapshot { implementation, code_hash: H256(keccak256(&code)) })
+}
+
+/// The snapshots of earlier runs, keyed by chain id and address.
+pub struct CodeCache {
+    path: PathBuf,
+}
+
+impl CodeCache {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The cache at `code-cache.json` in the state directory.
+    pub fn from_env() -> Self {
+        Self::new(config::state_dir().join("code-cache.json"))
+    }
+
+    fn load(&self) -> anyhow::Result<BTreeMap<String, Snapshot>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text).with_context(|| format!("corrupt code cache {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e).with_context(|| format!("failed to read code cache {}", self.path.display())),
+        }
+    }
+
+    pub fn get(&self, chain_id: u64, address: Address) -> anyhow::Result<Option<Snapshot>> {
+        Ok(self.load()?.get(&key(chain_id, address)).copied())
+    }
+
+    /// Records `snapshot`, replacing the file in one rename so a crash can't
+    /// leave it half written.
+    pub fn store(&self, chain_id: u64, address: Address, snapshot: Snapshot) -> anyhow::Result<()> {
+        let mut snapshots = self.load()?;
+        snapshots.insert(key(chain_id, address), snapshot);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(&snapshots)?)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+fn key(chain_id: u64, address: Address) -> String {
+    format!("{}:{:?}", chain_id, address)
+}
+
+/// Compares the contract with the snapshot of the previous run. A change is
+/// refused with [`Error::ContractUpgraded`] unless `acknowledge`d, in which
+/// case the new snapshot is remembered. The first run just records it.
+pub async fn check<M: Middleware>(client: &M, config: &Config, acknowledge: bool) -> anyhow::Result<()>
+where
+    M::Error: 'static,
+{
+    let cache = CodeCache::from_env();
+    let current = snapshot(client, config.contract_address).await?;
+    match cache.get(config.chain_id, config.contract_address)? {
+        Some(previous) if previous == current => Ok(()),
+        Some(previous) => {
+            let change = current.describe_change(&previous);
+            if !acknowledge {
+                return Err(Error::ContractUpgraded { address: config.contract_address, change }.into());
+            }
+            print_warn!("Contract {:?} changed since the last run: {}", config.contract_address, change);
+            style::emit("Continuing because of --acknowledge-upgrade");
+            style::emit("");
+            cache.store(config.chain_id, config.contract_address, current)
+        }
+        None => cache.store(config.chain_id, config.contract_address, current),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_round_trip() {
+        let path = std::env::temp_dir().join(format!("code-cache-test-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let cache = CodeCache::new(&path);
+        let contract = Address::repeat_byte(1);
+        assert_eq!(cache.get(1, contract).unwrap(), None);
+
+        let before = Snapshot { implementation: Some(Address::repeat_byte(2)), code_hash: H256::repeat_byte(3) };
+        cache.store(1, contract, before).unwrap();
+        assert_eq!(cache.get(1, contract).unwrap(), Some(before));
+        assert_eq!(cache.get(5, contract).unwrap(), None);
+
+        let after = Snapshot { implementation: Some(Address::repeat_byte(4)), code_hash: H256::repeat_byte(3) };
+        let change = after.describe_change(&before);
+        assert!(change.starts_with("implementation 0x0202"), "{}", change);
+        assert!(!change.contains("code hash"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+//! Time-bounded signatures (permit deadlines, EIP-3009 authorization
+//! windows, session key expiries) checked against the chain's clock rather
+//! than ours: the latest block's timestamp, which is what the contract
+//! compares them with, however far off the local clock is. The transaction
+//! lands in a later block, so a window must also stay open for
+//! CLOCK_SKEW_TOLERANCE (default 30s) beyond it.
+
+use crate::config::{self, env_var};
+use crate::error::Error;
+use crate::print_ok;
+use crate::schedule::{self, Schedule};
+use anyhow::Context;
+use ethers::prelude::*;
+use std::time::Duration;
+
+/// CLOCK_SKEW_TOLERANCE, or 30 seconds.
+pub fn tolerance() -> anyhow::Result<Duration> {
+    match env_var("CLOCK_SKEW_TOLERANCE") {
+        Some(value) => config::parse_duration(&value).context("invalid CLOCK_SKEW_TOLERANCE"),
+        None => Ok(Duration::from_secs(30)),
+    }
+}
+
+/// The latest block's timestamp, in Unix seconds.
+pub async fn chain_time<M: Middleware>(client: &M) -> anyhow::Result<u64>
+where
+    M::Error: 'static,
+{
+    let block = client.get_block(BlockNumber::Latest).await?.context("the node returned no latest block")?;
+    Ok(block.timestamp.as_u64())
+}
+
+/// When a signature is valid: after `valid_after` and before `valid_until`,
+/// both Unix seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub valid_after: u64,
+    pub valid_until: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validity {
+    /// Valid for this many more seconds.
+    Valid(u64),
+    /// Opens in this many seconds.
+    NotYetValid(u64),
+    /// Closes in this many seconds, closer to now than the tolerance.
+    ExpiresSoon(u64),
+    /// Closed this many seconds ago.
+    Expired(u64),
+}
+
+impl Window {
+    /// A window open from the start until `valid_until`.
+    pub fn until(valid_until: u64) -> Self {
+        Self { valid_after: 0, valid_until }
+    }
+
+    /// Where the window stands at chain time `now`, needing to stay open for
+    /// `tolerance` more seconds.
+    pub fn check(&self, now: u64, tolerance: u64) -> Validity {
+        if self.valid_until <= now {
+            return Validity::Expired(now - self.valid_until);
+        }
+        if self.valid_after > now {
+            return Validity::NotYetValid(self.valid_after - now);
+        }
+        match self.valid_until - now {
+            left if left <= tolerance => Validity::ExpiresSoon(left),
+            left => Validity::Valid(left),
+        }
+    }
+}
+
+/// Checks the `what` signature's window against the chain's clock, failing
+/// with how far off it is unless it is comfortably open, and with
+/// [`Error::SignatureExpired`] once it has closed. Returns the chain time it
+/// checked against.
+pub async fn preflight<M: Middleware>(client: &M, what: &str, window: Window) -> anyhow::Result<u64>
+where
+    M::Error: 'static,
+{
+    let tolerance = tolerance()?.as_secs();
+    let now = chain_time(client).await?;
+    match window.check(now, tolerance) {
+        Validity::Valid(left) => {
+            print_ok!("{} valid for another {} seconds by the chain's clock", what, left);
+            Ok(now)
+        }
+        Validity::NotYetValid(opens_in) => anyhow::bail!(
+            "{} is not yet valid: it opens in {} seconds by the chain's clock",
+            what,
+            opens_in
+        ),
+        Validity::ExpiresSoon(left) => anyhow::bail!(
+            "{} expires in {} seconds by the chain's clock, within the {}s clock-skew tolerance",
+            what,
+            left,
+            tolerance
+        ),
+        Validity::Expired(ago) => Err(Error::SignatureExpired { what: what.to_lowercase(), ago }.into()),
+    }
+}
+
+/// Waits, polling the latest block every `interval`, until the `what`
+/// signature's window has opened by the chain's clock.
+pub async fn wait_until_open<M: Middleware>(
+    client: &M,
+    what: &str,
+    window: Window,
+    interval: Duration,
+) -> anyhow::Result<()>
+where
+    M::Error: 'static,
+{
+    let now = chain_time(client).await?;
+    if let Validity::NotYetValid(opens_in) = window.check(now, tolerance()?.as_secs()) {
+        println!("{} opens in {} seconds by the chain's clock", what, opens_in);
+        // Any later block is past `valid_after`, which is what the contract wants.
+        schedule::wait_until(client, Schedule::Timestamp(window.valid_after), interval).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows() {
+        let window = Window { valid_after: 1_000, valid_until: 2_000 };
+        assert_eq!(window.check(1_500, 30), Validity::Valid(500));
+        assert_eq!(window.check(990, 30), Validity::NotYetValid(10));
+        assert_eq!(window.check(1_000, 30), Validity::Valid(1_000));
+        // Open, but not for long enough to be sure it's mined in time.
+        assert_eq!(window.check(1_980, 30), Validity::ExpiresSoon(20));
+        assert_eq!(window.check(1_970, 30), Validity::ExpiresSoon(30));
+        assert_eq!(window.check(2_000, 30), Validity::Expired(0));
+        assert_eq!(window.check(2_100, 30), Validity::Expired(100));
+        assert_eq!(Window::until(2_000).check(100, 30), Validity::Valid(1_900));
+    }
+}
@@ -0,0 +1,264 @@
+//! Source verification of a deployed contract on the chain's block explorer,
+//! so the explorer shows its code and ABI.
+//!
+//! Verification goes through the Etherscan API (ETHERSCAN_API_URL, by
+//! default the multichain v2 endpoint, which picks the explorer by chain id)
+//! with the secret setting ETHERSCAN_API_KEY, or through Sourcify
+//! (SOURCIFY_URL), which needs no key and covers chains without an
+//! Etherscan-style API.
+
+use crate::config::{self, env_var};
+use anyhow::Context;
+use ethers::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const DEFAULT_API_URL: &str = "https://api.etherscan.io/v2/api";
+const DEFAULT_SOURCIFY_URL: &str = "https://sourcify.dev/server";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Submissions made before the explorer has indexed a fresh deployment are
+/// refused; they are retried this many times.
+const SUBMIT_ATTEMPTS: usize = 6;
+
+/// What the explorer needs to rebuild the deployed bytecode.
+pub struct Submission {
+    pub address: Address,
+    /// Fully qualified name, e.g. `src/Vault.sol:Vault`.
+    pub contract_name: String,
+    /// Full solc version, e.g. `v0.8.24+commit.e11b9ed9`.
+    pub compiler_version: String,
+    /// The solc standard JSON input the contract was compiled from.
+    pub standard_json: String,
+    /// ABI-encoded constructor arguments.
+    pub constructor_args: Bytes,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    status: String,
+    result: String,
+}
+
+pub struct Etherscan {
+    url: String,
+    api_key: String,
+    chain_id: u64,
+}
+
+impl Etherscan {
+    pub fn from_env(chain_id: u64) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: env_var("ETHERSCAN_API_URL").unwrap_or_else(|| DEFAULT_API_URL.to_string()),
+            api_key: config::secret("ETHERSCAN_API_KEY")?,
+            chain_id,
+        })
+    }
+
+    /// Submits the source for verification, returning the explorer's GUID
+    /// for the request.
+    pub async fn submit(&self, submission: &Submission) -> anyhow::Result<String> {
+        let mut attempt = 1;
+        loop {
+            let response = self.submit_once(submission).await?;
+            if response.status == "1" {
+                return Ok(response.result);
+            }
+            if !response.result.contains("Unable to locate ContractCode") || attempt == SUBMIT_ATTEMPTS {
+                anyhow::bail!("Etherscan refused the submission: {}", response.result);
+            }
+            println!("The explorer hasn't indexed the contract yet; retrying");
+            tokio::time::sleep(POLL_INTERVAL).await;
+            attempt += 1;
+        }
+    }
+
+    async fn submit_once(&self, submission: &Submission) -> anyhow::Result<Response> {
+        let constructor_args = hex::encode(&submission.constructor_args);
+        let address = format!("{:?}", submission.address);
+        let form = [
+            ("module", "contract"),
+            ("action", "verifysourcecode"),
+            ("apikey", self.api_key.as_str()),
+            ("codeformat", "solidity-standard-json-input"),
+            ("sourceCode", submission.standard_json.as_str()),
+            ("contractaddress", address.as_str()),
+            ("contractname", submission.contract_name.as_str()),
+            ("compilerversion", submission.compiler_version.as_str()),
+            // Etherscan's spelling.
+            ("constructorArguements", constructor_args.as_str()),
+        ];
+        Ok(reqwest::Client::new()
+            .post(&self.url)
+            .query(&[("chainid", self.chain_id)])
+            .form(&form)
+            .send()
+            .await
+            .with_context(|| format!("Etherscan API {} unreachable", self.url))?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Polls the verification request until the explorer has decided. Fails
+    /// if verification fails or `timeout` passes first.
+    pub async fn wait(&self, guid: &str, timeout: Duration) -> anyhow::Result<()> {
+        let started = Instant::now();
+        loop {
+            let response: Response = reqwest::Client::new()
+                .get(&self.url)
+                .query(&[
+                    ("chainid", self.chain_id.to_string().as_str()),
+                    ("module", "contract"),
+                    ("action", "checkverifystatus"),
+                    ("guid", guid),
+                    ("apikey", self.api_key.as_str()),
+                ])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            match verification_state(&response) {
+                Some(Ok(())) => return Ok(()),
+                Some(Err(reason)) => anyhow::bail!("verification failed: {}", reason),
+                None if started.elapsed() >= timeout => {
+                    anyhow::bail!("verification still pending after {:?}: {}", timeout, response.result)
+                }
+                None => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+/// How well Sourcify matched the deployed bytecode.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Match {
+    /// Bytecode and metadata hash match.
+    Perfect,
+    /// Bytecode matches but the metadata hash (comments, file names) differs.
+    Partial,
+}
+
+pub struct Sourcify {
+    url: String,
+}
+
+impl Sourcify {
+    pub fn from_env() -> Self {
+        Self { url: env_var("SOURCIFY_URL").unwrap_or_else(|| DEFAULT_SOURCIFY_URL.to_string()) }
+    }
+
+    /// Uploads the compiler metadata and every source it names, returning
+    /// the match Sourcify found.
+    pub async fn verify(
+        &self,
+        chain_id: u64,
+        address: Address,
+        metadata: &str,
+        sources: &BTreeMap<String, String>,
+    ) -> anyhow::Result<Match> {
+        let mut files: BTreeMap<&str, &str> =
+            sources.iter().map(|(path, content)| (path.as_str(), content.as_str())).collect();
+        files.insert("metadata.json", metadata);
+        let body = json!({ "address": format!("{:?}", address), "chain": chain_id.to_string(), "files": files });
+        let response = reqwest::Client::new()
+            .post(format!("{}/verify", self.url.trim_end_matches('/')))
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("Sourcify {} unreachable", self.url))?;
+        let status = response.status();
+        let response: Value = response.json().await?;
+        if let Some(error) = response.get("error").and_then(Value::as_str) {
+            anyhow::bail!("Sourcify refused the verification ({}): {}", status, error);
+        }
+        let result = response.pointer("/result/0/status").cloned().context("Sourcify returned no result")?;
+        serde_json::from_value(result).context("Sourcify found no match")
+    }
+}
+
+/// Reads solc metadata from `path`: a metadata file, or a Foundry artifact
+/// carrying it as `rawMetadata`.
+pub fn read_metadata(path: &Path) -> anyhow::Result<String> {
+    let text = fs::read_to_string(path).with_context(|| format!("failed to read metadata {}", path.display()))?;
+    let value: Value = serde_json::from_str(&text).with_context(|| format!("{} is not JSON", path.display()))?;
+    match value.get("rawMetadata").and_then(Value::as_str) {
+        Some(raw) => Ok(raw.to_string()),
+        None if value.get("sources").is_some() && value.get("compiler").is_some() => Ok(text),
+        None => anyhow::bail!("{} is neither solc metadata nor an artifact with rawMetadata", path.display()),
+    }
+}
+
+/// The sources `metadata` names, by path: taken from a standard JSON input
+/// when given, and read from disk relative to the working directory
+/// otherwise.
+pub fn collect_sources(metadata: &str, standard_json: Option<&str>) -> anyhow::Result<BTreeMap<String, String>> {
+    let metadata: Value = serde_json::from_str(metadata)?;
+    let input: Option<Value> = standard_json.map(serde_json::from_str::<Value>).transpose()?;
+    let paths = metadata.get("sources").and_then(Value::as_object).context("metadata lists no sources")?;
+    let mut sources = BTreeMap::new();
+    for path in paths.keys() {
+        let embedded = input.as_ref().and_then(|input| input.pointer(&format!("/sources/{}/content", escape(path))));
+        let content = match embedded.and_then(Value::as_str) {
+            Some(content) => content.to_string(),
+            None => fs::read_to_string(path).with_context(|| format!("failed to read source {}", path))?,
+        };
+        sources.insert(path.clone(), content);
+    }
+    Ok(sources)
+}
+
+/// Escapes a key for use in a JSON pointer.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Whether a status response means verified, failed (with the reason), or
+/// still pending (`None`).
+fn verification_state(response: &Response) -> Option<Result<(), String>> {
+    let result = response.result.as_str();
+    if result.starts_with("Pass") || result.contains("Already Verified") {
+        return Some(Ok(()));
+    }
+    if result.contains("Pending") || result.contains("in queue") {
+        return None;
+    }
+    match response.status.as_str() {
+        "1" => Some(Ok(())),
+        _ => Some(Err(result.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: &str, result: &str) -> Response {
+        Response { status: status.to_string(), result: result.to_string() }
+    }
+
+    #[test]
+    fn sources_from_standard_json() {
+        let metadata = r#"{"compiler": {"version": "0.8.24"}, "sources": {"src/Vault.sol": {"keccak256": "0x00"}}}"#;
+        let input = r#"{"language": "Solidity", "sources": {"src/Vault.sol": {"content": "contract Vault {}"}}}"#;
+        let sources = collect_sources(metadata, Some(input)).unwrap();
+        assert_eq!(sources["src/Vault.sol"], "contract Vault {}");
+        assert!(collect_sources(metadata, None).is_err());
+    }
+
+    #[test]
+    fn verification_states() {
+        assert_eq!(verification_state(&response("1", "Pass - Verified")), Some(Ok(())));
+        assert_eq!(verification_state(&response("0", "Already Verified")), Some(Ok(())));
+        assert_eq!(verification_state(&response("0", "Pending in queue")), None);
+        assert_eq!(
+            verification_state(&response("0", "Fail - Unable to verify")),
+            Some(Err("Fail - Unable to verify".to_string()))
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! WalletConnect v2: a transaction signed by a mobile wallet, for one-off
+//! manual operations where no key is provisioned on the host. A pairing URI
+//! is printed, with its QR code, for the wallet to scan; once the wallet
+//! approves the session, it is asked to sign with `eth_signTransaction`.
+//!
+//! Messages go through the WalletConnect relay (WALLETCONNECT_RELAY_URL,
+//! default `wss://relay.walletconnect.com`) under the Cloud project
+//! WALLETCONNECT_PROJECT_ID, encrypted end to end with ChaCha20-Poly1305:
+//! under the pairing's key until the wallet answers the session proposal,
+//! then under the session key both sides derive by X25519.
+
+use crate::config::{env_var, var};
+use anyhow::Context;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::{Signer as _, SigningKey};
+use ethers::core::rand::{thread_rng, Rng, RngCore};
+use ethers::prelude::*;
+use futures::{SinkExt, StreamExt};
+use hkdf::Hkdf;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// How long the wallet gets to scan the pairing, and then to sign.
+const WAIT: Duration = Duration::from_secs(300);
+
+/// Relay tags of the messages exchanged, per the WalletConnect sign
+/// protocol; each response's tag is its request's plus one.
+const PROPOSE_TAG: u32 = 1100;
+const SETTLE_RESPONSE_TAG: u32 = 1103;
+const REQUEST_TAG: u32 = 1108;
+const DELETE_TAG: u32 = 1112;
+
+pub struct WalletConnect {
+    relay_url: String,
+    project_id: String,
+}
+
+impl WalletConnect {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            relay_url: env_var("WALLETCONNECT_RELAY_URL")
+                .unwrap_or_else(|| "wss://relay.walletconnect.com".to_string()),
+            project_id: var("WALLETCONNECT_PROJECT_ID")?,
+        })
+    }
+
+    /// Pairs with a wallet, checks it connected `from` on `chain_id`, and has
+    /// it sign `tx` (in its [`json_rpc`](crate::unsigned::json_rpc) form),
+    /// returning the signed transaction.
+    pub async fn sign_transaction(&self, tx: Value, from: Address, chain_id: u64) -> anyhow::Result<Bytes> {
+        let mut relay = Relay::connect(&self.relay_url, &self.project_id).await?;
+        let pairing_key: [u8; 32] = thread_rng().gen();
+        let pairing_topic = topic(&pairing_key);
+        relay.call("irn_subscribe", json!({ "topic": pairing_topic })).await?;
+
+        let secret = StaticSecret::from(thread_rng().gen::<[u8; 32]>());
+        let propose_id = payload_id();
+        let proposal = json!({
+            "id": propose_id,
+            "jsonrpc": "2.0",
+            "method": "wc_sessionPropose",
+            "params": {
+                "requiredNamespaces": {
+                    "eip155": {
+                        "chains": [format!("eip155:{}", chain_id)],
+                        "methods": ["eth_signTransaction"],
+                        "events": ["chainChanged", "accountsChanged"],
+                    },
+                },
+                "relays": [{ "protocol": "irn" }],
+                "proposer": {
+                    "publicKey": hex::encode(PublicKey::from(&secret).as_bytes()),
+                    "metadata": {
+                        "name": "ethers-rusty",
+                        "description": "Lock contract caller",
+                        "url": "https://github.com/livingrockrises/ethers-rusty",
+                        "icons": [],
+                    },
+                },
+            },
+        });
+        relay.publish(&pairing_topic, &pairing_key, &proposal, PROPOSE_TAG).await?;
+
+        let uri = pairing_uri(&pairing_topic, &pairing_key);
+        println!("Scan with the wallet of {:?}, or paste the URI into it:", from);
+        println!();
+        println!("{}", qr_code(&uri)?);
+        println!("{}", uri);
+        println!();
+
+        let responder = tokio::time::timeout(WAIT, relay.response(&pairing_topic, &pairing_key, propose_id))
+            .await
+            .context("no wallet paired in time")?
+            .context("the wallet rejected the session")?;
+        let responder = responder["responderPublicKey"].as_str().context("the wallet sent no public key")?;
+        let responder: [u8; 32] = hex::decode(responder)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("the wallet's public key is not 32 bytes"))?;
+        let session_key = session_key(&secret, responder);
+        let session_topic = topic(&session_key);
+        relay.call("irn_subscribe", json!({ "topic": session_topic })).await?;
+
+        let accounts = tokio::time::timeout(WAIT, relay.settle(&session_topic, &session_key))
+            .await
+            .context("the wallet didn't settle the session in time")??;
+        let account = format!("eip155:{}:{:?}", chain_id, from);
+        if !accounts.iter().any(|connected| connected.eq_ignore_ascii_case(&account)) {
+            relay.disconnect(&session_topic, &session_key).await?;
+            anyhow::bail!("the wallet connected {}, not {}", accounts.join(", "), account);
+        }
+        println!("Wallet connected; approve the transaction in it");
+
+        let request_id = payload_id();
+        let request = json!({
+            "id": request_id,
+            "jsonrpc": "2.0",
+            "method": "wc_sessionRequest",
+            "params": {
+                "request": { "method": "eth_signTransaction", "params": [tx] },
+                "chainId": format!("eip155:{}", chain_id),
+            },
+        });
+        relay.publish(&session_topic, &session_key, &request, REQUEST_TAG).await?;
+        let signed = tokio::time::timeout(WAIT, relay.response(&session_topic, &session_key, request_id))
+            .await
+            .context("the wallet didn't sign in time")?
+            .context("the wallet refused to sign")?;
+        relay.disconnect(&session_topic, &session_key).await?;
+        serde_json::from_value(signed).context("the wallet returned an invalid signed transaction")
+    }
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A connection to the relay. Messages published on subscribed topics are
+/// acknowledged as they arrive and queued until asked for.
+struct Relay {
+    socket: Socket,
+    inbox: VecDeque<(String, String)>,
+}
+
+impl Relay {
+    async fn connect(url: &str, project_id: &str) -> anyhow::Result<Self> {
+        let token = auth_token(&SigningKey::from_bytes(&thread_rng().gen()), url)?;
+        let (socket, _) = tokio_tungstenite::connect_async(format!("{}/?auth={}&projectId={}", url, token, project_id))
+            .await
+            .with_context(|| format!("failed to connect to the WalletConnect relay {}", url))?;
+        Ok(Self { socket, inbox: VecDeque::new() })
+    }
+
+    /// Makes a relay call, returning its result.
+    async fn call(&mut self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let id = payload_id();
+        let request = json!({ "id": id, "jsonrpc": "2.0", "method": method, "params": params });
+        self.socket.send(Message::Text(request.to_string())).await?;
+        loop {
+            let message = self.read().await?;
+            if message["id"] != id {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                anyhow::bail!("the relay refused {}: {}", method, error["message"].as_str().unwrap_or_default());
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+
+    /// Encrypts `payload` with `key` and publishes it on `topic`.
+    async fn publish(&mut self, topic: &str, key: &[u8; 32], payload: &Value, tag: u32) -> anyhow::Result<()> {
+        let params = json!({ "topic": topic, "message": seal(key, payload)?, "ttl": 300, "tag": tag, "prompt": true });
+        self.call("irn_publish", params).await?;
+        Ok(())
+    }
+
+    /// The next message on `topic`, decrypted with `key`.
+    async fn receive(&mut self, topic: &str, key: &[u8; 32]) -> anyhow::Result<Value> {
+        loop {
+            while let Some(index) = self.inbox.iter().position(|(from, _)| from == topic) {
+                let (_, message) = self.inbox.remove(index).unwrap_or_default();
+                match open(key, &message) {
+                    Ok(payload) => return Ok(payload),
+                    // Not for us, or not in a form we take part in.
+                    Err(_) => continue,
+                }
+            }
+            self.read().await?;
+        }
+    }
+
+    /// The result of the request `id` sent on `topic`, or the wallet's error.
+    async fn response(&mut self, topic: &str, key: &[u8; 32], id: u64) -> anyhow::Result<Value> {
+        loop {
+            let payload = self.receive(topic, key).await?;
+            if payload["id"] != id {
+                continue;
+            }
+            if let Some(error) = payload.get("error") {
+                anyhow::bail!("{}", error["message"].as_str().unwrap_or("no reason given"));
+            }
+            return Ok(payload["result"].clone());
+        }
+    }
+
+    /// Waits for the wallet to settle the session, acknowledges it, and
+    /// returns the accounts it connected.
+    async fn settle(&mut self, topic: &str, key: &[u8; 32]) -> anyhow::Result<Vec<String>> {
+        loop {
+            let payload = self.receive(topic, key).await?;
+            if payload["method"] != "wc_sessionSettle" {
+                continue;
+            }
+            let answer = json!({ "id": payload["id"], "jsonrpc": "2.0", "result": true });
+            let message = seal(key, &answer)?;
+            let params = json!({ "topic": topic, "message": message, "ttl": 300, "tag": SETTLE_RESPONSE_TAG });
+            self.call("irn_publish", params).await?;
+            let accounts = &payload["params"]["namespaces"]["eip155"]["accounts"];
+            return serde_json::from_value(accounts.clone()).context("the wallet settled without eip155 accounts");
+        }
+    }
+
+    /// Ends the session, so it doesn't linger in the wallet.
+    async fn disconnect(&mut self, topic: &str, key: &[u8; 32]) -> anyhow::Result<()> {
+        let delete = json!({
+            "id": payload_id(),
+            "jsonrpc": "2.0",
+            "method": "wc_sessionDelete",
+            "params": { "code": 6000, "message": "User disconnected." },
+        });
+        self.publish(topic, key, &delete, DELETE_TAG).await
+    }
+
+    /// Reads the next relay message that isn't a subscription delivery,
+    /// queueing deliveries on the way.
+    async fn read(&mut self) -> anyhow::Result<Value> {
+        loop {
+            let message = match self.socket.next().await.context("the relay closed the connection")?? {
+                Message::Text(text) => text,
+                Message::Close(_) => anyhow::bail!("the relay closed the connection"),
+                _ => continue,
+            };
+            let message: Value = serde_json::from_str(&message)?;
+            if message["method"] != "irn_subscription" {
+                return Ok(message);
+            }
+            let ack = json!({ "id": message["id"], "jsonrpc": "2.0", "result": true });
+            self.socket.send(Message::Text(ack.to_string())).await?;
+            let data = &message["params"]["data"];
+            if let (Some(topic), Some(body)) = (data["topic"].as_str(), data["message"].as_str()) {
+                self.inbox.push_back((topic.to_string(), body.to_string()));
+            }
+        }
+    }
+}
+
+/// A JSON-RPC id as WalletConnect makes them: milliseconds, with three
+/// random digits appended.
+fn payload_id() -> u64 {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default();
+    millis * 1000 + thread_rng().gen_range(0..1000)
+}
+
+/// The topic of messages encrypted with `key`: its SHA-256.
+fn topic(key: &[u8; 32]) -> String {
+    hex::encode(Sha256::digest(key))
+}
+
+fn pairing_uri(topic: &str, key: &[u8; 32]) -> String {
+    format!("wc:{}@2?relay-protocol=irn&symKey={}", topic, hex::encode(key))
+}
+
+/// The session key: HKDF-SHA256 over the X25519 shared secret.
+fn session_key(secret: &StaticSecret, peer: [u8; 32]) -> [u8; 32] {
+    let shared = secret.diffie_hellman(&PublicKey::from(peer));
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, shared.as_bytes()).expand(&[], &mut key).expect("32 bytes is a valid HKDF length");
+    key
+}
+
+/// Encrypts `payload` into a type-0 envelope: the type byte, a random IV
+/// and the ciphertext, base64-encoded.
+fn seal(key: &[u8; 32], payload: &Value) -> anyhow::Result<String> {
+    let mut iv = [0u8; 12];
+    thread_rng().fill_bytes(&mut iv);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let sealed = cipher
+        .encrypt(Nonce::from_slice(&iv), payload.to_string().as_bytes())
+        .map_err(|_| anyhow::anyhow!("failed to encrypt a WalletConnect message"))?;
+    Ok(STANDARD.encode([&[0u8][..], &iv, &sealed].concat()))
+}
+
+/// Decrypts a type-0 envelope made by [`seal`].
+fn open(key: &[u8; 32], message: &str) -> anyhow::Result<Value> {
+    let envelope = STANDARD.decode(message)?;
+    anyhow::ensure!(envelope.len() > 13 && envelope[0] == 0, "unsupported WalletConnect envelope");
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let plain = cipher
+        .decrypt(Nonce::from_slice(&envelope[1..13]), &envelope[13..])
+        .map_err(|_| anyhow::anyhow!("failed to decrypt a WalletConnect message"))?;
+    Ok(serde_json::from_slice(&plain)?)
+}
+
+/// The relay's auth token: a JWT signed with a throwaway Ed25519 client
+/// key, issued by that key's `did:key`.
+fn auth_token(key: &SigningKey, audience: &str) -> anyhow::Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let did = [&[0xed, 0x01][..], key.verifying_key().as_bytes()].concat();
+    let header = json!({ "alg": "EdDSA", "typ": "JWT" });
+    let claims = json!({
+        "iss": format!("did:key:z{}", bs58::encode(did).into_string()),
+        "sub": hex::encode(thread_rng().gen::<[u8; 32]>()),
+        "aud": audience,
+        "iat": now,
+        "exp": now + 86400,
+    });
+    let message = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let signature = key.sign(message.as_bytes());
+    Ok(format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature.to_bytes())))
+}
+
+/// `uri` as a QR code in terminal block characters.
+fn qr_code(uri: &str) -> anyhow::Result<String> {
+    use qrcode::render::unicode::Dense1x2;
+    let code = qrcode::QrCode::new(uri.as_bytes())?;
+    Ok(code.render::<Dense1x2>().dark_color(Dense1x2::Light).light_color(Dense1x2::Dark).build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelopes() {
+        let key = [7u8; 32];
+        let payload = json!({ "id": 1, "jsonrpc": "2.0", "result": true });
+        let sealed = seal(&key, &payload).unwrap();
+        assert_eq!(STANDARD.decode(&sealed).unwrap()[0], 0);
+        assert_eq!(open(&key, &sealed).unwrap(), payload);
+        assert!(open(&[8u8; 32], &sealed).is_err());
+
+        // Both sides of the session derive the same key, whose topic is its hash.
+        let (ours, theirs) = (StaticSecret::from([1u8; 32]), StaticSecret::from([2u8; 32]));
+        let key = session_key(&ours, *PublicKey::from(&theirs).as_bytes());
+        assert_eq!(key, session_key(&theirs, *PublicKey::from(&ours).as_bytes()));
+        assert_eq!(topic(&key).len(), 64);
+        assert_eq!(
+            pairing_uri("ab", &[0u8; 32]),
+            format!("wc:ab@2?relay-protocol=irn&symKey={}", "00".repeat(32))
+        );
+    }
+
+    #[test]
+    fn relay_tokens() {
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let token = auth_token(&key, "wss://relay.walletconnect.com").unwrap();
+        let parts: Vec<&str> = token.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts[1]).unwrap()).unwrap();
+        assert!(claims["iss"].as_str().unwrap().starts_with("did:key:z6Mk"));
+        assert_eq!(claims["aud"], "wss://relay.walletconnect.com");
+        let signature = ed25519_dalek::Signature::from_slice(&URL_SAFE_NO_PAD.decode(parts[2]).unwrap()).unwrap();
+        let message = format!("{}.{}", parts[0], parts[1]);
+        key.verifying_key().verify_strict(message.as_bytes(), &signature).unwrap();
+    }
+}
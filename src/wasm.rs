@@ -0,0 +1,106 @@
+//! Preflight checks distributed as WebAssembly modules, listed per chain in
+//! the active profile's `plugins`. Unlike hook commands, a module runs
+//! sandboxed: it is given no imports, so it can't touch the filesystem or
+//! network, and its fuel is capped so it can't spin forever.
+//!
+//! A module exports its `memory`, `alloc(len: i32) -> i32` returning a buffer
+//! for the host to write into, and `check(ptr: i32, len: i32) -> i64`, which
+//! receives the job as JSON (the hook payload, see [`hooks::payload`]). It
+//! returns 0 to allow the send, or `ptr << 32 | len` of a UTF-8 reason in
+//! its memory to refuse it.
+//!
+//! Built only with the `wasm` feature.
+
+use crate::hooks;
+use crate::plugins::{BoxFuture, Preflight, PreflightCheck};
+use anyhow::Context;
+use std::path::Path;
+use wasmtime::{Engine, Instance, Module, Store};
+
+/// Fuel each call may burn, roughly one unit per instruction.
+const FUEL: u64 = 100_000_000;
+
+pub struct WasmCheck {
+    name: String,
+    engine: Engine,
+    module: Module,
+}
+
+impl WasmCheck {
+    /// Compiles the module at `path`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| format!("failed to read plugin {}", path.display()))?;
+        Self::new(&path.display().to_string(), &bytes)
+    }
+
+    /// Compiles a module from its binary or text form.
+    pub fn new(name: &str, bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::new(&engine, bytes).with_context(|| format!("invalid plugin {}", name))?;
+        Ok(Self { name: name.to_string(), engine, module })
+    }
+
+    /// Runs the module's `check` on `input` in a fresh instance.
+    pub fn call(&self, input: &[u8]) -> anyhow::Result<Result<(), String>> {
+        let mut store = Store::new(&self.engine, ());
+        store.set_fuel(FUEL)?;
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance.get_memory(&mut store, "memory").context("plugin exports no memory")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let check = instance.get_typed_func::<(i32, i32), i64>(&mut store, "check")?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, input).context("plugin buffer out of bounds")?;
+        let verdict = check.call(&mut store, (ptr, len))? as u64;
+        if verdict == 0 {
+            return Ok(Ok(()));
+        }
+        let mut reason = vec![0; (verdict & 0xffff_ffff) as usize];
+        memory.read(&store, (verdict >> 32) as usize, &mut reason).context("plugin reason out of bounds")?;
+        Ok(Err(String::from_utf8_lossy(&reason).into_owned()))
+    }
+}
+
+impl PreflightCheck for WasmCheck {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check<'a>(&'a self, preflight: &'a Preflight<'a>) -> BoxFuture<'a, anyhow::Result<Result<(), String>>> {
+        Box::pin(async move {
+            let payload = hooks::payload(preflight.kind, preflight.config, preflight.sender, preflight.job);
+            self.call(payload.to_string().as_bytes())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Refuses any job whose JSON starts with `{"a`, with the reason "no".
+    const PLUGIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (data (i32.const 0) "no")
+        (func (export "alloc") (param i32) (result i32) (i32.const 16))
+        (func (export "check") (param $ptr i32) (param $len i32) (result i64)
+            (if (result i64) (i32.eq (i32.load16_u offset=1 (local.get $ptr)) (i32.const 0x6122))
+                (then (i64.const 2))
+                (else (i64.const 0)))))"#;
+
+    #[test]
+    fn plugins_allow_and_refuse() {
+        let plugin = WasmCheck::new("test", PLUGIN.as_bytes()).unwrap();
+        assert_eq!(plugin.call(br#"{"kind":"lock"}"#).unwrap(), Ok(()));
+        assert_eq!(plugin.call(br#"{"amount":"1"}"#).unwrap(), Err("no".to_string()));
+
+        let spinning = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "check") (param i32 i32) (result i64) (loop (br 0)) (i64.const 0)))"#;
+        assert!(WasmCheck::new("spin", spinning.as_bytes()).unwrap().call(b"{}").is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! Consensys Web3Signer: transactions signed by the `eth_signTransaction`
+//! endpoint of the remote signer at WEB3SIGNER_URL, so keys stay in the
+//! remote-signing infrastructure while this tool builds and broadcasts.
+
+use crate::config::var;
+use crate::unsigned;
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde_json::{json, Value};
+
+pub struct Web3Signer {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl Web3Signer {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self { url: var("WEB3SIGNER_URL")?, client: reqwest::Client::new() })
+    }
+
+    /// Fails unless the signer holds the key of `address`.
+    pub async fn ensure_holds(&self, address: Address) -> anyhow::Result<()> {
+        let accounts: Vec<Address> = serde_json::from_value(self.call("eth_accounts", json!([])).await?)
+            .context("Web3Signer returned an invalid account list")?;
+        anyhow::ensure!(accounts.contains(&address), "Web3Signer at {} holds no key for {:?}", self.url, address);
+        Ok(())
+    }
+
+    /// Has the signer sign `tx`, returning the signed transaction.
+    pub async fn sign_transaction(&self, tx: &TypedTransaction) -> anyhow::Result<Bytes> {
+        let signed = self.call("eth_signTransaction", json!([unsigned::json_rpc(tx)?])).await?;
+        serde_json::from_value(signed).context("Web3Signer returned an invalid signed transaction")
+    }
+
+    async fn call(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response: Value = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .with_context(|| format!("Web3Signer at {} unreachable", self.url))?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            anyhow::bail!("Web3Signer refused {}: {}", method, error["message"].as_str().unwrap_or("no reason given"));
+        }
+        response.get("result").cloned().with_context(|| format!("Web3Signer returned no result for {}", method))
+    }
+}
@@ -0,0 +1,239 @@
+//! Multi-step workflows, for flows that take more than one command (check,
+//! approve, lock, verify) and would otherwise be held together by a shell
+//! script. A workflow is a YAML list of steps run in order:
+//!
+//! ```yaml
+//! name: lock with approval
+//! steps:
+//!   - name: check
+//!     run: [doctor]
+//!   - name: approve
+//!     shell: ./approve.sh
+//!     retries: 2
+//!   - name: lock
+//!     run: [lock, --check-nonce]
+//!     env: { NONCE: "7" }
+//!     retries: 3
+//!     retry_delay: 30s
+//!   - name: alert
+//!     shell: ./page-oncall.sh
+//!     when: [lock failed]
+//! ```
+//!
+//! A step either runs one of this tool's subcommands (`run`) or a shell
+//! command (`shell`), with `env` added to its environment; a non-zero exit
+//! is a failure, retried up to `retries` times. By default a step runs only
+//! if no earlier step failed; `when` replaces that with conditions on named
+//! earlier steps (`<step> succeeded`, `failed` or `skipped`), all of which
+//! must hold.
+
+use crate::config;
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Workflow {
+    pub name: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub name: String,
+    /// Arguments to this tool, e.g. `[lock, --check-nonce]`.
+    #[serde(default)]
+    pub run: Vec<String>,
+    /// A command for `sh -c`, instead of `run`.
+    pub shell: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub when: Vec<String>,
+    #[serde(default)]
+    pub retries: u32,
+    /// Pause between attempts, e.g. `30s` (default 10s).
+    pub retry_delay: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepStatus {
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+impl fmt::Display for StepStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            StepStatus::Succeeded => "succeeded",
+            StepStatus::Failed => "failed",
+            StepStatus::Skipped => "skipped",
+        })
+    }
+}
+
+/// One `when` entry: `<step> <status>`.
+fn parse_condition(condition: &str) -> anyhow::Result<(&str, StepStatus)> {
+    let (step, status) = condition
+        .trim()
+        .rsplit_once(' ')
+        .with_context(|| format!("invalid condition {:?}, expected `<step> succeeded|failed|skipped`", condition))?;
+    let status = match status {
+        "succeeded" => StepStatus::Succeeded,
+        "failed" => StepStatus::Failed,
+        "skipped" => StepStatus::Skipped,
+        other => anyhow::bail!("invalid condition {:?}: unknown status {:?}", condition, other),
+    };
+    Ok((step.trim(), status))
+}
+
+impl Workflow {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("failed to read workflow {}", path.display()))?;
+        let workflow: Self =
+            serde_yaml::from_str(&contents).with_context(|| format!("invalid workflow {}", path.display()))?;
+        workflow.validate()?;
+        Ok(workflow)
+    }
+
+    /// Checks every step has exactly one action, a unique name, a valid retry
+    /// delay and conditions on earlier steps only.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.steps.is_empty(), "workflow has no steps");
+        for (index, step) in self.steps.iter().enumerate() {
+            let earlier = &self.steps[..index];
+            anyhow::ensure!(!earlier.iter().any(|other| other.name == step.name), "duplicate step {:?}", step.name);
+            anyhow::ensure!(
+                step.run.is_empty() != step.shell.is_none(),
+                "step {:?} needs exactly one of `run` and `shell`",
+                step.name
+            );
+            for condition in &step.when {
+                let (name, _) = parse_condition(condition)?;
+                anyhow::ensure!(
+                    earlier.iter().any(|other| other.name == name),
+                    "step {:?} depends on {:?}, which is not an earlier step",
+                    step.name,
+                    name
+                );
+            }
+            step.retry_delay()?;
+        }
+        Ok(())
+    }
+}
+
+impl Step {
+    fn retry_delay(&self) -> anyhow::Result<Duration> {
+        match &self.retry_delay {
+            Some(delay) => {
+                config::parse_duration(delay).with_context(|| format!("invalid retry_delay in step {:?}", self.name))
+            }
+            None => Ok(Duration::from_secs(10)),
+        }
+    }
+
+    /// Whether the step runs, given the earlier steps' `results`.
+    pub fn should_run(&self, results: &[(String, StepStatus)]) -> anyhow::Result<bool> {
+        if self.when.is_empty() {
+            return Ok(!results.iter().any(|(_, status)| *status == StepStatus::Failed));
+        }
+        for condition in &self.when {
+            let (name, expected) = parse_condition(condition)?;
+            if !results.iter().any(|(step, status)| step == name && *status == expected) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Runs the step once, with stdio inherited. Returns whether it succeeded.
+    async fn attempt(&self) -> anyhow::Result<bool> {
+        let mut command = match &self.shell {
+            Some(shell) => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(shell);
+                command
+            }
+            None => {
+                let mut command = Command::new(std::env::current_exe()?);
+                command.args(&self.run);
+                command
+            }
+        };
+        let status = command
+            .envs(&self.env)
+            .stdin(Stdio::null())
+            .status()
+            .await
+            .with_context(|| format!("failed to start step {:?}", self.name))?;
+        Ok(status.success())
+    }
+
+    /// Runs the step, retrying failed attempts.
+    pub async fn execute(&self) -> anyhow::Result<StepStatus> {
+        let delay = self.retry_delay()?;
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                let total = self.retries + 1;
+                println!("Retrying step {:?} in {}s (attempt {} of {})", self.name, delay.as_secs(), attempt + 1, total);
+                tokio::time::sleep(delay).await;
+            }
+            if self.attempt().await? {
+                return Ok(StepStatus::Succeeded);
+            }
+        }
+        Ok(StepStatus::Failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflow(yaml: &str) -> anyhow::Result<Workflow> {
+        let workflow: Workflow = serde_yaml::from_str(yaml)?;
+        workflow.validate()?;
+        Ok(workflow)
+    }
+
+    #[test]
+    fn conditions() {
+        let flow = workflow(
+            "steps:\n\
+             - { name: lock, run: [lock] }\n\
+             - { name: verify, run: [events] }\n\
+             - { name: alert, shell: ./alert.sh, when: [lock failed] }\n",
+        )
+        .unwrap();
+        let ran = |statuses: &[StepStatus]| {
+            let results: Vec<_> = flow.steps.iter().map(|step| step.name.clone()).zip(statuses.iter().copied()).collect();
+            flow.steps[results.len()].should_run(&results).unwrap()
+        };
+        assert!(ran(&[StepStatus::Succeeded]));
+        assert!(!ran(&[StepStatus::Failed]));
+        assert!(!ran(&[StepStatus::Succeeded, StepStatus::Succeeded]));
+        assert!(ran(&[StepStatus::Failed, StepStatus::Skipped]));
+    }
+
+    #[test]
+    fn invalid_workflows() {
+        assert!(workflow("steps: []").is_err());
+        assert!(workflow("steps: [{ name: a }]").is_err());
+        assert!(workflow("steps: [{ name: a, run: [lock], shell: x }]").is_err());
+        assert!(workflow("steps: [{ name: a, run: [lock] }, { name: a, run: [lock] }]").is_err());
+        assert!(workflow("steps: [{ name: a, run: [lock], when: [b failed] }, { name: b, run: [lock] }]").is_err());
+        assert!(workflow("steps: [{ name: a, run: [lock] }, { name: b, run: [lock], when: [a done] }]").is_err());
+        assert!(workflow("steps: [{ name: a, run: [lock], retry_delay: soon }]").is_err());
+        assert!(workflow("steps: [{ name: a, run: [lock], retries: 2, retry_delay: 1m }]").is_ok());
+    }
+}